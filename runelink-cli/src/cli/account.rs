@@ -111,8 +111,12 @@ pub async fn handle_account_commands(
         }
 
         AccountCommands::Create(create_args) => {
-            let raw_host = match &create_args.host {
-                Some(host) => host.clone(),
+            let raw_host = match create_args
+                .host
+                .clone()
+                .or_else(|| ctx.config.default_host.clone())
+            {
+                Some(host) => host,
                 None => read_input_preserving_whitespace("Host: ")?
                     .ok_or_else(|| {
                         CliError::InvalidArgument("Host is required.".into())
@@ -16,6 +16,7 @@ use crate::{
 use super::{
     context::CliContext,
     input::{read_input, unwrap_or_prompt},
+    output::print_json,
 };
 
 #[derive(clap::Args, Debug)]
@@ -73,6 +74,12 @@ pub struct ChannelCreateArgs {
     /// Skip description cli prompt
     #[clap(long)]
     pub no_description: bool,
+    /// Seconds members must wait between their messages
+    #[clap(long)]
+    pub slow_mode: Option<i32>,
+    /// The category to list the channel under
+    #[clap(long)]
+    pub category: Option<String>,
     /// The server ID
     #[clap(long)]
     pub server_id: Option<ServerId>,
@@ -148,14 +155,27 @@ pub async fn handle_channel_commands(
                     .await?
                 }
             };
+            if ctx.output.is_json() {
+                return print_json(&channels);
+            }
             if channels.is_empty() {
                 println!(
                     "No channels available.\n\
                     For more information, try `rune channel --help`."
                 )
             }
+            // Channels come sorted by category, so each group is contiguous
+            let mut category = None;
             for channel in channels {
-                println!("{}", channel.verbose());
+                if let Some(name) = &channel.category {
+                    if channel.category != category {
+                        println!("[{name}]");
+                    }
+                    println!("  {}", channel.verbose());
+                } else {
+                    println!("{}", channel.verbose());
+                }
+                category = channel.category;
             }
         }
 
@@ -176,6 +196,9 @@ pub async fn handle_channel_commands(
                 target_host.as_deref(),
             )
             .await?;
+            if ctx.output.is_json() {
+                return print_json(&channel);
+            }
             println!("{}", channel.verbose());
         }
 
@@ -206,7 +229,7 @@ pub async fn handle_channel_commands(
                 unwrap_or_prompt(create_args.title.clone(), "Channel Title")?;
             let desc = if create_args.description.is_some() {
                 create_args.description.clone()
-            } else if create_args.no_description {
+            } else if create_args.no_description || ctx.output.is_json() {
                 None
             } else {
                 read_input("Channel Description (leave blank for none):\n> ")?
@@ -214,7 +237,12 @@ pub async fn handle_channel_commands(
             let new_channel = NewChannel {
                 title,
                 description: desc,
+                slow_mode_secs: create_args.slow_mode,
+                category: create_args.category.clone(),
             };
+            new_channel.validate().map_err(|error| {
+                CliError::InvalidArgument(error.to_string())
+            })?;
             let target_host = if server.host != account.user_ref.host {
                 Some(server.host.as_str())
             } else {
@@ -229,6 +257,9 @@ pub async fn handle_channel_commands(
                 target_host,
             )
             .await?;
+            if ctx.output.is_json() {
+                return print_json(&channel);
+            }
             println!("Created channel: {}", channel.verbose());
         }
 
@@ -274,6 +305,11 @@ pub async fn handle_channel_commands(
                 Some(&selection.host),
             )
             .await?;
+            if ctx.output.is_json() {
+                return print_json(
+                    &serde_json::json!({ "channel_id": selection.channel_id }),
+                );
+            }
             println!("Deleted channel: {}", selection.channel_id);
         }
     };
@@ -1,7 +1,7 @@
 use super::{context::CliContext, select::select_inline};
 use crate::error::CliError;
-use crate::storage::AccountConfig;
-use crate::util::parse_user_ref_input;
+use crate::storage::{AccountConfig, AppConfig, resolve_api_url};
+use crate::util::{parse_host_input, parse_user_ref_input};
 
 #[derive(clap::Args, Debug)]
 pub struct ConfigArgs {
@@ -14,6 +14,25 @@ pub struct ConfigArgs {
 pub enum ConfigCommands {
     /// Manage default account
     DefaultAccount(DefaultAccountArgs),
+    /// Set the default host (checked for reachability before saving)
+    SetHost(SetHostArgs),
+    /// Show the current config
+    Show(ShowConfigArgs),
+    /// Print the path of the config file
+    Path,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SetHostArgs {
+    /// The host name of the home server
+    pub host: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ShowConfigArgs {
+    /// Print the config as JSON
+    #[clap(long)]
+    pub json: bool,
 }
 
 pub async fn handle_config_commands(
@@ -24,10 +43,59 @@ pub async fn handle_config_commands(
         ConfigCommands::DefaultAccount(default_account_args) => {
             handle_default_account_commands(ctx, default_account_args).await?;
         }
+
+        ConfigCommands::SetHost(set_host_args) => {
+            let host = parse_host_input(&set_host_args.host, ctx.strict_input)?;
+            let api_url =
+                resolve_api_url(ctx.client, ctx.config, &host).await?;
+            ctx.config.default_host = Some(host.clone());
+            ctx.config.save()?;
+            println!("Set default host: {host} ({api_url})");
+        }
+
+        ConfigCommands::Show(show_args) => {
+            if show_args.json {
+                println!("{}", serde_json::to_string_pretty(&ctx.config)?);
+            } else {
+                print_config(ctx.config);
+            }
+        }
+
+        ConfigCommands::Path => {
+            println!("{}", AppConfig::path()?.display());
+        }
     }
     Ok(())
 }
 
+fn print_config(config: &AppConfig) {
+    match &config.default_host {
+        Some(host) => println!("Default host: {host}"),
+        None => println!("Default host: (not set)"),
+    }
+    match config.get_default_account() {
+        Some(account) => println!("Default account: {account}"),
+        None => println!("Default account: (not set)"),
+    }
+    if config.accounts.is_empty() {
+        println!("Accounts: (none)");
+    } else {
+        println!("Accounts:");
+        for account in &config.accounts {
+            println!("  {account}");
+        }
+    }
+    if !config.hosts.is_empty() {
+        println!("Hosts:");
+        let mut hosts = config.hosts.iter().collect::<Vec<_>>();
+        hosts.sort_by_key(|(host, _)| host.as_str());
+        for (host, host_config) in hosts {
+            let scheme = if host_config.secure { "https" } else { "http" };
+            println!("  {host} ({scheme})");
+        }
+    }
+}
+
 // DEFAULT HOST
 
 #[derive(clap::Args, Debug)]
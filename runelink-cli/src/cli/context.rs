@@ -3,6 +3,7 @@ use runelink_client::requests;
 use runelink_types::UserRef;
 use time::OffsetDateTime;
 
+use crate::cli::output::OutputFormat;
 use crate::error::CliError;
 use crate::storage::{AccountConfig, AppConfig, TryGetHost, resolve_api_url};
 use crate::storage_auth::AuthCache;
@@ -13,6 +14,7 @@ pub struct CliContext<'a> {
     pub auth_cache: &'a mut AuthCache,
     pub account: Option<&'a AccountConfig>,
     pub strict_input: bool,
+    pub output: OutputFormat,
 }
 
 impl<'a> CliContext<'a> {
@@ -2,21 +2,47 @@ use std::{
     any::type_name,
     io::{self, Write},
     str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use crate::error::CliError;
 
-pub fn read_input(prompt: &str) -> io::Result<Option<String>> {
+/// Set once prompting is turned off, e.g. for JSON output.
+static PROMPTS_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Makes every later prompt fail instead of waiting for input, so scripts
+/// error out rather than hang on a terminal read.
+pub fn disable_prompts() {
+    PROMPTS_DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// Fails if prompting has been turned off.
+pub fn ensure_prompts_enabled() -> Result<(), CliError> {
+    if PROMPTS_DISABLED.load(Ordering::Relaxed) {
+        return Err(CliError::InvalidArgument(
+            "Input is required, but prompts are disabled with --output json. \
+            Pass the missing values as arguments."
+                .into(),
+        ));
+    }
+    Ok(())
+}
+
+pub fn read_input(prompt: &str) -> Result<Option<String>, CliError> {
     read_input_internal(prompt, true)
 }
 
 pub fn read_input_preserving_whitespace(
     prompt: &str,
-) -> io::Result<Option<String>> {
+) -> Result<Option<String>, CliError> {
     read_input_internal(prompt, false)
 }
 
-fn read_input_internal(prompt: &str, trim: bool) -> io::Result<Option<String>> {
+fn read_input_internal(
+    prompt: &str,
+    trim: bool,
+) -> Result<Option<String>, CliError> {
+    ensure_prompts_enabled()?;
     let mut stdout = io::stdout();
     let stdin = io::stdin();
 
@@ -38,6 +64,12 @@ fn read_input_internal(prompt: &str, trim: bool) -> io::Result<Option<String>> {
     }
 }
 
+/// Asks a yes/no question, defaulting to no.
+pub fn confirm(prompt: &str) -> Result<bool, CliError> {
+    let answer = read_input(prompt)?.unwrap_or_default();
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
+
 pub fn unwrap_or_prompt<T: FromStr>(
     arg: Option<T>,
     prompt: &str,
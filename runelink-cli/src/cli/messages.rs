@@ -1,17 +1,33 @@
-use runelink_client::requests;
+use std::io::{self, IsTerminal};
+
+use reqwest::StatusCode;
+use runelink_client::{
+    Error as ClientError,
+    requests::{self, reactions::ReactionTarget},
+};
 use runelink_types::{
     channel::ChannelId,
-    message::{MessageId, NewMessage},
+    message::{Message, MessageId, MessagePage, NewMessage, NewReaction},
     server::ServerId,
+    validation::validate_emoji,
 };
 
 use crate::{error::CliError, util::parse_optional_host_input};
 
 use super::{
-    context::CliContext, input::unwrap_or_prompt,
-    select::get_channel_selection_with_inputs,
+    context::CliContext,
+    input::{confirm, unwrap_or_prompt},
+    output::print_json,
+    select::{
+        ServerSelectionType, get_channel_selection_with_inputs,
+        get_message_selection, get_server_selection,
+    },
+    watch::watch_channel,
 };
 
+/// Number of messages `rune message list` shows when `--limit` is omitted.
+const DEFAULT_MESSAGE_LIST_LIMIT: u32 = 50;
+
 #[derive(clap::Args, Debug)]
 pub struct MessageArgs {
     #[clap(subcommand)]
@@ -28,6 +44,12 @@ pub enum MessageCommands {
     Send(MessageSendArgs),
     /// Delete a message
     Delete(MessageDeleteArgs),
+    /// Add or remove a reaction on a message
+    React(MessageReactArgs),
+    /// Search the messages in a server
+    Search(MessageSearchArgs),
+    /// Follow a channel's messages live
+    Watch(MessageWatchArgs),
 }
 
 #[derive(clap::Args, Debug)]
@@ -41,6 +63,32 @@ pub struct MessageListArgs {
     /// The host of the server
     #[clap(long)]
     pub host: Option<String>,
+    /// Maximum number of messages to show, newest first
+    ///
+    /// Omitting this uses the default rather than fetching everything.
+    #[clap(long, default_value_t = DEFAULT_MESSAGE_LIST_LIMIT)]
+    pub limit: u32,
+    /// Only show messages older than this message ID
+    #[clap(long)]
+    pub before: Option<MessageId>,
+    /// Print a single page without prompting to load more
+    ///
+    /// Prompting is only enabled when stdout is a terminal.
+    #[clap(long)]
+    pub no_interactive: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct MessageWatchArgs {
+    /// Optional: The ID of the server to watch
+    #[clap(long)]
+    pub server_id: Option<ServerId>,
+    /// Optional: The ID of the channel to watch
+    #[clap(long)]
+    pub channel_id: Option<ChannelId>,
+    /// The host of the server
+    #[clap(long)]
+    pub host: Option<String>,
 }
 
 #[derive(clap::Args, Debug)]
@@ -77,20 +125,81 @@ pub struct MessageSendArgs {
 
 #[derive(clap::Args, Debug)]
 pub struct MessageDeleteArgs {
-    /// The ID of the server
+    /// Optional: The ID of the server
     #[clap(long)]
-    pub server_id: ServerId,
-    /// The ID of the channel
+    pub server_id: Option<ServerId>,
+    /// Optional: The ID of the channel
     #[clap(long)]
-    pub channel_id: ChannelId,
-    /// The ID of the message to delete
+    pub channel_id: Option<ChannelId>,
+    /// Optional: The ID of the message to delete
+    ///
+    /// Omitting this prompts for one of the channel's recent messages.
     #[clap(long)]
-    pub message_id: MessageId,
+    pub message_id: Option<MessageId>,
     /// The host of the server
     #[clap(long)]
     pub host: Option<String>,
 }
 
+#[derive(clap::Args, Debug)]
+pub struct MessageReactArgs {
+    /// The emoji to react with, e.g. 👍 or :party_parrot:
+    #[clap(long)]
+    pub emoji: Option<String>,
+    /// Remove your reaction instead of adding it
+    #[clap(long)]
+    pub remove: bool,
+    /// Optional: The ID of the server
+    #[clap(long)]
+    pub server_id: Option<ServerId>,
+    /// Optional: The ID of the channel
+    #[clap(long)]
+    pub channel_id: Option<ChannelId>,
+    /// Optional: The ID of the message to react to
+    ///
+    /// Omitting this prompts for one of the channel's recent messages.
+    #[clap(long)]
+    pub message_id: Option<MessageId>,
+    /// The host of the server
+    #[clap(long)]
+    pub host: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct MessageSearchArgs {
+    /// The text to search for
+    #[clap(long)]
+    pub query: Option<String>,
+    /// The ID of the server to search
+    #[clap(long)]
+    pub server_id: Option<ServerId>,
+    /// The host of the server
+    #[clap(long)]
+    pub host: Option<String>,
+    /// Maximum number of results to show
+    #[clap(long)]
+    pub limit: Option<u32>,
+}
+
+/// Restricts a newest-first message list to one page.
+///
+/// Servers without message pagination ignore `limit` and `before` and return
+/// the full history, so the window is applied here as well.
+fn apply_page_window(
+    messages: Vec<Message>,
+    limit: u32,
+    before: Option<MessageId>,
+) -> Vec<Message> {
+    let start = before
+        .and_then(|id| messages.iter().position(|m| m.id == id))
+        .map_or(0, |pos| pos + 1);
+    messages
+        .into_iter()
+        .skip(start)
+        .take(limit as usize)
+        .collect()
+}
+
 pub async fn handle_message_commands(
     ctx: &mut CliContext<'_>,
     message_args: &MessageArgs,
@@ -117,17 +226,41 @@ pub async fn handle_message_commands(
             } else {
                 None
             };
-            let messages = requests::messages::fetch_by_channel(
-                ctx.client,
-                &api_url,
-                &access_token,
-                selection.server_id,
-                selection.channel_id,
-                target_host,
-            )
-            .await?;
-            for message in messages.iter().rev() {
-                println!("{message}");
+            let interactive = !list_args.no_interactive
+                && !ctx.output.is_json()
+                && io::stdout().is_terminal();
+            let mut before = list_args.before;
+            loop {
+                let messages = requests::messages::fetch_by_channel(
+                    ctx.client,
+                    &api_url,
+                    &access_token,
+                    selection.server_id,
+                    selection.channel_id,
+                    &MessagePage {
+                        before,
+                        limit: Some(list_args.limit),
+                    },
+                    target_host,
+                )
+                .await?;
+                let messages =
+                    apply_page_window(messages, list_args.limit, before);
+                if ctx.output.is_json() {
+                    return print_json(&messages);
+                }
+                for message in messages.iter().rev() {
+                    println!("{message}");
+                }
+                // A short page means we've reached the start of the channel
+                if !interactive || messages.len() < list_args.limit as usize {
+                    break;
+                }
+                if !confirm("Load more? [y/N] ")? {
+                    break;
+                }
+                // The oldest message shown is the cursor for the next page
+                before = messages.last().map(|message| message.id);
             }
         }
 
@@ -149,6 +282,9 @@ pub async fn handle_message_commands(
                 target_host.as_deref(),
             )
             .await?;
+            if ctx.output.is_json() {
+                return print_json(&message);
+            }
             println!("{message}");
         }
 
@@ -171,7 +307,11 @@ pub async fn handle_message_commands(
             let new_message = NewMessage {
                 author: account.user_ref.clone(),
                 body,
+                attachments: Vec::new(),
             };
+            new_message.validate().map_err(|error| {
+                CliError::InvalidArgument(error.to_string())
+            })?;
             let target_host = if selection.host != account.user_ref.host {
                 Some(selection.host.as_str())
             } else {
@@ -187,28 +327,251 @@ pub async fn handle_message_commands(
                 target_host,
             )
             .await?;
+            if ctx.output.is_json() {
+                return print_json(&message);
+            }
             println!("Sent message: {}", message.body);
         }
 
         MessageCommands::Delete(delete_args) => {
-            // TODO: Interactive message selection
-            let api_url = ctx.home_api_url().await?;
-            let access_token = ctx.get_access_token().await?;
+            let account = ctx.account.ok_or(CliError::MissingAccount)?;
             let target_host = parse_optional_host_input(
                 delete_args.host.as_deref(),
                 ctx.strict_input,
             )?;
+            let (server_id, channel_id, message_id, server_host) =
+                match delete_args.message_id {
+                    Some(message_id) => {
+                        let (Some(server_id), Some(channel_id)) =
+                            (delete_args.server_id, delete_args.channel_id)
+                        else {
+                            return Err(CliError::MissingContext(
+                                "Server ID and channel ID must be passed \
+                                    with message ID."
+                                    .into(),
+                            ));
+                        };
+                        let host = target_host
+                            .unwrap_or_else(|| account.user_ref.host.clone());
+                        (server_id, channel_id, message_id, host)
+                    }
+                    None => {
+                        let selection = get_channel_selection_with_inputs(
+                            ctx,
+                            delete_args.channel_id,
+                            delete_args.server_id,
+                            target_host.as_deref(),
+                        )
+                        .await?;
+                        let message =
+                            get_message_selection(ctx, &selection).await?;
+                        (
+                            selection.server_id,
+                            selection.channel_id,
+                            message.id,
+                            selection.host,
+                        )
+                    }
+                };
+            let api_url = ctx.home_api_url().await?;
+            let access_token = ctx.get_access_token().await?;
+            let target_host = if server_host != account.user_ref.host {
+                Some(server_host.as_str())
+            } else {
+                None
+            };
             requests::messages::delete(
                 ctx.client,
                 &api_url,
                 &access_token,
-                delete_args.server_id,
-                delete_args.channel_id,
-                delete_args.message_id,
+                server_id,
+                channel_id,
+                message_id,
+                target_host,
+            )
+            .await?;
+            if ctx.output.is_json() {
+                return print_json(
+                    &serde_json::json!({ "message_id": message_id }),
+                );
+            }
+            println!("Deleted message: {message_id}");
+        }
+
+        MessageCommands::React(react_args) => {
+            let account = ctx.account.ok_or(CliError::MissingAccount)?;
+            let target_host = parse_optional_host_input(
+                react_args.host.as_deref(),
+                ctx.strict_input,
+            )?;
+            let (target, server_host) = match react_args.message_id {
+                Some(message_id) => {
+                    let (Some(server_id), Some(channel_id)) =
+                        (react_args.server_id, react_args.channel_id)
+                    else {
+                        return Err(CliError::MissingContext(
+                            "Server ID and channel ID must be passed with \
+                                message ID."
+                                .into(),
+                        ));
+                    };
+                    let target = ReactionTarget {
+                        server_id,
+                        channel_id,
+                        message_id,
+                    };
+                    let host = target_host
+                        .unwrap_or_else(|| account.user_ref.host.clone());
+                    (target, host)
+                }
+                None => {
+                    let selection = get_channel_selection_with_inputs(
+                        ctx,
+                        react_args.channel_id,
+                        react_args.server_id,
+                        target_host.as_deref(),
+                    )
+                    .await?;
+                    let message =
+                        get_message_selection(ctx, &selection).await?;
+                    (ReactionTarget::from(&message), selection.host)
+                }
+            };
+            let emoji = unwrap_or_prompt(react_args.emoji.clone(), "Emoji")?;
+            let emoji = validate_emoji(&emoji).map_err(|error| {
+                CliError::InvalidArgument(error.to_string())
+            })?;
+            let api_url = ctx.home_api_url().await?;
+            let access_token = ctx.get_access_token().await?;
+            let target_host = if server_host != account.user_ref.host {
+                Some(server_host.as_str())
+            } else {
+                None
+            };
+            let result = if react_args.remove {
+                requests::reactions::remove(
+                    ctx.client,
+                    &api_url,
+                    &access_token,
+                    target,
+                    &emoji,
+                    target_host,
+                )
+                .await
+            } else {
+                requests::reactions::add(
+                    ctx.client,
+                    &api_url,
+                    &access_token,
+                    target,
+                    &NewReaction { emoji },
+                    target_host,
+                )
+                .await
+            };
+            let message = result.map_err(|error| match error {
+                // The server rejects emoji it doesn't accept, and too many
+                // distinct reactions, as bad requests
+                ClientError::Api { status, body }
+                    if status == StatusCode::BAD_REQUEST =>
+                {
+                    CliError::InvalidArgument(body.message)
+                }
+                error => CliError::from(error),
+            })?;
+            if ctx.output.is_json() {
+                return print_json(&message);
+            }
+            if message.reactions.is_empty() {
+                println!("No reactions on message {}", message.id);
+            } else {
+                let reactions = message
+                    .reactions
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>();
+                println!(
+                    "Reactions on message {}: {}",
+                    message.id,
+                    reactions.join(", ")
+                );
+            }
+        }
+
+        MessageCommands::Search(search_args) => {
+            let account = ctx.account.ok_or(CliError::MissingAccount)?;
+            let target_host = parse_optional_host_input(
+                search_args.host.as_deref(),
+                ctx.strict_input,
+            )?;
+            let (server_id, server_host) = match search_args.server_id {
+                Some(server_id) => (
+                    server_id,
+                    target_host
+                        .unwrap_or_else(|| account.user_ref.host.clone()),
+                ),
+                None => {
+                    let server = get_server_selection(
+                        ctx,
+                        ServerSelectionType::MemberOnly,
+                    )
+                    .await?;
+                    (server.id, server.host)
+                }
+            };
+            let query = unwrap_or_prompt(search_args.query.clone(), "Search")?;
+            let api_url = ctx.home_api_url().await?;
+            let access_token = ctx.get_access_token().await?;
+            let target_host = if server_host != account.user_ref.host {
+                Some(server_host.as_str())
+            } else {
+                None
+            };
+            let messages = requests::messages::search(
+                ctx.client,
+                &api_url,
+                &access_token,
+                server_id,
+                &query,
+                search_args.limit,
+                target_host,
+            )
+            .await?;
+            if ctx.output.is_json() {
+                return print_json(&messages);
+            }
+            if messages.is_empty() {
+                println!("No messages matched \"{query}\".");
+            }
+            for message in messages {
+                println!("{message}");
+            }
+        }
+        MessageCommands::Watch(watch_args) => {
+            let account = ctx.account.ok_or(CliError::MissingAccount)?;
+            let target_host = parse_optional_host_input(
+                watch_args.host.as_deref(),
+                ctx.strict_input,
+            )?;
+            let selection = get_channel_selection_with_inputs(
+                ctx,
+                watch_args.channel_id,
+                watch_args.server_id,
                 target_host.as_deref(),
             )
             .await?;
-            println!("Deleted message: {}", delete_args.message_id);
+            let target_host = if selection.host != account.user_ref.host {
+                Some(selection.host.as_str())
+            } else {
+                None
+            };
+            watch_channel(
+                ctx,
+                selection.server_id,
+                selection.channel_id,
+                target_host,
+            )
+            .await?;
         }
     };
     Ok(())
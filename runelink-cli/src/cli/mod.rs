@@ -2,6 +2,7 @@ use clap::CommandFactory;
 use clap_complete::Shell;
 use context::CliContext;
 use log::LevelFilter;
+use output::OutputFormat;
 use reqwest::Client;
 
 use crate::{
@@ -15,9 +16,11 @@ pub mod config;
 pub mod context;
 pub mod input;
 pub mod messages;
+pub mod output;
 pub mod select;
 pub mod servers;
 pub mod users;
+pub mod watch;
 
 #[derive(clap::Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -35,6 +38,9 @@ pub struct Cli {
     /// Disable automatic identity input normalization and fail on non-canonical values
     #[clap(long)]
     pub strict_input: bool,
+    /// How results are printed; `json` also disables interactive prompts
+    #[clap(long, value_enum, global = true, default_value_t)]
+    pub output: OutputFormat,
     /// Increase logging verbosity (-v, -vv, -vvv)
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
@@ -54,12 +60,14 @@ pub enum Commands {
     User(users::UserArgs),
     /// Manage config
     Config(config::ConfigArgs),
-    /// Generate shell completion scripts
+    /// Print a shell completion script to stdout
+    #[command(hide = true)]
     Completions(CompletionsArgs),
 }
 
 #[derive(clap::Args, Debug)]
 pub struct CompletionsArgs {
+    /// The shell to generate completions for
     #[clap(value_parser = clap::value_parser!(Shell))]
     pub shell: Shell,
 }
@@ -82,6 +90,9 @@ pub async fn handle_cli(
     auth_cache: &mut AuthCache,
 ) -> Result<(), CliError> {
     init_logging(cli.verbose);
+    if cli.output.is_json() {
+        input::disable_prompts();
+    }
     let account_owned = match (&cli.name, &cli.host) {
         (Some(name), Some(host)) => {
             let user_ref = parse_user_ref_input(name, host, cli.strict_input)?;
@@ -95,6 +106,7 @@ pub async fn handle_cli(
         auth_cache,
         account: account_owned.as_ref(),
         strict_input: cli.strict_input,
+        output: cli.output,
     };
     let ctx = &mut ctx_owned;
 
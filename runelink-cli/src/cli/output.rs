@@ -0,0 +1,33 @@
+use serde::Serialize;
+
+use crate::error::CliError;
+
+/// How command results are printed.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// JSON on stdout, for scripts; prompts fail instead of waiting for input
+    Json,
+}
+
+impl OutputFormat {
+    pub fn is_json(self) -> bool {
+        self == OutputFormat::Json
+    }
+}
+
+/// Prints `value` as pretty JSON.
+pub fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<(), CliError> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Prints `value` as JSON on one line, for output that streams.
+pub fn print_json_line<T: Serialize + ?Sized>(
+    value: &T,
+) -> Result<(), CliError> {
+    println!("{}", serde_json::to_string(value)?);
+    Ok(())
+}
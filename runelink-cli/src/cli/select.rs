@@ -8,47 +8,138 @@ use crossterm::{
 use runelink_client::requests;
 use runelink_types::{
     channel::{Channel, ChannelId},
+    message::{Message, MessagePage},
     server::{Server, ServerId},
 };
 use std::collections::HashSet;
 use std::io::Write;
+use std::ops::Range;
 
 use crate::error::CliError;
 
-use super::context::CliContext;
+use super::{context::CliContext, input::ensure_prompts_enabled};
 
+/// Number of recent messages offered by `get_message_selection`.
+const MESSAGE_SELECTION_LIMIT: u32 = 20;
+
+/// Longest message body shown in a selection list, in characters.
+const MESSAGE_PREVIEW_CHARS: usize = 60;
+
+/// Lines of the terminal `select_inline` leaves free for the prompt and the
+/// line the cursor rests on.
+const SELECT_RESERVED_ROWS: usize = 2;
+
+/// Returns the first visible item after moving the selection, scrolling only
+/// as far as needed to keep `selected` on screen.
+fn scroll_offset(offset: usize, selected: usize, height: usize) -> usize {
+    if selected < offset {
+        selected
+    } else if selected >= offset + height {
+        selected + 1 - height
+    } else {
+        offset
+    }
+}
+
+/// Indices of the labels containing `filter`, ignoring case.
+fn filter_matches(labels: &[String], filter: &str) -> Vec<usize> {
+    let filter = filter.to_lowercase();
+    labels
+        .iter()
+        .enumerate()
+        .filter(|(_, label)| label.to_lowercase().contains(&filter))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Draws the prompt line followed by one line per position in `window`, so
+/// the next redraw can always move back up by the same amount.
+fn draw_selection(
+    stdout: &mut std::io::Stdout,
+    prompt: &str,
+    filter: &str,
+    labels: &[String],
+    matches: &[usize],
+    selected: usize,
+    window: Range<usize>,
+) -> std::io::Result<()> {
+    let prompt_line = if filter.is_empty() {
+        prompt.to_string()
+    } else {
+        format!("{prompt}: {filter}")
+    };
+    execute!(
+        stdout,
+        Clear(ClearType::CurrentLine),
+        MoveToColumn(0),
+        Print(format!("{prompt_line}\n"))
+    )?;
+    let first = window.start;
+    for position in window {
+        let line = match matches.get(position) {
+            Some(&i) => {
+                let prefix = if position == selected { "> " } else { "  " };
+                format!("{prefix}{}", labels[i])
+            }
+            None if position == first && matches.is_empty() => {
+                "  (no matches)".into()
+            }
+            None => String::new(),
+        };
+        execute!(
+            stdout,
+            Clear(ClearType::CurrentLine),
+            MoveToColumn(0),
+            Print(format!("{line}\n"))
+        )?;
+    }
+    Ok(())
+}
+
+/// Lets the user pick one of `items` with the arrow keys.
+///
+/// Typing narrows the list to items whose label contains the typed text;
+/// Backspace edits it and Esc clears it, cancelling once it is empty.
 pub fn select_inline<'a, T, F>(
     items: &'a [T],
     prompt: &str,
     display: F,
-) -> std::io::Result<Option<&'a T>>
+) -> Result<Option<&'a T>, CliError>
 where
     F: Fn(&T) -> String,
 {
+    ensure_prompts_enabled()?;
     if items.is_empty() {
         println!("(no items to select)");
         return Ok(None);
     }
+    let labels = items.iter().map(&display).collect::<Vec<_>>();
+    // Only a window of the list is drawn, so redrawing never scrolls the
+    // terminal and moving back up always lands on the prompt line
+    let rows = crossterm::terminal::size().map_or(24, |(_, rows)| rows);
+    let height = (rows as usize)
+        .saturating_sub(SELECT_RESERVED_ROWS)
+        .clamp(1, items.len());
+    let mut filter = String::new();
+    let mut matches = (0..items.len()).collect::<Vec<_>>();
+    // Positions within `matches`, not `items`
+    let mut selected = 0;
+    let mut offset = 0;
+
     let mut stdout = std::io::stdout();
     enable_raw_mode()?;
-    execute!(
-        stdout,
-        Hide,
-        MoveToColumn(0),
-        Print(format!("{}\n", prompt))
+    execute!(stdout, Hide, MoveToColumn(0))?;
+    draw_selection(
+        &mut stdout,
+        prompt,
+        &filter,
+        &labels,
+        &matches,
+        selected,
+        offset..offset + height,
     )?;
-
-    for (i, item) in items.iter().enumerate() {
-        let prefix = if i == 0 { "> " } else { "  " };
-        execute!(
-            stdout,
-            MoveToColumn(0),
-            Print(format!("{}{}\n", prefix, display(item)))
-        )?;
-    }
     stdout.flush()?;
 
-    let mut selected = 0;
     loop {
         if let Event::Key(KeyEvent {
             kind: KeyEventKind::Press,
@@ -57,71 +148,92 @@ where
             ..
         }) = crossterm::event::read()?
         {
+            let last = matches.len().saturating_sub(1);
             match (code, modifiers) {
                 // Ctrl-C - propagate as Interrupted
                 (KeyCode::Char('c'), KeyModifiers::CONTROL) => {
                     execute!(stdout, MoveToColumn(0))?;
                     disable_raw_mode()?;
                     execute!(stdout, Show)?;
-                    panic!("Interrupted by Ctrl-C");
+                    return Err(CliError::Interrupted);
                 }
-                // Esc or 'q' - cancel
-                (KeyCode::Esc, _) | (KeyCode::Char('q'), _) => {
-                    execute!(stdout, MoveToColumn(0))?;
-                    disable_raw_mode()?;
-                    execute!(stdout, Show)?;
-                    return Ok(None);
+                // Esc - clear the filter, or cancel
+                (KeyCode::Esc, _) => {
+                    if filter.is_empty() {
+                        execute!(stdout, MoveToColumn(0))?;
+                        disable_raw_mode()?;
+                        execute!(stdout, Show)?;
+                        return Ok(None);
+                    }
+                    filter.clear();
+                    matches = filter_matches(&labels, &filter);
+                    selected = 0;
                 }
                 // Enter - confirm
                 (KeyCode::Enter, _) => {
+                    let Some(&index) = matches.get(selected) else {
+                        continue;
+                    };
                     execute!(
                         stdout,
-                        MoveUp(items.len() as u16),
+                        MoveUp(height as u16),
                         MoveToColumn(0),
                         Clear(ClearType::FromCursorDown),
-                        Print(format!("> {}\n", display(&items[selected]))),
+                        Print(format!("> {}\n", labels[index])),
                         MoveToColumn(0),
                     )?;
                     stdout.flush()?;
                     disable_raw_mode()?;
                     execute!(stdout, Show)?;
-                    return Ok(Some(&items[selected]));
+                    return Ok(Some(&items[index]));
                 }
-                // Up or 'k'
-                (KeyCode::Up, _) | (KeyCode::Char('k'), _) => {
-                    selected = if selected == 0 { 0 } else { selected - 1 };
+                (KeyCode::Backspace, _) => {
+                    filter.pop();
+                    matches = filter_matches(&labels, &filter);
+                    selected = 0;
                 }
-                // Down or 'j'
-                (KeyCode::Down, _) | (KeyCode::Char('j'), _) => {
-                    selected = if selected == items.len() - 1 {
-                        items.len() - 1
-                    } else {
-                        selected + 1
-                    };
+                (KeyCode::Char(c), modifiers)
+                    if !modifiers.intersects(
+                        KeyModifiers::CONTROL | KeyModifiers::ALT,
+                    ) =>
+                {
+                    filter.push(c);
+                    matches = filter_matches(&labels, &filter);
+                    selected = 0;
+                }
+                (KeyCode::Up, _) => {
+                    selected = selected.saturating_sub(1);
                 }
-                (KeyCode::Char('g'), KeyModifiers::NONE)
-                | (KeyCode::Home, _) => {
+                (KeyCode::Down, _) => {
+                    selected = (selected + 1).min(last);
+                }
+                (KeyCode::PageUp, _) => {
+                    selected = selected.saturating_sub(height);
+                }
+                (KeyCode::PageDown, _) => {
+                    selected = (selected + height).min(last);
+                }
+                (KeyCode::Home, _) => {
                     selected = 0;
                 }
-                (KeyCode::Char('g'), KeyModifiers::SHIFT)
-                | (KeyCode::Char('G'), _)
-                | (KeyCode::End, _) => {
-                    selected = items.len() - 1;
+                (KeyCode::End, _) => {
+                    selected = last;
                 }
                 _ => {}
             }
+            offset = scroll_offset(offset, selected, height);
 
-            // Redraw the list in place
-            execute!(stdout, MoveUp(items.len() as u16))?;
-            for (i, item) in items.iter().enumerate() {
-                let prefix = if i == selected { "> " } else { "  " };
-                execute!(
-                    stdout,
-                    Clear(ClearType::CurrentLine),
-                    MoveToColumn(0),
-                    Print(format!("{}{}\n", prefix, display(item)))
-                )?;
-            }
+            // Redraw the prompt and viewport in place
+            execute!(stdout, MoveUp(height as u16 + 1))?;
+            draw_selection(
+                &mut stdout,
+                prompt,
+                &filter,
+                &labels,
+                &matches,
+                selected,
+                offset..offset + height,
+            )?;
             stdout.flush()?;
         }
     }
@@ -275,3 +387,63 @@ pub async fn get_channel_selection_with_inputs(
         }
     }
 }
+
+/// Formats a message as its author and the start of its body, on one line.
+fn message_preview(message: &Message) -> String {
+    let author = message
+        .author
+        .as_ref()
+        .map(|u| u.name.as_str())
+        .unwrap_or("anon");
+    let body = message.body.replace('\n', " ");
+    let preview = if body.chars().count() > MESSAGE_PREVIEW_CHARS {
+        let truncated =
+            body.chars().take(MESSAGE_PREVIEW_CHARS).collect::<String>();
+        format!("{truncated}...")
+    } else {
+        body
+    };
+    if message.system {
+        format!("* {preview}")
+    } else {
+        format!("{author}: {preview}")
+    }
+}
+
+pub async fn get_message_selection(
+    ctx: &mut CliContext<'_>,
+    channel: &ChannelSelection,
+) -> Result<Message, CliError> {
+    let api_url = ctx.home_api_url().await?;
+    let access_token = ctx.get_access_token().await?;
+    let account = ctx.account.ok_or(CliError::MissingAccount)?;
+    let target_host = if channel.host != account.user_ref.host {
+        Some(channel.host.as_str())
+    } else {
+        None
+    };
+    let messages = requests::messages::fetch_by_channel(
+        ctx.client,
+        &api_url,
+        &access_token,
+        channel.server_id,
+        channel.channel_id,
+        &MessagePage {
+            before: None,
+            limit: Some(MESSAGE_SELECTION_LIMIT),
+        },
+        target_host,
+    )
+    .await?;
+    if messages.is_empty() {
+        return Err(CliError::NoActionPossible(
+            "No messages in this channel.\n\
+                For more information, try `rune message --help`."
+                .into(),
+        ));
+    }
+    let message = select_inline(&messages, "Select message", message_preview)?
+        .ok_or(CliError::Cancellation)?;
+    println!();
+    Ok(message.clone())
+}
@@ -4,7 +4,8 @@
 
 use runelink_client::requests;
 use runelink_types::server::{
-    NewServer, NewServerMembership, ServerId, ServerRole,
+    NewServer, NewServerInvite, NewServerMembership, ServerId, ServerRole,
+    ServerVisibility,
 };
 
 use crate::util::parse_optional_host_input;
@@ -13,6 +14,7 @@ use crate::{error::CliError, util::group_memberships_by_host};
 use super::{
     context::CliContext,
     input::{read_input, unwrap_or_prompt},
+    output::print_json,
     select::{ServerSelectionType, get_server_selection},
 };
 
@@ -32,6 +34,8 @@ pub enum ServerCommands {
     Create(ServerCreateArgs),
     /// Create a new server
     Join(ServerJoinArgs),
+    /// Create an invite code for a server
+    Invite(ServerInviteArgs),
     /// Leave a server
     Leave(ServerLeaveArgs),
     /// Delete a server
@@ -66,6 +70,9 @@ pub struct ServerCreateArgs {
     /// Skip description cli prompt
     #[clap(long)]
     pub no_description: bool,
+    /// Make the server private (hidden from listings)
+    #[clap(long)]
+    pub private: bool,
     /// The host of the server
     #[clap(long)]
     pub host: Option<String>,
@@ -76,6 +83,25 @@ pub struct ServerJoinArgs {
     /// The ID of the server
     #[clap(long)]
     pub server_id: Option<ServerId>,
+    /// An invite code for the server
+    #[clap(long)]
+    pub invite: Option<String>,
+    /// The host of the server
+    #[clap(long)]
+    pub host: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ServerInviteArgs {
+    /// The ID of the server
+    #[clap(long)]
+    pub server_id: Option<ServerId>,
+    /// How many times the invite can be used (unlimited if not provided)
+    #[clap(long)]
+    pub max_uses: Option<u32>,
+    /// Hours until the invite expires (never if not provided)
+    #[clap(long)]
+    pub expires_in_hours: Option<i64>,
     /// The host of the server
     #[clap(long)]
     pub host: Option<String>,
@@ -121,6 +147,9 @@ pub async fn handle_server_commands(
                     Some(host.as_str()),
                 )
                 .await?;
+                if ctx.output.is_json() {
+                    return print_json(&servers);
+                }
                 if servers.is_empty() {
                     println!("No servers found in host: {host}");
                 } else {
@@ -137,6 +166,9 @@ pub async fn handle_server_commands(
                     account.user_ref.clone(),
                 )
                 .await?;
+                if ctx.output.is_json() {
+                    return print_json(&memberships);
+                }
                 if memberships.is_empty() {
                     println!(
                         "No servers joined.\n\
@@ -179,6 +211,9 @@ pub async fn handle_server_commands(
                 target_host.as_deref(),
             )
             .await?;
+            if ctx.output.is_json() {
+                return print_json(&server);
+            }
             println!(
                 "{host} / {title} ({id})",
                 host = server.host,
@@ -199,12 +234,24 @@ pub async fn handle_server_commands(
                 unwrap_or_prompt(create_args.title.clone(), "Server Title")?;
             let description = if create_args.description.is_some() {
                 create_args.description.clone()
-            } else if create_args.no_description {
+            } else if create_args.no_description || ctx.output.is_json() {
                 None
             } else {
                 read_input("Server Description (leave blank for none):\n> ")?
             };
-            let new_server = NewServer { title, description };
+            let visibility = if create_args.private {
+                ServerVisibility::Private
+            } else {
+                ServerVisibility::Public
+            };
+            let new_server = NewServer {
+                title,
+                description,
+                visibility,
+            };
+            new_server.validate().map_err(|error| {
+                CliError::InvalidArgument(error.to_string())
+            })?;
             let server = requests::servers::create(
                 ctx.client,
                 &api_url,
@@ -213,6 +260,9 @@ pub async fn handle_server_commands(
                 target_host.as_deref(),
             )
             .await?;
+            if ctx.output.is_json() {
+                return print_json(&server);
+            }
             println!("Created server: {}", server.verbose());
         }
 
@@ -224,6 +274,21 @@ pub async fn handle_server_commands(
                 join_args.host.as_deref(),
                 ctx.strict_input,
             )?;
+            if let Some(code) = &join_args.invite {
+                let membership = requests::invites::redeem(
+                    ctx.client,
+                    &api_url,
+                    &access_token,
+                    code,
+                    target_host.as_deref(),
+                )
+                .await?;
+                if ctx.output.is_json() {
+                    return print_json(&membership);
+                }
+                println!("Joined server: {}", membership.server.verbose());
+                return Ok(());
+            }
             let server = if let Some(server_id) = join_args.server_id {
                 requests::servers::fetch_by_id(
                     ctx.client,
@@ -248,16 +313,60 @@ pub async fn handle_server_commands(
                 server_host: server.host.clone(),
                 role: ServerRole::Member,
             };
-            let _member = requests::memberships::create(
+            let membership = requests::memberships::create(
                 ctx.client,
                 &api_url,
                 &access_token,
                 &new_member,
             )
             .await?;
+            if ctx.output.is_json() {
+                return print_json(&membership);
+            }
             println!("Joined server: {}", server.verbose());
         }
 
+        ServerCommands::Invite(invite_args) => {
+            let api_url = ctx.home_api_url().await?;
+            let access_token = ctx.get_access_token().await?;
+            let target_host = parse_optional_host_input(
+                invite_args.host.as_deref(),
+                ctx.strict_input,
+            )?;
+            // Private servers can't be fetched anonymously, so an explicit
+            // ID is used as given
+            let (server_id, server_host) = if let Some(server_id) =
+                invite_args.server_id
+            {
+                (server_id, target_host)
+            } else {
+                let server =
+                    get_server_selection(ctx, ServerSelectionType::MemberOnly)
+                        .await?;
+                (server.id, Some(server.host))
+            };
+            let new_invite = NewServerInvite {
+                expires_at: invite_args.expires_in_hours.map(|hours| {
+                    time::OffsetDateTime::now_utc()
+                        + time::Duration::hours(hours)
+                }),
+                max_uses: invite_args.max_uses,
+            };
+            let invite = requests::invites::create(
+                ctx.client,
+                &api_url,
+                &access_token,
+                server_id,
+                &new_invite,
+                server_host.as_deref(),
+            )
+            .await?;
+            if ctx.output.is_json() {
+                return print_json(&invite);
+            }
+            println!("Invite code: {}", invite.code);
+        }
+
         ServerCommands::Leave(leave_args) => {
             let account = ctx.account.ok_or(CliError::MissingAccount)?;
             let api_url = ctx.home_api_url().await?;
@@ -290,6 +399,9 @@ pub async fn handle_server_commands(
                 Some(server.host.as_str()),
             )
             .await?;
+            if ctx.output.is_json() {
+                return print_json(&server);
+            }
             println!("Left server: {}", server.verbose());
         }
 
@@ -319,6 +431,11 @@ pub async fn handle_server_commands(
                 target_host.as_deref(),
             )
             .await?;
+            if ctx.output.is_json() {
+                return print_json(
+                    &serde_json::json!({ "server_id": server_id }),
+                );
+            }
             println!("Deleted server: {server_id}");
         }
     }
@@ -1,12 +1,12 @@
 use runelink_client::requests;
-use runelink_types::server::ServerId;
+use runelink_types::{server::ServerId, user::UserQuery};
 
 use crate::{
     error::CliError,
     util::{parse_optional_host_input, parse_user_ref_input},
 };
 
-use super::context::CliContext;
+use super::{context::CliContext, output::print_json};
 
 #[derive(clap::Args, Debug)]
 pub struct UserArgs {
@@ -40,6 +40,15 @@ pub struct UserListArgs {
     /// The ID of the server
     #[clap(long)]
     pub server_id: Option<ServerId>,
+    /// Only list users whose name starts with this
+    #[clap(long)]
+    pub prefix: Option<String>,
+    /// The most users to list
+    #[clap(long)]
+    pub limit: Option<u32>,
+    /// How many users to skip
+    #[clap(long)]
+    pub offset: Option<u32>,
 }
 
 pub async fn handle_user_commands(
@@ -65,13 +74,23 @@ pub async fn handle_user_commands(
                 .await?;
                 users = members.into_iter().map(|m| m.user).collect();
             } else {
+                let query = UserQuery {
+                    name_prefix: list_args.prefix.clone(),
+                    limit: list_args.limit,
+                    offset: list_args.offset,
+                    ..Default::default()
+                };
                 users = requests::users::fetch_all(
                     ctx.client,
                     &api_url,
+                    &query,
                     target_host.as_deref(),
                 )
                 .await?;
             }
+            if ctx.output.is_json() {
+                return print_json(&users);
+            }
             for user in users {
                 println!("{user}");
             }
@@ -87,6 +106,9 @@ pub async fn handle_user_commands(
             let user =
                 requests::users::fetch_by_ref(ctx.client, &api_url, user_ref)
                     .await?;
+            if ctx.output.is_json() {
+                return print_json(&user);
+            }
             println!("{user}");
         }
     }
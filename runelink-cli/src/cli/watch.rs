@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+
+use runelink_client::{
+    requests,
+    util::get_client_ws_url,
+    ws::{WsClient, WsClientOptions, WsConnectionState},
+};
+use runelink_types::{
+    channel::ChannelId,
+    message::{Message, MessageId, MessagePage},
+    server::ServerId,
+    ws::{
+        AuthTokenAccessRequest, ClientWsRequest, ClientWsUpdate,
+        WS_PROTOCOL_VERSION,
+    },
+};
+use time::OffsetDateTime;
+use tokio::sync::watch;
+
+use crate::error::CliError;
+
+use super::{
+    context::CliContext,
+    output::{OutputFormat, print_json_line},
+};
+
+/// Messages printed before following a channel.
+const HISTORY_LIMIT: u32 = 50;
+
+/// When each printed message was last edited, as of printing it.
+type Printed = HashMap<MessageId, Option<OffsetDateTime>>;
+
+/// Prints a channel's recent history, then its new, edited and deleted
+/// messages as they happen until Ctrl-C.
+///
+/// With JSON output each one is printed as a `ClientWsUpdate` on its own
+/// line, history included.
+///
+/// A dropped connection is reopened, printing whatever arrived meanwhile.
+pub async fn watch_channel(
+    ctx: &mut CliContext<'_>,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    target_host: Option<&str>,
+) -> Result<(), CliError> {
+    let follow = follow_channel(ctx, server_id, channel_id, target_host);
+    tokio::select! {
+        result = follow => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    }
+}
+
+async fn follow_channel(
+    ctx: &mut CliContext<'_>,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    target_host: Option<&str>,
+) -> Result<(), CliError> {
+    let api_url = ctx.home_api_url().await?;
+    let secure = api_url.starts_with("https://");
+    let ws_url = get_client_ws_url(ctx.home_host()?, secure);
+    let client = WsClient::connect(&ws_url, WsClientOptions::default()).await?;
+    let mut updates = client.updates();
+    let mut state = client.state_changes();
+    let mut printed = Printed::new();
+    loop {
+        // The server forgets the session whenever the connection drops, so
+        // this runs again after every reconnect
+        let session_state = *state.borrow_and_update();
+        let session = start_session(
+            ctx,
+            &client,
+            &api_url,
+            server_id,
+            channel_id,
+            target_host,
+            &mut printed,
+        )
+        .await;
+        match session {
+            Ok(()) => {}
+            Err(error @ CliError::WebsocketError(_)) => {
+                // Still on the same connection, so retrying won't help
+                if client.state() == session_state {
+                    return Err(error);
+                }
+                eprintln!("Connection lost: {error}");
+                wait_for_reconnect(&mut state).await?;
+                continue;
+            }
+            Err(error) => return Err(error),
+        }
+
+        loop {
+            tokio::select! {
+                update = updates.next() => {
+                    let Some(update) = update else {
+                        return Err(connection_closed());
+                    };
+                    print_update(update, channel_id, &mut printed, ctx.output)?;
+                }
+                changed = state.changed() => {
+                    if changed.is_err() {
+                        return Err(connection_closed());
+                    }
+                    match *state.borrow_and_update() {
+                        WsConnectionState::Reconnecting => {
+                            eprintln!("Connection lost, reconnecting...");
+                        }
+                        WsConnectionState::Connected { .. } => break,
+                        WsConnectionState::Closed => {
+                            return Err(connection_closed());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Negotiates the protocol, authenticates the connection, subscribes to the
+/// channel and prints the messages not printed yet.
+async fn start_session(
+    ctx: &mut CliContext<'_>,
+    client: &WsClient,
+    api_url: &str,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    target_host: Option<&str>,
+    printed: &mut Printed,
+) -> Result<(), CliError> {
+    client
+        .request(ClientWsRequest::Hello {
+            protocol_version: WS_PROTOCOL_VERSION,
+        })
+        .await?;
+    let access_token = ctx.get_access_token().await?;
+    client
+        .request(ClientWsRequest::AuthTokenAccess(AuthTokenAccessRequest {
+            access_token: access_token.clone(),
+        }))
+        .await?;
+    client
+        .request(ClientWsRequest::Subscribe {
+            server_id,
+            channel_id,
+        })
+        .await?;
+    // Fetched after subscribing so nothing falls in between
+    let history = requests::messages::fetch_by_channel(
+        ctx.client,
+        api_url,
+        &access_token,
+        server_id,
+        channel_id,
+        &MessagePage {
+            before: None,
+            limit: Some(HISTORY_LIMIT),
+        },
+        target_host,
+    )
+    .await?;
+    for message in history.into_iter().rev() {
+        print_message(&message, printed, ctx.output)?;
+    }
+    Ok(())
+}
+
+fn print_update(
+    update: ClientWsUpdate,
+    channel_id: ChannelId,
+    printed: &mut Printed,
+    output: OutputFormat,
+) -> Result<(), CliError> {
+    let text = match &update {
+        ClientWsUpdate::MessageUpserted(message)
+            if message.channel_id == channel_id =>
+        {
+            return print_message(message, printed, output);
+        }
+        ClientWsUpdate::MessageDeleted {
+            channel_id: deleted_from,
+            message_id,
+            ..
+        } if *deleted_from == channel_id => {
+            format!("(deleted message {message_id})")
+        }
+        ClientWsUpdate::ChannelPurged {
+            channel_id: purged, ..
+        } if *purged == channel_id => "(channel purged)".to_string(),
+        // Mentions in the watched channel already show as messages
+        ClientWsUpdate::Mentioned {
+            message,
+            channel_id: mentioned_in,
+        } if *mentioned_in != channel_id => {
+            format!("(mentioned in channel {mentioned_in}) {message}")
+        }
+        _ => return Ok(()),
+    };
+    if output.is_json() {
+        return print_json_line(&update);
+    }
+    println!("{text}");
+    Ok(())
+}
+
+/// Prints a message the first time it's seen, and again whenever it was
+/// edited since.
+fn print_message(
+    message: &Message,
+    printed: &mut Printed,
+    output: OutputFormat,
+) -> Result<(), CliError> {
+    let previous = printed.insert(message.id, message.edited_at);
+    if previous == Some(message.edited_at) {
+        return Ok(());
+    }
+    if output.is_json() {
+        return print_json_line(&ClientWsUpdate::MessageUpserted(
+            message.clone(),
+        ));
+    }
+    println!("{message}");
+    Ok(())
+}
+
+async fn wait_for_reconnect(
+    state: &mut watch::Receiver<WsConnectionState>,
+) -> Result<(), CliError> {
+    let reconnected = state
+        .wait_for(|state| *state != WsConnectionState::Reconnecting)
+        .await
+        .map(|state| *state != WsConnectionState::Closed);
+    match reconnected {
+        Ok(true) => Ok(()),
+        _ => Err(connection_closed()),
+    }
+}
+
+fn connection_closed() -> CliError {
+    CliError::WebsocketError("connection closed".into())
+}
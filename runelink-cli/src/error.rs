@@ -35,9 +35,15 @@ pub enum CliError {
     #[error("No Action Possible: {0}")]
     NoActionPossible(String),
 
+    #[error("Websocket error: {0}")]
+    WebsocketError(String),
+
     #[error("Operation Canceled")]
     Cancellation,
 
+    #[error("Interrupted")]
+    Interrupted,
+
     #[error("Unexpected error: {0}")]
     Unknown(String),
 }
@@ -49,7 +55,15 @@ impl From<ClientError> for CliError {
             ClientError::Status(status, message) => {
                 CliError::ApiStatusError { status, message }
             }
+            ClientError::Api { status, body } => CliError::ApiStatusError {
+                status,
+                message: body.message,
+            },
             ClientError::Json(e) => CliError::JsonError(e),
+            ClientError::Remote(e) => CliError::InvalidArgument(e.message),
+            e @ (ClientError::Websocket(_)
+            | ClientError::Disconnected
+            | ClientError::Timeout) => CliError::WebsocketError(e.to_string()),
         }
     }
 }
@@ -109,7 +123,9 @@ impl From<CliError> for ExitCode {
             CliError::MissingContext(_) => EX_USAGE,
             CliError::MissingAccount => EX_USAGE,
             CliError::NoActionPossible(_) => EX_USAGE,
+            CliError::WebsocketError(_) => EX_UNAVAILABLE,
             CliError::Cancellation => EX_USER_CANCEL,
+            CliError::Interrupted => EX_USER_CANCEL,
             CliError::Unknown(_) => EX_SOFTWARE,
         })
     }
@@ -15,6 +15,8 @@ const CONFIG_FILENAME: &str = "config.json";
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct AppConfig {
     pub default_account: Option<UserRef>,
+    #[serde(default)]
+    pub default_host: Option<String>,
     pub default_server: Option<Uuid>,
     pub accounts: Vec<AccountConfig>,
     #[serde(default)]
@@ -50,6 +52,10 @@ impl AppConfig {
         save_data(self, CONFIG_FILENAME)
     }
 
+    pub fn path() -> Result<PathBuf, CliError> {
+        get_data_file_path(CONFIG_FILENAME)
+    }
+
     pub fn get_default_account(&self) -> Option<&AccountConfig> {
         self.default_account.as_ref().and_then(|user_ref| {
             self.accounts.iter().find(|ac| ac.user_ref == *user_ref)
@@ -166,7 +172,13 @@ fn is_transport_error(error: &ClientError) -> bool {
         ClientError::Reqwest(error) => {
             error.is_connect() || error.is_timeout() || error.is_request()
         }
-        ClientError::Status(_, _) | ClientError::Json(_) => false,
+        ClientError::Websocket(_)
+        | ClientError::Disconnected
+        | ClientError::Timeout => true,
+        ClientError::Status(_, _)
+        | ClientError::Api { .. }
+        | ClientError::Json(_)
+        | ClientError::Remote(_) => false,
     }
 }
 
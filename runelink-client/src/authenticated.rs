@@ -0,0 +1,166 @@
+use std::{future::Future, sync::Arc};
+
+use log::info;
+use reqwest::{Client, StatusCode};
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+
+use crate::{error::Result, requests};
+
+/// Access tokens this close to expiring are refreshed before use.
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+/// The tokens an `AuthenticatedClient` signs requests with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    pub access_token: Option<String>,
+    /// Unix timestamp the access token expires at, if known.
+    pub expires_at: Option<i64>,
+    pub refresh_token: String,
+    pub scope: Option<String>,
+    pub client_id: Option<String>,
+}
+
+impl Session {
+    /// The access token, unless it's missing or about to expire.
+    fn usable_access_token(&self, now: i64) -> Option<&str> {
+        let access_token = self.access_token.as_deref()?;
+        match self.expires_at {
+            Some(expires_at) if expires_at <= now + EXPIRY_MARGIN_SECS => None,
+            _ => Some(access_token),
+        }
+    }
+}
+
+/// An HTTP client for one account's home server that keeps its access token
+/// fresh.
+///
+/// Tokens are refreshed through `/auth/token` when they're about to expire
+/// or a request is rejected with 401. Concurrent requests share a single
+/// refresh.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedClient {
+    client: Client,
+    api_url: String,
+    session: Arc<Mutex<Session>>,
+}
+
+impl AuthenticatedClient {
+    pub fn new(client: Client, api_url: String, session: Session) -> Self {
+        Self {
+            client,
+            api_url,
+            session: Arc::new(Mutex::new(session)),
+        }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn api_url(&self) -> &str {
+        &self.api_url
+    }
+
+    /// The current tokens, e.g. to persist them after a refresh.
+    pub async fn session(&self) -> Session {
+        self.session.lock().await.clone()
+    }
+
+    /// Returns a usable access token, refreshing it first if needed.
+    pub async fn access_token(&self) -> Result<String> {
+        let mut session = self.session.lock().await;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        if let Some(access_token) = session.usable_access_token(now) {
+            return Ok(access_token.to_string());
+        }
+        self.refresh(&mut session).await
+    }
+
+    /// Runs a request with an access token, refreshing the token and
+    /// retrying once if the server rejects it.
+    ///
+    /// `request` is given the token and may be called twice.
+    pub async fn send<T, F, Fut>(&self, request: F) -> Result<T>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let access_token = self.access_token().await?;
+        match request(access_token.clone()).await {
+            Err(error) if error.status() == Some(StatusCode::UNAUTHORIZED) => {
+                let access_token = self.refresh_rejected(&access_token).await?;
+                request(access_token).await
+            }
+            result => result,
+        }
+    }
+
+    /// Replaces a token the server rejected, unless a concurrent request
+    /// already has.
+    async fn refresh_rejected(&self, rejected: &str) -> Result<String> {
+        let mut session = self.session.lock().await;
+        if let Some(access_token) = &session.access_token
+            && access_token != rejected
+        {
+            return Ok(access_token.clone());
+        }
+        self.refresh(&mut session).await
+    }
+
+    /// Called with the session locked, so concurrent callers wait for this
+    /// refresh instead of starting their own.
+    async fn refresh(&self, session: &mut Session) -> Result<String> {
+        info!("refreshing access token");
+        let token_response = requests::auth::token_refresh(
+            &self.client,
+            &self.api_url,
+            &session.refresh_token,
+            session.scope.as_deref(),
+            session.client_id.as_deref(),
+        )
+        .await?;
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        session.access_token = Some(token_response.access_token.clone());
+        session.expires_at = Some(now + token_response.expires_in);
+        if !token_response.refresh_token.is_empty() {
+            session.refresh_token = token_response.refresh_token;
+        }
+        Ok(token_response.access_token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(expires_at: Option<i64>) -> Session {
+        Session {
+            access_token: Some("access".into()),
+            expires_at,
+            refresh_token: "refresh".into(),
+            scope: None,
+            client_id: None,
+        }
+    }
+
+    #[test]
+    fn test_usable_access_token_respects_expiry_margin() {
+        let now = 1_000;
+        assert_eq!(session(None).usable_access_token(now), Some("access"));
+        assert_eq!(
+            session(Some(now + EXPIRY_MARGIN_SECS + 1))
+                .usable_access_token(now),
+            Some("access")
+        );
+        assert_eq!(
+            session(Some(now + EXPIRY_MARGIN_SECS)).usable_access_token(now),
+            None
+        );
+
+        let mut missing = session(None);
+        missing.access_token = None;
+        assert_eq!(missing.usable_access_token(now), None);
+    }
+}
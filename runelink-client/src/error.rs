@@ -1,5 +1,7 @@
 use reqwest::StatusCode;
+use runelink_types::ws::{ApiErrorBody, WsError, WsErrorCode};
 use thiserror::Error;
+use tokio_tungstenite::tungstenite;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -8,9 +10,55 @@ pub enum Error {
     #[error("request error: {0}")]
     Reqwest(#[from] reqwest::Error),
 
+    /// An error status without a structured body, e.g. from a proxy.
     #[error("unexpected status {0}: {1}")]
     Status(StatusCode, String),
 
+    #[error("{} ({status}): {}", body.code, body.message)]
+    Api {
+        status: StatusCode,
+        body: ApiErrorBody,
+    },
+
     #[error("json error: {0}")]
     Json(#[from] serde_json::Error),
+
+    #[error("websocket error: {0}")]
+    Websocket(Box<tungstenite::Error>),
+
+    #[error("remote error {}: {}", .0.code, .0.message)]
+    Remote(WsError),
+
+    #[error("websocket disconnected")]
+    Disconnected,
+
+    #[error("websocket request timed out")]
+    Timeout,
+}
+
+impl Error {
+    /// The HTTP status the server answered with, if it answered.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Status(status, _) | Error::Api { status, .. } => {
+                Some(*status)
+            }
+            _ => None,
+        }
+    }
+
+    /// The server's error code, if it sent one.
+    pub fn code(&self) -> Option<WsErrorCode> {
+        match self {
+            Error::Api { body, .. } => Some(body.code),
+            Error::Remote(error) => Some(error.code),
+            _ => None,
+        }
+    }
+}
+
+impl From<tungstenite::Error> for Error {
+    fn from(err: tungstenite::Error) -> Self {
+        Error::Websocket(Box::new(err))
+    }
 }
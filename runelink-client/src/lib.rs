@@ -1,6 +1,8 @@
+pub mod authenticated;
 pub mod error;
 pub mod requests;
 pub mod util;
 pub mod validation;
+pub mod ws;
 
 pub use error::*;
@@ -3,9 +3,9 @@ use reqwest::Client;
 use runelink_types::{SignupRequest, TokenResponse, User};
 use std::collections::HashMap;
 
-use crate::error::{Error, Result};
+use crate::error::Result;
 
-use super::post_json;
+use super::{error_from_response, post_json};
 
 /// Create a new user account.
 ///
@@ -46,12 +46,8 @@ pub async fn token_password(
     }
 
     let response = client.post(&url).form(&form).send().await?;
-    let status = response.status();
-    if !status.is_success() {
-        let message = response.text().await.unwrap_or_else(|e| {
-            format!("Failed to get error message body: {e}")
-        });
-        return Err(Error::Status(status, message));
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
     }
     let data = response.json::<TokenResponse>().await?;
     Ok(data)
@@ -81,12 +77,8 @@ pub async fn token_refresh(
     }
 
     let response = client.post(&url).form(&form).send().await?;
-    let status = response.status();
-    if !status.is_success() {
-        let message = response.text().await.unwrap_or_else(|e| {
-            format!("Failed to get error message body: {e}")
-        });
-        return Err(Error::Status(status, message));
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
     }
     let data = response.json::<TokenResponse>().await?;
     Ok(data)
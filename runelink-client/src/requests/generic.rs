@@ -1,18 +1,29 @@
 use log::debug;
-use reqwest::Client;
+use reqwest::{Client, Response};
+use runelink_types::ws::ApiErrorBody;
 use serde::{Serialize, de::DeserializeOwned};
 
 use crate::error::{Error, Result};
 
+/// Turns an unsuccessful response into an error, keeping the server's
+/// structured error body when it sent one.
+pub(crate) async fn error_from_response(response: Response) -> Error {
+    let status = response.status();
+    let text = match response.text().await {
+        Ok(text) => text,
+        Err(e) => format!("Failed to get error message body: {e}"),
+    };
+    match serde_json::from_str::<ApiErrorBody>(&text) {
+        Ok(body) => Error::Api { status, body },
+        Err(_) => Error::Status(status, text),
+    }
+}
+
 pub async fn fetch_text(client: &Client, url: &str) -> Result<String> {
     debug!("fetching text: {url}");
     let response = client.get(url).send().await?;
-    let status = response.status();
-    if !status.is_success() {
-        let message = response.text().await.unwrap_or_else(|e| {
-            format!("Failed to get error message body: {e}")
-        });
-        return Err(Error::Status(status, message));
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
     }
     let text_data = response.text().await?;
     Ok(text_data)
@@ -24,12 +35,8 @@ where
 {
     debug!("fetching json: {url}");
     let response = client.get(url).send().await?;
-    let status = response.status();
-    if !status.is_success() {
-        let message = response.text().await.unwrap_or_else(|e| {
-            format!("Failed to get error message body: {e}")
-        });
-        return Err(Error::Status(status, message));
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
     }
     let data = response.json::<T>().await?;
     Ok(data)
@@ -49,12 +56,8 @@ where
         serde_json::to_string_pretty(request_body).unwrap()
     );
     let response = client.post(url).json(request_body).send().await?;
-    let status = response.status();
-    if !status.is_success() {
-        let message = response.text().await.unwrap_or_else(|e| {
-            format!("Failed to get error message body: {e}")
-        });
-        return Err(Error::Status(status, message));
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
     }
     let data = response.json::<O>().await?;
     Ok(data)
@@ -72,12 +75,8 @@ pub async fn fetch_text_authed(
         .header("Authorization", format!("Bearer {access_token}"))
         .send()
         .await?;
-    let status = response.status();
-    if !status.is_success() {
-        let message = response.text().await.unwrap_or_else(|e| {
-            format!("Failed to get error message body: {e}")
-        });
-        return Err(Error::Status(status, message));
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
     }
     let text_data = response.text().await?;
     Ok(text_data)
@@ -98,12 +97,8 @@ where
         .header("Authorization", format!("Bearer {access_token}"))
         .send()
         .await?;
-    let status = response.status();
-    if !status.is_success() {
-        let message = response.text().await.unwrap_or_else(|e| {
-            format!("Failed to get error message body: {e}")
-        });
-        return Err(Error::Status(status, message));
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
     }
     let data = response.json::<T>().await?;
     Ok(data)
@@ -130,12 +125,8 @@ where
         .json(request_body)
         .send()
         .await?;
-    let status = response.status();
-    if !status.is_success() {
-        let message = response.text().await.unwrap_or_else(|e| {
-            format!("Failed to get error message body: {e}")
-        });
-        return Err(Error::Status(status, message));
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
     }
     let data = response.json::<O>().await?;
     Ok(data)
@@ -153,12 +144,30 @@ pub async fn delete_authed(
         .header("Authorization", format!("Bearer {access_token}"))
         .send()
         .await?;
-    let status = response.status();
-    if !status.is_success() {
-        let message = response.text().await.unwrap_or_else(|e| {
-            format!("Failed to get error message body: {e}")
-        });
-        return Err(Error::Status(status, message));
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
     }
     Ok(())
 }
+
+/// Helper to delete with client access token, returning the JSON response.
+pub async fn delete_json_authed<T>(
+    client: &Client,
+    url: &str,
+    access_token: &str,
+) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    debug!("deleting (authenticated): {url}");
+    let response = client
+        .delete(url)
+        .header("Authorization", format!("Bearer {access_token}"))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
+    }
+    let data = response.json::<T>().await?;
+    Ok(data)
+}
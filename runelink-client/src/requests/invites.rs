@@ -0,0 +1,51 @@
+use log::info;
+use reqwest::Client;
+use runelink_types::server::{
+    FullServerMembership, InviteRedemption, NewServerInvite, ServerId,
+    ServerInvite,
+};
+
+use crate::error::Result;
+
+use super::post_json_authed;
+
+pub async fn create(
+    client: &Client,
+    api_url: &str,
+    access_token: &str,
+    server_id: ServerId,
+    new_invite: &NewServerInvite,
+    target_host: Option<&str>,
+) -> Result<ServerInvite> {
+    let mut url = format!("{api_url}/servers/{server_id}/invites");
+    if let Some(host) = target_host {
+        url = format!("{url}?target_host={host}");
+    }
+    info!("creating invite: {url}");
+    post_json_authed::<_, ServerInvite>(client, &url, access_token, new_invite)
+        .await
+}
+
+pub async fn redeem(
+    client: &Client,
+    api_url: &str,
+    access_token: &str,
+    code: &str,
+    target_host: Option<&str>,
+) -> Result<FullServerMembership> {
+    let mut url = format!("{api_url}/invites/redeem");
+    if let Some(host) = target_host {
+        url = format!("{url}?target_host={host}");
+    }
+    info!("redeeming invite: {url}");
+    let redemption = InviteRedemption {
+        code: code.to_string(),
+    };
+    post_json_authed::<_, FullServerMembership>(
+        client,
+        &url,
+        access_token,
+        &redemption,
+    )
+    .await
+}
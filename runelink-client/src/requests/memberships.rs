@@ -2,7 +2,8 @@ use log::info;
 use reqwest::Client;
 use runelink_types::{
     server::{
-        FullServerMembership, NewServerMembership, ServerId, ServerMembership,
+        BulkMembershipEntry, BulkMembershipResult, FullServerMembership,
+        NewServerMembership, ServerId, ServerMembership,
     },
     user::UserRef,
 };
@@ -78,6 +79,24 @@ pub async fn create(
     .await
 }
 
+pub async fn import_bulk(
+    client: &Client,
+    api_url: &str,
+    access_token: &str,
+    server_id: ServerId,
+    entries: &[BulkMembershipEntry],
+) -> Result<Vec<BulkMembershipResult>> {
+    let url = format!("{api_url}/servers/{server_id}/members/bulk");
+    info!("importing {} memberships: {url}", entries.len());
+    post_json_authed::<&[BulkMembershipEntry], Vec<BulkMembershipResult>>(
+        client,
+        &url,
+        access_token,
+        &entries,
+    )
+    .await
+}
+
 pub async fn delete(
     client: &Client,
     api_url: &str,
@@ -2,11 +2,11 @@ use log::info;
 use reqwest::Client;
 use runelink_types::{
     channel::ChannelId,
-    message::{Message, MessageId, NewMessage},
+    message::{Message, MessageId, MessagePage, NewMessage},
     server::ServerId,
 };
 
-use crate::error::Result;
+use crate::{error::Result, util::encode_query_value};
 
 use super::{delete_authed, fetch_json_authed, post_json_authed};
 
@@ -71,17 +71,52 @@ pub async fn fetch_by_channel(
     access_token: &str,
     server_id: ServerId,
     channel_id: ChannelId,
+    page: &MessagePage,
     target_host: Option<&str>,
 ) -> Result<Vec<Message>> {
+    let mut params = Vec::new();
+    if let Some(limit) = page.limit {
+        params.push(format!("limit={limit}"));
+    }
+    if let Some(before) = page.before {
+        params.push(format!("before={before}"));
+    }
+    if let Some(host) = target_host {
+        params.push(format!("target_host={host}"));
+    }
     let mut url =
         format!("{api_url}/servers/{server_id}/channels/{channel_id}/messages");
-    if let Some(host) = target_host {
-        url = format!("{url}?target_host={host}");
+    if !params.is_empty() {
+        url = format!("{url}?{}", params.join("&"));
     }
     info!("fetching messages by channel: {url}");
     fetch_json_authed::<Vec<Message>>(client, &url, access_token).await
 }
 
+pub async fn search(
+    client: &Client,
+    api_url: &str,
+    access_token: &str,
+    server_id: ServerId,
+    query: &str,
+    limit: Option<u32>,
+    target_host: Option<&str>,
+) -> Result<Vec<Message>> {
+    let mut params = vec![format!("q={}", encode_query_value(query))];
+    if let Some(limit) = limit {
+        params.push(format!("limit={limit}"));
+    }
+    if let Some(host) = target_host {
+        params.push(format!("target_host={host}"));
+    }
+    let url = format!(
+        "{api_url}/servers/{server_id}/messages/search?{}",
+        params.join("&")
+    );
+    info!("searching messages: {url}");
+    fetch_json_authed::<Vec<Message>>(client, &url, access_token).await
+}
+
 pub async fn fetch_by_id(
     client: &Client,
     api_url: &str,
@@ -5,10 +5,13 @@ use crate::error::Result;
 pub mod auth;
 pub mod channels;
 pub mod generic;
+pub mod invites;
 pub mod memberships;
 pub mod messages;
+pub mod reactions;
 pub mod servers;
 pub mod users;
+pub mod webhooks;
 
 pub use generic::*;
 
@@ -0,0 +1,86 @@
+use log::info;
+use reqwest::Client;
+use runelink_types::{
+    channel::ChannelId,
+    message::{Message, MessageId, NewReaction},
+    server::ServerId,
+};
+
+use crate::{error::Result, util::encode_query_value};
+
+use super::{delete_json_authed, post_json_authed};
+
+/// The message a reaction is added to or removed from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReactionTarget {
+    pub server_id: ServerId,
+    pub channel_id: ChannelId,
+    pub message_id: MessageId,
+}
+
+impl From<&Message> for ReactionTarget {
+    fn from(message: &Message) -> Self {
+        Self {
+            server_id: message.server_id,
+            channel_id: message.channel_id,
+            message_id: message.id,
+        }
+    }
+}
+
+impl ReactionTarget {
+    fn url(&self, api_url: &str) -> String {
+        let Self {
+            server_id,
+            channel_id,
+            message_id,
+        } = self;
+        format!(
+            "{api_url}/servers/{server_id}/channels/{channel_id}/messages/{message_id}/reactions"
+        )
+    }
+}
+
+/// React to a message. Returns the message with its updated reactions.
+pub async fn add(
+    client: &Client,
+    api_url: &str,
+    access_token: &str,
+    target: ReactionTarget,
+    new_reaction: &NewReaction,
+    target_host: Option<&str>,
+) -> Result<Message> {
+    let mut url = target.url(api_url);
+    if let Some(host) = target_host {
+        url = format!("{url}?target_host={host}");
+    }
+    info!("adding reaction: {url}");
+    post_json_authed::<NewReaction, Message>(
+        client,
+        &url,
+        access_token,
+        new_reaction,
+    )
+    .await
+}
+
+/// Remove the user's reaction from a message. Returns the message with its
+/// updated reactions.
+pub async fn remove(
+    client: &Client,
+    api_url: &str,
+    access_token: &str,
+    target: ReactionTarget,
+    emoji: &str,
+    target_host: Option<&str>,
+) -> Result<Message> {
+    // Everything but unreserved characters is escaped, so this is also safe
+    // for a path segment
+    let mut url =
+        format!("{}/{}", target.url(api_url), encode_query_value(emoji));
+    if let Some(host) = target_host {
+        url = format!("{url}?target_host={host}");
+    }
+    info!("removing reaction: {url}");
+    delete_json_authed::<Message>(client, &url, access_token).await
+}
@@ -1,7 +1,7 @@
 use log::info;
 use reqwest::Client;
 use runelink_types::{
-    server::{NewServer, Server, ServerId, ServerWithChannels},
+    server::{NewServer, Server, ServerId, ServerSummary, ServerWithChannels},
     user::UserRef,
 };
 
@@ -51,6 +51,20 @@ pub async fn fetch_by_id(
     fetch_json::<Server>(client, &url).await
 }
 
+pub async fn fetch_summary(
+    client: &Client,
+    api_url: &str,
+    server_id: ServerId,
+    target_host: Option<&str>,
+) -> Result<ServerSummary> {
+    let mut url = format!("{api_url}/servers/{server_id}/summary");
+    if let Some(host) = target_host {
+        url = format!("{url}?target_host={host}");
+    }
+    info!("fetching server summary: {url}");
+    fetch_json::<ServerSummary>(client, &url).await
+}
+
 pub async fn fetch_by_user(
     client: &Client,
     api_url: &str,
@@ -71,11 +85,14 @@ pub async fn fetch_with_channels(
     api_url: &str,
     access_token: &str,
     server_id: ServerId,
+    include_last_messages: bool,
     target_host: Option<&str>,
 ) -> Result<ServerWithChannels> {
-    let mut url = format!("{api_url}/servers/{server_id}/with_channels");
+    let mut url = format!(
+        "{api_url}/servers/{server_id}/with_channels?include_last_messages={include_last_messages}"
+    );
     if let Some(host) = target_host {
-        url = format!("{url}?target_host={host}");
+        url = format!("{url}&target_host={host}");
     }
     info!("fetching server with channels (federation): {url}");
     fetch_json_authed::<ServerWithChannels>(client, &url, access_token).await
@@ -1,10 +1,13 @@
 use log::info;
 use reqwest::Client;
-use runelink_types::user::{NewUser, User, UserRef};
+use runelink_types::{
+    auth::SessionInfo,
+    user::{NewUser, User, UserQuery, UserRef},
+};
 
-use crate::error::Result;
+use crate::{error::Result, util::encode_query_value};
 
-use super::{delete_authed, fetch_json, post_json_authed};
+use super::{delete_authed, fetch_json, fetch_json_authed, post_json_authed};
 
 pub async fn create(
     client: &Client,
@@ -21,16 +24,49 @@ pub async fn create(
 pub async fn fetch_all(
     client: &Client,
     api_url: &str,
+    query: &UserQuery,
     target_host: Option<&str>,
 ) -> Result<Vec<User>> {
-    let mut url = format!("{api_url}/users");
+    let mut params = Vec::new();
+    if let Some(prefix) = &query.name_prefix {
+        params.push(format!("name_prefix={}", encode_query_value(prefix)));
+    }
+    if let Some(host) = &query.host {
+        params.push(format!("host={}", encode_query_value(host)));
+    }
+    if let Some(limit) = query.limit {
+        params.push(format!("limit={limit}"));
+    }
+    if let Some(offset) = query.offset {
+        params.push(format!("offset={offset}"));
+    }
     if let Some(host) = target_host {
-        url = format!("{url}?target_host={host}");
+        params.push(format!("target_host={host}"));
+    }
+    let mut url = format!("{api_url}/users");
+    if !params.is_empty() {
+        url = format!("{url}?{}", params.join("&"));
     }
     info!("fetching all users: {url}");
     fetch_json::<Vec<User>>(client, &url).await
 }
 
+/// Search users by name on the host behind `api_url` and on `hosts`.
+pub async fn search(
+    client: &Client,
+    api_url: &str,
+    query: &str,
+    hosts: &[String],
+) -> Result<Vec<User>> {
+    let mut url =
+        format!("{api_url}/users/search?q={}", encode_query_value(query));
+    if !hosts.is_empty() {
+        url = format!("{url}&hosts={}", encode_query_value(&hosts.join(",")));
+    }
+    info!("searching users: {url}");
+    fetch_json::<Vec<User>>(client, &url).await
+}
+
 pub async fn fetch_by_ref(
     client: &Client,
     api_url: &str,
@@ -77,3 +113,35 @@ pub async fn fetch_associated_hosts(
     info!("fetching user associated hosts: {url}");
     fetch_json::<Vec<String>>(client, &url).await
 }
+
+/// Lists the user's active sessions.
+pub async fn fetch_sessions(
+    client: &Client,
+    api_url: &str,
+    access_token: &str,
+    user: UserRef,
+) -> Result<Vec<SessionInfo>> {
+    let url = format!(
+        "{api_url}/users/{host}/{name}/sessions",
+        host = user.host,
+        name = user.name
+    );
+    info!("fetching sessions: {url}");
+    fetch_json_authed::<Vec<SessionInfo>>(client, &url, access_token).await
+}
+
+/// Revokes all of the user's sessions.
+pub async fn revoke_sessions(
+    client: &Client,
+    api_url: &str,
+    access_token: &str,
+    user: UserRef,
+) -> Result<()> {
+    let url = format!(
+        "{api_url}/users/{host}/{name}/sessions",
+        host = user.host,
+        name = user.name
+    );
+    info!("revoking sessions: {url}");
+    delete_authed(client, &url, access_token).await
+}
@@ -0,0 +1,53 @@
+use log::info;
+use reqwest::Client;
+use runelink_types::{
+    channel::{ChannelId, ChannelWebhook, WebhookId, WebhookPayload},
+    message::Message,
+    server::ServerId,
+};
+
+use crate::error::Result;
+
+use super::{delete_authed, post_json, post_json_authed};
+
+pub async fn create(
+    client: &Client,
+    api_url: &str,
+    access_token: &str,
+    server_id: ServerId,
+    channel_id: ChannelId,
+) -> Result<ChannelWebhook> {
+    let url =
+        format!("{api_url}/servers/{server_id}/channels/{channel_id}/webhooks");
+    info!("creating webhook: {url}");
+    post_json_authed::<_, ChannelWebhook>(client, &url, access_token, &()).await
+}
+
+pub async fn delete(
+    client: &Client,
+    api_url: &str,
+    access_token: &str,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    webhook_id: WebhookId,
+) -> Result<()> {
+    let url = format!(
+        "{api_url}/servers/{server_id}/channels/{channel_id}/webhooks/{webhook_id}"
+    );
+    info!("deleting webhook: {url}");
+    delete_authed(client, &url, access_token).await
+}
+
+/// Post to a webhook as an external system would; no login is needed.
+pub async fn execute(
+    client: &Client,
+    api_url: &str,
+    webhook_id: WebhookId,
+    token: &str,
+    payload: &WebhookPayload,
+) -> Result<Message> {
+    // The token is a secret, so it is left out of the log line
+    info!("posting to webhook {webhook_id}");
+    let url = format!("{api_url}/webhooks/{webhook_id}/{token}");
+    post_json::<_, Message>(client, &url, payload).await
+}
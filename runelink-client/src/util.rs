@@ -55,6 +55,24 @@ pub fn get_federation_ws_url(host: &str, secure: bool) -> String {
     format!("{scheme}://{host_with_port}/ws/federation")
 }
 
+/// Percent-encodes a value for use in a URL query string.
+pub fn encode_query_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -83,6 +101,22 @@ mod tests {
         assert_eq!(host_from_issuer("https://example.com/"), "example.com");
     }
 
+    #[test]
+    fn test_host_from_issuer_strips_scheme_and_trailing_slash() {
+        for issuer in [
+            "http://example.com",
+            "https://example.com",
+            "http://example.com/",
+            "https://example.com/",
+        ] {
+            assert_eq!(host_from_issuer(issuer), "example.com", "{issuer}");
+        }
+        assert_eq!(
+            host_from_issuer("https://example.com:8080/"),
+            "example.com:8080"
+        );
+    }
+
     #[test]
     fn test_no_port() {
         let url = get_api_url("example.com", false);
@@ -129,4 +163,11 @@ mod tests {
             "wss://example.com:7000/ws/federation"
         );
     }
+
+    #[test]
+    fn test_encode_query_value() {
+        assert_eq!(encode_query_value("hello-world_1.0~"), "hello-world_1.0~");
+        assert_eq!(encode_query_value("a b&c=d"), "a%20b%26c%3Dd");
+        assert_eq!(encode_query_value("é"), "%C3%A9");
+    }
 }
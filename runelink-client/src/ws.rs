@@ -0,0 +1,402 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, warn};
+use runelink_types::ws::{
+    ClientWsEnvelope, ClientWsReply, ClientWsRequest, ClientWsUpdate,
+    RequestId, WsError,
+};
+use tokio::{
+    net::TcpStream,
+    sync::{Mutex, broadcast, mpsc, oneshot, watch},
+};
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream, connect_async,
+    tungstenite::{Message, client::IntoClientRequest},
+};
+
+use crate::error::{Error, Result};
+
+/// Updates buffered per `WsUpdates` before the slowest one starts missing
+/// some.
+const UPDATE_BUFFER: usize = 256;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+type PendingReplySender =
+    oneshot::Sender<std::result::Result<ClientWsReply, WsError>>;
+
+type PendingReplies = Arc<Mutex<HashMap<RequestId, PendingReplySender>>>;
+
+/// Settings for a `WsClient`.
+#[derive(Clone, Debug)]
+pub struct WsClientOptions {
+    /// How long `WsClient::request` waits for a reply.
+    pub request_timeout: Duration,
+    /// Whether a dropped connection is reopened.
+    pub reconnect: bool,
+    /// First delay before reconnecting; doubles up to `reconnect_max_delay`.
+    pub reconnect_initial_delay: Duration,
+    pub reconnect_max_delay: Duration,
+}
+
+impl Default for WsClientOptions {
+    fn default() -> Self {
+        Self {
+            request_timeout: Duration::from_secs(10),
+            reconnect: true,
+            reconnect_initial_delay: Duration::from_secs(1),
+            reconnect_max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Where a `WsClient`'s connection is at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WsConnectionState {
+    /// The connection dropped and is being reopened.
+    Reconnecting,
+    /// Connected; `generation` goes up by one each time the connection is
+    /// reopened, since the server forgets a connection's session when it
+    /// drops.
+    Connected { generation: u64 },
+    /// The connection dropped and won't be reopened.
+    Closed,
+}
+
+/// A typed client for the `/ws/client` websocket.
+///
+/// Replies are matched to their requests by `request_id`, so requests can be
+/// made concurrently from clones of the same client. The connection closes
+/// once every clone is dropped.
+#[derive(Clone, Debug)]
+pub struct WsClient {
+    outbound: mpsc::UnboundedSender<ClientWsEnvelope>,
+    pending: PendingReplies,
+    updates: broadcast::Sender<ClientWsUpdate>,
+    state: watch::Receiver<WsConnectionState>,
+    request_timeout: Duration,
+}
+
+impl WsClient {
+    /// Opens a connection to `url`, failing if the first attempt does.
+    pub async fn connect(url: &str, options: WsClientOptions) -> Result<Self> {
+        let socket = open_socket(url).await?;
+        let (outbound, outbound_rx) = mpsc::unbounded_channel();
+        let pending = PendingReplies::default();
+        let (updates, _) = broadcast::channel(UPDATE_BUFFER);
+        let (state_tx, state) =
+            watch::channel(WsConnectionState::Connected { generation: 0 });
+        let request_timeout = options.request_timeout;
+        tokio::spawn(run_connection(
+            url.to_string(),
+            socket,
+            options,
+            outbound_rx,
+            pending.clone(),
+            updates.clone(),
+            state_tx,
+        ));
+        Ok(Self {
+            outbound,
+            pending,
+            updates,
+            state,
+            request_timeout,
+        })
+    }
+
+    /// Sends a request and waits for its reply.
+    pub async fn request(
+        &self,
+        request: ClientWsRequest,
+    ) -> Result<ClientWsReply> {
+        if !matches!(self.state(), WsConnectionState::Connected { .. }) {
+            return Err(Error::Disconnected);
+        }
+        let request_id = RequestId::new();
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().await.insert(request_id, tx);
+        let envelope = ClientWsEnvelope::Request {
+            request_id,
+            request,
+        };
+        if self.outbound.send(envelope).is_err() {
+            self.pending.lock().await.remove(&request_id);
+            return Err(Error::Disconnected);
+        }
+
+        match tokio::time::timeout(self.request_timeout, rx).await {
+            Ok(Ok(Ok(reply))) => Ok(reply),
+            Ok(Ok(Err(error))) => Err(Error::Remote(error)),
+            // Pending requests are dropped when the connection is
+            Ok(Err(_)) => Err(Error::Disconnected),
+            Err(_) => {
+                self.pending.lock().await.remove(&request_id);
+                Err(Error::Timeout)
+            }
+        }
+    }
+
+    /// Updates pushed by the server from now on.
+    pub fn updates(&self) -> WsUpdates {
+        WsUpdates {
+            updates: self.updates.subscribe(),
+            state: self.state.clone(),
+        }
+    }
+
+    pub fn state(&self) -> WsConnectionState {
+        *self.state.borrow()
+    }
+
+    /// Watches the connection state, e.g. to authenticate and subscribe
+    /// again after a reconnect.
+    pub fn state_changes(&self) -> watch::Receiver<WsConnectionState> {
+        self.state.clone()
+    }
+}
+
+/// A stream of the updates a `WsClient` receives.
+#[derive(Debug)]
+pub struct WsUpdates {
+    updates: broadcast::Receiver<ClientWsUpdate>,
+    state: watch::Receiver<WsConnectionState>,
+}
+
+impl WsUpdates {
+    /// The next update, or `None` once the connection is closed for good.
+    ///
+    /// Updates that arrived faster than they were read are skipped.
+    pub async fn next(&mut self) -> Option<ClientWsUpdate> {
+        loop {
+            let closed =
+                self.state.wait_for(|s| *s == WsConnectionState::Closed);
+            let received = tokio::select! {
+                // Hand out what was buffered before noticing the close
+                biased;
+                received = self.updates.recv() => received,
+                _ = closed => return None,
+            };
+            match received {
+                Ok(update) => return Some(update),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Skipped {skipped} websocket updates");
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+async fn open_socket(url: &str) -> Result<Socket> {
+    let request = url.into_client_request()?;
+    let (socket, _) = connect_async(request).await?;
+    Ok(socket)
+}
+
+/// Drives the connection, reopening it as configured until every
+/// `WsClient` is dropped.
+async fn run_connection(
+    url: String,
+    socket: Socket,
+    options: WsClientOptions,
+    mut outbound_rx: mpsc::UnboundedReceiver<ClientWsEnvelope>,
+    pending: PendingReplies,
+    updates: broadcast::Sender<ClientWsUpdate>,
+    state: watch::Sender<WsConnectionState>,
+) {
+    let mut socket = socket;
+    let mut generation = 0;
+    loop {
+        let clients_gone =
+            pump(socket, &mut outbound_rx, &pending, &updates).await;
+        // Their replies would have come over the old connection
+        pending.lock().await.clear();
+        if clients_gone || !options.reconnect {
+            state.send_replace(WsConnectionState::Closed);
+            return;
+        }
+        state.send_replace(WsConnectionState::Reconnecting);
+
+        let mut delay = options.reconnect_initial_delay;
+        socket = loop {
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                envelope = outbound_rx.recv() => {
+                    // Nothing can be sent while disconnected
+                    match envelope {
+                        Some(envelope) => {
+                            fail_request(&pending, &envelope).await;
+                        }
+                        None => {
+                            state.send_replace(WsConnectionState::Closed);
+                            return;
+                        }
+                    }
+                    continue;
+                }
+            }
+            match open_socket(&url).await {
+                Ok(socket) => break socket,
+                Err(e) => {
+                    debug!("Reconnecting to {url} failed: {e}");
+                    delay = (delay * 2).min(options.reconnect_max_delay);
+                }
+            }
+        };
+        generation += 1;
+        state.send_replace(WsConnectionState::Connected { generation });
+    }
+}
+
+/// Moves frames between the socket and the client until one side goes away.
+///
+/// Returns `true` if that was the clients rather than the socket.
+async fn pump(
+    socket: Socket,
+    outbound_rx: &mut mpsc::UnboundedReceiver<ClientWsEnvelope>,
+    pending: &PendingReplies,
+    updates: &broadcast::Sender<ClientWsUpdate>,
+) -> bool {
+    let (mut sink, mut stream) = socket.split();
+    loop {
+        tokio::select! {
+            envelope = outbound_rx.recv() => {
+                let Some(envelope) = envelope else {
+                    let _ = sink.send(Message::Close(None)).await;
+                    return true;
+                };
+                let payload = match serde_json::to_string(&envelope) {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        warn!("Failed to encode websocket request: {e}");
+                        fail_request(pending, &envelope).await;
+                        continue;
+                    }
+                };
+                if sink.send(Message::Text(payload.into())).await.is_err() {
+                    return false;
+                }
+            }
+            frame = stream.next() => match frame {
+                Some(Ok(Message::Text(payload))) => {
+                    dispatch(&payload, pending, updates).await;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                    return false;
+                }
+                // Pings are answered by the socket itself
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// Routes an incoming frame to the request waiting on it, or to the update
+/// stream.
+async fn dispatch(
+    payload: &str,
+    pending: &PendingReplies,
+    updates: &broadcast::Sender<ClientWsUpdate>,
+) {
+    let envelope = match serde_json::from_str::<ClientWsEnvelope>(payload) {
+        Ok(envelope) => envelope,
+        Err(e) => {
+            warn!("Ignoring malformed websocket frame: {e}");
+            return;
+        }
+    };
+    let (request_id, result) = match envelope {
+        ClientWsEnvelope::Reply {
+            request_id, reply, ..
+        } => (request_id, Ok(reply)),
+        ClientWsEnvelope::Error {
+            request_id: Some(request_id),
+            error,
+            ..
+        } => (request_id, Err(error)),
+        ClientWsEnvelope::Error {
+            request_id: None,
+            error,
+            ..
+        } => {
+            warn!("Websocket error: {}", error.message);
+            return;
+        }
+        ClientWsEnvelope::Update { update, .. } => {
+            // No one listening is fine
+            let _ = updates.send(update);
+            return;
+        }
+        ClientWsEnvelope::Request { .. } => return,
+    };
+    if let Some(tx) = pending.lock().await.remove(&request_id) {
+        let _ = tx.send(result);
+    }
+}
+
+/// Drops the pending reply for a request that couldn't be sent, so its
+/// caller sees `Error::Disconnected` right away.
+async fn fail_request(pending: &PendingReplies, envelope: &ClientWsEnvelope) {
+    if let ClientWsEnvelope::Request { request_id, .. } = envelope {
+        pending.lock().await.remove(request_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runelink_types::ws::{EventId, WsErrorCode};
+
+    #[tokio::test]
+    async fn test_dispatch_routes_replies_by_request_id() {
+        let pending = PendingReplies::default();
+        let (updates, _) = broadcast::channel(1);
+        let request_id = RequestId::new();
+        let (tx, rx) = oneshot::channel();
+        pending.lock().await.insert(request_id, tx);
+
+        let other = serde_json::to_string(&ClientWsEnvelope::Reply {
+            request_id: RequestId::new(),
+            event_id: EventId::new(),
+            reply: ClientWsReply::Pong,
+        })
+        .unwrap();
+        dispatch(&other, &pending, &updates).await;
+        assert_eq!(pending.lock().await.len(), 1);
+
+        let error = WsError {
+            code: WsErrorCode::Forbidden,
+            message: "no".into(),
+            details: None,
+        };
+        let reply = serde_json::to_string(&ClientWsEnvelope::Error {
+            request_id: Some(request_id),
+            event_id: EventId::new(),
+            error: error.clone(),
+        })
+        .unwrap();
+        dispatch(&reply, &pending, &updates).await;
+        assert!(pending.lock().await.is_empty());
+        assert_eq!(rx.await.unwrap(), Err(error));
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_forwards_updates() {
+        let pending = PendingReplies::default();
+        let (updates, mut rx) = broadcast::channel(1);
+        let update = ClientWsUpdate::MessageDeleted {
+            server_id: runelink_types::server::ServerId::new(),
+            channel_id: runelink_types::channel::ChannelId::new(),
+            message_id: runelink_types::message::MessageId::new(),
+        };
+        let payload = serde_json::to_string(&ClientWsEnvelope::Update {
+            event_id: EventId::new(),
+            update: update.clone(),
+        })
+        .unwrap();
+        dispatch(&payload, &pending, &updates).await;
+        assert_eq!(rx.recv().await.unwrap(), update);
+    }
+}
@@ -0,0 +1,2 @@
+ALTER TABLE messages
+    ADD COLUMN system BOOLEAN NOT NULL DEFAULT FALSE;
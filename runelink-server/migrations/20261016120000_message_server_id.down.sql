@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_messages_server_id_created_at;
+
+ALTER TABLE messages
+    DROP COLUMN IF EXISTS server_id;
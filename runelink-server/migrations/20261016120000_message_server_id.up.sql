@@ -0,0 +1,18 @@
+-- Denormalize the owning server onto messages so lookups and fanout don't
+-- need to go through channels. Channels never move between servers, so the
+-- value is fixed at insert time.
+ALTER TABLE messages
+    ADD COLUMN server_id UUID
+        REFERENCES servers (id)
+        ON DELETE CASCADE;
+
+UPDATE messages m
+    SET server_id = c.server_id
+    FROM channels c
+    WHERE c.id = m.channel_id;
+
+ALTER TABLE messages
+    ALTER COLUMN server_id SET NOT NULL;
+
+CREATE INDEX idx_messages_server_id_created_at
+    ON messages (server_id, created_at);
@@ -0,0 +1,2 @@
+DROP FUNCTION IF EXISTS message_reaction_counts(UUID, TEXT, TEXT);
+DROP TABLE IF EXISTS message_reactions;
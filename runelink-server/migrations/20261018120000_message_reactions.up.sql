@@ -0,0 +1,54 @@
+CREATE TABLE message_reactions (
+    message_id UUID NOT NULL
+        REFERENCES messages (id)
+        ON DELETE CASCADE,
+    user_name TEXT NOT NULL,
+    user_host TEXT NOT NULL,
+    emoji TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (message_id, user_name, user_host, emoji),
+    CONSTRAINT message_reactions_user_fkey
+        FOREIGN KEY (user_name, user_host)
+        REFERENCES users(name, host)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX idx_message_reactions_user
+    ON message_reactions (user_name, user_host);
+
+-- Reactions on a message grouped by emoji, as a JSON array of
+-- {emoji, count, reacted}. `reacted` is whether the viewer is among the
+-- reactors; pass NULLs when there is no viewer.
+CREATE FUNCTION message_reaction_counts(
+    target_message_id UUID,
+    viewer_name TEXT,
+    viewer_host TEXT
+)
+    RETURNS JSONB AS $$
+    SELECT COALESCE(
+        jsonb_agg(
+            jsonb_build_object(
+                'emoji', r.emoji,
+                'count', r.count,
+                'reacted', r.reacted
+            )
+            ORDER BY r.first_reacted_at, r.emoji
+        ),
+        '[]'::jsonb
+    )
+    FROM (
+        SELECT
+            mr.emoji,
+            COUNT(*) AS count,
+            COALESCE(
+                BOOL_OR(
+                    mr.user_name = viewer_name AND mr.user_host = viewer_host
+                ),
+                FALSE
+            ) AS reacted,
+            MIN(mr.created_at) AS first_reacted_at
+        FROM message_reactions mr
+        WHERE mr.message_id = target_message_id
+        GROUP BY mr.emoji
+    ) r;
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,7 @@
+ALTER TABLE cached_remote_servers
+    DROP COLUMN IF EXISTS visibility;
+
+ALTER TABLE servers
+    DROP COLUMN IF EXISTS visibility;
+
+DROP TYPE IF EXISTS server_visibility;
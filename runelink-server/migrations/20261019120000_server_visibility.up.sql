@@ -0,0 +1,9 @@
+CREATE TYPE server_visibility AS ENUM ('public', 'private');
+
+ALTER TABLE servers
+    ADD COLUMN visibility server_visibility NOT NULL DEFAULT 'public';
+
+-- Cached copies keep the remote host's visibility so private servers are
+-- never served from the cache to non-members.
+ALTER TABLE cached_remote_servers
+    ADD COLUMN visibility server_visibility NOT NULL DEFAULT 'public';
@@ -0,0 +1,20 @@
+CREATE TABLE server_invites (
+    code TEXT PRIMARY KEY,
+    server_id UUID NOT NULL
+        REFERENCES servers (id)
+        ON DELETE CASCADE,
+    created_by_name TEXT NOT NULL,
+    created_by_host TEXT NOT NULL,
+    expires_at TIMESTAMPTZ,
+    max_uses INTEGER CHECK (max_uses > 0),
+    -- NULL when the invite has no use limit.
+    uses_remaining INTEGER CHECK (uses_remaining >= 0),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT server_invites_created_by_fkey
+        FOREIGN KEY (created_by_name, created_by_host)
+        REFERENCES users(name, host)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX idx_server_invites_server_id
+    ON server_invites (server_id);
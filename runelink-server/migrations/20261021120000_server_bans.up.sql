@@ -0,0 +1,15 @@
+-- Users barred from (re)joining a server. The banned user and the admin
+-- are plain columns rather than foreign keys: a remote user can be banned
+-- before this host has ever seen them, and a ban outlives its admin.
+CREATE TABLE server_bans (
+    server_id UUID NOT NULL
+        REFERENCES servers (id)
+        ON DELETE CASCADE,
+    user_name TEXT NOT NULL,
+    user_host TEXT NOT NULL,
+    banned_by_name TEXT NOT NULL,
+    banned_by_host TEXT NOT NULL,
+    reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (server_id, user_name, user_host)
+);
@@ -0,0 +1,3 @@
+ALTER TABLE users
+    ADD COLUMN display_name TEXT,
+    ADD COLUMN avatar_url TEXT;
@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS direct_channels;
+DELETE FROM servers WHERE direct;
+DROP INDEX IF EXISTS idx_servers_direct;
+ALTER TABLE servers
+    DROP COLUMN IF EXISTS direct;
@@ -0,0 +1,26 @@
+-- Direct message channels all live in one hidden server per host. It has
+-- no members and is never listed; access goes through direct_channels.
+ALTER TABLE servers
+    ADD COLUMN direct BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE UNIQUE INDEX idx_servers_direct
+    ON servers (direct)
+    WHERE direct;
+
+INSERT INTO servers (title, visibility, direct)
+VALUES ('Direct messages', 'private', TRUE);
+
+-- The two participants of a direct message channel, stored in a fixed
+-- order so each pair has at most one channel. Like server_bans, these are
+-- plain columns because a remote participant may not be cached here.
+CREATE TABLE direct_channels (
+    channel_id UUID PRIMARY KEY
+        REFERENCES channels (id)
+        ON DELETE CASCADE,
+    user_a_name TEXT NOT NULL,
+    user_a_host TEXT NOT NULL,
+    user_b_name TEXT NOT NULL,
+    user_b_host TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (user_a_name, user_a_host, user_b_name, user_b_host)
+);
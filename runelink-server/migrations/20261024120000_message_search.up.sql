@@ -0,0 +1,9 @@
+-- Full-text search over message bodies. The 'simple' configuration skips
+-- stemming and stop words, which suit chat in any language better than a
+-- single language's dictionary.
+ALTER TABLE messages
+    ADD COLUMN search_vector TSVECTOR
+        GENERATED ALWAYS AS (to_tsvector('simple', body)) STORED;
+
+CREATE INDEX idx_messages_search_vector
+    ON messages USING GIN (search_vector);
@@ -0,0 +1,24 @@
+-- How far each user has read in each channel. The position is kept as the
+-- message's send time as well as its id, so it survives the message being
+-- deleted.
+CREATE TABLE channel_read_state (
+    user_name TEXT NOT NULL,
+    user_host TEXT NOT NULL,
+    channel_id UUID NOT NULL
+        REFERENCES channels (id)
+        ON DELETE CASCADE,
+    server_id UUID NOT NULL
+        REFERENCES servers (id)
+        ON DELETE CASCADE,
+    last_read_message_id UUID NOT NULL,
+    last_read_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_name, user_host, channel_id),
+    CONSTRAINT channel_read_state_user_fkey
+        FOREIGN KEY (user_name, user_host)
+        REFERENCES users(name, host)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX idx_channel_read_state_user_server
+    ON channel_read_state (user_name, user_host, server_id);
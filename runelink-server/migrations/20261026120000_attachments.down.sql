@@ -0,0 +1,2 @@
+DROP FUNCTION IF EXISTS message_attachments(UUID);
+DROP TABLE IF EXISTS attachments;
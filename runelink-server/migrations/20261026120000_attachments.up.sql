@@ -0,0 +1,43 @@
+-- Files attached to messages. An upload is stored on its uploader's home
+-- host under its id; other hosts keep the upload's URL and download it the
+-- first time it's fetched. `stored` is whether the file is on disk here.
+-- The channel is a plain column since uploads for a remote channel are
+-- stored on the uploader's host.
+CREATE TABLE attachments (
+    id UUID PRIMARY KEY,
+    channel_id UUID NOT NULL,
+    message_id UUID
+        REFERENCES messages (id)
+        ON DELETE CASCADE,
+    uploader_name TEXT NOT NULL,
+    uploader_host TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    url TEXT NOT NULL,
+    stored BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_attachments_message ON attachments (message_id);
+
+-- A message's attachments in upload order, as a JSON array of
+-- {id, filename, content_type, size, url}.
+CREATE FUNCTION message_attachments(target_message_id UUID)
+    RETURNS JSONB AS $$
+    SELECT COALESCE(
+        jsonb_agg(
+            jsonb_build_object(
+                'id', a.id,
+                'filename', a.filename,
+                'content_type', a.content_type,
+                'size', a.size,
+                'url', a.url
+            )
+            ORDER BY a.created_at, a.id
+        ),
+        '[]'::jsonb
+    )
+    FROM attachments a
+    WHERE a.message_id = target_message_id;
+$$ LANGUAGE SQL STABLE;
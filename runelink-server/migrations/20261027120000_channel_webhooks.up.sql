@@ -0,0 +1,22 @@
+-- Incoming webhooks. Whoever knows a webhook's id and token can post to its
+-- channel; deleting the row revokes it.
+CREATE TABLE channel_webhooks (
+    id UUID PRIMARY KEY,
+    server_id UUID NOT NULL
+        REFERENCES servers (id)
+        ON DELETE CASCADE,
+    channel_id UUID NOT NULL
+        REFERENCES channels (id)
+        ON DELETE CASCADE,
+    token TEXT NOT NULL,
+    created_by_name TEXT NOT NULL,
+    created_by_host TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT channel_webhooks_created_by_fkey
+        FOREIGN KEY (created_by_name, created_by_host)
+        REFERENCES users(name, host)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX idx_channel_webhooks_channel_id
+    ON channel_webhooks (channel_id);
@@ -0,0 +1,16 @@
+-- Members of a message's server mentioned in it as @name@host.
+CREATE TABLE message_mentions (
+    message_id UUID NOT NULL
+        REFERENCES messages (id)
+        ON DELETE CASCADE,
+    user_name TEXT NOT NULL,
+    user_host TEXT NOT NULL,
+    PRIMARY KEY (message_id, user_name, user_host),
+    CONSTRAINT message_mentions_user_fkey
+        FOREIGN KEY (user_name, user_host)
+        REFERENCES users(name, host)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX idx_message_mentions_user
+    ON message_mentions (user_name, user_host);
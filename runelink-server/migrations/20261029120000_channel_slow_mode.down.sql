@@ -0,0 +1 @@
+ALTER TABLE channels DROP COLUMN slow_mode_secs;
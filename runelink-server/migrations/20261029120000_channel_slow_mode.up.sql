@@ -0,0 +1,2 @@
+ALTER TABLE channels
+    ADD COLUMN slow_mode_secs INTEGER CHECK (slow_mode_secs > 0);
@@ -0,0 +1,3 @@
+ALTER TABLE channels
+    DROP COLUMN category,
+    DROP COLUMN position;
@@ -0,0 +1,16 @@
+ALTER TABLE channels
+    ADD COLUMN position INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN category TEXT;
+
+-- Keep the existing order, which was by creation time
+UPDATE channels c
+SET position = ordered.position
+FROM (
+    SELECT
+        id,
+        ROW_NUMBER() OVER (
+            PARTITION BY server_id ORDER BY created_at
+        ) - 1 AS position
+    FROM channels
+) AS ordered
+WHERE c.id = ordered.id;
@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS idx_messages_deleted_at;
+
+-- Tombstones can't be represented without the column
+DELETE FROM messages WHERE deleted_at IS NOT NULL;
+
+ALTER TABLE messages DROP COLUMN deleted_at;
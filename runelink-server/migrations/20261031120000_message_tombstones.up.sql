@@ -0,0 +1,6 @@
+-- Deleted messages are kept as tombstones until purged.
+ALTER TABLE messages ADD COLUMN deleted_at TIMESTAMPTZ;
+
+CREATE INDEX idx_messages_deleted_at
+    ON messages (deleted_at)
+    WHERE deleted_at IS NOT NULL;
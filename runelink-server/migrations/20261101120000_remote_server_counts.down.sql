@@ -0,0 +1,3 @@
+ALTER TABLE cached_remote_servers
+    DROP COLUMN channel_count,
+    DROP COLUMN member_count;
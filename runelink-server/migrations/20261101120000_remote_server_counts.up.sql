@@ -0,0 +1,4 @@
+-- Counts reported by a remote server's host, when it reports them.
+ALTER TABLE cached_remote_servers
+    ADD COLUMN member_count BIGINT,
+    ADD COLUMN channel_count BIGINT;
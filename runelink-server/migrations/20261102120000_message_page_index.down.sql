@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS idx_server_users_server_id;
+
+CREATE INDEX idx_messages_channel_id_created_at
+    ON messages (channel_id, created_at);
+
+DROP INDEX IF EXISTS idx_messages_channel_page;
@@ -0,0 +1,13 @@
+-- Channel history pages on (created_at, id), newest first. Covering the id
+-- tiebreak lets the keyset cursor be an index condition instead of a filter
+-- followed by a sort. It supersedes the (channel_id, created_at) index.
+CREATE INDEX idx_messages_channel_page
+    ON messages (channel_id, created_at DESC, id DESC);
+
+DROP INDEX IF EXISTS idx_messages_channel_id_created_at;
+
+-- Lookups by member already use the (user_name, user_host, server_id)
+-- primary key; lookups by server, such as fanout targets and member lists,
+-- had no index.
+CREATE INDEX idx_server_users_server_id
+    ON server_users (server_id);
@@ -0,0 +1,22 @@
+-- Direct message channels all live in the hidden direct server and are
+-- never listed by title, so give each one a title of its own
+UPDATE channels
+SET title = 'Direct messages ' || id
+WHERE server_id IN (SELECT id FROM servers WHERE direct);
+
+-- Keep the oldest channel of each clashing title and suffix the others
+-- with their id, since a counter could land on a title already taken
+UPDATE channels c
+SET title = c.title || ' (' || c.id || ')'
+FROM (
+    SELECT
+        id,
+        ROW_NUMBER() OVER (
+            PARTITION BY server_id, LOWER(title) ORDER BY created_at, id
+        ) AS rank
+    FROM channels
+) AS ranked
+WHERE c.id = ranked.id AND ranked.rank > 1;
+
+CREATE UNIQUE INDEX idx_channels_server_id_title
+    ON channels (server_id, LOWER(title));
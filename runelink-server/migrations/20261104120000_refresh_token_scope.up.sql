@@ -0,0 +1,4 @@
+-- Tokens issued before scopes were stored keep the full default scope
+ALTER TABLE refresh_tokens
+    ADD COLUMN scope TEXT NOT NULL DEFAULT 'openid messages.read messages.write';
+ALTER TABLE refresh_tokens ALTER COLUMN scope DROP DEFAULT;
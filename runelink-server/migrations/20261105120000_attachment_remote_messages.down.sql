@@ -0,0 +1 @@
+ALTER TABLE attachments DROP COLUMN remote_message_id;
@@ -0,0 +1,3 @@
+-- Uploads sent with a message in another host's channel are on a message
+-- that isn't stored here, so only its id is kept, to mark them as in use
+ALTER TABLE attachments ADD COLUMN remote_message_id UUID;
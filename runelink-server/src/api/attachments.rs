@@ -0,0 +1,82 @@
+use axum::{
+    extract::{Multipart, Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+};
+use log::info;
+use runelink_types::{
+    channel::ChannelId, message::AttachmentId, server::ServerId,
+};
+
+use crate::{
+    auth::{Principal, authorize},
+    error::{ApiError, ApiResult},
+    ops,
+    state::AppState,
+};
+
+/// The multipart field holding the uploaded file.
+const FILE_FIELD: &str = "file";
+
+/// POST /servers/{server_id}/channels/{channel_id}/attachments
+pub async fn upload(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((server_id, channel_id)): Path<(ServerId, ChannelId)>,
+    mut multipart: Multipart,
+) -> ApiResult<impl IntoResponse> {
+    info!("POST /servers/{server_id}/channels/{channel_id}/attachments");
+    let session = authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::attachments::auth::upload(server_id),
+    )
+    .await?;
+    while let Some(mut field) = multipart.next_field().await? {
+        if field.name() != Some(FILE_FIELD) {
+            continue;
+        }
+        let filename = field.file_name().map(str::to_string);
+        let content_type = field.content_type().map(str::to_string);
+        // Read in chunks so oversized uploads are rejected early
+        let mut data = Vec::new();
+        while let Some(chunk) = field.chunk().await? {
+            ops::attachments::check_size(&state, data.len() + chunk.len())?;
+            data.extend_from_slice(&chunk);
+        }
+        let attachment = ops::attachments::upload(
+            &state,
+            &session,
+            server_id,
+            channel_id,
+            filename.as_deref(),
+            content_type.as_deref(),
+            data,
+        )
+        .await?;
+        return Ok((StatusCode::CREATED, axum::Json(attachment)));
+    }
+    Err(ApiError::BadRequest(format!(
+        "Missing multipart field `{FILE_FIELD}`"
+    )))
+}
+
+/// GET /attachments/{attachment_id}
+pub async fn download(
+    State(state): State<AppState>,
+    Path(attachment_id): Path<AttachmentId>,
+) -> ApiResult<impl IntoResponse> {
+    info!("GET /attachments/{attachment_id}");
+    let file = ops::attachments::get_file(&state, attachment_id).await?;
+    let disposition = format!(
+        "inline; filename=\"{}\"",
+        file.attachment.filename.replace(['"', '\\'], "_")
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, file.attachment.content_type),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        file.data,
+    ))
+}
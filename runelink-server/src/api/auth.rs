@@ -1,6 +1,9 @@
+use std::net::SocketAddr;
+
 use axum::{
     Form, Json, Router,
-    extract::State,
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
     response::IntoResponse,
     routing::{get, post},
 };
@@ -12,8 +15,10 @@ use runelink_types::{
 };
 use serde_json::json;
 
+use super::extract::ApiJson;
 use crate::{
     auth_service,
+    bearer_auth::ClientAuth,
     error::{ApiError, ApiResult},
     state::AppState,
 };
@@ -48,7 +53,10 @@ pub async fn discovery(
         userinfo_endpoint: format!("{issuer}/auth/userinfo"),
         grant_types_supported: vec!["password".into(), "refresh_token".into()],
         response_types_supported: vec![],
-        scopes_supported: vec![],
+        scopes_supported: auth_service::SUPPORTED_SCOPES
+            .iter()
+            .map(|scope| scope.to_string())
+            .collect(),
         token_endpoint_auth_methods_supported: vec!["none".into()],
     })
 }
@@ -57,19 +65,18 @@ pub async fn discovery(
 pub async fn jwks(State(state): State<AppState>) -> Json<JwksResponse> {
     info!("GET /.well-known/jwks.json");
     Json(JwksResponse {
-        keys: vec![state.key_manager.public_jwk.clone()],
+        keys: state.key_manager.public_jwks(),
     })
 }
 
 pub async fn token(
     State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     Form(req): Form<TokenRequest>,
 ) -> ApiResult<impl IntoResponse> {
     info!("POST /auth/token?grant_type={}", req.grant_type);
     // TODO: check dynamic client IDs for validity
     let client_id = req.client_id.unwrap_or_else(|| "default".into());
-    // TODO: check requested scopes for validity
-    let scope = req.scope.unwrap_or_else(|| "openid".into());
 
     match req.grant_type.as_str() {
         "password" => {
@@ -82,9 +89,10 @@ pub async fn token(
                     password: req.password.ok_or(ApiError::BadRequest(
                         "missing password".into(),
                     ))?,
-                    scope: Some(scope),
+                    scope: req.scope,
                     client_id: Some(client_id),
                 },
+                peer.ip(),
             )
             .await?;
 
@@ -98,9 +106,10 @@ pub async fn token(
                     refresh_token: req.refresh_token.ok_or(
                         ApiError::BadRequest("missing refresh_token".into()),
                     )?,
-                    scope: Some(scope),
+                    scope: req.scope,
                     client_id: Some(client_id),
                 },
+                peer.ip(),
             )
             .await?;
 
@@ -111,14 +120,15 @@ pub async fn token(
     }
 }
 
-/// Protected endpoint returning user claims (stubbed for now)
-pub async fn userinfo() -> Json<serde_json::Value> {
+/// Protected endpoint returning the authenticated user's claims
+pub async fn userinfo(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> ApiResult<impl IntoResponse> {
     info!("GET /auth/userinfo");
-    // TODO: Implement userinfo endpoint with actual user data
-    Json(json!({
-        "error": "not_implemented",
-        "message": "Userinfo endpoint not yet implemented"
-    }))
+    let auth = ClientAuth::from_headers(&headers, &state)?;
+    let userinfo = auth_service::userinfo(&state, &auth).await?;
+    Ok((StatusCode::OK, Json(userinfo)))
 }
 
 /// Dynamic Client Registration endpoint (stubbed for now)
@@ -134,10 +144,11 @@ pub async fn register_client() -> Json<serde_json::Value> {
 /// POST /auth/signup
 pub async fn signup(
     State(state): State<AppState>,
-    Json(req): Json<SignupRequest>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    ApiJson(req): ApiJson<SignupRequest>,
 ) -> ApiResult<impl IntoResponse> {
     info!("POST /auth/signup\nsignup_request = {:#?}", req);
-    let user = auth_service::signup(&state, req).await?;
+    let user = auth_service::signup(&state, req, peer.ip()).await?;
 
     Ok((StatusCode::CREATED, Json(user)))
 }
@@ -10,6 +10,7 @@ use runelink_types::{
 };
 use serde::Deserialize;
 
+use super::extract::ApiJson;
 use crate::{
     auth::{Principal, authorize},
     error::ApiResult,
@@ -28,7 +29,7 @@ pub async fn create(
     headers: HeaderMap,
     Path(server_id): Path<ServerId>,
     Query(params): Query<ChannelQueryParams>,
-    Json(new_channel): Json<NewChannel>,
+    ApiJson(new_channel): ApiJson<NewChannel>,
 ) -> ApiResult<impl IntoResponse> {
     info!(
         "POST /servers/{server_id}/channels?target_host={:?}\nnew_channel = {:#?}",
@@ -0,0 +1,93 @@
+use axum::{
+    Json,
+    extract::{FromRequest, Request, rejection::JsonRejection},
+};
+
+use crate::error::ApiError;
+
+/// A JSON body extractor that reports parse failures as `ApiError`.
+///
+/// Axum's own `Json` rejection is a plain-text body; this keeps malformed
+/// request bodies in the same `{"error": ...}` shape as every other error.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApiJson<T>(pub T);
+
+impl<T, S> FromRequest<S> for ApiJson<T>
+where
+    Json<T>: FromRequest<S, Rejection = JsonRejection>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, ApiError> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        Ok(Self(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::{Body, to_bytes},
+        http::{StatusCode, header},
+        response::IntoResponse,
+    };
+    use runelink_types::message::NewMessage;
+
+    fn json_request(body: &'static str) -> Request {
+        Request::builder()
+            .method("POST")
+            .uri("/servers/s/channels/c/messages")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn rejection_body(rejection: ApiError) -> (StatusCode, String) {
+        let response = rejection.into_response();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        (status, json["error"].as_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_malformed_json_is_structured_bad_request() {
+        let rejection =
+            ApiJson::<NewMessage>::from_request(json_request("{invalid}"), &())
+                .await
+                .unwrap_err();
+        let (status, error) = rejection_body(rejection).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(error.starts_with("Bad request: "));
+        assert!(error.contains("line 1"));
+    }
+
+    #[tokio::test]
+    async fn test_missing_field_names_the_field() {
+        let rejection = ApiJson::<NewMessage>::from_request(
+            json_request(r#"{"author": {"name": "a", "host": "h"}}"#),
+            &(),
+        )
+        .await
+        .unwrap_err();
+        let (status, error) = rejection_body(rejection).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(error.contains("body"));
+    }
+
+    #[tokio::test]
+    async fn test_missing_content_type_is_bad_request() {
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .body(Body::from("{}"))
+            .unwrap();
+        let rejection = ApiJson::<NewMessage>::from_request(req, &())
+            .await
+            .unwrap_err();
+        let (status, _) = rejection_body(rejection).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,76 @@
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use log::info;
+use runelink_types::server::{InviteRedemption, NewServerInvite, ServerId};
+use serde::Deserialize;
+
+use super::extract::ApiJson;
+use crate::{
+    auth::{Principal, authorize},
+    error::ApiResult,
+    ops,
+    state::AppState,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct InviteQueryParams {
+    pub target_host: Option<String>,
+}
+
+/// POST /servers/{server_id}/invites
+pub async fn create(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(server_id): Path<ServerId>,
+    Query(params): Query<InviteQueryParams>,
+    ApiJson(new_invite): ApiJson<NewServerInvite>,
+) -> ApiResult<impl IntoResponse> {
+    info!(
+        "POST /servers/{server_id}/invites?target_host={:?}\nnew_invite = {:#?}",
+        params.target_host, new_invite
+    );
+    let session = authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::invites::auth::create(server_id),
+    )
+    .await?;
+    let invite = ops::invites::create(
+        &state,
+        &session,
+        server_id,
+        &new_invite,
+        params.target_host.as_deref(),
+    )
+    .await?;
+    Ok((StatusCode::CREATED, Json(invite)))
+}
+
+/// POST /invites/redeem
+pub async fn redeem(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<InviteQueryParams>,
+    ApiJson(redemption): ApiJson<InviteRedemption>,
+) -> ApiResult<impl IntoResponse> {
+    // The code itself is a secret, so it is left out of the log line
+    info!("POST /invites/redeem?target_host={:?}", params.target_host);
+    let session = authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::memberships::auth::create_via_invite(),
+    )
+    .await?;
+    let membership = ops::memberships::create_via_invite(
+        &state,
+        &session,
+        &redemption.code,
+        None,
+        params.target_host.as_deref(),
+    )
+    .await?;
+    Ok((StatusCode::CREATED, Json(membership)))
+}
@@ -5,11 +5,12 @@ use axum::{
 };
 use log::info;
 use runelink_types::{
-    server::{NewServerMembership, ServerId},
+    server::{BulkMembershipEntry, NewServerMembership, ServerId},
     user::UserRef,
 };
 use serde::Deserialize;
 
+use super::extract::ApiJson;
 use crate::{
     auth::{Principal, authorize},
     error::{ApiError, ApiResult},
@@ -66,7 +67,7 @@ pub async fn create(
     State(state): State<AppState>,
     headers: HeaderMap,
     Path(server_id): Path<ServerId>,
-    Json(new_membership): Json<NewServerMembership>,
+    ApiJson(new_membership): ApiJson<NewServerMembership>,
 ) -> ApiResult<impl IntoResponse> {
     info!(
         "POST /servers/{server_id}/users\nnew_membership = {:#?}",
@@ -89,6 +90,28 @@ pub async fn create(
     Ok((StatusCode::CREATED, Json(membership)))
 }
 
+/// POST /servers/{server_id}/members/bulk
+pub async fn import_bulk(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(server_id): Path<ServerId>,
+    ApiJson(entries): ApiJson<Vec<BulkMembershipEntry>>,
+) -> ApiResult<impl IntoResponse> {
+    info!(
+        "POST /servers/{server_id}/members/bulk ({} entries)",
+        entries.len()
+    );
+    authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::memberships::auth::import_bulk(server_id),
+    )
+    .await?;
+    let results =
+        ops::memberships::import_bulk(&state, server_id, &entries).await?;
+    Ok((StatusCode::OK, Json(results)))
+}
+
 /// GET /users/{host}/{name}/memberships
 pub async fn get_by_user(
     State(state): State<AppState>,
@@ -6,11 +6,12 @@ use axum::{
 use log::info;
 use runelink_types::{
     channel::ChannelId,
-    message::{MessageId, NewMessage},
+    message::{MessageId, NewMessage, NewReaction},
     server::ServerId,
 };
 use serde::Deserialize;
 
+use super::extract::ApiJson;
 use crate::{
     auth::{Principal, authorize},
     error::ApiResult,
@@ -23,13 +24,28 @@ pub struct MessageQueryParams {
     pub target_host: Option<String>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct MessageSearchQueryParams {
+    pub target_host: Option<String>,
+    pub q: String,
+    pub limit: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MessageListQueryParams {
+    pub target_host: Option<String>,
+    /// Only return messages older than this one.
+    pub before: Option<MessageId>,
+    pub limit: Option<u32>,
+}
+
 /// POST /servers/{server_id}/channels/{channel_id}/messages
 pub async fn create(
     State(state): State<AppState>,
     headers: HeaderMap,
     Path((server_id, channel_id)): Path<(ServerId, ChannelId)>,
     Query(params): Query<MessageQueryParams>,
-    Json(new_message): Json<NewMessage>,
+    ApiJson(new_message): ApiJson<NewMessage>,
 ) -> ApiResult<impl IntoResponse> {
     info!(
         "POST /servers/{server_id}/channels/{channel_id}/messages?target_host={:?}\nnew_message = {:#?}",
@@ -104,11 +120,11 @@ pub async fn get_by_channel(
     State(state): State<AppState>,
     headers: HeaderMap,
     Path((server_id, channel_id)): Path<(ServerId, ChannelId)>,
-    Query(params): Query<MessageQueryParams>,
+    Query(params): Query<MessageListQueryParams>,
 ) -> ApiResult<impl IntoResponse> {
     info!(
-        "GET /servers/{server_id}/channels/{channel_id}/messages?target_host={:?}",
-        params.target_host
+        "GET /servers/{server_id}/channels/{channel_id}/messages?target_host={:?}&before={:?}&limit={:?}",
+        params.target_host, params.before, params.limit
     );
     let session = authorize(
         &state,
@@ -121,6 +137,37 @@ pub async fn get_by_channel(
         &session,
         server_id,
         channel_id,
+        params.before,
+        params.limit,
+        params.target_host.as_deref(),
+    )
+    .await?;
+    Ok((StatusCode::OK, Json(messages)))
+}
+
+/// GET /servers/{server_id}/messages/search
+pub async fn search(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(server_id): Path<ServerId>,
+    Query(params): Query<MessageSearchQueryParams>,
+) -> ApiResult<impl IntoResponse> {
+    info!(
+        "GET /servers/{server_id}/messages/search?q={:?}&limit={:?}&target_host={:?}",
+        params.q, params.limit, params.target_host
+    );
+    let session = authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::messages::auth::search(server_id),
+    )
+    .await?;
+    let messages = ops::messages::search(
+        &state,
+        &session,
+        server_id,
+        &params.q,
+        params.limit,
         params.target_host.as_deref(),
     )
     .await?;
@@ -192,3 +239,125 @@ pub async fn delete(
     .await?;
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// POST /servers/{server_id}/channels/{channel_id}/messages/{message_id}/reactions
+pub async fn add_reaction(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((server_id, channel_id, message_id)): Path<(
+        ServerId,
+        ChannelId,
+        MessageId,
+    )>,
+    Query(params): Query<MessageQueryParams>,
+    ApiJson(new_reaction): ApiJson<NewReaction>,
+) -> ApiResult<impl IntoResponse> {
+    info!(
+        "POST /servers/{server_id}/channels/{channel_id}/messages/{message_id}/reactions?target_host={:?}\nnew_reaction = {:#?}",
+        params.target_host, new_reaction
+    );
+    let session = authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::messages::auth::react(server_id),
+    )
+    .await?;
+    let message = ops::messages::add_reaction(
+        &state,
+        &session,
+        server_id,
+        channel_id,
+        message_id,
+        &new_reaction,
+        params.target_host.as_deref(),
+    )
+    .await?;
+    Ok((StatusCode::OK, Json(message)))
+}
+
+/// DELETE /servers/{server_id}/channels/{channel_id}/messages/{message_id}/reactions/{emoji}
+pub async fn remove_reaction(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((server_id, channel_id, message_id, emoji)): Path<(
+        ServerId,
+        ChannelId,
+        MessageId,
+        String,
+    )>,
+    Query(params): Query<MessageQueryParams>,
+) -> ApiResult<impl IntoResponse> {
+    info!(
+        "DELETE /servers/{server_id}/channels/{channel_id}/messages/{message_id}/reactions/{emoji}?target_host={:?}",
+        params.target_host
+    );
+    let session = authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::messages::auth::react(server_id),
+    )
+    .await?;
+    let message = ops::messages::remove_reaction(
+        &state,
+        &session,
+        server_id,
+        channel_id,
+        message_id,
+        &emoji,
+        params.target_host.as_deref(),
+    )
+    .await?;
+    Ok((StatusCode::OK, Json(message)))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChannelPurgeQueryParams {
+    pub target_host: Option<String>,
+    /// Only delete messages older than this one.
+    pub before: Option<MessageId>,
+}
+
+/// POST /servers/{server_id}/channels/{channel_id}/messages/purge
+pub async fn purge_channel(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((server_id, channel_id)): Path<(ServerId, ChannelId)>,
+    Query(params): Query<ChannelPurgeQueryParams>,
+) -> ApiResult<impl IntoResponse> {
+    info!(
+        "POST /servers/{server_id}/channels/{channel_id}/messages/purge?target_host={:?}&before={:?}",
+        params.target_host, params.before
+    );
+    let session = authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::messages::auth::purge_channel(server_id),
+    )
+    .await?;
+    let report = ops::messages::purge_channel(
+        &state,
+        &session,
+        server_id,
+        channel_id,
+        params.before,
+        params.target_host.as_deref(),
+    )
+    .await?;
+    Ok((StatusCode::OK, Json(report)))
+}
+
+/// POST /admin/messages/purge
+pub async fn purge_deleted(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> ApiResult<impl IntoResponse> {
+    info!("POST /admin/messages/purge");
+    authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::messages::auth::purge_deleted(),
+    )
+    .await?;
+    let report = ops::messages::purge_deleted(&state).await?;
+    Ok((StatusCode::OK, Json(report)))
+}
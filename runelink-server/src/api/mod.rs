@@ -1,15 +1,24 @@
 use crate::{state::AppState, ws};
-use axum::{Router, extract::Query, response::IntoResponse, routing::get};
+use axum::{
+    Router,
+    extract::{DefaultBodyLimit, Query},
+    response::IntoResponse,
+    routing::{delete, get, post},
+};
 use log::info;
 use serde::Deserialize;
 use tower_http::cors;
 
+mod attachments;
 mod auth;
 mod channels;
+mod extract;
+mod invites;
 mod memberships;
 mod messages;
 mod servers;
 mod users;
+mod webhooks;
 
 /// Creates a router for all API endpoints.
 pub fn router() -> Router<AppState> {
@@ -27,10 +36,18 @@ pub fn router() -> Router<AppState> {
         // API routes
         .route("/ping", get(ping))
         .route("/users", get(users::get_all).post(users::create))
+        .route("/users/search", get(users::search))
+        .route("/admin/users", post(users::admin_create))
+        .route("/admin/federation/resync", post(servers::resync_remote))
+        .route("/admin/messages/purge", post(messages::purge_deleted))
         .route(
             "/users/{host}/{name}",
             get(users::get_by_ref).delete(users::delete),
         )
+        .route(
+            "/users/{host}/{name}/sessions",
+            get(users::get_sessions).delete(users::revoke_sessions),
+        )
         .route(
             "/users/{host}/{name}/hosts",
             get(users::get_associated_hosts),
@@ -39,11 +56,26 @@ pub fn router() -> Router<AppState> {
             "/users/{host}/{name}/servers",
             get(memberships::get_by_user),
         )
+        .route("/invites/redeem", post(invites::redeem))
         .route("/messages", get(messages::get_all))
         .route(
             "/servers/{server_id}/channels/{channel_id}/messages/{message_id}",
             get(messages::get_by_id).delete(messages::delete),
         )
+        .route(
+            "/servers/{server_id}/channels/{channel_id}/messages/{message_id}/reactions",
+            post(messages::add_reaction),
+        )
+        .route(
+            "/servers/{server_id}/channels/{channel_id}/messages/{message_id}/reactions/{emoji}",
+            delete(messages::remove_reaction),
+        )
+        .route(
+            "/servers/{server_id}/channels/{channel_id}/attachments",
+            // The configured attachment limit is enforced while reading
+            post(attachments::upload).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/attachments/{attachment_id}", get(attachments::download))
         .route("/channels", get(channels::get_all))
         .route(
             "/servers/{server_id}/channels/{channel_id}",
@@ -53,6 +85,19 @@ pub fn router() -> Router<AppState> {
             "/servers/{server_id}/channels/{channel_id}/messages",
             get(messages::get_by_channel).post(messages::create),
         )
+        .route(
+            "/servers/{server_id}/channels/{channel_id}/messages/purge",
+            post(messages::purge_channel),
+        )
+        .route(
+            "/servers/{server_id}/channels/{channel_id}/webhooks",
+            post(webhooks::create),
+        )
+        .route(
+            "/servers/{server_id}/channels/{channel_id}/webhooks/{webhook_id}",
+            delete(webhooks::delete),
+        )
+        .route("/webhooks/{webhook_id}/{token}", post(webhooks::execute))
         .route("/servers", get(servers::get_all).post(servers::create))
         .route(
             "/servers/{server_id}",
@@ -66,14 +111,28 @@ pub fn router() -> Router<AppState> {
             "/servers/{server_id}/messages",
             get(messages::get_by_server),
         )
+        .route(
+            "/servers/{server_id}/messages/search",
+            get(messages::search),
+        )
+        .route("/servers/{server_id}/summary", get(servers::get_summary))
         .route(
             "/servers/{server_id}/with_channels",
             get(servers::get_with_channels),
         )
+        .route(
+            "/servers/{server_id}/analytics",
+            get(servers::get_analytics),
+        )
         .route(
             "/servers/{server_id}/users",
             get(memberships::get_members_by_server).post(memberships::create),
         )
+        .route("/servers/{server_id}/invites", post(invites::create))
+        .route(
+            "/servers/{server_id}/members/bulk",
+            post(memberships::import_bulk),
+        )
         .route(
             "/servers/{server_id}/users/{host}/{name}",
             get(memberships::get_by_user_and_server)
@@ -1,15 +1,19 @@
 use axum::{
     extract::{Json, Path, Query, State},
-    http::{HeaderMap, StatusCode},
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION},
     response::IntoResponse,
 };
 use log::info;
-use runelink_types::server::{NewServer, ServerId};
+use runelink_types::{
+    server::{FederationResyncRequest, NewServer, ServerId},
+    user::UserRef,
+};
 use serde::Deserialize;
 
+use super::extract::ApiJson;
 use crate::{
-    auth::{Principal, authorize},
-    error::ApiResult,
+    auth::{Principal, Requirement, authorize},
+    error::{ApiError, ApiResult},
     ops,
     state::AppState,
 };
@@ -19,12 +23,34 @@ pub struct ServerQueryParams {
     pub target_host: Option<String>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ServerListQueryParams {
+    pub target_host: Option<String>,
+    /// Also list private servers; host admins only.
+    #[serde(default)]
+    pub include_private: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ServerGetQueryParams {
+    pub target_host: Option<String>,
+    #[serde(default)]
+    pub force_refresh: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ServerWithChannelsQueryParams {
+    pub target_host: Option<String>,
+    #[serde(default)]
+    pub include_last_messages: bool,
+}
+
 /// POST /servers
 pub async fn create(
     State(state): State<AppState>,
     headers: HeaderMap,
     Query(params): Query<ServerQueryParams>,
-    Json(new_server): Json<NewServer>,
+    ApiJson(new_server): ApiJson<NewServer>,
 ) -> ApiResult<impl IntoResponse> {
     info!(
         "POST /servers?target_host={:?}\nnew_server = {:#?}",
@@ -49,43 +75,131 @@ pub async fn create(
 /// GET /servers
 pub async fn get_all(
     State(state): State<AppState>,
-    Query(params): Query<ServerQueryParams>,
+    headers: HeaderMap,
+    Query(params): Query<ServerListQueryParams>,
 ) -> ApiResult<impl IntoResponse> {
-    info!("GET /servers?target_host={:?}", params.target_host);
-    let servers =
-        ops::servers::get_all(&state, params.target_host.as_deref()).await?;
+    info!(
+        "GET /servers?target_host={:?}&include_private={}",
+        params.target_host, params.include_private
+    );
+    if params.include_private {
+        authorize(
+            &state,
+            Principal::from_client_headers(&headers, &state)?,
+            ops::servers::auth::get_all_including_private(),
+        )
+        .await?;
+    }
+    let servers = ops::servers::get_all(
+        &state,
+        params.include_private,
+        params.target_host.as_deref(),
+    )
+    .await?;
     Ok((StatusCode::OK, Json(servers)))
 }
 
 /// GET /servers/{server_id}
 pub async fn get_by_id(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Path(server_id): Path<ServerId>,
-    Query(params): Query<ServerQueryParams>,
+    Query(params): Query<ServerGetQueryParams>,
 ) -> ApiResult<impl IntoResponse> {
     info!(
-        "GET /servers/{server_id}?target_host={:?}",
-        params.target_host
+        "GET /servers/{server_id}?target_host={:?}&force_refresh={}",
+        params.target_host, params.force_refresh
     );
+    let viewer = authorize_viewer(
+        &state,
+        &headers,
+        server_id,
+        params.target_host.as_deref(),
+    )
+    .await?;
     let server = ops::servers::get_by_id(
         &state,
+        viewer.as_ref(),
         server_id,
+        params.force_refresh,
         params.target_host.as_deref(),
     )
     .await?;
     Ok((StatusCode::OK, Json(server)))
 }
 
+/// GET /servers/{server_id}/summary
+pub async fn get_summary(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(server_id): Path<ServerId>,
+    Query(params): Query<ServerGetQueryParams>,
+) -> ApiResult<impl IntoResponse> {
+    info!(
+        "GET /servers/{server_id}/summary?target_host={:?}&force_refresh={}",
+        params.target_host, params.force_refresh
+    );
+    let viewer = authorize_viewer(
+        &state,
+        &headers,
+        server_id,
+        params.target_host.as_deref(),
+    )
+    .await?;
+    let summary = ops::servers::get_summary(
+        &state,
+        viewer.as_ref(),
+        server_id,
+        params.force_refresh,
+        params.target_host.as_deref(),
+    )
+    .await?;
+    Ok((StatusCode::OK, Json(summary)))
+}
+
+/// Authorize a lookup of a single server, returning the viewer to act as.
+async fn authorize_viewer(
+    state: &AppState,
+    headers: &HeaderMap,
+    server_id: ServerId,
+    target_host: Option<&str>,
+) -> ApiResult<Option<UserRef>> {
+    let requirement =
+        ops::servers::auth::get_by_id(state, server_id, target_host).await?;
+    // Remote lookups are delegated as the caller when they sent a token, so
+    // the home host can check membership of private servers.
+    let session = match requirement {
+        Some(requirement) => Some(
+            authorize(
+                state,
+                Principal::from_client_headers(headers, state)?,
+                requirement,
+            )
+            .await?,
+        ),
+        None if headers.contains_key(AUTHORIZATION) => Some(
+            authorize(
+                state,
+                Principal::from_client_headers(headers, state)?,
+                Requirement::Client,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    Ok(session.and_then(|session| session.user_ref))
+}
+
 /// GET /servers/{server_id}/with_channels
 pub async fn get_with_channels(
     State(state): State<AppState>,
     headers: HeaderMap,
     Path(server_id): Path<ServerId>,
-    Query(params): Query<ServerQueryParams>,
+    Query(params): Query<ServerWithChannelsQueryParams>,
 ) -> ApiResult<impl IntoResponse> {
     info!(
-        "GET /servers/{server_id}/with_channels?target_host={:?}",
-        params.target_host
+        "GET /servers/{server_id}/with_channels?target_host={:?}&include_last_messages={}",
+        params.target_host, params.include_last_messages
     );
     let session = authorize(
         &state,
@@ -97,12 +211,58 @@ pub async fn get_with_channels(
         &state,
         &session,
         server_id,
+        params.include_last_messages,
         params.target_host.as_deref(),
     )
     .await?;
     Ok((StatusCode::OK, Json(server_with_channels)))
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ServerAnalyticsQueryParams {
+    /// Window size such as `7d`; defaults to 7 days.
+    pub window: Option<String>,
+}
+
+/// GET /servers/{server_id}/analytics
+pub async fn get_analytics(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(server_id): Path<ServerId>,
+    Query(params): Query<ServerAnalyticsQueryParams>,
+) -> ApiResult<impl IntoResponse> {
+    info!(
+        "GET /servers/{server_id}/analytics?window={:?}",
+        params.window
+    );
+    let window_days = match params.window.as_deref() {
+        Some(window) => parse_window_days(window)?,
+        None => 7,
+    };
+    authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::servers::auth::get_analytics(server_id),
+    )
+    .await?;
+    let analytics =
+        ops::servers::get_analytics(&state, server_id, window_days).await?;
+    Ok((StatusCode::OK, Json(analytics)))
+}
+
+/// Parses a window such as `7d` (or a bare `7`) into a number of days.
+fn parse_window_days(window: &str) -> ApiResult<u32> {
+    window
+        .strip_suffix('d')
+        .unwrap_or(window)
+        .parse::<u32>()
+        .map_err(|_| {
+            ApiError::BadRequest(format!(
+                "Invalid analytics window '{window}', expected e.g. '7d'"
+            ))
+        })
+}
+
 /// DELETE /servers/{server_id}
 pub async fn delete(
     State(state): State<AppState>,
@@ -129,3 +289,20 @@ pub async fn delete(
     .await?;
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// POST /admin/federation/resync
+pub async fn resync_remote(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiJson(request): ApiJson<FederationResyncRequest>,
+) -> ApiResult<impl IntoResponse> {
+    info!("POST /admin/federation/resync\nrequest = {:#?}", request);
+    authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::servers::auth::resync_remote(),
+    )
+    .await?;
+    let report = ops::servers::resync_remote(&state, &request).await?;
+    Ok((StatusCode::OK, Json(report)))
+}
@@ -1,3 +1,4 @@
+use super::extract::ApiJson;
 use crate::{
     auth::{Principal, authorize},
     error::ApiResult,
@@ -10,7 +11,7 @@ use axum::{
     response::IntoResponse,
 };
 use log::info;
-use runelink_types::{NewUser, UserRef};
+use runelink_types::{AdminCreateUserRequest, NewUser, UserQuery, UserRef};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug)]
@@ -18,31 +19,102 @@ pub struct UserQueryParams {
     pub target_host: Option<String>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct UserSearchQueryParams {
+    pub q: String,
+    /// Comma-separated hosts to search besides this one.
+    pub hosts: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UserListQueryParams {
+    pub target_host: Option<String>,
+    /// Only users whose name starts with this.
+    pub name_prefix: Option<String>,
+    /// Only users from this host.
+    pub host: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
 /// POST /users
 pub async fn create(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(new_user): Json<NewUser>,
+    ApiJson(new_user): ApiJson<NewUser>,
 ) -> ApiResult<impl IntoResponse> {
     info!("POST /users\nnew_user = {:#?}", new_user);
-    let session = authorize(
+    let mut session = authorize(
         &state,
         Principal::from_client_headers(&headers, &state)?,
         ops::users::auth::create(),
     )
     .await?;
-    let user = ops::users::create(&state, &session, &new_user).await?;
+    let user = ops::users::create(&state, &mut session, &new_user).await?;
     Ok((StatusCode::CREATED, Json(user)))
 }
 
+/// POST /admin/users
+pub async fn admin_create(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiJson(request): ApiJson<AdminCreateUserRequest>,
+) -> ApiResult<impl IntoResponse> {
+    info!("POST /admin/users\nrequest = {:#?}", request);
+    let session = authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::users::auth::admin_create(),
+    )
+    .await?;
+    let created = ops::users::admin_create(&state, &session, request).await?;
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
 /// GET /users
 pub async fn get_all(
     State(state): State<AppState>,
-    Query(params): Query<UserQueryParams>,
+    Query(params): Query<UserListQueryParams>,
 ) -> ApiResult<impl IntoResponse> {
-    info!("GET /users?target_host={:?}", params.target_host);
+    info!(
+        "GET /users?target_host={:?}&name_prefix={:?}&host={:?}&limit={:?}&offset={:?}",
+        params.target_host,
+        params.name_prefix,
+        params.host,
+        params.limit,
+        params.offset
+    );
+    let query = UserQuery {
+        name_prefix: params.name_prefix,
+        host: params.host,
+        limit: params.limit,
+        offset: params.offset,
+    };
     let users =
-        ops::users::get_all(&state, params.target_host.as_deref()).await?;
+        ops::users::get_all(&state, &query, params.target_host.as_deref())
+            .await?;
+    Ok((StatusCode::OK, Json(users)))
+}
+
+/// GET /users/search
+pub async fn search(
+    State(state): State<AppState>,
+    Query(params): Query<UserSearchQueryParams>,
+) -> ApiResult<impl IntoResponse> {
+    info!(
+        "GET /users/search?q={:?}&hosts={:?}",
+        params.q, params.hosts
+    );
+    let hosts = params
+        .hosts
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|host| !host.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let users = ops::users::search(&state, &params.q, &hosts).await?;
     Ok((StatusCode::OK, Json(users)))
 }
 
@@ -100,3 +172,40 @@ pub async fn delete(
     ops::users::delete_home_user(&state, &session, &user_ref).await?;
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// GET /users/{host}/{name}/sessions
+pub async fn get_sessions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((host, name)): Path<(String, String)>,
+) -> ApiResult<impl IntoResponse> {
+    let user_ref = UserRef::new(name.clone(), host.clone());
+    info!("GET /users/{host}/{name}/sessions");
+    let session = authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::users::auth::sessions(user_ref.clone()),
+    )
+    .await?;
+    let sessions =
+        ops::users::get_sessions(&state, &session, &user_ref).await?;
+    Ok((StatusCode::OK, Json(sessions)))
+}
+
+/// DELETE /users/{host}/{name}/sessions
+pub async fn revoke_sessions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((host, name)): Path<(String, String)>,
+) -> ApiResult<impl IntoResponse> {
+    let user_ref = UserRef::new(name.clone(), host.clone());
+    info!("DELETE /users/{host}/{name}/sessions");
+    let session = authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::users::auth::sessions(user_ref.clone()),
+    )
+    .await?;
+    ops::users::revoke_sessions(&state, &session, &user_ref).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,74 @@
+use axum::{
+    extract::{Json, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use log::info;
+use runelink_types::{
+    channel::{ChannelId, WebhookId, WebhookPayload},
+    server::ServerId,
+};
+
+use super::extract::ApiJson;
+use crate::{
+    auth::{Principal, authorize},
+    error::ApiResult,
+    ops,
+    state::AppState,
+};
+
+/// POST /servers/{server_id}/channels/{channel_id}/webhooks
+pub async fn create(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((server_id, channel_id)): Path<(ServerId, ChannelId)>,
+) -> ApiResult<impl IntoResponse> {
+    info!("POST /servers/{server_id}/channels/{channel_id}/webhooks");
+    let session = authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::webhooks::auth::create(server_id),
+    )
+    .await?;
+    let webhook =
+        ops::webhooks::create(&state, &session, server_id, channel_id).await?;
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+/// DELETE /servers/{server_id}/channels/{channel_id}/webhooks/{webhook_id}
+pub async fn delete(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((server_id, channel_id, webhook_id)): Path<(
+        ServerId,
+        ChannelId,
+        WebhookId,
+    )>,
+) -> ApiResult<impl IntoResponse> {
+    info!(
+        "DELETE /servers/{server_id}/channels/{channel_id}/webhooks/{webhook_id}"
+    );
+    authorize(
+        &state,
+        Principal::from_client_headers(&headers, &state)?,
+        ops::webhooks::auth::delete(server_id),
+    )
+    .await?;
+    ops::webhooks::delete(&state, server_id, channel_id, webhook_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// POST /webhooks/{webhook_id}/{token}
+///
+/// Public: the token in the URL is the only credential.
+pub async fn execute(
+    State(state): State<AppState>,
+    Path((webhook_id, token)): Path<(WebhookId, String)>,
+    ApiJson(payload): ApiJson<WebhookPayload>,
+) -> ApiResult<impl IntoResponse> {
+    // The token is a secret, so it is left out of the log line
+    info!("POST /webhooks/{webhook_id}");
+    let message =
+        ops::webhooks::execute(&state, webhook_id, &token, payload).await?;
+    Ok((StatusCode::CREATED, Json(message)))
+}
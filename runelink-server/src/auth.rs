@@ -20,10 +20,10 @@ use crate::{
 #[macro_export]
 macro_rules! and {
     () => {
-        crate::auth::Requirement::And(vec![])
+        $crate::auth::Requirement::And(vec![])
     };
     ( $( $req:expr ),+ $(,)? ) => {
-        crate::auth::Requirement::And(vec![ $( $req ),+ ])
+        $crate::auth::Requirement::And(vec![ $( $req ),+ ])
     };
 }
 
@@ -32,10 +32,10 @@ macro_rules! and {
 #[macro_export]
 macro_rules! or {
     () => {
-        crate::auth::Requirement::Or(vec![])
+        $crate::auth::Requirement::Or(vec![])
     };
     ( $( $req:expr ),+ $(,)? ) => {
-        crate::auth::Requirement::Or(vec![ $( $req ),+ ])
+        $crate::auth::Requirement::Or(vec![ $( $req ),+ ])
     };
 }
 
@@ -63,6 +63,11 @@ impl Principal {
     }
 }
 
+/// Scope a client token needs to read messages.
+pub const MESSAGES_READ: &str = "messages.read";
+/// Scope a client token needs to send messages or upload files for them.
+pub const MESSAGES_WRITE: &str = "messages.write";
+
 #[derive(Clone, Debug)]
 pub enum Requirement {
     /// Must be authenticated with a client token.
@@ -79,6 +84,11 @@ pub enum Requirement {
     ServerMember(ServerId),
     /// Must be an admin of the referenced server.
     ServerAdmin(ServerId),
+    /// Client tokens must carry the scope; federation requests pass.
+    ///
+    /// A missing scope fails the whole check rather than letting another
+    /// `Or` branch satisfy it.
+    Scope(&'static str),
     /// A requirement that will always be satisfied.
     Always,
     /// A requirement that will never be satisfied.
@@ -102,6 +112,10 @@ impl Requirement {
         and!(Requirement::Federation, self)
     }
 
+    pub fn with_scope(self, scope: &'static str) -> Self {
+        and!(Requirement::Scope(scope), self)
+    }
+
     async fn check(
         &self,
         ctx: &mut AuthContext<'_>,
@@ -178,6 +192,16 @@ impl Requirement {
                 }
             }
 
+            Requirement::Scope(scope) => {
+                if let Principal::Client(auth) = &ctx.principal
+                    && !auth.claims.has_scope(scope)
+                {
+                    return Err(ApiError::Forbidden(format!(
+                        "Token is missing the {scope} scope"
+                    )));
+                }
+            }
+
             Requirement::Always => {
                 return Ok(None);
             }
@@ -318,10 +342,10 @@ impl Session {
     /// Returns an error if the user reference is missing or the user is not in the DB.
     pub async fn require_user(&mut self, state: &AppState) -> ApiResult<User> {
         let user_ref = self.user_ref.clone().ok_or_else(|| {
-            ApiError::AuthError("No delegated user in session".into())
+            ApiError::Forbidden("No delegated user in session".into())
         })?;
         let user = self.lookup_user(state).await?.ok_or_else(|| {
-            ApiError::AuthError(format!("User {user_ref} not found locally"))
+            ApiError::Forbidden(format!("User {user_ref} not found locally"))
         })?;
         Ok(user)
     }
@@ -338,7 +362,7 @@ pub async fn authorize(
         Principal::Client(auth) => {
             let user_ref = UserRef::parse_subject(&auth.claims.sub)
                 .ok_or_else(|| {
-                    ApiError::AuthError(
+                    ApiError::Unauthorized(
                         "Invalid token subject (expected name@host)".into(),
                     )
                 })?;
@@ -356,15 +380,11 @@ pub async fn authorize(
         memberships: None,
     };
     if let Some(error) = req.check(&mut ctx).await? {
-        return Err(ApiError::AuthError(error));
+        return Err(ApiError::Forbidden(error));
     }
     let cached_user = if ctx.user_ref.is_some() {
-        if let Some(user) = ctx.user {
-            Some(Some(user))
-        } else {
-            // Not looked up yet
-            None
-        }
+        // None if not looked up yet
+        ctx.user.map(Some)
     } else {
         // Not delegated
         Some(None)
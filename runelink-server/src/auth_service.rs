@@ -1,23 +1,84 @@
 use argon2::{
     Argon2, PasswordHasher, PasswordVerifier,
-    password_hash::{PasswordHash, SaltString, rand_core::OsRng},
+    password_hash::{
+        PasswordHash, SaltString,
+        rand_core::{OsRng, RngCore},
+    },
 };
-use jsonwebtoken::{Algorithm, Header, Validation};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{Algorithm, Validation};
 use runelink_client::validation::validate_username;
 use runelink_types::{
     ClientAccessClaims, NewUser, RefreshToken, SignupRequest, TokenResponse,
     User, UserRef, UserRole,
-    auth::{AuthTokenPasswordRequest, AuthTokenRefreshRequest},
+    auth::{
+        AdminCreateUserRequest, AdminCreateUserResponse,
+        AuthTokenPasswordRequest, AuthTokenRefreshRequest, UserinfoResponse,
+    },
 };
-use time::{Duration, OffsetDateTime};
+use std::net::IpAddr;
+use time::OffsetDateTime;
 
 use crate::{
     bearer_auth::ClientAuth,
     error::{ApiError, ApiResult},
-    queries,
+    queries, rate_limit,
     state::AppState,
 };
 
+/// Scopes a client may request for its access tokens.
+pub const SUPPORTED_SCOPES: &[&str] =
+    &["openid", "messages.read", "messages.write"];
+
+/// Granted when a client does not ask for any particular scopes.
+const DEFAULT_SCOPE: &str = "openid messages.read messages.write";
+
+/// Checks requested scopes against `SUPPORTED_SCOPES`, falling back to
+/// `DEFAULT_SCOPE` when none were requested.
+fn resolve_scope(requested: Option<String>) -> ApiResult<String> {
+    let Some(requested) = requested else {
+        return Ok(DEFAULT_SCOPE.into());
+    };
+    let mut scopes = Vec::<&str>::new();
+    for scope in requested.split_whitespace() {
+        if !SUPPORTED_SCOPES.contains(&scope) {
+            return Err(ApiError::BadRequest(format!(
+                "invalid scope: {scope}"
+            )));
+        }
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    if scopes.is_empty() {
+        return Ok(DEFAULT_SCOPE.into());
+    }
+    Ok(scopes.join(" "))
+}
+
+/// Like [`resolve_scope`], but limited to the scopes `granted` to the
+/// refresh token, which are kept when none were requested.
+fn resolve_refresh_scope(
+    requested: Option<String>,
+    granted: &str,
+) -> ApiResult<String> {
+    let requested = requested.filter(|scope| !scope.trim().is_empty());
+    if requested.is_none() {
+        return Ok(granted.into());
+    }
+    let scope = resolve_scope(requested)?;
+    let granted = granted.split_whitespace().collect::<Vec<_>>();
+    if let Some(wider) = scope
+        .split_whitespace()
+        .find(|scope| !granted.contains(scope))
+    {
+        return Err(ApiError::BadRequest(format!(
+            "scope not granted to this refresh token: {wider}"
+        )));
+    }
+    Ok(scope)
+}
+
 pub struct IssuedClientToken {
     pub user_ref: UserRef,
     pub response: TokenResponse,
@@ -34,21 +95,95 @@ pub fn authenticate_access_token(
 
     let data = jsonwebtoken::decode::<ClientAccessClaims>(
         access_token,
-        &state.key_manager.decoding_key,
+        state.key_manager.decoding_key_for(access_token)?,
         &validation,
     )
-    .map_err(|_| ApiError::AuthError("Invalid or expired token".into()))?;
+    .map_err(|_| ApiError::Unauthorized("Invalid or expired token".into()))?;
 
     Ok(ClientAuth {
         claims: data.claims,
     })
 }
 
+/// Resolve the user behind a validated access token.
+pub async fn userinfo(
+    state: &AppState,
+    auth: &ClientAuth,
+) -> ApiResult<UserinfoResponse> {
+    let user_ref =
+        UserRef::parse_subject(&auth.claims.sub).ok_or_else(|| {
+            ApiError::Unauthorized(
+                "Invalid token subject (expected name@host)".into(),
+            )
+        })?;
+    userinfo_for(state, user_ref).await
+}
+
+/// Build the userinfo claims for an already-authenticated user.
+pub async fn userinfo_for(
+    state: &AppState,
+    user_ref: UserRef,
+) -> ApiResult<UserinfoResponse> {
+    let user = queries::users::get_by_ref(&state.db_pool, user_ref)
+        .await
+        .map_err(|e| match e {
+            // A valid token for a deleted user is still not a valid login
+            ApiError::NotFound => {
+                ApiError::Unauthorized("Token subject no longer exists".into())
+            }
+            other => other,
+        })?;
+    Ok(UserinfoResponse {
+        sub: user.as_ref().as_subject(),
+        name: user.name,
+        host: user.host,
+        role: user.role,
+    })
+}
+
 pub async fn signup(
     state: &AppState,
     request: SignupRequest,
+    client_ip: IpAddr,
+) -> ApiResult<User> {
+    rate_limit::check_auth(&state.rate_limits, client_ip).await?;
+    if !state.config.signups_enabled {
+        return Err(ApiError::Forbidden(
+            "Signups are disabled on this host".into(),
+        ));
+    }
+    create_account(state, &request.name, &request.password).await
+}
+
+/// Create a local account on behalf of a host admin.
+///
+/// Works regardless of `signups_enabled`.
+pub async fn admin_create_account(
+    state: &AppState,
+    request: AdminCreateUserRequest,
+) -> ApiResult<AdminCreateUserResponse> {
+    let (password, temporary_password) = match request.password {
+        Some(password) => (password, None),
+        None => {
+            let mut bytes = [0u8; 18];
+            OsRng.fill_bytes(&mut bytes);
+            let password = URL_SAFE_NO_PAD.encode(bytes);
+            (password.clone(), Some(password))
+        }
+    };
+    let user = create_account(state, &request.name, &password).await?;
+    Ok(AdminCreateUserResponse {
+        user,
+        temporary_password,
+    })
+}
+
+async fn create_account(
+    state: &AppState,
+    name: &str,
+    password: &str,
 ) -> ApiResult<User> {
-    let name = validate_username(&request.name)
+    let name = validate_username(name)
         .map_err(|error| ApiError::BadRequest(error.to_string()))?;
     let new_user = NewUser {
         name,
@@ -59,7 +194,7 @@ pub async fn signup(
 
     let salt = SaltString::generate(&mut OsRng);
     let password_hash = Argon2::default()
-        .hash_password(request.password.as_bytes(), &salt)
+        .hash_password(password.as_bytes(), &salt)
         .map_err(|error| ApiError::Internal(format!("hashing error: {error}")))?
         .to_string();
 
@@ -73,30 +208,54 @@ pub async fn signup(
     Ok(user)
 }
 
+/// Password grant. Failures count against `client_ip`'s stricter failed
+/// attempt limit.
 pub async fn issue_password_token(
     state: &AppState,
     request: AuthTokenPasswordRequest,
+    client_ip: IpAddr,
+) -> ApiResult<IssuedClientToken> {
+    rate_limit::check_auth(&state.rate_limits, client_ip).await?;
+    let issued = password_grant(state, request).await;
+    if let Err(ApiError::InvalidGrant(_)) = &issued {
+        rate_limit::record_auth_failure(&state.rate_limits, client_ip).await;
+    }
+    issued
+}
+
+async fn password_grant(
+    state: &AppState,
+    request: AuthTokenPasswordRequest,
 ) -> ApiResult<IssuedClientToken> {
     let username = validate_username(&request.username)
         .map_err(|error| ApiError::BadRequest(error.to_string()))?;
     let client_id = request.client_id.unwrap_or_else(|| "default".into());
-    let scope = request.scope.unwrap_or_else(|| "openid".into());
+    let scope = resolve_scope(request.scope)?;
 
+    // Unknown users fail the same way as wrong passwords
+    let invalid_credentials = |error| match error {
+        ApiError::NotFound => {
+            ApiError::InvalidGrant("invalid credentials".into())
+        }
+        error => error,
+    };
     let user = queries::users::get_by_ref(
         &state.db_pool,
         UserRef::new(username, state.config.public_host()),
     )
-    .await?;
+    .await
+    .map_err(invalid_credentials)?;
 
     let user_ref = user.as_ref();
     let account =
         queries::accounts::get_by_user(&state.db_pool, user_ref.clone())
-            .await?;
+            .await
+            .map_err(invalid_credentials)?;
     let parsed_hash = PasswordHash::new(&account.password_hash)
-        .map_err(|_| ApiError::AuthError("invalid password hash".into()))?;
+        .map_err(|_| ApiError::Internal("invalid password hash".into()))?;
     Argon2::default()
         .verify_password(request.password.as_bytes(), &parsed_hash)
-        .map_err(|_| ApiError::AuthError("invalid credentials".into()))?;
+        .map_err(|_| ApiError::InvalidGrant("invalid credentials".into()))?;
 
     issue_client_token_response(state, user_ref, client_id, scope, None).await
 }
@@ -104,14 +263,22 @@ pub async fn issue_password_token(
 pub async fn issue_refresh_token(
     state: &AppState,
     request: AuthTokenRefreshRequest,
+    client_ip: IpAddr,
 ) -> ApiResult<IssuedClientToken> {
+    rate_limit::check_auth(&state.rate_limits, client_ip).await?;
     let refresh_token =
         queries::tokens::get_refresh(&state.db_pool, &request.refresh_token)
-            .await?;
+            .await
+            .map_err(|error| match error {
+                ApiError::NotFound => {
+                    ApiError::InvalidGrant("unknown refresh token".into())
+                }
+                error => error,
+            })?;
 
     let now = OffsetDateTime::now_utc();
     if refresh_token.revoked || refresh_token.expires_at <= now {
-        return Err(ApiError::AuthError(
+        return Err(ApiError::InvalidGrant(
             "refresh token expired or revoked".into(),
         ));
     }
@@ -122,7 +289,7 @@ pub async fn issue_refresh_token(
     );
     let client_id =
         request.client_id.unwrap_or(refresh_token.client_id.clone());
-    let scope = request.scope.unwrap_or_else(|| "openid".into());
+    let scope = resolve_refresh_scope(request.scope, &refresh_token.scope)?;
 
     issue_client_token_response(
         state,
@@ -141,16 +308,16 @@ async fn issue_client_token_response(
     scope: String,
     refresh_token: Option<String>,
 ) -> ApiResult<IssuedClientToken> {
-    let lifetime = Duration::hours(1);
+    let lifetime = state.config.access_token_ttl;
     let claims = ClientAccessClaims::new(
         &user_ref,
         client_id.clone(),
         state.config.api_url(),
-        scope,
+        scope.clone(),
         lifetime,
     );
     let access_token = jsonwebtoken::encode(
-        &Header::new(Algorithm::EdDSA),
+        &state.key_manager.header(),
         &claims,
         &state.key_manager.private_key,
     )
@@ -162,7 +329,8 @@ async fn issue_client_token_response(
             let token = RefreshToken::new(
                 user_ref.clone(),
                 client_id,
-                Duration::days(30),
+                scope,
+                state.config.refresh_token_ttl,
             );
             queries::tokens::insert_refresh(&state.db_pool, &token).await?;
             token.token
@@ -180,3 +348,74 @@ async fn issue_client_token_response(
         },
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::DbPool, test_util};
+
+    #[test]
+    fn test_resolve_scope() {
+        assert_eq!(resolve_scope(None).unwrap(), DEFAULT_SCOPE);
+        assert_eq!(resolve_scope(Some(" ".into())).unwrap(), DEFAULT_SCOPE);
+        assert_eq!(
+            resolve_scope(Some("openid messages.read openid".into())).unwrap(),
+            "openid messages.read"
+        );
+        assert!(matches!(
+            resolve_scope(Some("openid admin".into())),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[sqlx::test]
+    async fn test_refresh_keeps_the_granted_scope(pool: DbPool) {
+        let state = test_util::state(pool);
+        let client_ip = IpAddr::from([127, 0, 0, 1]);
+        admin_create_account(
+            &state,
+            AdminCreateUserRequest {
+                name: "alice".into(),
+                password: Some("correct horse".into()),
+            },
+        )
+        .await
+        .unwrap();
+        let login = issue_password_token(
+            &state,
+            AuthTokenPasswordRequest {
+                username: "alice".into(),
+                password: "correct horse".into(),
+                scope: Some("openid messages.read".into()),
+                client_id: None,
+            },
+            client_ip,
+        )
+        .await
+        .unwrap();
+        let refresh = |scope: Option<&str>| AuthTokenRefreshRequest {
+            refresh_token: login.response.refresh_token.clone(),
+            scope: scope.map(Into::into),
+            client_id: None,
+        };
+
+        let refreshed = issue_refresh_token(&state, refresh(None), client_ip)
+            .await
+            .unwrap();
+        assert_eq!(refreshed.response.scope, "openid messages.read");
+        let narrowed =
+            issue_refresh_token(&state, refresh(Some("openid")), client_ip)
+                .await
+                .unwrap();
+        assert_eq!(narrowed.response.scope, "openid");
+        assert!(matches!(
+            issue_refresh_token(
+                &state,
+                refresh(Some("openid messages.write")),
+                client_ip,
+            )
+            .await,
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+}
@@ -13,12 +13,12 @@ fn extract_bearer_token(headers: &HeaderMap) -> ApiResult<String> {
         .get(header::AUTHORIZATION)
         .and_then(|h| h.to_str().ok())
         .ok_or_else(|| {
-            ApiError::AuthError("Missing Authorization header".into())
+            ApiError::Unauthorized("Missing Authorization header".into())
         })?;
     let token = auth_header
         .strip_prefix("Bearer ")
         .ok_or_else(|| {
-            ApiError::AuthError("Invalid Authorization header format".into())
+            ApiError::Unauthorized("Invalid Authorization header format".into())
         })?
         .trim();
     Ok(token.into())
@@ -43,10 +43,12 @@ impl ClientAuth {
 
         let data = jsonwebtoken::decode::<ClientAccessClaims>(
             &token,
-            &state.key_manager.decoding_key,
+            state.key_manager.decoding_key_for(&token)?,
             &validation,
         )
-        .map_err(|_| ApiError::AuthError("Invalid or expired token".into()))?;
+        .map_err(|_| {
+            ApiError::Unauthorized("Invalid or expired token".into())
+        })?;
 
         Ok(Self {
             claims: data.claims,
@@ -101,7 +103,7 @@ mod tests {
         let headers = make_headers(None);
         let err = extract_bearer_token(&headers).unwrap_err();
         match err {
-            ApiError::AuthError(msg) => {
+            ApiError::Unauthorized(msg) => {
                 assert!(msg.contains("Missing Authorization"))
             }
             _ => panic!("unexpected error type"),
@@ -114,7 +116,7 @@ mod tests {
         let headers = make_headers(Some("Token abc.def.ghi"));
         let err = extract_bearer_token(&headers).unwrap_err();
         match err {
-            ApiError::AuthError(msg) => {
+            ApiError::Unauthorized(msg) => {
                 assert!(msg.contains("Invalid Authorization"))
             }
             _ => panic!("unexpected error type"),
@@ -138,7 +140,7 @@ mod tests {
         headers.insert(header::AUTHORIZATION, value);
         let err = extract_bearer_token(&headers).unwrap_err();
         match err {
-            ApiError::AuthError(msg) => {
+            ApiError::Unauthorized(msg) => {
                 assert!(msg.contains("Missing Authorization"));
             }
             _ => panic!("unexpected error type"),
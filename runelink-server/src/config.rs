@@ -1,11 +1,20 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use runelink_client::util::{get_api_url, pad_host};
-use runelink_client::validation::validate_config_host;
+use runelink_client::validation::{validate_config_host, validate_host};
 use serde::Deserialize;
 
+use crate::rate_limit::RateLimit;
+
 pub type ConfigResult<T> = std::result::Result<T, ConfigError>;
 
+/// Prefix of the environment variables that override config file settings.
+const ENV_PREFIX: &str = "RUNELINK";
+
 #[derive(thiserror::Error, Debug)]
 pub enum ConfigError {
     #[error("Failed to read config file `{0}`: {1}")]
@@ -57,24 +66,133 @@ pub struct ServerConfig {
     pub bind_host: String,
     pub bind_port: u16,
     pub secure: bool,
+    /// Certificate and key to serve HTTPS/WSS with; plain TCP without.
+    pub tls: Option<TlsConfig>,
+    /// Whether `GET /metrics` is served.
+    pub metrics_enabled: bool,
+    /// If set, metrics are served on this port of `bind_host` instead of
+    /// alongside the API, so they can be kept off the public listener.
+    pub metrics_port: Option<u16>,
     pub key_dir: PathBuf,
+    /// Peers to connect to at startup so the first request skips setup.
+    pub federation_warm_hosts: Vec<String>,
+    /// Attempts made to open a federation connection before giving up.
+    pub federation_connect_attempts: u32,
+    /// Delay before the first connection retry; doubles with each retry.
+    pub federation_connect_backoff_base: Duration,
+    /// Longest delay between connection retries.
+    pub federation_connect_backoff_max: Duration,
+    /// How long a host that used up its connection attempts is skipped.
+    pub federation_host_cooldown: Duration,
+    /// If set, the only hosts this server federates with.
+    pub federation_allowlist: Option<Vec<String>>,
+    /// Hosts this server never federates with.
+    pub federation_blocklist: Vec<String>,
+    /// Consecutive failed requests to a host before requests to it are
+    /// failed without being sent.
+    pub federation_breaker_threshold: u32,
+    /// How long a tripped host's requests fail before it is tried again.
+    pub federation_breaker_cooldown: Duration,
+    /// Federation event ids remembered for dropping repeated updates.
+    pub federation_seen_events_capacity: usize,
+    /// Whether outbound federation websockets ask peers for MessagePack
+    /// frames instead of JSON.
+    pub federation_msgpack: bool,
+    /// Whether `/auth/signup` is open. Admins can always create accounts.
+    pub signups_enabled: bool,
+    /// How often websocket connections are pinged.
+    pub ws_ping_interval: Duration,
+    /// How long a websocket may go without any frame before it is dropped.
+    pub ws_idle_timeout: Duration,
+    /// Envelopes buffered per websocket before the connection is dropped.
+    pub ws_outbound_queue_capacity: usize,
+    /// Largest websocket message read from a peer, in bytes.
+    pub max_ws_frame_bytes: usize,
+    /// Recent updates kept per server for clients catching up after a
+    /// reconnect.
+    pub ws_replay_buffer_size: usize,
+    /// Distinct emoji a single message can be reacted with.
+    pub max_reactions_per_message: usize,
+    /// Longest message body accepted from an incoming webhook, in bytes.
+    pub max_webhook_body_len: usize,
+    /// Where uploaded attachments are stored, one file per attachment id.
+    pub attachments_dir: PathBuf,
+    /// Largest attachment upload accepted, in bytes.
+    pub max_attachment_size: usize,
+    /// Content types attachments may be uploaded as.
+    pub attachment_content_types: Vec<String>,
+    /// How long a peer's published signing keys are trusted before refetch.
+    pub jwks_cache_ttl: Duration,
+    /// How long to wait after a failed key fetch before trying that peer
+    /// again.
+    pub jwks_negative_cache_ttl: Duration,
+    /// How long in-flight requests and websockets get to finish on shutdown.
+    pub shutdown_grace_period: Duration,
+    /// How often expired tokens and stale remote caches are deleted.
+    pub cleanup_interval: Duration,
+    /// How long a cached remote server or membership may go without being
+    /// synced before it is deleted.
+    pub remote_cache_ttl: Duration,
+    /// How long deleted messages are kept as tombstones before an admin
+    /// purge may remove them.
+    pub deleted_message_ttl: Duration,
+    /// How often local users' remote memberships are re-fetched from their
+    /// servers' hosts.
+    pub membership_resync_interval: Duration,
+    /// Up to this much is added to each resync delay, so instances don't
+    /// all query the same peers at once.
+    pub membership_resync_jitter: Duration,
+    /// How long issued client access tokens are valid for.
+    pub access_token_ttl: time::Duration,
+    /// How long issued refresh tokens are valid for.
+    pub refresh_token_ttl: time::Duration,
+    /// How long the tokens this server presents to peers are valid for.
+    pub federation_token_ttl: time::Duration,
+    /// Token grants and signups allowed per client IP.
+    pub auth_rate_limit: RateLimit,
+    /// Failed password attempts allowed per client IP before its auth
+    /// requests are refused.
+    pub auth_failure_rate_limit: RateLimit,
+    /// Messages each user may send.
+    pub message_rate_limit: RateLimit,
+}
+
+/// PEM files a server terminates TLS with.
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
 }
 
 impl ServerConfig {
+    /// Loads the servers in the config file, with `RUNELINK_*` environment
+    /// variables overriding its settings (see `RawServerConfig::apply_env`).
     pub fn from_toml_file(path: &PathBuf) -> ConfigResult<Vec<Self>> {
         let file_contents = std::fs::read_to_string(path)
             .map_err(|e| ConfigError::ReadConfigFile(path.clone(), e))?;
-        let parsed: RootConfig = toml::from_str(&file_contents)
-            .map_err(|e| ConfigError::ParseConfigFile(path.clone(), e))?;
+        Self::from_toml_str(&file_contents, path, |key| std::env::var(key).ok())
+    }
+
+    pub(crate) fn from_toml_str(
+        file_contents: &str,
+        path: &Path,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> ConfigResult<Vec<Self>> {
+        let parsed: RootConfig = toml::from_str(file_contents)
+            .map_err(|e| ConfigError::ParseConfigFile(path.to_path_buf(), e))?;
         if parsed.servers.is_empty() {
-            return Err(ConfigError::NoServers(path.clone()));
+            return Err(ConfigError::NoServers(path.to_path_buf()));
         }
         let configs = parsed
             .servers
             .into_iter()
             .enumerate()
-            .map(|(index, raw)| raw.resolve(index))
+            .map(|(index, mut raw)| {
+                raw.apply_env(index, &env)?;
+                raw.resolve(index)
+            })
             .collect::<ConfigResult<Vec<Self>>>()?;
+        // After the overrides, so duplicates they introduce are caught too
         validate_unique_resources(&configs)?;
         Ok(configs)
     }
@@ -101,6 +219,12 @@ impl ServerConfig {
         format!("{}:{}", self.bind_host, self.bind_port)
     }
 
+    /// Address of the separate metrics listener, if one is configured.
+    pub fn metrics_bind_addr(&self) -> Option<String> {
+        self.metrics_port
+            .map(|port| format!("{}:{}", self.bind_host, port))
+    }
+
     #[allow(dead_code)]
     pub fn client_ws_url(&self) -> String {
         runelink_client::util::get_client_ws_url(
@@ -123,6 +247,16 @@ impl ServerConfig {
         };
         pad_host(host) != pad_host(self.public_host().as_str())
     }
+
+    /// Whether the allow and block lists permit federating with `host`.
+    pub fn is_federation_allowed(&self, host: &str) -> bool {
+        let host = pad_host(host);
+        !self.federation_blocklist.contains(&host)
+            && self
+                .federation_allowlist
+                .as_ref()
+                .is_none_or(|allowlist| allowlist.contains(&host))
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -133,8 +267,9 @@ struct RootConfig {
 
 #[derive(Deserialize, Debug)]
 struct RawServerConfig {
-    public_host: String,
-    database_url: String,
+    /// Optional in the file when set through the environment.
+    public_host: Option<String>,
+    database_url: Option<String>,
     #[serde(default = "default_public_port")]
     public_port: u16,
     #[serde(default = "default_bind_host")]
@@ -142,19 +277,153 @@ struct RawServerConfig {
     bind_port: Option<u16>,
     #[serde(default = "default_secure")]
     secure: bool,
+    tls_cert_path: Option<PathBuf>,
+    tls_key_path: Option<PathBuf>,
+    #[serde(default)]
+    metrics_enabled: bool,
+    metrics_port: Option<u16>,
     key_dir: Option<PathBuf>,
+    #[serde(default)]
+    federation_warm_hosts: Vec<String>,
+    #[serde(default = "default_federation_connect_attempts")]
+    federation_connect_attempts: u32,
+    #[serde(default = "default_federation_connect_backoff_base_ms")]
+    federation_connect_backoff_base_ms: u64,
+    #[serde(default = "default_federation_connect_backoff_max_ms")]
+    federation_connect_backoff_max_ms: u64,
+    #[serde(default = "default_federation_host_cooldown_secs")]
+    federation_host_cooldown_secs: u64,
+    federation_allowlist: Option<Vec<String>>,
+    #[serde(default)]
+    federation_blocklist: Vec<String>,
+    #[serde(default = "default_federation_breaker_threshold")]
+    federation_breaker_threshold: u32,
+    #[serde(default = "default_federation_breaker_cooldown_secs")]
+    federation_breaker_cooldown_secs: u64,
+    #[serde(default = "default_federation_seen_events_capacity")]
+    federation_seen_events_capacity: usize,
+    #[serde(default)]
+    federation_msgpack: bool,
+    #[serde(default = "default_signups_enabled")]
+    signups_enabled: bool,
+    #[serde(default = "default_ws_ping_interval_secs")]
+    ws_ping_interval_secs: u64,
+    #[serde(default = "default_ws_idle_timeout_secs")]
+    ws_idle_timeout_secs: u64,
+    #[serde(default = "default_ws_outbound_queue_capacity")]
+    ws_outbound_queue_capacity: usize,
+    #[serde(default = "default_max_ws_frame_bytes")]
+    max_ws_frame_bytes: usize,
+    #[serde(default = "default_ws_replay_buffer_size")]
+    ws_replay_buffer_size: usize,
+    #[serde(default = "default_max_reactions_per_message")]
+    max_reactions_per_message: usize,
+    #[serde(default = "default_max_webhook_body_len")]
+    max_webhook_body_len: usize,
+    attachments_dir: Option<PathBuf>,
+    #[serde(default = "default_max_attachment_size")]
+    max_attachment_size: usize,
+    #[serde(default = "default_attachment_content_types")]
+    attachment_content_types: Vec<String>,
+    #[serde(default = "default_jwks_cache_ttl_secs")]
+    jwks_cache_ttl_secs: u64,
+    #[serde(default = "default_jwks_negative_cache_ttl_secs")]
+    jwks_negative_cache_ttl_secs: u64,
+    #[serde(default = "default_shutdown_grace_period_secs")]
+    shutdown_grace_period_secs: u64,
+    #[serde(default = "default_cleanup_interval_secs")]
+    cleanup_interval_secs: u64,
+    #[serde(default = "default_remote_cache_ttl_secs")]
+    remote_cache_ttl_secs: u64,
+    #[serde(default = "default_deleted_message_ttl_secs")]
+    deleted_message_ttl_secs: u64,
+    #[serde(default = "default_membership_resync_interval_secs")]
+    membership_resync_interval_secs: u64,
+    #[serde(default = "default_membership_resync_jitter_secs")]
+    membership_resync_jitter_secs: u64,
+    #[serde(default = "default_access_token_ttl_secs")]
+    access_token_ttl_secs: u32,
+    #[serde(default = "default_refresh_token_ttl_secs")]
+    refresh_token_ttl_secs: u32,
+    #[serde(default = "default_federation_token_ttl_secs")]
+    federation_token_ttl_secs: u32,
+    #[serde(default = "default_auth_rate_limit_requests")]
+    auth_rate_limit_requests: u32,
+    #[serde(default = "default_auth_rate_limit_window_secs")]
+    auth_rate_limit_window_secs: u64,
+    #[serde(default = "default_auth_failure_rate_limit_requests")]
+    auth_failure_rate_limit_requests: u32,
+    #[serde(default = "default_auth_failure_rate_limit_window_secs")]
+    auth_failure_rate_limit_window_secs: u64,
+    #[serde(default = "default_message_rate_limit_requests")]
+    message_rate_limit_requests: u32,
+    #[serde(default = "default_message_rate_limit_window_secs")]
+    message_rate_limit_window_secs: u64,
 }
 
 impl RawServerConfig {
+    /// Overrides settings from the environment: `RUNELINK_<index>_<NAME>`
+    /// for one server, else `RUNELINK_<NAME>` for every server.
+    ///
+    /// `DATABASE_URL`, `LOCAL_HOST` and `PORT` set `database_url`,
+    /// `public_host` and `public_port`.
+    fn apply_env(
+        &mut self,
+        index: usize,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> ConfigResult<()> {
+        let lookup = |name: &str| {
+            let indexed = format!("{ENV_PREFIX}_{index}_{name}");
+            let shared = format!("{ENV_PREFIX}_{name}");
+            match env(&indexed) {
+                Some(value) => Some((indexed, value)),
+                None => env(&shared).map(|value| (shared, value)),
+            }
+        };
+        if let Some((_, database_url)) = lookup("DATABASE_URL") {
+            self.database_url = Some(database_url);
+        }
+        if let Some((_, public_host)) = lookup("LOCAL_HOST") {
+            self.public_host = Some(public_host);
+        }
+        if let Some((key, port)) = lookup("PORT") {
+            self.public_port = port.trim().parse().map_err(|_| {
+                ConfigError::InvalidServerEntry {
+                    index,
+                    reason: format!(
+                        "{key} must be a port number, got `{port}`"
+                    ),
+                }
+            })?;
+        }
+        Ok(())
+    }
+
     fn resolve(self, index: usize) -> ConfigResult<ServerConfig> {
+        let public_host = self.public_host.ok_or_else(|| {
+            ConfigError::InvalidServerEntry {
+                index,
+                reason: format!(
+                    "public_host is required (or set {ENV_PREFIX}_LOCAL_HOST)"
+                ),
+            }
+        })?;
         let public_host =
-            validate_config_host(&self.public_host).map_err(|error| {
+            validate_config_host(&public_host).map_err(|error| {
                 ConfigError::InvalidServerEntry {
                     index,
                     reason: error.to_string(),
                 }
             })?;
-        let database_url = self.database_url.trim().to_string();
+        let database_url = self.database_url.ok_or_else(|| {
+            ConfigError::InvalidServerEntry {
+                index,
+                reason: format!(
+                    "database_url is required (or set {ENV_PREFIX}_DATABASE_URL)"
+                ),
+            }
+        })?;
+        let database_url = database_url.trim().to_string();
         if database_url.is_empty() {
             return Err(ConfigError::InvalidServerEntry {
                 index,
@@ -169,9 +438,260 @@ impl RawServerConfig {
             });
         }
         let bind_port = self.bind_port.unwrap_or(self.public_port);
+        let federation_warm_hosts = self
+            .federation_warm_hosts
+            .iter()
+            .map(|host| validate_host(host))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| ConfigError::InvalidServerEntry {
+                index,
+                reason: format!("invalid federation_warm_hosts entry: {error}"),
+            })?;
+        let host_list = |hosts: &[String], field: &str| {
+            hosts
+                .iter()
+                .map(|host| validate_host(host).map(|host| pad_host(&host)))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|error| ConfigError::InvalidServerEntry {
+                    index,
+                    reason: format!("invalid {field} entry: {error}"),
+                })
+        };
+        let federation_allowlist = self
+            .federation_allowlist
+            .as_deref()
+            .map(|hosts| host_list(hosts, "federation_allowlist"))
+            .transpose()?;
+        let federation_blocklist =
+            host_list(&self.federation_blocklist, "federation_blocklist")?;
+        if self.federation_breaker_threshold == 0 {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason: "federation_breaker_threshold must be greater than 0"
+                    .to_string(),
+            });
+        }
+        if self.federation_seen_events_capacity == 0 {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason:
+                    "federation_seen_events_capacity must be greater than 0"
+                        .to_string(),
+            });
+        }
+        if self.federation_connect_attempts == 0 {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason: "federation_connect_attempts must be greater than 0"
+                    .to_string(),
+            });
+        }
+        if self.federation_connect_backoff_max_ms
+            < self.federation_connect_backoff_base_ms
+        {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason: "federation_connect_backoff_max_ms must be at least federation_connect_backoff_base_ms"
+                    .to_string(),
+            });
+        }
+        if self.ws_ping_interval_secs == 0 {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason: "ws_ping_interval_secs must be greater than 0"
+                    .to_string(),
+            });
+        }
+        if self.ws_idle_timeout_secs <= self.ws_ping_interval_secs {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason: "ws_idle_timeout_secs must be greater than ws_ping_interval_secs"
+                    .to_string(),
+            });
+        }
+        if self.ws_outbound_queue_capacity == 0 {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason: "ws_outbound_queue_capacity must be greater than 0"
+                    .to_string(),
+            });
+        }
+        if self.max_ws_frame_bytes == 0 {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason: "max_ws_frame_bytes must be greater than 0".to_string(),
+            });
+        }
+        if self.ws_replay_buffer_size == 0 {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason: "ws_replay_buffer_size must be greater than 0"
+                    .to_string(),
+            });
+        }
+        if self.max_reactions_per_message == 0 {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason: "max_reactions_per_message must be greater than 0"
+                    .to_string(),
+            });
+        }
+        if self.max_webhook_body_len == 0 {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason: "max_webhook_body_len must be greater than 0"
+                    .to_string(),
+            });
+        }
+        if self.max_attachment_size == 0 {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason: "max_attachment_size must be greater than 0"
+                    .to_string(),
+            });
+        }
+        if self.jwks_cache_ttl_secs == 0 {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason: "jwks_cache_ttl_secs must be greater than 0"
+                    .to_string(),
+            });
+        }
+        if self.cleanup_interval_secs == 0 || self.remote_cache_ttl_secs == 0 {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason: "cleanup_interval_secs and remote_cache_ttl_secs must \
+                         be greater than 0"
+                    .to_string(),
+            });
+        }
+        if self.deleted_message_ttl_secs == 0 {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason: "deleted_message_ttl_secs must be greater than 0"
+                    .to_string(),
+            });
+        }
+        if self.membership_resync_interval_secs == 0 {
+            return Err(ConfigError::InvalidServerEntry {
+                index,
+                reason:
+                    "membership_resync_interval_secs must be greater than 0"
+                        .to_string(),
+            });
+        }
+        let token_ttl = |secs: u32, field: &str| {
+            if secs == 0 {
+                return Err(ConfigError::InvalidServerEntry {
+                    index,
+                    reason: format!("{field} must be greater than 0"),
+                });
+            }
+            Ok(time::Duration::seconds(secs.into()))
+        };
+        let access_token_ttl =
+            token_ttl(self.access_token_ttl_secs, "access_token_ttl_secs")?;
+        let refresh_token_ttl =
+            token_ttl(self.refresh_token_ttl_secs, "refresh_token_ttl_secs")?;
+        let federation_token_ttl = token_ttl(
+            self.federation_token_ttl_secs,
+            "federation_token_ttl_secs",
+        )?;
+        let rate_limit = |requests: u32, window_secs: u64, field: &str| {
+            if requests == 0 || window_secs == 0 {
+                return Err(ConfigError::InvalidServerEntry {
+                    index,
+                    reason: format!(
+                        "{field}_requests and {field}_window_secs must be greater than 0"
+                    ),
+                });
+            }
+            Ok(RateLimit {
+                requests,
+                window: Duration::from_secs(window_secs),
+            })
+        };
+        let auth_rate_limit = rate_limit(
+            self.auth_rate_limit_requests,
+            self.auth_rate_limit_window_secs,
+            "auth_rate_limit",
+        )?;
+        let auth_failure_rate_limit = rate_limit(
+            self.auth_failure_rate_limit_requests,
+            self.auth_failure_rate_limit_window_secs,
+            "auth_failure_rate_limit",
+        )?;
+        let message_rate_limit = rate_limit(
+            self.message_rate_limit_requests,
+            self.message_rate_limit_window_secs,
+            "message_rate_limit",
+        )?;
+        let attachment_content_types = self
+            .attachment_content_types
+            .iter()
+            .map(|content_type| content_type.trim().to_ascii_lowercase())
+            .filter(|content_type| !content_type.is_empty())
+            .collect();
+        let tls = match (self.tls_cert_path, self.tls_key_path) {
+            (None, None) => None,
+            (Some(cert_path), Some(key_path)) => {
+                if !self.secure {
+                    return Err(ConfigError::InvalidServerEntry {
+                        index,
+                        reason: "tls_cert_path and tls_key_path require \
+                                 secure = true"
+                            .to_string(),
+                    });
+                }
+                for (field, path) in
+                    [("tls_cert_path", &cert_path), ("tls_key_path", &key_path)]
+                {
+                    std::fs::File::open(path).map_err(|error| {
+                        ConfigError::InvalidServerEntry {
+                            index,
+                            reason: format!(
+                                "{field} `{}` cannot be read: {error}",
+                                path.display()
+                            ),
+                        }
+                    })?;
+                }
+                Some(TlsConfig {
+                    cert_path,
+                    key_path,
+                })
+            }
+            _ => {
+                return Err(ConfigError::InvalidServerEntry {
+                    index,
+                    reason: "tls_cert_path and tls_key_path must be set \
+                             together"
+                        .to_string(),
+                });
+            }
+        };
+        if let Some(metrics_port) = self.metrics_port {
+            if !self.metrics_enabled {
+                return Err(ConfigError::InvalidServerEntry {
+                    index,
+                    reason: "metrics_port requires metrics_enabled = true"
+                        .to_string(),
+                });
+            }
+            if metrics_port == bind_port {
+                return Err(ConfigError::InvalidServerEntry {
+                    index,
+                    reason: "metrics_port must differ from bind_port"
+                        .to_string(),
+                });
+            }
+        }
         let key_dir = self
             .key_dir
             .unwrap_or_else(|| default_key_dir(self.public_port));
+        let attachments_dir = self
+            .attachments_dir
+            .unwrap_or_else(|| default_attachments_dir(self.public_port));
         Ok(ServerConfig {
             public_host_raw: public_host,
             database_url,
@@ -179,7 +699,65 @@ impl RawServerConfig {
             bind_host,
             bind_port,
             secure: self.secure,
+            tls,
+            metrics_enabled: self.metrics_enabled,
+            metrics_port: self.metrics_port,
             key_dir,
+            federation_warm_hosts,
+            federation_connect_attempts: self.federation_connect_attempts,
+            federation_connect_backoff_base: Duration::from_millis(
+                self.federation_connect_backoff_base_ms,
+            ),
+            federation_connect_backoff_max: Duration::from_millis(
+                self.federation_connect_backoff_max_ms,
+            ),
+            federation_host_cooldown: Duration::from_secs(
+                self.federation_host_cooldown_secs,
+            ),
+            federation_allowlist,
+            federation_blocklist,
+            federation_breaker_threshold: self.federation_breaker_threshold,
+            federation_breaker_cooldown: Duration::from_secs(
+                self.federation_breaker_cooldown_secs,
+            ),
+            federation_seen_events_capacity: self
+                .federation_seen_events_capacity,
+            federation_msgpack: self.federation_msgpack,
+            signups_enabled: self.signups_enabled,
+            ws_ping_interval: Duration::from_secs(self.ws_ping_interval_secs),
+            ws_idle_timeout: Duration::from_secs(self.ws_idle_timeout_secs),
+            ws_outbound_queue_capacity: self.ws_outbound_queue_capacity,
+            max_ws_frame_bytes: self.max_ws_frame_bytes,
+            ws_replay_buffer_size: self.ws_replay_buffer_size,
+            max_reactions_per_message: self.max_reactions_per_message,
+            max_webhook_body_len: self.max_webhook_body_len,
+            attachments_dir,
+            max_attachment_size: self.max_attachment_size,
+            attachment_content_types,
+            jwks_cache_ttl: Duration::from_secs(self.jwks_cache_ttl_secs),
+            jwks_negative_cache_ttl: Duration::from_secs(
+                self.jwks_negative_cache_ttl_secs,
+            ),
+            shutdown_grace_period: Duration::from_secs(
+                self.shutdown_grace_period_secs,
+            ),
+            cleanup_interval: Duration::from_secs(self.cleanup_interval_secs),
+            remote_cache_ttl: Duration::from_secs(self.remote_cache_ttl_secs),
+            deleted_message_ttl: Duration::from_secs(
+                self.deleted_message_ttl_secs,
+            ),
+            membership_resync_interval: Duration::from_secs(
+                self.membership_resync_interval_secs,
+            ),
+            membership_resync_jitter: Duration::from_secs(
+                self.membership_resync_jitter_secs,
+            ),
+            access_token_ttl,
+            refresh_token_ttl,
+            federation_token_ttl,
+            auth_rate_limit,
+            auth_failure_rate_limit,
+            message_rate_limit,
         })
     }
 }
@@ -192,6 +770,152 @@ fn default_secure() -> bool {
     true
 }
 
+fn default_federation_connect_attempts() -> u32 {
+    3
+}
+
+fn default_federation_connect_backoff_base_ms() -> u64 {
+    200
+}
+
+fn default_federation_connect_backoff_max_ms() -> u64 {
+    2000
+}
+
+fn default_federation_host_cooldown_secs() -> u64 {
+    30
+}
+
+fn default_federation_breaker_threshold() -> u32 {
+    5
+}
+
+fn default_federation_breaker_cooldown_secs() -> u64 {
+    60
+}
+
+fn default_federation_seen_events_capacity() -> usize {
+    4096
+}
+
+fn default_signups_enabled() -> bool {
+    true
+}
+
+fn default_ws_ping_interval_secs() -> u64 {
+    30
+}
+
+fn default_ws_idle_timeout_secs() -> u64 {
+    90
+}
+
+fn default_ws_outbound_queue_capacity() -> usize {
+    256
+}
+
+fn default_max_ws_frame_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_ws_replay_buffer_size() -> usize {
+    256
+}
+
+fn default_max_reactions_per_message() -> usize {
+    20
+}
+
+fn default_max_webhook_body_len() -> usize {
+    4000
+}
+
+fn default_max_attachment_size() -> usize {
+    10 * 1024 * 1024
+}
+
+fn default_attachment_content_types() -> Vec<String> {
+    [
+        "image/png",
+        "image/jpeg",
+        "image/gif",
+        "image/webp",
+        "application/pdf",
+        "text/plain",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect()
+}
+
+fn default_jwks_cache_ttl_secs() -> u64 {
+    600
+}
+
+fn default_jwks_negative_cache_ttl_secs() -> u64 {
+    30
+}
+
+fn default_shutdown_grace_period_secs() -> u64 {
+    10
+}
+
+fn default_cleanup_interval_secs() -> u64 {
+    60 * 60
+}
+
+fn default_remote_cache_ttl_secs() -> u64 {
+    90 * 24 * 60 * 60
+}
+
+fn default_deleted_message_ttl_secs() -> u64 {
+    30 * 24 * 60 * 60
+}
+
+fn default_membership_resync_interval_secs() -> u64 {
+    60 * 60
+}
+
+fn default_membership_resync_jitter_secs() -> u64 {
+    5 * 60
+}
+
+fn default_access_token_ttl_secs() -> u32 {
+    60 * 60
+}
+
+fn default_refresh_token_ttl_secs() -> u32 {
+    30 * 24 * 60 * 60
+}
+
+fn default_federation_token_ttl_secs() -> u32 {
+    5 * 60
+}
+
+fn default_auth_rate_limit_requests() -> u32 {
+    20
+}
+
+fn default_auth_rate_limit_window_secs() -> u64 {
+    60
+}
+
+fn default_auth_failure_rate_limit_requests() -> u32 {
+    5
+}
+
+fn default_auth_failure_rate_limit_window_secs() -> u64 {
+    300
+}
+
+fn default_message_rate_limit_requests() -> u32 {
+    30
+}
+
+fn default_message_rate_limit_window_secs() -> u64 {
+    10
+}
+
 fn default_bind_host() -> String {
     "0.0.0.0".to_string()
 }
@@ -202,6 +926,18 @@ fn default_key_dir(port: u16) -> PathBuf {
     path
 }
 
+fn default_attachments_dir(port: u16) -> PathBuf {
+    let mut path = dirs_next::home_dir().expect("failed to get home directory");
+    path.extend([
+        ".local",
+        "share",
+        "runelink",
+        "attachments",
+        &port.to_string(),
+    ]);
+    path
+}
+
 fn validate_unique_resources(configs: &[ServerConfig]) -> ConfigResult<()> {
     let mut index_by_public_addr = HashMap::<String, usize>::new();
     let mut index_by_bind_addr = HashMap::<String, usize>::new();
@@ -228,6 +964,19 @@ fn validate_unique_resources(configs: &[ServerConfig]) -> ConfigResult<()> {
                 second_index: index,
             });
         }
+        if let Some(metrics_port) = config.metrics_port {
+            let metrics_addr = format!("{}:{metrics_port}", config.bind_host);
+            if let Some(first_index) =
+                index_by_bind_addr.insert(metrics_addr, index)
+            {
+                return Err(ConfigError::DuplicateBindAddress {
+                    bind_host: config.bind_host.clone(),
+                    bind_port: metrics_port,
+                    first_index,
+                    second_index: index,
+                });
+            }
+        }
         if let Some(first_index) =
             index_by_database_url.insert(config.database_url.clone(), index)
         {
@@ -240,3 +989,136 @@ fn validate_unique_resources(configs: &[ServerConfig]) -> ConfigResult<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWO_SERVERS: &str = r#"
+        [[servers]]
+        public_host = "localhost"
+        public_port = 7000
+        database_url = "postgres://localhost/one"
+
+        [[servers]]
+        public_host = "localhost"
+        public_port = 7001
+        database_url = "postgres://localhost/two"
+    "#;
+
+    fn load(
+        contents: &str,
+        vars: &[(&str, &str)],
+    ) -> ConfigResult<Vec<ServerConfig>> {
+        let vars = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        ServerConfig::from_toml_str(contents, Path::new("config.toml"), |key| {
+            vars.get(key).cloned()
+        })
+    }
+
+    #[test]
+    fn test_indexed_env_overrides_shared_and_file() {
+        let configs = load(
+            TWO_SERVERS,
+            &[
+                ("RUNELINK_DATABASE_URL", "postgres://db/shared"),
+                ("RUNELINK_1_DATABASE_URL", "postgres://db/second"),
+                ("RUNELINK_1_PORT", "7100"),
+            ],
+        );
+        let configs = configs.unwrap();
+        assert_eq!(configs[0].database_url, "postgres://db/shared");
+        assert_eq!(configs[0].public_port, 7000);
+        assert_eq!(configs[1].database_url, "postgres://db/second");
+        assert_eq!(configs[1].public_port, 7100);
+    }
+
+    #[test]
+    fn test_env_duplicates_are_rejected() {
+        let result = load(TWO_SERVERS, &[("RUNELINK_PORT", "7200")]);
+        assert!(matches!(
+            result,
+            Err(ConfigError::DuplicatePublicAddress { .. })
+        ));
+    }
+
+    #[test]
+    fn test_env_fills_in_required_settings() {
+        let contents = "[[servers]]\npublic_port = 7000\n";
+        assert!(matches!(
+            load(contents, &[]),
+            Err(ConfigError::InvalidServerEntry { index: 0, .. })
+        ));
+        let configs = load(
+            contents,
+            &[
+                ("RUNELINK_LOCAL_HOST", "example.com"),
+                ("RUNELINK_DATABASE_URL", "postgres://db/runelink"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(configs[0].public_host_raw, "example.com");
+    }
+
+    #[test]
+    fn test_invalid_env_port_names_the_variable() {
+        let Err(ConfigError::InvalidServerEntry { reason, .. }) =
+            load(TWO_SERVERS, &[("RUNELINK_0_PORT", "http")])
+        else {
+            panic!("expected an invalid server entry");
+        };
+        assert!(reason.contains("RUNELINK_0_PORT"));
+    }
+
+    #[test]
+    fn test_token_ttls_default_and_reject_zero() {
+        let configs = load(TWO_SERVERS, &[]).unwrap();
+        assert_eq!(configs[0].access_token_ttl, time::Duration::hours(1));
+        assert_eq!(configs[0].refresh_token_ttl, time::Duration::days(30));
+        assert_eq!(configs[0].federation_token_ttl, time::Duration::minutes(5));
+
+        let contents = format!("{TWO_SERVERS}access_token_ttl_secs = 0\n");
+        let Err(ConfigError::InvalidServerEntry { index, reason }) =
+            load(&contents, &[])
+        else {
+            panic!("expected an invalid server entry");
+        };
+        assert_eq!(index, 1);
+        assert!(reason.contains("access_token_ttl_secs"));
+    }
+
+    #[test]
+    fn test_max_ws_frame_bytes_defaults_and_rejects_zero() {
+        let configs = load(TWO_SERVERS, &[]).unwrap();
+        assert_eq!(configs[0].max_ws_frame_bytes, 1024 * 1024);
+
+        let contents = format!("{TWO_SERVERS}max_ws_frame_bytes = 0\n");
+        let Err(ConfigError::InvalidServerEntry { reason, .. }) =
+            load(&contents, &[])
+        else {
+            panic!("expected an invalid server entry");
+        };
+        assert!(reason.contains("max_ws_frame_bytes"));
+    }
+
+    #[test]
+    fn test_metrics_port_requires_metrics_enabled() {
+        let contents = format!("{TWO_SERVERS}metrics_port = 9100\n");
+        assert!(matches!(
+            load(&contents, &[]),
+            Err(ConfigError::InvalidServerEntry { index: 1, .. })
+        ));
+        let contents = format!(
+            "{TWO_SERVERS}metrics_enabled = true\nmetrics_port = 9100\n"
+        );
+        let configs = load(&contents, &[]).unwrap();
+        assert_eq!(configs[0].metrics_bind_addr(), None);
+        assert_eq!(
+            configs[1].metrics_bind_addr(),
+            Some(format!("{}:9100", configs[1].bind_host))
+        );
+    }
+}
@@ -1,11 +1,15 @@
 use axum::{
     Json,
-    http::StatusCode,
+    extract::{multipart::MultipartError, rejection::JsonRejection},
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
 use runelink_client::Error as ClientError;
-use runelink_types::ws::WsError;
-use serde::Serialize;
+use runelink_types::ws::{
+    ApiErrorBody, MIN_SUPPORTED_WS_PROTOCOL_VERSION, WS_PROTOCOL_VERSION,
+    WsError, WsErrorCode,
+};
+use serde_json::{Value, json};
 use thiserror::Error;
 use tokio::task::JoinError;
 
@@ -13,11 +17,12 @@ pub type ApiResult<T> = std::result::Result<T, ApiError>;
 
 #[derive(Error, Debug)]
 pub enum ApiError {
-    #[error("Database connection error: {0}")]
-    DbConnectionError(String),
+    /// A transient outage (database or federation peer); safe to retry.
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
 
-    #[error("Unique constraint violation")]
-    UniqueViolation,
+    #[error("Conflict: {0}")]
+    Conflict(String),
 
     #[error("Resource not found")]
     NotFound,
@@ -25,12 +30,35 @@ pub enum ApiError {
     #[error("Database error: {0}")]
     DatabaseError(String),
 
+    /// Missing or invalid credentials; the caller should authenticate again.
     #[error("Unauthorized: {0}")]
-    AuthError(String),
+    Unauthorized(String),
+
+    /// Bad credentials or an unusable refresh token on a token grant.
+    #[error("Invalid grant: {0}")]
+    InvalidGrant(String),
+
+    /// Authenticated, but not allowed to do this.
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
 
     #[error("Bad request: {0}")]
     BadRequest(String),
 
+    #[error("Invite has expired")]
+    InviteExpired,
+
+    #[error("Invite has no uses remaining")]
+    InviteExhausted,
+
+    /// The peer offered a websocket protocol version this host can't speak.
+    #[error("Unsupported protocol version {0}")]
+    UnsupportedProtocol(u32),
+
+    /// Too many requests from one client or user.
+    #[error("Too many requests, retry in {}s", ceil_secs(*.retry_after))]
+    RateLimited { retry_after: std::time::Duration },
+
     #[error("Unknown error: {0}")]
     Unknown(String),
 
@@ -44,12 +72,18 @@ pub enum ApiError {
 impl From<sqlx::Error> for ApiError {
     fn from(e: sqlx::Error) -> Self {
         match e {
-            sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => {
-                ApiError::DbConnectionError(e.to_string())
+            sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::WorkerCrashed => {
+                ApiError::ServiceUnavailable(e.to_string())
             }
 
             sqlx::Error::Database(db_err) => match db_err.code().as_deref() {
-                Some("23505") => ApiError::UniqueViolation,
+                Some("23505") => {
+                    ApiError::Conflict("Unique constraint violation".into())
+                }
                 _ => ApiError::DatabaseError(db_err.message().to_string()),
             },
 
@@ -66,51 +100,311 @@ impl From<JoinError> for ApiError {
     }
 }
 
-#[derive(Serialize)]
-struct ErrorResponse {
-    error: String,
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        // body_text includes the serde path and position where available
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<MultipartError> for ApiError {
+    fn from(error: MultipartError) -> Self {
+        ApiError::BadRequest(error.body_text())
+    }
+}
+
+/// Seconds clients should wait before retrying a `ServiceUnavailable`.
+const RETRY_AFTER_SECS: u64 = 5;
+
+/// Whole seconds to wait, rounded up so a client never retries early.
+fn ceil_secs(duration: std::time::Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+impl ApiError {
+    pub fn code(&self) -> WsErrorCode {
+        match self {
+            ApiError::Unauthorized(_) => WsErrorCode::Unauthorized,
+            ApiError::InvalidGrant(_) => WsErrorCode::InvalidGrant,
+            ApiError::Forbidden(_) => WsErrorCode::Forbidden,
+            ApiError::BadRequest(_) => WsErrorCode::BadRequest,
+            ApiError::UnsupportedProtocol(_) => {
+                WsErrorCode::UnsupportedProtocol
+            }
+            ApiError::InviteExpired => WsErrorCode::InviteExpired,
+            ApiError::InviteExhausted => WsErrorCode::InviteExhausted,
+            ApiError::RateLimited { .. } => WsErrorCode::RateLimited,
+            ApiError::NotFound => WsErrorCode::NotFound,
+            ApiError::Conflict(_) => WsErrorCode::Conflict,
+            ApiError::ServiceUnavailable(_) => WsErrorCode::ServiceUnavailable,
+            ApiError::Client(_) => WsErrorCode::UpstreamError,
+            ApiError::DatabaseError(_)
+            | ApiError::Internal(_)
+            | ApiError::Unknown(_) => WsErrorCode::InternalError,
+        }
+    }
+
+    /// The caller-facing reason behind the error, as structured details.
+    ///
+    /// Only variants whose text is written for the caller have one; database,
+    /// internal and upstream failures may describe this host's internals.
+    fn details(&self) -> Option<Value> {
+        if let ApiError::RateLimited { retry_after } = self {
+            return Some(json!({ "retry_after": ceil_secs(*retry_after) }));
+        }
+        if let ApiError::UnsupportedProtocol(version) = self {
+            return Some(json!({
+                "protocol_version": version,
+                "server_version": WS_PROTOCOL_VERSION,
+                "min_supported": MIN_SUPPORTED_WS_PROTOCOL_VERSION,
+            }));
+        }
+        let reason = match self {
+            ApiError::Unauthorized(reason)
+            | ApiError::InvalidGrant(reason)
+            | ApiError::Forbidden(reason)
+            | ApiError::BadRequest(reason)
+            | ApiError::Conflict(reason) => reason,
+            _ => return None,
+        };
+        Some(json!({ "reason": reason }))
+    }
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
         let status = match self {
-            ApiError::DbConnectionError(_)
-            | ApiError::DatabaseError(_)
+            ApiError::DatabaseError(_)
             | ApiError::Internal(_)
             | ApiError::Unknown(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            ApiError::UniqueViolation => StatusCode::CONFLICT,
+            ApiError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
             ApiError::NotFound => StatusCode::NOT_FOUND,
-            ApiError::AuthError(_) => StatusCode::UNAUTHORIZED,
-            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) | ApiError::InvalidGrant(_) => {
+                StatusCode::UNAUTHORIZED
+            }
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::BadRequest(_) | ApiError::UnsupportedProtocol(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            ApiError::InviteExpired | ApiError::InviteExhausted => {
+                StatusCode::GONE
+            }
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
             ApiError::Client(ref client_err) => match client_err {
                 ClientError::Status(code, _) => *code,
+                ClientError::Api { status, .. } => *status,
                 _ => StatusCode::BAD_GATEWAY,
             },
         };
-        let body = Json(ErrorResponse {
-            error: self.to_string(),
+        let retry_after = match self {
+            ApiError::ServiceUnavailable(_) => Some(RETRY_AFTER_SECS),
+            ApiError::RateLimited { retry_after } => {
+                Some(ceil_secs(retry_after))
+            }
+            _ => None,
+        }
+        .map(|secs| [(header::RETRY_AFTER, secs.to_string())]);
+        let body = Json(ApiErrorBody {
+            message: self.to_string(),
+            code: self.code(),
+            details: self.details(),
         });
-        (status, body).into_response()
+        (status, retry_after, body).into_response()
     }
 }
 
 impl From<ApiError> for WsError {
     fn from(error: ApiError) -> Self {
-        let code = match error {
-            ApiError::AuthError(_) => "auth_error",
-            ApiError::BadRequest(_) => "bad_request",
-            ApiError::NotFound => "not_found",
-            ApiError::UniqueViolation => "conflict",
-            ApiError::DbConnectionError(_)
-            | ApiError::DatabaseError(_)
-            | ApiError::Internal(_)
-            | ApiError::Unknown(_)
-            | ApiError::Client(_) => "internal_error",
-        };
         WsError {
-            code: code.to_string(),
+            code: error.code(),
             message: error.to_string(),
-            details: None,
+            details: error.details(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conflict_maps_to_409() {
+        let response =
+            ApiError::Conflict("User already exists".into()).into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn test_conflict_maps_to_ws_conflict_code() {
+        let error = WsError::from(ApiError::Conflict(
+            "Channel title already in use".into(),
+        ));
+        assert_eq!(error.code, WsErrorCode::Conflict);
+        assert!(error.message.contains("Channel title already in use"));
+    }
+
+    #[test]
+    fn test_bad_request_is_not_conflict() {
+        let response = ApiError::BadRequest("nope".into()).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error = WsError::from(ApiError::BadRequest("nope".into()));
+        assert_eq!(error.code, WsErrorCode::BadRequest);
+    }
+
+    #[test]
+    fn test_forbidden_maps_to_403() {
+        let response =
+            ApiError::Forbidden("Signups are disabled".into()).into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let error = WsError::from(ApiError::Forbidden("nope".into()));
+        assert_eq!(error.code, WsErrorCode::Forbidden);
+    }
+
+    #[test]
+    fn test_unauthorized_maps_to_401() {
+        let error = ApiError::Unauthorized("Invalid or expired token".into());
+        assert_eq!(error.into_response().status(), StatusCode::UNAUTHORIZED);
+        let error = WsError::from(ApiError::Unauthorized("nope".into()));
+        assert_eq!(error.code, WsErrorCode::Unauthorized);
+        assert_eq!(error.code.as_str(), "unauthorized");
+    }
+
+    #[test]
+    fn test_invalid_grant_has_its_own_ws_code() {
+        let error = ApiError::InvalidGrant("invalid credentials".into());
+        assert_eq!(error.into_response().status(), StatusCode::UNAUTHORIZED);
+        let error =
+            WsError::from(ApiError::InvalidGrant("invalid credentials".into()));
+        assert_eq!(error.code, WsErrorCode::InvalidGrant);
+        assert!(error.message.contains("invalid credentials"));
+    }
+
+    #[test]
+    fn test_unusable_invites_have_distinct_ws_codes() {
+        let response = ApiError::InviteExpired.into_response();
+        assert_eq!(response.status(), StatusCode::GONE);
+        let response = ApiError::InviteExhausted.into_response();
+        assert_eq!(response.status(), StatusCode::GONE);
+        assert_eq!(
+            WsError::from(ApiError::InviteExpired).code,
+            WsErrorCode::InviteExpired
+        );
+        assert_eq!(
+            WsError::from(ApiError::InviteExhausted).code,
+            WsErrorCode::InviteExhausted
+        );
+    }
+
+    #[test]
+    fn test_details_only_for_caller_facing_errors() {
+        let error =
+            WsError::from(ApiError::BadRequest("title is empty".into()));
+        assert_eq!(error.details, Some(json!({ "reason": "title is empty" })));
+        let error =
+            WsError::from(ApiError::DatabaseError("relation missing".into()));
+        assert_eq!(error.code, WsErrorCode::InternalError);
+        assert_eq!(error.details, None);
+    }
+
+    #[derive(Debug)]
+    struct UniqueViolation;
+
+    impl std::fmt::Display for UniqueViolation {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "duplicate key value violates unique constraint")
+        }
+    }
+
+    impl std::error::Error for UniqueViolation {}
+
+    impl sqlx::error::DatabaseError for UniqueViolation {
+        fn message(&self) -> &str {
+            "duplicate key value violates unique constraint"
+        }
+
+        fn code(&self) -> Option<std::borrow::Cow<'_, str>> {
+            Some("23505".into())
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(
+            &mut self,
+        ) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(
+            self: Box<Self>,
+        ) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> sqlx::error::ErrorKind {
+            sqlx::error::ErrorKind::UniqueViolation
+        }
+    }
+
+    #[test]
+    fn test_pool_timeout_maps_to_503_with_retry_after() {
+        let error = ApiError::from(sqlx::Error::PoolTimedOut);
+        assert!(matches!(error, ApiError::ServiceUnavailable(_)));
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers().get(header::RETRY_AFTER).unwrap(),
+            &RETRY_AFTER_SECS.to_string()
+        );
+    }
+
+    #[test]
+    fn test_unique_violation_maps_to_conflict() {
+        let error =
+            ApiError::from(sqlx::Error::Database(Box::new(UniqueViolation)));
+        assert!(matches!(error, ApiError::Conflict(_)));
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+    }
+
+    #[test]
+    fn test_rate_limited_is_429_with_retry_after() {
+        let error = ApiError::RateLimited {
+            retry_after: std::time::Duration::from_millis(1500),
+        };
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+
+        let error = WsError::from(ApiError::RateLimited {
+            retry_after: std::time::Duration::from_secs(3),
+        });
+        assert_eq!(error.code, WsErrorCode::RateLimited);
+        assert_eq!(error.details, Some(json!({ "retry_after": 3 })));
+    }
+
+    #[test]
+    fn test_unsupported_protocol_reports_supported_range() {
+        let response = ApiError::UnsupportedProtocol(99).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error = WsError::from(ApiError::UnsupportedProtocol(99));
+        assert_eq!(error.code, WsErrorCode::UnsupportedProtocol);
+        assert_eq!(
+            error.details,
+            Some(json!({
+                "protocol_version": 99,
+                "server_version": WS_PROTOCOL_VERSION,
+                "min_supported": MIN_SUPPORTED_WS_PROTOCOL_VERSION,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_service_unavailable_ws_code() {
+        let error = WsError::from(ApiError::ServiceUnavailable("db".into()));
+        assert_eq!(error.code, WsErrorCode::ServiceUnavailable);
+    }
+}
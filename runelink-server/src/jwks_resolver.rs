@@ -1,9 +1,10 @@
 #![allow(dead_code)]
 
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
 use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use log::warn;
 use runelink_types::{FederationClaims, PublicJwk};
 use serde::Deserialize;
 use time::{Duration, OffsetDateTime};
@@ -12,13 +13,43 @@ use crate::{
     error::{ApiError, ApiResult},
     state::AppState,
 };
-use runelink_client::util::get_api_url;
+use runelink_client::util::{get_api_url, host_from_issuer};
 
-#[derive(Debug, Clone)]
+/// Shortest gap between refetches of a host's keys when a token names a
+/// kid we haven't seen, so bogus kids can't be used to hammer the host.
+const MIN_REFRESH_INTERVAL: Duration = Duration::seconds(30);
+
+/// A host's signing keys by kid (raw ed25519 public key bytes).
+pub type JwksKeys = Arc<HashMap<String, Vec<u8>>>;
+
+/// Cached signing keys for one host.
+#[derive(Debug, Clone, Default)]
 pub struct CachedJwks {
-    fetched_at: OffsetDateTime,
-    // kid -> raw public key bytes (currently ed25519 raw 32 bytes)
-    keys_by_kid: HashMap<String, Vec<u8>>,
+    /// Keys from the last successful fetch, still used once stale if the
+    /// host can't be reached.
+    keys: Option<JwksKeys>,
+    fetched_at: Option<OffsetDateTime>,
+    /// When the last fetch failed, if it did.
+    failed_at: Option<OffsetDateTime>,
+}
+
+impl CachedJwks {
+    fn should_fetch(
+        &self,
+        now: OffsetDateTime,
+        ttl: std::time::Duration,
+        negative_ttl: std::time::Duration,
+        force: bool,
+    ) -> bool {
+        if self.failed_at.is_some_and(|at| at + negative_ttl > now) {
+            return false;
+        }
+        match self.fetched_at {
+            None => true,
+            Some(at) if at + ttl <= now => true,
+            Some(at) => force && at + MIN_REFRESH_INTERVAL <= now,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -41,23 +72,25 @@ fn parse_iss_unverified(token: &str) -> ApiResult<String> {
     // We need `iss` to locate the JWKS before we can verify the signature.
     let parts: Vec<&str> = token.split('.').collect();
     if parts.len() != 3 {
-        return Err(ApiError::AuthError("invalid JWT format".into()));
+        return Err(ApiError::Unauthorized("invalid JWT format".into()));
     }
     let payload = URL_SAFE_NO_PAD.decode(parts[1]).map_err(|e| {
-        ApiError::AuthError(format!("invalid JWT payload: {e}"))
+        ApiError::Unauthorized(format!("invalid JWT payload: {e}"))
     })?;
     let parsed: IssOnly = serde_json::from_slice(&payload).map_err(|e| {
-        ApiError::AuthError(format!("invalid JWT payload json: {e}"))
+        ApiError::Unauthorized(format!("invalid JWT payload json: {e}"))
     })?;
     Ok(parsed.iss)
 }
 
-async fn fetch_jwks(state: &AppState, iss: &str) -> ApiResult<CachedJwks> {
-    let url = jwks_url_for_iss(iss);
-    let response =
-        state.http_client.get(url).send().await.map_err(|e| {
-            ApiError::Internal(format!("jwks fetch error: {e}"))
-        })?;
+async fn fetch_jwks(
+    state: &AppState,
+    api_url: &str,
+) -> ApiResult<HashMap<String, Vec<u8>>> {
+    let url = jwks_url_for_iss(api_url);
+    let response = state.http_client.get(url).send().await.map_err(|e| {
+        ApiError::ServiceUnavailable(format!("jwks fetch error: {e}"))
+    })?;
 
     let status = response.status();
     if !status.is_success() {
@@ -65,7 +98,7 @@ async fn fetch_jwks(state: &AppState, iss: &str) -> ApiResult<CachedJwks> {
             .text()
             .await
             .unwrap_or_else(|e| format!("failed to read error body: {e}"));
-        return Err(ApiError::AuthError(format!(
+        return Err(ApiError::ServiceUnavailable(format!(
             "jwks fetch failed: {status} {body}"
         )));
     }
@@ -76,65 +109,104 @@ async fn fetch_jwks(state: &AppState, iss: &str) -> ApiResult<CachedJwks> {
 
     let mut keys_by_kid = HashMap::new();
     for key in jwks.keys {
-        // For now we only support Ed25519 OKP keys as produced by KeyManager.
-        // Future: validate kty/crv/alg/use_ and support rotation.
+        // Only Ed25519 OKP keys as produced by KeyManager are supported
+        if key.kty != "OKP" || key.crv != "Ed25519" {
+            continue;
+        }
         let pub_bytes = URL_SAFE_NO_PAD.decode(key.x).map_err(|e| {
             ApiError::Internal(format!("jwks key decode error: {e}"))
         })?;
         keys_by_kid.insert(key.kid, pub_bytes);
     }
+    Ok(keys_by_kid)
+}
 
-    Ok(CachedJwks {
-        fetched_at: OffsetDateTime::now_utc(),
-        keys_by_kid,
-    })
+/// Signing keys published by `host`, cached for `jwks_cache_ttl`.
+///
+/// If a refetch fails, the last keys fetched are used until the host is
+/// reachable again, and the host isn't retried for `jwks_negative_cache_ttl`.
+pub async fn get_keys(state: &AppState, host: &str) -> ApiResult<JwksKeys> {
+    load_keys(state, host, false).await
 }
 
-async fn get_cached_jwks(state: &AppState, iss: &str) -> ApiResult<CachedJwks> {
-    let iss_key = iss.trim_end_matches('/');
-    // Simple time-based cache. This will be expanded later with better
-    // negative caching and rotation support.
-    let ttl = Duration::minutes(10);
-    {
-        let cache = state.jwks_cache.read().await;
-        if let Some(entry) = cache.get(iss_key) {
-            if entry.fetched_at + ttl > OffsetDateTime::now_utc() {
-                return Ok(entry.clone());
-            }
-        }
+/// Like [`get_keys`], but refetches unless the keys were fetched very
+/// recently, for when a token names a kid the cached keys don't have.
+async fn refresh_keys(state: &AppState, host: &str) -> ApiResult<JwksKeys> {
+    load_keys(state, host, true).await
+}
+
+async fn load_keys(
+    state: &AppState,
+    host: &str,
+    force: bool,
+) -> ApiResult<JwksKeys> {
+    let api_url = get_api_url(host, state.config.secure);
+    let now = OffsetDateTime::now_utc();
+    let cached = state
+        .jwks_cache
+        .read()
+        .await
+        .get(&api_url)
+        .cloned()
+        .unwrap_or_default();
+    if !cached.should_fetch(
+        now,
+        state.config.jwks_cache_ttl,
+        state.config.jwks_negative_cache_ttl,
+        force,
+    ) {
+        return cached.keys.ok_or_else(|| {
+            ApiError::ServiceUnavailable(format!(
+                "Signing keys for {host} are unavailable"
+            ))
+        });
     }
 
-    let fetched = fetch_jwks(state, iss_key).await?;
-    {
-        let mut cache = state.jwks_cache.write().await;
-        cache.insert(iss_key.to_string(), fetched.clone());
+    match fetch_jwks(state, &api_url).await {
+        Ok(keys) => {
+            let keys = Arc::new(keys);
+            state.jwks_cache.write().await.insert(
+                api_url,
+                CachedJwks {
+                    keys: Some(keys.clone()),
+                    fetched_at: Some(now),
+                    failed_at: None,
+                },
+            );
+            Ok(keys)
+        }
+        Err(error) => {
+            warn!("Failed to fetch signing keys for {host}: {error}");
+            let mut cache = state.jwks_cache.write().await;
+            let entry = cache.entry(api_url).or_default();
+            entry.failed_at = Some(now);
+            entry.keys.clone().ok_or(error)
+        }
     }
-    Ok(fetched)
 }
 
 fn select_public_key_bytes<'a>(
-    cached: &'a CachedJwks,
+    keys: &'a HashMap<String, Vec<u8>>,
     kid: Option<&str>,
 ) -> ApiResult<&'a [u8]> {
     if let Some(kid) = kid {
-        return cached
-            .keys_by_kid
+        return keys
             .get(kid)
             .map(|v| v.as_slice())
-            .ok_or_else(|| ApiError::AuthError("unknown jwk kid".into()));
+            .ok_or_else(|| ApiError::Unauthorized("unknown jwk kid".into()));
     }
 
-    if cached.keys_by_kid.len() == 1 {
-        return Ok(cached.keys_by_kid.values().next().unwrap().as_slice());
+    if keys.len() == 1 {
+        return Ok(keys.values().next().unwrap().as_slice());
     }
 
-    Err(ApiError::AuthError(
+    Err(ApiError::Unauthorized(
         "missing kid and multiple jwks keys available".into(),
     ))
 }
 
-/// Validate a federation JWT (server-to-server delegated authority) by
-/// discovering the issuer's JWKS via `{iss}/.well-known/jwks.json`.
+/// Validate a federation JWT (server-to-server delegated authority) against
+/// the keys the issuing host publishes at `/.well-known/jwks.json`.
 ///
 /// Notes:
 /// - This does an **unverified** parse of `iss` from the JWT payload solely to
@@ -146,15 +218,22 @@ pub async fn decode_federation_jwt(
     token: &str,
     expected_audience: &str,
 ) -> ApiResult<FederationClaims> {
-    let header = jsonwebtoken::decode_header(token)
-        .map_err(|e| ApiError::AuthError(format!("invalid JWT header: {e}")))?;
+    let header = jsonwebtoken::decode_header(token).map_err(|e| {
+        ApiError::Unauthorized(format!("invalid JWT header: {e}"))
+    })?;
     let iss = parse_iss_unverified(token)?;
 
-    let cached = get_cached_jwks(state, &iss).await?;
-    let pub_bytes = select_public_key_bytes(&cached, header.kid.as_deref())?;
+    let host = host_from_issuer(&iss);
+    let kid = header.kid.as_deref();
+    let mut keys = get_keys(state, &host).await?;
+    if select_public_key_bytes(&keys, kid).is_err() {
+        // The host may have rotated its key since it was cached
+        keys = refresh_keys(state, &host).await?;
+    }
+    let pub_bytes = select_public_key_bytes(&keys, kid)?;
 
     if pub_bytes.len() != 32 {
-        return Err(ApiError::AuthError(
+        return Err(ApiError::Unauthorized(
             "invalid jwks ed25519 key length".into(),
         ));
     }
@@ -169,14 +248,14 @@ pub async fn decode_federation_jwt(
         &decoding_key,
         &validation,
     )
-    .map_err(|_| ApiError::AuthError("invalid or expired token".into()))?;
+    .map_err(|_| ApiError::Unauthorized("invalid or expired token".into()))?;
     let claims = data.claims;
 
     // Verify delegation policy: issuer can only delegate users from their own host
     if let Some(user_ref) = &claims.user_ref {
         let expected_iss = get_api_url(&user_ref.host, state.config.secure);
         if claims.iss != expected_iss {
-            return Err(ApiError::AuthError(format!(
+            return Err(ApiError::Unauthorized(format!(
                 "Federation delegation mismatch: token from {} cannot delegate user from {}",
                 claims.iss, user_ref.host
             )));
@@ -185,3 +264,81 @@ pub async fn decode_federation_jwt(
 
     Ok(claims)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: std::time::Duration = std::time::Duration::from_secs(600);
+    const NEGATIVE_TTL: std::time::Duration =
+        std::time::Duration::from_secs(30);
+
+    fn fetched(ago: Duration) -> CachedJwks {
+        CachedJwks {
+            keys: Some(Arc::new(HashMap::new())),
+            fetched_at: Some(OffsetDateTime::now_utc() - ago),
+            failed_at: None,
+        }
+    }
+
+    #[test]
+    fn test_fresh_keys_are_not_refetched() {
+        let now = OffsetDateTime::now_utc();
+        assert!(CachedJwks::default().should_fetch(
+            now,
+            TTL,
+            NEGATIVE_TTL,
+            false
+        ));
+        assert!(!fetched(Duration::minutes(1)).should_fetch(
+            now,
+            TTL,
+            NEGATIVE_TTL,
+            false
+        ));
+        assert!(fetched(Duration::minutes(11)).should_fetch(
+            now,
+            TTL,
+            NEGATIVE_TTL,
+            false
+        ));
+    }
+
+    #[test]
+    fn test_forced_refresh_is_rate_limited() {
+        let now = OffsetDateTime::now_utc();
+        assert!(!fetched(Duration::seconds(5)).should_fetch(
+            now,
+            TTL,
+            NEGATIVE_TTL,
+            true
+        ));
+        assert!(fetched(Duration::minutes(1)).should_fetch(
+            now,
+            TTL,
+            NEGATIVE_TTL,
+            true
+        ));
+    }
+
+    #[test]
+    fn test_recent_failure_suppresses_fetch() {
+        let now = OffsetDateTime::now_utc();
+        let mut entry = fetched(Duration::minutes(11));
+        entry.failed_at = Some(now - Duration::seconds(5));
+        assert!(!entry.should_fetch(now, TTL, NEGATIVE_TTL, false));
+        entry.failed_at = Some(now - Duration::minutes(1));
+        assert!(entry.should_fetch(now, TTL, NEGATIVE_TTL, false));
+    }
+
+    #[test]
+    fn test_select_public_key_by_kid() {
+        let keys = HashMap::from([
+            ("old".to_string(), vec![1u8; 32]),
+            ("new".to_string(), vec![2u8; 32]),
+        ]);
+        assert_eq!(select_public_key_bytes(&keys, Some("new")).unwrap()[0], 2);
+        assert!(select_public_key_bytes(&keys, Some("other")).is_err());
+        assert!(select_public_key_bytes(&keys, None).is_err());
+    }
+}
@@ -1,24 +1,35 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
 use ed25519_dalek::{
     SigningKey, VerifyingKey,
     pkcs8::{
         DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey,
     },
 };
-use jsonwebtoken::{DecodingKey, EncodingKey};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header};
 use rand::rngs::OsRng;
 use runelink_types::auth::PublicJwk;
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::error::{ApiError, ApiResult};
 
-/// Handles JWT signing keys and JWKS publication
-#[allow(dead_code)]
+/// Kid of keys generated before key ids were stored.
+const LEGACY_KID: &str = "primary";
+
+/// Handles JWT signing keys and JWKS publication.
+///
+/// There is one current signing key plus any previous keys it replaced,
+/// which are kept verify-only so tokens signed before a rotation stay valid
+/// until they expire.
 #[derive(Clone)]
 pub struct KeyManager {
     pub private_key: EncodingKey,
-    pub decoding_key: DecodingKey,
-    pub public_jwk: PublicJwk,
+    /// Kid of the current signing key.
+    pub kid: String,
+    /// Raw public keys by kid, current first.
+    verifying_keys: Vec<(String, [u8; 32])>,
+    decoding_keys: HashMap<String, DecodingKey>,
     pub path: PathBuf,
 }
 
@@ -26,7 +37,8 @@ impl std::fmt::Debug for KeyManager {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("KeyManager")
             .field("private_key", &"[REDACTED]")
-            .field("public_jwk", &self.public_jwk)
+            .field("kid", &self.kid)
+            .field("public_jwks", &self.public_jwks())
             .field("path", &self.path)
             .finish()
     }
@@ -34,95 +46,297 @@ impl std::fmt::Debug for KeyManager {
 
 impl KeyManager {
     /// Load keys if they exist under `path` or generate a new Ed25519 keypair
+    ///
+    /// Layout of `path`:
+    /// - `private_ed25519.der`: current signing key, PKCS#8 DER
+    /// - `public_ed25519.der`:  current verifying key, SPKI DER
+    /// - `kid`:                 current key id (`primary` if missing)
+    /// - `previous/<kid>.der`:  verify-only keys from earlier rotations
     pub fn load_or_generate(path: PathBuf) -> ApiResult<Self> {
-        // Stored formats:
-        // - private: PKCS#8 DER
-        // - public:  SPKI (SubjectPublicKeyInfo) DER
-        //
-        // Note: jsonwebtoken's EdDSA verifier expects the *raw 32-byte*
-        // Ed25519 public key, so we parse SPKI and convert to raw
-        // for `DecodingKey`.
         let priv_path = path.join("private_ed25519.der");
         let pub_path = path.join("public_ed25519.der");
 
-        if priv_path.exists() && pub_path.exists() {
-            // Load from disk
-            let priv_bytes = fs::read(&priv_path).map_err(|e| {
-                ApiError::Internal(format!("failed to read private key: {e}"))
-            })?;
-            let pub_bytes = fs::read(&pub_path).map_err(|e| {
-                ApiError::Internal(format!("failed to read public key: {e}"))
-            })?;
-
-            // Parse private key as PKCS#8 DER
-            let signing_key =
-                SigningKey::from_pkcs8_der(&priv_bytes).map_err(|e| {
-                    ApiError::Internal(format!(
-                        "invalid private key (expected PKCS#8 DER): {e}"
-                    ))
-                })?;
-
-            // Parse public key as SPKI DER and convert to raw 32 bytes
-            // for jsonwebtoken.
-            let loaded_pub: [u8; 32] =
-                VerifyingKey::from_public_key_der(&pub_bytes)
-                    .map_err(|e| {
-                        ApiError::Internal(format!(
-                            "invalid public key (expected SPKI DER): {e}"
-                        ))
-                    })?
-                    .to_bytes();
-
-            // Ensure the public key matches the private key
-            let derived_pub = signing_key.verifying_key().to_bytes();
-            if derived_pub != loaded_pub {
-                return Err(ApiError::Internal(
-                    "public key does not match private key".into(),
-                ));
-            }
-
-            let kid = "primary".to_string(); // TODO: should this change?
-            Ok(Self {
-                private_key: EncodingKey::from_ed_der(&priv_bytes),
-                decoding_key: DecodingKey::from_ed_der(&loaded_pub),
-                public_jwk: PublicJwk::from_ed25519_bytes(&loaded_pub, kid),
-                path,
-            })
+        let (signing_key, kid) = if priv_path.exists() && pub_path.exists() {
+            let signing_key = load_signing_key(&priv_path, &pub_path)?;
+            let kid = match fs::read_to_string(path.join("kid")) {
+                Ok(kid) => kid.trim().to_string(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    LEGACY_KID.to_string()
+                }
+                Err(e) => {
+                    return Err(ApiError::Internal(format!(
+                        "failed to read key id: {e}"
+                    )));
+                }
+            };
+            (signing_key, kid)
         } else {
-            // Generate new keypair
             let signing_key = SigningKey::generate(&mut OsRng);
-            let verify_key = signing_key.verifying_key();
-            let priv_pkcs8 = signing_key.to_pkcs8_der().map_err(|e| {
+            let kid = kid_for(&signing_key.verifying_key());
+            write_current_key(&path, &signing_key, &kid)?;
+            println!("Generated new ed25519 keypair at {:?}", path);
+            (signing_key, kid)
+        };
+
+        let previous = load_previous_keys(&path.join("previous"))?;
+        Self::new(path, &signing_key, kid, previous)
+    }
+
+    /// Replace the signing key with a newly generated one.
+    ///
+    /// The old key is persisted as verify-only, so tokens it signed are
+    /// still accepted until they expire.
+    pub fn rotate(&mut self) -> ApiResult<()> {
+        // The current public key file is already SPKI, so it moves as is
+        let old_kid = self.kid.clone();
+        let old_spki =
+            fs::read(self.path.join("public_ed25519.der")).map_err(|e| {
+                ApiError::Internal(format!("failed to read public key: {e}"))
+            })?;
+        let previous_dir = self.path.join("previous");
+        fs::create_dir_all(&previous_dir).map_err(|e| {
+            ApiError::Internal(format!(
+                "failed to create previous keys dir: {e}"
+            ))
+        })?;
+        fs::write(previous_dir.join(format!("{old_kid}.der")), &old_spki)
+            .map_err(|e| {
                 ApiError::Internal(format!(
-                    "failed to encode private key (pkcs8): {e}"
+                    "failed to write previous public key: {e}"
                 ))
             })?;
-            let pub_spki = verify_key.to_public_key_der().map_err(|e| {
+
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let kid = kid_for(&signing_key.verifying_key());
+        write_current_key(&self.path, &signing_key, &kid)?;
+        *self = Self::new(
+            self.path.clone(),
+            &signing_key,
+            kid,
+            self.verifying_keys.clone(),
+        )?;
+        Ok(())
+    }
+
+    /// Header for tokens signed with the current key.
+    pub fn header(&self) -> Header {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.kid.clone());
+        header
+    }
+
+    /// Key that verifies `token`, chosen by the kid in its header.
+    pub fn decoding_key_for(&self, token: &str) -> ApiResult<&DecodingKey> {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| {
+            ApiError::Unauthorized("Invalid or expired token".into())
+        })?;
+        self.decoding_key(header.kid.as_deref())
+            .ok_or_else(|| ApiError::Unauthorized("Unknown signing key".into()))
+    }
+
+    /// Key that verifies tokens signed with `kid`.
+    ///
+    /// Tokens without a kid predate key ids and are checked against the
+    /// current key.
+    fn decoding_key(&self, kid: Option<&str>) -> Option<&DecodingKey> {
+        self.decoding_keys.get(kid.unwrap_or(&self.kid))
+    }
+
+    /// Every key tokens from this host may be signed with, current first.
+    pub fn public_jwks(&self) -> Vec<PublicJwk> {
+        self.verifying_keys
+            .iter()
+            .map(|(kid, key)| PublicJwk::from_ed25519_bytes(key, kid.clone()))
+            .collect()
+    }
+
+    fn new(
+        path: PathBuf,
+        signing_key: &SigningKey,
+        kid: String,
+        mut previous: Vec<(String, [u8; 32])>,
+    ) -> ApiResult<Self> {
+        let priv_pkcs8 = signing_key.to_pkcs8_der().map_err(|e| {
+            ApiError::Internal(format!(
+                "failed to encode private key (pkcs8): {e}"
+            ))
+        })?;
+        // jsonwebtoken's EdDSA verifier expects the *raw 32-byte* Ed25519
+        // public key rather than SPKI
+        let current = signing_key.verifying_key().to_bytes();
+        previous.retain(|(previous_kid, _)| previous_kid != &kid);
+        previous.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut verifying_keys = vec![(kid.clone(), current)];
+        verifying_keys.extend(previous);
+        let decoding_keys = verifying_keys
+            .iter()
+            .map(|(kid, key)| (kid.clone(), DecodingKey::from_ed_der(key)))
+            .collect();
+        Ok(Self {
+            private_key: EncodingKey::from_ed_der(priv_pkcs8.as_bytes()),
+            kid,
+            verifying_keys,
+            decoding_keys,
+            path,
+        })
+    }
+}
+
+/// Derive a key id from the public key, so ids differ across rotations.
+fn kid_for(key: &VerifyingKey) -> String {
+    URL_SAFE_NO_PAD.encode(&key.to_bytes()[..12])
+}
+
+fn load_signing_key(
+    priv_path: &Path,
+    pub_path: &Path,
+) -> ApiResult<SigningKey> {
+    let priv_bytes = fs::read(priv_path).map_err(|e| {
+        ApiError::Internal(format!("failed to read private key: {e}"))
+    })?;
+    let pub_bytes = fs::read(pub_path).map_err(|e| {
+        ApiError::Internal(format!("failed to read public key: {e}"))
+    })?;
+
+    let signing_key = SigningKey::from_pkcs8_der(&priv_bytes).map_err(|e| {
+        ApiError::Internal(format!(
+            "invalid private key (expected PKCS#8 DER): {e}"
+        ))
+    })?;
+
+    // Ensure the public key matches the private key
+    let loaded_pub = parse_public_key(&pub_bytes)?;
+    if signing_key.verifying_key().to_bytes() != loaded_pub {
+        return Err(ApiError::Internal(
+            "public key does not match private key".into(),
+        ));
+    }
+    Ok(signing_key)
+}
+
+/// Parse an SPKI DER public key into its raw 32 bytes.
+fn parse_public_key(bytes: &[u8]) -> ApiResult<[u8; 32]> {
+    VerifyingKey::from_public_key_der(bytes)
+        .map(|key| key.to_bytes())
+        .map_err(|e| {
+            ApiError::Internal(format!(
+                "invalid public key (expected SPKI DER): {e}"
+            ))
+        })
+}
+
+/// Read the verify-only keys in `dir`, named by their kid.
+fn load_previous_keys(dir: &Path) -> ApiResult<Vec<(String, [u8; 32])>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Vec::new());
+        }
+        Err(e) => {
+            return Err(ApiError::Internal(format!(
+                "failed to read previous keys dir: {e}"
+            )));
+        }
+    };
+    let mut keys = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| {
+                ApiError::Internal(format!(
+                    "failed to read previous keys dir: {e}"
+                ))
+            })?
+            .path();
+        if path.extension().is_none_or(|ext| ext != "der") {
+            continue;
+        }
+        let Some(kid) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let bytes = fs::read(&path).map_err(|e| {
+            ApiError::Internal(format!(
+                "failed to read previous key {kid}: {e}"
+            ))
+        })?;
+        keys.push((kid.to_string(), parse_public_key(&bytes)?));
+    }
+    Ok(keys)
+}
+
+fn write_current_key(
+    path: &Path,
+    signing_key: &SigningKey,
+    kid: &str,
+) -> ApiResult<()> {
+    let priv_pkcs8 = signing_key.to_pkcs8_der().map_err(|e| {
+        ApiError::Internal(format!("failed to encode private key (pkcs8): {e}"))
+    })?;
+    let pub_spki =
+        signing_key
+            .verifying_key()
+            .to_public_key_der()
+            .map_err(|e| {
                 ApiError::Internal(format!(
                     "failed to encode public key (spki): {e}"
                 ))
             })?;
-            let pub_raw = verify_key.to_bytes();
 
-            fs::create_dir_all(&path).map_err(|e| {
-                ApiError::Internal(format!("failed to create keys dir: {e}"))
-            })?;
-            fs::write(&priv_path, priv_pkcs8.as_bytes()).map_err(|e| {
-                ApiError::Internal(format!("failed to write private key: {e}"))
-            })?;
-            fs::write(&pub_path, pub_spki.as_bytes()).map_err(|e| {
-                ApiError::Internal(format!("failed to write public key: {e}"))
-            })?;
-            println!("Generated new ed25519 keypair at {:?}", path);
+    fs::create_dir_all(path).map_err(|e| {
+        ApiError::Internal(format!("failed to create keys dir: {e}"))
+    })?;
+    fs::write(path.join("private_ed25519.der"), priv_pkcs8.as_bytes())
+        .map_err(|e| {
+            ApiError::Internal(format!("failed to write private key: {e}"))
+        })?;
+    fs::write(path.join("public_ed25519.der"), pub_spki.as_bytes()).map_err(
+        |e| ApiError::Internal(format!("failed to write public key: {e}")),
+    )?;
+    fs::write(path.join("kid"), kid).map_err(|e| {
+        ApiError::Internal(format!("failed to write key id: {e}"))
+    })?;
+    Ok(())
+}
 
-            let kid = "primary".to_string(); // TODO: should this change?
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            Ok(Self {
-                private_key: EncodingKey::from_ed_der(priv_pkcs8.as_bytes()),
-                decoding_key: DecodingKey::from_ed_der(&pub_raw),
-                public_jwk: PublicJwk::from_ed25519_bytes(&pub_raw, kid),
-                path,
-            })
-        }
+    fn temp_key_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("runelink-keys-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_rotate_keeps_previous_key_for_verification() {
+        let dir = temp_key_dir("rotate");
+        let mut keys = KeyManager::load_or_generate(dir.clone()).unwrap();
+        let old_kid = keys.kid.clone();
+
+        keys.rotate().unwrap();
+        assert_ne!(keys.kid, old_kid);
+        assert!(keys.decoding_key(Some(&old_kid)).is_some());
+        assert!(keys.decoding_key(Some(&keys.kid)).is_some());
+        assert_eq!(keys.public_jwks()[0].kid, keys.kid);
+        assert_eq!(keys.public_jwks().len(), 2);
+
+        // Both keys survive a restart
+        let reloaded = KeyManager::load_or_generate(dir.clone()).unwrap();
+        assert_eq!(reloaded.kid, keys.kid);
+        assert!(reloaded.decoding_key(Some(&old_kid)).is_some());
+        assert_eq!(reloaded.public_jwks(), keys.public_jwks());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_missing_kid_uses_current_key() {
+        let dir = temp_key_dir("missing-kid");
+        let keys = KeyManager::load_or_generate(dir.clone()).unwrap();
+        assert!(keys.decoding_key(None).is_some());
+        assert!(keys.decoding_key(Some("unknown")).is_none());
+        let _ = fs::remove_dir_all(&dir);
     }
 }
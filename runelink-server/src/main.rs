@@ -1,9 +1,15 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{future::IntoFuture, net::SocketAddr, process::ExitCode, sync::Arc};
 
+use axum::{Router, extract::connect_info::IntoMakeServiceWithConnectInfo};
+use axum_server::{Handle, tls_rustls::RustlsConfig};
+use futures_util::FutureExt;
 use sqlx::migrate::Migrator;
-use tokio::{net::TcpListener, sync::RwLock, task::JoinSet};
+use tokio::{net::TcpListener, sync::watch, task::JoinSet};
 
-use crate::{config::ServerConfig, key_manager::KeyManager, state::AppState};
+use crate::{
+    config::ServerConfig, key_manager::KeyManager, startup::StartupError,
+    state::AppState,
+};
 
 mod api;
 mod auth;
@@ -15,16 +21,21 @@ mod error;
 mod ids;
 mod jwks_resolver;
 mod key_manager;
+mod metrics;
 mod ops;
 mod queries;
+mod rate_limit;
+mod startup;
 mod state;
+#[cfg(test)]
+mod test_util;
 mod ws;
 
 // Embed all sql migrations in binary
 static MIGRATOR: Migrator = sqlx::migrate!();
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> ExitCode {
     // Initialize logger - reads RUST_LOG environment variable
     // Examples: RUST_LOG=info, RUST_LOG=debug, RUST_LOG=runelink_server=debug
     // Defaults to info level if RUST_LOG is not set
@@ -33,10 +44,26 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     )
     .init();
 
+    // Report failures as a single line rather than a debug dump
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            log::error!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run() -> Result<(), StartupError> {
     let config_path = std::env::var("RUNELINK_CONFIG")
         .map(std::path::PathBuf::from)
         .unwrap_or_else(|_| std::path::PathBuf::from("config.toml"));
     let server_configs = ServerConfig::from_toml_file(&config_path)?;
+    // Served TLS uses the same rustls backend as outbound HTTPS; installing
+    // it fails only if it's already installed
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let rotate_keys = std::env::var("RUNELINK_ROTATE_KEYS")
+        .is_ok_and(|value| value == "1" || value == "true");
 
     if server_configs.len() > 1 {
         log::info!(
@@ -47,57 +74,217 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         log::info!("Starting single server instance");
     }
 
+    let instances = server_configs.len();
     let mut join_set = JoinSet::new();
 
+    // Every instance drains on the same signal
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        log::info!("Shutdown signal received, draining connections");
+        let _ = shutdown_tx.send(true);
+    });
+
     for config in server_configs {
         let config = Arc::new(config);
-        let db_pool = Arc::new(db::get_pool(&config).await?);
-
-        let app_state = AppState {
-            config: config.clone(),
-            db_pool: db_pool.clone(),
-            http_client: reqwest::Client::new(),
-            client_ws_manager: ws::ClientWsManager::new(),
-            federation_ws_manager: ws::FederationWsManager::new(),
-            key_manager: KeyManager::load_or_generate(config.key_dir.clone())?,
-            jwks_cache: Arc::new(RwLock::new(HashMap::new())),
-            routing_index: ws::RoutingIndex::new(
-                db_pool.clone(),
-                config.clone(),
-            ),
-        };
-
-        MIGRATOR.run(db_pool.as_ref()).await?;
-        log::info!(
-            "Migrations are up to date for {}.",
-            config.public_host_with_explicit_port()
+        let db_pool = Arc::new(
+            db::get_pool(&config)
+                .await
+                .map_err(|e| StartupError::database(&config, e))?,
         );
+        let mut key_manager =
+            KeyManager::load_or_generate(config.key_dir.clone())
+                .map_err(|e| StartupError::keys(&config, e))?;
+        // Set RUNELINK_ROTATE_KEYS=1 to start signing with a fresh key; the
+        // old one keeps verifying tokens it already signed
+        if rotate_keys {
+            key_manager
+                .rotate()
+                .map_err(|e| StartupError::keys(&config, e))?;
+            log::info!(
+                "Rotated signing key for {}, now using kid {}",
+                config.public_host_with_explicit_port(),
+                key_manager.kid
+            );
+        }
+
+        let app_state =
+            AppState::new(config.clone(), db_pool.clone(), key_manager);
 
-        let app = api::router().with_state(app_state);
+        MIGRATOR
+            .run(db_pool.as_ref())
+            .await
+            .map_err(|e| StartupError::migrations(&config, e))?;
+
+        let mut router = api::router();
+        if config.metrics_enabled && config.metrics_port.is_none() {
+            router = router.merge(metrics::router());
+        }
+        let app = router.with_state(app_state.clone());
 
+        let tls = match &config.tls {
+            Some(tls) => Some(
+                RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path)
+                    .await
+                    .map_err(|e| StartupError::tls(&config, tls, e))?,
+            ),
+            None => None,
+        };
         let bind_addr = config.bind_addr();
-        let listener = TcpListener::bind(&bind_addr).await?;
+        let listener = TcpListener::bind(&bind_addr)
+            .await
+            .map_err(|e| StartupError::bind(&config, e))?;
         let host = config.public_host_with_explicit_port();
+        if let Some(metrics_addr) = config.metrics_bind_addr() {
+            let metrics_listener = TcpListener::bind(&metrics_addr)
+                .await
+                .map_err(|e| StartupError::metrics_bind(&config, e))?;
+            let metrics_app = metrics::router().with_state(app_state.clone());
+            let mut stop = shutdown_rx.clone();
+            let host = host.clone();
+            join_set.spawn(async move {
+                axum::serve(metrics_listener, metrics_app)
+                    .with_graceful_shutdown(async move {
+                        let _ = stop.wait_for(|stop| *stop).await;
+                    })
+                    .await
+                    .map_err(|e| {
+                        format!(
+                            "metrics listener for {host} exited with error: {e}"
+                        )
+                    })
+            });
+        }
 
-        log::info!("Starting server {host} on {bind_addr}");
+        // Warmup runs in the background and never blocks startup
+        app_state.federation_ws_manager.spawn_warmup(
+            app_state.clone(),
+            config.federation_warm_hosts.clone(),
+        );
+        ops::presence::spawn_announcer(app_state.clone());
+        ops::cleanup::spawn(app_state.clone());
+        ops::membership_sync::spawn(app_state.clone());
+
+        log::info!("{}", startup::readiness_summary(&config, instances));
+        let mut stop = shutdown_rx.clone();
+        let mut grace_deadline = shutdown_rx.clone();
+        let grace_period = config.shutdown_grace_period;
         join_set.spawn(async move {
-            axum::serve(listener, app)
-                .await
-                .map_err(|e| format!("server {host} exited with error: {e}"))
+            // Peer addresses key the per-IP rate limits
+            let app = app.into_make_service_with_connect_info::<SocketAddr>();
+            let stopped = {
+                let state = app_state.clone();
+                async move {
+                    let _ = stop.wait_for(|stop| *stop).await;
+                    close_websockets(&state).await;
+                }
+            };
+            let serve = match tls {
+                Some(tls) => serve_tls(listener, tls, app, stopped).boxed(),
+                None => axum::serve(listener, app)
+                    .with_graceful_shutdown(stopped)
+                    .into_future()
+                    .boxed(),
+            };
+            let drain = async {
+                serve.await.map_err(|e| {
+                    format!("server {host} exited with error: {e}")
+                })?;
+                app_state.open_sockets.all_closed().await;
+                Ok::<_, String>(())
+            };
+            tokio::select! {
+                result = drain => result,
+                _ = async {
+                    let _ = grace_deadline.wait_for(|stop| *stop).await;
+                    tokio::time::sleep(grace_period).await;
+                } => {
+                    log::warn!(
+                        "{host} still had open connections after {}s, exiting anyway",
+                        grace_period.as_secs()
+                    );
+                    Ok(())
+                }
+            }
         });
     }
 
     while let Some(join_result) = join_set.join_next().await {
         match join_result {
             Ok(Ok(())) => {}
-            Ok(Err(err_msg)) => return Err(err_msg.into()),
+            Ok(Err(err_msg)) => return Err(StartupError::Exited(err_msg)),
             Err(join_err) => {
-                return Err(
-                    format!("server task join failure: {join_err}").into()
-                );
+                return Err(StartupError::Exited(format!(
+                    "server task join failure: {join_err}"
+                )));
             }
         }
     }
 
     Ok(())
 }
+
+/// Serves HTTPS and WSS on `listener` until `stopped` resolves.
+async fn serve_tls(
+    listener: TcpListener,
+    tls: RustlsConfig,
+    app: IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    stopped: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    let handle = Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            stopped.await;
+            // Requests in flight finish; the caller bounds how long that takes
+            handle.graceful_shutdown(None);
+        }
+    });
+    axum_server::from_tcp_rustls(listener.into_std()?, tls)
+        .handle(handle)
+        .serve(app)
+        .await
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("Failed to listen for Ctrl-C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                log::error!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Sends a close frame on every websocket and cancels outbound federation
+/// requests still waiting for a reply.
+async fn close_websockets(state: &AppState) {
+    let clients = state.client_ws_manager.close_all().await;
+    let peers = state.federation_ws_manager.close_all().await;
+    log::info!(
+        "{}: closing {clients} client and {peers} federation websockets ({} open)",
+        state.config.public_host_with_explicit_port(),
+        state.open_sockets.count()
+    );
+}
@@ -0,0 +1,247 @@
+use std::{
+    fmt::Write,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use axum::{
+    Router, extract::State, http::header, response::IntoResponse, routing::get,
+};
+
+use crate::{
+    state::AppState,
+    ws::breaker::{BreakerState, HostBreakerStatus},
+};
+
+/// Content type of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Counters bumped as events happen; gauges are read from the pools when
+/// metrics are scraped.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    messages_created: Arc<AtomicU64>,
+    federation_request_failures: Arc<AtomicU64>,
+}
+
+impl Metrics {
+    pub fn message_created(&self) {
+        self.messages_created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// An outbound federation request failed without a reply from the host.
+    pub fn federation_request_failed(&self) {
+        self.federation_request_failures
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Everything reported by one scrape.
+#[derive(Debug, Default)]
+struct Snapshot {
+    client_connections: usize,
+    federation_connections: usize,
+    federation_hosts: Vec<(String, usize)>,
+    federation_pending_requests: usize,
+    federation_breakers: Vec<HostBreakerStatus>,
+    messages_created: u64,
+    federation_request_failures: u64,
+}
+
+impl Snapshot {
+    async fn take(state: &AppState) -> Self {
+        let federation = &state.federation_ws_manager;
+        let mut federation_hosts = federation.host_connection_counts().await;
+        federation_hosts.sort();
+        Self {
+            client_connections: state
+                .client_ws_manager
+                .connection_count()
+                .await,
+            federation_connections: federation.connection_count().await,
+            federation_hosts,
+            federation_pending_requests: federation.pending_count().await,
+            federation_breakers: federation.breaker_statuses(state).await,
+            messages_created: state
+                .metrics
+                .messages_created
+                .load(Ordering::Relaxed),
+            federation_request_failures: state
+                .metrics
+                .federation_request_failures
+                .load(Ordering::Relaxed),
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        let name = describe(
+            &mut out,
+            "runelink_client_connections",
+            "gauge",
+            "Open client websocket connections.",
+        );
+        let _ = writeln!(out, "{name} {}", self.client_connections);
+
+        let name = describe(
+            &mut out,
+            "runelink_federation_connections",
+            "gauge",
+            "Open federation websocket connections, authenticated or not.",
+        );
+        let _ = writeln!(out, "{name} {}", self.federation_connections);
+
+        let name = describe(
+            &mut out,
+            "runelink_federation_host_connections",
+            "gauge",
+            "Authenticated federation connections per host.",
+        );
+        for (host, count) in &self.federation_hosts {
+            let _ =
+                writeln!(out, "{name}{{host=\"{}\"}} {count}", escape(host));
+        }
+
+        let name = describe(
+            &mut out,
+            "runelink_federation_pending_requests",
+            "gauge",
+            "Outbound federation requests waiting for a reply.",
+        );
+        let _ = writeln!(out, "{name} {}", self.federation_pending_requests);
+
+        let name = describe(
+            &mut out,
+            "runelink_federation_breaker_state",
+            "gauge",
+            "Circuit breaker of each host with recent failed requests: \
+             0 closed, 1 half-open, 2 open.",
+        );
+        for breaker in &self.federation_breakers {
+            let value = match breaker.state {
+                BreakerState::Closed => 0,
+                BreakerState::HalfOpen => 1,
+                BreakerState::Open => 2,
+            };
+            let host = escape(&breaker.host);
+            let _ = writeln!(out, "{name}{{host=\"{host}\"}} {value}");
+        }
+
+        let name = describe(
+            &mut out,
+            "runelink_federation_breaker_consecutive_failures",
+            "gauge",
+            "Consecutive failed requests to each host with recent failures.",
+        );
+        for breaker in &self.federation_breakers {
+            let host = escape(&breaker.host);
+            let failures = breaker.consecutive_failures;
+            let _ = writeln!(out, "{name}{{host=\"{host}\"}} {failures}");
+        }
+
+        let name = describe(
+            &mut out,
+            "runelink_messages_created_total",
+            "counter",
+            "Messages created on this server.",
+        );
+        let _ = writeln!(out, "{name} {}", self.messages_created);
+
+        let name = describe(
+            &mut out,
+            "runelink_federation_request_failures_total",
+            "counter",
+            "Outbound federation requests that got no reply.",
+        );
+        let _ = writeln!(out, "{name} {}", self.federation_request_failures);
+        out
+    }
+}
+
+/// Writes a metric's HELP and TYPE lines and returns its name.
+fn describe<'a>(
+    out: &mut String,
+    name: &'a str,
+    kind: &str,
+    help: &str,
+) -> &'a str {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    name
+}
+
+/// Escapes a label value for the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// GET /metrics
+pub async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let body = Snapshot::take(&state).await.render();
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], body)
+}
+
+/// Just the metrics endpoint, for a listener of its own.
+pub fn router() -> Router<AppState> {
+    Router::new().route("/metrics", get(get_metrics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_reports_every_metric() {
+        let snapshot = Snapshot {
+            client_connections: 3,
+            federation_connections: 2,
+            federation_hosts: vec![
+                ("a.example".into(), 1),
+                ("b.example:7001".into(), 2),
+            ],
+            federation_pending_requests: 1,
+            federation_breakers: vec![
+                HostBreakerStatus {
+                    host: "a.example".into(),
+                    state: BreakerState::Closed,
+                    consecutive_failures: 1,
+                },
+                HostBreakerStatus {
+                    host: "c.example".into(),
+                    state: BreakerState::Open,
+                    consecutive_failures: 5,
+                },
+            ],
+            messages_created: 42,
+            federation_request_failures: 5,
+        };
+        let rendered = snapshot.render();
+        for line in [
+            "# TYPE runelink_client_connections gauge",
+            "runelink_client_connections 3",
+            "runelink_federation_connections 2",
+            "runelink_federation_host_connections{host=\"a.example\"} 1",
+            "runelink_federation_host_connections{host=\"b.example:7001\"} 2",
+            "runelink_federation_pending_requests 1",
+            "runelink_federation_breaker_state{host=\"a.example\"} 0",
+            "runelink_federation_breaker_state{host=\"c.example\"} 2",
+            "runelink_federation_breaker_consecutive_failures{host=\"c.example\"} 5",
+            "# TYPE runelink_messages_created_total counter",
+            "runelink_messages_created_total 42",
+            "runelink_federation_request_failures_total 5",
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "missing `{line}`");
+        }
+    }
+
+    #[test]
+    fn test_escape_label_value() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
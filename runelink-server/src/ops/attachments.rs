@@ -0,0 +1,369 @@
+use std::path::PathBuf;
+
+use runelink_client::util::get_api_url;
+use runelink_types::{
+    channel::ChannelId,
+    message::{AttachmentId, AttachmentRef},
+    server::ServerId,
+    user::UserRef,
+};
+
+use crate::{
+    auth::Session,
+    error::{ApiError, ApiResult},
+    queries,
+    state::AppState,
+};
+
+/// Longest filename kept for an attachment, in characters.
+const MAX_FILENAME_LENGTH: usize = 255;
+
+/// An attachment's metadata along with its contents.
+pub struct AttachmentFile {
+    pub attachment: AttachmentRef,
+    pub data: Vec<u8>,
+}
+
+/// Store a file uploaded by the session user for a channel.
+///
+/// Uploads are always stored on the uploader's home host, including those
+/// for channels on other hosts.
+pub async fn upload(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    filename: Option<&str>,
+    content_type: Option<&str>,
+    data: Vec<u8>,
+) -> ApiResult<AttachmentRef> {
+    let uploader = session.user_ref.as_ref().ok_or_else(|| {
+        ApiError::Forbidden("User reference required for uploads".into())
+    })?;
+    if queries::servers::exists(&state.db_pool, server_id).await? {
+        let channel =
+            queries::channels::get_by_id(&state.db_pool, channel_id).await?;
+        if channel.server_id != server_id {
+            return Err(ApiError::NotFound);
+        }
+    }
+    let content_type = content_type.map(normalize_content_type);
+    let content_type = check_content_type(state, content_type.as_deref())?;
+    check_size(state, data.len())?;
+
+    let id = AttachmentId::new();
+    let attachment = AttachmentRef {
+        id,
+        filename: sanitize_filename(filename.unwrap_or_default()),
+        content_type,
+        size: data.len() as i64,
+        url: format!("{}/attachments/{id}", state.config.api_url()),
+    };
+    write_file(state, id, &data).await?;
+    queries::attachments::insert(
+        &state.db_pool,
+        &attachment,
+        channel_id,
+        uploader,
+        true,
+    )
+    .await?;
+    Ok(attachment)
+}
+
+/// Resolve the attachments listed on a new message.
+///
+/// A local author's attachments are looked up among their uploads, so only
+/// the ids they send matter. A remote author's are checked against the
+/// metadata their home host sent; the message insert records them, and
+/// they're downloaded when first fetched.
+pub async fn resolve_for_message(
+    state: &AppState,
+    channel_id: ChannelId,
+    author: &UserRef,
+    attachments: &[AttachmentRef],
+) -> ApiResult<Vec<AttachmentRef>> {
+    if attachments.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !state.config.is_remote_host(Some(author.host.as_str())) {
+        let ids = attachments
+            .iter()
+            .map(|attachment| attachment.id)
+            .collect::<Vec<_>>();
+        return queries::attachments::get_unattached(
+            &state.db_pool,
+            &ids,
+            channel_id,
+            author,
+        )
+        .await;
+    }
+    let author_api_url = get_api_url(&author.host, state.config.secure);
+    let mut resolved = Vec::with_capacity(attachments.len());
+    for attachment in attachments {
+        // Only fetch from the author's own host
+        let expected_url =
+            format!("{author_api_url}/attachments/{}", attachment.id);
+        if attachment.url != expected_url {
+            return Err(ApiError::BadRequest(format!(
+                "Attachment {} is not hosted by {}",
+                attachment.id, author.host
+            )));
+        }
+        let content_type = check_content_type(
+            state,
+            Some(&normalize_content_type(&attachment.content_type)),
+        )?;
+        let size = usize::try_from(attachment.size).map_err(|_| {
+            ApiError::BadRequest("Attachment size is invalid".into())
+        })?;
+        check_size(state, size)?;
+        resolved.push(AttachmentRef {
+            id: attachment.id,
+            filename: sanitize_filename(&attachment.filename),
+            content_type,
+            size: attachment.size,
+            url: attachment.url.clone(),
+        });
+    }
+    Ok(resolved)
+}
+
+/// Get an attachment's contents, downloading it from its home host the
+/// first time it's fetched here.
+///
+/// Attachment ids are random and unlisted, so fetching one needs no auth.
+pub async fn get_file(
+    state: &AppState,
+    attachment_id: AttachmentId,
+) -> ApiResult<AttachmentFile> {
+    let row =
+        queries::attachments::get_by_id(&state.db_pool, attachment_id).await?;
+    let stored = row.stored;
+    let attachment = AttachmentRef::from(row);
+    if stored {
+        let data = tokio::fs::read(file_path(state, attachment_id))
+            .await
+            .map_err(|error| {
+                ApiError::Internal(format!(
+                    "Failed to read attachment {attachment_id}: {error}"
+                ))
+            })?;
+        return Ok(AttachmentFile { attachment, data });
+    }
+
+    let download_error = |error: reqwest::Error| {
+        ApiError::ServiceUnavailable(format!(
+            "Attachment {attachment_id} could not be downloaded: {error}"
+        ))
+    };
+    let mut response = state
+        .http_client
+        .get(&attachment.url)
+        .send()
+        .await
+        .map_err(download_error)?;
+    if !response.status().is_success() {
+        return Err(ApiError::ServiceUnavailable(format!(
+            "Attachment {attachment_id} could not be downloaded: {}",
+            response.status()
+        )));
+    }
+    // Refuse oversized files up front when the peer says how big they are,
+    // and stop reading once the limit is passed when it doesn't (or lies)
+    if let Some(length) = response.content_length() {
+        check_size(state, usize::try_from(length).unwrap_or(usize::MAX))?;
+    }
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(download_error)? {
+        check_size(state, data.len() + chunk.len())?;
+        data.extend_from_slice(&chunk);
+    }
+    write_file(state, attachment_id, &data).await?;
+    queries::attachments::mark_stored(&state.db_pool, attachment_id).await?;
+    Ok(AttachmentFile { attachment, data })
+}
+
+/// Fails unless an upload of `size` bytes is within the configured limit.
+pub fn check_size(state: &AppState, size: usize) -> ApiResult<()> {
+    if size > state.config.max_attachment_size {
+        return Err(ApiError::BadRequest(format!(
+            "Attachment is larger than {} bytes",
+            state.config.max_attachment_size
+        )));
+    }
+    Ok(())
+}
+
+fn check_content_type(
+    state: &AppState,
+    content_type: Option<&str>,
+) -> ApiResult<String> {
+    let Some(content_type) = content_type else {
+        return Err(ApiError::BadRequest(
+            "Attachment content type is required".into(),
+        ));
+    };
+    if !state
+        .config
+        .attachment_content_types
+        .iter()
+        .any(|allowed| allowed == content_type)
+    {
+        return Err(ApiError::BadRequest(format!(
+            "Attachments of type {content_type} are not allowed"
+        )));
+    }
+    Ok(content_type.to_string())
+}
+
+/// Lowercases a content type and drops any parameters.
+fn normalize_content_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// Keeps only the final path component of a filename, trimmed and capped
+/// in length, falling back to a generic name.
+fn sanitize_filename(filename: &str) -> String {
+    let name = filename
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_FILENAME_LENGTH)
+        .collect::<String>();
+    if name.is_empty() || name == "." || name == ".." {
+        "attachment".to_string()
+    } else {
+        name
+    }
+}
+
+fn file_path(state: &AppState, attachment_id: AttachmentId) -> PathBuf {
+    state.config.attachments_dir.join(attachment_id.to_string())
+}
+
+async fn write_file(
+    state: &AppState,
+    attachment_id: AttachmentId,
+    data: &[u8],
+) -> ApiResult<()> {
+    let write = async {
+        tokio::fs::create_dir_all(&state.config.attachments_dir).await?;
+        tokio::fs::write(file_path(state, attachment_id), data).await
+    };
+    write.await.map_err(|error| {
+        ApiError::Internal(format!(
+            "Failed to store attachment {attachment_id}: {error}"
+        ))
+    })
+}
+
+/// Auth requirements for attachment operations.
+pub mod auth {
+    use super::*;
+    use crate::auth::{MESSAGES_WRITE, Requirement as Req};
+
+    pub fn upload(server_id: ServerId) -> Req {
+        Req::ServerMember(server_id)
+            .or_admin()
+            .client_only()
+            .with_scope(MESSAGES_WRITE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::IntoFuture;
+
+    use axum::{Router, body::Body, routing::get};
+    use futures_util::stream;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{db::DbPool, test_util};
+
+    #[test]
+    fn test_normalize_content_type_drops_parameters() {
+        assert_eq!(
+            normalize_content_type("Text/Plain; charset=utf-8"),
+            "text/plain"
+        );
+        assert_eq!(normalize_content_type(" image/PNG "), "image/png");
+    }
+
+    #[test]
+    fn test_sanitize_filename_keeps_last_component() {
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("C:\\Users\\me\\cat.png"), "cat.png");
+        assert_eq!(sanitize_filename("  notes.txt "), "notes.txt");
+    }
+
+    #[test]
+    fn test_sanitize_filename_falls_back() {
+        assert_eq!(sanitize_filename(""), "attachment");
+        assert_eq!(sanitize_filename("dir/"), "attachment");
+        assert_eq!(sanitize_filename(".."), "attachment");
+        assert_eq!(sanitize_filename(&"a".repeat(300)).len(), 255);
+    }
+
+    #[sqlx::test]
+    async fn test_remote_download_is_capped(pool: DbPool) {
+        let mut config = test_util::config();
+        config.max_attachment_size = 32;
+        let state = test_util::state_with_config(pool, config);
+        let owner = test_util::local_user(&state, "owner").await.as_ref();
+        let remote = test_util::user(&state, "bob", "remote.example").await;
+        let server = test_util::server(&state, &owner, "Guild").await;
+        let channel = test_util::channel(&state, server.id, "general").await;
+
+        // A peer serving a file that fits, one that's too big and says so,
+        // and one that's too big without a length up front
+        let chunks = || {
+            stream::iter((0..4).map(|_| Ok::<_, std::io::Error>(vec![0u8; 16])))
+        };
+        let router = Router::new()
+            .route("/small", get(|| async { vec![0u8; 16] }))
+            .route("/sized", get(|| async { vec![0u8; 64] }))
+            .route(
+                "/streamed",
+                get(move || async move { Body::from_stream(chunks()) }),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(axum::serve(listener, router).into_future());
+
+        let mut results = Vec::new();
+        for path in ["small", "sized", "streamed"] {
+            let attachment = AttachmentRef {
+                id: AttachmentId::new(),
+                filename: format!("{path}.bin"),
+                content_type: "application/octet-stream".into(),
+                size: 16,
+                url: format!("{peer}/{path}"),
+            };
+            queries::attachments::insert(
+                &state.db_pool,
+                &attachment,
+                channel.id,
+                &remote.as_ref(),
+                false,
+            )
+            .await
+            .unwrap();
+            results.push(get_file(&state, attachment.id).await);
+        }
+        assert_eq!(results[0].as_ref().unwrap().data.len(), 16);
+        for result in &results[1..] {
+            assert!(matches!(result, Err(ApiError::BadRequest(_))));
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use runelink_types::{
+    server::{NewServerBan, ServerBan, ServerId},
+    user::UserRef,
+    ws::{FederationWsReply, FederationWsRequest},
+};
+
+use super::{federation, memberships};
+use crate::{
+    auth::Session,
+    error::{ApiError, ApiResult},
+    queries,
+    state::AppState,
+};
+
+/// Ban a user from a server, removing their membership if they have one.
+pub async fn ban(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    new_ban: &NewServerBan,
+    target_host: Option<&str>,
+) -> ApiResult<ServerBan> {
+    let banned_by = session.user_ref.clone().ok_or_else(|| {
+        ApiError::Forbidden("User reference required for bans".into())
+    })?;
+    if new_ban.user_ref == banned_by {
+        return Err(ApiError::BadRequest("Cannot ban yourself".into()));
+    }
+    if !state.config.is_remote_host(target_host) {
+        // Handle local case
+        queries::servers::get_by_id(state, server_id).await?;
+        let ban = queries::bans::upsert(
+            &state.db_pool,
+            server_id,
+            new_ban,
+            &banned_by,
+        )
+        .await?;
+        match memberships::remove_local_member(
+            state,
+            server_id,
+            new_ban.user_ref.clone(),
+        )
+        .await
+        {
+            Ok(()) | Err(ApiError::NotFound) => Ok(ban),
+            Err(error) => Err(error),
+        }
+    } else {
+        // Ban on remote host, acting as the session user
+        let host = target_host.unwrap();
+        let reply = federation::request(
+            state,
+            host,
+            Some(banned_by),
+            FederationWsRequest::BansCreate {
+                server_id,
+                new_ban: new_ban.clone(),
+            },
+        )
+        .await?;
+        let FederationWsReply::BansCreate(ban) = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for bans.create"
+            )));
+        };
+        let _ = queries::memberships::delete_remote(
+            &state.db_pool,
+            server_id,
+            new_ban.user_ref.clone(),
+        )
+        .await;
+        state.routing_index.invalidate_server(server_id).await;
+        Ok(ban)
+    }
+}
+
+/// Lift a ban so the user can join the server again.
+pub async fn unban(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    user_ref: UserRef,
+    target_host: Option<&str>,
+) -> ApiResult<()> {
+    if !state.config.is_remote_host(target_host) {
+        // Handle local case
+        if !queries::bans::delete(&state.db_pool, server_id, &user_ref).await? {
+            return Err(ApiError::NotFound);
+        }
+        Ok(())
+    } else {
+        // Unban on remote host, acting as the session user
+        let host = target_host.unwrap();
+        let reply = federation::request(
+            state,
+            host,
+            session.user_ref.clone(),
+            FederationWsRequest::BansDelete {
+                server_id,
+                user_ref,
+            },
+        )
+        .await?;
+        let FederationWsReply::BansDelete = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for bans.delete"
+            )));
+        };
+        Ok(())
+    }
+}
+
+/// Auth requirements for ban operations.
+pub mod auth {
+    use super::*;
+    use crate::auth::Requirement as Req;
+
+    pub fn ban(server_id: ServerId) -> Req {
+        Req::ServerAdmin(server_id).or_admin().client_only()
+    }
+
+    pub fn unban(server_id: ServerId) -> Req {
+        Req::ServerAdmin(server_id).or_admin().client_only()
+    }
+
+    pub mod federated {
+        use super::*;
+
+        pub fn ban(server_id: ServerId) -> Req {
+            Req::ServerAdmin(server_id).federated_only()
+        }
+
+        pub fn unban(server_id: ServerId) -> Req {
+            Req::ServerAdmin(server_id).federated_only()
+        }
+    }
+}
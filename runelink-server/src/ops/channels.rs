@@ -1,5 +1,5 @@
 use runelink_types::{
-    channel::{Channel, ChannelId, NewChannel},
+    channel::{Channel, ChannelId, ChannelUpdate, NewChannel},
     server::ServerId,
     ws::{
         ClientWsUpdate, FederationWsReply, FederationWsRequest,
@@ -16,6 +16,9 @@ use crate::{
     state::AppState,
 };
 
+/// The longest slow mode a channel can have: six hours.
+pub const MAX_SLOW_MODE_SECS: i32 = 6 * 60 * 60;
+
 /// Create a new channel in a server.
 pub async fn create(
     state: &AppState,
@@ -24,8 +27,16 @@ pub async fn create(
     new_channel: &NewChannel,
     target_host: Option<&str>,
 ) -> ApiResult<Channel> {
+    new_channel
+        .validate()
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
     // Handle local case
     if !state.config.is_remote_host(target_host) {
+        validate_slow_mode(new_channel.slow_mode_secs)?;
+        let new_channel = &NewChannel {
+            slow_mode_secs: new_channel.slow_mode_secs.filter(|&secs| secs > 0),
+            ..new_channel.clone()
+        };
         let channel =
             queries::channels::insert(&state.db_pool, server_id, new_channel)
                 .await?;
@@ -109,6 +120,10 @@ pub async fn get_by_server(
 ) -> ApiResult<Vec<Channel>> {
     if !state.config.is_remote_host(target_host) {
         // Handle local case
+        // An unknown server is NotFound rather than an empty channel list
+        if !queries::servers::exists(&state.db_pool, server_id).await? {
+            return Err(ApiError::NotFound);
+        }
         queries::channels::get_by_server(&state.db_pool, server_id).await
     } else {
         // Fetch from remote host using federation
@@ -176,6 +191,154 @@ pub async fn get_by_id(
     }
 }
 
+/// Rejects a negative slow mode or one longer than `MAX_SLOW_MODE_SECS`.
+fn validate_slow_mode(slow_mode_secs: Option<i32>) -> ApiResult<()> {
+    if let Some(secs) = slow_mode_secs
+        && !(0..=MAX_SLOW_MODE_SECS).contains(&secs)
+    {
+        return Err(ApiError::BadRequest(format!(
+            "Slow mode must be between 0 and {MAX_SLOW_MODE_SECS} seconds"
+        )));
+    }
+    Ok(())
+}
+
+/// Trims the update's title and rejects an empty one or an invalid slow
+/// mode.
+fn normalize_update(update: &ChannelUpdate) -> ApiResult<ChannelUpdate> {
+    let title = match update.title.as_deref().map(str::trim) {
+        Some("") => {
+            return Err(ApiError::BadRequest(
+                "Channel title cannot be empty".into(),
+            ));
+        }
+        title => title.map(str::to_string),
+    };
+    validate_slow_mode(update.slow_mode_secs)?;
+    Ok(ChannelUpdate {
+        title,
+        description: update.description.clone(),
+        slow_mode_secs: update.slow_mode_secs,
+    })
+}
+
+/// Update a channel's title, description and/or slow mode.
+pub async fn update(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    update: &ChannelUpdate,
+    target_host: Option<&str>,
+) -> ApiResult<Channel> {
+    // Handle local case
+    if !state.config.is_remote_host(target_host) {
+        let update = normalize_update(update)?;
+        // Verify the channel belongs to the server
+        let channel =
+            queries::channels::get_by_id(&state.db_pool, channel_id).await?;
+        if channel.server_id != server_id {
+            return Err(ApiError::NotFound);
+        }
+        let channel =
+            queries::channels::update(&state.db_pool, channel_id, &update)
+                .await?;
+        fanout::fanout_update(
+            state,
+            fanout::resolve_server_targets(state, server_id).await?,
+            ClientWsUpdate::ChannelUpserted(channel.clone()),
+            FederationWsUpdate::ChannelUpserted(channel.clone()),
+        )
+        .await;
+        Ok(channel)
+    } else {
+        // Update on remote host using federation
+        let host = target_host.unwrap();
+        let user_ref = session.user_ref.clone().ok_or_else(|| {
+            ApiError::Internal(
+                "User reference required for federated channel update"
+                    .to_string(),
+            )
+        })?;
+        let reply = federation::request(
+            state,
+            host,
+            Some(user_ref),
+            FederationWsRequest::ChannelsUpdate {
+                server_id,
+                channel_id,
+                update: update.clone(),
+            },
+        )
+        .await?;
+        let FederationWsReply::ChannelsUpdate(channel) = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for channels.update"
+            )));
+        };
+        Ok(channel)
+    }
+}
+
+/// Reorder a server's channels; `ordered_channel_ids` must list each of
+/// them once. Returns the channels in their new order.
+pub async fn reorder(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    ordered_channel_ids: &[ChannelId],
+    target_host: Option<&str>,
+) -> ApiResult<Vec<Channel>> {
+    // Handle local case
+    if !state.config.is_remote_host(target_host) {
+        let changed = queries::channels::reorder(
+            &state.db_pool,
+            server_id,
+            ordered_channel_ids,
+        )
+        .await?;
+        if !changed.is_empty() {
+            let targets =
+                fanout::resolve_server_targets(state, server_id).await?;
+            for channel in changed {
+                fanout::fanout_update(
+                    state,
+                    targets.clone(),
+                    ClientWsUpdate::ChannelUpserted(channel.clone()),
+                    FederationWsUpdate::ChannelUpserted(channel),
+                )
+                .await;
+            }
+        }
+        queries::channels::get_by_server(&state.db_pool, server_id).await
+    } else {
+        // Reorder on remote host using federation
+        let host = target_host.unwrap();
+        let user_ref = session.user_ref.clone().ok_or_else(|| {
+            ApiError::Internal(
+                "User reference required for federated channel reordering"
+                    .to_string(),
+            )
+        })?;
+        let reply = federation::request(
+            state,
+            host,
+            Some(user_ref),
+            FederationWsRequest::ChannelsReorder {
+                server_id,
+                channel_ids: ordered_channel_ids.to_vec(),
+            },
+        )
+        .await?;
+        let FederationWsReply::ChannelsReorder(channels) = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for channels.reorder"
+            )));
+        };
+        Ok(channels)
+    }
+}
+
 /// Delete a channel by ID.
 pub async fn delete(
     state: &AppState,
@@ -190,9 +353,7 @@ pub async fn delete(
         let channel =
             queries::channels::get_by_id(&state.db_pool, channel_id).await?;
         if channel.server_id != server_id {
-            return Err(ApiError::AuthError(
-                "Channel not found in specified server".into(),
-            ));
+            return Err(ApiError::NotFound);
         }
         queries::channels::delete(&state.db_pool, channel_id).await?;
         fanout::fanout_update(
@@ -258,6 +419,14 @@ pub mod auth {
         Req::ServerMember(server_id).or_admin().client_only()
     }
 
+    pub fn update(server_id: ServerId) -> Req {
+        Req::ServerAdmin(server_id).or_admin().client_only()
+    }
+
+    pub fn reorder(server_id: ServerId) -> Req {
+        Req::ServerAdmin(server_id).or_admin().client_only()
+    }
+
     pub fn delete(server_id: ServerId) -> Req {
         Req::ServerAdmin(server_id).or_admin().client_only()
     }
@@ -281,8 +450,63 @@ pub mod auth {
             Req::ServerMember(server_id).federated_only()
         }
 
+        pub fn update(server_id: ServerId) -> Req {
+            Req::ServerAdmin(server_id).federated_only()
+        }
+
+        pub fn reorder(server_id: ServerId) -> Req {
+            Req::ServerAdmin(server_id).federated_only()
+        }
+
         pub fn delete(server_id: ServerId) -> Req {
             Req::ServerAdmin(server_id).federated_only()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_title_is_rejected() {
+        for title in ["", "   "] {
+            let update = ChannelUpdate {
+                title: Some(title.into()),
+                ..Default::default()
+            };
+            assert!(matches!(
+                normalize_update(&update),
+                Err(ApiError::BadRequest(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_partial_update_keeps_unset_fields() {
+        let update = ChannelUpdate {
+            title: Some(" general ".into()),
+            ..Default::default()
+        };
+        let normalized = normalize_update(&update).unwrap();
+        assert_eq!(normalized.title.as_deref(), Some("general"));
+        assert_eq!(normalized.description, None);
+        assert_eq!(
+            normalize_update(&ChannelUpdate::default()).unwrap(),
+            ChannelUpdate::default()
+        );
+    }
+
+    #[test]
+    fn test_slow_mode_must_be_in_range() {
+        for secs in [None, Some(0), Some(30), Some(MAX_SLOW_MODE_SECS)] {
+            assert!(validate_slow_mode(secs).is_ok());
+        }
+        for secs in [Some(-1), Some(MAX_SLOW_MODE_SECS + 1)] {
+            assert!(matches!(
+                validate_slow_mode(secs),
+                Err(ApiError::BadRequest(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use time::OffsetDateTime;
+
+use crate::{error::ApiResult, queries, state::AppState};
+
+/// Spawns the task that periodically deletes expired refresh tokens and
+/// stale cached remote servers and memberships.
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(state.config.cleanup_interval);
+        loop {
+            interval.tick().await;
+            if let Err(error) = run_once(&state).await {
+                log::warn!("Cleanup failed: {error}");
+            }
+        }
+    });
+}
+
+async fn run_once(state: &AppState) -> ApiResult<()> {
+    let host = state.config.public_host_with_explicit_port();
+    let tokens = queries::tokens::delete_expired(&state.db_pool).await?;
+    let synced_before =
+        OffsetDateTime::now_utc() - state.config.remote_cache_ttl;
+    let pruned =
+        queries::servers::prune_stale_remote(&state.db_pool, synced_before)
+            .await?;
+    if pruned.memberships > 0 || pruned.servers > 0 {
+        state.routing_index.invalidate_all().await;
+    }
+    if tokens > 0 || pruned.memberships > 0 || pruned.servers > 0 {
+        log::info!(
+            "{host}: removed {tokens} expired refresh tokens, {} stale remote \
+             memberships and {} stale remote servers",
+            pruned.memberships,
+            pruned.servers
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use runelink_types::{
+    channel::ChannelId,
+    message::{Message, MessageId, NewMessage},
+    user::{User, UserRef},
+    ws::{ClientWsUpdate, FederationWsReply, FederationWsRequest},
+};
+
+use super::{
+    federation,
+    messages::{DEFAULT_MESSAGE_PAGE_SIZE, MAX_MESSAGE_PAGE_SIZE},
+};
+use crate::{
+    auth::Session,
+    error::{ApiError, ApiResult},
+    queries, rate_limit,
+    state::AppState,
+};
+
+/// Get the direct message channel between the session user and another
+/// user, creating it on first use.
+pub async fn get_or_create_channel(
+    state: &AppState,
+    session: &Session,
+    other_user_ref: &UserRef,
+) -> ApiResult<ChannelId> {
+    let user_ref = session_user(session)?;
+    if user_ref == other_user_ref {
+        return Err(ApiError::BadRequest(
+            "Cannot direct message yourself".into(),
+        ));
+    }
+    queries::dms::get_or_create_channel(
+        &state.db_pool,
+        user_ref,
+        other_user_ref,
+    )
+    .await
+}
+
+/// Send a direct message from the session user.
+///
+/// Each host keeps its own copy of a conversation, so a message to a remote
+/// user is delivered to their home host first and then stored here.
+pub async fn send(
+    state: &AppState,
+    session: &Session,
+    to: &UserRef,
+    body: String,
+) -> ApiResult<Message> {
+    let from = session_user(session)?.clone();
+    if from == *to {
+        return Err(ApiError::BadRequest(
+            "Cannot direct message yourself".into(),
+        ));
+    }
+    let new_message = new_direct_message(&from, body)?;
+    rate_limit::check_message(&state.rate_limits, &from).await?;
+    if state.config.is_remote_host(Some(&to.host)) {
+        let sender =
+            queries::users::get_by_ref(&state.db_pool, from.clone()).await?;
+        let reply = federation::request(
+            state,
+            &to.host,
+            Some(from.clone()),
+            FederationWsRequest::DmSend {
+                from: sender,
+                to: to.clone(),
+                body: new_message.body.clone(),
+            },
+        )
+        .await?;
+        let FederationWsReply::DmSend(_) = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {} for dms.send",
+                to.host
+            )));
+        };
+    } else {
+        queries::users::get_by_ref(&state.db_pool, to.clone()).await?;
+    }
+    let channel_id = get_or_create_channel(state, session, to).await?;
+    deliver(state, channel_id, &new_message, to).await
+}
+
+/// Store a direct message sent to a local user from another host.
+pub async fn receive(
+    state: &AppState,
+    from_user: &User,
+    to: &UserRef,
+    body: String,
+) -> ApiResult<Message> {
+    if state.config.is_remote_host(Some(&to.host)) {
+        return Err(ApiError::BadRequest(
+            "Direct message recipient is not a user of this host".into(),
+        ));
+    }
+    let from = from_user.as_ref();
+    let new_message = new_direct_message(&from, body)?;
+    queries::users::get_by_ref(&state.db_pool, to.clone()).await?;
+    queries::users::upsert_remote(&state.db_pool, from_user).await?;
+    let channel_id =
+        queries::dms::get_or_create_channel(&state.db_pool, &from, to).await?;
+    deliver(state, channel_id, &new_message, to).await
+}
+
+/// Get the direct messages exchanged with another user, newest first.
+pub async fn get_history(
+    state: &AppState,
+    session: &Session,
+    with: &UserRef,
+    before: Option<MessageId>,
+    limit: Option<u32>,
+) -> ApiResult<Vec<Message>> {
+    let user_ref = session_user(session)?;
+    let Some(channel_id) =
+        queries::dms::get_channel(&state.db_pool, user_ref, with).await?
+    else {
+        return Ok(Vec::new());
+    };
+    let limit = limit
+        .unwrap_or(DEFAULT_MESSAGE_PAGE_SIZE)
+        .clamp(1, MAX_MESSAGE_PAGE_SIZE);
+    queries::messages::get_by_channel(
+        &state.db_pool,
+        channel_id,
+        before,
+        limit,
+        Some(user_ref),
+        true,
+    )
+    .await
+}
+
+/// Build a direct message from `from`, refusing bodies a channel message
+/// would not accept.
+fn new_direct_message(from: &UserRef, body: String) -> ApiResult<NewMessage> {
+    let new_message = NewMessage {
+        author: from.clone(),
+        body,
+        attachments: Vec::new(),
+    };
+    new_message
+        .validate()
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    Ok(new_message)
+}
+
+/// Store a message in a direct channel and push it to both participants'
+/// connections on this host.
+async fn deliver(
+    state: &AppState,
+    channel_id: ChannelId,
+    new_message: &NewMessage,
+    to: &UserRef,
+) -> ApiResult<Message> {
+    let message = queries::messages::insert(
+        &state.db_pool,
+        channel_id,
+        new_message,
+        false,
+    )
+    .await?;
+    let _ = state
+        .client_ws_manager
+        .send_update_to_users(
+            [&new_message.author, to],
+            ClientWsUpdate::MessageUpserted(message.clone()),
+        )
+        .await;
+    Ok(message)
+}
+
+fn session_user(session: &Session) -> ApiResult<&UserRef> {
+    session.user_ref.as_ref().ok_or_else(|| {
+        ApiError::Forbidden(
+            "User reference required for direct messages".into(),
+        )
+    })
+}
+
+/// Auth requirements for direct message operations.
+pub mod auth {
+    use super::*;
+    use crate::auth::{MESSAGES_READ, MESSAGES_WRITE, Requirement as Req};
+
+    pub fn send() -> Req {
+        Req::Client.with_scope(MESSAGES_WRITE)
+    }
+
+    pub fn get_history() -> Req {
+        Req::Client.with_scope(MESSAGES_READ)
+    }
+
+    pub mod federated {
+        use super::*;
+
+        pub fn send(from: UserRef) -> Req {
+            Req::FederatedUser(from).federated_only()
+        }
+    }
+}
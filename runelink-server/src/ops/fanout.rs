@@ -1,4 +1,5 @@
 use runelink_types::{
+    channel::ChannelId,
     server::ServerId,
     user::UserRef,
     ws::{ClientWsUpdate, FederationWsUpdate},
@@ -13,15 +14,17 @@ pub struct ServerFanoutTargets {
 }
 
 /// Resolve the targets for a server update.
+///
+/// Targets are cached briefly per server, so callers that change its
+/// members must invalidate them through the routing index first.
 pub async fn resolve_server_targets(
     state: &AppState,
     server_id: ServerId,
 ) -> ApiResult<ServerFanoutTargets> {
-    let local_users = state
+    let (local_users, remote_hosts) = state
         .routing_index
-        .users_for_local_server(server_id)
+        .targets_for_local_server(server_id)
         .await?;
-    let remote_hosts = state.routing_index.hosts_for_server(server_id).await?;
     Ok(ServerFanoutTargets {
         local_users,
         remote_hosts,
@@ -35,14 +38,126 @@ pub async fn fanout_update(
     client_update: ClientWsUpdate,
     federation_update: FederationWsUpdate,
 ) {
-    for user_ref in &targets.local_users {
-        let _ = state
-            .client_ws_manager
-            .send_update_to_user(user_ref, client_update.clone())
-            .await;
-    }
+    let _ = state
+        .client_ws_manager
+        .send_update_to_users(&targets.local_users, client_update)
+        .await;
     let _ = state
         .federation_ws_manager
         .send_update_to_hosts(targets.remote_hosts, federation_update)
         .await;
 }
+
+/// Fanout a channel message update to the given targets (best effort).
+///
+/// Local users only receive it on connections subscribed to the channel;
+/// remote hosts apply their own subscriptions.
+pub async fn fanout_channel_update(
+    state: &AppState,
+    targets: ServerFanoutTargets,
+    channel_id: ChannelId,
+    client_update: ClientWsUpdate,
+    federation_update: FederationWsUpdate,
+) {
+    let _ = state
+        .client_ws_manager
+        .send_update_to_subscribers(
+            &targets.local_users,
+            channel_id,
+            client_update,
+        )
+        .await;
+    let _ = state
+        .federation_ws_manager
+        .send_update_to_hosts(targets.remote_hosts, federation_update)
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use runelink_types::{
+        channel::ChannelId,
+        message::MessageId,
+        ws::{ClientWsEnvelope, ClientWsReplay},
+    };
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{db::DbPool, test_util};
+
+    #[sqlx::test]
+    async fn test_fanout_buffers_one_entry_for_all_members(pool: DbPool) {
+        let state = test_util::state(pool);
+        let server_id = ServerId::new();
+        let members = ["alice", "bob", "carol"]
+            .map(|name| UserRef::new(name.into(), test_util::HOST.into()));
+        let mut receivers = Vec::new();
+        for user in &members {
+            let (sender, receiver) = mpsc::channel(4);
+            let conn_id = state
+                .client_ws_manager
+                .register_connection(sender, IpAddr::V4(Ipv4Addr::LOCALHOST))
+                .await;
+            state
+                .client_ws_manager
+                .authenticate_connection(conn_id, user.clone())
+                .await;
+            receivers.push(receiver);
+        }
+
+        // An earlier update marks where the replay starts
+        state
+            .client_ws_manager
+            .send_update_to_users(
+                &members[..1],
+                ClientWsUpdate::MessageDeleted {
+                    server_id,
+                    channel_id: ChannelId::new(),
+                    message_id: MessageId::new(),
+                },
+            )
+            .await;
+        let Ok(ClientWsEnvelope::Update {
+            event_id: marker, ..
+        }) = receivers[0].try_recv()
+        else {
+            panic!("alice should receive the marker");
+        };
+
+        let targets = ServerFanoutTargets {
+            local_users: members.to_vec(),
+            remote_hosts: Vec::new(),
+        };
+        fanout_update(
+            &state,
+            targets,
+            ClientWsUpdate::ServerDeleted { server_id },
+            FederationWsUpdate::ServerDeleted { server_id },
+        )
+        .await;
+
+        let ClientWsReplay::Updates(replayed) = state
+            .client_ws_manager
+            .replay_since(server_id, marker)
+            .await
+        else {
+            panic!("expected buffered updates");
+        };
+        assert_eq!(replayed.len(), 1);
+        assert_eq!(
+            replayed[0].update,
+            ClientWsUpdate::ServerDeleted { server_id }
+        );
+        // Every member got the buffered event under the same id
+        for receiver in &mut receivers {
+            let Ok(ClientWsEnvelope::Update { event_id, .. }) =
+                receiver.try_recv()
+            else {
+                panic!("every member should receive the update");
+            };
+            assert_eq!(event_id, replayed[0].event_id);
+        }
+    }
+}
@@ -1,13 +1,23 @@
 use std::time::Duration;
 
+use log::warn;
+use runelink_client::util::get_api_url;
 use runelink_types::{
+    server::ServerId,
     user::UserRef,
     ws::{FederationWsReply, FederationWsRequest},
 };
 
-use crate::{error::ApiResult, state::AppState};
+use crate::{
+    error::{ApiError, ApiResult},
+    ids::ConnId,
+    queries,
+    state::AppState,
+};
 
 const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_ATTEMPTS: u32 = 2;
+const RETRY_DELAY: Duration = Duration::from_millis(250);
 
 pub(super) async fn request(
     state: &AppState,
@@ -15,15 +25,78 @@ pub(super) async fn request(
     delegated_user_ref: Option<UserRef>,
     request: FederationWsRequest,
 ) -> ApiResult<FederationWsReply> {
-    let request_future =
-        Box::pin(state.federation_ws_manager.send_request_to_host(
-            state,
-            host,
-            delegated_user_ref,
-            request,
-            REQUEST_TIMEOUT,
-        ));
-    request_future
+    let mut attempt = 1;
+    loop {
+        let request_future =
+            Box::pin(state.federation_ws_manager.send_request_to_host(
+                state,
+                host,
+                delegated_user_ref.clone(),
+                request.clone(),
+                REQUEST_TIMEOUT,
+            ));
+        match request_future.await {
+            Ok(reply) => return Ok(reply),
+            Err(error) if error.is_retryable() && attempt < MAX_ATTEMPTS => {
+                warn!(
+                    "Federation request to {host} failed (attempt {attempt}), retrying: {error}"
+                );
+                attempt += 1;
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+            Err(error) => return Err(error.into_api_error(host)),
+        }
+    }
+}
+
+/// Checks that an update about a member of a server came from a host that
+/// may report it: the user's home host for a server hosted here, otherwise
+/// the server's host. Returns whether the server is hosted here.
+pub(super) async fn verify_member_update_source(
+    state: &AppState,
+    conn_id: ConnId,
+    server_id: ServerId,
+    user_ref: &UserRef,
+) -> ApiResult<bool> {
+    let Some(issuer) = state
+        .federation_ws_manager
+        .authenticated_issuer(conn_id)
         .await
-        .map_err(|error| error.into_api_error(host))
+    else {
+        return Err(ApiError::Unauthorized(
+            "Member updates require an authenticated host".into(),
+        ));
+    };
+    let is_local = queries::servers::exists(&state.db_pool, server_id).await?;
+    let authoritative_hosts = if is_local {
+        vec![user_ref.host.clone()]
+    } else {
+        queries::servers::get_cached_remote_refs(
+            &state.db_pool,
+            Some(server_id),
+            None,
+        )
+        .await?
+        .into_iter()
+        .map(|(_, host)| host)
+        .collect()
+    };
+    if !authoritative_hosts
+        .iter()
+        .any(|host| get_api_url(host, state.config.secure) == issuer)
+    {
+        return Err(ApiError::Forbidden(format!(
+            "Update about {user_ref} in server {server_id} was not reported \
+             by an authoritative host"
+        )));
+    }
+    if is_local {
+        queries::memberships::get_local_member_by_user_and_server(
+            &state.db_pool,
+            server_id,
+            user_ref.clone(),
+        )
+        .await?;
+    }
+    Ok(is_local)
 }
@@ -0,0 +1,155 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand::{RngCore, rngs::OsRng};
+use runelink_types::{
+    server::{NewServerInvite, ServerId, ServerInvite},
+    ws::{FederationWsReply, FederationWsRequest},
+};
+use time::OffsetDateTime;
+
+use super::federation;
+use crate::{
+    auth::Session,
+    error::{ApiError, ApiResult},
+    queries,
+    state::AppState,
+};
+
+/// Number of random bytes in an invite code.
+const INVITE_CODE_BYTES: usize = 16;
+
+/// Create an invite code for a server.
+pub async fn create(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    new_invite: &NewServerInvite,
+    target_host: Option<&str>,
+) -> ApiResult<ServerInvite> {
+    let created_by = session.user_ref.clone().ok_or_else(|| {
+        ApiError::Forbidden("User reference required for invites".into())
+    })?;
+    if !state.config.is_remote_host(target_host) {
+        // Handle local case
+        validate_new_invite(new_invite, OffsetDateTime::now_utc())?;
+        queries::servers::get_by_id(state, server_id).await?;
+        let invite = queries::invites::insert(
+            state,
+            &generate_code(),
+            server_id,
+            &created_by,
+            new_invite,
+        )
+        .await?;
+        Ok(invite)
+    } else {
+        // Create on the server's home host, acting as the session user
+        let host = target_host.unwrap();
+        let reply = federation::request(
+            state,
+            host,
+            Some(created_by),
+            FederationWsRequest::InvitesCreate {
+                server_id,
+                new_invite: new_invite.clone(),
+            },
+        )
+        .await?;
+        let FederationWsReply::InvitesCreate(invite) = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for invites.create"
+            )));
+        };
+        Ok(invite)
+    }
+}
+
+fn validate_new_invite(
+    new_invite: &NewServerInvite,
+    now: OffsetDateTime,
+) -> ApiResult<()> {
+    if let Some(max_uses) = new_invite.max_uses
+        && (max_uses == 0 || max_uses > i32::MAX as u32)
+    {
+        return Err(ApiError::BadRequest(format!(
+            "Invite max_uses must be between 1 and {}",
+            i32::MAX
+        )));
+    }
+    if let Some(expires_at) = new_invite.expires_at
+        && expires_at <= now
+    {
+        return Err(ApiError::BadRequest(
+            "Invite expiry must be in the future".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn generate_code() -> String {
+    let mut bytes = [0u8; INVITE_CODE_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Auth requirements for invite operations.
+pub mod auth {
+    use super::*;
+    use crate::auth::Requirement as Req;
+
+    pub fn create(server_id: ServerId) -> Req {
+        Req::ServerAdmin(server_id).or_admin().client_only()
+    }
+
+    pub mod federated {
+        use super::*;
+
+        pub fn create(server_id: ServerId) -> Req {
+            Req::ServerAdmin(server_id).federated_only()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Duration;
+
+    #[test]
+    fn test_invite_limits_must_be_usable() {
+        let now = OffsetDateTime::now_utc();
+        let zero_uses = NewServerInvite {
+            max_uses: Some(0),
+            ..Default::default()
+        };
+        assert!(matches!(
+            validate_new_invite(&zero_uses, now),
+            Err(ApiError::BadRequest(_))
+        ));
+        let already_expired = NewServerInvite {
+            expires_at: Some(now - Duration::seconds(1)),
+            ..Default::default()
+        };
+        assert!(matches!(
+            validate_new_invite(&already_expired, now),
+            Err(ApiError::BadRequest(_))
+        ));
+        let limited = NewServerInvite {
+            expires_at: Some(now + Duration::days(1)),
+            max_uses: Some(5),
+        };
+        assert!(validate_new_invite(&limited, now).is_ok());
+        assert!(validate_new_invite(&NewServerInvite::default(), now).is_ok());
+    }
+
+    #[test]
+    fn test_generated_codes_are_url_safe_and_distinct() {
+        let first = generate_code();
+        let second = generate_code();
+        assert_ne!(first, second);
+        assert!(
+            first
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        );
+    }
+}
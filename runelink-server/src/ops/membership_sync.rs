@@ -0,0 +1,267 @@
+use std::time::Duration;
+
+use runelink_types::{
+    server::{ServerId, ServerMembership},
+    user::UserRef,
+    ws::{ClientWsUpdate, FederationWsReply, FederationWsRequest},
+};
+
+use super::federation;
+use crate::{
+    error::{ApiError, ApiResult},
+    queries,
+    state::AppState,
+};
+
+/// Spawns the task that periodically reconciles local users' cached remote
+/// memberships with the hosts of those servers, catching up on membership
+/// updates that were missed.
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(next_delay(&state)).await;
+            if let Err(error) = resync_all(&state).await {
+                log::warn!("Membership resync failed: {error}");
+            }
+        }
+    });
+}
+
+fn next_delay(state: &AppState) -> Duration {
+    let jitter = state
+        .config
+        .membership_resync_jitter
+        .mul_f64(rand::random::<f64>());
+    state.config.membership_resync_interval + jitter
+}
+
+async fn resync_all(state: &AppState) -> ApiResult<()> {
+    let users = queries::memberships::get_user_refs_with_remote_memberships(
+        &state.db_pool,
+    )
+    .await?;
+    let (mut upserted, mut removed) = (0, 0);
+    for user_ref in users {
+        let hosts = queries::memberships::get_remote_server_hosts_for_user(
+            &state.db_pool,
+            user_ref.clone(),
+        )
+        .await?;
+        for host in hosts {
+            match resync_user_on_host(state, &user_ref, &host).await {
+                Ok(changes) => {
+                    upserted += changes.0;
+                    removed += changes.1;
+                }
+                Err(error) => log::warn!(
+                    "Failed to resync memberships of {user_ref} on {host}: {error}"
+                ),
+            }
+        }
+    }
+    if upserted > 0 || removed > 0 {
+        log::info!(
+            "{}: membership resync updated {upserted} and removed {removed} \
+             remote memberships",
+            state.config.public_host_with_explicit_port()
+        );
+    }
+    Ok(())
+}
+
+/// Replaces a user's cached memberships in `host`'s servers with what
+/// `host` reports, telling the user's clients about each change.
+///
+/// Returns how many memberships were updated and removed.
+async fn resync_user_on_host(
+    state: &AppState,
+    user_ref: &UserRef,
+    host: &str,
+) -> ApiResult<(usize, usize)> {
+    let reply = federation::request(
+        state,
+        host,
+        None,
+        FederationWsRequest::MembershipsGetByUser {
+            user_ref: user_ref.clone(),
+        },
+    )
+    .await?;
+    let FederationWsReply::MembershipsGetByUser(memberships) = reply else {
+        return Err(ApiError::Internal(format!(
+            "Unexpected federation reply from {host} for memberships.get_by_user"
+        )));
+    };
+    let fresh = memberships
+        .into_iter()
+        .filter(|membership| {
+            membership.server.host == host && membership.user_ref == *user_ref
+        })
+        .collect::<Vec<_>>();
+    let cached = queries::memberships::get_by_user(state, user_ref.clone())
+        .await?
+        .into_iter()
+        .filter(|membership| membership.server.host == host)
+        .collect::<Vec<_>>();
+    let (changed, removed) = diff_memberships(&cached, &fresh);
+
+    let user = if changed.is_empty() {
+        None
+    } else {
+        Some(
+            queries::users::get_by_ref(&state.db_pool, user_ref.clone())
+                .await?,
+        )
+    };
+    // Every fresh row is rewritten so its synced_at moves forward
+    for membership in &fresh {
+        queries::servers::upsert_remote(&state.db_pool, &membership.server)
+            .await?;
+        let cached =
+            queries::memberships::insert_remote(&state.db_pool, membership)
+                .await?;
+        state
+            .routing_index
+            .invalidate_server(membership.server.id)
+            .await;
+        if let Some(user) = &user
+            && changed.contains(&membership.server.id)
+        {
+            notify_local_members(
+                state,
+                membership.server.id,
+                user_ref,
+                ClientWsUpdate::MembershipUpserted(
+                    cached.as_full(user.clone()),
+                ),
+            )
+            .await?;
+        }
+    }
+    for &server_id in &removed {
+        match queries::memberships::delete_remote(
+            &state.db_pool,
+            server_id,
+            user_ref.clone(),
+        )
+        .await
+        {
+            // Already gone, e.g. the user left meanwhile
+            Ok(()) | Err(ApiError::NotFound) => {}
+            Err(error) => return Err(error),
+        }
+        state.routing_index.invalidate_server(server_id).await;
+        notify_local_members(
+            state,
+            server_id,
+            user_ref,
+            ClientWsUpdate::MembershipDeleted {
+                server_id,
+                user_ref: user_ref.clone(),
+            },
+        )
+        .await?;
+    }
+    Ok((changed.len(), removed.len()))
+}
+
+/// Sends a caught-up membership change of `user_ref` once to the local
+/// members of the server and the user, as if its federation update had
+/// arrived.
+async fn notify_local_members(
+    state: &AppState,
+    server_id: ServerId,
+    user_ref: &UserRef,
+    update: ClientWsUpdate,
+) -> ApiResult<()> {
+    let mut targets = state
+        .routing_index
+        .users_for_remote_server(server_id)
+        .await?;
+    if !targets.contains(user_ref) {
+        targets.push(user_ref.clone());
+    }
+    let _ = state
+        .client_ws_manager
+        .send_update_to_users(targets, update)
+        .await;
+    Ok(())
+}
+
+/// Servers whose membership is new or differs from the cached one, and
+/// servers whose cached membership no longer exists upstream.
+fn diff_memberships(
+    cached: &[ServerMembership],
+    fresh: &[ServerMembership],
+) -> (Vec<ServerId>, Vec<ServerId>) {
+    let changed = fresh
+        .iter()
+        .filter(|membership| {
+            !cached.iter().any(|cached| {
+                cached.server == membership.server
+                    && cached.role == membership.role
+                    && cached.updated_at == membership.updated_at
+            })
+        })
+        .map(|membership| membership.server.id)
+        .collect();
+    let removed = cached
+        .iter()
+        .filter(|cached| {
+            !fresh
+                .iter()
+                .any(|membership| membership.server.id == cached.server.id)
+        })
+        .map(|cached| cached.server.id)
+        .collect();
+    (changed, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use runelink_types::server::{Server, ServerRole, ServerVisibility};
+    use time::OffsetDateTime;
+
+    use super::*;
+
+    fn membership() -> ServerMembership {
+        let now = OffsetDateTime::UNIX_EPOCH;
+        ServerMembership {
+            server: Server {
+                id: ServerId::new(),
+                host: "remote.example".into(),
+                title: "Remote".into(),
+                description: None,
+                visibility: ServerVisibility::Public,
+                created_at: now,
+                updated_at: now,
+            },
+            user_ref: UserRef::new("ada".into(), "local.example".into()),
+            role: ServerRole::Member,
+            joined_at: now,
+            updated_at: now,
+            synced_at: None,
+        }
+    }
+
+    #[test]
+    fn test_diff_memberships() {
+        let kept = membership();
+        let promoted = membership();
+        let left = membership();
+        let joined = membership();
+
+        let cached = vec![kept.clone(), promoted.clone(), left.clone()];
+        let fresh = vec![
+            kept,
+            ServerMembership {
+                role: ServerRole::Admin,
+                ..promoted.clone()
+            },
+            joined.clone(),
+        ];
+        let (changed, removed) = diff_memberships(&cached, &fresh);
+        assert_eq!(changed, vec![promoted.server.id, joined.server.id]);
+        assert_eq!(removed, vec![left.server.id]);
+    }
+}
@@ -1,7 +1,8 @@
 use runelink_types::{
     server::{
-        FullServerMembership, NewServerMembership, ServerId, ServerMember,
-        ServerMembership,
+        BulkMembershipEntry, BulkMembershipResult, FullServerMembership,
+        NewServerMembership, ServerId, ServerMember, ServerMembership,
+        ServerRole,
     },
     user::{User, UserRef},
     ws::{
@@ -10,7 +11,7 @@ use runelink_types::{
     },
 };
 
-use super::{federation, users};
+use super::{federation, messages, users};
 use crate::{
     auth::Session,
     error::{ApiError, ApiResult},
@@ -22,7 +23,7 @@ use crate::{
 /// Create a new membership for a user in a server.
 pub async fn upsert(
     state: &AppState,
-    _session: &mut Session,
+    session: &mut Session,
     new_membership: &NewServerMembership,
     remote_user: Option<&User>,
 ) -> ApiResult<FullServerMembership> {
@@ -42,10 +43,11 @@ pub async fn upsert(
         let user =
             users::get_by_ref(state, new_membership.user_ref.clone(), None)
                 .await?;
+        // Delegate as the acting admin; the remote host checks their role
         let reply = federation::request(
             state,
             host,
-            Some(new_membership.user_ref.clone()),
+            session.user_ref.clone(),
             FederationWsRequest::MembershipsUpsert {
                 new_membership: new_membership.clone().as_full(user),
             },
@@ -65,35 +67,153 @@ pub async fn upsert(
             &membership.into(),
         )
         .await?;
+        state
+            .routing_index
+            .invalidate_server(new_membership.server_id)
+            .await;
         // synced_at comes from cached membership
         return Ok(cached_membership.as_full(user));
     }
 
-    if new_membership.user_ref.host != state.config.public_host() {
-        let remote_user = match remote_user {
-            Some(user) => user.clone(),
-            None => {
-                users::get_by_ref(state, new_membership.user_ref.clone(), None)
-                    .await?
-            }
-        };
-        if remote_user.as_ref() != new_membership.user_ref {
-            return Err(ApiError::BadRequest(
-                "User payload does not match membership user_ref".into(),
-            ));
-        }
-        queries::users::upsert_remote(&state.db_pool, &remote_user).await?;
-    }
+    ensure_not_banned(
+        state,
+        new_membership.server_id,
+        &new_membership.user_ref,
+    )
+    .await?;
+    cache_remote_user(state, &new_membership.user_ref, remote_user).await?;
 
     // Create the membership
-    let member =
-        queries::memberships::upsert_local(&state.db_pool, new_membership)
-            .await?;
-    let membership = queries::memberships::get_local_by_user_and_server(
+    queries::memberships::upsert_local(&state.db_pool, new_membership).await?;
+    state
+        .routing_index
+        .invalidate_server(new_membership.server_id)
+        .await;
+    announce_local_membership(
         state,
         new_membership.server_id,
         new_membership.user_ref.clone(),
     )
+    .await
+}
+
+/// Join a server as a member by redeeming one of its invite codes.
+pub async fn create_via_invite(
+    state: &AppState,
+    session: &Session,
+    code: &str,
+    remote_user: Option<&User>,
+    target_host: Option<&str>,
+) -> ApiResult<FullServerMembership> {
+    let user_ref = session.user_ref.clone().ok_or_else(|| {
+        ApiError::Forbidden("User reference required for joining server".into())
+    })?;
+
+    if state.config.is_remote_host(target_host) {
+        // Redeem on the server's home host and cache the result locally
+        let host = target_host.unwrap();
+        let user = users::get_by_ref(state, user_ref.clone(), None).await?;
+        let reply = federation::request(
+            state,
+            host,
+            Some(user_ref),
+            FederationWsRequest::InvitesRedeem {
+                code: code.to_string(),
+                user,
+            },
+        )
+        .await?;
+        let FederationWsReply::InvitesRedeem(membership) = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for invites.redeem"
+            )));
+        };
+        let user = membership.user.clone();
+        let server_id = membership.server.id;
+        queries::servers::upsert_remote(&state.db_pool, &membership.server)
+            .await?;
+        let cached_membership = queries::memberships::insert_remote(
+            &state.db_pool,
+            &membership.into(),
+        )
+        .await?;
+        state.routing_index.invalidate_server(server_id).await;
+        return Ok(cached_membership.as_full(user));
+    }
+
+    cache_remote_user(state, &user_ref, remote_user).await?;
+    let server_id =
+        queries::invites::redeem(&state.db_pool, code, &user_ref).await?;
+    state.routing_index.invalidate_server(server_id).await;
+    let membership =
+        announce_local_membership(state, server_id, user_ref.clone()).await?;
+    welcome(state, server_id, &user_ref).await;
+    Ok(membership)
+}
+
+/// Greet a user who just joined in the server's first channel. A failed
+/// greeting shouldn't fail the join, so errors are only logged.
+async fn welcome(state: &AppState, server_id: ServerId, user_ref: &UserRef) {
+    let result = async {
+        let channels =
+            queries::channels::get_by_server(&state.db_pool, server_id).await?;
+        let Some(channel) = channels.first() else {
+            return Ok(());
+        };
+        messages::create_system(
+            state,
+            channel.id,
+            format!("Welcome {user_ref}!"),
+        )
+        .await
+        .map(|_| ())
+    }
+    .await;
+    if let Err(error) = result {
+        log::warn!(
+            "Failed to welcome {user_ref} to server {server_id}: {error}"
+        );
+    }
+}
+
+/// Make sure a remote user joining a local server has a local user record,
+/// using the payload their host sent when there is one.
+async fn cache_remote_user(
+    state: &AppState,
+    user_ref: &UserRef,
+    remote_user: Option<&User>,
+) -> ApiResult<()> {
+    if user_ref.host == state.config.public_host() {
+        return Ok(());
+    }
+    let remote_user = match remote_user {
+        Some(user) => user.clone(),
+        None => users::get_by_ref(state, user_ref.clone(), None).await?,
+    };
+    if remote_user.as_ref() != *user_ref {
+        return Err(ApiError::BadRequest(
+            "User payload does not match membership user_ref".into(),
+        ));
+    }
+    queries::users::upsert_remote(&state.db_pool, &remote_user).await?;
+    Ok(())
+}
+
+/// Load a freshly written local membership and fan it out to the server.
+pub(super) async fn announce_local_membership(
+    state: &AppState,
+    server_id: ServerId,
+    user_ref: UserRef,
+) -> ApiResult<FullServerMembership> {
+    let member = queries::memberships::get_local_member_by_user_and_server(
+        &state.db_pool,
+        server_id,
+        user_ref.clone(),
+    )
+    .await?;
+    let membership = queries::memberships::get_local_by_user_and_server(
+        state, server_id, user_ref,
+    )
     .await?;
     let full_membership = FullServerMembership {
         server: membership.server,
@@ -105,7 +225,7 @@ pub async fn upsert(
     };
     fanout::fanout_update(
         state,
-        fanout::resolve_server_targets(state, new_membership.server_id).await?,
+        fanout::resolve_server_targets(state, server_id).await?,
         ClientWsUpdate::MembershipUpserted(full_membership.clone()),
         FederationWsUpdate::MembershipUpserted(full_membership.clone()),
     )
@@ -113,6 +233,119 @@ pub async fn upsert(
     Ok(full_membership)
 }
 
+/// Maximum number of entries accepted by a single bulk import.
+pub const MAX_BULK_MEMBERSHIPS: usize = 500;
+
+/// Import many memberships into a local server at once.
+///
+/// Every user is resolved first (remote users via federation, cached like
+/// `upsert` does); entries that can't be resolved are reported as errors and
+/// skipped. The remaining memberships are written in one transaction.
+pub async fn import_bulk(
+    state: &AppState,
+    server_id: ServerId,
+    entries: &[BulkMembershipEntry],
+) -> ApiResult<Vec<BulkMembershipResult>> {
+    if entries.len() > MAX_BULK_MEMBERSHIPS {
+        return Err(ApiError::BadRequest(format!(
+            "Bulk import is limited to {MAX_BULK_MEMBERSHIPS} memberships"
+        )));
+    }
+    // Make sure the server exists locally before resolving anyone
+    queries::servers::get_by_id(state, server_id).await?;
+
+    let mut results = Vec::with_capacity(entries.len());
+    let mut resolved = Vec::new();
+    for entry in entries {
+        if let Err(error) =
+            ensure_not_banned(state, server_id, &entry.user_ref).await
+        {
+            results.push(failed_entry(entry, error));
+            continue;
+        }
+        let user = match users::get_by_ref(state, entry.user_ref.clone(), None)
+            .await
+        {
+            Ok(user) => user,
+            Err(error) => {
+                results.push(failed_entry(entry, error));
+                continue;
+            }
+        };
+        if user.as_ref() != entry.user_ref {
+            let error = ApiError::BadRequest(
+                "Resolved user does not match user_ref".into(),
+            );
+            results.push(failed_entry(entry, error));
+            continue;
+        }
+        if state.config.is_remote_host(Some(&user.host))
+            && let Err(error) =
+                queries::users::upsert_remote(&state.db_pool, &user).await
+        {
+            results.push(failed_entry(entry, error));
+            continue;
+        }
+        resolved.push(entry.clone());
+    }
+
+    queries::memberships::insert_local_many(
+        &state.db_pool,
+        server_id,
+        &resolved,
+    )
+    .await?;
+    state.routing_index.invalidate_server(server_id).await;
+
+    let targets = fanout::resolve_server_targets(state, server_id).await?;
+    for entry in resolved {
+        let membership = queries::memberships::get_local_by_user_and_server(
+            state,
+            server_id,
+            entry.user_ref.clone(),
+        )
+        .await?;
+        let member = queries::memberships::get_local_member_by_user_and_server(
+            &state.db_pool,
+            server_id,
+            entry.user_ref.clone(),
+        )
+        .await?;
+        let full_membership = FullServerMembership {
+            server: membership.server,
+            user: member.user,
+            role: membership.role,
+            joined_at: membership.joined_at,
+            updated_at: membership.updated_at,
+            synced_at: membership.synced_at,
+        };
+        fanout::fanout_update(
+            state,
+            targets.clone(),
+            ClientWsUpdate::MembershipUpserted(full_membership.clone()),
+            FederationWsUpdate::MembershipUpserted(full_membership.clone()),
+        )
+        .await;
+        results.push(BulkMembershipResult {
+            user_ref: entry.user_ref,
+            membership: Some(full_membership),
+            error: None,
+        });
+    }
+    Ok(results)
+}
+
+fn failed_entry(
+    entry: &BulkMembershipEntry,
+    error: ApiError,
+) -> BulkMembershipResult {
+    BulkMembershipResult {
+        user_ref: entry.user_ref.clone(),
+        membership: None,
+        error: Some(error.to_string()),
+    }
+}
+
 /// Get all members of a server (public).
 pub async fn get_members_by_server(
     state: &AppState,
@@ -205,102 +438,364 @@ pub async fn delete(
     target_host: Option<&str>,
 ) -> ApiResult<()> {
     let _session_user_ref = session.user_ref.clone().ok_or_else(|| {
-        ApiError::AuthError("User reference required for leaving server".into())
+        ApiError::Forbidden("User reference required for leaving server".into())
     })?;
 
     // Handle local case
     if !state.config.is_remote_host(target_host) {
-        let mut targets =
-            fanout::resolve_server_targets(state, server_id).await?;
-        if !targets.local_users.contains(&user_ref) {
-            targets.local_users.push(user_ref.clone());
-        }
-        if user_ref.host != state.config.public_host() {
-            targets.remote_hosts.push(user_ref.host.clone());
-        }
-        // Verify the membership exists
-        queries::memberships::get_local_member_by_user_and_server(
+        remove_local_member(state, server_id, user_ref).await
+    } else {
+        // Delete on remote host using federation
+        let host = target_host.unwrap();
+        let reply = federation::request(
+            state,
+            host,
+            Some(user_ref.clone()),
+            FederationWsRequest::MembershipsDelete {
+                server_id,
+                user_ref: user_ref.clone(),
+            },
+        )
+        .await?;
+        let FederationWsReply::MembershipsDelete = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for memberships.delete"
+            )));
+        };
+        // Also delete from local cache if it exists
+        let _ = queries::memberships::delete_remote(
             &state.db_pool,
             server_id,
-            user_ref.clone(),
+            user_ref,
         )
-        .await?;
-        queries::memberships::delete_local(
+        .await;
+        state.routing_index.invalidate_server(server_id).await;
+        Ok(())
+    }
+}
+
+/// Promote or demote a server member.
+pub async fn update_role(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    user_ref: UserRef,
+    role: ServerRole,
+    target_host: Option<&str>,
+) -> ApiResult<FullServerMembership> {
+    // Handle local case
+    if !state.config.is_remote_host(target_host) {
+        queries::memberships::update_role(
             &state.db_pool,
             server_id,
-            user_ref.clone(),
+            &user_ref,
+            role,
         )
         .await?;
-        fanout::fanout_update(
+        announce_local_membership(state, server_id, user_ref).await
+    } else {
+        // Update on remote host, acting as the session user
+        let host = target_host.unwrap();
+        let reply = federation::request(
             state,
-            targets,
-            ClientWsUpdate::MembershipDeleted {
+            host,
+            session.user_ref.clone(),
+            FederationWsRequest::MembershipsUpdateRole {
                 server_id,
                 user_ref: user_ref.clone(),
-            },
-            FederationWsUpdate::MembershipDeleted {
-                server_id,
-                user_ref,
+                role,
             },
         )
-        .await;
-        Ok(())
+        .await?;
+        let FederationWsReply::MembershipsUpdateRole(membership) = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for memberships.update_role"
+            )));
+        };
+        // Keep the local cache in step when the member is one of ours
+        if user_ref.host == state.config.public_host() {
+            queries::servers::upsert_remote(&state.db_pool, &membership.server)
+                .await?;
+            queries::memberships::insert_remote(
+                &state.db_pool,
+                &membership.clone().into(),
+            )
+            .await?;
+            state.routing_index.invalidate_server(server_id).await;
+        }
+        Ok(membership)
+    }
+}
+
+/// Remove another user from a server. Unlike `delete`, the target need
+/// not be the caller.
+pub async fn remove_member(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    user_ref: UserRef,
+    target_host: Option<&str>,
+) -> ApiResult<()> {
+    // Handle local case
+    if !state.config.is_remote_host(target_host) {
+        remove_local_member(state, server_id, user_ref).await
     } else {
-        // Delete on remote host using federation
+        // Remove on remote host, acting as the session user
         let host = target_host.unwrap();
         let reply = federation::request(
             state,
             host,
-            Some(user_ref.clone()),
-            FederationWsRequest::MembershipsDelete {
+            session.user_ref.clone(),
+            FederationWsRequest::MembershipsRemove {
                 server_id,
                 user_ref: user_ref.clone(),
             },
         )
         .await?;
-        let FederationWsReply::MembershipsDelete = reply else {
+        let FederationWsReply::MembershipsRemove = reply else {
             return Err(ApiError::Internal(format!(
-                "Unexpected federation reply from {host} for memberships.delete"
+                "Unexpected federation reply from {host} for memberships.remove"
             )));
         };
-        // Also delete from local cache if it exists
         let _ = queries::memberships::delete_remote(
             &state.db_pool,
             server_id,
             user_ref,
         )
         .await;
+        state.routing_index.invalidate_server(server_id).await;
         Ok(())
     }
 }
 
+/// Delete a local membership and tell the server, the removed user, and
+/// the removed user's home host.
+pub(super) async fn remove_local_member(
+    state: &AppState,
+    server_id: ServerId,
+    user_ref: UserRef,
+) -> ApiResult<()> {
+    let mut targets = fanout::resolve_server_targets(state, server_id).await?;
+    if !targets.local_users.contains(&user_ref) {
+        targets.local_users.push(user_ref.clone());
+    }
+    if user_ref.host != state.config.public_host() {
+        targets.remote_hosts.push(user_ref.host.clone());
+    }
+    // Verify the membership exists
+    queries::memberships::get_local_member_by_user_and_server(
+        &state.db_pool,
+        server_id,
+        user_ref.clone(),
+    )
+    .await?;
+    queries::memberships::delete_local(
+        &state.db_pool,
+        server_id,
+        user_ref.clone(),
+    )
+    .await?;
+    state.routing_index.invalidate_server(server_id).await;
+    fanout::fanout_update(
+        state,
+        targets,
+        ClientWsUpdate::MembershipDeleted {
+            server_id,
+            user_ref: user_ref.clone(),
+        },
+        FederationWsUpdate::MembershipDeleted {
+            server_id,
+            user_ref,
+        },
+    )
+    .await;
+    Ok(())
+}
+
+/// Reject users banned from a local server.
+async fn ensure_not_banned(
+    state: &AppState,
+    server_id: ServerId,
+    user_ref: &UserRef,
+) -> ApiResult<()> {
+    if queries::bans::exists(&state.db_pool, server_id, user_ref).await? {
+        return Err(ApiError::Forbidden(
+            "User is banned from this server".into(),
+        ));
+    }
+    Ok(())
+}
+
 /// Auth requirements for membership operations.
 pub mod auth {
     use super::*;
+    use crate::and;
     use crate::auth::Requirement as Req;
-    use crate::or;
 
-    pub fn create(_server_id: ServerId) -> Req {
-        // TODO: make this admin only and create an invite system
-        // Servers should also be public or private
-        Req::Always.or_admin().client_only()
+    /// Adding members directly is for admins; everyone else joins through
+    /// an invite.
+    pub fn create(server_id: ServerId) -> Req {
+        Req::ServerAdmin(server_id).or_admin().client_only()
+    }
+
+    pub fn create_via_invite() -> Req {
+        Req::Client
     }
 
-    pub fn delete(server_id: ServerId, user_ref: UserRef) -> Req {
-        or!(Req::User(user_ref), Req::ServerAdmin(server_id))
-            .or_admin()
-            .client_only()
+    pub fn import_bulk(server_id: ServerId) -> Req {
+        Req::ServerAdmin(server_id).or_admin().client_only()
+    }
+
+    /// Leaving is for the member themself; admins use `remove_member`.
+    pub fn delete(_server_id: ServerId, user_ref: UserRef) -> Req {
+        Req::User(user_ref).or_admin().client_only()
+    }
+
+    pub fn remove_member(server_id: ServerId) -> Req {
+        Req::ServerAdmin(server_id).or_admin().client_only()
+    }
+
+    pub fn update_role(server_id: ServerId) -> Req {
+        Req::ServerAdmin(server_id).or_admin().client_only()
     }
 
     pub mod federated {
         use super::*;
 
-        pub fn create(_server_id: ServerId, user_ref: UserRef) -> Req {
+        /// `admin_ref` is the delegated user adding the member.
+        pub fn create(server_id: ServerId, admin_ref: UserRef) -> Req {
+            and!(Req::FederatedUser(admin_ref), Req::ServerAdmin(server_id))
+                .federated_only()
+        }
+
+        pub fn create_via_invite(user_ref: UserRef) -> Req {
             Req::FederatedUser(user_ref).federated_only()
         }
 
         pub fn delete(_server_id: ServerId, user_ref: UserRef) -> Req {
             Req::FederatedUser(user_ref).federated_only()
         }
+
+        pub fn update_role(server_id: ServerId) -> Req {
+            Req::ServerAdmin(server_id).federated_only()
+        }
+
+        pub fn remove_member(server_id: ServerId) -> Req {
+            Req::ServerAdmin(server_id).federated_only()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use runelink_types::{
+        server::{BulkMembershipEntry, NewServerInvite},
+        user::UserRole,
+    };
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::{db::DbPool, test_util};
+
+    #[sqlx::test]
+    async fn test_joining_via_invite_posts_a_welcome(pool: DbPool) {
+        let state = test_util::state(pool);
+        let owner = test_util::local_user(&state, "owner").await;
+        let joiner = test_util::local_user(&state, "joiner").await;
+        let server = test_util::server(&state, &owner.as_ref(), "Guild").await;
+        let channel = test_util::channel(&state, server.id, "general").await;
+        let new_invite = NewServerInvite {
+            expires_at: None,
+            max_uses: None,
+        };
+        queries::invites::insert(
+            &state,
+            "welcome",
+            server.id,
+            &owner.as_ref(),
+            &new_invite,
+        )
+        .await
+        .unwrap();
+
+        let session = test_util::session(&state, &joiner.as_ref()).await;
+        create_via_invite(&state, &session, "welcome", None, None)
+            .await
+            .unwrap();
+
+        let messages = queries::messages::get_by_channel(
+            &state.db_pool,
+            channel.id,
+            None,
+            10,
+            None,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].system);
+        let author = messages[0].author.as_ref().unwrap();
+        assert!(author.as_ref().is_system());
+        assert_eq!(messages[0].body, "Welcome joiner@localhost!");
+    }
+
+    #[sqlx::test]
+    async fn test_bulk_import_mixes_local_and_remote_users(pool: DbPool) {
+        let state = test_util::state(pool);
+        let owner = test_util::local_user(&state, "owner").await.as_ref();
+        let alice = test_util::local_user(&state, "alice").await.as_ref();
+        let server = test_util::server(&state, &owner, "Guild").await;
+        let remote_host = test_util::federation_peer(|request| {
+            let FederationWsRequest::UsersGetByRef { user_ref } = request
+            else {
+                return None;
+            };
+            let now = OffsetDateTime::now_utc();
+            Some(FederationWsReply::UsersGetByRef(User {
+                name: user_ref.name,
+                host: user_ref.host,
+                role: UserRole::User,
+                display_name: None,
+                avatar_url: None,
+                created_at: now,
+                updated_at: now,
+                synced_at: None,
+            }))
+        })
+        .await;
+        let bob = UserRef::new("bob".into(), remote_host);
+        let ghost = UserRef::new("ghost".into(), test_util::HOST.into());
+        let entries = [
+            (alice.clone(), ServerRole::Member),
+            (bob.clone(), ServerRole::Admin),
+            (ghost.clone(), ServerRole::Member),
+        ]
+        .map(|(user_ref, role)| BulkMembershipEntry { user_ref, role });
+
+        let results = import_bulk(&state, server.id, &entries).await.unwrap();
+        assert_eq!(results.len(), 3);
+        let result = |user_ref: &UserRef| {
+            results.iter().find(|r| &r.user_ref == user_ref).unwrap()
+        };
+        let role = |user_ref: &UserRef| {
+            result(user_ref).membership.as_ref().map(|m| m.role)
+        };
+        assert_eq!(role(&alice), Some(ServerRole::Member));
+        assert_eq!(role(&bob), Some(ServerRole::Admin));
+        assert!(result(&ghost).error.is_some());
+
+        // The remote user was cached so the membership has a user row
+        queries::users::get_by_ref(&state.db_pool, bob.clone())
+            .await
+            .unwrap();
+        let member = queries::memberships::get_local_member_by_user_and_server(
+            &state.db_pool,
+            server.id,
+            bob,
+        )
+        .await
+        .unwrap();
+        assert_eq!(member.role, ServerRole::Admin);
     }
 }
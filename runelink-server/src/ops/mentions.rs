@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use runelink_client::util::get_api_url;
+use runelink_types::{
+    message::{Message, parse_mentions},
+    user::UserRef,
+    ws::{ClientWsUpdate, FederationWsUpdate},
+};
+
+use crate::{
+    error::{ApiError, ApiResult},
+    ids::ConnId,
+    queries,
+    state::AppState,
+};
+
+/// Record the members of a new local message's server it mentions and
+/// notify each of them: local users directly, others through their host.
+///
+/// Mentions of anyone who isn't a member, including unknown users, are
+/// ignored. The author mentioning themselves isn't notified.
+pub async fn notify(state: &AppState, message: &Message) -> ApiResult<()> {
+    let author = message.author.as_ref().map(|author| author.as_ref());
+    let mentioned = parse_mentions(&message.body)
+        .into_iter()
+        .filter(|user_ref| Some(user_ref) != author.as_ref())
+        .collect::<Vec<_>>();
+    if mentioned.is_empty() {
+        return Ok(());
+    }
+    let members = queries::mentions::insert_for_members(
+        &state.db_pool,
+        message.id,
+        message.server_id,
+        &mentioned,
+    )
+    .await?;
+
+    let local_host = state.config.public_host();
+    let mut by_host = HashMap::<String, Vec<UserRef>>::new();
+    for user_ref in members {
+        if user_ref.host == local_host {
+            notify_local_user(state, &user_ref, message).await;
+        } else {
+            by_host
+                .entry(user_ref.host.clone())
+                .or_default()
+                .push(user_ref);
+        }
+    }
+    for (host, user_refs) in by_host {
+        let _ = state
+            .federation_ws_manager
+            .send_update_to_host(
+                &host,
+                FederationWsUpdate::Mentioned {
+                    message: message.clone(),
+                    user_refs,
+                },
+            )
+            .await;
+    }
+    Ok(())
+}
+
+/// Apply mentions relayed over federation.
+///
+/// They must come from the message's server's host, and only this host's
+/// users who are members of that server are notified.
+pub async fn apply_federated(
+    state: &AppState,
+    conn_id: ConnId,
+    message: Message,
+    user_refs: Vec<UserRef>,
+) -> ApiResult<()> {
+    let Some(issuer) = state
+        .federation_ws_manager
+        .authenticated_issuer(conn_id)
+        .await
+    else {
+        return Err(ApiError::Unauthorized(
+            "Mentions require an authenticated host".into(),
+        ));
+    };
+    let server_hosts = queries::servers::get_cached_remote_refs(
+        &state.db_pool,
+        Some(message.server_id),
+        None,
+    )
+    .await?;
+    if !server_hosts
+        .iter()
+        .any(|(_, host)| get_api_url(host, state.config.secure) == issuer)
+    {
+        return Err(ApiError::Forbidden(format!(
+            "Mentions in server {} must come from its host",
+            message.server_id
+        )));
+    }
+    let members = state
+        .routing_index
+        .users_for_remote_server(message.server_id)
+        .await?;
+    for user_ref in user_refs {
+        if members.contains(&user_ref) {
+            notify_local_user(state, &user_ref, &message).await;
+        }
+    }
+    Ok(())
+}
+
+async fn notify_local_user(
+    state: &AppState,
+    user_ref: &UserRef,
+    message: &Message,
+) {
+    state
+        .client_ws_manager
+        .send_update_to_user(
+            user_ref,
+            ClientWsUpdate::Mentioned {
+                message: message.clone(),
+                channel_id: message.channel_id,
+            },
+        )
+        .await;
+}
@@ -1,19 +1,27 @@
+use std::time::Duration;
+
 use runelink_types::{
-    channel::ChannelId,
-    message::{Message, MessageId, NewMessage},
-    server::ServerId,
+    channel::{Channel, ChannelId, ChannelReadState, ChannelUnreadCount},
+    message::{
+        ChannelPurgeReport, Message, MessageId, MessagePurgeReport,
+        MessageUpdate, NewMessage, NewReaction,
+    },
+    server::{ServerId, ServerRole},
+    user::{NewUser, UserRef, UserRole},
+    validation::validate_emoji,
     ws::{
         ClientWsUpdate, FederationWsReply, FederationWsRequest,
         FederationWsUpdate,
     },
 };
+use time::OffsetDateTime;
 
-use super::federation;
+use super::{attachments, federation, mentions};
 use crate::{
     auth::Session,
     error::{ApiError, ApiResult},
     ops::fanout,
-    queries,
+    queries, rate_limit,
     state::AppState,
 };
 
@@ -26,21 +34,41 @@ pub async fn create(
     new_message: &NewMessage,
     target_host: Option<&str>,
 ) -> ApiResult<Message> {
+    new_message
+        .validate()
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    let user_ref = session_author(session, new_message)?;
+    rate_limit::check_message(&state.rate_limits, user_ref).await?;
     // Handle local case
     if !state.config.is_remote_host(target_host) {
         let channel =
             queries::channels::get_by_id(&state.db_pool, channel_id).await?;
         if channel.server_id != server_id {
-            return Err(ApiError::AuthError(
-                "Channel not found in specified server".into(),
-            ));
+            return Err(ApiError::NotFound);
         }
-        let message =
-            queries::messages::insert(&state.db_pool, channel_id, new_message)
-                .await?;
-        fanout::fanout_update(
+        check_slow_mode(state, &channel, user_ref).await?;
+        let new_message = &NewMessage {
+            attachments: attachments::resolve_for_message(
+                state,
+                channel_id,
+                user_ref,
+                &new_message.attachments,
+            )
+            .await?,
+            ..new_message.clone()
+        };
+        let message = queries::messages::insert(
+            &state.db_pool,
+            channel_id,
+            new_message,
+            false,
+        )
+        .await?;
+        state.metrics.message_created();
+        fanout::fanout_channel_update(
             state,
             fanout::resolve_server_targets(state, server_id).await?,
+            channel_id,
             ClientWsUpdate::MessageUpserted(message.clone()),
             FederationWsUpdate::MessageUpserted {
                 server_id,
@@ -48,16 +76,25 @@ pub async fn create(
             },
         )
         .await;
+        // The message is already posted, so a failure here only loses the
+        // notifications
+        if let Err(error) = mentions::notify(state, &message).await {
+            log::warn!("Failed to notify mentions in {}: {error}", message.id);
+        }
         Ok(message)
     } else {
         // Create on remote host using federation
         let host = target_host.unwrap();
-        let user_ref = session.user_ref.as_ref().ok_or_else(|| {
-            ApiError::Internal(
-                "User reference required for federated message creation"
-                    .to_string(),
+        let new_message = &NewMessage {
+            attachments: attachments::resolve_for_message(
+                state,
+                channel_id,
+                user_ref,
+                &new_message.attachments,
             )
-        })?;
+            .await?,
+            ..new_message.clone()
+        };
         let reply = federation::request(
             state,
             host,
@@ -74,10 +111,179 @@ pub async fn create(
                 "Unexpected federation reply from {host} for messages.create"
             )));
         };
+        // Uploads are kept here, so note that they're now in use
+        if !new_message.attachments.is_empty() {
+            let attachment_ids = new_message
+                .attachments
+                .iter()
+                .map(|attachment| attachment.id)
+                .collect::<Vec<_>>();
+            queries::attachments::mark_attached_remotely(
+                &state.db_pool,
+                &attachment_ids,
+                message.id,
+            )
+            .await?;
+        }
         Ok(message)
     }
 }
 
+/// The session user, who must also be the author of `new_message`.
+fn session_author<'a>(
+    session: &'a Session,
+    new_message: &NewMessage,
+) -> ApiResult<&'a UserRef> {
+    let user_ref = session.user_ref.as_ref().ok_or_else(|| {
+        ApiError::Forbidden("User reference required to post messages".into())
+    })?;
+    if new_message.author != *user_ref {
+        return Err(ApiError::Forbidden(
+            "Cannot post messages as another user".into(),
+        ));
+    }
+    Ok(user_ref)
+}
+
+/// Refuses a message from `author` while the channel's slow mode window
+/// since their last message there is still open. Server and host admins
+/// are exempt.
+async fn check_slow_mode(
+    state: &AppState,
+    channel: &Channel,
+    author: &UserRef,
+) -> ApiResult<()> {
+    let Some(slow_mode_secs) = channel.slow_mode_secs else {
+        return Ok(());
+    };
+    let last_posted_at =
+        queries::messages::last_posted_at(&state.db_pool, channel.id, author)
+            .await?;
+    let Some(retry_after) = slow_mode_remaining(
+        slow_mode_secs,
+        last_posted_at,
+        OffsetDateTime::now_utc(),
+    ) else {
+        return Ok(());
+    };
+    if is_moderator(state, channel.server_id, author).await? {
+        return Ok(());
+    }
+    Err(ApiError::RateLimited { retry_after })
+}
+
+/// How long until a slow mode window that started at `last_posted_at`
+/// closes, or `None` if it already has.
+fn slow_mode_remaining(
+    slow_mode_secs: i32,
+    last_posted_at: Option<OffsetDateTime>,
+    now: OffsetDateTime,
+) -> Option<Duration> {
+    let last_posted_at = last_posted_at?;
+    let window = time::Duration::seconds(i64::from(slow_mode_secs));
+    let remaining = last_posted_at + window - now;
+    // Round up so clients never retry a moment too early
+    let secs = remaining.whole_seconds()
+        + i64::from(remaining.subsec_nanoseconds() > 0);
+    u64::try_from(secs)
+        .ok()
+        .filter(|&secs| secs > 0)
+        .map(Duration::from_secs)
+}
+
+/// Whether `user_ref` is an admin of the server or of this host.
+async fn is_moderator(
+    state: &AppState,
+    server_id: ServerId,
+    user_ref: &UserRef,
+) -> ApiResult<bool> {
+    match queries::memberships::get_local_member_by_user_and_server(
+        &state.db_pool,
+        server_id,
+        user_ref.clone(),
+    )
+    .await
+    {
+        Ok(member) if member.role == ServerRole::Admin => return Ok(true),
+        Ok(_) | Err(ApiError::NotFound) => {}
+        Err(error) => return Err(error),
+    }
+    if user_ref.host != state.config.public_host() {
+        return Ok(false);
+    }
+    let user =
+        queries::users::get_by_ref(&state.db_pool, user_ref.clone()).await?;
+    Ok(user.role == UserRole::Admin)
+}
+
+/// Post a message in a local channel as the host's system identity.
+///
+/// This is internal-only: it skips member auth and is not exposed to clients.
+pub async fn create_system(
+    state: &AppState,
+    channel_id: ChannelId,
+    body: String,
+) -> ApiResult<Message> {
+    let author = UserRef::system(state.config.public_host());
+    create_as_reserved(state, channel_id, author, body, true).await
+}
+
+/// Post a message in a local channel as the host's webhook identity.
+///
+/// The caller is responsible for checking the webhook's token.
+pub async fn create_from_webhook(
+    state: &AppState,
+    channel_id: ChannelId,
+    body: String,
+) -> ApiResult<Message> {
+    let author = UserRef::webhook(state.config.public_host());
+    create_as_reserved(state, channel_id, author, body, false).await
+}
+
+/// Insert and fan out a message by one of the host's reserved identities,
+/// creating the identity's user on first use.
+async fn create_as_reserved(
+    state: &AppState,
+    channel_id: ChannelId,
+    author: UserRef,
+    body: String,
+    system: bool,
+) -> ApiResult<Message> {
+    queries::users::insert_if_missing(
+        &state.db_pool,
+        &NewUser {
+            name: author.name.clone(),
+            host: author.host.clone(),
+            role: UserRole::User,
+        },
+    )
+    .await?;
+    let message = queries::messages::insert(
+        &state.db_pool,
+        channel_id,
+        &NewMessage {
+            author,
+            body,
+            attachments: Vec::new(),
+        },
+        system,
+    )
+    .await?;
+    state.metrics.message_created();
+    fanout::fanout_channel_update(
+        state,
+        fanout::resolve_server_targets(state, message.server_id).await?,
+        message.channel_id,
+        ClientWsUpdate::MessageUpserted(message.clone()),
+        FederationWsUpdate::MessageUpserted {
+            server_id: message.server_id,
+            message: message.clone(),
+        },
+    )
+    .await;
+    Ok(message)
+}
+
 /// Get all messages.
 pub async fn get_all(
     state: &AppState,
@@ -86,7 +292,12 @@ pub async fn get_all(
 ) -> ApiResult<Vec<Message>> {
     // Handle local case
     if !state.config.is_remote_host(target_host) {
-        let messages = queries::messages::get_all(&state.db_pool).await?;
+        let messages = queries::messages::get_all(
+            &state.db_pool,
+            session.user_ref.as_ref(),
+            false,
+        )
+        .await?;
         Ok(messages)
     } else {
         // Fetch from remote host using federation
@@ -122,8 +333,13 @@ pub async fn get_by_server(
 ) -> ApiResult<Vec<Message>> {
     // Handle local case
     if !state.config.is_remote_host(target_host) {
-        let messages =
-            queries::messages::get_by_server(&state.db_pool, server_id).await?;
+        let messages = queries::messages::get_by_server(
+            &state.db_pool,
+            server_id,
+            session.user_ref.as_ref(),
+            false,
+        )
+        .await?;
         Ok(messages)
     } else {
         // Fetch from remote host using federation
@@ -150,19 +366,40 @@ pub async fn get_by_server(
     }
 }
 
-/// Get messages in a channel.
+/// Page size used when a channel history request gives no limit.
+pub const DEFAULT_MESSAGE_PAGE_SIZE: u32 = 50;
+/// Largest page of channel history returned at once.
+pub const MAX_MESSAGE_PAGE_SIZE: u32 = 200;
+
+/// Get a page of messages in a channel, newest first.
+///
+/// Pass the oldest message of the previous page as `before` to continue. A
+/// page shorter than `limit` means the start of the channel was reached.
+/// Deleted messages appear as tombstones, so a client catching up can drop
+/// its copies.
 pub async fn get_by_channel(
     state: &AppState,
     session: &Session,
     server_id: ServerId,
     channel_id: ChannelId,
+    before: Option<MessageId>,
+    limit: Option<u32>,
     target_host: Option<&str>,
 ) -> ApiResult<Vec<Message>> {
     // Handle local case
     if !state.config.is_remote_host(target_host) {
-        let messages =
-            queries::messages::get_by_channel(&state.db_pool, channel_id)
-                .await?;
+        let limit = limit
+            .unwrap_or(DEFAULT_MESSAGE_PAGE_SIZE)
+            .clamp(1, MAX_MESSAGE_PAGE_SIZE);
+        let messages = queries::messages::get_by_channel(
+            &state.db_pool,
+            channel_id,
+            before,
+            limit,
+            session.user_ref.as_ref(),
+            true,
+        )
+        .await?;
         Ok(messages)
     } else {
         // Fetch from remote host using federation
@@ -180,6 +417,8 @@ pub async fn get_by_channel(
             FederationWsRequest::MessagesGetByChannel {
                 server_id,
                 channel_id,
+                before,
+                limit,
             },
         )
         .await?;
@@ -192,6 +431,147 @@ pub async fn get_by_channel(
     }
 }
 
+/// Search a server's messages, best matches first.
+pub async fn search(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    query: &str,
+    limit: Option<u32>,
+    target_host: Option<&str>,
+) -> ApiResult<Vec<Message>> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Err(ApiError::BadRequest("Search query is empty".into()));
+    }
+    // Handle local case
+    if !state.config.is_remote_host(target_host) {
+        let limit = limit
+            .unwrap_or(DEFAULT_MESSAGE_PAGE_SIZE)
+            .clamp(1, MAX_MESSAGE_PAGE_SIZE);
+        let messages = queries::messages::search(
+            &state.db_pool,
+            server_id,
+            query,
+            limit,
+            session.user_ref.as_ref(),
+        )
+        .await?;
+        Ok(messages)
+    } else {
+        // Search on remote host using federation
+        let host = target_host.unwrap();
+        let user_ref = session.user_ref.as_ref().ok_or_else(|| {
+            ApiError::Internal(
+                "User reference required for federated message search"
+                    .to_string(),
+            )
+        })?;
+        let reply = federation::request(
+            state,
+            host,
+            Some(user_ref.clone()),
+            FederationWsRequest::MessagesSearch {
+                server_id,
+                query: query.to_string(),
+                limit,
+            },
+        )
+        .await?;
+        let FederationWsReply::MessagesSearch(messages) = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for messages.search"
+            )));
+        };
+        Ok(messages)
+    }
+}
+
+/// Move the session user's read position in a channel to a message.
+///
+/// The position is stored on the channel's home host.
+pub async fn mark_read(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    target_host: Option<&str>,
+) -> ApiResult<ChannelReadState> {
+    let user_ref = session.user_ref.as_ref().ok_or_else(|| {
+        ApiError::Forbidden("User reference required for read state".into())
+    })?;
+    // Handle local case
+    if !state.config.is_remote_host(target_host) {
+        queries::read_states::upsert(
+            &state.db_pool,
+            server_id,
+            channel_id,
+            message_id,
+            user_ref,
+        )
+        .await
+    } else {
+        // Store on the remote host using federation
+        let host = target_host.unwrap();
+        let reply = federation::request(
+            state,
+            host,
+            Some(user_ref.clone()),
+            FederationWsRequest::ChannelsMarkRead {
+                server_id,
+                channel_id,
+                message_id,
+            },
+        )
+        .await?;
+        let FederationWsReply::ChannelsMarkRead(read_state) = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for messages.mark_read"
+            )));
+        };
+        Ok(read_state)
+    }
+}
+
+/// Get the session user's unread message counts for each channel in a
+/// server.
+pub async fn get_unread_counts(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    target_host: Option<&str>,
+) -> ApiResult<Vec<ChannelUnreadCount>> {
+    let user_ref = session.user_ref.as_ref().ok_or_else(|| {
+        ApiError::Forbidden("User reference required for read state".into())
+    })?;
+    // Handle local case
+    if !state.config.is_remote_host(target_host) {
+        queries::read_states::get_unread_counts(
+            &state.db_pool,
+            server_id,
+            user_ref,
+        )
+        .await
+    } else {
+        // Fetch from remote host using federation
+        let host = target_host.unwrap();
+        let reply = federation::request(
+            state,
+            host,
+            Some(user_ref.clone()),
+            FederationWsRequest::UnreadCounts { server_id },
+        )
+        .await?;
+        let FederationWsReply::UnreadCounts(counts) = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for messages.get_unread_counts"
+            )));
+        };
+        Ok(counts)
+    }
+}
+
 /// Get a message by its ID.
 pub async fn get_by_id(
     state: &AppState,
@@ -203,21 +583,14 @@ pub async fn get_by_id(
 ) -> ApiResult<Message> {
     // Handle local case
     if !state.config.is_remote_host(target_host) {
-        let message =
-            queries::messages::get_by_id(&state.db_pool, message_id).await?;
-        if message.channel_id != channel_id {
-            return Err(ApiError::AuthError(
-                "Message not found in specified channel".into(),
-            ));
-        }
-        let channel =
-            queries::channels::get_by_id(&state.db_pool, channel_id).await?;
-        if channel.server_id != server_id {
-            return Err(ApiError::AuthError(
-                "Message not found in specified server".into(),
-            ));
-        }
-        Ok(message)
+        queries::messages::get_by_id_scoped(
+            &state.db_pool,
+            server_id,
+            channel_id,
+            message_id,
+            session.user_ref.as_ref(),
+        )
+        .await
     } else {
         // Fetch from remote host using federation
         let host = target_host.unwrap();
@@ -247,37 +620,97 @@ pub async fn get_by_id(
     }
 }
 
-/// Delete a message by ID.
-pub async fn delete(
+/// Edit a message's body.
+pub async fn update(
     state: &AppState,
     session: &Session,
     server_id: ServerId,
     channel_id: ChannelId,
     message_id: MessageId,
+    update: &MessageUpdate,
     target_host: Option<&str>,
-) -> ApiResult<()> {
+) -> ApiResult<Message> {
+    update
+        .validate()
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
     // Handle local case
     if !state.config.is_remote_host(target_host) {
-        // Verify the message belongs to the channel and server
-        // TODO: This should be done with one database query
         let message =
-            queries::messages::get_by_id(&state.db_pool, message_id).await?;
+            queries::messages::get_by_id(&state.db_pool, message_id, None)
+                .await?;
         if message.channel_id != channel_id {
-            return Err(ApiError::AuthError(
-                "Message not found in specified channel".into(),
-            ));
+            return Err(ApiError::NotFound);
         }
-        let channel =
-            queries::channels::get_by_id(&state.db_pool, channel_id).await?;
-        if channel.server_id != server_id {
-            return Err(ApiError::AuthError(
-                "Message not found in specified server".into(),
-            ));
+        if message.server_id != server_id {
+            return Err(ApiError::NotFound);
         }
-        queries::messages::delete(&state.db_pool, message_id).await?;
-        fanout::fanout_update(
+        let message =
+            queries::messages::update(&state.db_pool, message_id, update)
+                .await?;
+        fanout::fanout_channel_update(
+            state,
+            fanout::resolve_server_targets(state, server_id).await?,
+            channel_id,
+            ClientWsUpdate::MessageUpserted(message.clone()),
+            FederationWsUpdate::MessageUpserted {
+                server_id,
+                message: message.clone(),
+            },
+        )
+        .await;
+        Ok(message)
+    } else {
+        // Update on remote host using federation
+        let host = target_host.unwrap();
+        let user_ref = session.user_ref.as_ref().ok_or_else(|| {
+            ApiError::Internal(
+                "User reference required for federated message update"
+                    .to_string(),
+            )
+        })?;
+        let reply = federation::request(
+            state,
+            host,
+            Some(user_ref.clone()),
+            FederationWsRequest::MessagesUpdate {
+                server_id,
+                channel_id,
+                message_id,
+                update: update.clone(),
+            },
+        )
+        .await?;
+        let FederationWsReply::MessagesUpdate(message) = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for messages.update"
+            )));
+        };
+        Ok(message)
+    }
+}
+
+/// Delete a message by ID, leaving a tombstone until it is purged.
+pub async fn delete(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    target_host: Option<&str>,
+) -> ApiResult<()> {
+    // Handle local case
+    if !state.config.is_remote_host(target_host) {
+        queries::messages::delete_scoped(
+            &state.db_pool,
+            server_id,
+            channel_id,
+            message_id,
+        )
+        .await?;
+        fanout::fanout_channel_update(
             state,
             fanout::resolve_server_targets(state, server_id).await?,
+            channel_id,
             ClientWsUpdate::MessageDeleted {
                 server_id,
                 channel_id,
@@ -320,39 +753,384 @@ pub async fn delete(
     }
 }
 
+/// Delete every message in a channel, or only those older than `before`.
+///
+/// Members get a single `ChannelPurged` update instead of one
+/// `MessageDeleted` per message.
+pub async fn purge_channel(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    before: Option<MessageId>,
+    target_host: Option<&str>,
+) -> ApiResult<ChannelPurgeReport> {
+    // Handle local case
+    if !state.config.is_remote_host(target_host) {
+        let channel =
+            queries::channels::get_by_id(&state.db_pool, channel_id).await?;
+        if channel.server_id != server_id {
+            return Err(ApiError::NotFound);
+        }
+        let purged = queries::messages::purge_channel(
+            &state.db_pool,
+            server_id,
+            channel_id,
+            before,
+        )
+        .await?;
+        if purged > 0 {
+            fanout::fanout_channel_update(
+                state,
+                fanout::resolve_server_targets(state, server_id).await?,
+                channel_id,
+                ClientWsUpdate::ChannelPurged {
+                    server_id,
+                    channel_id,
+                    before,
+                },
+                FederationWsUpdate::ChannelPurged {
+                    server_id,
+                    channel_id,
+                    before,
+                },
+            )
+            .await;
+        }
+        Ok(ChannelPurgeReport { channel_id, purged })
+    } else {
+        // Purge on remote host using federation
+        let host = target_host.unwrap();
+        let user_ref = session.user_ref.as_ref().ok_or_else(|| {
+            ApiError::Internal(
+                "User reference required for federated channel purge"
+                    .to_string(),
+            )
+        })?;
+        let reply = federation::request(
+            state,
+            host,
+            Some(user_ref.clone()),
+            FederationWsRequest::MessagesPurgeChannel {
+                server_id,
+                channel_id,
+                before,
+            },
+        )
+        .await?;
+        let FederationWsReply::MessagesPurgeChannel(report) = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for messages.purge_channel"
+            )));
+        };
+        Ok(report)
+    }
+}
+
+/// Permanently remove messages deleted longer ago than the configured
+/// tombstone TTL.
+pub async fn purge_deleted(state: &AppState) -> ApiResult<MessagePurgeReport> {
+    let deleted_before =
+        OffsetDateTime::now_utc() - state.config.deleted_message_ttl;
+    let purged =
+        queries::messages::purge_deleted(&state.db_pool, deleted_before)
+            .await?;
+    Ok(MessagePurgeReport { purged })
+}
+
+/// Validates a reaction emoji and checks it fits under the per-message cap.
+///
+/// Reacting with an emoji the message already has never counts against the
+/// cap. Returns the normalized emoji.
+fn validate_new_reaction(
+    emoji: &str,
+    existing: &[String],
+    max_distinct: usize,
+) -> ApiResult<String> {
+    let emoji = validate_emoji(emoji)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    if !existing.contains(&emoji) && existing.len() >= max_distinct {
+        return Err(ApiError::BadRequest(format!(
+            "Message already has the maximum of {max_distinct} distinct reactions"
+        )));
+    }
+    Ok(emoji)
+}
+
+/// Fetch a local message, checking it belongs to the channel and server.
+async fn get_local_in_channel(
+    state: &AppState,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    viewer: Option<&UserRef>,
+) -> ApiResult<Message> {
+    let message =
+        queries::messages::get_by_id(&state.db_pool, message_id, viewer)
+            .await?;
+    if message.channel_id != channel_id {
+        return Err(ApiError::NotFound);
+    }
+    if message.server_id != server_id {
+        return Err(ApiError::NotFound);
+    }
+    Ok(message)
+}
+
+/// React to a message as the session user.
+///
+/// Reacting twice with the same emoji is a no-op. Returns the message with
+/// its updated reactions.
+pub async fn add_reaction(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    new_reaction: &NewReaction,
+    target_host: Option<&str>,
+) -> ApiResult<Message> {
+    let user_ref = session.user_ref.as_ref().ok_or_else(|| {
+        ApiError::Internal("User reference required for reactions".to_string())
+    })?;
+    // Handle local case
+    if !state.config.is_remote_host(target_host) {
+        get_local_in_channel(state, server_id, channel_id, message_id, None)
+            .await?;
+        let existing =
+            queries::messages::get_reaction_emojis(&state.db_pool, message_id)
+                .await?;
+        let emoji = validate_new_reaction(
+            &new_reaction.emoji,
+            &existing,
+            state.config.max_reactions_per_message,
+        )?;
+        let added = queries::messages::add_reaction(
+            &state.db_pool,
+            message_id,
+            user_ref,
+            &emoji,
+        )
+        .await?;
+        if added {
+            fanout::fanout_channel_update(
+                state,
+                fanout::resolve_server_targets(state, server_id).await?,
+                channel_id,
+                ClientWsUpdate::MessageReactionUpserted {
+                    server_id,
+                    channel_id,
+                    message_id,
+                    user_ref: user_ref.clone(),
+                    emoji: emoji.clone(),
+                },
+                FederationWsUpdate::MessageReactionUpserted {
+                    server_id,
+                    channel_id,
+                    message_id,
+                    user_ref: user_ref.clone(),
+                    emoji,
+                },
+            )
+            .await;
+        }
+        get_local_in_channel(
+            state,
+            server_id,
+            channel_id,
+            message_id,
+            Some(user_ref),
+        )
+        .await
+    } else {
+        // React on remote host using federation
+        let host = target_host.unwrap();
+        let reply = federation::request(
+            state,
+            host,
+            Some(user_ref.clone()),
+            FederationWsRequest::MessagesReact {
+                server_id,
+                channel_id,
+                message_id,
+                new_reaction: new_reaction.clone(),
+            },
+        )
+        .await?;
+        let FederationWsReply::MessagesReact(message) = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for messages.react"
+            )));
+        };
+        Ok(message)
+    }
+}
+
+/// Remove the session user's reaction from a message.
+///
+/// Removing a reaction that isn't there is a no-op. Returns the message with
+/// its updated reactions.
+pub async fn remove_reaction(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    emoji: &str,
+    target_host: Option<&str>,
+) -> ApiResult<Message> {
+    let user_ref = session.user_ref.as_ref().ok_or_else(|| {
+        ApiError::Internal("User reference required for reactions".to_string())
+    })?;
+    // Handle local case
+    if !state.config.is_remote_host(target_host) {
+        get_local_in_channel(state, server_id, channel_id, message_id, None)
+            .await?;
+        let emoji = emoji.trim();
+        let removed = queries::messages::remove_reaction(
+            &state.db_pool,
+            message_id,
+            user_ref,
+            emoji,
+        )
+        .await?;
+        if removed {
+            fanout::fanout_channel_update(
+                state,
+                fanout::resolve_server_targets(state, server_id).await?,
+                channel_id,
+                ClientWsUpdate::MessageReactionRemoved {
+                    server_id,
+                    channel_id,
+                    message_id,
+                    user_ref: user_ref.clone(),
+                    emoji: emoji.to_string(),
+                },
+                FederationWsUpdate::MessageReactionRemoved {
+                    server_id,
+                    channel_id,
+                    message_id,
+                    user_ref: user_ref.clone(),
+                    emoji: emoji.to_string(),
+                },
+            )
+            .await;
+        }
+        get_local_in_channel(
+            state,
+            server_id,
+            channel_id,
+            message_id,
+            Some(user_ref),
+        )
+        .await
+    } else {
+        // Unreact on remote host using federation
+        let host = target_host.unwrap();
+        let reply = federation::request(
+            state,
+            host,
+            Some(user_ref.clone()),
+            FederationWsRequest::MessagesUnreact {
+                server_id,
+                channel_id,
+                message_id,
+                emoji: emoji.to_string(),
+            },
+        )
+        .await?;
+        let FederationWsReply::MessagesUnreact(message) = reply else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for messages.unreact"
+            )));
+        };
+        Ok(message)
+    }
+}
+
 /// Auth requirements for message operations.
 pub mod auth {
     use super::*;
-    use crate::auth::Requirement as Req;
+    use crate::auth::{MESSAGES_READ, MESSAGES_WRITE, Requirement as Req};
     use crate::or;
 
     pub fn create(server_id: ServerId) -> Req {
-        Req::ServerMember(server_id).or_admin().client_only()
+        Req::ServerMember(server_id)
+            .or_admin()
+            .client_only()
+            .with_scope(MESSAGES_WRITE)
     }
 
     pub fn get_all() -> Req {
-        Req::HostAdmin.client_only()
+        Req::HostAdmin.client_only().with_scope(MESSAGES_READ)
     }
 
     pub fn get_by_server(server_id: ServerId) -> Req {
-        Req::ServerMember(server_id).or_admin().client_only()
+        Req::ServerMember(server_id)
+            .or_admin()
+            .client_only()
+            .with_scope(MESSAGES_READ)
     }
 
     pub fn get_by_channel(server_id: ServerId) -> Req {
-        Req::ServerMember(server_id).or_admin().client_only()
+        Req::ServerMember(server_id)
+            .or_admin()
+            .client_only()
+            .with_scope(MESSAGES_READ)
     }
 
     pub fn get_by_id(server_id: ServerId) -> Req {
-        Req::ServerMember(server_id).or_admin().client_only()
+        Req::ServerMember(server_id)
+            .or_admin()
+            .client_only()
+            .with_scope(MESSAGES_READ)
+    }
+
+    pub fn search(server_id: ServerId) -> Req {
+        Req::ServerMember(server_id)
+            .or_admin()
+            .client_only()
+            .with_scope(MESSAGES_READ)
+    }
+
+    pub fn mark_read(server_id: ServerId) -> Req {
+        Req::ServerMember(server_id)
+            .client_only()
+            .with_scope(MESSAGES_READ)
     }
 
-    async fn delete_base(
+    pub fn get_unread_counts(server_id: ServerId) -> Req {
+        Req::ServerMember(server_id)
+            .client_only()
+            .with_scope(MESSAGES_READ)
+    }
+
+    pub fn react(server_id: ServerId) -> Req {
+        Req::ServerMember(server_id)
+            .client_only()
+            .with_scope(MESSAGES_WRITE)
+    }
+
+    pub fn purge_deleted() -> Req {
+        Req::HostAdmin.client_only()
+    }
+
+    pub fn purge_channel(server_id: ServerId) -> Req {
+        Req::ServerAdmin(server_id)
+            .or_admin()
+            .client_only()
+            .with_scope(MESSAGES_WRITE)
+    }
+
+    /// The message's author or a server admin.
+    async fn author_or_server_admin(
         state: &AppState,
         server_id: ServerId,
         message_id: MessageId,
     ) -> ApiResult<Req> {
         let message =
-            queries::messages::get_by_id(&state.db_pool, message_id).await?;
+            queries::messages::get_by_id(&state.db_pool, message_id, None)
+                .await?;
         if let Some(author) = message.author {
             Ok(or!(Req::User(author.into()), Req::ServerAdmin(server_id)))
         } else {
@@ -365,8 +1143,17 @@ pub mod auth {
         server_id: ServerId,
         message_id: MessageId,
     ) -> ApiResult<Req> {
-        let base = delete_base(state, server_id, message_id).await?;
-        Ok(base.or_admin().client_only())
+        let base = author_or_server_admin(state, server_id, message_id).await?;
+        Ok(base.or_admin().client_only().with_scope(MESSAGES_WRITE))
+    }
+
+    pub async fn update(
+        state: &AppState,
+        server_id: ServerId,
+        message_id: MessageId,
+    ) -> ApiResult<Req> {
+        let base = author_or_server_admin(state, server_id, message_id).await?;
+        Ok(base.or_admin().client_only().with_scope(MESSAGES_WRITE))
     }
 
     pub mod federated {
@@ -392,14 +1179,215 @@ pub mod auth {
             Req::ServerMember(server_id).federated_only()
         }
 
+        pub fn search(server_id: ServerId) -> Req {
+            Req::ServerMember(server_id).federated_only()
+        }
+
+        pub fn mark_read(server_id: ServerId) -> Req {
+            Req::ServerMember(server_id).federated_only()
+        }
+
+        pub fn get_unread_counts(server_id: ServerId) -> Req {
+            Req::ServerMember(server_id).federated_only()
+        }
+
+        pub fn react(server_id: ServerId) -> Req {
+            Req::ServerMember(server_id).federated_only()
+        }
+
+        pub fn purge_channel(server_id: ServerId) -> Req {
+            Req::ServerAdmin(server_id).federated_only()
+        }
+
+        pub async fn update(
+            state: &AppState,
+            server_id: ServerId,
+            message_id: MessageId,
+        ) -> ApiResult<Req> {
+            let base =
+                author_or_server_admin(state, server_id, message_id).await?;
+            Ok(base.federated_only())
+        }
+
         pub async fn delete(
             state: &AppState,
             server_id: ServerId,
             message_id: MessageId,
         ) -> ApiResult<Req> {
             // TODO: Check if the author is from the same host as the server
-            let base = delete_base(state, server_id, message_id).await?;
+            let base =
+                author_or_server_admin(state, server_id, message_id).await?;
             Ok(base.federated_only())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use runelink_client::util::get_api_url;
+    use runelink_types::message::{AttachmentId, AttachmentRef};
+
+    use super::*;
+    use crate::{db::DbPool, test_util};
+
+    #[test]
+    fn test_valid_emoji_is_accepted_and_trimmed() {
+        assert_eq!(validate_new_reaction(" 👍 ", &[], 20).unwrap(), "👍");
+        assert_eq!(
+            validate_new_reaction(":party_parrot:", &[], 20).unwrap(),
+            ":party_parrot:"
+        );
+    }
+
+    #[test]
+    fn test_arbitrary_string_is_rejected() {
+        let long = "not an emoji ".repeat(100);
+        assert!(matches!(
+            validate_new_reaction(&long, &[], 20),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_distinct_reaction_cap() {
+        let existing = vec!["👍".to_string(), "🎉".to_string()];
+        // An emoji already on the message doesn't add a distinct reaction.
+        assert!(validate_new_reaction("👍", &existing, 2).is_ok());
+        assert!(matches!(
+            validate_new_reaction("❤️", &existing, 2),
+            Err(ApiError::BadRequest(_))
+        ));
+        assert!(validate_new_reaction("❤️", &existing, 3).is_ok());
+    }
+
+    #[test]
+    fn test_slow_mode_remaining_rounds_up() {
+        let now = OffsetDateTime::now_utc();
+        let ago = |secs: f64| Some(now - time::Duration::seconds_f64(secs));
+        assert_eq!(slow_mode_remaining(30, None, now), None);
+        assert_eq!(slow_mode_remaining(30, ago(30.0), now), None);
+        assert_eq!(slow_mode_remaining(30, ago(45.0), now), None);
+        assert_eq!(
+            slow_mode_remaining(30, ago(10.5), now),
+            Some(Duration::from_secs(20))
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_rejected_message_records_no_attachments(pool: DbPool) {
+        let state = test_util::state(pool);
+        let owner = test_util::local_user(&state, "owner").await.as_ref();
+        let remote = test_util::user(&state, "bob", "remote.example")
+            .await
+            .as_ref();
+        let server = test_util::server(&state, &owner, "Guild").await;
+        let other = test_util::server(&state, &owner, "Other").await;
+        test_util::join(&state, server.id, &remote).await;
+        let channel = test_util::channel(&state, server.id, "general").await;
+        let session = test_util::session(&state, &remote).await;
+
+        let id = AttachmentId::new();
+        let new_message = NewMessage {
+            author: remote.clone(),
+            body: "look".into(),
+            attachments: vec![AttachmentRef {
+                id,
+                filename: "cat.png".into(),
+                content_type: "image/png".into(),
+                size: 16,
+                url: format!(
+                    "{}/attachments/{id}",
+                    get_api_url(&remote.host, state.config.secure)
+                ),
+            }],
+        };
+        // The channel isn't in this server
+        let result =
+            create(&state, &session, other.id, channel.id, &new_message, None)
+                .await;
+        assert!(matches!(result, Err(ApiError::NotFound)));
+        assert!(matches!(
+            queries::attachments::get_by_id(&state.db_pool, id).await,
+            Err(ApiError::NotFound)
+        ));
+
+        let message =
+            create(&state, &session, server.id, channel.id, &new_message, None)
+                .await
+                .unwrap();
+        assert_eq!(message.attachments.len(), 1);
+        assert_eq!(message.attachments[0].id, id);
+    }
+
+    #[sqlx::test]
+    async fn test_uploads_sent_to_a_remote_channel_are_used_up(pool: DbPool) {
+        let state = test_util::state(pool);
+        let alice = test_util::local_user(&state, "alice").await.as_ref();
+        let session = test_util::session(&state, &alice).await;
+        let host = test_util::federation_peer(|request| {
+            let FederationWsRequest::MessagesCreate {
+                server_id,
+                channel_id,
+                new_message,
+            } = request
+            else {
+                return None;
+            };
+            let now = OffsetDateTime::now_utc();
+            Some(FederationWsReply::MessagesCreate(Message {
+                id: MessageId::new(),
+                channel_id,
+                server_id,
+                author: None,
+                body: new_message.body,
+                system: false,
+                created_at: now,
+                updated_at: now,
+                edited_at: None,
+                deleted: false,
+                reactions: Vec::new(),
+                attachments: new_message.attachments,
+            }))
+        })
+        .await;
+        let (server_id, channel_id) = (ServerId::new(), ChannelId::new());
+        let upload = attachments::upload(
+            &state,
+            &session,
+            server_id,
+            channel_id,
+            Some("notes.txt"),
+            Some("text/plain"),
+            b"hello".to_vec(),
+        )
+        .await
+        .unwrap();
+
+        let new_message = NewMessage {
+            author: alice.clone(),
+            body: "see attached".into(),
+            attachments: vec![upload.clone()],
+        };
+        let message = create(
+            &state,
+            &session,
+            server_id,
+            channel_id,
+            &new_message,
+            Some(&host),
+        )
+        .await
+        .unwrap();
+        assert_eq!(message.attachments, vec![upload.clone()]);
+        assert!(matches!(
+            queries::attachments::get_unattached(
+                &state.db_pool,
+                &[upload.id],
+                channel_id,
+                &alice,
+            )
+            .await,
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+}
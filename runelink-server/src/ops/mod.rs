@@ -1,8 +1,19 @@
 mod fanout;
 mod federation;
 
+pub mod attachments;
+pub mod bans;
 pub mod channels;
+pub mod cleanup;
+pub mod dms;
+pub mod invites;
+pub mod membership_sync;
 pub mod memberships;
+pub mod mentions;
 pub mod messages;
+pub mod presence;
+pub mod replay;
 pub mod servers;
+pub mod typing;
 pub mod users;
+pub mod webhooks;
@@ -0,0 +1,192 @@
+use runelink_types::{
+    ids::EventId,
+    server::ServerId,
+    user::UserRef,
+    ws::{ClientWsUpdate, FederationWsUpdate},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use super::federation;
+use crate::{
+    error::{ApiError, ApiResult},
+    ids::ConnId,
+    queries,
+    state::AppState,
+    ws::PresenceChange,
+};
+
+/// Get the members of a server that are currently online.
+///
+/// Remote members only show up once their home host has reported them.
+pub async fn get_online(
+    state: &AppState,
+    server_id: ServerId,
+) -> ApiResult<Vec<UserRef>> {
+    let members = if queries::servers::exists(&state.db_pool, server_id).await?
+    {
+        queries::memberships::get_user_refs_by_local_server(
+            &state.db_pool,
+            server_id,
+        )
+        .await?
+    } else {
+        state
+            .routing_index
+            .users_for_remote_server(server_id)
+            .await?
+    };
+    Ok(state
+        .client_ws_manager
+        .online_users(server_id, &members)
+        .await)
+}
+
+/// Spawns the task that announces local users going online or offline.
+pub fn spawn_announcer(state: AppState) {
+    tokio::spawn(async move {
+        let mut changes = state.client_ws_manager.presence_changes().await;
+        loop {
+            match changes.recv().await {
+                Ok(change) => {
+                    if let Err(error) = announce(&state, change).await {
+                        log::warn!("Failed to announce presence: {error}");
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    log::warn!("Presence announcer fell behind by {missed}");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Announce a local user's presence to subscribers of each of their servers,
+/// here and on the hosts that share those servers.
+async fn announce(state: &AppState, change: PresenceChange) -> ApiResult<()> {
+    let PresenceChange { user_ref, online } = change;
+    let memberships =
+        queries::memberships::get_by_user(state, user_ref.clone()).await?;
+    let server_ids = memberships.iter().map(|membership| membership.server.id);
+    let _ = state
+        .client_ws_manager
+        .send_update_to_presence_subscribers(
+            server_ids,
+            ClientWsUpdate::PresenceChanged {
+                user_ref: user_ref.clone(),
+                online,
+            },
+        )
+        .await;
+    for membership in memberships {
+        let server = membership.server;
+        let hosts = if state.config.is_remote_host(Some(server.host.as_str())) {
+            vec![server.host]
+        } else {
+            state.routing_index.hosts_for_server(server.id).await?
+        };
+        let _ = state
+            .federation_ws_manager
+            .send_update_to_hosts(
+                hosts,
+                FederationWsUpdate::PresenceChanged {
+                    server_id: server.id,
+                    user_ref: user_ref.clone(),
+                    online,
+                },
+            )
+            .await;
+    }
+    Ok(())
+}
+
+/// Apply a presence change reported over federation.
+///
+/// For a local server the report must come from the user's home host and
+/// is relayed to the other hosts with members there. For a remote server
+/// it must come from that server's host.
+pub async fn apply_federated(
+    state: &AppState,
+    conn_id: ConnId,
+    event_id: EventId,
+    server_id: ServerId,
+    user_ref: UserRef,
+    online: bool,
+) -> ApiResult<()> {
+    let is_local = federation::verify_member_update_source(
+        state, conn_id, server_id, &user_ref,
+    )
+    .await?;
+    let manager = &state.federation_ws_manager;
+    let Some(reported_by) = manager.authenticated_host(conn_id).await else {
+        return Err(ApiError::Unauthorized(
+            "Presence updates require an authenticated host".into(),
+        ));
+    };
+
+    let changed = state
+        .client_ws_manager
+        .set_remote_presence(server_id, user_ref.clone(), online, reported_by)
+        .await;
+    if !changed {
+        return Ok(());
+    }
+    let _ = state
+        .client_ws_manager
+        .send_update_to_presence_subscribers(
+            [server_id],
+            ClientWsUpdate::PresenceChanged {
+                user_ref: user_ref.clone(),
+                online,
+            },
+        )
+        .await;
+    if is_local {
+        let hosts = state
+            .routing_index
+            .hosts_for_server(server_id)
+            .await?
+            .into_iter()
+            .filter(|host| host != &user_ref.host)
+            .collect::<Vec<_>>();
+        let _ = manager
+            .relay_update_to_hosts(
+                hosts,
+                event_id,
+                FederationWsUpdate::PresenceChanged {
+                    server_id,
+                    user_ref,
+                    online,
+                },
+            )
+            .await;
+    }
+    Ok(())
+}
+
+/// Mark everyone a host reported as offline once its connection is gone.
+pub async fn forget_host(state: &AppState, host: &str) {
+    let forgotten = state.client_ws_manager.forget_remote_presence(host).await;
+    for (server_id, user_ref) in forgotten {
+        let _ = state
+            .client_ws_manager
+            .send_update_to_presence_subscribers(
+                [server_id],
+                ClientWsUpdate::PresenceChanged {
+                    user_ref,
+                    online: false,
+                },
+            )
+            .await;
+    }
+}
+
+/// Auth requirements for presence operations.
+pub mod auth {
+    use super::*;
+    use crate::auth::Requirement as Req;
+
+    pub fn subscribe(server_id: ServerId) -> Req {
+        Req::ServerMember(server_id).or_admin().client_only()
+    }
+}
@@ -0,0 +1,31 @@
+use runelink_types::{ids::EventId, server::ServerId, ws::ClientWsReplay};
+
+use crate::state::AppState;
+
+/// Updates about a server sent after `after_event_id`, for a client
+/// catching up after a reconnect.
+///
+/// Only recent updates are buffered; if the event has fallen out of the
+/// buffer the client is told to refetch the server instead.
+pub async fn since(
+    state: &AppState,
+    server_id: ServerId,
+    after_event_id: EventId,
+) -> ClientWsReplay {
+    state
+        .client_ws_manager
+        .replay_since(server_id, after_event_id)
+        .await
+}
+
+/// Auth requirements for replaying updates.
+pub mod auth {
+    use super::*;
+    use crate::auth::Requirement as Req;
+
+    /// Members only, without the admin override: the direct message server
+    /// has no members, so its updates are never replayed to anyone.
+    pub fn since(server_id: ServerId) -> Req {
+        Req::ServerMember(server_id).client_only()
+    }
+}
@@ -1,15 +1,21 @@
+use std::collections::HashMap;
+
 use runelink_types::{
     server::{
-        FullServerMembership, NewServer, NewServerMembership, Server, ServerId,
-        ServerMembership, ServerRole, ServerWithChannels,
+        ChannelWithLastMessage, FederationResyncFailure,
+        FederationResyncReport, FederationResyncRequest, FullServerMembership,
+        NewServer, Server, ServerAnalytics, ServerId, ServerMembership,
+        ServerRole, ServerSummary, ServerVisibility, ServerWithChannels,
     },
+    user::UserRef,
     ws::{
         ClientWsUpdate, FederationWsReply, FederationWsRequest,
         FederationWsUpdate,
     },
 };
+use time::{Duration, OffsetDateTime};
 
-use super::federation;
+use super::{federation, memberships};
 use crate::{
     auth::Session,
     error::{ApiError, ApiResult},
@@ -25,9 +31,11 @@ pub async fn create(
     new_server: &NewServer,
     target_host: Option<&str>,
 ) -> ApiResult<Server> {
+    new_server
+        .validate()
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
     // Handle local case
     if !state.config.is_remote_host(target_host) {
-        let server = queries::servers::insert(state, new_server).await?;
         // Get the creator's user identity
         // Since this requires HostAdmin (which requires client auth), these fields are always present
         let user_ref = session.user_ref.clone().ok_or_else(|| {
@@ -35,16 +43,9 @@ pub async fn create(
                 "Session missing user identity for server creation".into(),
             )
         })?;
-        // Ensure user exists (creates record for federated users from other hosts)
-        queries::users::ensure_exists(&state.db_pool, user_ref.clone()).await?;
-        let new_membership = NewServerMembership {
-            user_ref,
-            server_id: server.id,
-            server_host: server.host.clone(),
-            role: ServerRole::Admin,
-        };
-        let member =
-            queries::memberships::upsert_local(&state.db_pool, &new_membership)
+        // Committed before anything is announced
+        let (server, member) =
+            queries::servers::insert_with_owner(state, new_server, &user_ref)
                 .await?;
         let targets = fanout::resolve_server_targets(state, server.id).await?;
         fanout::fanout_update(
@@ -103,23 +104,32 @@ pub async fn create(
         };
         queries::memberships::insert_remote(&state.db_pool, &remote_membership)
             .await?;
+        state.routing_index.invalidate_server(server.id).await;
         Ok(server)
     }
 }
 
-/// List all servers (public).
+/// List servers on a host, with their member and channel counts.
+///
+/// Only public servers are listed unless `include_private` is set, which is
+/// limited to host admins and to the local host.
 pub async fn get_all(
     state: &AppState,
+    include_private: bool,
     target_host: Option<&str>,
-) -> ApiResult<Vec<Server>> {
+) -> ApiResult<Vec<ServerSummary>> {
     if !state.config.is_remote_host(target_host) {
         // Handle local case
-        // TODO: add visibility specification for servers
-        let servers = queries::servers::get_all(state).await?;
+        let servers = queries::servers::get_all(state, include_private).await?;
         Ok(servers)
     } else {
         // Fetch from remote host
         let host = target_host.unwrap();
+        if include_private {
+            return Err(ApiError::BadRequest(
+                "Private servers can only be listed on their own host".into(),
+            ));
+        }
         let reply = federation::request(
             state,
             host,
@@ -132,28 +142,67 @@ pub async fn get_all(
                 "Unexpected federation reply from {host} for servers.get_all"
             )));
         };
+        for summary in &servers {
+            queries::servers::upsert_remote(&state.db_pool, &summary.server)
+                .await?;
+            queries::servers::set_remote_counts(
+                &state.db_pool,
+                summary.server.id,
+                summary.member_count,
+                summary.channel_count,
+            )
+            .await?;
+        }
         Ok(servers)
     }
 }
 
-/// Get a server by ID (public).
+/// How long a cached remote server is served without re-federating.
+const REMOTE_SERVER_CACHE_TTL: Duration = Duration::minutes(5);
+
+/// Get a server by ID.
+///
+/// Private local servers must be authorized with `auth::get_by_id` first.
+/// For remote servers the home host enforces visibility, so `viewer` is
+/// delegated with the request.
+///
+/// Public remote servers are served from `cached_remote_servers` while the
+/// cached copy is younger than `REMOTE_SERVER_CACHE_TTL`, unless
+/// `force_refresh` is set. Private ones are always re-fetched. Every
+/// successful federated fetch refreshes the cache.
 pub async fn get_by_id(
     state: &AppState,
+    viewer: Option<&UserRef>,
     server_id: ServerId,
+    force_refresh: bool,
     target_host: Option<&str>,
 ) -> ApiResult<Server> {
     if !state.config.is_remote_host(target_host) {
         // Handle local case
-        // TODO: separate public and private server objects?
         let server = queries::servers::get_by_id(state, server_id).await?;
         Ok(server)
     } else {
         // Fetch from remote host
         let host = target_host.unwrap();
+        if !force_refresh {
+            let cached = queries::servers::get_cached_remote_by_id(
+                &state.db_pool,
+                server_id,
+                host,
+            )
+            .await?;
+            if let Some((server, synced_at)) = cached
+                && server.visibility == ServerVisibility::Public
+                && OffsetDateTime::now_utc() - synced_at
+                    < REMOTE_SERVER_CACHE_TTL
+            {
+                return Ok(server);
+            }
+        }
         let reply = federation::request(
             state,
             host,
-            None,
+            viewer.cloned(),
             FederationWsRequest::ServersGetById { server_id },
         )
         .await?;
@@ -162,26 +211,81 @@ pub async fn get_by_id(
                 "Unexpected federation reply from {host} for servers.get_by_id"
             )));
         };
+        queries::servers::upsert_remote(&state.db_pool, &server).await?;
         Ok(server)
     }
 }
 
-/// Get a server with its channels.
+/// Get a server by ID with its member and channel counts.
+///
+/// Takes the same authorization as `get_by_id`. Counts for remote servers
+/// are the ones their host last reported in a server list, or `None` if it
+/// hasn't.
+pub async fn get_summary(
+    state: &AppState,
+    viewer: Option<&UserRef>,
+    server_id: ServerId,
+    force_refresh: bool,
+    target_host: Option<&str>,
+) -> ApiResult<ServerSummary> {
+    if !state.config.is_remote_host(target_host) {
+        // Handle local case
+        queries::servers::get_summary_by_id(state, server_id).await
+    } else {
+        let server =
+            get_by_id(state, viewer, server_id, force_refresh, target_host)
+                .await?;
+        let (member_count, channel_count) =
+            queries::servers::get_remote_counts(&state.db_pool, server_id)
+                .await?;
+        Ok(ServerSummary {
+            server,
+            member_count,
+            channel_count,
+        })
+    }
+}
+
+/// Get a server with its channels, optionally including the most recent
+/// message in each channel.
 pub async fn get_with_channels(
     state: &AppState,
     session: &Session,
     server_id: ServerId,
+    include_last_messages: bool,
     target_host: Option<&str>,
 ) -> ApiResult<ServerWithChannels> {
     if !state.config.is_remote_host(target_host) {
         // Handle local case
-        let (server, channels) = tokio::join!(
-            queries::servers::get_by_id(state, server_id),
+        let (summary, channels) = tokio::join!(
+            queries::servers::get_summary_by_id(state, server_id),
             queries::channels::get_by_server(&state.db_pool, server_id),
         );
+        let summary = summary?;
+        let mut last_messages = if include_last_messages {
+            queries::messages::get_latest_by_server(
+                &state.db_pool,
+                server_id,
+                session.user_ref.as_ref(),
+            )
+            .await?
+            .into_iter()
+            .map(|message| (message.channel_id, message))
+            .collect()
+        } else {
+            HashMap::new()
+        };
+        let channels = channels?
+            .into_iter()
+            .map(|channel| ChannelWithLastMessage {
+                last_message: last_messages.remove(&channel.id),
+                channel,
+            })
+            .collect();
         Ok(ServerWithChannels {
-            server: server?,
-            channels: channels?,
+            server: summary.server,
+            channels,
+            member_count: summary.member_count,
         })
     } else {
         // Fetch from remote host using federation
@@ -196,7 +300,10 @@ pub async fn get_with_channels(
             state,
             host,
             Some(user_ref),
-            FederationWsRequest::ServersGetWithChannels { server_id },
+            FederationWsRequest::ServersGetWithChannels {
+                server_id,
+                include_last_messages,
+            },
         )
         .await?;
         let FederationWsReply::ServersGetWithChannels(server_with_channels) =
@@ -206,10 +313,82 @@ pub async fn get_with_channels(
                 "Unexpected federation reply from {host} for servers.get_with_channels"
             )));
         };
+        if server_with_channels.member_count.is_some() {
+            queries::servers::set_remote_counts(
+                &state.db_pool,
+                server_id,
+                server_with_channels.member_count,
+                Some(server_with_channels.channels.len() as i64),
+            )
+            .await?;
+        }
         Ok(server_with_channels)
     }
 }
 
+/// Largest analytics window, in days.
+pub const MAX_ANALYTICS_WINDOW_DAYS: u32 = 90;
+const ANALYTICS_CACHE_TTL: Duration = Duration::seconds(60);
+const ANALYTICS_TOP_POSTERS: i64 = 10;
+
+/// Get message activity for a local server over the last `window_days` days.
+///
+/// Results are cached briefly per server and window.
+pub async fn get_analytics(
+    state: &AppState,
+    server_id: ServerId,
+    window_days: u32,
+) -> ApiResult<ServerAnalytics> {
+    if window_days == 0 || window_days > MAX_ANALYTICS_WINDOW_DAYS {
+        return Err(ApiError::BadRequest(format!(
+            "Analytics window must be between 1 and {MAX_ANALYTICS_WINDOW_DAYS} days"
+        )));
+    }
+    let now = OffsetDateTime::now_utc();
+    let cache_key = (server_id, window_days);
+    if let Some((computed_at, analytics)) =
+        state.analytics_cache.read().await.get(&cache_key)
+        && now - *computed_at < ANALYTICS_CACHE_TTL
+    {
+        return Ok(analytics.clone());
+    }
+
+    // Ensure the server exists and is local
+    queries::servers::get_by_id(state, server_id).await?;
+    let since = now - Duration::days(window_days.into());
+    let (daily_message_counts, active_members, top_posters) = tokio::join!(
+        queries::analytics::get_daily_message_counts(
+            &state.db_pool,
+            server_id,
+            since,
+        ),
+        queries::analytics::count_active_members(
+            &state.db_pool,
+            server_id,
+            since,
+        ),
+        queries::analytics::get_top_posters(
+            &state.db_pool,
+            server_id,
+            since,
+            ANALYTICS_TOP_POSTERS,
+        ),
+    );
+    let analytics = ServerAnalytics {
+        server_id,
+        window_days,
+        daily_message_counts: daily_message_counts?,
+        active_members: active_members?,
+        top_posters: top_posters?,
+    };
+    state
+        .analytics_cache
+        .write()
+        .await
+        .insert(cache_key, (now, analytics.clone()));
+    Ok(analytics)
+}
+
 /// Delete a server by ID.
 pub async fn delete(
     state: &AppState,
@@ -221,6 +400,7 @@ pub async fn delete(
     if !state.config.is_remote_host(target_host) {
         let targets = fanout::resolve_server_targets(state, server_id).await?;
         queries::servers::delete(state, server_id).await?;
+        state.routing_index.invalidate_server(server_id).await;
         fanout::fanout_update(
             state,
             targets,
@@ -254,6 +434,239 @@ pub async fn delete(
     }
 }
 
+/// Make `new_admin` an admin of a server, and with `demote_self` make the
+/// caller a plain member, in one step so the server never lacks an admin.
+///
+/// Returns the new admin's membership.
+pub async fn transfer_ownership(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    new_admin: UserRef,
+    demote_self: bool,
+    target_host: Option<&str>,
+) -> ApiResult<FullServerMembership> {
+    let user_ref = session.user_ref.clone().ok_or_else(|| {
+        ApiError::Forbidden(
+            "User reference required for ownership transfer".into(),
+        )
+    })?;
+    // Handle local case
+    if !state.config.is_remote_host(target_host) {
+        let demoted = queries::memberships::transfer_admin(
+            &state.db_pool,
+            server_id,
+            &user_ref,
+            &new_admin,
+            demote_self,
+        )
+        .await?;
+        if demoted {
+            memberships::announce_local_membership(state, server_id, user_ref)
+                .await?;
+        }
+        memberships::announce_local_membership(state, server_id, new_admin)
+            .await
+    } else {
+        // Transfer on remote host, acting as the session user
+        let host = target_host.unwrap();
+        let reply = federation::request(
+            state,
+            host,
+            Some(user_ref),
+            FederationWsRequest::ServersTransferOwnership {
+                server_id,
+                new_admin: new_admin.clone(),
+                demote_self,
+            },
+        )
+        .await?;
+        let FederationWsReply::ServersTransferOwnership(membership) = reply
+        else {
+            return Err(ApiError::Internal(format!(
+                "Unexpected federation reply from {host} for servers.transfer_ownership"
+            )));
+        };
+        // Keep the local cache in step when the new admin is one of ours
+        if new_admin.host == state.config.public_host() {
+            queries::servers::upsert_remote(&state.db_pool, &membership.server)
+                .await?;
+            queries::memberships::insert_remote(
+                &state.db_pool,
+                &membership.clone().into(),
+            )
+            .await?;
+            state.routing_index.invalidate_server(server_id).await;
+        }
+        Ok(membership)
+    }
+}
+
+/// Re-fetch cached remote servers and local users' memberships in them from
+/// their home hosts, repairing caches that drifted while updates were missed.
+///
+/// Each server is re-synced independently; a failure on one is recorded in
+/// the report and does not stop the rest.
+pub async fn resync_remote(
+    state: &AppState,
+    request: &FederationResyncRequest,
+) -> ApiResult<FederationResyncReport> {
+    if request.server_id.is_none() && request.host.is_none() {
+        return Err(ApiError::BadRequest(
+            "Either server_id or host is required".into(),
+        ));
+    }
+    let cached = queries::servers::get_cached_remote_refs(
+        &state.db_pool,
+        request.server_id,
+        request.host.as_deref(),
+    )
+    .await?;
+    if cached.is_empty() {
+        return Err(ApiError::NotFound);
+    }
+    let mut report = FederationResyncReport::default();
+    for (server_id, host) in cached {
+        if let Err(error) =
+            resync_remote_server(state, server_id, &host, &mut report).await
+        {
+            report.failures.push(FederationResyncFailure {
+                server_id,
+                error: error.to_string(),
+            });
+        }
+    }
+    Ok(report)
+}
+
+async fn resync_remote_server(
+    state: &AppState,
+    server_id: ServerId,
+    host: &str,
+    report: &mut FederationResyncReport,
+) -> ApiResult<()> {
+    let cached_users = state
+        .routing_index
+        .users_for_remote_server(server_id)
+        .await?;
+    // Private servers are only readable by members, so ask as one of ours.
+    let reply = match federation::request(
+        state,
+        host,
+        cached_users.first().cloned(),
+        FederationWsRequest::ServersGetById { server_id },
+    )
+    .await
+    {
+        Ok(reply) => reply,
+        Err(ApiError::NotFound) => {
+            // The server is gone upstream; notify members, then drop caches.
+            state
+                .client_ws_manager
+                .send_update_to_users(
+                    &cached_users,
+                    ClientWsUpdate::ServerDeleted { server_id },
+                )
+                .await;
+            queries::servers::delete_cached_remote(&state.db_pool, server_id)
+                .await?;
+            state.routing_index.invalidate_server(server_id).await;
+            report.servers_removed.push(server_id);
+            return Ok(());
+        }
+        Err(error) => return Err(error),
+    };
+    let FederationWsReply::ServersGetById(server) = reply else {
+        return Err(ApiError::Internal(format!(
+            "Unexpected federation reply from {host} for servers.get_by_id"
+        )));
+    };
+    let reply = federation::request(
+        state,
+        host,
+        None,
+        FederationWsRequest::MembershipsGetMembersByServer { server_id },
+    )
+    .await?;
+    let FederationWsReply::MembershipsGetMembersByServer(members) = reply
+    else {
+        return Err(ApiError::Internal(format!(
+            "Unexpected federation reply from {host} for memberships.get_members_by_server"
+        )));
+    };
+
+    queries::servers::upsert_remote(&state.db_pool, &server).await?;
+    report.servers_refreshed.push(server_id);
+    state
+        .client_ws_manager
+        .send_update_to_users(
+            &cached_users,
+            ClientWsUpdate::ServerUpserted(server.clone()),
+        )
+        .await;
+
+    let local_host = state.config.public_host();
+    let local_members = members
+        .into_iter()
+        .filter(|member| member.user.host == local_host)
+        .collect::<Vec<_>>();
+    let fresh_users = local_members
+        .iter()
+        .map(|member| member.user.as_ref())
+        .collect::<Vec<_>>();
+    for member in local_members {
+        let membership = ServerMembership {
+            server: server.clone(),
+            user_ref: member.user.as_ref(),
+            role: member.role,
+            joined_at: member.joined_at,
+            updated_at: member.updated_at,
+            synced_at: None,
+        };
+        let membership =
+            queries::memberships::insert_remote(&state.db_pool, &membership)
+                .await?;
+        state.routing_index.invalidate_server(server_id).await;
+        report.memberships_upserted += 1;
+        state
+            .client_ws_manager
+            .send_update_to_users(
+                &fresh_users,
+                ClientWsUpdate::MembershipUpserted(
+                    membership.as_full(member.user),
+                ),
+            )
+            .await;
+    }
+    for user_ref in stale_memberships(cached_users, &fresh_users) {
+        queries::memberships::delete_remote(
+            &state.db_pool,
+            server_id,
+            user_ref.clone(),
+        )
+        .await?;
+        state.routing_index.invalidate_server(server_id).await;
+        report.memberships_removed += 1;
+        let update = ClientWsUpdate::MembershipDeleted {
+            server_id,
+            user_ref: user_ref.clone(),
+        };
+        state
+            .client_ws_manager
+            .send_update_to_users(fresh_users.iter().chain([&user_ref]), update)
+            .await;
+    }
+    Ok(())
+}
+
+/// Cached members that are missing from the authoritative member list.
+fn stale_memberships(cached: Vec<UserRef>, fresh: &[UserRef]) -> Vec<UserRef> {
+    cached
+        .into_iter()
+        .filter(|user_ref| !fresh.contains(user_ref))
+        .collect()
+}
+
 /// Auth requirements for server operations.
 pub mod auth {
     use super::*;
@@ -264,14 +677,45 @@ pub mod auth {
         Req::Always.or_admin().client_only()
     }
 
+    pub fn get_all_including_private() -> Req {
+        Req::HostAdmin.client_only()
+    }
+
+    /// Returns `None` when no auth is needed, i.e. for public servers and
+    /// for remote servers, whose home host checks visibility itself.
+    pub async fn get_by_id(
+        state: &AppState,
+        server_id: ServerId,
+        target_host: Option<&str>,
+    ) -> ApiResult<Option<Req>> {
+        if state.config.is_remote_host(target_host) {
+            return Ok(None);
+        }
+        let server = queries::servers::get_by_id(state, server_id).await?;
+        Ok((server.visibility == ServerVisibility::Private)
+            .then(|| Req::ServerMember(server_id).or_admin().client_only()))
+    }
+
     pub fn get_with_channels(server_id: ServerId) -> Req {
         Req::ServerMember(server_id).or_admin().client_only()
     }
 
+    pub fn get_analytics(server_id: ServerId) -> Req {
+        Req::ServerAdmin(server_id).or_admin().client_only()
+    }
+
     pub fn delete(server_id: ServerId) -> Req {
         Req::ServerAdmin(server_id).or_admin().client_only()
     }
 
+    pub fn transfer_ownership(server_id: ServerId) -> Req {
+        Req::ServerAdmin(server_id).or_admin().client_only()
+    }
+
+    pub fn resync_remote() -> Req {
+        Req::HostAdmin.client_only()
+    }
+
     pub mod federated {
         use super::*;
 
@@ -280,6 +724,16 @@ pub mod auth {
             Req::Always.federated_only()
         }
 
+        /// Returns `None` for public servers, which any host may read.
+        pub async fn get_by_id(
+            state: &AppState,
+            server_id: ServerId,
+        ) -> ApiResult<Option<Req>> {
+            let server = queries::servers::get_by_id(state, server_id).await?;
+            Ok((server.visibility == ServerVisibility::Private)
+                .then(|| Req::ServerMember(server_id).federated_only()))
+        }
+
         pub fn get_with_channels(server_id: ServerId) -> Req {
             Req::ServerMember(server_id).federated_only()
         }
@@ -287,5 +741,198 @@ pub mod auth {
         pub fn delete(server_id: ServerId) -> Req {
             Req::ServerAdmin(server_id).federated_only()
         }
+
+        pub fn transfer_ownership(server_id: ServerId) -> Req {
+            Req::ServerAdmin(server_id).federated_only()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use runelink_types::{message::NewMessage, server::NewServerInvite};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{db::DbPool, test_util};
+
+    fn user(name: &str) -> UserRef {
+        UserRef::new(name.into(), "example.com".into())
+    }
+
+    #[test]
+    fn test_stale_memberships_are_cached_but_not_fresh() {
+        let cached = vec![user("alice"), user("bob"), user("carol")];
+        let fresh = vec![user("alice"), user("carol"), user("dave")];
+        assert_eq!(stale_memberships(cached, &fresh), vec![user("bob")]);
+    }
+
+    #[test]
+    fn test_no_stale_memberships_when_in_sync() {
+        let cached = vec![user("alice")];
+        assert!(stale_memberships(cached, &[user("alice")]).is_empty());
+    }
+
+    #[sqlx::test]
+    async fn test_delete_leaves_no_orphaned_rows(pool: DbPool) {
+        let state = test_util::state(pool);
+        let owner = test_util::local_user(&state, "owner").await.as_ref();
+        let member = test_util::local_user(&state, "member").await.as_ref();
+        let server = test_util::server(&state, &owner, "Doomed").await;
+        test_util::join(&state, server.id, &member).await;
+        let mut channel_ids = Vec::new();
+        let mut message_ids = Vec::new();
+        for title in ["general", "random"] {
+            let channel = test_util::channel(&state, server.id, title).await;
+            channel_ids.push(channel.id.as_uuid());
+            for author in [&owner, &member] {
+                let new_message = NewMessage {
+                    author: author.clone(),
+                    body: format!("hello from {author}"),
+                    attachments: Vec::new(),
+                };
+                let message = queries::messages::insert(
+                    &state.db_pool,
+                    channel.id,
+                    &new_message,
+                    false,
+                )
+                .await
+                .unwrap();
+                queries::messages::add_reaction(
+                    &state.db_pool,
+                    message.id,
+                    &member,
+                    "👍",
+                )
+                .await
+                .unwrap();
+                queries::read_states::upsert(
+                    &state.db_pool,
+                    server.id,
+                    channel.id,
+                    message.id,
+                    author,
+                )
+                .await
+                .unwrap();
+                message_ids.push(message.id.as_uuid());
+            }
+            queries::webhooks::insert(
+                &state.db_pool,
+                server.id,
+                channel.id,
+                &format!("token-{title}"),
+                &owner,
+            )
+            .await
+            .unwrap();
+        }
+        let new_invite = NewServerInvite::default();
+        queries::invites::insert(
+            &state,
+            "doomed",
+            server.id,
+            &owner,
+            &new_invite,
+        )
+        .await
+        .unwrap();
+
+        let session = test_util::session(&state, &owner).await;
+        delete(&state, &session, server.id, None).await.unwrap();
+
+        // Every column that points at the server, one of its channels or
+        // one of its messages, in any table
+        let columns: Vec<(String, String)> = sqlx::query_as(
+            "SELECT table_name::TEXT, column_name::TEXT \
+             FROM information_schema.columns \
+             WHERE table_schema = 'public' AND data_type = 'uuid' \
+                AND column_name ~ '(server|channel|message)_id$'",
+        )
+        .fetch_all(state.db_pool.as_ref())
+        .await
+        .unwrap();
+        assert!(!columns.is_empty());
+        let deleted_ids: Vec<Uuid> = [server.id.as_uuid()]
+            .into_iter()
+            .chain(channel_ids)
+            .chain(message_ids)
+            .collect();
+        for (table, column) in columns {
+            let orphans: i64 = sqlx::query_scalar(&format!(
+                "SELECT COUNT(*) FROM {table} WHERE {column} = ANY($1)"
+            ))
+            .bind(&deleted_ids)
+            .fetch_one(state.db_pool.as_ref())
+            .await
+            .unwrap();
+            assert_eq!(orphans, 0, "{table}.{column} has orphaned rows");
+        }
+        for table in ["servers", "channels", "messages"] {
+            let orphans: i64 = sqlx::query_scalar(&format!(
+                "SELECT COUNT(*) FROM {table} WHERE id = ANY($1)"
+            ))
+            .bind(&deleted_ids)
+            .fetch_one(state.db_pool.as_ref())
+            .await
+            .unwrap();
+            assert_eq!(orphans, 0, "{table} still has deleted rows");
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_each_channel_carries_its_last_message(pool: DbPool) {
+        let state = test_util::state(pool);
+        let owner = test_util::local_user(&state, "owner").await.as_ref();
+        let server = test_util::server(&state, &owner, "Server").await;
+        let general = test_util::channel(&state, server.id, "general").await;
+        let empty = test_util::channel(&state, server.id, "empty").await;
+        let mut latest = None;
+        for body in ["first", "second"] {
+            let new_message = NewMessage {
+                author: owner.clone(),
+                body: body.into(),
+                attachments: Vec::new(),
+            };
+            latest = Some(
+                queries::messages::insert(
+                    &state.db_pool,
+                    general.id,
+                    &new_message,
+                    false,
+                )
+                .await
+                .unwrap(),
+            );
+        }
+        let session = test_util::session(&state, &owner).await;
+
+        let with_previews =
+            get_with_channels(&state, &session, server.id, true, None)
+                .await
+                .unwrap();
+        let preview = |channel_id| {
+            with_previews
+                .channels
+                .iter()
+                .find(|entry| entry.channel.id == channel_id)
+                .unwrap()
+                .last_message
+                .clone()
+        };
+        assert_eq!(preview(general.id), latest);
+        assert_eq!(preview(empty.id), None);
+
+        let without_previews =
+            get_with_channels(&state, &session, server.id, false, None)
+                .await
+                .unwrap();
+        assert!(
+            without_previews
+                .channels
+                .iter()
+                .all(|entry| entry.last_message.is_none())
+        );
     }
 }
@@ -0,0 +1,180 @@
+use runelink_types::{
+    channel::ChannelId,
+    ids::EventId,
+    server::ServerId,
+    user::UserRef,
+    ws::{ClientWsUpdate, FederationWsUpdate},
+};
+use time::Duration;
+
+use super::federation;
+use crate::{
+    auth::Session,
+    error::{ApiError, ApiResult},
+    ids::ConnId,
+    queries,
+    state::AppState,
+};
+
+/// Shortest gap between typing indicators from one connection; anything
+/// sent sooner is dropped.
+pub const TYPING_INTERVAL: Duration = Duration::seconds(3);
+
+/// Tell a channel's subscribers that the session user is typing.
+///
+/// Nothing is stored. For a remote server the indicator goes to the
+/// server's host, which relays it to the other participating hosts.
+pub async fn send(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    channel_id: ChannelId,
+) -> ApiResult<()> {
+    let user_ref = session.user_ref.as_ref().ok_or_else(|| {
+        ApiError::Forbidden(
+            "User reference required for typing indicators".into(),
+        )
+    })?;
+    let is_local = queries::servers::exists(&state.db_pool, server_id).await?;
+    let (local_users, hosts) = if is_local {
+        state
+            .routing_index
+            .targets_for_local_server(server_id)
+            .await?
+    } else {
+        (
+            state
+                .routing_index
+                .users_for_remote_server(server_id)
+                .await?,
+            queries::servers::get_cached_remote_refs(
+                &state.db_pool,
+                Some(server_id),
+                None,
+            )
+            .await?
+            .into_iter()
+            .map(|(_, host)| host)
+            .collect(),
+        )
+    };
+    relay(
+        state,
+        EventId::new(),
+        server_id,
+        channel_id,
+        user_ref,
+        local_users,
+        hosts,
+    )
+    .await;
+    Ok(())
+}
+
+/// Apply a typing indicator reported over federation.
+///
+/// For a local server it must come from the user's home host and is relayed
+/// to the other hosts with members there. For a remote server it must come
+/// from that server's host.
+pub async fn apply_federated(
+    state: &AppState,
+    conn_id: ConnId,
+    event_id: EventId,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    user_ref: UserRef,
+) -> ApiResult<()> {
+    let is_local = federation::verify_member_update_source(
+        state, conn_id, server_id, &user_ref,
+    )
+    .await?;
+    if is_local {
+        let local_users = state
+            .routing_index
+            .users_for_local_server(server_id)
+            .await?;
+        let hosts = state
+            .routing_index
+            .hosts_for_server(server_id)
+            .await?
+            .into_iter()
+            .filter(|host| host != &user_ref.host)
+            .collect();
+        relay(
+            state,
+            event_id,
+            server_id,
+            channel_id,
+            &user_ref,
+            local_users,
+            hosts,
+        )
+        .await;
+    } else {
+        let local_users = state
+            .routing_index
+            .users_for_remote_server(server_id)
+            .await?;
+        relay(
+            state,
+            event_id,
+            server_id,
+            channel_id,
+            &user_ref,
+            local_users,
+            Vec::new(),
+        )
+        .await;
+    }
+    Ok(())
+}
+
+/// Push a typing indicator to the channel's local subscribers other than
+/// the typing user, and to the given hosts (best effort) as `event_id`.
+async fn relay(
+    state: &AppState,
+    event_id: EventId,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    user_ref: &UserRef,
+    local_users: Vec<UserRef>,
+    hosts: Vec<String>,
+) {
+    let recipients = local_users
+        .into_iter()
+        .filter(|local_user| local_user != user_ref);
+    let _ = state
+        .client_ws_manager
+        .send_update_to_subscribers(
+            recipients,
+            channel_id,
+            ClientWsUpdate::Typing {
+                server_id,
+                channel_id,
+                user_ref: user_ref.clone(),
+            },
+        )
+        .await;
+    let _ = state
+        .federation_ws_manager
+        .relay_update_to_hosts(
+            hosts,
+            event_id,
+            FederationWsUpdate::Typing {
+                server_id,
+                channel_id,
+                user_ref: user_ref.clone(),
+            },
+        )
+        .await;
+}
+
+/// Auth requirements for typing indicators.
+pub mod auth {
+    use super::*;
+    use crate::auth::Requirement as Req;
+
+    pub fn send(server_id: ServerId) -> Req {
+        Req::ServerMember(server_id).or_admin().client_only()
+    }
+}
@@ -1,6 +1,12 @@
+use std::{collections::HashSet, time::Duration};
+
+use futures_util::future::join_all;
+use log::warn;
 use runelink_client::util::get_api_url;
 use runelink_types::{
-    user::{NewUser, User, UserRef},
+    auth::{AdminCreateUserRequest, AdminCreateUserResponse, SessionInfo},
+    user::{NewUser, User, UserProfileUpdate, UserQuery, UserRef, UserRole},
+    validation::{validate_avatar_url, validate_display_name},
     ws::{
         ClientWsUpdate, FederationWsReply, FederationWsRequest,
         FederationWsUpdate,
@@ -10,18 +16,33 @@ use runelink_types::{
 use super::federation;
 use crate::{
     auth::Session,
+    auth_service,
     error::{ApiError, ApiResult},
     queries,
     state::AppState,
 };
 
 /// Create a new user.
+///
+/// Only host admins may create admins; anyone else gets `UserRole::User`
+/// regardless of the requested role.
 pub async fn create(
     state: &AppState,
-    _session: &Session,
+    session: &mut Session,
     new_user: &NewUser,
 ) -> ApiResult<User> {
-    let user = queries::users::insert(&state.db_pool, new_user).await?;
+    new_user
+        .validate()
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    let creator_is_admin = session
+        .lookup_user(state)
+        .await?
+        .is_some_and(|user| user.role == UserRole::Admin);
+    let new_user = NewUser {
+        role: effective_role(new_user.role, creator_is_admin),
+        ..new_user.clone()
+    };
+    let user = queries::users::insert(&state.db_pool, &new_user).await?;
     let _ = state
         .client_ws_manager
         .broadcast_update(ClientWsUpdate::UserUpserted(user.clone()))
@@ -29,13 +50,52 @@ pub async fn create(
     Ok(user)
 }
 
-/// List all users (public).
+fn effective_role(requested: UserRole, creator_is_admin: bool) -> UserRole {
+    if creator_is_admin {
+        requested
+    } else {
+        UserRole::User
+    }
+}
+
+/// Create a local account as a host admin, bypassing `signups_enabled`.
+pub async fn admin_create(
+    state: &AppState,
+    _session: &Session,
+    request: AdminCreateUserRequest,
+) -> ApiResult<AdminCreateUserResponse> {
+    let created = auth_service::admin_create_account(state, request).await?;
+    let _ = state
+        .client_ws_manager
+        .broadcast_update(ClientWsUpdate::UserUpserted(created.user.clone()))
+        .await;
+    Ok(created)
+}
+
+/// Page size used when a user listing gives no limit.
+pub const DEFAULT_USER_PAGE_SIZE: u32 = 50;
+/// Largest page of users returned at once.
+pub const MAX_USER_PAGE_SIZE: u32 = 200;
+
+/// List a page of users (public).
 pub async fn get_all(
     state: &AppState,
+    query: &UserQuery,
     target_host: Option<&str>,
 ) -> ApiResult<Vec<User>> {
     if !state.config.is_remote_host(target_host) {
-        let users = queries::users::get_all(&state.db_pool).await?;
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_USER_PAGE_SIZE)
+            .clamp(1, MAX_USER_PAGE_SIZE);
+        let users = queries::users::get_all(
+            &state.db_pool,
+            query.name_prefix.as_deref(),
+            query.host.as_deref(),
+            limit,
+            query.offset.unwrap_or(0),
+        )
+        .await?;
         Ok(users)
     } else {
         let host = target_host.unwrap();
@@ -43,7 +103,9 @@ pub async fn get_all(
             state,
             host,
             None,
-            FederationWsRequest::UsersGetAll,
+            FederationWsRequest::UsersGetAll {
+                query: query.clone(),
+            },
         )
         .await?;
         let FederationWsReply::UsersGetAll(users) = reply else {
@@ -82,6 +144,156 @@ pub async fn get_by_ref(
     }
 }
 
+/// Most users one host returns for a search.
+const SEARCH_LIMIT: u32 = 25;
+/// How long a search waits for each other host before leaving it out.
+const SEARCH_HOST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Find users by name on this host and, concurrently, on `hosts` (public).
+///
+/// Each host only reports its own users. Hosts that fail or time out are
+/// left out of the results rather than failing the search.
+pub async fn search(
+    state: &AppState,
+    query: &str,
+    hosts: &[String],
+) -> ApiResult<Vec<User>> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Err(ApiError::BadRequest(
+            "Search query must not be empty".into(),
+        ));
+    }
+    let local = queries::users::search(
+        &state.db_pool,
+        &state.config.public_host(),
+        query,
+        SEARCH_LIMIT,
+    )
+    .await?;
+
+    let mut remote_hosts = Vec::<&str>::new();
+    for host in hosts {
+        if state.config.is_remote_host(Some(host))
+            && !remote_hosts.contains(&host.as_str())
+        {
+            remote_hosts.push(host);
+        }
+    }
+    let remote = join_all(
+        remote_hosts
+            .into_iter()
+            .map(|host| search_remote(state, host, query)),
+    )
+    .await;
+
+    let mut batches = vec![local];
+    batches.extend(remote.into_iter().flatten());
+    Ok(merge_users(batches))
+}
+
+/// A remote host's own users matching `query`, or `None` if it couldn't
+/// answer in time.
+async fn search_remote(
+    state: &AppState,
+    host: &str,
+    query: &str,
+) -> Option<Vec<User>> {
+    let request = federation::request(
+        state,
+        host,
+        None,
+        FederationWsRequest::UsersSearch {
+            query: query.to_string(),
+        },
+    );
+    let reply = match tokio::time::timeout(SEARCH_HOST_TIMEOUT, request).await {
+        Ok(Ok(reply)) => reply,
+        Ok(Err(error)) => {
+            warn!("User search on {host} failed: {error}");
+            return None;
+        }
+        Err(_) => {
+            warn!("User search on {host} timed out");
+            return None;
+        }
+    };
+    let FederationWsReply::UsersSearch(users) = reply else {
+        warn!("Unexpected federation reply from {host} for users.search");
+        return None;
+    };
+    Some(users.into_iter().filter(|user| user.host == host).collect())
+}
+
+/// Concatenates search results, keeping the first copy of each user.
+fn merge_users(batches: Vec<Vec<User>>) -> Vec<User> {
+    let mut seen = HashSet::new();
+    batches
+        .into_iter()
+        .flatten()
+        .filter(|user| seen.insert(user.as_ref()))
+        .collect()
+}
+
+/// Update a local user's display name and avatar.
+///
+/// The new profile is pushed to local clients and to every host the user
+/// holds a remote membership on, so their cached copies stay current.
+pub async fn update_profile(
+    state: &AppState,
+    _session: &Session,
+    user_ref: &UserRef,
+    update: &UserProfileUpdate,
+) -> ApiResult<User> {
+    if user_ref.host != state.config.public_host() {
+        return Err(ApiError::BadRequest(
+            "Can only update profiles of users on their home server".into(),
+        ));
+    }
+    let update = normalize_profile_update(update)?;
+    let user =
+        queries::users::update_profile(&state.db_pool, user_ref, &update)
+            .await?;
+    let foreign_hosts = queries::memberships::get_remote_server_hosts_for_user(
+        &state.db_pool,
+        user_ref.clone(),
+    )
+    .await?;
+    let _ = state
+        .client_ws_manager
+        .broadcast_update(ClientWsUpdate::UserUpserted(user.clone()))
+        .await;
+    let _ = state
+        .federation_ws_manager
+        .send_update_to_hosts(
+            foreign_hosts,
+            FederationWsUpdate::UserUpserted(user.clone()),
+        )
+        .await;
+    Ok(user)
+}
+
+fn normalize_profile_update(
+    update: &UserProfileUpdate,
+) -> ApiResult<UserProfileUpdate> {
+    let display_name = update
+        .display_name
+        .as_deref()
+        .map(validate_display_name)
+        .transpose()
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    let avatar_url = update
+        .avatar_url
+        .as_deref()
+        .map(validate_avatar_url)
+        .transpose()
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    Ok(UserProfileUpdate {
+        display_name,
+        avatar_url,
+    })
+}
+
 /// Delete a user from their home server.
 pub async fn delete_home_user(
     state: &AppState,
@@ -102,12 +314,24 @@ pub async fn delete_home_user(
     )
     .await?;
 
+    let mut audience = queries::memberships::get_user_refs_sharing_server_with(
+        &state.db_pool,
+        user_ref,
+    )
+    .await?;
+    audience.push(user_ref.clone());
+
     queries::users::delete(&state.db_pool, user_ref.clone()).await?;
+    // Their memberships went with them
+    state.routing_index.invalidate_all().await;
     let _ = state
         .client_ws_manager
-        .broadcast_update(ClientWsUpdate::UserDeleted {
-            user_ref: user_ref.clone(),
-        })
+        .send_update_to_users(
+            audience,
+            ClientWsUpdate::UserDeleted {
+                user_ref: user_ref.clone(),
+            },
+        )
         .await;
     let _ = state
         .federation_ws_manager
@@ -128,7 +352,7 @@ pub async fn delete_remote_user_record(
     user_ref: &UserRef,
 ) -> ApiResult<()> {
     let session_user_ref = session.user_ref.clone().ok_or_else(|| {
-        ApiError::AuthError(
+        ApiError::Forbidden(
             "User reference required for federated user deletion".into(),
         )
     })?;
@@ -149,21 +373,32 @@ pub async fn delete_remote_user_record(
     let expected_home_server_url =
         get_api_url(&session_user_ref.host, state.config.secure);
     let federation_claims = session.federation.as_ref().ok_or_else(|| {
-        ApiError::AuthError("Federation claims required".into())
+        ApiError::Forbidden("Federation claims required".into())
     })?;
 
     if federation_claims.iss != expected_home_server_url {
-        return Err(ApiError::AuthError(
+        return Err(ApiError::Forbidden(
             "Only the home server can delete a user".into(),
         ));
     }
 
+    let audience = queries::memberships::get_user_refs_sharing_server_with(
+        &state.db_pool,
+        user_ref,
+    )
+    .await?;
+
     queries::users::delete(&state.db_pool, user_ref.clone()).await?;
+    // Their memberships went with them
+    state.routing_index.invalidate_all().await;
     let _ = state
         .client_ws_manager
-        .broadcast_update(ClientWsUpdate::UserDeleted {
-            user_ref: user_ref.clone(),
-        })
+        .send_update_to_users(
+            audience,
+            ClientWsUpdate::UserDeleted {
+                user_ref: user_ref.clone(),
+            },
+        )
         .await;
     Ok(())
 }
@@ -198,6 +433,39 @@ pub async fn get_associated_hosts(
 }
 
 /// Auth requirements for user operations.
+/// List a local user's active sessions, i.e. their unrevoked refresh tokens.
+pub async fn get_sessions(
+    state: &AppState,
+    _session: &Session,
+    user_ref: &UserRef,
+) -> ApiResult<Vec<SessionInfo>> {
+    ensure_home_user(state, user_ref)?;
+    queries::tokens::get_active_by_user(&state.db_pool, user_ref).await
+}
+
+/// Revoke every session of a local user, signing them out everywhere.
+///
+/// Access tokens already issued stay valid until they expire, but can no
+/// longer be refreshed.
+pub async fn revoke_sessions(
+    state: &AppState,
+    _session: &Session,
+    user_ref: &UserRef,
+) -> ApiResult<()> {
+    ensure_home_user(state, user_ref)?;
+    queries::tokens::revoke_all_by_user(&state.db_pool, user_ref).await?;
+    Ok(())
+}
+
+fn ensure_home_user(state: &AppState, user_ref: &UserRef) -> ApiResult<()> {
+    if user_ref.host != state.config.public_host() {
+        return Err(ApiError::BadRequest(
+            "Sessions are managed by the user's home server".into(),
+        ));
+    }
+    Ok(())
+}
+
 pub mod auth {
     use super::*;
     use crate::auth::Requirement as Req;
@@ -206,10 +474,22 @@ pub mod auth {
         Req::Client
     }
 
+    pub fn admin_create() -> Req {
+        Req::HostAdmin.client_only()
+    }
+
     pub fn delete(user_ref: UserRef) -> Req {
         Req::User(user_ref).or_admin().client_only()
     }
 
+    pub fn update_profile(user_ref: UserRef) -> Req {
+        Req::User(user_ref).or_admin().client_only()
+    }
+
+    pub fn sessions(user_ref: UserRef) -> Req {
+        Req::User(user_ref).or_admin().client_only()
+    }
+
     pub mod federated {
         use super::*;
 
@@ -218,3 +498,112 @@ pub mod auth {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use runelink_types::ws::ClientWsEnvelope;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{db::DbPool, test_util};
+
+    #[test]
+    fn test_non_admin_cannot_assign_admin_role() {
+        assert_eq!(effective_role(UserRole::Admin, false), UserRole::User);
+        assert_eq!(effective_role(UserRole::User, false), UserRole::User);
+    }
+
+    #[test]
+    fn test_admin_can_assign_admin_role() {
+        assert_eq!(effective_role(UserRole::Admin, true), UserRole::Admin);
+        assert_eq!(effective_role(UserRole::User, true), UserRole::User);
+    }
+
+    #[test]
+    fn test_profile_update_is_validated_and_trimmed() {
+        let update = UserProfileUpdate {
+            display_name: Some("  Ada  ".into()),
+            avatar_url: None,
+        };
+        let normalized = normalize_profile_update(&update).unwrap();
+        assert_eq!(normalized.display_name.as_deref(), Some("Ada"));
+        assert_eq!(normalized.avatar_url, None);
+
+        let bad_url = UserProfileUpdate {
+            display_name: None,
+            avatar_url: Some("ftp://example.com/a.png".into()),
+        };
+        assert!(matches!(
+            normalize_profile_update(&bad_url),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_merge_users_keeps_first_copy() {
+        let user = |name: &str, host: &str, display_name: Option<&str>| User {
+            name: name.into(),
+            host: host.into(),
+            role: UserRole::User,
+            display_name: display_name.map(Into::into),
+            avatar_url: None,
+            created_at: time::OffsetDateTime::UNIX_EPOCH,
+            updated_at: time::OffsetDateTime::UNIX_EPOCH,
+            synced_at: None,
+        };
+        let merged = merge_users(vec![
+            vec![user("ada", "a.example", Some("Ada"))],
+            vec![
+                user("ada", "a.example", Some("Stale")),
+                user("ada", "b.example", None),
+            ],
+        ]);
+        assert_eq!(
+            merged,
+            vec![
+                user("ada", "a.example", Some("Ada")),
+                user("ada", "b.example", None),
+            ]
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_user_deleted_reaches_only_users_sharing_a_server(
+        pool: DbPool,
+    ) {
+        let state = test_util::state(pool);
+        let alice = test_util::local_user(&state, "alice").await.as_ref();
+        let bob = test_util::local_user(&state, "bob").await.as_ref();
+        let carol = test_util::local_user(&state, "carol").await.as_ref();
+        let shared = test_util::server(&state, &alice, "Shared").await;
+        test_util::join(&state, shared.id, &bob).await;
+        test_util::server(&state, &carol, "Elsewhere").await;
+
+        let mut receivers = Vec::new();
+        for user in [&bob, &carol] {
+            let (sender, receiver) = mpsc::channel(4);
+            let conn_id = state
+                .client_ws_manager
+                .register_connection(sender, IpAddr::V4(Ipv4Addr::LOCALHOST))
+                .await;
+            state
+                .client_ws_manager
+                .authenticate_connection(conn_id, user.clone())
+                .await;
+            receivers.push(receiver);
+        }
+
+        let session = test_util::session(&state, &alice).await;
+        delete_home_user(&state, &session, &alice).await.unwrap();
+
+        let Ok(ClientWsEnvelope::Update { update, .. }) =
+            receivers[0].try_recv()
+        else {
+            panic!("bob should be told about the deletion");
+        };
+        assert_eq!(update, ClientWsUpdate::UserDeleted { user_ref: alice });
+        assert!(receivers[1].try_recv().is_err());
+    }
+}
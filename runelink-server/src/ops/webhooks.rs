@@ -0,0 +1,137 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand::{RngCore, rngs::OsRng};
+use runelink_types::{
+    channel::{ChannelId, ChannelWebhook, WebhookId, WebhookPayload},
+    message::Message,
+    server::ServerId,
+};
+
+use super::messages;
+use crate::{
+    auth::Session,
+    error::{ApiError, ApiResult},
+    queries,
+    state::AppState,
+};
+
+/// Number of random bytes in a webhook token.
+const WEBHOOK_TOKEN_BYTES: usize = 32;
+
+/// Create an incoming webhook for a local channel.
+pub async fn create(
+    state: &AppState,
+    session: &Session,
+    server_id: ServerId,
+    channel_id: ChannelId,
+) -> ApiResult<ChannelWebhook> {
+    let created_by = session.user_ref.clone().ok_or_else(|| {
+        ApiError::Forbidden("User reference required for webhooks".into())
+    })?;
+    let channel =
+        queries::channels::get_by_id(&state.db_pool, channel_id).await?;
+    if channel.server_id != server_id {
+        return Err(ApiError::NotFound);
+    }
+    let webhook = queries::webhooks::insert(
+        &state.db_pool,
+        server_id,
+        channel_id,
+        &generate_token(),
+        &created_by,
+    )
+    .await?;
+    Ok(webhook)
+}
+
+/// Revoke a webhook; its URL stops working immediately.
+pub async fn delete(
+    state: &AppState,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    webhook_id: WebhookId,
+) -> ApiResult<()> {
+    let deleted = queries::webhooks::delete(
+        &state.db_pool,
+        server_id,
+        channel_id,
+        webhook_id,
+    )
+    .await?;
+    if !deleted {
+        return Err(ApiError::NotFound);
+    }
+    Ok(())
+}
+
+/// Post a webhook's payload to its channel.
+///
+/// Unknown webhooks and wrong tokens both fail with `NotFound`.
+pub async fn execute(
+    state: &AppState,
+    webhook_id: WebhookId,
+    token: &str,
+    payload: WebhookPayload,
+) -> ApiResult<Message> {
+    validate_payload(&payload, state.config.max_webhook_body_len)?;
+    let channel_id = queries::webhooks::get_channel_by_token(
+        &state.db_pool,
+        webhook_id,
+        token,
+    )
+    .await?
+    .ok_or(ApiError::NotFound)?;
+    messages::create_from_webhook(state, channel_id, payload.body).await
+}
+
+fn validate_payload(payload: &WebhookPayload, max_len: usize) -> ApiResult<()> {
+    if payload.body.trim().is_empty() {
+        return Err(ApiError::BadRequest(
+            "Webhook body must not be empty".into(),
+        ));
+    }
+    if payload.body.len() > max_len {
+        return Err(ApiError::BadRequest(format!(
+            "Webhook body must be at most {max_len} bytes"
+        )));
+    }
+    Ok(())
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; WEBHOOK_TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Auth requirements for webhook operations.
+pub mod auth {
+    use super::*;
+    use crate::auth::Requirement as Req;
+
+    pub fn create(server_id: ServerId) -> Req {
+        Req::ServerAdmin(server_id).or_admin().client_only()
+    }
+
+    pub fn delete(server_id: ServerId) -> Req {
+        Req::ServerAdmin(server_id).or_admin().client_only()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_body_must_fit() {
+        let payload = |body: &str| WebhookPayload { body: body.into() };
+        assert!(validate_payload(&payload("deploy finished"), 20).is_ok());
+        assert!(matches!(
+            validate_payload(&payload("  \n"), 20),
+            Err(ApiError::BadRequest(_))
+        ));
+        assert!(matches!(
+            validate_payload(&payload(&"a".repeat(21)), 20),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+}
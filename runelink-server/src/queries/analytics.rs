@@ -0,0 +1,179 @@
+use runelink_types::{
+    channel::ChannelId,
+    server::{ChannelDailyMessageCount, ServerId, TopPoster},
+    user::UserRef,
+};
+use time::OffsetDateTime;
+
+use crate::{db::DbPool, error::ApiResult};
+
+pub async fn get_daily_message_counts(
+    pool: &DbPool,
+    server_id: ServerId,
+    since: OffsetDateTime,
+) -> ApiResult<Vec<ChannelDailyMessageCount>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            m.channel_id,
+            date_trunc('day', m.created_at, 'UTC') AS "day!",
+            COUNT(*) AS "count!"
+        FROM messages m
+        JOIN channels c ON c.id = m.channel_id
+        WHERE c.server_id = $1
+            AND m.created_at >= $2
+            AND m.deleted_at IS NULL
+        GROUP BY m.channel_id, 2
+        ORDER BY 2, m.channel_id;
+        "#,
+        server_id.as_uuid(),
+        since,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| ChannelDailyMessageCount {
+            channel_id: ChannelId::from(row.channel_id),
+            day: row.day,
+            count: row.count,
+        })
+        .collect())
+}
+
+pub async fn count_active_members(
+    pool: &DbPool,
+    server_id: ServerId,
+    since: OffsetDateTime,
+) -> ApiResult<i64> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(DISTINCT (m.author_name, m.author_host)) AS "count!"
+        FROM messages m
+        JOIN channels c ON c.id = m.channel_id
+        WHERE c.server_id = $1
+            AND m.created_at >= $2
+            AND m.author_name IS NOT NULL
+            AND NOT m.system
+            AND m.deleted_at IS NULL;
+        "#,
+        server_id.as_uuid(),
+        since,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(count)
+}
+
+pub async fn get_top_posters(
+    pool: &DbPool,
+    server_id: ServerId,
+    since: OffsetDateTime,
+    limit: i64,
+) -> ApiResult<Vec<TopPoster>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            m.author_name AS "name!",
+            m.author_host AS "host!",
+            COUNT(*) AS "message_count!"
+        FROM messages m
+        JOIN channels c ON c.id = m.channel_id
+        WHERE c.server_id = $1
+            AND m.created_at >= $2
+            AND m.author_name IS NOT NULL
+            AND NOT m.system
+            AND m.deleted_at IS NULL
+        GROUP BY m.author_name, m.author_host
+        ORDER BY 3 DESC, m.author_name, m.author_host
+        LIMIT $3;
+        "#,
+        server_id.as_uuid(),
+        since,
+        limit,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| TopPoster {
+            user_ref: UserRef::new(row.name, row.host),
+            message_count: row.message_count,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use runelink_types::message::NewMessage;
+    use time::{Duration, Time};
+
+    use super::*;
+    use crate::{queries, test_util};
+
+    #[sqlx::test]
+    async fn test_daily_counts_match_seeded_messages(pool: DbPool) {
+        let state = test_util::state(pool);
+        let alice = test_util::local_user(&state, "alice").await.as_ref();
+        let server = test_util::server(&state, &alice, "Guild").await;
+        let general = test_util::channel(&state, server.id, "general").await;
+        let random = test_util::channel(&state, server.id, "random").await;
+        let today = OffsetDateTime::now_utc().replace_time(Time::MIDNIGHT);
+        let day = |ago: i64| today - Duration::days(ago);
+
+        // (channel, days ago, hour, deleted)
+        let seed = [
+            (general.id, 1, 9, false),
+            (general.id, 1, 23, false),
+            (general.id, 1, 12, true),
+            (random.id, 1, 0, false),
+            (general.id, 3, 15, false),
+            (random.id, 10, 8, false),
+        ];
+        for (channel_id, ago, hour, deleted) in seed {
+            let new_message = NewMessage {
+                author: alice.clone(),
+                body: "hi".into(),
+                attachments: Vec::new(),
+            };
+            let message = queries::messages::insert(
+                &state.db_pool,
+                channel_id,
+                &new_message,
+                false,
+            )
+            .await
+            .unwrap();
+            let created_at = day(ago) + Duration::hours(hour);
+            sqlx::query(
+                "UPDATE messages SET created_at = $2, deleted_at = $3 \
+                 WHERE id = $1",
+            )
+            .bind(message.id.as_uuid())
+            .bind(created_at)
+            .bind(deleted.then_some(created_at))
+            .execute(state.db_pool.as_ref())
+            .await
+            .unwrap();
+        }
+
+        let counts =
+            get_daily_message_counts(&state.db_pool, server.id, day(7))
+                .await
+                .unwrap();
+        let counts: Vec<_> = counts
+            .into_iter()
+            .map(|count| (count.day, count.channel_id, count.count))
+            .collect();
+        let mut expected = vec![
+            (day(3), general.id, 1),
+            (day(1), general.id, 2),
+            (day(1), random.id, 1),
+        ];
+        // Channels within a day are ordered by id
+        if random.id.as_uuid() < general.id.as_uuid() {
+            expected.swap(1, 2);
+        }
+        assert!(counts == expected, "unexpected counts: {counts:?}");
+    }
+}
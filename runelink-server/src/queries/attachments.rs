@@ -0,0 +1,177 @@
+use runelink_types::{
+    channel::ChannelId,
+    message::{AttachmentId, AttachmentRef, MessageId},
+    user::UserRef,
+};
+
+use crate::{
+    db::DbPool,
+    error::{ApiError, ApiResult},
+};
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct AttachmentRow {
+    pub id: AttachmentId,
+    pub filename: String,
+    pub content_type: String,
+    pub size: i64,
+    pub url: String,
+    /// Whether the file is on disk on this host.
+    pub stored: bool,
+}
+
+impl From<AttachmentRow> for AttachmentRef {
+    fn from(row: AttachmentRow) -> Self {
+        AttachmentRef {
+            id: row.id,
+            filename: row.filename,
+            content_type: row.content_type,
+            size: row.size,
+            url: row.url,
+        }
+    }
+}
+
+/// Records an attachment that isn't on a message yet. Recording one that
+/// is already known does nothing.
+pub async fn insert(
+    pool: &DbPool,
+    attachment: &AttachmentRef,
+    channel_id: ChannelId,
+    uploader: &UserRef,
+    stored: bool,
+) -> ApiResult<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO attachments (
+            id, channel_id, uploader_name, uploader_host, filename,
+            content_type, size, url, stored
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (id) DO NOTHING;
+        "#,
+        attachment.id.as_uuid(),
+        channel_id.as_uuid(),
+        uploader.name,
+        uploader.host,
+        attachment.filename,
+        attachment.content_type,
+        attachment.size,
+        attachment.url,
+        stored,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_by_id(
+    pool: &DbPool,
+    attachment_id: AttachmentId,
+) -> ApiResult<AttachmentRow> {
+    let row = sqlx::query_as!(
+        AttachmentRow,
+        r#"
+        SELECT
+            id AS "id: AttachmentId",
+            filename,
+            content_type,
+            size,
+            url,
+            stored
+        FROM attachments
+        WHERE id = $1;
+        "#,
+        attachment_id.as_uuid(),
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row)
+}
+
+/// The given attachments, provided each was uploaded by `uploader` for the
+/// channel and isn't on a message yet.
+pub async fn get_unattached(
+    pool: &DbPool,
+    attachment_ids: &[AttachmentId],
+    channel_id: ChannelId,
+    uploader: &UserRef,
+) -> ApiResult<Vec<AttachmentRef>> {
+    let ids = attachment_ids
+        .iter()
+        .map(AttachmentId::as_uuid)
+        .collect::<Vec<_>>();
+    let rows = sqlx::query_as!(
+        AttachmentRow,
+        r#"
+        SELECT
+            id AS "id: AttachmentId",
+            filename,
+            content_type,
+            size,
+            url,
+            stored
+        FROM attachments
+        WHERE id = ANY($1)
+            AND channel_id = $2
+            AND uploader_name = $3
+            AND uploader_host = $4
+            AND message_id IS NULL
+            AND remote_message_id IS NULL
+        ORDER BY created_at, id;
+        "#,
+        &ids,
+        channel_id.as_uuid(),
+        uploader.name,
+        uploader.host,
+    )
+    .fetch_all(pool)
+    .await?;
+    if rows.len() != attachment_ids.len() {
+        return Err(ApiError::BadRequest(
+            "Attachment not found or already used".into(),
+        ));
+    }
+    Ok(rows.into_iter().map(AttachmentRef::from).collect())
+}
+
+/// Notes that uploads were sent with a message on another host, so they're
+/// no longer free to attach.
+pub async fn mark_attached_remotely(
+    pool: &DbPool,
+    attachment_ids: &[AttachmentId],
+    message_id: MessageId,
+) -> ApiResult<()> {
+    let ids = attachment_ids
+        .iter()
+        .map(AttachmentId::as_uuid)
+        .collect::<Vec<_>>();
+    sqlx::query!(
+        r#"
+        UPDATE attachments
+        SET remote_message_id = $1
+        WHERE id = ANY($2)
+            AND message_id IS NULL
+            AND remote_message_id IS NULL;
+        "#,
+        message_id.as_uuid(),
+        &ids,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Notes that an attachment from another host has been downloaded here.
+pub async fn mark_stored(
+    pool: &DbPool,
+    attachment_id: AttachmentId,
+) -> ApiResult<()> {
+    sqlx::query!(
+        "UPDATE attachments SET stored = TRUE WHERE id = $1;",
+        attachment_id.as_uuid(),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use runelink_types::{
+    server::{NewServerBan, ServerBan, ServerId},
+    user::UserRef,
+};
+use time::OffsetDateTime;
+
+use crate::{db::DbPool, error::ApiResult};
+
+#[derive(sqlx::FromRow, Debug)]
+struct BanRow {
+    pub server_id: ServerId,
+    pub user_name: String,
+    pub user_host: String,
+    pub banned_by_name: String,
+    pub banned_by_host: String,
+    pub reason: Option<String>,
+    pub created_at: OffsetDateTime,
+}
+
+impl From<BanRow> for ServerBan {
+    fn from(row: BanRow) -> Self {
+        ServerBan {
+            server_id: row.server_id,
+            user_ref: UserRef::new(row.user_name, row.user_host),
+            banned_by: UserRef::new(row.banned_by_name, row.banned_by_host),
+            reason: row.reason,
+            created_at: row.created_at,
+        }
+    }
+}
+
+/// Bans a user from a server; banning again replaces the reason.
+pub async fn upsert(
+    pool: &DbPool,
+    server_id: ServerId,
+    new_ban: &NewServerBan,
+    banned_by: &UserRef,
+) -> ApiResult<ServerBan> {
+    let row = sqlx::query_as!(
+        BanRow,
+        r#"
+        INSERT INTO server_bans (
+            server_id, user_name, user_host, banned_by_name, banned_by_host,
+            reason
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (server_id, user_name, user_host) DO UPDATE
+            SET banned_by_name = EXCLUDED.banned_by_name,
+                banned_by_host = EXCLUDED.banned_by_host,
+                reason = EXCLUDED.reason
+        RETURNING *;
+        "#,
+        server_id.as_uuid(),
+        new_ban.user_ref.name,
+        new_ban.user_ref.host,
+        banned_by.name,
+        banned_by.host,
+        new_ban.reason,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.into())
+}
+
+pub async fn exists(
+    pool: &DbPool,
+    server_id: ServerId,
+    user_ref: &UserRef,
+) -> ApiResult<bool> {
+    let exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM server_bans
+            WHERE server_id = $1 AND user_name = $2 AND user_host = $3
+        ) AS "exists!";
+        "#,
+        server_id.as_uuid(),
+        user_ref.name,
+        user_ref.host,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(exists)
+}
+
+/// Lifts a ban, returning whether there was one.
+pub async fn delete(
+    pool: &DbPool,
+    server_id: ServerId,
+    user_ref: &UserRef,
+) -> ApiResult<bool> {
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM server_bans
+        WHERE server_id = $1 AND user_name = $2 AND user_host = $3;
+        "#,
+        server_id.as_uuid(),
+        user_ref.name,
+        user_ref.host,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(deleted > 0)
+}
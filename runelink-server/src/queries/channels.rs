@@ -1,10 +1,17 @@
+use std::collections::HashSet;
+
 use runelink_types::{
-    channel::{Channel, ChannelId, NewChannel},
+    channel::{Channel, ChannelId, ChannelUpdate, NewChannel},
     server::ServerId,
 };
+use uuid::Uuid;
 
-use crate::{db::DbPool, error::ApiResult};
+use crate::{
+    db::DbPool,
+    error::{ApiError, ApiResult},
+};
 
+/// Inserts a channel after the server's existing ones.
 pub async fn insert(
     pool: &DbPool,
     server_id: ServerId,
@@ -13,16 +20,23 @@ pub async fn insert(
     let channel = sqlx::query_as!(
         Channel,
         r#"
-        INSERT INTO channels (server_id, title, description)
-        VALUES ($1, $2, $3)
+        INSERT INTO channels (
+            server_id, title, description, slow_mode_secs, category, position
+        )
+        SELECT $1, $2, $3, $4, $5, COALESCE(MAX(position) + 1, 0)
+        FROM channels
+        WHERE server_id = $1
         RETURNING *;
         "#,
         server_id.as_uuid(),
         new_channel.title,
         new_channel.description,
+        new_channel.slow_mode_secs,
+        new_channel.category,
     )
     .fetch_one(pool)
-    .await?;
+    .await
+    .map_err(|error| title_conflict(error, &new_channel.title))?;
     Ok(channel)
 }
 
@@ -47,6 +61,8 @@ pub async fn get_all(pool: &DbPool) -> ApiResult<Vec<Channel>> {
     Ok(channels)
 }
 
+/// Returns the server's channels grouped by category, uncategorized first,
+/// in position order.
 pub async fn get_by_server(
     pool: &DbPool,
     server_id: ServerId,
@@ -56,7 +72,7 @@ pub async fn get_by_server(
         r#"
         SELECT * FROM channels
         WHERE server_id = $1
-        ORDER BY created_at;
+        ORDER BY category NULLS FIRST, position, created_at;
         "#,
         server_id.as_uuid(),
     )
@@ -65,9 +81,198 @@ pub async fn get_by_server(
     Ok(channels)
 }
 
+/// Applies a partial update. An empty description and a zero slow mode
+/// are stored as NULL.
+pub async fn update(
+    pool: &DbPool,
+    channel_id: ChannelId,
+    update: &ChannelUpdate,
+) -> ApiResult<Channel> {
+    let channel = sqlx::query_as!(
+        Channel,
+        r#"
+        UPDATE channels
+        SET
+            title = COALESCE($2, title),
+            description = CASE
+                WHEN $3::text IS NULL THEN description
+                ELSE NULLIF($3, '')
+            END,
+            slow_mode_secs = CASE
+                WHEN $4::int IS NULL THEN slow_mode_secs
+                ELSE NULLIF($4, 0)
+            END
+        WHERE id = $1
+        RETURNING *;
+        "#,
+        channel_id.as_uuid(),
+        update.title,
+        update.description,
+        update.slow_mode_secs,
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|error| match &update.title {
+        Some(title) => title_conflict(error, title),
+        None => error.into(),
+    })?;
+    Ok(channel)
+}
+
+/// Sets each channel's position to its index in `ordered_ids`, which must
+/// list every channel of the server exactly once. Returns the channels
+/// whose position changed.
+pub async fn reorder(
+    pool: &DbPool,
+    server_id: ServerId,
+    ordered_ids: &[ChannelId],
+) -> ApiResult<Vec<Channel>> {
+    let mut tx = pool.begin().await?;
+    let current: Vec<Uuid> = sqlx::query_scalar!(
+        "SELECT id FROM channels WHERE server_id = $1 FOR UPDATE;",
+        server_id.as_uuid(),
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let ordered = ordered_ids
+        .iter()
+        .map(|channel_id| channel_id.as_uuid())
+        .collect::<Vec<_>>();
+    if !is_permutation(&current, &ordered) {
+        return Err(ApiError::BadRequest(
+            "Channel order must list each of the server's channels once".into(),
+        ));
+    }
+    let changed = sqlx::query_as!(
+        Channel,
+        r#"
+        UPDATE channels c
+        SET position = (o.ordinality - 1)::int
+        FROM UNNEST($2::uuid[]) WITH ORDINALITY AS o(id, ordinality)
+        WHERE c.id = o.id
+            AND c.server_id = $1
+            AND c.position <> (o.ordinality - 1)::int
+        RETURNING c.*;
+        "#,
+        server_id.as_uuid(),
+        &ordered,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(changed)
+}
+
+fn is_permutation(current: &[Uuid], ordered: &[Uuid]) -> bool {
+    let current = current.iter().collect::<HashSet<_>>();
+    let ordered_set = ordered.iter().collect::<HashSet<_>>();
+    ordered_set.len() == ordered.len() && ordered_set == current
+}
+
 pub async fn delete(pool: &DbPool, channel_id: ChannelId) -> ApiResult<()> {
     sqlx::query!("DELETE FROM channels WHERE id = $1;", channel_id.as_uuid())
         .execute(pool)
         .await?;
     Ok(())
 }
+
+/// Channel titles are unique per server, ignoring case.
+fn title_conflict(error: sqlx::Error, title: &str) -> ApiError {
+    match ApiError::from(error) {
+        ApiError::Conflict(_) => ApiError::Conflict(format!(
+            "Channel title '{title}' already in use in this server"
+        )),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn new_channel(title: &str) -> NewChannel {
+        NewChannel {
+            title: title.into(),
+            description: None,
+            slow_mode_secs: None,
+            category: None,
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_titles_are_unique_per_server_ignoring_case(pool: DbPool) {
+        let state = test_util::state(pool);
+        let owner = test_util::local_user(&state, "owner").await;
+        let first = test_util::server(&state, &owner.as_ref(), "First").await;
+        let second = test_util::server(&state, &owner.as_ref(), "Second").await;
+        let pool = &state.db_pool;
+
+        insert(pool, first.id, &new_channel("general"))
+            .await
+            .unwrap();
+        let error = insert(pool, first.id, &new_channel("General"))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ApiError::Conflict(_)));
+        // Other servers can reuse the title
+        insert(pool, second.id, &new_channel("general"))
+            .await
+            .unwrap();
+
+        let random = insert(pool, first.id, &new_channel("random"))
+            .await
+            .unwrap();
+        let rename = ChannelUpdate {
+            title: Some("GENERAL".into()),
+            description: None,
+            slow_mode_secs: None,
+        };
+        let error = update(pool, random.id, &rename).await.unwrap_err();
+        assert!(matches!(error, ApiError::Conflict(_)));
+    }
+
+    #[sqlx::test]
+    async fn test_migration_suffixes_clashing_titles_with_their_id(
+        pool: DbPool,
+    ) {
+        // The migration that made titles unique
+        const UNIQUE_TITLES: i64 = 20261103120000;
+        let state = test_util::state(pool);
+        let owner = test_util::local_user(&state, "owner").await;
+        let server = test_util::server(&state, &owner.as_ref(), "Server").await;
+        let pool = &state.db_pool;
+
+        crate::MIGRATOR
+            .undo(pool.as_ref(), UNIQUE_TITLES - 1)
+            .await
+            .unwrap();
+        let first = insert(pool, server.id, &new_channel("general"))
+            .await
+            .unwrap();
+        let second = insert(pool, server.id, &new_channel("General"))
+            .await
+            .unwrap();
+        // A title a counter suffix could have landed on
+        insert(pool, server.id, &new_channel("General (2)"))
+            .await
+            .unwrap();
+        crate::MIGRATOR.run(pool.as_ref()).await.unwrap();
+
+        assert_eq!(get_by_id(pool, first.id).await.unwrap().title, "general");
+        assert_eq!(
+            get_by_id(pool, second.id).await.unwrap().title,
+            format!("General ({})", second.id)
+        );
+    }
+
+    #[test]
+    fn test_order_must_list_each_channel_once() {
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        assert!(is_permutation(&[a, b, c], &[c, a, b]));
+        assert!(is_permutation(&[], &[]));
+        assert!(!is_permutation(&[a, b, c], &[a, b]));
+        assert!(!is_permutation(&[a, b], &[a, b, b]));
+        assert!(!is_permutation(&[a, b], &[a, c]));
+    }
+}
@@ -0,0 +1,135 @@
+use runelink_types::{channel::ChannelId, user::UserRef};
+
+use crate::{
+    db::DbPool,
+    error::{ApiError, ApiResult},
+};
+
+/// Orders a pair of users so each pair maps to a single channel row.
+fn ordered_pair<'a>(
+    first: &'a UserRef,
+    second: &'a UserRef,
+) -> (&'a UserRef, &'a UserRef) {
+    if (&first.host, &first.name) <= (&second.host, &second.name) {
+        (first, second)
+    } else {
+        (second, first)
+    }
+}
+
+/// The direct message channel between two users, if they have one.
+pub async fn get_channel(
+    pool: &DbPool,
+    first: &UserRef,
+    second: &UserRef,
+) -> ApiResult<Option<ChannelId>> {
+    let (user_a, user_b) = ordered_pair(first, second);
+    let channel_id = sqlx::query_scalar!(
+        r#"
+        SELECT channel_id
+        FROM direct_channels
+        WHERE user_a_name = $1 AND user_a_host = $2
+            AND user_b_name = $3 AND user_b_host = $4;
+        "#,
+        user_a.name,
+        user_a.host,
+        user_b.name,
+        user_b.host,
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(ChannelId::from);
+    Ok(channel_id)
+}
+
+/// The direct message channel between two users, creating it in the hidden
+/// direct message server the first time.
+pub async fn get_or_create_channel(
+    pool: &DbPool,
+    first: &UserRef,
+    second: &UserRef,
+) -> ApiResult<ChannelId> {
+    if let Some(channel_id) = get_channel(pool, first, second).await? {
+        return Ok(channel_id);
+    }
+    let (user_a, user_b) = ordered_pair(first, second);
+    let mut tx = pool.begin().await?;
+    let channel_id = sqlx::query_scalar!(
+        r#"
+        WITH new_channel AS (SELECT gen_random_uuid() AS id)
+        INSERT INTO channels (id, server_id, title)
+        SELECT new_channel.id, servers.id, 'Direct messages ' || new_channel.id
+        FROM servers, new_channel
+        WHERE servers.direct
+        RETURNING id;
+        "#,
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map(ChannelId::from)?;
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO direct_channels (
+            channel_id, user_a_name, user_a_host, user_b_name, user_b_host
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (user_a_name, user_a_host, user_b_name, user_b_host)
+            DO NOTHING;
+        "#,
+        channel_id.as_uuid(),
+        user_a.name,
+        user_a.host,
+        user_b.name,
+        user_b.host,
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    if inserted == 0 {
+        // Created concurrently; dropping the transaction discards our channel
+        drop(tx);
+        return get_channel(pool, first, second)
+            .await?
+            .ok_or(ApiError::NotFound);
+    }
+    tx.commit().await?;
+    Ok(channel_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn test_pair_order_ignores_argument_order() {
+        let alice = UserRef::new("alice".into(), "b.example".into());
+        let bob = UserRef::new("bob".into(), "a.example".into());
+        assert_eq!(ordered_pair(&alice, &bob), ordered_pair(&bob, &alice));
+        // Host sorts before name.
+        assert_eq!(ordered_pair(&alice, &bob).0, &bob);
+    }
+
+    #[sqlx::test]
+    async fn test_channel_is_created_once_per_pair(pool: DbPool) {
+        let state = test_util::state(pool);
+        let alice = test_util::local_user(&state, "alice").await.as_ref();
+        let bob = test_util::local_user(&state, "bob").await.as_ref();
+        assert!(
+            get_channel(&state.db_pool, &alice, &bob)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let created = get_or_create_channel(&state.db_pool, &alice, &bob)
+            .await
+            .unwrap();
+        let again = get_or_create_channel(&state.db_pool, &bob, &alice)
+            .await
+            .unwrap();
+        assert!(created == again);
+        let found = get_channel(&state.db_pool, &bob, &alice).await.unwrap();
+        assert!(found == Some(created));
+    }
+}
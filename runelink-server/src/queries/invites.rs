@@ -0,0 +1,222 @@
+use runelink_types::{
+    server::{NewServerInvite, ServerId, ServerInvite, ServerRole},
+    user::UserRef,
+};
+use time::OffsetDateTime;
+
+use crate::{
+    config::ServerConfig,
+    db::DbPool,
+    error::{ApiError, ApiResult},
+    state::AppState,
+};
+
+#[derive(sqlx::FromRow, Debug)]
+struct InviteRow {
+    pub code: String,
+    pub server_id: ServerId,
+    pub created_by_name: String,
+    pub created_by_host: String,
+    pub expires_at: Option<OffsetDateTime>,
+    pub max_uses: Option<i32>,
+    pub uses_remaining: Option<i32>,
+    pub created_at: OffsetDateTime,
+}
+
+impl InviteRow {
+    fn into_invite(self, config: &ServerConfig) -> ServerInvite {
+        ServerInvite {
+            code: self.code,
+            server_id: self.server_id,
+            server_host: config.public_host(),
+            created_by: UserRef::new(
+                self.created_by_name,
+                self.created_by_host,
+            ),
+            expires_at: self.expires_at,
+            max_uses: self.max_uses.map(|uses| uses as u32),
+            uses_remaining: self.uses_remaining.map(|uses| uses as u32),
+            created_at: self.created_at,
+        }
+    }
+
+    /// Why a stored invite can no longer be redeemed.
+    fn unusable_error(&self, now: OffsetDateTime) -> ApiError {
+        match self.expires_at {
+            Some(expires_at) if expires_at <= now => ApiError::InviteExpired,
+            _ => ApiError::InviteExhausted,
+        }
+    }
+}
+
+pub async fn insert(
+    state: &AppState,
+    code: &str,
+    server_id: ServerId,
+    created_by: &UserRef,
+    new_invite: &NewServerInvite,
+) -> ApiResult<ServerInvite> {
+    let max_uses = new_invite.max_uses.map(|uses| uses as i32);
+    let row = sqlx::query_as!(
+        InviteRow,
+        r#"
+        INSERT INTO server_invites (
+            code, server_id, created_by_name, created_by_host, expires_at,
+            max_uses, uses_remaining
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $6)
+        RETURNING *;
+        "#,
+        code,
+        server_id.as_uuid(),
+        created_by.name,
+        created_by.host,
+        new_invite.expires_at,
+        max_uses,
+    )
+    .fetch_one(state.db_pool.as_ref())
+    .await?;
+    Ok(row.into_invite(&state.config))
+}
+
+/// Uses up one redemption of an invite and adds `user_ref` to its server
+/// as a member, in one transaction.
+///
+/// Fails with `InviteExpired` or `InviteExhausted` when the invite can no
+/// longer be used. Fails with `Forbidden` when the user is banned from the
+/// server and with `Conflict` when they are already a member; neither uses
+/// up the invite.
+pub async fn redeem(
+    pool: &DbPool,
+    code: &str,
+    user_ref: &UserRef,
+) -> ApiResult<ServerId> {
+    let mut tx = pool.begin().await?;
+    let server_id = sqlx::query_scalar!(
+        r#"
+        UPDATE server_invites
+        SET uses_remaining = uses_remaining - 1
+        WHERE code = $1
+            AND (expires_at IS NULL OR expires_at > NOW())
+            AND (uses_remaining IS NULL OR uses_remaining > 0)
+        RETURNING server_id;
+        "#,
+        code,
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .map(ServerId::from);
+    let Some(server_id) = server_id else {
+        let row = sqlx::query_as!(
+            InviteRow,
+            "SELECT * FROM server_invites WHERE code = $1;",
+            code,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        return Err(row.unusable_error(OffsetDateTime::now_utc()));
+    };
+    let banned = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM server_bans
+            WHERE server_id = $1 AND user_name = $2 AND user_host = $3
+        ) AS "exists!";
+        "#,
+        server_id.as_uuid(),
+        user_ref.name,
+        user_ref.host,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if banned {
+        return Err(ApiError::Forbidden(
+            "User is banned from this server".into(),
+        ));
+    }
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO server_users (server_id, user_name, user_host, role)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (server_id, user_name, user_host) DO NOTHING;
+        "#,
+        server_id.as_uuid(),
+        user_ref.name,
+        user_ref.host,
+        ServerRole::Member as ServerRole,
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    if inserted == 0 {
+        return Err(ApiError::Conflict(
+            "User is already a member of this server".into(),
+        ));
+    }
+    tx.commit().await?;
+    Ok(server_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use time::Duration;
+
+    fn row(
+        expires_at: Option<OffsetDateTime>,
+        uses_remaining: Option<i32>,
+    ) -> InviteRow {
+        InviteRow {
+            code: "code".into(),
+            server_id: ServerId::new(),
+            created_by_name: "admin".into(),
+            created_by_host: "example.com".into(),
+            expires_at,
+            max_uses: uses_remaining.map(|_| 1),
+            uses_remaining,
+            created_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    #[test]
+    fn test_past_expiry_is_reported_as_expired() {
+        let now = OffsetDateTime::now_utc();
+        let error =
+            row(Some(now - Duration::minutes(1)), Some(0)).unusable_error(now);
+        assert!(matches!(error, ApiError::InviteExpired));
+    }
+
+    #[test]
+    fn test_live_invite_without_uses_is_exhausted() {
+        let now = OffsetDateTime::now_utc();
+        let error =
+            row(Some(now + Duration::hours(1)), Some(0)).unusable_error(now);
+        assert!(matches!(error, ApiError::InviteExhausted));
+        let error = row(None, Some(0)).unusable_error(now);
+        assert!(matches!(error, ApiError::InviteExhausted));
+    }
+
+    #[sqlx::test]
+    async fn test_redeem_joins_server_and_uses_up_invite(pool: DbPool) {
+        let state = test_util::state(pool);
+        let owner = test_util::local_user(&state, "owner").await.as_ref();
+        let first = test_util::local_user(&state, "first").await.as_ref();
+        let second = test_util::local_user(&state, "second").await.as_ref();
+        let server = test_util::server(&state, &owner, "Guild").await;
+        let new_invite = NewServerInvite {
+            expires_at: None,
+            max_uses: Some(1),
+        };
+        insert(&state, "once", server.id, &owner, &new_invite)
+            .await
+            .unwrap();
+
+        let server_id = redeem(&state.db_pool, "once", &first).await.unwrap();
+        assert!(server_id == server.id);
+        assert!(matches!(
+            redeem(&state.db_pool, "once", &second).await,
+            Err(ApiError::InviteExhausted)
+        ));
+    }
+}
@@ -2,13 +2,13 @@
 
 use runelink_types::{
     server::{
-        NewServerMembership, Server, ServerId, ServerMember, ServerMembership,
-        ServerRole,
+        BulkMembershipEntry, NewServerMembership, Server, ServerId,
+        ServerMember, ServerMembership, ServerRole, ServerVisibility,
     },
     user::{User, UserRef},
 };
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, types::Json};
+use sqlx::{FromRow, PgConnection, types::Json};
 use time::OffsetDateTime;
 
 use crate::{
@@ -48,6 +48,7 @@ struct ServerMembershipRow {
     server_id: Option<ServerId>,
     server_title: Option<String>,
     server_description: Option<String>,
+    server_visibility: Option<ServerVisibility>,
     server_host_from_db: Option<String>,
     server_created_at: Option<OffsetDateTime>,
     server_updated_at: Option<OffsetDateTime>,
@@ -75,6 +76,7 @@ impl ServerMembershipRow {
                 id: self.server_id.ok_or_else(get_error)?,
                 title: self.server_title.ok_or_else(get_error)?,
                 description: self.server_description,
+                visibility: self.server_visibility.ok_or_else(get_error)?,
                 host: server_host,
                 created_at: self.server_created_at.ok_or_else(get_error)?,
                 updated_at: self.server_updated_at.ok_or_else(get_error)?,
@@ -91,25 +93,44 @@ impl ServerMembershipRow {
     }
 }
 
+/// Adds or updates a local membership, refusing to demote a server's last
+/// admin like `update_role`.
+///
+/// Fails with `Conflict` when the user is already a member with that role.
 pub async fn upsert_local(
     pool: &DbPool,
     new_membership: &NewServerMembership,
 ) -> ApiResult<ServerMember> {
-    sqlx::query!(
+    let mut tx = pool.begin().await?;
+    let admins = lock_admins(&mut tx, new_membership.server_id).await?;
+    check_admin_remains(
+        &admins,
+        &new_membership.user_ref,
+        new_membership.role,
+    )?;
+    let upserted = sqlx::query!(
         r#"
         INSERT INTO server_users (server_id, user_name, user_host, role)
         VALUES ($1, $2, $3, $4)
         ON CONFLICT (server_id, user_name, user_host) DO UPDATE
             SET role = EXCLUDED.role,
-                updated_at = NOW();
+                updated_at = NOW()
+            WHERE server_users.role <> EXCLUDED.role;
         "#,
         new_membership.server_id.as_uuid(),
         new_membership.user_ref.name,
         new_membership.user_ref.host,
         new_membership.role as ServerRole,
     )
-    .execute(pool)
-    .await?;
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    if upserted == 0 {
+        return Err(ApiError::Conflict(
+            "User is already a member of this server".into(),
+        ));
+    }
+    tx.commit().await?;
     get_local_member_by_user_and_server(
         pool,
         new_membership.server_id,
@@ -118,6 +139,170 @@ pub async fn upsert_local(
     .await
 }
 
+/// Changes a local member's role, refusing to demote a server's last admin.
+///
+/// The server's admin rows are locked for the duration so two admins can't
+/// demote each other at the same time.
+pub async fn update_role(
+    pool: &DbPool,
+    server_id: ServerId,
+    user_ref: &UserRef,
+    role: ServerRole,
+) -> ApiResult<()> {
+    let mut tx = pool.begin().await?;
+    let admins = lock_admins(&mut tx, server_id).await?;
+    check_admin_remains(&admins, user_ref, role)?;
+    let updated = sqlx::query!(
+        r#"
+        UPDATE server_users
+        SET role = $4, updated_at = NOW()
+        WHERE server_id = $1 AND user_name = $2 AND user_host = $3;
+        "#,
+        server_id.as_uuid(),
+        user_ref.name,
+        user_ref.host,
+        role as ServerRole,
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    if updated == 0 {
+        return Err(ApiError::NotFound);
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Makes `to` an admin and, with `demote_from`, `from` a plain member, in
+/// one transaction. `to` must already be a member. Returns whether `from`
+/// was demoted.
+pub async fn transfer_admin(
+    pool: &DbPool,
+    server_id: ServerId,
+    from: &UserRef,
+    to: &UserRef,
+    demote_from: bool,
+) -> ApiResult<bool> {
+    let mut tx = pool.begin().await?;
+    // Locked like `update_role`, so a concurrent demotion can't interleave
+    lock_admins(&mut tx, server_id).await?;
+    let promoted = sqlx::query!(
+        r#"
+        UPDATE server_users
+        SET role = 'admin', updated_at = NOW()
+        WHERE server_id = $1 AND user_name = $2 AND user_host = $3;
+        "#,
+        server_id.as_uuid(),
+        to.name,
+        to.host,
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    if promoted == 0 {
+        return Err(ApiError::NotFound);
+    }
+    let mut demoted = 0;
+    if demote_from && from != to {
+        demoted = sqlx::query!(
+            r#"
+            UPDATE server_users
+            SET role = 'member', updated_at = NOW()
+            WHERE server_id = $1 AND user_name = $2 AND user_host = $3;
+            "#,
+            server_id.as_uuid(),
+            from.name,
+            from.host,
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    }
+    tx.commit().await?;
+    Ok(demoted > 0)
+}
+
+/// The server's admins, with their rows locked until the transaction ends.
+async fn lock_admins(
+    conn: &mut PgConnection,
+    server_id: ServerId,
+) -> ApiResult<Vec<UserRef>> {
+    let admins = sqlx::query!(
+        r#"
+        SELECT user_name, user_host
+        FROM server_users
+        WHERE server_id = $1 AND role = 'admin'
+        FOR UPDATE;
+        "#,
+        server_id.as_uuid(),
+    )
+    .fetch_all(conn)
+    .await?
+    .into_iter()
+    .map(|row| UserRef::new(row.user_name, row.user_host))
+    .collect();
+    Ok(admins)
+}
+
+fn check_admin_remains(
+    admins: &[UserRef],
+    user_ref: &UserRef,
+    role: ServerRole,
+) -> ApiResult<()> {
+    if role != ServerRole::Admin
+        && admins.len() == 1
+        && admins.first() == Some(user_ref)
+    {
+        return Err(ApiError::BadRequest(
+            "server must retain at least one admin".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Upserts several local memberships in a single transaction, refusing to
+/// leave the server without an admin.
+pub async fn insert_local_many(
+    pool: &DbPool,
+    server_id: ServerId,
+    entries: &[BulkMembershipEntry],
+) -> ApiResult<()> {
+    let mut tx = pool.begin().await?;
+    let mut admins = lock_admins(&mut tx, server_id).await?;
+    // Promotions count first, so a batch may hand the admin role over
+    for entry in entries {
+        if entry.role == ServerRole::Admin && !admins.contains(&entry.user_ref)
+        {
+            admins.push(entry.user_ref.clone());
+        }
+    }
+    for entry in entries {
+        if entry.role != ServerRole::Admin {
+            check_admin_remains(&admins, &entry.user_ref, entry.role)?;
+            admins.retain(|admin| admin != &entry.user_ref);
+        }
+    }
+    for entry in entries {
+        sqlx::query!(
+            r#"
+            INSERT INTO server_users (server_id, user_name, user_host, role)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (server_id, user_name, user_host) DO UPDATE
+                SET role = EXCLUDED.role,
+                    updated_at = NOW();
+            "#,
+            server_id.as_uuid(),
+            entry.user_ref.name,
+            entry.user_ref.host,
+            entry.role as ServerRole,
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
 pub async fn insert_remote(
     pool: &DbPool,
     membership: &ServerMembership,
@@ -152,6 +337,7 @@ pub async fn insert_remote(
           s.host,
           s.title,
           s.description,
+          s.visibility AS "visibility: ServerVisibility",
           s.remote_created_at AS server_created_at,
           s.remote_updated_at AS server_updated_at,
           m.role AS "role: ServerRole",
@@ -176,6 +362,7 @@ pub async fn insert_remote(
             host: row.host,
             title: row.title,
             description: row.description,
+            visibility: row.visibility,
             created_at: row.server_created_at,
             updated_at: row.server_updated_at,
         },
@@ -319,6 +506,42 @@ pub async fn get_user_refs_by_remote_server(
         .collect())
 }
 
+/// Get the users who share at least one server (local or cached remote) with
+/// the given user, including the user themselves if they have memberships.
+pub async fn get_user_refs_sharing_server_with(
+    pool: &DbPool,
+    user_ref: &UserRef,
+) -> ApiResult<Vec<UserRef>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT su.user_name AS "user_name!", su.user_host AS "user_host!"
+        FROM server_users su
+        WHERE su.server_id IN (
+            SELECT server_id FROM server_users
+            WHERE user_name = $1 AND user_host = $2
+        )
+
+        UNION
+
+        SELECT ursm.user_name, ursm.user_host
+        FROM user_remote_server_memberships ursm
+        WHERE ursm.remote_server_id IN (
+            SELECT remote_server_id FROM user_remote_server_memberships
+            WHERE user_name = $1 AND user_host = $2
+        )
+        "#,
+        user_ref.name,
+        user_ref.host,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| UserRef::new(row.user_name, row.user_host))
+        .collect())
+}
+
 pub async fn get_local_by_user_and_server(
     state: &AppState,
     server_id: ServerId,
@@ -330,6 +553,7 @@ pub async fn get_local_by_user_and_server(
             s.id,
             s.title,
             s.description,
+            s.visibility AS "visibility: ServerVisibility",
             s.created_at AS server_created_at,
             s.updated_at AS server_updated_at,
             su.role AS "role: ServerRole",
@@ -354,6 +578,7 @@ pub async fn get_local_by_user_and_server(
             host: state.config.public_host(),
             title: row.title,
             description: row.description,
+            visibility: row.visibility,
             created_at: row.server_created_at,
             updated_at: row.server_updated_at,
         },
@@ -377,6 +602,7 @@ pub async fn get_by_user(
             s.id AS "server_id: ServerId",
             s.title AS server_title,
             s.description AS server_description,
+            s.visibility AS "server_visibility!: Option<ServerVisibility>",
             NULL::TEXT AS server_host_from_db,
             s.created_at AS server_created_at,
             s.updated_at AS server_updated_at,
@@ -397,6 +623,7 @@ pub async fn get_by_user(
             crs.id AS "server_id: ServerId",
             crs.title AS server_title,
             crs.description AS server_description,
+            crs.visibility AS "server_visibility!: Option<ServerVisibility>",
             crs.host AS server_host_from_db,
             crs.remote_created_at AS server_created_at,
             crs.remote_updated_at AS server_updated_at,
@@ -411,7 +638,9 @@ pub async fn get_by_user(
             ON crs.id = ursm.remote_server_id
         WHERE ursm.user_name = $1 AND ursm.user_host = $2
 
-        ORDER BY server_title ASC
+        -- Output columns keep their sqlx type-annotated aliases, so the
+        -- server ID must be referenced by its full quoted name here.
+        ORDER BY server_title ASC, "server_id: ServerId" ASC
         "#,
         user.name,
         user.host,
@@ -424,6 +653,25 @@ pub async fn get_by_user(
         .collect()
 }
 
+/// Local users with at least one cached remote server membership.
+pub async fn get_user_refs_with_remote_memberships(
+    pool: &DbPool,
+) -> ApiResult<Vec<UserRef>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT DISTINCT user_name, user_host
+        FROM user_remote_server_memberships
+        ORDER BY user_host, user_name
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| UserRef::new(row.user_name, row.user_host))
+        .collect())
+}
+
 /// Get distinct remote server hosts where a user has memberships.
 pub async fn get_remote_server_hosts_for_user(
     pool: &DbPool,
@@ -445,12 +693,17 @@ pub async fn get_remote_server_hosts_for_user(
     Ok(rows.into_iter().map(|row| row.host).collect())
 }
 
-/// Delete a local server membership.
+/// Delete a local server membership, refusing to remove a server's last
+/// admin.
 pub async fn delete_local(
     pool: &DbPool,
     server_id: ServerId,
     user: UserRef,
 ) -> ApiResult<()> {
+    let mut tx = pool.begin().await?;
+    let admins = lock_admins(&mut tx, server_id).await?;
+    // Leaving is checked like being demoted to a plain member
+    check_admin_remains(&admins, &user, ServerRole::Member)?;
     let result = sqlx::query!(
         r#"
         DELETE FROM server_users
@@ -460,11 +713,12 @@ pub async fn delete_local(
         user.name,
         user.host,
     )
-    .execute(pool)
+    .execute(&mut *tx)
     .await?;
     if result.rows_affected() == 0 {
         return Err(ApiError::NotFound);
     }
+    tx.commit().await?;
     Ok(())
 }
 
@@ -489,3 +743,159 @@ pub async fn delete_remote(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn test_last_admin_cannot_be_demoted() {
+        let admin = UserRef::new("admin".into(), "example.com".into());
+        let other = UserRef::new("other".into(), "example.com".into());
+        assert!(matches!(
+            check_admin_remains(
+                std::slice::from_ref(&admin),
+                &admin,
+                ServerRole::Member
+            ),
+            Err(ApiError::BadRequest(_))
+        ));
+        assert!(
+            check_admin_remains(
+                &[admin.clone(), other.clone()],
+                &admin,
+                ServerRole::Member
+            )
+            .is_ok()
+        );
+        // Re-affirming the last admin, or demoting a non-admin, is fine
+        assert!(
+            check_admin_remains(
+                std::slice::from_ref(&admin),
+                &admin,
+                ServerRole::Admin
+            )
+            .is_ok()
+        );
+        assert!(
+            check_admin_remains(
+                std::slice::from_ref(&admin),
+                &other,
+                ServerRole::Member
+            )
+            .is_ok()
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_upserts_keep_the_last_admin(pool: DbPool) {
+        let state = test_util::state(pool);
+        let owner = test_util::local_user(&state, "owner").await.as_ref();
+        let other = test_util::local_user(&state, "other").await.as_ref();
+        let server = test_util::server(&state, &owner, "Guild").await;
+        test_util::join(&state, server.id, &other).await;
+        let entry = |user_ref: &UserRef, role| BulkMembershipEntry {
+            user_ref: user_ref.clone(),
+            role,
+        };
+
+        let demotion = NewServerMembership {
+            user_ref: owner.clone(),
+            server_id: server.id,
+            server_host: state.config.public_host(),
+            role: ServerRole::Member,
+        };
+        assert!(matches!(
+            upsert_local(&state.db_pool, &demotion).await,
+            Err(ApiError::BadRequest(_))
+        ));
+        assert!(matches!(
+            insert_local_many(
+                &state.db_pool,
+                server.id,
+                &[entry(&owner, ServerRole::Member)]
+            )
+            .await,
+            Err(ApiError::BadRequest(_))
+        ));
+
+        // Handing the role over in the same batch is fine
+        insert_local_many(
+            &state.db_pool,
+            server.id,
+            &[
+                entry(&owner, ServerRole::Member),
+                entry(&other, ServerRole::Admin),
+            ],
+        )
+        .await
+        .unwrap();
+        let member = get_local_member_by_user_and_server(
+            &state.db_pool,
+            server.id,
+            owner.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(member.role, ServerRole::Member);
+    }
+
+    #[sqlx::test]
+    async fn test_existing_membership_is_a_conflict(pool: DbPool) {
+        let state = test_util::state(pool);
+        let owner = test_util::local_user(&state, "owner").await.as_ref();
+        let member = test_util::local_user(&state, "member").await.as_ref();
+        let server = test_util::server(&state, &owner, "Guild").await;
+        let membership = |role| NewServerMembership {
+            user_ref: member.clone(),
+            server_id: server.id,
+            server_host: state.config.public_host(),
+            role,
+        };
+
+        upsert_local(&state.db_pool, &membership(ServerRole::Member))
+            .await
+            .unwrap();
+        assert!(matches!(
+            upsert_local(&state.db_pool, &membership(ServerRole::Member)).await,
+            Err(ApiError::Conflict(_))
+        ));
+        // A new role is an update rather than a duplicate
+        let promoted =
+            upsert_local(&state.db_pool, &membership(ServerRole::Admin))
+                .await
+                .unwrap();
+        assert_eq!(promoted.role, ServerRole::Admin);
+    }
+
+    #[sqlx::test]
+    async fn test_servers_with_equal_titles_keep_a_stable_order(pool: DbPool) {
+        let state = test_util::state(pool);
+        let user = test_util::local_user(&state, "alice").await.as_ref();
+        let mut twins = Vec::new();
+        for title in ["Twin", "Alpha", "Twin"] {
+            let server = test_util::server(&state, &user, title).await;
+            if title == "Twin" {
+                twins.push(server.id.as_uuid());
+            }
+        }
+        twins.sort();
+
+        for _ in 0..3 {
+            let memberships = get_by_user(&state, user.clone()).await.unwrap();
+            let order: Vec<_> = memberships
+                .iter()
+                .map(|m| (m.server.title.as_str(), m.server.id.as_uuid()))
+                .collect();
+            assert_eq!(
+                order,
+                [
+                    ("Alpha", order[0].1),
+                    ("Twin", twins[0]),
+                    ("Twin", twins[1])
+                ]
+            );
+        }
+    }
+}
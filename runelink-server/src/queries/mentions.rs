@@ -0,0 +1,40 @@
+use runelink_types::{message::MessageId, server::ServerId, user::UserRef};
+
+use crate::{db::DbPool, error::ApiResult};
+
+/// Records which of `mentioned` are members of `server_id` as mentioned in
+/// a message, returning those members. Non-members are skipped.
+pub async fn insert_for_members(
+    pool: &DbPool,
+    message_id: MessageId,
+    server_id: ServerId,
+    mentioned: &[UserRef],
+) -> ApiResult<Vec<UserRef>> {
+    let (names, hosts): (Vec<String>, Vec<String>) = mentioned
+        .iter()
+        .map(|user_ref| (user_ref.name.clone(), user_ref.host.clone()))
+        .unzip();
+    let rows = sqlx::query!(
+        r#"
+        INSERT INTO message_mentions (message_id, user_name, user_host)
+        SELECT $1, su.user_name, su.user_host
+        FROM server_users su
+        JOIN UNNEST($3::TEXT[], $4::TEXT[]) AS m(name, host)
+            ON su.user_name = m.name AND su.user_host = m.host
+        WHERE su.server_id = $2
+        ON CONFLICT DO NOTHING
+        RETURNING user_name, user_host;
+        "#,
+        message_id.as_uuid(),
+        server_id.as_uuid(),
+        &names,
+        &hosts,
+    )
+    .fetch_all(pool)
+    .await?;
+    let members = rows
+        .into_iter()
+        .map(|row| UserRef::new(row.user_name, row.user_host))
+        .collect();
+    Ok(members)
+}
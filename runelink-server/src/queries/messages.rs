@@ -1,78 +1,190 @@
 use runelink_types::{
     channel::ChannelId,
-    message::{Message, MessageId, NewMessage},
+    message::{
+        AttachmentRef, Message, MessageId, MessageUpdate, NewMessage,
+        ReactionCount,
+    },
     server::ServerId,
-    user::User,
+    user::{User, UserRef},
 };
 use serde::{Deserialize, Serialize};
 use sqlx::types::Json;
 use time::OffsetDateTime;
 use uuid::Uuid;
 
-use crate::{db::DbPool, error::ApiResult};
+use crate::{
+    db::DbPool,
+    error::{ApiError, ApiResult},
+};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbMessage {
     pub id: MessageId,
     pub channel_id: ChannelId,
+    pub server_id: ServerId,
     pub author: Option<Json<User>>,
     pub body: String,
+    pub system: bool,
     #[serde(with = "time::serde::rfc3339")]
     pub created_at: OffsetDateTime,
     #[serde(with = "time::serde::rfc3339")]
     pub updated_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub edited_at: Option<OffsetDateTime>,
+    pub deleted: bool,
+    pub reactions: Json<Vec<ReactionCount>>,
+    pub attachments: Json<Vec<AttachmentRef>>,
 }
 
 impl From<DbMessage> for Message {
     fn from(msg: DbMessage) -> Self {
-        Message {
+        let mut message = Message {
             id: msg.id,
             channel_id: msg.channel_id,
+            server_id: msg.server_id,
             author: msg.author.map(|json_user| json_user.0),
             body: msg.body,
+            system: msg.system,
             created_at: msg.created_at,
             updated_at: msg.updated_at,
+            edited_at: msg.edited_at,
+            deleted: msg.deleted,
+            reactions: msg.reactions.0,
+            attachments: msg.attachments.0,
+        };
+        // A tombstone only says that a message was there
+        if message.deleted {
+            message.body.clear();
+            message.reactions.clear();
+            message.attachments.clear();
         }
+        message
     }
 }
 
+/// Insert a message, attaching the author's uploads listed on it.
+///
+/// The attachments must already be recorded for the channel and not be on
+/// another message.
 pub async fn insert(
     pool: &DbPool,
     channel_id: ChannelId,
     new_message: &NewMessage,
+    system: bool,
 ) -> ApiResult<Message> {
+    let mut tx = pool.begin().await?;
     let new_id: Uuid = sqlx::query_scalar!(
         r#"
-        INSERT INTO messages (channel_id, author_name, author_host, body)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO messages (
+            channel_id, server_id, author_name, author_host, body, system
+        )
+        SELECT c.id, c.server_id, $2, $3, $4, $5
+        FROM channels c
+        WHERE c.id = $1
         RETURNING id;
         "#,
         channel_id.as_uuid(),
         new_message.author.name,
         new_message.author.host,
         new_message.body,
+        system,
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await?;
-    let message = get_by_id(pool, new_id.into()).await?;
+    if !new_message.attachments.is_empty() {
+        // Attachments not known here yet are a remote author's, which are
+        // only recorded once their message is
+        for attachment in &new_message.attachments {
+            sqlx::query!(
+                r#"
+                INSERT INTO attachments (
+                    id, channel_id, uploader_name, uploader_host, filename,
+                    content_type, size, url, stored
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, FALSE)
+                ON CONFLICT (id) DO NOTHING;
+                "#,
+                attachment.id.as_uuid(),
+                channel_id.as_uuid(),
+                new_message.author.name,
+                new_message.author.host,
+                attachment.filename,
+                attachment.content_type,
+                attachment.size,
+                attachment.url,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        let attachment_ids = new_message
+            .attachments
+            .iter()
+            .map(|attachment| attachment.id.as_uuid())
+            .collect::<Vec<_>>();
+        let attached = sqlx::query!(
+            r#"
+            UPDATE attachments
+            SET message_id = $1
+            WHERE id = ANY($2)
+                AND channel_id = $3
+                AND uploader_name = $4
+                AND uploader_host = $5
+                AND message_id IS NULL
+                AND remote_message_id IS NULL;
+            "#,
+            new_id,
+            &attachment_ids,
+            channel_id.as_uuid(),
+            new_message.author.name,
+            new_message.author.host,
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if attached != attachment_ids.len() as u64 {
+            return Err(ApiError::BadRequest(
+                "Attachment not found or already used".into(),
+            ));
+        }
+    }
+    tx.commit().await?;
+    let message = get_by_id(pool, new_id.into(), None).await?;
     Ok(message)
 }
 
-pub async fn get_all(pool: &DbPool) -> ApiResult<Vec<Message>> {
+/// Returns every message, newest first. Tombstones are included, blanked,
+/// only with `include_deleted`.
+pub async fn get_all(
+    pool: &DbPool,
+    viewer: Option<&UserRef>,
+    include_deleted: bool,
+) -> ApiResult<Vec<Message>> {
     let rows = sqlx::query_as!(
         DbMessage,
         r#"
         SELECT
             m.id,
             m.channel_id,
+            m.server_id,
             m.body,
+            m.system,
             m.created_at,
             m.updated_at,
-            to_jsonb(a) AS "author: Json<User>"
+            m.edited_at,
+            m.deleted_at IS NOT NULL AS "deleted!",
+            to_jsonb(a) AS "author: Json<User>",
+            message_reaction_counts(m.id, $1, $2)
+                AS "reactions!: Json<Vec<ReactionCount>>",
+            message_attachments(m.id)
+                AS "attachments!: Json<Vec<AttachmentRef>>"
         FROM messages m
         LEFT JOIN users a ON a.name = m.author_name AND a.host = m.author_host
+        WHERE $3 OR m.deleted_at IS NULL
         ORDER BY m.created_at DESC;
-        "#
+        "#,
+        viewer.map(|user| user.name.as_str()),
+        viewer.map(|user| user.host.as_str()),
+        include_deleted,
     )
     .fetch_all(pool)
     .await?;
@@ -80,9 +192,13 @@ pub async fn get_all(pool: &DbPool) -> ApiResult<Vec<Message>> {
     Ok(messages)
 }
 
+/// Returns the server's messages, newest first. Tombstones are included,
+/// blanked, only with `include_deleted`.
 pub async fn get_by_server(
     pool: &DbPool,
     server_id: ServerId,
+    viewer: Option<&UserRef>,
+    include_deleted: bool,
 ) -> ApiResult<Vec<Message>> {
     let rows = sqlx::query_as!(
         DbMessage,
@@ -90,17 +206,27 @@ pub async fn get_by_server(
         SELECT
             m.id,
             m.channel_id,
+            m.server_id,
             m.body,
+            m.system,
             m.created_at,
             m.updated_at,
-            to_jsonb(a) AS "author: Json<User>"
+            m.edited_at,
+            m.deleted_at IS NOT NULL AS "deleted!",
+            to_jsonb(a) AS "author: Json<User>",
+            message_reaction_counts(m.id, $2, $3)
+                AS "reactions!: Json<Vec<ReactionCount>>",
+            message_attachments(m.id)
+                AS "attachments!: Json<Vec<AttachmentRef>>"
         FROM messages m
         LEFT JOIN users a ON a.name = m.author_name AND a.host = m.author_host
-        JOIN channels c ON c.id = m.channel_id
-        WHERE c.server_id = $1
+        WHERE m.server_id = $1 AND ($4 OR m.deleted_at IS NULL)
         ORDER BY m.created_at DESC;
         "#,
         server_id.as_uuid(),
+        viewer.map(|user| user.name.as_str()),
+        viewer.map(|user| user.host.as_str()),
+        include_deleted,
     )
     .fetch_all(pool)
     .await?;
@@ -108,9 +234,18 @@ pub async fn get_by_server(
     Ok(messages)
 }
 
+/// Returns up to `limit` messages in the channel, newest first.
+///
+/// With `before`, only messages older than that message are returned. Paging
+/// is keyset-based on `(created_at, id)`, so it stays cheap deep in history.
+/// Tombstones are included, blanked, only with `include_deleted`.
 pub async fn get_by_channel(
     pool: &DbPool,
     channel_id: ChannelId,
+    before: Option<MessageId>,
+    limit: u32,
+    viewer: Option<&UserRef>,
+    include_deleted: bool,
 ) -> ApiResult<Vec<Message>> {
     let rows = sqlx::query_as!(
         DbMessage,
@@ -118,16 +253,83 @@ pub async fn get_by_channel(
         SELECT
             m.id,
             m.channel_id,
+            m.server_id,
             m.body,
+            m.system,
             m.created_at,
             m.updated_at,
-            to_jsonb(a) AS "author: Json<User>"
+            m.edited_at,
+            m.deleted_at IS NOT NULL AS "deleted!",
+            to_jsonb(a) AS "author: Json<User>",
+            message_reaction_counts(m.id, $4, $5)
+                AS "reactions!: Json<Vec<ReactionCount>>",
+            message_attachments(m.id)
+                AS "attachments!: Json<Vec<AttachmentRef>>"
         FROM messages m
         LEFT JOIN users a ON a.name = m.author_name AND a.host = m.author_host
         WHERE m.channel_id = $1
-        ORDER BY m.created_at DESC;
+            AND ($6 OR m.deleted_at IS NULL)
+            -- Without a cursor the bound is past every row. Unlike an OR,
+            -- this stays an index condition in a generic plan.
+            AND (m.created_at, m.id) < (
+                SELECT c.created_at, c.id
+                FROM messages c
+                WHERE c.id = $2 AND c.channel_id = $1
+                UNION ALL
+                SELECT
+                    'infinity'::timestamptz,
+                    'ffffffff-ffff-ffff-ffff-ffffffffffff'::uuid
+                WHERE $2::uuid IS NULL
+            )
+        ORDER BY m.created_at DESC, m.id DESC
+        LIMIT $3;
         "#,
         channel_id.as_uuid(),
+        before.map(|id| id.as_uuid()),
+        i64::from(limit),
+        viewer.map(|user| user.name.as_str()),
+        viewer.map(|user| user.host.as_str()),
+        include_deleted,
+    )
+    .fetch_all(pool)
+    .await?;
+    let messages = rows.into_iter().map(Message::from).collect();
+    Ok(messages)
+}
+
+/// Returns the most recent message in each channel of the server, skipping
+/// tombstones.
+pub async fn get_latest_by_server(
+    pool: &DbPool,
+    server_id: ServerId,
+    viewer: Option<&UserRef>,
+) -> ApiResult<Vec<Message>> {
+    let rows = sqlx::query_as!(
+        DbMessage,
+        r#"
+        SELECT DISTINCT ON (m.channel_id)
+            m.id,
+            m.channel_id,
+            m.server_id,
+            m.body,
+            m.system,
+            m.created_at,
+            m.updated_at,
+            m.edited_at,
+            m.deleted_at IS NOT NULL AS "deleted!",
+            to_jsonb(a) AS "author: Json<User>",
+            message_reaction_counts(m.id, $2, $3)
+                AS "reactions!: Json<Vec<ReactionCount>>",
+            message_attachments(m.id)
+                AS "attachments!: Json<Vec<AttachmentRef>>"
+        FROM messages m
+        LEFT JOIN users a ON a.name = m.author_name AND a.host = m.author_host
+        WHERE m.server_id = $1 AND m.deleted_at IS NULL
+        ORDER BY m.channel_id, m.created_at DESC;
+        "#,
+        server_id.as_uuid(),
+        viewer.map(|user| user.name.as_str()),
+        viewer.map(|user| user.host.as_str()),
     )
     .fetch_all(pool)
     .await?;
@@ -135,31 +337,496 @@ pub async fn get_by_channel(
     Ok(messages)
 }
 
-pub async fn get_by_id(pool: &DbPool, msg_id: MessageId) -> ApiResult<Message> {
+/// When `author` last posted in the channel, if ever.
+pub async fn last_posted_at(
+    pool: &DbPool,
+    channel_id: ChannelId,
+    author: &UserRef,
+) -> ApiResult<Option<OffsetDateTime>> {
+    let created_at = sqlx::query_scalar!(
+        r#"
+        SELECT MAX(created_at)
+        FROM messages
+        WHERE channel_id = $1 AND author_name = $2 AND author_host = $3;
+        "#,
+        channel_id.as_uuid(),
+        author.name,
+        author.host,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(created_at)
+}
+
+/// Fetches a message. `viewer` decides which reactions are marked as
+/// `reacted`; pass `None` for messages that are pushed to many users.
+/// Queries shorter than this many characters match by substring instead of
+/// full-text search, which needs whole words to be useful.
+const MIN_FULL_TEXT_QUERY_LENGTH: usize = 3;
+
+/// Returns up to `limit` messages in the server matching `query`.
+///
+/// Longer queries use full-text search ranked by relevance, newest first
+/// among equals. Short ones fall back to a case-insensitive substring
+/// match, newest first.
+pub async fn search(
+    pool: &DbPool,
+    server_id: ServerId,
+    query: &str,
+    limit: u32,
+    viewer: Option<&UserRef>,
+) -> ApiResult<Vec<Message>> {
+    let rows = if query.chars().count() < MIN_FULL_TEXT_QUERY_LENGTH {
+        sqlx::query_as!(
+            DbMessage,
+            r#"
+            SELECT
+                m.id,
+                m.channel_id,
+                m.server_id,
+                m.body,
+                m.system,
+                m.created_at,
+                m.updated_at,
+                m.edited_at,
+                m.deleted_at IS NOT NULL AS "deleted!",
+                to_jsonb(a) AS "author: Json<User>",
+                message_reaction_counts(m.id, $4, $5)
+                    AS "reactions!: Json<Vec<ReactionCount>>",
+                message_attachments(m.id)
+                    AS "attachments!: Json<Vec<AttachmentRef>>"
+            FROM messages m
+            LEFT JOIN users a
+                ON a.name = m.author_name AND a.host = m.author_host
+            WHERE m.server_id = $1
+                AND m.deleted_at IS NULL
+                AND m.body ILIKE $2
+            ORDER BY m.created_at DESC, m.id DESC
+            LIMIT $3;
+            "#,
+            server_id.as_uuid(),
+            like_pattern(query),
+            i64::from(limit),
+            viewer.map(|user| user.name.as_str()),
+            viewer.map(|user| user.host.as_str()),
+        )
+        .fetch_all(pool)
+        .await?
+    } else {
+        sqlx::query_as!(
+            DbMessage,
+            r#"
+            SELECT
+                m.id,
+                m.channel_id,
+                m.server_id,
+                m.body,
+                m.system,
+                m.created_at,
+                m.updated_at,
+                m.edited_at,
+                m.deleted_at IS NOT NULL AS "deleted!",
+                to_jsonb(a) AS "author: Json<User>",
+                message_reaction_counts(m.id, $4, $5)
+                    AS "reactions!: Json<Vec<ReactionCount>>",
+                message_attachments(m.id)
+                    AS "attachments!: Json<Vec<AttachmentRef>>"
+            FROM messages m
+            LEFT JOIN users a
+                ON a.name = m.author_name AND a.host = m.author_host
+            WHERE m.server_id = $1
+                AND m.deleted_at IS NULL
+                AND m.search_vector @@ websearch_to_tsquery('simple', $2)
+            ORDER BY
+                ts_rank(m.search_vector, websearch_to_tsquery('simple', $2))
+                    DESC,
+                m.created_at DESC,
+                m.id DESC
+            LIMIT $3;
+            "#,
+            server_id.as_uuid(),
+            query,
+            i64::from(limit),
+            viewer.map(|user| user.name.as_str()),
+            viewer.map(|user| user.host.as_str()),
+        )
+        .fetch_all(pool)
+        .await?
+    };
+    let messages = rows.into_iter().map(Message::from).collect();
+    Ok(messages)
+}
+
+/// An `ILIKE` pattern matching `query` anywhere, with its wildcards escaped.
+pub(super) fn like_pattern(query: &str) -> String {
+    let mut pattern = String::with_capacity(query.len() + 2);
+    pattern.push('%');
+    for c in query.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+pub async fn get_by_id(
+    pool: &DbPool,
+    msg_id: MessageId,
+    viewer: Option<&UserRef>,
+) -> ApiResult<Message> {
     let db_message = sqlx::query_as!(
         DbMessage,
         r#"
         SELECT
             m.id,
             m.channel_id,
+            m.server_id,
             m.body,
+            m.system,
             m.created_at,
             m.updated_at,
-            to_jsonb(a) AS "author: Json<User>"
+            m.edited_at,
+            m.deleted_at IS NOT NULL AS "deleted!",
+            to_jsonb(a) AS "author: Json<User>",
+            message_reaction_counts(m.id, $2, $3)
+                AS "reactions!: Json<Vec<ReactionCount>>",
+            message_attachments(m.id)
+                AS "attachments!: Json<Vec<AttachmentRef>>"
         FROM messages m
         LEFT JOIN users a ON a.name = m.author_name AND a.host = m.author_host
-        WHERE m.id = $1;
+        WHERE m.id = $1 AND m.deleted_at IS NULL;
         "#,
         msg_id.as_uuid(),
+        viewer.map(|user| user.name.as_str()),
+        viewer.map(|user| user.host.as_str()),
     )
     .fetch_one(pool)
     .await?;
     Ok(db_message.into())
 }
 
-pub async fn delete(pool: &DbPool, message_id: MessageId) -> ApiResult<()> {
-    sqlx::query!("DELETE FROM messages WHERE id = $1;", message_id.as_uuid())
-        .execute(pool)
-        .await?;
+/// Get a message, provided it is in the given server and channel.
+pub async fn get_by_id_scoped(
+    pool: &DbPool,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    msg_id: MessageId,
+    viewer: Option<&UserRef>,
+) -> ApiResult<Message> {
+    let db_message = sqlx::query_as!(
+        DbMessage,
+        r#"
+        SELECT
+            m.id,
+            m.channel_id,
+            m.server_id,
+            m.body,
+            m.system,
+            m.created_at,
+            m.updated_at,
+            m.edited_at,
+            m.deleted_at IS NOT NULL AS "deleted!",
+            to_jsonb(a) AS "author: Json<User>",
+            message_reaction_counts(m.id, $4, $5)
+                AS "reactions!: Json<Vec<ReactionCount>>",
+            message_attachments(m.id)
+                AS "attachments!: Json<Vec<AttachmentRef>>"
+        FROM messages m
+        LEFT JOIN users a ON a.name = m.author_name AND a.host = m.author_host
+        WHERE m.id = $3
+            AND m.channel_id = $2
+            AND m.server_id = $1
+            AND m.deleted_at IS NULL;
+        "#,
+        server_id.as_uuid(),
+        channel_id.as_uuid(),
+        msg_id.as_uuid(),
+        viewer.map(|user| user.name.as_str()),
+        viewer.map(|user| user.host.as_str()),
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(db_message.into())
+}
+
+/// Replace a message's body and stamp `edited_at`.
+pub async fn update(
+    pool: &DbPool,
+    message_id: MessageId,
+    update: &MessageUpdate,
+) -> ApiResult<Message> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE messages
+        SET body = $2, edited_at = NOW()
+        WHERE id = $1 AND deleted_at IS NULL;
+        "#,
+        message_id.as_uuid(),
+        update.body,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    if updated == 0 {
+        return Err(ApiError::NotFound);
+    }
+    get_by_id(pool, message_id, None).await
+}
+
+/// Replace a message with a tombstone, provided it is in the given server
+/// and channel. The body is wiped now; the row stays until purged.
+pub async fn delete_scoped(
+    pool: &DbPool,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+) -> ApiResult<()> {
+    let deleted = sqlx::query!(
+        r#"
+        UPDATE messages
+        SET deleted_at = NOW(), body = ''
+        WHERE id = $3
+            AND channel_id = $2
+            AND server_id = $1
+            AND deleted_at IS NULL;
+        "#,
+        server_id.as_uuid(),
+        channel_id.as_uuid(),
+        message_id.as_uuid(),
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    if deleted == 0 {
+        return Err(ApiError::NotFound);
+    }
     Ok(())
 }
+
+/// Deletes every message in a channel, or only those older than `before`,
+/// in one statement, leaving tombstones. Returns how many were deleted.
+pub async fn purge_channel(
+    pool: &DbPool,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    before: Option<MessageId>,
+) -> ApiResult<u64> {
+    let purged = sqlx::query!(
+        r#"
+        UPDATE messages m
+        SET deleted_at = NOW(), body = ''
+        WHERE m.server_id = $1
+            AND m.channel_id = $2
+            AND m.deleted_at IS NULL
+            AND (
+                $3::uuid IS NULL
+                OR (m.created_at, m.id) < (
+                    SELECT c.created_at, c.id
+                    FROM messages c
+                    WHERE c.id = $3 AND c.channel_id = $2
+                )
+            );
+        "#,
+        server_id.as_uuid(),
+        channel_id.as_uuid(),
+        before.map(|id| id.as_uuid()),
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(purged)
+}
+
+/// Removes tombstones of messages deleted before `deleted_before`,
+/// returning how many were removed.
+pub async fn purge_deleted(
+    pool: &DbPool,
+    deleted_before: OffsetDateTime,
+) -> ApiResult<u64> {
+    let purged = sqlx::query!(
+        "DELETE FROM messages WHERE deleted_at < $1;",
+        deleted_before,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(purged)
+}
+
+/// Adds a user's reaction. Returns false if it was already there.
+pub async fn add_reaction(
+    pool: &DbPool,
+    message_id: MessageId,
+    user_ref: &UserRef,
+    emoji: &str,
+) -> ApiResult<bool> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO message_reactions (message_id, user_name, user_host, emoji)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO NOTHING;
+        "#,
+        message_id.as_uuid(),
+        user_ref.name,
+        user_ref.host,
+        emoji,
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Removes a user's reaction. Returns false if there was none.
+pub async fn remove_reaction(
+    pool: &DbPool,
+    message_id: MessageId,
+    user_ref: &UserRef,
+    emoji: &str,
+) -> ApiResult<bool> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM message_reactions
+        WHERE message_id = $1
+            AND user_name = $2
+            AND user_host = $3
+            AND emoji = $4;
+        "#,
+        message_id.as_uuid(),
+        user_ref.name,
+        user_ref.host,
+        emoji,
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns the distinct emoji a message has been reacted with.
+pub async fn get_reaction_emojis(
+    pool: &DbPool,
+    message_id: MessageId,
+) -> ApiResult<Vec<String>> {
+    let emojis = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT emoji
+        FROM message_reactions
+        WHERE message_id = $1;
+        "#,
+        message_id.as_uuid(),
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(emojis)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::test_util;
+
+    #[sqlx::test]
+    async fn test_latest_by_server_is_newest_message_per_channel(pool: DbPool) {
+        let state = test_util::state(pool);
+        let author = test_util::local_user(&state, "author").await;
+        let server =
+            test_util::server(&state, &author.as_ref(), "Server").await;
+        let general = test_util::channel(&state, server.id, "general").await;
+        let random = test_util::channel(&state, server.id, "random").await;
+        test_util::channel(&state, server.id, "empty").await;
+
+        let mut newest = HashMap::new();
+        for (channel_id, body) in [
+            (general.id, "one"),
+            (random.id, "two"),
+            (general.id, "three"),
+            (random.id, "four"),
+            (general.id, "five"),
+        ] {
+            let new_message = NewMessage {
+                author: author.as_ref(),
+                body: body.into(),
+                attachments: Vec::new(),
+            };
+            let message =
+                insert(&state.db_pool, channel_id, &new_message, false)
+                    .await
+                    .unwrap();
+            newest.insert(channel_id, message);
+        }
+
+        let latest = get_latest_by_server(&state.db_pool, server.id, None)
+            .await
+            .unwrap();
+        // The empty channel has no preview
+        assert_eq!(latest.len(), 2);
+        for message in latest {
+            assert_eq!(message.id, newest[&message.channel_id].id);
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_editing_a_tombstone_is_not_found(pool: DbPool) {
+        let state = test_util::state(pool);
+        let author = test_util::local_user(&state, "author").await;
+        let server =
+            test_util::server(&state, &author.as_ref(), "Server").await;
+        let channel = test_util::channel(&state, server.id, "general").await;
+        let new_message = NewMessage {
+            author: author.as_ref(),
+            body: "hello".into(),
+            attachments: Vec::new(),
+        };
+        let message = insert(&state.db_pool, channel.id, &new_message, false)
+            .await
+            .unwrap();
+        delete_scoped(&state.db_pool, server.id, channel.id, message.id)
+            .await
+            .unwrap();
+
+        let edit = MessageUpdate {
+            body: "edited".into(),
+        };
+        assert!(matches!(
+            update(&state.db_pool, message.id, &edit).await,
+            Err(ApiError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_like_pattern_escapes_wildcards() {
+        assert_eq!(like_pattern("hi"), "%hi%");
+        assert_eq!(like_pattern("5%"), "%5\\%%");
+        assert_eq!(like_pattern("a_b\\"), "%a\\_b\\\\%");
+    }
+
+    #[test]
+    fn test_tombstone_is_blanked() {
+        let now = OffsetDateTime::now_utc();
+        let row = DbMessage {
+            id: MessageId::new(),
+            channel_id: ChannelId::new(),
+            server_id: ServerId::new(),
+            author: None,
+            body: "secret".into(),
+            system: false,
+            created_at: now,
+            updated_at: now,
+            edited_at: None,
+            deleted: true,
+            reactions: Json(vec![ReactionCount {
+                emoji: "👍".into(),
+                count: 1,
+                reacted: false,
+            }]),
+            attachments: Json(Vec::new()),
+        };
+        let message = Message::from(row);
+        assert!(message.deleted);
+        assert!(message.body.is_empty());
+        assert!(message.reactions.is_empty());
+    }
+}
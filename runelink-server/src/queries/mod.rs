@@ -1,7 +1,15 @@
 pub mod accounts;
+pub mod analytics;
+pub mod attachments;
+pub mod bans;
 pub mod channels;
+pub mod dms;
+pub mod invites;
 pub mod memberships;
+pub mod mentions;
 pub mod messages;
+pub mod read_states;
 pub mod servers;
 pub mod tokens;
 pub mod users;
+pub mod webhooks;
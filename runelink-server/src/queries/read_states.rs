@@ -0,0 +1,115 @@
+use runelink_types::{
+    channel::{ChannelId, ChannelReadState, ChannelUnreadCount},
+    message::MessageId,
+    server::ServerId,
+    user::UserRef,
+};
+use time::OffsetDateTime;
+
+use crate::{db::DbPool, error::ApiResult};
+
+#[derive(sqlx::FromRow, Debug)]
+struct ReadStateRow {
+    pub server_id: ServerId,
+    pub channel_id: ChannelId,
+    pub user_name: String,
+    pub user_host: String,
+    pub last_read_message_id: MessageId,
+    pub last_read_at: OffsetDateTime,
+}
+
+impl From<ReadStateRow> for ChannelReadState {
+    fn from(row: ReadStateRow) -> Self {
+        ChannelReadState {
+            server_id: row.server_id,
+            channel_id: row.channel_id,
+            user_ref: UserRef::new(row.user_name, row.user_host),
+            last_read_message_id: row.last_read_message_id,
+            last_read_at: row.last_read_at,
+        }
+    }
+}
+
+/// Moves a user's read position in a channel to the given message.
+///
+/// Fails with not found unless the message is in that server and channel.
+pub async fn upsert(
+    pool: &DbPool,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    user_ref: &UserRef,
+) -> ApiResult<ChannelReadState> {
+    let row = sqlx::query_as!(
+        ReadStateRow,
+        r#"
+        INSERT INTO channel_read_state (
+            user_name, user_host, channel_id, server_id,
+            last_read_message_id, last_read_at
+        )
+        SELECT $4, $5, m.channel_id, m.server_id, m.id, m.created_at
+        FROM messages m
+        WHERE m.id = $3 AND m.channel_id = $2 AND m.server_id = $1
+        ON CONFLICT (user_name, user_host, channel_id) DO UPDATE
+            SET last_read_message_id = EXCLUDED.last_read_message_id,
+                last_read_at = EXCLUDED.last_read_at,
+                updated_at = NOW()
+        RETURNING
+            server_id AS "server_id: ServerId",
+            channel_id AS "channel_id: ChannelId",
+            user_name,
+            user_host,
+            last_read_message_id AS "last_read_message_id: MessageId",
+            last_read_at;
+        "#,
+        server_id.as_uuid(),
+        channel_id.as_uuid(),
+        message_id.as_uuid(),
+        user_ref.name,
+        user_ref.host,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.into())
+}
+
+/// Per-channel counts of messages a user hasn't read in a server.
+///
+/// Channels the user has never marked read count every message.
+pub async fn get_unread_counts(
+    pool: &DbPool,
+    server_id: ServerId,
+    user_ref: &UserRef,
+) -> ApiResult<Vec<ChannelUnreadCount>> {
+    let counts = sqlx::query_as!(
+        ChannelUnreadCount,
+        r#"
+        SELECT
+            c.id AS "channel_id: ChannelId",
+            COUNT(m.id) AS "unread!"
+        FROM channels c
+        LEFT JOIN channel_read_state rs
+            ON rs.channel_id = c.id
+            AND rs.user_name = $2
+            AND rs.user_host = $3
+        LEFT JOIN messages m
+            ON m.channel_id = c.id
+            AND (
+                rs.last_read_at IS NULL
+                OR (m.created_at, m.id)
+                    > (rs.last_read_at, rs.last_read_message_id)
+            )
+            AND (m.author_name, m.author_host) IS DISTINCT FROM ($2, $3)
+            AND m.deleted_at IS NULL
+        WHERE c.server_id = $1
+        GROUP BY c.id
+        ORDER BY c.created_at, c.id;
+        "#,
+        server_id.as_uuid(),
+        user_ref.name,
+        user_ref.host,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(counts)
+}
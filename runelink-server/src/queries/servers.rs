@@ -1,8 +1,18 @@
-use runelink_types::server::{NewServer, Server, ServerId};
+use runelink_types::{
+    server::{
+        NewServer, Server, ServerId, ServerMember, ServerRole, ServerSummary,
+        ServerVisibility,
+    },
+    user::UserRef,
+};
 use time::OffsetDateTime;
 
+use super::memberships;
 use crate::{
-    config::ServerConfig, db::DbPool, error::ApiResult, state::AppState,
+    config::ServerConfig,
+    db::DbPool,
+    error::{ApiError, ApiResult},
+    state::AppState,
 };
 
 #[derive(sqlx::FromRow, Debug)]
@@ -10,6 +20,7 @@ struct LocalServerRow {
     pub id: ServerId,
     pub title: String,
     pub description: Option<String>,
+    pub visibility: ServerVisibility,
     pub created_at: OffsetDateTime,
     pub updated_at: OffsetDateTime,
     // No 'host' field
@@ -22,43 +33,91 @@ impl LocalServerRow {
             host: config.public_host(),
             title: self.title,
             description: self.description,
+            visibility: self.visibility,
             created_at: self.created_at,
             updated_at: self.updated_at,
         }
     }
 }
 
-pub async fn insert(
+/// Insert a server along with its creator as admin, in one transaction, so
+/// there's never a server without an admin.
+///
+/// The creator gets a user record first if they don't have one yet, e.g.
+/// when they're from another host.
+pub async fn insert_with_owner(
     state: &AppState,
     new_server: &NewServer,
-) -> ApiResult<Server> {
+    owner: &UserRef,
+) -> ApiResult<(Server, ServerMember)> {
+    let mut tx = state.db_pool.begin().await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO users (name, host, role)
+        VALUES ($1, $2, 'user')
+        ON CONFLICT (name, host) DO UPDATE SET updated_at = NOW();
+        "#,
+        owner.name,
+        owner.host,
+    )
+    .execute(&mut *tx)
+    .await?;
     let row = sqlx::query_as!(
         LocalServerRow,
         r#"
-        INSERT INTO servers (title, description)
-        VALUES ($1, $2)
-        RETURNING *;
+        INSERT INTO servers (title, description, visibility)
+        VALUES ($1, $2, $3)
+        RETURNING
+            id,
+            title,
+            description,
+            visibility AS "visibility: ServerVisibility",
+            created_at,
+            updated_at;
         "#,
         new_server.title,
         new_server.description,
+        new_server.visibility as ServerVisibility,
     )
-    .fetch_one(state.db_pool.as_ref())
+    .fetch_one(&mut *tx)
     .await?;
-    Ok(row.into_server(&state.config))
+    sqlx::query!(
+        r#"
+        INSERT INTO server_users (server_id, user_name, user_host, role)
+        VALUES ($1, $2, $3, $4);
+        "#,
+        row.id.as_uuid(),
+        owner.name,
+        owner.host,
+        ServerRole::Admin as ServerRole,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    let server = row.into_server(&state.config);
+    let member = memberships::get_local_member_by_user_and_server(
+        &state.db_pool,
+        server.id,
+        owner.clone(),
+    )
+    .await?;
+    Ok((server, member))
 }
 
 pub async fn upsert_remote(pool: &DbPool, server: &Server) -> ApiResult<()> {
     sqlx::query!(
         r#"
         INSERT INTO cached_remote_servers (
-            id, host, title, description, remote_created_at,
+            id, host, title, description, visibility, remote_created_at,
             remote_updated_at, synced_at
         )
-        VALUES ($1, $2, $3, $4, $5, $6, NOW())
+        VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
         ON CONFLICT(id) DO UPDATE
             SET host = EXCLUDED.host,
                 title = EXCLUDED.title,
                 description = EXCLUDED.description,
+                visibility = EXCLUDED.visibility,
                 remote_created_at = EXCLUDED.remote_created_at,
                 remote_updated_at = EXCLUDED.remote_updated_at,
                 synced_at = NOW()
@@ -67,6 +126,7 @@ pub async fn upsert_remote(pool: &DbPool, server: &Server) -> ApiResult<()> {
         server.host,
         server.title,
         server.description,
+        server.visibility as ServerVisibility,
         server.created_at,
         server.updated_at,
     )
@@ -80,7 +140,17 @@ pub async fn get_by_id(
 ) -> ApiResult<Server> {
     let row = sqlx::query_as!(
         LocalServerRow,
-        "SELECT * FROM servers WHERE id = $1;",
+        r#"
+        SELECT
+            id,
+            title,
+            description,
+            visibility AS "visibility: ServerVisibility",
+            created_at,
+            updated_at
+        FROM servers
+        WHERE id = $1;
+        "#,
         server_id.as_uuid(),
     )
     .fetch_one(state.db_pool.as_ref())
@@ -88,20 +158,280 @@ pub async fn get_by_id(
     Ok(row.into_server(&state.config))
 }
 
-pub async fn get_all(state: &AppState) -> ApiResult<Vec<Server>> {
-    let rows = sqlx::query_as!(LocalServerRow, "SELECT * FROM servers",)
-        .fetch_all(state.db_pool.as_ref())
-        .await?;
+/// A local server with its member and channel counts.
+pub async fn get_summary_by_id(
+    state: &AppState,
+    server_id: ServerId,
+) -> ApiResult<ServerSummary> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            s.id AS "id: ServerId",
+            s.title,
+            s.description,
+            s.visibility AS "visibility: ServerVisibility",
+            s.created_at,
+            s.updated_at,
+            (
+                SELECT COUNT(*) FROM server_users su WHERE su.server_id = s.id
+            ) AS "member_count!",
+            (
+                SELECT COUNT(*) FROM channels c WHERE c.server_id = s.id
+            ) AS "channel_count!"
+        FROM servers s
+        WHERE s.id = $1;
+        "#,
+        server_id.as_uuid(),
+    )
+    .fetch_one(state.db_pool.as_ref())
+    .await?;
+    Ok(ServerSummary {
+        server: Server {
+            id: row.id,
+            host: state.config.public_host(),
+            title: row.title,
+            description: row.description,
+            visibility: row.visibility,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        },
+        member_count: Some(row.member_count),
+        channel_count: Some(row.channel_count),
+    })
+}
+
+/// A cached remote server along with when it was last synced.
+pub async fn get_cached_remote_by_id(
+    pool: &DbPool,
+    server_id: ServerId,
+    host: &str,
+) -> ApiResult<Option<(Server, OffsetDateTime)>> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            id AS "id: ServerId",
+            host,
+            title,
+            description,
+            visibility AS "visibility: ServerVisibility",
+            remote_created_at,
+            remote_updated_at,
+            synced_at
+        FROM cached_remote_servers
+        WHERE id = $1 AND host = $2;
+        "#,
+        server_id.as_uuid(),
+        host,
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|row| {
+        let server = Server {
+            id: row.id,
+            host: row.host,
+            title: row.title,
+            description: row.description,
+            visibility: row.visibility,
+            created_at: row.remote_created_at,
+            updated_at: row.remote_updated_at,
+        };
+        (server, row.synced_at)
+    }))
+}
+
+/// Record the counts a remote server's host reported for it. Servers that
+/// aren't cached are left alone.
+pub async fn set_remote_counts(
+    pool: &DbPool,
+    server_id: ServerId,
+    member_count: Option<i64>,
+    channel_count: Option<i64>,
+) -> ApiResult<()> {
+    sqlx::query!(
+        r#"
+        UPDATE cached_remote_servers
+        SET member_count = $2, channel_count = $3
+        WHERE id = $1;
+        "#,
+        server_id.as_uuid(),
+        member_count,
+        channel_count,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// The last counts reported for a cached remote server, if any.
+pub async fn get_remote_counts(
+    pool: &DbPool,
+    server_id: ServerId,
+) -> ApiResult<(Option<i64>, Option<i64>)> {
+    let row = sqlx::query!(
+        r#"
+        SELECT member_count, channel_count
+        FROM cached_remote_servers
+        WHERE id = $1;
+        "#,
+        server_id.as_uuid(),
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map_or((None, None), |row| (row.member_count, row.channel_count)))
+}
+
+/// IDs and hosts of cached remote servers, optionally filtered by either.
+pub async fn get_cached_remote_refs(
+    pool: &DbPool,
+    server_id: Option<ServerId>,
+    host: Option<&str>,
+) -> ApiResult<Vec<(ServerId, String)>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id AS "id: ServerId", host
+        FROM cached_remote_servers
+        WHERE ($1::uuid IS NULL OR id = $1)
+            AND ($2::text IS NULL OR host = $2)
+        ORDER BY host, id;
+        "#,
+        server_id.map(|id| id.as_uuid()),
+        host,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|row| (row.id, row.host)).collect())
+}
+
+pub async fn exists(pool: &DbPool, server_id: ServerId) -> ApiResult<bool> {
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM servers WHERE id = $1) AS "exists!";"#,
+        server_id.as_uuid(),
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(exists)
+}
+
+/// List local servers with their member and channel counts. Private ones
+/// are only included when asked for, and the hidden server holding direct
+/// messages never is.
+pub async fn get_all(
+    state: &AppState,
+    include_private: bool,
+) -> ApiResult<Vec<ServerSummary>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            s.id AS "id: ServerId",
+            s.title,
+            s.description,
+            s.visibility AS "visibility: ServerVisibility",
+            s.created_at,
+            s.updated_at,
+            (
+                SELECT COUNT(*) FROM server_users su WHERE su.server_id = s.id
+            ) AS "member_count!",
+            (
+                SELECT COUNT(*) FROM channels c WHERE c.server_id = s.id
+            ) AS "channel_count!"
+        FROM servers s
+        WHERE NOT s.direct AND ($1 OR s.visibility = 'public')
+        ORDER BY s.created_at;
+        "#,
+        include_private,
+    )
+    .fetch_all(state.db_pool.as_ref())
+    .await?;
     let servers = rows
         .into_iter()
-        .map(|row| row.into_server(&state.config))
+        .map(|row| ServerSummary {
+            server: Server {
+                id: row.id,
+                host: state.config.public_host(),
+                title: row.title,
+                description: row.description,
+                visibility: row.visibility,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+            },
+            member_count: Some(row.member_count),
+            channel_count: Some(row.channel_count),
+        })
         .collect();
     Ok(servers)
 }
 
+/// Delete a local server.
+///
+/// Channels, messages, and memberships are removed by `ON DELETE CASCADE`
+/// in the same statement, so no orphaned rows are left behind.
 pub async fn delete(state: &AppState, server_id: ServerId) -> ApiResult<()> {
-    sqlx::query!("DELETE FROM servers WHERE id = $1;", server_id.as_uuid())
-        .execute(state.db_pool.as_ref())
-        .await?;
+    let result =
+        sqlx::query!("DELETE FROM servers WHERE id = $1;", server_id.as_uuid())
+            .execute(state.db_pool.as_ref())
+            .await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound);
+    }
+    Ok(())
+}
+
+/// Delete a cached remote server along with local users' memberships in it.
+pub async fn delete_cached_remote(
+    pool: &DbPool,
+    server_id: ServerId,
+) -> ApiResult<()> {
+    sqlx::query!(
+        "DELETE FROM cached_remote_servers WHERE id = $1;",
+        server_id.as_uuid()
+    )
+    .execute(pool)
+    .await?;
     Ok(())
 }
+
+/// Rows removed by `prune_stale_remote`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PrunedRemote {
+    pub memberships: u64,
+    pub servers: u64,
+}
+
+/// Delete cached remote memberships not synced since `synced_before`, then
+/// cached remote servers that are as stale or have no memberships left.
+pub async fn prune_stale_remote(
+    pool: &DbPool,
+    synced_before: OffsetDateTime,
+) -> ApiResult<PrunedRemote> {
+    let mut tx = pool.begin().await?;
+    let memberships = sqlx::query!(
+        r#"
+        DELETE FROM user_remote_server_memberships
+        WHERE synced_at < $1;
+        "#,
+        synced_before,
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    let servers = sqlx::query!(
+        r#"
+        DELETE FROM cached_remote_servers s
+        WHERE s.synced_at < $1
+            OR NOT EXISTS (
+                SELECT 1
+                FROM user_remote_server_memberships m
+                WHERE m.remote_server_id = s.id
+            );
+        "#,
+        synced_before,
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    tx.commit().await?;
+    Ok(PrunedRemote {
+        memberships,
+        servers,
+    })
+}
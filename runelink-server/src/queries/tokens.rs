@@ -1,4 +1,4 @@
-use runelink_types::RefreshToken;
+use runelink_types::{RefreshToken, SessionInfo, UserRef};
 
 use crate::{db::DbPool, error::ApiResult};
 
@@ -9,15 +9,17 @@ pub async fn insert_refresh(
     let refresh_token = sqlx::query_as!(
         RefreshToken,
         r#"
-        INSERT INTO refresh_tokens (token, user_name, user_host, client_id, issued_at,
-                                    expires_at, revoked)
-        VALUES ($1, $2, $3, $4, $5, $6, $7)
-        RETURNING token, user_name, user_host, client_id, issued_at, expires_at, revoked
+        INSERT INTO refresh_tokens (token, user_name, user_host, client_id, scope,
+                                    issued_at, expires_at, revoked)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING token, user_name, user_host, client_id, scope, issued_at,
+                  expires_at, revoked
         "#,
         rt.token,
         rt.user_name,
         rt.user_host,
         rt.client_id,
+        rt.scope,
         rt.issued_at,
         rt.expires_at,
         rt.revoked,
@@ -34,7 +36,8 @@ pub async fn get_refresh(
     let refresh_token = sqlx::query_as!(
         RefreshToken,
         r#"
-        SELECT token, user_name, user_host, client_id, issued_at, expires_at, revoked
+        SELECT token, user_name, user_host, client_id, scope, issued_at,
+               expires_at, revoked
         FROM refresh_tokens
         WHERE token = $1
         "#,
@@ -59,3 +62,58 @@ pub async fn revoke_refresh(pool: &DbPool, token_str: &str) -> ApiResult<()> {
     .await?;
     Ok(())
 }
+
+/// Refresh tokens of a user that are neither revoked nor expired, newest
+/// first.
+pub async fn get_active_by_user(
+    pool: &DbPool,
+    user_ref: &UserRef,
+) -> ApiResult<Vec<SessionInfo>> {
+    let sessions = sqlx::query_as!(
+        SessionInfo,
+        r#"
+        SELECT client_id, issued_at, expires_at
+        FROM refresh_tokens
+        WHERE user_name = $1 AND user_host = $2
+          AND NOT revoked AND expires_at > NOW()
+        ORDER BY issued_at DESC
+        "#,
+        user_ref.name,
+        user_ref.host,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(sessions)
+}
+
+/// Revokes every refresh token of a user, returning how many were active.
+pub async fn revoke_all_by_user(
+    pool: &DbPool,
+    user_ref: &UserRef,
+) -> ApiResult<u64> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked = TRUE
+        WHERE user_name = $1 AND user_host = $2 AND NOT revoked
+        "#,
+        user_ref.name,
+        user_ref.host,
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Deletes refresh tokens past their expiry, returning how many.
+pub async fn delete_expired(pool: &DbPool) -> ApiResult<u64> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM refresh_tokens
+        WHERE expires_at <= NOW()
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
@@ -1,9 +1,23 @@
-use runelink_types::{NewUser, User, UserRef, UserRole};
+use runelink_types::{
+    NewUser, SYSTEM_USER_NAME, User, UserProfileUpdate, UserRef, UserRole,
+    WEBHOOK_USER_NAME,
+};
 use time::OffsetDateTime;
 
-use crate::{db::DbPool, error::ApiResult};
+use crate::{
+    db::DbPool,
+    error::{ApiError, ApiResult},
+};
 
+/// Insert a new user. The host's reserved identities can't be claimed here;
+/// they are created on first use by [`insert_if_missing`].
 pub async fn insert(pool: &DbPool, new_user: &NewUser) -> ApiResult<User> {
+    if new_user.name == SYSTEM_USER_NAME || new_user.name == WEBHOOK_USER_NAME {
+        return Err(ApiError::BadRequest(format!(
+            "Username '{}' is reserved",
+            new_user.name
+        )));
+    }
     let user = sqlx::query_as!(
         User,
         r#"
@@ -13,6 +27,8 @@ pub async fn insert(pool: &DbPool, new_user: &NewUser) -> ApiResult<User> {
             name,
             host,
             role AS "role: UserRole",
+            display_name,
+            avatar_url,
             created_at,
             updated_at,
             synced_at;
@@ -22,10 +38,37 @@ pub async fn insert(pool: &DbPool, new_user: &NewUser) -> ApiResult<User> {
         new_user.role as UserRole,
     )
     .fetch_one(pool)
-    .await?;
+    .await
+    .map_err(|error| match ApiError::from(error) {
+        ApiError::Conflict(_) => ApiError::Conflict(format!(
+            "User {}@{} already exists",
+            new_user.name, new_user.host
+        )),
+        other => other,
+    })?;
     Ok(user)
 }
 
+/// Inserts the user if it does not exist yet, leaving existing rows intact.
+pub async fn insert_if_missing(
+    pool: &DbPool,
+    new_user: &NewUser,
+) -> ApiResult<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO users (name, host, role)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (name, host) DO NOTHING;
+        "#,
+        new_user.name,
+        new_user.host,
+        new_user.role as UserRole,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
 pub async fn upsert_remote(
     pool: &DbPool,
     remote_user: &User,
@@ -33,16 +76,23 @@ pub async fn upsert_remote(
     let user = sqlx::query_as!(
         User,
         r#"
-        INSERT INTO users (name, host, role, created_at, updated_at, synced_at)
-        VALUES ($1, $2, $3, $4, $5, $6)
+        INSERT INTO users (
+            name, host, role, display_name, avatar_url, created_at,
+            updated_at, synced_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
         ON CONFLICT (name, host) DO UPDATE SET
             role = EXCLUDED.role,
+            display_name = EXCLUDED.display_name,
+            avatar_url = EXCLUDED.avatar_url,
             updated_at = EXCLUDED.updated_at,
             synced_at = EXCLUDED.synced_at
         RETURNING
             name,
             host,
             role AS "role: UserRole",
+            display_name,
+            avatar_url,
             created_at,
             updated_at,
             synced_at;
@@ -50,6 +100,8 @@ pub async fn upsert_remote(
         remote_user.name,
         remote_user.host,
         UserRole::User as UserRole,
+        remote_user.display_name,
+        remote_user.avatar_url,
         remote_user.created_at,
         remote_user.updated_at,
         OffsetDateTime::now_utc(),
@@ -59,7 +111,16 @@ pub async fn upsert_remote(
     Ok(user)
 }
 
-pub async fn get_all(pool: &DbPool) -> ApiResult<Vec<User>> {
+/// A page of users ordered by host then name, optionally only those from
+/// `host` whose name starts with `name_prefix`.
+pub async fn get_all(
+    pool: &DbPool,
+    name_prefix: Option<&str>,
+    host: Option<&str>,
+    limit: u32,
+    offset: u32,
+) -> ApiResult<Vec<User>> {
+    let name_pattern = name_prefix.map(prefix_pattern);
     let users = sqlx::query_as!(
         User,
         r#"
@@ -67,41 +128,75 @@ pub async fn get_all(pool: &DbPool) -> ApiResult<Vec<User>> {
             name,
             host,
             role AS "role: UserRole",
+            display_name,
+            avatar_url,
             created_at,
             updated_at,
             synced_at
-        FROM users;
-        "#
+        FROM users
+        WHERE ($1::TEXT IS NULL OR name LIKE $1)
+            AND ($2::TEXT IS NULL OR host = $2)
+        ORDER BY host, name
+        LIMIT $3 OFFSET $4;
+        "#,
+        name_pattern,
+        host,
+        i64::from(limit),
+        i64::from(offset),
     )
     .fetch_all(pool)
     .await?;
     Ok(users)
 }
 
-pub async fn ensure_exists(
+/// Up to `limit` of `host`'s users whose name or display name contains
+/// `query`, ignoring case, in name order.
+pub async fn search(
     pool: &DbPool,
-    user_ref: UserRef,
-) -> ApiResult<User> {
-    let user = sqlx::query_as!(
+    host: &str,
+    query: &str,
+    limit: u32,
+) -> ApiResult<Vec<User>> {
+    let pattern = super::messages::like_pattern(query);
+    let users = sqlx::query_as!(
         User,
         r#"
-        INSERT INTO users (name, host, role)
-        VALUES ($1, $2, 'user')
-        ON CONFLICT (name, host) DO UPDATE SET updated_at = NOW()
-        RETURNING
+        SELECT
             name,
             host,
             role AS "role: UserRole",
+            display_name,
+            avatar_url,
             created_at,
             updated_at,
-            synced_at;
+            synced_at
+        FROM users
+        WHERE host = $1
+            AND (name ILIKE $2 OR display_name ILIKE $2)
+        ORDER BY name
+        LIMIT $3;
         "#,
-        user_ref.name,
-        user_ref.host,
+        host,
+        pattern,
+        i64::from(limit),
     )
-    .fetch_one(pool)
+    .fetch_all(pool)
     .await?;
-    Ok(user)
+    Ok(users)
+}
+
+/// A `LIKE` pattern matching names starting with `prefix`, with its
+/// wildcards escaped.
+fn prefix_pattern(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+    for c in prefix.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
 }
 
 pub async fn get_by_ref(pool: &DbPool, user_ref: UserRef) -> ApiResult<User> {
@@ -112,6 +207,8 @@ pub async fn get_by_ref(pool: &DbPool, user_ref: UserRef) -> ApiResult<User> {
             name,
             host,
             role AS "role: UserRole",
+            display_name,
+            avatar_url,
             created_at,
             updated_at,
             synced_at
@@ -126,6 +223,47 @@ pub async fn get_by_ref(pool: &DbPool, user_ref: UserRef) -> ApiResult<User> {
     Ok(user)
 }
 
+/// Applies a partial profile update. Empty strings are stored as NULL.
+pub async fn update_profile(
+    pool: &DbPool,
+    user_ref: &UserRef,
+    update: &UserProfileUpdate,
+) -> ApiResult<User> {
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        UPDATE users
+        SET
+            display_name = CASE
+                WHEN $3::text IS NULL THEN display_name
+                ELSE NULLIF($3, '')
+            END,
+            avatar_url = CASE
+                WHEN $4::text IS NULL THEN avatar_url
+                ELSE NULLIF($4, '')
+            END,
+            updated_at = NOW()
+        WHERE name = $1 AND host = $2
+        RETURNING
+            name,
+            host,
+            role AS "role: UserRole",
+            display_name,
+            avatar_url,
+            created_at,
+            updated_at,
+            synced_at;
+        "#,
+        user_ref.name,
+        user_ref.host,
+        update.display_name,
+        update.avatar_url,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(user)
+}
+
 pub async fn delete(pool: &DbPool, user_ref: UserRef) -> ApiResult<()> {
     sqlx::query!(
         "DELETE FROM users WHERE name = $1 AND host = $2;",
@@ -156,3 +294,31 @@ pub async fn get_associated_hosts(
     .await?;
     Ok(hosts)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_pattern_escapes_wildcards() {
+        assert_eq!(prefix_pattern("ad"), "ad%");
+        assert_eq!(prefix_pattern("a_b%\\"), "a\\_b\\%\\\\%");
+    }
+
+    #[sqlx::test]
+    async fn test_reserved_names_are_rejected(pool: DbPool) {
+        for name in [SYSTEM_USER_NAME, WEBHOOK_USER_NAME] {
+            let new_user = NewUser {
+                name: name.into(),
+                host: "localhost".into(),
+                role: UserRole::User,
+            };
+            assert!(matches!(
+                insert(&pool, &new_user).await,
+                Err(ApiError::BadRequest(_))
+            ));
+            // The host still creates its own reserved identities on first use
+            insert_if_missing(&pool, &new_user).await.unwrap();
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use runelink_types::{
+    channel::{ChannelId, ChannelWebhook, WebhookId},
+    server::ServerId,
+    user::UserRef,
+};
+use time::OffsetDateTime;
+
+use crate::{db::DbPool, error::ApiResult};
+
+#[derive(sqlx::FromRow, Debug)]
+struct WebhookRow {
+    pub id: WebhookId,
+    pub server_id: ServerId,
+    pub channel_id: ChannelId,
+    pub token: String,
+    pub created_by_name: String,
+    pub created_by_host: String,
+    pub created_at: OffsetDateTime,
+}
+
+impl From<WebhookRow> for ChannelWebhook {
+    fn from(row: WebhookRow) -> Self {
+        ChannelWebhook {
+            id: row.id,
+            server_id: row.server_id,
+            channel_id: row.channel_id,
+            token: row.token,
+            created_by: UserRef::new(row.created_by_name, row.created_by_host),
+            created_at: row.created_at,
+        }
+    }
+}
+
+pub async fn insert(
+    pool: &DbPool,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    token: &str,
+    created_by: &UserRef,
+) -> ApiResult<ChannelWebhook> {
+    let row = sqlx::query_as!(
+        WebhookRow,
+        r#"
+        INSERT INTO channel_webhooks (
+            id, server_id, channel_id, token, created_by_name,
+            created_by_host
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING *;
+        "#,
+        WebhookId::new().as_uuid(),
+        server_id.as_uuid(),
+        channel_id.as_uuid(),
+        token,
+        created_by.name,
+        created_by.host,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.into())
+}
+
+/// The channel a webhook posts to, if `token` is the webhook's token.
+pub async fn get_channel_by_token(
+    pool: &DbPool,
+    webhook_id: WebhookId,
+    token: &str,
+) -> ApiResult<Option<ChannelId>> {
+    let channel_id = sqlx::query_scalar!(
+        r#"
+        SELECT channel_id
+        FROM channel_webhooks
+        WHERE id = $1 AND token = $2;
+        "#,
+        webhook_id.as_uuid(),
+        token,
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(ChannelId::from);
+    Ok(channel_id)
+}
+
+/// Deletes a webhook of a channel. Returns whether it existed.
+pub async fn delete(
+    pool: &DbPool,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    webhook_id: WebhookId,
+) -> ApiResult<bool> {
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM channel_webhooks
+        WHERE id = $1 AND server_id = $2 AND channel_id = $3;
+        "#,
+        webhook_id.as_uuid(),
+        server_id.as_uuid(),
+        channel_id.as_uuid(),
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(deleted > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[sqlx::test]
+    async fn test_channel_lookup_requires_the_token(pool: DbPool) {
+        let state = test_util::state(pool);
+        let owner = test_util::local_user(&state, "owner").await.as_ref();
+        let server = test_util::server(&state, &owner, "Guild").await;
+        let channel = test_util::channel(&state, server.id, "general").await;
+        let webhook =
+            insert(&state.db_pool, server.id, channel.id, "secret", &owner)
+                .await
+                .unwrap();
+
+        let found = get_channel_by_token(&state.db_pool, webhook.id, "secret")
+            .await
+            .unwrap();
+        assert!(found == Some(channel.id));
+        let found = get_channel_by_token(&state.db_pool, webhook.id, "guess")
+            .await
+            .unwrap();
+        assert!(found.is_none());
+    }
+}
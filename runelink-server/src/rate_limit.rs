@@ -0,0 +1,214 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, Hash, RandomState},
+    net::IpAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use runelink_types::user::UserRef;
+use tokio::{sync::Mutex, time::Instant};
+
+use crate::{
+    config::ServerConfig,
+    error::{ApiError, ApiResult},
+};
+
+/// Shards per limiter, so unrelated keys rarely wait on the same lock.
+const SHARDS: usize = 16;
+
+/// Buckets a shard holds before full ones are dropped.
+const SHARD_PRUNE_THRESHOLD: usize = 1024;
+
+/// How many requests are allowed per window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub window: Duration,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn full(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(limit.requests),
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at);
+        let capacity = f64::from(limit.requests);
+        let regained =
+            elapsed.as_secs_f64() / limit.window.as_secs_f64() * capacity;
+        self.tokens = (self.tokens + regained).min(capacity);
+        self.updated_at = now;
+    }
+
+    /// How long until a whole token is available, if it isn't already.
+    fn wait(&self, limit: RateLimit) -> Option<Duration> {
+        if self.tokens >= 1.0 {
+            return None;
+        }
+        let missing = 1.0 - self.tokens;
+        Some(limit.window.mul_f64(missing / f64::from(limit.requests)))
+    }
+
+    fn is_full(&self, limit: RateLimit) -> bool {
+        self.tokens >= f64::from(limit.requests)
+    }
+}
+
+/// Token buckets keyed by `K`: each key holds up to `requests` tokens and
+/// regains them evenly over `window`.
+#[derive(Clone, Debug)]
+pub struct RateLimiter<K> {
+    limit: RateLimit,
+    hasher: RandomState,
+    shards: Arc<[Mutex<HashMap<K, Bucket>>]>,
+}
+
+impl<K: Hash + Eq + Clone> RateLimiter<K> {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            hasher: RandomState::new(),
+            shards: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+
+    /// Takes a token for `key`, or returns how long until one is available.
+    pub async fn acquire(&self, key: &K) -> Result<(), Duration> {
+        self.update(key, Instant::now(), true).await
+    }
+
+    /// Like `acquire`, but leaves the token in place.
+    pub async fn check(&self, key: &K) -> Result<(), Duration> {
+        self.update(key, Instant::now(), false).await
+    }
+
+    async fn update(
+        &self,
+        key: &K,
+        now: Instant,
+        take: bool,
+    ) -> Result<(), Duration> {
+        let shard = self.hasher.hash_one(key) as usize % self.shards.len();
+        let mut buckets = self.shards[shard].lock().await;
+        if buckets.len() >= SHARD_PRUNE_THRESHOLD {
+            let limit = self.limit;
+            buckets.retain(|_, bucket| {
+                bucket.refill(limit, now);
+                !bucket.is_full(limit)
+            });
+        }
+
+        let bucket = buckets
+            .entry(key.clone())
+            .or_insert_with(|| Bucket::full(self.limit, now));
+        bucket.refill(self.limit, now);
+        if let Some(wait) = bucket.wait(self.limit) {
+            return Err(wait);
+        }
+        if take {
+            bucket.tokens -= 1.0;
+        }
+        Ok(())
+    }
+}
+
+/// The limiters shared by a server's endpoints.
+#[derive(Clone, Debug)]
+pub struct RateLimits {
+    /// Token grants and signups, per client IP.
+    pub auth: RateLimiter<IpAddr>,
+    /// Failed password attempts, per client IP.
+    pub auth_failures: RateLimiter<IpAddr>,
+    /// Messages created, per author.
+    pub messages: RateLimiter<UserRef>,
+}
+
+impl RateLimits {
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            auth: RateLimiter::new(config.auth_rate_limit),
+            auth_failures: RateLimiter::new(config.auth_failure_rate_limit),
+            messages: RateLimiter::new(config.message_rate_limit),
+        }
+    }
+}
+
+fn rate_limited(retry_after: Duration) -> ApiError {
+    ApiError::RateLimited { retry_after }
+}
+
+/// Counts an auth request from `ip`, refusing it if `ip` has made too many
+/// requests or failed too many password attempts lately.
+pub async fn check_auth(limits: &RateLimits, ip: IpAddr) -> ApiResult<()> {
+    limits
+        .auth_failures
+        .check(&ip)
+        .await
+        .map_err(rate_limited)?;
+    limits.auth.acquire(&ip).await.map_err(rate_limited)
+}
+
+/// Counts a failed password attempt from `ip`.
+pub async fn record_auth_failure(limits: &RateLimits, ip: IpAddr) {
+    // An exhausted bucket just stays exhausted
+    let _ = limits.auth_failures.acquire(&ip).await;
+}
+
+/// Counts a message from `author`, refusing it if they've sent too many
+/// lately.
+pub async fn check_message(
+    limits: &RateLimits,
+    author: &UserRef,
+) -> ApiResult<()> {
+    limits.messages.acquire(author).await.map_err(rate_limited)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit {
+        requests: 2,
+        window: Duration::from_secs(10),
+    };
+
+    #[tokio::test]
+    async fn test_bucket_empties_and_refills() {
+        let limiter = RateLimiter::new(LIMIT);
+        let now = Instant::now();
+        assert!(limiter.update(&"a", now, true).await.is_ok());
+        assert!(limiter.update(&"a", now, true).await.is_ok());
+        let wait = limiter.update(&"a", now, true).await.unwrap_err();
+        assert_eq!(wait, Duration::from_secs(5));
+
+        // Other keys have their own bucket
+        assert!(limiter.update(&"b", now, true).await.is_ok());
+
+        let later = now + Duration::from_secs(5);
+        assert!(limiter.update(&"a", later, true).await.is_ok());
+        assert!(limiter.update(&"a", later, true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_does_not_take_a_token() {
+        let limiter = RateLimiter::new(RateLimit {
+            requests: 1,
+            window: Duration::from_secs(60),
+        });
+        let now = Instant::now();
+        assert!(limiter.update(&"a", now, false).await.is_ok());
+        assert!(limiter.update(&"a", now, false).await.is_ok());
+        assert!(limiter.update(&"a", now, true).await.is_ok());
+        assert!(limiter.update(&"a", now, false).await.is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use std::path::PathBuf;
+
+use crate::{
+    config::{ConfigError, ServerConfig, TlsConfig},
+    error::ApiError,
+};
+
+/// A failed startup precondition, worded so an operator can act on it.
+#[derive(thiserror::Error, Debug)]
+pub enum StartupError {
+    #[error("Invalid configuration: {0}")]
+    Config(#[from] ConfigError),
+
+    #[error(
+        "Cannot connect to the database for {host}: {source}. Check that Postgres is running and that `database_url` is correct."
+    )]
+    Database {
+        host: String,
+        #[source]
+        source: sqlx::Error,
+    },
+
+    #[error("Failed to run database migrations for {host}: {source}")]
+    Migrations {
+        host: String,
+        #[source]
+        source: sqlx::migrate::MigrateError,
+    },
+
+    #[error(
+        "Cannot load or create signing keys in `{key_dir}` for {host}: {reason}. Check that `key_dir` exists and is writable."
+    )]
+    Keys {
+        host: String,
+        key_dir: PathBuf,
+        reason: String,
+    },
+
+    #[error("Cannot bind {bind_addr} for {host}: {source}")]
+    Bind {
+        host: String,
+        bind_addr: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error(
+        "Cannot load the TLS certificate `{cert_path}` and key `{key_path}` for {host}: {source}"
+    )]
+    Tls {
+        host: String,
+        cert_path: PathBuf,
+        key_path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("{0}")]
+    Exited(String),
+}
+
+impl StartupError {
+    pub fn database(config: &ServerConfig, source: sqlx::Error) -> Self {
+        Self::Database {
+            host: config.public_host_with_explicit_port(),
+            source,
+        }
+    }
+
+    pub fn migrations(
+        config: &ServerConfig,
+        source: sqlx::migrate::MigrateError,
+    ) -> Self {
+        Self::Migrations {
+            host: config.public_host_with_explicit_port(),
+            source,
+        }
+    }
+
+    pub fn keys(config: &ServerConfig, error: ApiError) -> Self {
+        let reason = match error {
+            ApiError::Internal(reason) => reason,
+            other => other.to_string(),
+        };
+        Self::Keys {
+            host: config.public_host_with_explicit_port(),
+            key_dir: config.key_dir.clone(),
+            reason,
+        }
+    }
+
+    pub fn tls(
+        config: &ServerConfig,
+        tls: &TlsConfig,
+        source: std::io::Error,
+    ) -> Self {
+        Self::Tls {
+            host: config.public_host_with_explicit_port(),
+            cert_path: tls.cert_path.clone(),
+            key_path: tls.key_path.clone(),
+            source,
+        }
+    }
+
+    pub fn bind(config: &ServerConfig, source: std::io::Error) -> Self {
+        Self::Bind {
+            host: config.public_host_with_explicit_port(),
+            bind_addr: config.bind_addr(),
+            source,
+        }
+    }
+
+    pub fn metrics_bind(config: &ServerConfig, source: std::io::Error) -> Self {
+        Self::Bind {
+            host: config.public_host_with_explicit_port(),
+            bind_addr: config.metrics_bind_addr().unwrap_or_default(),
+            source,
+        }
+    }
+}
+
+/// One-line summary of an instance's effective configuration, logged once
+/// it is ready to serve.
+pub fn readiness_summary(config: &ServerConfig, instances: usize) -> String {
+    let mode = if instances > 1 { "cluster" } else { "single" };
+    let federation = if config.secure { "wss" } else { "ws" };
+    let tls = if config.tls.is_some() { "on" } else { "off" };
+    let metrics = match config.metrics_bind_addr() {
+        Some(addr) => addr,
+        None if config.metrics_enabled => "api".to_string(),
+        None => "off".to_string(),
+    };
+    format!(
+        "startup complete: host={} bind={} db=connected migrations=up_to_date \
+         mode={mode} federation={federation} tls={tls} metrics={metrics} \
+         warm_hosts={} signups_enabled={}",
+        config.public_host_with_explicit_port(),
+        config.bind_addr(),
+        config.federation_warm_hosts.len(),
+        config.signups_enabled,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db, rate_limit::RateLimit};
+
+    fn config(database_url: &str) -> ServerConfig {
+        ServerConfig {
+            public_host_raw: "example.com".into(),
+            database_url: database_url.into(),
+            public_port: 7000,
+            bind_host: "127.0.0.1".into(),
+            bind_port: 7000,
+            secure: false,
+            tls: None,
+            metrics_enabled: false,
+            metrics_port: None,
+            key_dir: PathBuf::from("/nonexistent/keys"),
+            federation_warm_hosts: vec!["peer.example.com".into()],
+            federation_connect_attempts: 3,
+            federation_connect_backoff_base: std::time::Duration::from_millis(
+                200,
+            ),
+            federation_connect_backoff_max: std::time::Duration::from_millis(
+                2000,
+            ),
+            federation_host_cooldown: std::time::Duration::from_secs(30),
+            federation_allowlist: None,
+            federation_blocklist: Vec::new(),
+            federation_breaker_threshold: 5,
+            federation_breaker_cooldown: std::time::Duration::from_secs(60),
+            federation_seen_events_capacity: 4096,
+            federation_msgpack: false,
+            signups_enabled: true,
+            ws_ping_interval: std::time::Duration::from_secs(30),
+            ws_idle_timeout: std::time::Duration::from_secs(90),
+            ws_outbound_queue_capacity: 256,
+            max_ws_frame_bytes: 1024 * 1024,
+            ws_replay_buffer_size: 256,
+            max_reactions_per_message: 20,
+            max_webhook_body_len: 4000,
+            attachments_dir: PathBuf::from("/nonexistent/attachments"),
+            max_attachment_size: 10 * 1024 * 1024,
+            attachment_content_types: vec!["image/png".into()],
+            jwks_cache_ttl: std::time::Duration::from_secs(600),
+            jwks_negative_cache_ttl: std::time::Duration::from_secs(30),
+            shutdown_grace_period: std::time::Duration::from_secs(10),
+            cleanup_interval: std::time::Duration::from_secs(60 * 60),
+            remote_cache_ttl: std::time::Duration::from_secs(90 * 24 * 60 * 60),
+            deleted_message_ttl: std::time::Duration::from_secs(
+                30 * 24 * 60 * 60,
+            ),
+            membership_resync_interval: std::time::Duration::from_secs(60 * 60),
+            membership_resync_jitter: std::time::Duration::from_secs(5 * 60),
+            access_token_ttl: time::Duration::hours(1),
+            refresh_token_ttl: time::Duration::days(30),
+            federation_token_ttl: time::Duration::minutes(5),
+            auth_rate_limit: RateLimit {
+                requests: 20,
+                window: std::time::Duration::from_secs(60),
+            },
+            auth_failure_rate_limit: RateLimit {
+                requests: 5,
+                window: std::time::Duration::from_secs(300),
+            },
+            message_rate_limit: RateLimit {
+                requests: 30,
+                window: std::time::Duration::from_secs(10),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_missing_database_is_actionable_error() {
+        // Port 1 is never a Postgres server, so the connect fails fast.
+        let config = config("postgres://runelink@127.0.0.1:1/runelink");
+        let error = db::get_pool(&config)
+            .await
+            .map_err(|e| StartupError::database(&config, e))
+            .unwrap_err();
+        let message = error.to_string();
+        assert!(message.starts_with("Cannot connect to the database"));
+        assert!(message.contains(&config.public_host_with_explicit_port()));
+        assert!(message.contains("`database_url`"));
+    }
+
+    #[test]
+    fn test_key_error_names_key_dir() {
+        let config = config("postgres://unused");
+        let error = StartupError::keys(
+            &config,
+            ApiError::Internal("failed to create keys dir: denied".into()),
+        );
+        let message = error.to_string();
+        assert!(message.contains("/nonexistent/keys"));
+        assert!(message.contains("failed to create keys dir: denied"));
+        assert!(!message.contains("Internal error"));
+    }
+
+    #[test]
+    fn test_readiness_summary_lists_effective_config() {
+        let config = config("postgres://unused");
+        let summary = readiness_summary(&config, 1);
+        assert!(summary.starts_with("startup complete: "));
+        assert!(summary.contains("bind=127.0.0.1:7000"));
+        assert!(summary.contains("mode=single"));
+        assert!(summary.contains("federation=ws"));
+        assert!(summary.contains("tls=off"));
+        assert!(summary.contains("metrics=off"));
+        assert!(summary.contains("warm_hosts=1"));
+        assert!(summary.contains("signups_enabled=true"));
+    }
+}
@@ -1,10 +1,22 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
-use crate::{config::ServerConfig, db::DbPool, key_manager::KeyManager, ws};
+use runelink_types::server::{ServerAnalytics, ServerId};
+use time::OffsetDateTime;
+
+use crate::{
+    config::ServerConfig, db::DbPool, key_manager::KeyManager,
+    metrics::Metrics, rate_limit::RateLimits, ws,
+};
 
 pub type JwksCache =
     std::collections::HashMap<String, crate::jwks_resolver::CachedJwks>;
 
+/// (server, window in days) -> (computed at, analytics)
+pub type AnalyticsCache = std::collections::HashMap<
+    (ServerId, u32),
+    (OffsetDateTime, ServerAnalytics),
+>;
+
 #[derive(Clone, Debug)]
 pub struct AppState {
     pub config: Arc<ServerConfig>,
@@ -14,5 +26,43 @@ pub struct AppState {
     pub federation_ws_manager: ws::FederationWsManager,
     pub key_manager: KeyManager,
     pub jwks_cache: Arc<tokio::sync::RwLock<JwksCache>>,
+    /// Federation updates already applied, for dropping repeats.
+    pub seen_federation_events: ws::SeenEvents,
     pub routing_index: ws::RoutingIndex,
+    pub analytics_cache: Arc<tokio::sync::RwLock<AnalyticsCache>>,
+    /// Websocket loops still running, for draining on shutdown.
+    pub open_sockets: ws::OpenSockets,
+    pub rate_limits: RateLimits,
+    pub metrics: Metrics,
+}
+
+impl AppState {
+    pub fn new(
+        config: Arc<ServerConfig>,
+        db_pool: Arc<DbPool>,
+        key_manager: KeyManager,
+    ) -> Self {
+        Self {
+            http_client: reqwest::Client::new(),
+            client_ws_manager: ws::ClientWsManager::with_replay_capacity(
+                config.ws_replay_buffer_size,
+            ),
+            federation_ws_manager: ws::FederationWsManager::new(),
+            key_manager,
+            jwks_cache: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
+            seen_federation_events: ws::SeenEvents::new(
+                config.federation_seen_events_capacity,
+            ),
+            routing_index: ws::RoutingIndex::new(
+                db_pool.clone(),
+                config.clone(),
+            ),
+            analytics_cache: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
+            open_sockets: ws::OpenSockets::new(),
+            rate_limits: RateLimits::new(&config),
+            metrics: Metrics::default(),
+            config,
+            db_pool,
+        }
+    }
 }
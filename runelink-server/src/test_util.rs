@@ -0,0 +1,191 @@
+//! Fixtures for tests that run against a database. Each `#[sqlx::test]`
+//! gets its own freshly migrated database, so tests can insert freely.
+
+use std::{path::Path, sync::Arc};
+
+use futures_util::{SinkExt, StreamExt};
+use runelink_types::{
+    ClientAccessClaims, EventId, FederationWsEnvelope, FederationWsReply,
+    FederationWsRequest,
+    channel::{Channel, NewChannel},
+    server::{
+        NewServer, NewServerMembership, Server, ServerId, ServerRole,
+        ServerVisibility,
+    },
+    user::{NewUser, User, UserRef, UserRole},
+};
+
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+    auth::{Principal, Requirement, Session, authorize},
+    bearer_auth::ClientAuth,
+    config::ServerConfig,
+    db::DbPool,
+    key_manager::KeyManager,
+    queries,
+    state::AppState,
+};
+
+/// The public host of the test server.
+pub const HOST: &str = "localhost";
+
+pub fn config() -> ServerConfig {
+    let scratch = std::env::temp_dir()
+        .join(format!("runelink-test-{}", uuid::Uuid::new_v4()));
+    let (key_dir, attachments_dir) =
+        (scratch.join("keys"), scratch.join("attachments"));
+    let contents = format!(
+        r#"
+        [[servers]]
+        public_host = "{HOST}"
+        secure = false
+        database_url = "postgres://unused"
+        key_dir = "{}"
+        attachments_dir = "{}"
+        "#,
+        key_dir.display(),
+        attachments_dir.display()
+    );
+    ServerConfig::from_toml_str(&contents, Path::new("test.toml"), |_| None)
+        .unwrap()
+        .remove(0)
+}
+
+pub fn state(pool: DbPool) -> AppState {
+    state_with_config(pool, config())
+}
+
+/// Like [`state`], for tests that need to adjust the configuration.
+pub fn state_with_config(pool: DbPool, config: ServerConfig) -> AppState {
+    let key_manager =
+        KeyManager::load_or_generate(config.key_dir.clone()).unwrap();
+    AppState::new(Arc::new(config), Arc::new(pool), key_manager)
+}
+
+pub async fn user(state: &AppState, name: &str, host: &str) -> User {
+    let new_user = NewUser {
+        name: name.into(),
+        host: host.into(),
+        role: UserRole::User,
+    };
+    queries::users::insert(&state.db_pool, &new_user)
+        .await
+        .unwrap()
+}
+
+pub async fn local_user(state: &AppState, name: &str) -> User {
+    user(state, name, &state.config.public_host()).await
+}
+
+/// A public server owned (and administered) by `owner`.
+pub async fn server(state: &AppState, owner: &UserRef, title: &str) -> Server {
+    let new_server = NewServer {
+        title: title.into(),
+        description: None,
+        visibility: ServerVisibility::Public,
+    };
+    let (server, _) =
+        queries::servers::insert_with_owner(state, &new_server, owner)
+            .await
+            .unwrap();
+    server
+}
+
+/// Add `user_ref` to a local server as a plain member.
+pub async fn join(state: &AppState, server_id: ServerId, user_ref: &UserRef) {
+    let new_membership = NewServerMembership {
+        user_ref: user_ref.clone(),
+        server_id,
+        server_host: state.config.public_host(),
+        role: ServerRole::Member,
+    };
+    queries::memberships::upsert_local(&state.db_pool, &new_membership)
+        .await
+        .unwrap();
+}
+
+pub async fn channel(
+    state: &AppState,
+    server_id: ServerId,
+    title: &str,
+) -> Channel {
+    let new_channel = NewChannel {
+        title: title.into(),
+        description: None,
+        slow_mode_secs: None,
+        category: None,
+    };
+    queries::channels::insert(&state.db_pool, server_id, &new_channel)
+        .await
+        .unwrap()
+}
+
+/// A client session for `user_ref`, as if it had presented a valid token.
+pub async fn session(state: &AppState, user_ref: &UserRef) -> Session {
+    let claims = ClientAccessClaims::new(
+        user_ref,
+        "test".into(),
+        state.config.api_url(),
+        "openid".into(),
+        time::Duration::hours(1),
+    );
+    let principal = Principal::Client(ClientAuth { claims });
+    authorize(state, principal, Requirement::Client)
+        .await
+        .unwrap()
+}
+
+/// Start a federation peer on a local port and return its host. It accepts
+/// any websocket, answers requests with whatever `reply` returns for them
+/// and ignores everything else.
+pub async fn federation_peer<F>(reply: F) -> String
+where
+    F: Fn(FederationWsRequest) -> Option<FederationWsReply>
+        + Send
+        + Sync
+        + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let host = listener.local_addr().unwrap().to_string();
+    let reply = Arc::new(reply);
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let reply = reply.clone();
+            tokio::spawn(async move {
+                let Ok(mut socket) =
+                    tokio_tungstenite::accept_async(stream).await
+                else {
+                    return;
+                };
+                while let Some(Ok(message)) = socket.next().await {
+                    let Message::Text(text) = message else {
+                        continue;
+                    };
+                    let Ok(FederationWsEnvelope::Request {
+                        request_id,
+                        request,
+                        ..
+                    }) = serde_json::from_str(&text)
+                    else {
+                        continue;
+                    };
+                    let Some(reply) = reply(request) else {
+                        continue;
+                    };
+                    let envelope = FederationWsEnvelope::Reply {
+                        request_id,
+                        event_id: EventId::new(),
+                        reply,
+                    };
+                    let text = serde_json::to_string(&envelope).unwrap();
+                    if socket.send(Message::Text(text.into())).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    host
+}
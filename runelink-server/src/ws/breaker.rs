@@ -0,0 +1,136 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use tokio::{sync::Mutex, time::Instant};
+
+/// Where a host's circuit breaker stands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Requests go through.
+    Closed,
+    /// Too many consecutive failures; requests fail without being sent.
+    Open,
+    /// The cooldown has passed; the next request decides whether the
+    /// breaker closes or opens again.
+    HalfOpen,
+}
+
+/// A snapshot of one host's breaker.
+#[derive(Clone, Debug)]
+pub struct HostBreakerStatus {
+    pub host: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+struct HostBreaker {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+impl HostBreaker {
+    fn state(&self, now: Instant, cooldown: Duration) -> BreakerState {
+        match self.opened_at {
+            None => BreakerState::Closed,
+            Some(opened_at) if now < opened_at + cooldown => BreakerState::Open,
+            Some(_) => BreakerState::HalfOpen,
+        }
+    }
+
+    fn record_failure(&mut self, now: Instant, threshold: u32) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        if self.consecutive_failures >= threshold {
+            self.opened_at = Some(now);
+        }
+    }
+}
+
+/// Per-host circuit breakers for federation requests.
+///
+/// A host's breaker opens after `threshold` consecutive failed requests and
+/// stays open for `cooldown`, after which requests are let through again
+/// until one succeeds (closing it) or fails (reopening it).
+#[derive(Clone, Debug, Default)]
+pub struct CircuitBreakers {
+    hosts: Arc<Mutex<HashMap<String, HostBreaker>>>,
+}
+
+impl CircuitBreakers {
+    /// Whether a request to `host` may be sent.
+    pub async fn allows(&self, host: &str, cooldown: Duration) -> bool {
+        let hosts = self.hosts.lock().await;
+        hosts.get(host).is_none_or(|breaker| {
+            breaker.state(Instant::now(), cooldown) != BreakerState::Open
+        })
+    }
+
+    pub async fn record_success(&self, host: &str) {
+        self.hosts.lock().await.remove(host);
+    }
+
+    pub async fn record_failure(&self, host: &str, threshold: u32) {
+        let mut hosts = self.hosts.lock().await;
+        hosts
+            .entry(host.to_string())
+            .or_default()
+            .record_failure(Instant::now(), threshold);
+    }
+
+    /// The breaker of every host with recent failures.
+    pub async fn statuses(&self, cooldown: Duration) -> Vec<HostBreakerStatus> {
+        let now = Instant::now();
+        let hosts = self.hosts.lock().await;
+        let mut statuses = hosts
+            .iter()
+            .map(|(host, breaker)| HostBreakerStatus {
+                host: host.clone(),
+                state: breaker.state(now, cooldown),
+                consecutive_failures: breaker.consecutive_failures,
+            })
+            .collect::<Vec<_>>();
+        statuses.sort_by(|a, b| a.host.cmp(&b.host));
+        statuses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_breaker_opens_after_threshold() {
+        let now = Instant::now();
+        let mut breaker = HostBreaker::default();
+        breaker.record_failure(now, 3);
+        breaker.record_failure(now, 3);
+        assert_eq!(breaker.state(now, COOLDOWN), BreakerState::Closed);
+        breaker.record_failure(now, 3);
+        assert_eq!(breaker.state(now, COOLDOWN), BreakerState::Open);
+    }
+
+    #[test]
+    fn test_breaker_half_opens_after_cooldown() {
+        let now = Instant::now();
+        let mut breaker = HostBreaker::default();
+        breaker.record_failure(now, 1);
+        let later = now + COOLDOWN;
+        assert_eq!(breaker.state(later, COOLDOWN), BreakerState::HalfOpen);
+
+        // A failed trial reopens it for another cooldown
+        breaker.record_failure(later, 1);
+        assert_eq!(breaker.state(later, COOLDOWN), BreakerState::Open);
+    }
+
+    #[tokio::test]
+    async fn test_success_resets_breaker() {
+        let breakers = CircuitBreakers::default();
+        breakers.record_failure("remote.example:7000", 1).await;
+        assert!(!breakers.allows("remote.example:7000", COOLDOWN).await);
+        assert!(breakers.allows("other.example:7000", COOLDOWN).await);
+        breakers.record_success("remote.example:7000").await;
+        assert!(breakers.allows("remote.example:7000", COOLDOWN).await);
+        assert!(breakers.statuses(COOLDOWN).await.is_empty());
+    }
+}
@@ -1,15 +1,28 @@
 #![allow(dead_code)]
 
-use std::borrow::Borrow;
+use std::{
+    borrow::Borrow,
+    collections::{HashMap, HashSet, VecDeque},
+    net::IpAddr,
+    sync::Arc,
+};
 
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand::{RngCore, rngs::OsRng};
 use runelink_types::{
+    channel::ChannelId,
     ids::{EventId, RequestId},
+    server::ServerId,
     user::UserRef,
-    ws::{ClientWsEnvelope, ClientWsReply, ClientWsUpdate, WsError},
+    ws::{
+        ClientWsEnvelope, ClientWsReplay, ClientWsReply, ClientWsUpdate,
+        ReplayedUpdate, WsError,
+    },
 };
-use tokio::sync::mpsc;
+use time::{Duration, OffsetDateTime};
+use tokio::sync::{RwLock, broadcast, mpsc};
 
-use super::pools::ClientWsPool;
+use super::pools::{ClientWsPool, PresenceChange};
 use crate::ids::ConnId;
 
 /// High-level manager for client websocket connections.
@@ -19,6 +32,51 @@ use crate::ids::ConnId;
 #[derive(Clone, Debug, Default)]
 pub struct ClientWsManager {
     pool: ClientWsPool,
+    resume_tokens: ResumeTokens,
+    remote_presence: RemotePresence,
+    recent_updates: RecentUpdates,
+}
+
+/// How long a resume token stays valid after it is issued.
+pub const RESUME_TOKEN_TTL: Duration = Duration::minutes(5);
+
+/// Outstanding resume tokens, keyed by the opaque token string.
+#[derive(Clone, Default)]
+struct ResumeTokens {
+    inner: Arc<RwLock<HashMap<String, (UserRef, OffsetDateTime)>>>,
+}
+
+impl std::fmt::Debug for ResumeTokens {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResumeTokens").finish_non_exhaustive()
+    }
+}
+
+/// Remote users reported online in a server, keyed by server and user, with
+/// the federation host that reported them.
+#[derive(Clone, Debug, Default)]
+struct RemotePresence {
+    inner: Arc<RwLock<HashMap<(ServerId, UserRef), String>>>,
+}
+
+/// Updates buffered per server when no size is configured.
+const DEFAULT_REPLAY_CAPACITY: usize = 256;
+
+/// The most recent replayable updates of each server, oldest first, so a
+/// reconnecting client can catch up on what it missed.
+#[derive(Clone, Debug)]
+struct RecentUpdates {
+    inner: Arc<RwLock<HashMap<ServerId, VecDeque<ReplayedUpdate>>>>,
+    capacity: usize,
+}
+
+impl Default for RecentUpdates {
+    fn default() -> Self {
+        Self {
+            inner: Arc::default(),
+            capacity: DEFAULT_REPLAY_CAPACITY,
+        }
+    }
 }
 
 impl ClientWsManager {
@@ -26,15 +84,77 @@ impl ClientWsManager {
         Self::default()
     }
 
+    /// A manager that buffers up to `capacity` updates per server for
+    /// replay.
+    pub fn with_replay_capacity(capacity: usize) -> Self {
+        Self {
+            recent_updates: RecentUpdates {
+                inner: Arc::default(),
+                capacity,
+            },
+            ..Self::default()
+        }
+    }
+
+    /// Wraps an update in an envelope, buffering it for replay if it
+    /// belongs to a server.
+    async fn update_envelope(
+        &self,
+        update: ClientWsUpdate,
+    ) -> ClientWsEnvelope {
+        let event_id = EventId::new();
+        if let Some(server_id) = update.replay_server_id() {
+            let mut inner = self.recent_updates.inner.write().await;
+            let buffer = inner.entry(server_id).or_default();
+            if buffer.len() >= self.recent_updates.capacity {
+                buffer.pop_front();
+            }
+            buffer.push_back(ReplayedUpdate {
+                event_id,
+                update: update.clone(),
+            });
+        }
+        ClientWsEnvelope::Update { event_id, update }
+    }
+
+    /// The buffered updates of a server sent after `after_event_id`, or a
+    /// resync signal if that event is no longer buffered.
+    pub async fn replay_since(
+        &self,
+        server_id: ServerId,
+        after_event_id: EventId,
+    ) -> ClientWsReplay {
+        let inner = self.recent_updates.inner.read().await;
+        let Some(buffer) = inner.get(&server_id) else {
+            return ClientWsReplay::ResyncRequired;
+        };
+        let Some(position) = buffer
+            .iter()
+            .position(|replayed| replayed.event_id == after_event_id)
+        else {
+            return ClientWsReplay::ResyncRequired;
+        };
+        ClientWsReplay::Updates(
+            buffer.iter().skip(position + 1).cloned().collect(),
+        )
+    }
+
     pub async fn register_connection(
         &self,
-        sender: mpsc::UnboundedSender<ClientWsEnvelope>,
+        sender: mpsc::Sender<ClientWsEnvelope>,
+        peer_ip: IpAddr,
     ) -> ConnId {
         let conn_id = ConnId::new();
-        self.pool.register_connection(conn_id, sender).await;
+        self.pool
+            .register_connection(conn_id, sender, peer_ip)
+            .await;
         conn_id
     }
 
+    pub async fn peer_ip(&self, conn_id: ConnId) -> Option<IpAddr> {
+        self.pool.peer_ip(conn_id).await
+    }
+
     pub async fn authenticate_connection(
         &self,
         conn_id: ConnId,
@@ -43,10 +163,151 @@ impl ClientWsManager {
         self.pool.authenticate_connection(conn_id, user_ref).await
     }
 
+    /// Issues a single-use resume token for `user_ref`.
+    ///
+    /// Returns the token and its expiry. Expired tokens are pruned here so the
+    /// map does not grow without bound.
+    pub async fn issue_resume_token(
+        &self,
+        user_ref: UserRef,
+    ) -> (String, OffsetDateTime) {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+        let now = OffsetDateTime::now_utc();
+        let expires_at = now + RESUME_TOKEN_TTL;
+        let mut tokens = self.resume_tokens.inner.write().await;
+        tokens.retain(|_, (_, expiry)| *expiry > now);
+        tokens.insert(token.clone(), (user_ref, expires_at));
+        (token, expires_at)
+    }
+
+    /// Consumes a resume token, returning its user if it was valid.
+    ///
+    /// Tokens are single-use: a second attempt with the same token fails.
+    pub async fn consume_resume_token(&self, token: &str) -> Option<UserRef> {
+        let (user_ref, expires_at) =
+            self.resume_tokens.inner.write().await.remove(token)?;
+        if expires_at <= OffsetDateTime::now_utc() {
+            return None;
+        }
+        Some(user_ref)
+    }
+
+    pub async fn subscribe(
+        &self,
+        conn_id: ConnId,
+        channel_id: ChannelId,
+    ) -> bool {
+        self.pool.subscribe(conn_id, channel_id).await
+    }
+
+    /// Records a typing indicator, returning false if it should be dropped
+    /// because the connection sent one less than `interval` ago.
+    pub async fn mark_typing(
+        &self,
+        conn_id: ConnId,
+        interval: Duration,
+    ) -> bool {
+        self.pool.mark_typing(conn_id, interval).await
+    }
+
+    pub async fn subscribe_presence(
+        &self,
+        conn_id: ConnId,
+        server_id: ServerId,
+    ) -> bool {
+        self.pool.subscribe_presence(conn_id, server_id).await
+    }
+
+    /// Returns a receiver for local users going online or offline.
+    pub async fn presence_changes(
+        &self,
+    ) -> broadcast::Receiver<PresenceChange> {
+        self.pool.presence_changes().await
+    }
+
+    /// Returns the given users that are online, either connected here or
+    /// reported online in `server_id` by another host.
+    pub async fn online_users(
+        &self,
+        server_id: ServerId,
+        users: &[UserRef],
+    ) -> Vec<UserRef> {
+        let connected = self
+            .pool
+            .online_users(users)
+            .await
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let remote = self.remote_presence.inner.read().await;
+        users
+            .iter()
+            .filter(|user| {
+                connected.contains(*user)
+                    || remote.contains_key(&(server_id, (*user).clone()))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Records a remote user's presence in a server as reported by
+    /// `reported_by`. Returns whether anything changed.
+    pub async fn set_remote_presence(
+        &self,
+        server_id: ServerId,
+        user_ref: UserRef,
+        online: bool,
+        reported_by: String,
+    ) -> bool {
+        let mut remote = self.remote_presence.inner.write().await;
+        let key = (server_id, user_ref);
+        if online {
+            remote.insert(key, reported_by).is_none()
+        } else {
+            remote.remove(&key).is_some()
+        }
+    }
+
+    /// Forgets every presence reported by a host, returning what was
+    /// removed so it can be announced as offline.
+    pub async fn forget_remote_presence(
+        &self,
+        reported_by: &str,
+    ) -> Vec<(ServerId, UserRef)> {
+        let mut remote = self.remote_presence.inner.write().await;
+        let forgotten = remote
+            .iter()
+            .filter(|(_, host)| host.as_str() == reported_by)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in &forgotten {
+            remote.remove(key);
+        }
+        forgotten
+    }
+
+    pub async fn unsubscribe(
+        &self,
+        conn_id: ConnId,
+        channel_id: ChannelId,
+    ) -> bool {
+        self.pool.unsubscribe(conn_id, channel_id).await
+    }
+
     pub async fn deregister_connection(&self, conn_id: ConnId) -> bool {
         self.pool.deregister_connection(conn_id).await
     }
 
+    /// Closes every client connection, for shutdown.
+    pub async fn close_all(&self) -> usize {
+        self.pool.close_all().await
+    }
+
+    pub async fn connection_count(&self) -> usize {
+        self.pool.connection_count().await
+    }
+
     pub async fn authenticated_user_ref(
         &self,
         conn_id: ConnId,
@@ -54,19 +315,17 @@ impl ClientWsManager {
         self.pool.authenticated_user_ref(conn_id).await
     }
 
+    /// Sends an update to one connection rather than all of a user's.
+    ///
+    /// Returns whether the connection was live; one whose queue is full or
+    /// closed is pruned.
     pub async fn send_update_to_connection(
         &self,
         conn_id: ConnId,
         update: ClientWsUpdate,
     ) -> bool {
         self.pool
-            .send_to_connection(
-                conn_id,
-                ClientWsEnvelope::Update {
-                    event_id: EventId::new(),
-                    update,
-                },
-            )
+            .send_to_connection(conn_id, self.update_envelope(update).await)
             .await
     }
 
@@ -76,12 +335,22 @@ impl ClientWsManager {
         update: ClientWsUpdate,
     ) -> usize {
         self.pool
-            .send_to_user(
+            .send_to_user(user_ref, self.update_envelope(update).await)
+            .await
+    }
+
+    /// Sends an update to a user's connections other than `except`.
+    pub async fn send_update_to_user_except(
+        &self,
+        user_ref: &UserRef,
+        except: ConnId,
+        update: ClientWsUpdate,
+    ) -> usize {
+        self.pool
+            .send_to_user_except(
                 user_ref,
-                ClientWsEnvelope::Update {
-                    event_id: EventId::new(),
-                    update,
-                },
+                except,
+                self.update_envelope(update).await,
             )
             .await
     }
@@ -96,12 +365,45 @@ impl ClientWsManager {
         S: Borrow<UserRef>,
     {
         self.pool
-            .send_to_users(
+            .send_to_users(users, self.update_envelope(update).await)
+            .await
+    }
+
+    /// Sends an update to the given users' connections that are subscribed
+    /// to the channel.
+    pub async fn send_update_to_subscribers<I, S>(
+        &self,
+        users: I,
+        channel_id: ChannelId,
+        update: ClientWsUpdate,
+    ) -> usize
+    where
+        I: IntoIterator<Item = S>,
+        S: Borrow<UserRef>,
+    {
+        self.pool
+            .send_to_subscribers(
                 users,
-                ClientWsEnvelope::Update {
-                    event_id: EventId::new(),
-                    update,
-                },
+                channel_id,
+                self.update_envelope(update).await,
+            )
+            .await
+    }
+
+    /// Sends an update to the connections subscribed to presence changes for
+    /// any of the given servers.
+    pub async fn send_update_to_presence_subscribers<I>(
+        &self,
+        server_ids: I,
+        update: ClientWsUpdate,
+    ) -> usize
+    where
+        I: IntoIterator<Item = ServerId>,
+    {
+        self.pool
+            .send_to_presence_subscribers(
+                server_ids,
+                self.update_envelope(update).await,
             )
             .await
     }
@@ -144,10 +446,187 @@ impl ClientWsManager {
 
     pub async fn broadcast_update(&self, update: ClientWsUpdate) -> usize {
         self.pool
-            .broadcast(ClientWsEnvelope::Update {
-                event_id: EventId::new(),
-                update,
-            })
+            .broadcast(self.update_envelope(update).await)
             .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use runelink_types::message::MessageId;
+
+    use super::*;
+
+    fn alice() -> UserRef {
+        UserRef::new("alice".into(), "example.com".into())
+    }
+
+    #[tokio::test]
+    async fn test_resume_token_is_single_use() {
+        let manager = ClientWsManager::new();
+        let (token, expires_at) = manager.issue_resume_token(alice()).await;
+        assert!(expires_at > OffsetDateTime::now_utc());
+        assert_eq!(manager.consume_resume_token(&token).await, Some(alice()));
+        assert_eq!(manager.consume_resume_token(&token).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_resume_token_is_rejected() {
+        let manager = ClientWsManager::new();
+        manager.issue_resume_token(alice()).await;
+        assert_eq!(manager.consume_resume_token("not-a-token").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_expired_resume_token_is_rejected() {
+        let manager = ClientWsManager::new();
+        let (token, _) = manager.issue_resume_token(alice()).await;
+        manager
+            .resume_tokens
+            .inner
+            .write()
+            .await
+            .get_mut(&token)
+            .unwrap()
+            .1 = OffsetDateTime::now_utc() - Duration::seconds(1);
+        assert_eq!(manager.consume_resume_token(&token).await, None);
+    }
+
+    fn message_deleted(server_id: ServerId) -> ClientWsUpdate {
+        ClientWsUpdate::MessageDeleted {
+            server_id,
+            channel_id: ChannelId::new(),
+            message_id: MessageId::new(),
+        }
+    }
+
+    async fn send(
+        manager: &ClientWsManager,
+        update: ClientWsUpdate,
+    ) -> EventId {
+        match manager.update_envelope(update).await {
+            ClientWsEnvelope::Update { event_id, .. } => event_id,
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_returns_updates_after_event() {
+        let manager = ClientWsManager::new();
+        let server_id = ServerId::new();
+        let first = send(&manager, message_deleted(server_id)).await;
+        let second = send(&manager, message_deleted(server_id)).await;
+        send(&manager, message_deleted(ServerId::new())).await;
+        send(
+            &manager,
+            ClientWsUpdate::Typing {
+                server_id,
+                channel_id: ChannelId::new(),
+                user_ref: alice(),
+            },
+        )
+        .await;
+        let third = send(&manager, message_deleted(server_id)).await;
+
+        let ClientWsReplay::Updates(updates) =
+            manager.replay_since(server_id, first).await
+        else {
+            panic!("expected buffered updates");
+        };
+        let event_ids = updates
+            .iter()
+            .map(|replayed| replayed.event_id)
+            .collect::<Vec<_>>();
+        assert_eq!(event_ids, vec![second, third]);
+    }
+
+    #[tokio::test]
+    async fn test_replay_past_the_buffer_requires_resync() {
+        let manager = ClientWsManager::with_replay_capacity(2);
+        let server_id = ServerId::new();
+        let evicted = send(&manager, message_deleted(server_id)).await;
+        send(&manager, message_deleted(server_id)).await;
+        send(&manager, message_deleted(server_id)).await;
+        assert_eq!(
+            manager.replay_since(server_id, evicted).await,
+            ClientWsReplay::ResyncRequired
+        );
+        assert_eq!(
+            manager.replay_since(ServerId::new(), evicted).await,
+            ClientWsReplay::ResyncRequired
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remote_presence_is_forgotten_with_its_host() {
+        let manager = ClientWsManager::new();
+        let server_id = ServerId::new();
+        let bob = UserRef::new("bob".into(), "remote.example".into());
+        let reporter = "remote.example".to_string();
+        assert!(
+            manager
+                .set_remote_presence(
+                    server_id,
+                    bob.clone(),
+                    true,
+                    reporter.clone()
+                )
+                .await
+        );
+        // A repeated report is not a change.
+        assert!(
+            !manager
+                .set_remote_presence(
+                    server_id,
+                    bob.clone(),
+                    true,
+                    reporter.clone()
+                )
+                .await
+        );
+        let members = [alice(), bob.clone()];
+        assert_eq!(
+            manager.online_users(server_id, &members).await,
+            vec![bob.clone()]
+        );
+        assert_eq!(
+            manager.forget_remote_presence(&reporter).await,
+            vec![(server_id, bob)]
+        );
+        assert!(manager.online_users(server_id, &members).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_update_to_connection_targets_one_connection() {
+        let manager = ClientWsManager::new();
+        let localhost = IpAddr::from([127, 0, 0, 1]);
+        let (sender, mut receiver) = mpsc::channel(1);
+        let (other_sender, mut other_receiver) = mpsc::channel(1);
+        let conn_id = manager.register_connection(sender, localhost).await;
+        let other = manager.register_connection(other_sender, localhost).await;
+        manager.authenticate_connection(conn_id, alice()).await;
+        manager.authenticate_connection(other, alice()).await;
+
+        let update = message_deleted(ServerId::new());
+        assert!(
+            manager
+                .send_update_to_connection(conn_id, update.clone())
+                .await
+        );
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(ClientWsEnvelope::Update { .. })
+        ));
+        assert!(other_receiver.try_recv().is_err());
+
+        // A closed connection reports the failure and is pruned
+        drop(receiver);
+        assert!(
+            !manager
+                .send_update_to_connection(conn_id, update.clone())
+                .await
+        );
+        assert_eq!(manager.authenticated_user_ref(conn_id).await, None);
+        assert!(!manager.send_update_to_connection(conn_id, update).await);
+    }
+}
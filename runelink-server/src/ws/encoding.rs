@@ -0,0 +1,137 @@
+//! Wire encodings for websocket envelopes.
+//!
+//! JSON text frames are the default. A peer opts into MessagePack binary
+//! frames by negotiating `WS_MSGPACK_SUBPROTOCOL`. MessagePack frames keep
+//! field names and string ids, so they decode to the same shape as the JSON
+//! form.
+
+use runelink_types::ws::WS_MSGPACK_SUBPROTOCOL;
+use serde::{Serialize, de::DeserializeOwned};
+
+/// How envelopes sent on one connection are encoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WsEncoding {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+/// The payload of one websocket data frame.
+#[derive(Debug)]
+pub enum WsFrame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl WsEncoding {
+    /// The encoding a negotiated subprotocol stands for; JSON if none was.
+    pub fn from_subprotocol(protocol: Option<&[u8]>) -> Self {
+        match protocol {
+            Some(protocol) if protocol == WS_MSGPACK_SUBPROTOCOL.as_bytes() => {
+                WsEncoding::MessagePack
+            }
+            _ => WsEncoding::Json,
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<WsFrame, String> {
+        match self {
+            WsEncoding::Json => serde_json::to_string(value)
+                .map(WsFrame::Text)
+                .map_err(|error| error.to_string()),
+            WsEncoding::MessagePack => {
+                let mut payload = Vec::new();
+                let mut serializer = rmp_serde::Serializer::new(&mut payload)
+                    .with_struct_map()
+                    .with_human_readable();
+                value
+                    .serialize(&mut serializer)
+                    .map_err(|error| error.to_string())?;
+                Ok(WsFrame::Binary(payload))
+            }
+        }
+    }
+}
+
+impl WsFrame {
+    /// The payload's size in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            WsFrame::Text(payload) => payload.len(),
+            WsFrame::Binary(payload) => payload.len(),
+        }
+    }
+
+    /// Decodes the frame by its type: text as JSON, binary as MessagePack.
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, String> {
+        match self {
+            WsFrame::Text(payload) => {
+                serde_json::from_str(payload).map_err(|error| error.to_string())
+            }
+            WsFrame::Binary(payload) => {
+                let mut deserializer =
+                    rmp_serde::Deserializer::new(payload.as_slice())
+                        .with_human_readable();
+                T::deserialize(&mut deserializer)
+                    .map_err(|error| error.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use runelink_types::ws::{
+        ClientWsEnvelope, ClientWsRequest, RequestId, WS_JSON_SUBPROTOCOL,
+    };
+    use serde_json::Value;
+
+    use super::*;
+
+    fn envelope() -> ClientWsEnvelope {
+        ClientWsEnvelope::Request {
+            request_id: RequestId::new(),
+            request: ClientWsRequest::Hello {
+                protocol_version: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn test_subprotocol_selects_encoding() {
+        assert_eq!(
+            WsEncoding::from_subprotocol(Some(
+                WS_MSGPACK_SUBPROTOCOL.as_bytes()
+            )),
+            WsEncoding::MessagePack
+        );
+        assert_eq!(
+            WsEncoding::from_subprotocol(Some(WS_JSON_SUBPROTOCOL.as_bytes())),
+            WsEncoding::Json
+        );
+        assert_eq!(WsEncoding::from_subprotocol(None), WsEncoding::Json);
+    }
+
+    #[test]
+    fn test_msgpack_round_trips_and_mirrors_json() {
+        let envelope = envelope();
+        let frame = WsEncoding::MessagePack.encode(&envelope).unwrap();
+        assert!(matches!(frame, WsFrame::Binary(_)));
+        assert_eq!(frame.decode::<ClientWsEnvelope>().unwrap(), envelope);
+        assert_eq!(
+            frame.decode::<Value>().unwrap(),
+            serde_json::to_value(&envelope).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_json_is_text() {
+        let envelope = envelope();
+        let frame = WsEncoding::Json.encode(&envelope).unwrap();
+        let WsFrame::Text(ref payload) = frame else {
+            panic!("expected a text frame");
+        };
+        assert_eq!(payload, &serde_json::to_string(&envelope).unwrap());
+        assert_eq!(frame.decode::<ClientWsEnvelope>().unwrap(), envelope);
+    }
+}
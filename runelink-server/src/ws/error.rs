@@ -1,4 +1,7 @@
-use runelink_types::{ids::RequestId, ws::WsError};
+use runelink_types::{
+    ids::RequestId,
+    ws::{WsError, WsErrorCode},
+};
 use thiserror::Error;
 
 use crate::error::ApiError;
@@ -9,28 +12,68 @@ pub type FederationRequestResult<T> = Result<T, FederationRequestError>;
 pub enum FederationRequestError {
     #[error("No active federation connection for host '{host}'")]
     HostUnavailable { host: String },
+    #[error("Federation with host '{host}' is not allowed")]
+    HostNotAllowed { host: String },
     #[error("Timed out waiting for request '{request_id}' reply from '{host}'")]
     Timeout { host: String, request_id: RequestId },
     #[error("Request '{request_id}' waiter dropped before completion")]
     ChannelClosed { request_id: RequestId },
+    #[error("Server is shutting down")]
+    ShuttingDown,
     #[error("Remote federation error [{code}]: {message}")]
     Remote {
-        code: String,
+        code: WsErrorCode,
         message: String,
         error: WsError,
     },
 }
 
 impl FederationRequestError {
+    /// Whether the failure is transient and the request may be retried.
+    ///
+    /// Transport failures are retryable, as are remote errors that signal a
+    /// problem on the remote side. Remote errors describing the request itself
+    /// (auth, forbidden, bad request, not found, conflict, malformed, too
+    /// large, unsupported protocol) are permanent.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            FederationRequestError::HostUnavailable { .. }
+            | FederationRequestError::Timeout { .. }
+            | FederationRequestError::ChannelClosed { .. } => true,
+            FederationRequestError::HostNotAllowed { .. }
+            | FederationRequestError::ShuttingDown => false,
+            FederationRequestError::Remote { code, .. } => !matches!(
+                code,
+                WsErrorCode::Unauthorized
+                    | WsErrorCode::AuthError
+                    | WsErrorCode::InvalidGrant
+                    | WsErrorCode::Forbidden
+                    | WsErrorCode::BadRequest
+                    | WsErrorCode::MalformedEnvelope
+                    | WsErrorCode::PayloadTooLarge
+                    | WsErrorCode::UnsupportedProtocol
+                    | WsErrorCode::InviteExpired
+                    | WsErrorCode::InviteExhausted
+                    | WsErrorCode::NotFound
+                    | WsErrorCode::Conflict
+            ),
+        }
+    }
+
     pub fn into_api_error(self, host: &str) -> ApiError {
         match self {
             FederationRequestError::HostUnavailable { .. } => {
-                ApiError::Internal(format!(
+                ApiError::ServiceUnavailable(format!(
                     "No active federation websocket connection for host {host}"
                 ))
             }
+            FederationRequestError::HostNotAllowed { .. } => {
+                ApiError::Forbidden(format!(
+                    "Federation with {host} is not allowed"
+                ))
+            }
             FederationRequestError::Timeout { .. } => {
-                ApiError::Internal(format!(
+                ApiError::ServiceUnavailable(format!(
                     "Timed out waiting for federation websocket reply from {host}"
                 ))
             }
@@ -39,17 +82,171 @@ impl FederationRequestError {
                     "Federation websocket reply channel closed for host {host}"
                 ))
             }
-            FederationRequestError::Remote { code, message, .. } => {
-                match code.as_str() {
-                    "auth_error" => ApiError::AuthError(message),
-                    "bad_request" => ApiError::BadRequest(message),
-                    "not_found" => ApiError::NotFound,
-                    "conflict" => ApiError::UniqueViolation,
-                    _ => ApiError::Internal(format!(
-                        "Remote federation websocket error from {host} [{code}]: {message}"
+            FederationRequestError::ShuttingDown => {
+                ApiError::ServiceUnavailable(format!(
+                    "Request to {host} cancelled: server is shutting down"
+                ))
+            }
+            FederationRequestError::Remote {
+                code,
+                message,
+                error,
+            } => {
+                // The bare reason, when given, avoids repeating the remote's
+                // "Conflict: " style prefix in our own message
+                let reason = error
+                    .details
+                    .as_ref()
+                    .and_then(|details| details["reason"].as_str())
+                    .map(str::to_string)
+                    .unwrap_or(message);
+                match code {
+                    WsErrorCode::Unauthorized | WsErrorCode::AuthError => {
+                        ApiError::Unauthorized(reason)
+                    }
+                    WsErrorCode::InvalidGrant => ApiError::InvalidGrant(reason),
+                    WsErrorCode::Forbidden => ApiError::Forbidden(reason),
+                    WsErrorCode::BadRequest
+                    | WsErrorCode::MalformedEnvelope
+                    | WsErrorCode::PayloadTooLarge => {
+                        ApiError::BadRequest(reason)
+                    }
+                    WsErrorCode::InviteExpired => ApiError::InviteExpired,
+                    WsErrorCode::InviteExhausted => ApiError::InviteExhausted,
+                    WsErrorCode::RateLimited => ApiError::RateLimited {
+                        retry_after: std::time::Duration::from_secs(
+                            error
+                                .details
+                                .as_ref()
+                                .and_then(|details| {
+                                    details["retry_after"].as_u64()
+                                })
+                                .unwrap_or(1),
+                        ),
+                    },
+                    WsErrorCode::NotFound => ApiError::NotFound,
+                    WsErrorCode::Conflict => ApiError::Conflict(reason),
+                    WsErrorCode::ServiceUnavailable => {
+                        ApiError::ServiceUnavailable(format!(
+                            "{host} is unavailable: {reason}"
+                        ))
+                    }
+                    WsErrorCode::UnsupportedProtocol
+                    | WsErrorCode::UpstreamError
+                    | WsErrorCode::InternalError
+                    | WsErrorCode::Unknown => ApiError::Internal(format!(
+                        "Remote federation websocket error from {host} [{code}]: {reason}"
                     )),
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_conflict_maps_to_conflict() {
+        let error = FederationRequestError::Remote {
+            code: WsErrorCode::Conflict,
+            message: "Channel title already in use".into(),
+            error: WsError {
+                code: WsErrorCode::Conflict,
+                message: "Channel title already in use".into(),
+                details: None,
+            },
+        };
+        match error.into_api_error("remote.example") {
+            ApiError::Conflict(msg) => {
+                assert_eq!(msg, "Channel title already in use")
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+
+    fn remote(code: WsErrorCode) -> FederationRequestError {
+        FederationRequestError::Remote {
+            code,
+            message: "remote failure".into(),
+            error: WsError {
+                code,
+                message: "remote failure".into(),
+                details: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_transport_errors_are_retryable() {
+        let request_id = RequestId::new();
+        assert!(
+            FederationRequestError::HostUnavailable {
+                host: "remote.example".into()
+            }
+            .is_retryable()
+        );
+        assert!(
+            FederationRequestError::Timeout {
+                host: "remote.example".into(),
+                request_id,
+            }
+            .is_retryable()
+        );
+        assert!(
+            FederationRequestError::ChannelClosed { request_id }.is_retryable()
+        );
+    }
+
+    #[test]
+    fn test_remote_request_errors_are_permanent() {
+        for code in [
+            WsErrorCode::Unauthorized,
+            WsErrorCode::AuthError,
+            WsErrorCode::Forbidden,
+            WsErrorCode::BadRequest,
+            WsErrorCode::NotFound,
+            WsErrorCode::Conflict,
+            WsErrorCode::MalformedEnvelope,
+            WsErrorCode::PayloadTooLarge,
+            WsErrorCode::UnsupportedProtocol,
+        ] {
+            assert!(!remote(code).is_retryable(), "{code} should be permanent");
+        }
+    }
+
+    #[test]
+    fn test_disallowed_host_is_forbidden() {
+        let error = FederationRequestError::HostNotAllowed {
+            host: "blocked.example:7000".into(),
+        };
+        assert!(!error.is_retryable());
+        assert!(matches!(
+            error.into_api_error("blocked.example:7000"),
+            ApiError::Forbidden(_)
+        ));
+    }
+
+    #[test]
+    fn test_remote_reason_is_used_without_prefix() {
+        let error = FederationRequestError::Remote {
+            code: WsErrorCode::Conflict,
+            message: "Conflict: Channel title already in use".into(),
+            error: WsError::from(ApiError::Conflict(
+                "Channel title already in use".into(),
+            )),
+        };
+        match error.into_api_error("remote.example") {
+            ApiError::Conflict(msg) => {
+                assert_eq!(msg, "Channel title already in use")
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_remote_internal_error_is_retryable() {
+        assert!(remote(WsErrorCode::InternalError).is_retryable());
+    }
+}
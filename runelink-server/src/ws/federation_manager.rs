@@ -1,11 +1,16 @@
 #![allow(dead_code)]
 
 use std::{
-    collections::HashMap, future::Future, pin::Pin, sync::Arc,
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
     time::Duration as StdDuration,
 };
 
-use jsonwebtoken::{Algorithm, Header};
 use log::{info, warn};
 use runelink_client::util::{get_api_url, get_federation_ws_url, pad_host};
 use runelink_types::{
@@ -14,22 +19,38 @@ use runelink_types::{
     user::UserRef,
     ws::{
         FederationWsEnvelope, FederationWsReply, FederationWsRequest,
-        FederationWsUpdate, WsError,
+        FederationWsUpdate, WS_MSGPACK_SUBPROTOCOL, WS_PROTOCOL_VERSION,
+        WS_PROTOCOL_VERSION_HEADER, WsError,
     },
 };
-use time::Duration;
-use tokio::sync::{Mutex, mpsc, oneshot};
+use tokio::{
+    sync::{Mutex, mpsc, oneshot},
+    time::Instant,
+};
 use tokio_tungstenite::{
-    connect_async, tungstenite::client::IntoClientRequest,
+    connect_async_with_config,
+    tungstenite::{
+        client::IntoClientRequest,
+        http::{HeaderValue, header::SEC_WEBSOCKET_PROTOCOL},
+        protocol::WebSocketConfig,
+    },
 };
 
 use super::{
+    breaker::{CircuitBreakers, HostBreakerStatus},
+    encoding::WsEncoding,
     error::{FederationRequestError, FederationRequestResult},
     pools::FederationWsPool,
-    socket_loops::{FederationSocket, federation_socket_loop},
+    socket_loops::{
+        FederationSocket, federation_socket_loop, peer_protocol_version,
+        transport_limit,
+    },
 };
 use crate::{ids::ConnId, state::AppState};
 
+/// How often the warmup supervisor re-checks its hosts.
+const WARMUP_INTERVAL: StdDuration = StdDuration::from_secs(30);
+
 type PendingFederationReplySender =
     oneshot::Sender<Result<FederationWsReply, WsError>>;
 
@@ -42,6 +63,12 @@ type PendingFederationReplySender =
 pub struct FederationWsManager {
     pool: FederationWsPool,
     pending: Arc<Mutex<HashMap<RequestId, PendingFederationReplySender>>>,
+    /// Hosts that used up their connection attempts, and when they may be
+    /// tried again.
+    cooldowns: Arc<Mutex<HashMap<String, Instant>>>,
+    breakers: CircuitBreakers,
+    /// Set by `close_all`; no new requests go out once it is.
+    shutting_down: Arc<AtomicBool>,
 }
 
 impl Default for FederationWsManager {
@@ -57,13 +84,16 @@ impl FederationWsManager {
         Self {
             pool,
             pending: Arc::new(Mutex::new(HashMap::new())),
+            cooldowns: Arc::new(Mutex::new(HashMap::new())),
+            breakers: CircuitBreakers::default(),
+            shutting_down: Arc::new(AtomicBool::new(false)),
         }
     }
 
     /// Registers a new connection with the manager.
     pub async fn register_connection(
         &self,
-        sender: mpsc::UnboundedSender<FederationWsEnvelope>,
+        sender: mpsc::Sender<FederationWsEnvelope>,
     ) -> ConnId {
         let conn_id = ConnId::new();
         self.pool.register_connection(conn_id, sender).await;
@@ -87,6 +117,29 @@ impl FederationWsManager {
         self.pool.deregister_connection(conn_id).await
     }
 
+    /// Closes every federation connection and cancels pending outbound
+    /// requests, for shutdown.
+    ///
+    /// Waiting callers get `FederationRequestError::ShuttingDown`, as does
+    /// any request made afterwards.
+    pub async fn close_all(&self) -> usize {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        // Dropping the reply senders wakes every waiter
+        let cancelled = std::mem::take(&mut *self.pending.lock().await);
+        if !cancelled.is_empty() {
+            info!(
+                "Cancelling {} pending federation requests for shutdown",
+                cancelled.len()
+            );
+        }
+        drop(cancelled);
+        self.pool.close_all().await
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
     pub async fn authenticated_host(&self, conn_id: ConnId) -> Option<String> {
         self.pool.authenticated_host(conn_id).await
     }
@@ -98,7 +151,40 @@ impl FederationWsManager {
         self.pool.authenticated_issuer(conn_id).await
     }
 
+    /// Returns whether the given host currently has an authenticated
+    /// connection.
+    pub async fn has_host(&self, host: &str) -> bool {
+        self.pool.has_host(host).await
+    }
+
+    pub async fn connection_count(&self) -> usize {
+        self.pool.connection_count().await
+    }
+
+    /// Hosts with an authenticated connection, and how many each has.
+    pub async fn host_connection_counts(&self) -> Vec<(String, usize)> {
+        self.pool.host_connection_counts().await
+    }
+
+    /// Outbound requests still waiting for a reply.
+    pub async fn pending_count(&self) -> usize {
+        self.pending.lock().await.len()
+    }
+
+    /// The circuit breaker of every host with recent failed requests.
+    pub async fn breaker_statuses(
+        &self,
+        state: &AppState,
+    ) -> Vec<HostBreakerStatus> {
+        self.breakers
+            .statuses(state.config.federation_breaker_cooldown)
+            .await
+    }
+
     /// Sends a request to the given host and waits for a reply with a timeout.
+    ///
+    /// Fails without sending if the host isn't allowed or its circuit
+    /// breaker is open.
     pub async fn send_request_to_host(
         &self,
         state: &AppState,
@@ -108,6 +194,53 @@ impl FederationWsManager {
         timeout: StdDuration,
     ) -> FederationRequestResult<FederationWsReply> {
         let host = pad_host(host);
+        if !state.config.is_federation_allowed(&host) {
+            return Err(FederationRequestError::HostNotAllowed { host });
+        }
+        if !self
+            .breakers
+            .allows(&host, state.config.federation_breaker_cooldown)
+            .await
+        {
+            state.metrics.federation_request_failed();
+            return Err(FederationRequestError::HostUnavailable { host });
+        }
+
+        let result = self
+            .send_request(state, &host, delegated_user_ref, request, timeout)
+            .await;
+        match &result {
+            // A remote error still means the host is up and answering
+            Ok(_) | Err(FederationRequestError::Remote { .. }) => {
+                self.breakers.record_success(&host).await;
+            }
+            // Says nothing about the host
+            Err(FederationRequestError::ShuttingDown) => {}
+            Err(_) => {
+                state.metrics.federation_request_failed();
+                self.breakers
+                    .record_failure(
+                        &host,
+                        state.config.federation_breaker_threshold,
+                    )
+                    .await;
+            }
+        }
+        result
+    }
+
+    async fn send_request(
+        &self,
+        state: &AppState,
+        host: &str,
+        delegated_user_ref: Option<UserRef>,
+        request: FederationWsRequest,
+        timeout: StdDuration,
+    ) -> FederationRequestResult<FederationWsReply> {
+        if self.is_shutting_down() {
+            return Err(FederationRequestError::ShuttingDown);
+        }
+        let host = host.to_string();
         if !self.ensure_connection(state, &host).await {
             return Err(FederationRequestError::HostUnavailable { host });
         }
@@ -138,10 +271,13 @@ impl FederationWsManager {
         match result {
             Ok(Ok(Ok(reply))) => Ok(reply),
             Ok(Ok(Err(remote_error))) => Err(FederationRequestError::Remote {
-                code: remote_error.code.clone(),
+                code: remote_error.code,
                 message: remote_error.message.clone(),
                 error: remote_error,
             }),
+            Ok(Err(_)) if self.is_shutting_down() => {
+                Err(FederationRequestError::ShuttingDown)
+            }
             Ok(Err(_)) => {
                 Err(FederationRequestError::ChannelClosed { request_id })
             }
@@ -191,6 +327,26 @@ impl FederationWsManager {
             .await
     }
 
+    /// Forwards an update that arrived from another host, keeping its event
+    /// id so hosts that have already seen it drop it.
+    pub async fn relay_update_to_hosts<I, S>(
+        &self,
+        hosts: I,
+        event_id: EventId,
+        update: FederationWsUpdate,
+    ) -> usize
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.pool
+            .send_to_hosts(
+                hosts,
+                FederationWsEnvelope::Update { event_id, update },
+            )
+            .await
+    }
+
     /// Sends a reply to the given connection.
     pub async fn send_reply_to_connection(
         &self,
@@ -272,16 +428,87 @@ impl FederationWsManager {
             .await
     }
 
+    /// Spawns a supervisor that keeps connections to `hosts` open.
+    ///
+    /// Each host is connected immediately and then re-checked every
+    /// `WARMUP_INTERVAL`, so a peer that is down at startup (or drops later)
+    /// is reconnected without waiting for the first real request. Failures
+    /// are only logged.
+    pub fn spawn_warmup(&self, state: AppState, hosts: Vec<String>) {
+        if hosts.is_empty() {
+            return;
+        }
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let hosts: Vec<String> =
+                hosts.iter().map(|h| pad_host(h)).collect();
+            let mut interval = tokio::time::interval(WARMUP_INTERVAL);
+            loop {
+                interval.tick().await;
+                for host in &hosts {
+                    if !manager.ensure_connection(&state, host).await {
+                        warn!(
+                            "Federation warmup to {host} failed, retrying in {}s",
+                            WARMUP_INTERVAL.as_secs()
+                        );
+                    }
+                }
+            }
+        });
+    }
+
+    /// Makes sure there is a connection to `host`, retrying with backoff.
+    ///
+    /// A host that fails every attempt is skipped until its cooldown ends.
     async fn ensure_connection(&self, state: &AppState, host: &str) -> bool {
         if self.pool.has_host(host).await {
             return true;
         }
-
-        if !self.connect_to_host(state, host).await {
+        if self.is_shutting_down() || !state.config.is_federation_allowed(host)
+        {
             return false;
         }
+        {
+            let mut cooldowns = self.cooldowns.lock().await;
+            match cooldowns.get(host) {
+                Some(until) if *until > Instant::now() => return false,
+                Some(_) => {
+                    cooldowns.remove(host);
+                }
+                None => {}
+            }
+        }
 
-        self.pool.has_host(host).await
+        let attempts = state.config.federation_connect_attempts;
+        for attempt in 1..=attempts {
+            if self.connect_to_host(state, host).await
+                && self.pool.has_host(host).await
+            {
+                return true;
+            }
+            if attempt < attempts {
+                let delay = connect_backoff(
+                    state.config.federation_connect_backoff_base,
+                    state.config.federation_connect_backoff_max,
+                    attempt,
+                );
+                warn!(
+                    "Federation connection to {host} failed (attempt {attempt}/{attempts}), retrying in {}ms",
+                    delay.as_millis()
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+
+        warn!(
+            "Federation connection to {host} failed {attempts} times, skipping it for {}s",
+            state.config.federation_host_cooldown.as_secs()
+        );
+        self.cooldowns.lock().await.insert(
+            host.to_string(),
+            Instant::now() + state.config.federation_host_cooldown,
+        );
+        false
     }
 
     fn connect_to_host<'a>(
@@ -294,10 +521,10 @@ impl FederationWsManager {
             let claims = FederationClaims::new_server_only(
                 state.config.api_url(),
                 get_api_url(host, state.config.secure),
-                Duration::minutes(5),
+                state.config.federation_token_ttl,
             );
             let token = match jsonwebtoken::encode(
-                &Header::new(Algorithm::EdDSA),
+                &state.key_manager.header(),
                 &claims,
                 &state.key_manager.private_key,
             ) {
@@ -329,9 +556,41 @@ impl FederationWsManager {
                 }
             };
             request.headers_mut().insert("Authorization", auth_header);
+            request.headers_mut().insert(
+                WS_PROTOCOL_VERSION_HEADER,
+                HeaderValue::from(WS_PROTOCOL_VERSION),
+            );
+            if state.config.federation_msgpack {
+                request.headers_mut().insert(
+                    SEC_WEBSOCKET_PROTOCOL,
+                    HeaderValue::from_static(WS_MSGPACK_SUBPROTOCOL),
+                );
+            }
 
-            let stream = match connect_async(request).await {
-                Ok((stream, _)) => stream,
+            let limit = transport_limit(state);
+            let config = WebSocketConfig::default()
+                .max_message_size(Some(limit))
+                .max_frame_size(Some(limit));
+            let connected =
+                connect_async_with_config(request, Some(config), false).await;
+            let (stream, encoding) = match connected {
+                Ok((stream, response)) => {
+                    if let Err(error) =
+                        peer_protocol_version(response.headers())
+                    {
+                        warn!(
+                            "Refusing federation websocket to {host}: {error}"
+                        );
+                        return false;
+                    }
+                    let encoding = WsEncoding::from_subprotocol(
+                        response
+                            .headers()
+                            .get(SEC_WEBSOCKET_PROTOCOL)
+                            .map(HeaderValue::as_bytes),
+                    );
+                    (stream, encoding)
+                }
                 Err(error) => {
                     warn!(
                         "Failed opening federation websocket to {host}: {error}"
@@ -340,8 +599,9 @@ impl FederationWsManager {
                 }
             };
 
-            let (sender, outbound_rx) =
-                mpsc::unbounded_channel::<FederationWsEnvelope>();
+            let (sender, outbound_rx) = mpsc::channel::<FederationWsEnvelope>(
+                state.config.ws_outbound_queue_capacity,
+            );
             let conn_id = self.register_connection(sender).await;
             let issuer = get_api_url(host, state.config.secure);
             let _ = self
@@ -355,7 +615,8 @@ impl FederationWsManager {
                     federation_socket_loop(
                         state,
                         conn_id,
-                        FederationSocket::Outbound(stream),
+                        FederationSocket::Outbound(Box::new(stream)),
+                        encoding,
                         outbound_rx,
                     )
                     .await;
@@ -366,3 +627,48 @@ impl FederationWsManager {
         })
     }
 }
+
+/// Delay before retry `attempt` (1-based): `base` doubled per earlier
+/// retry, capped at `max`.
+fn connect_backoff(
+    base: StdDuration,
+    max: StdDuration,
+    attempt: u32,
+) -> StdDuration {
+    base.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::DbPool, test_util};
+
+    #[test]
+    fn test_connect_backoff_doubles_up_to_max() {
+        let base = StdDuration::from_millis(200);
+        let max = StdDuration::from_millis(1000);
+        let delays = (1..=5)
+            .map(|attempt| connect_backoff(base, max, attempt).as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [200, 400, 800, 1000, 1000]);
+        assert_eq!(connect_backoff(base, max, 64), max);
+    }
+
+    #[sqlx::test]
+    async fn test_warm_host_is_connected_shortly_after_startup(pool: DbPool) {
+        let state = test_util::state(pool);
+        let host = test_util::federation_peer(|_| None).await;
+
+        let manager = &state.federation_ws_manager;
+        manager.spawn_warmup(state.clone(), vec![host.clone()]);
+        let connected = async {
+            while !manager.pool.has_host(&host).await {
+                tokio::time::sleep(StdDuration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(StdDuration::from_secs(5), connected)
+            .await
+            .expect("warm host should be connected");
+    }
+}
@@ -1,12 +1,18 @@
+use std::net::IpAddr;
+
 use log::info;
 use runelink_types::{
     auth::{JwksResponse, OidcDiscoveryDocument},
     user::UserRef,
     ws::{
-        AuthTokenAccessRequest, ClientWsConnectionState, ClientWsReply,
-        ClientWsRequest,
+        AuthTokenAccessRequest, ClientWsConnectionState, ClientWsReplay,
+        ClientWsReply, ClientWsRequest, ClientWsUpdate,
+        MIN_SUPPORTED_WS_PROTOCOL_VERSION, ReplayCursor, ResumeSessionRequest,
+        ResumeToken, ResumedSession, ServerReplay, WS_PROTOCOL_VERSION,
+        is_supported_protocol_version,
     },
 };
+use time::OffsetDateTime;
 
 use super::shared::authorize_client;
 use crate::{
@@ -17,6 +23,49 @@ use crate::{
     state::AppState,
 };
 
+/// Address the connection came from, for per-IP rate limits.
+async fn peer_ip(state: &AppState, conn_id: ConnId) -> ApiResult<IpAddr> {
+    state
+        .client_ws_manager
+        .peer_ip(conn_id)
+        .await
+        .ok_or_else(|| {
+            ApiError::Internal(
+                "Client websocket connection not registered".into(),
+            )
+        })
+}
+
+/// Mark a connection as authenticated and hand it a fresh resume token.
+async fn authenticate_connection(
+    state: &AppState,
+    conn_id: ConnId,
+    user_ref: UserRef,
+) -> ApiResult<()> {
+    let authenticated = state
+        .client_ws_manager
+        .authenticate_connection(conn_id, user_ref.clone())
+        .await;
+    if !authenticated {
+        return Err(ApiError::Internal(
+            "Client websocket connection not registered".into(),
+        ));
+    }
+    let (resume_token, expires_at) =
+        state.client_ws_manager.issue_resume_token(user_ref).await;
+    state
+        .client_ws_manager
+        .send_update_to_connection(
+            conn_id,
+            ClientWsUpdate::ResumeTokenIssued(ResumeToken {
+                resume_token,
+                expires_at,
+            }),
+        )
+        .await;
+    Ok(())
+}
+
 /// Handle a client websocket request.
 pub(super) async fn handle_client_request(
     state: &AppState,
@@ -25,8 +74,22 @@ pub(super) async fn handle_client_request(
 ) -> ApiResult<ClientWsReply> {
     info!("WS client: request={:#?}", request);
     match request {
+        ClientWsRequest::Hello { protocol_version } => {
+            if !is_supported_protocol_version(protocol_version) {
+                return Err(ApiError::UnsupportedProtocol(protocol_version));
+            }
+            Ok(ClientWsReply::Hello {
+                server_version: WS_PROTOCOL_VERSION,
+                min_supported: MIN_SUPPORTED_WS_PROTOCOL_VERSION,
+            })
+        }
+
         ClientWsRequest::Ping => Ok(ClientWsReply::Pong),
 
+        ClientWsRequest::ServerTime => {
+            Ok(ClientWsReply::ServerTime(OffsetDateTime::now_utc()))
+        }
+
         ClientWsRequest::OidcDiscovery => {
             let issuer = state.config.api_url();
             Ok(ClientWsReply::OidcDiscovery(OidcDiscoveryDocument {
@@ -39,14 +102,17 @@ pub(super) async fn handle_client_request(
                     "refresh_token".into(),
                 ],
                 response_types_supported: vec![],
-                scopes_supported: vec![],
+                scopes_supported: auth_service::SUPPORTED_SCOPES
+                    .iter()
+                    .map(|scope| scope.to_string())
+                    .collect(),
                 token_endpoint_auth_methods_supported: vec!["none".into()],
             }))
         }
 
         ClientWsRequest::OidcJwks => {
             Ok(ClientWsReply::OidcJwks(JwksResponse {
-                keys: vec![state.key_manager.public_jwk.clone()],
+                keys: state.key_manager.public_jwks(),
             }))
         }
 
@@ -71,78 +137,124 @@ pub(super) async fn handle_client_request(
                 auth_service::authenticate_access_token(state, &access_token)?;
             let user_ref = UserRef::parse_subject(&auth.claims.sub)
                 .ok_or_else(|| {
-                    ApiError::AuthError(
+                    ApiError::Unauthorized(
                         "Invalid token subject (expected name@host)".into(),
                     )
                 })?;
-            let authenticated = state
-                .client_ws_manager
-                .authenticate_connection(conn_id, user_ref.clone())
-                .await;
-            if !authenticated {
-                return Err(ApiError::Internal(
-                    "Client websocket connection not registered".into(),
-                ));
-            }
+            authenticate_connection(state, conn_id, user_ref.clone()).await?;
             Ok(ClientWsReply::AuthTokenAccess(
                 ClientWsConnectionState::Authenticated { user_ref },
             ))
         }
 
+        ClientWsRequest::ResumeSession(ResumeSessionRequest {
+            resume_token,
+            cursors,
+        }) => {
+            let user_ref = state
+                .client_ws_manager
+                .consume_resume_token(&resume_token)
+                .await
+                .ok_or_else(|| {
+                    ApiError::Unauthorized(
+                        "Invalid or expired resume token; authenticate again"
+                            .into(),
+                    )
+                })?;
+            authenticate_connection(state, conn_id, user_ref.clone()).await?;
+            let mut replays = Vec::with_capacity(cursors.len());
+            for ReplayCursor {
+                server_id,
+                after_event_id,
+            } in cursors
+            {
+                let replay = match authorize_client(
+                    state,
+                    conn_id,
+                    ops::replay::auth::since(server_id),
+                )
+                .await
+                {
+                    Ok(_) => {
+                        ops::replay::since(state, server_id, after_event_id)
+                            .await
+                    }
+                    // No longer a member, so there is nothing to catch up on
+                    Err(ApiError::Forbidden(_)) => {
+                        ClientWsReplay::ResyncRequired
+                    }
+                    Err(error) => return Err(error),
+                };
+                replays.push(ServerReplay { server_id, replay });
+            }
+            Ok(ClientWsReply::ResumeSession(ResumedSession {
+                state: ClientWsConnectionState::Authenticated { user_ref },
+                replays,
+            }))
+        }
+
         ClientWsRequest::AuthSignup(signup_request) => {
-            let user = auth_service::signup(state, signup_request).await?;
+            let client_ip = peer_ip(state, conn_id).await?;
+            let user =
+                auth_service::signup(state, signup_request, client_ip).await?;
             Ok(ClientWsReply::AuthSignup(user))
         }
 
         ClientWsRequest::AuthTokenPassword(password_request) => {
-            let issued =
-                auth_service::issue_password_token(state, password_request)
-                    .await?;
-            let authenticated = state
-                .client_ws_manager
-                .authenticate_connection(conn_id, issued.user_ref)
-                .await;
-            if !authenticated {
-                return Err(ApiError::Internal(
-                    "Client websocket connection not registered".into(),
-                ));
-            }
+            let client_ip = peer_ip(state, conn_id).await?;
+            let issued = auth_service::issue_password_token(
+                state,
+                password_request,
+                client_ip,
+            )
+            .await?;
+            authenticate_connection(state, conn_id, issued.user_ref).await?;
             Ok(ClientWsReply::AuthToken(issued.response))
         }
 
         ClientWsRequest::AuthTokenRefresh(refresh_request) => {
-            let issued =
-                auth_service::issue_refresh_token(state, refresh_request)
-                    .await?;
-            let authenticated = state
-                .client_ws_manager
-                .authenticate_connection(conn_id, issued.user_ref)
-                .await;
-            if !authenticated {
-                return Err(ApiError::Internal(
-                    "Client websocket connection not registered".into(),
-                ));
-            }
+            let client_ip = peer_ip(state, conn_id).await?;
+            let issued = auth_service::issue_refresh_token(
+                state,
+                refresh_request,
+                client_ip,
+            )
+            .await?;
+            authenticate_connection(state, conn_id, issued.user_ref).await?;
             Ok(ClientWsReply::AuthToken(issued.response))
         }
 
-        ClientWsRequest::AuthUserinfo | ClientWsRequest::AuthRegisterClient => {
-            Err(ApiError::BadRequest(
-                "This auth operation is not implemented over websocket".into(),
-            ))
+        ClientWsRequest::AuthUserinfo => {
+            let user_ref = state
+                .client_ws_manager
+                .authenticated_user_ref(conn_id)
+                .await
+                .ok_or_else(|| {
+                    ApiError::Unauthorized(
+                        "Connection is not authenticated".into(),
+                    )
+                })?;
+            let userinfo = auth_service::userinfo_for(state, user_ref).await?;
+            Ok(ClientWsReply::AuthUserinfo(userinfo))
         }
 
+        ClientWsRequest::AuthRegisterClient => Err(ApiError::BadRequest(
+            "This auth operation is not implemented over websocket".into(),
+        )),
+
         ClientWsRequest::UsersCreate(new_user) => {
-            let session =
+            let mut session =
                 authorize_client(state, conn_id, ops::users::auth::create())
                     .await?;
-            let user = ops::users::create(state, &session, &new_user).await?;
+            let user =
+                ops::users::create(state, &mut session, &new_user).await?;
             Ok(ClientWsReply::UsersCreate(user))
         }
 
-        ClientWsRequest::UsersGetAll { target_host } => {
+        ClientWsRequest::UsersGetAll { query, target_host } => {
             let users =
-                ops::users::get_all(state, target_host.as_deref()).await?;
+                ops::users::get_all(state, &query, target_host.as_deref())
+                    .await?;
             Ok(ClientWsReply::UsersGetAll(users))
         }
 
@@ -156,6 +268,11 @@ pub(super) async fn handle_client_request(
             Ok(ClientWsReply::UsersGetByRef(user))
         }
 
+        ClientWsRequest::UsersSearch { query, hosts } => {
+            let users = ops::users::search(state, &query, &hosts).await?;
+            Ok(ClientWsReply::UsersSearch(users))
+        }
+
         ClientWsRequest::UsersGetAssociatedHosts {
             user_ref,
             target_host,
@@ -180,6 +297,19 @@ pub(super) async fn handle_client_request(
             Ok(ClientWsReply::UsersDelete)
         }
 
+        ClientWsRequest::UsersUpdateProfile { user_ref, update } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::users::auth::update_profile(user_ref.clone()),
+            )
+            .await?;
+            let user =
+                ops::users::update_profile(state, &session, &user_ref, &update)
+                    .await?;
+            Ok(ClientWsReply::UsersUpdateProfile(user))
+        }
+
         ClientWsRequest::MembershipsGetByUser { user_ref } => {
             let memberships =
                 ops::memberships::get_by_user(state, user_ref).await?;
@@ -262,6 +392,136 @@ pub(super) async fn handle_client_request(
             Ok(ClientWsReply::MembershipsDelete)
         }
 
+        ClientWsRequest::MembershipsUpdateRole {
+            server_id,
+            user_ref,
+            role,
+            target_host,
+        } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::memberships::auth::update_role(server_id),
+            )
+            .await?;
+            let membership = ops::memberships::update_role(
+                state,
+                &session,
+                server_id,
+                user_ref,
+                role,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::MembershipsUpdateRole(membership))
+        }
+
+        ClientWsRequest::MembershipsRemove {
+            server_id,
+            user_ref,
+            target_host,
+        } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::memberships::auth::remove_member(server_id),
+            )
+            .await?;
+            ops::memberships::remove_member(
+                state,
+                &session,
+                server_id,
+                user_ref,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::MembershipsRemove)
+        }
+
+        ClientWsRequest::BansCreate {
+            server_id,
+            new_ban,
+            target_host,
+        } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::bans::auth::ban(server_id),
+            )
+            .await?;
+            let ban = ops::bans::ban(
+                state,
+                &session,
+                server_id,
+                &new_ban,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::BansCreate(ban))
+        }
+
+        ClientWsRequest::BansDelete {
+            server_id,
+            user_ref,
+            target_host,
+        } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::bans::auth::unban(server_id),
+            )
+            .await?;
+            ops::bans::unban(
+                state,
+                &session,
+                server_id,
+                user_ref,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::BansDelete)
+        }
+
+        ClientWsRequest::InvitesCreate {
+            server_id,
+            new_invite,
+            target_host,
+        } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::invites::auth::create(server_id),
+            )
+            .await?;
+            let invite = ops::invites::create(
+                state,
+                &session,
+                server_id,
+                &new_invite,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::InvitesCreate(invite))
+        }
+
+        ClientWsRequest::InvitesRedeem { code, target_host } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::memberships::auth::create_via_invite(),
+            )
+            .await?;
+            let membership = ops::memberships::create_via_invite(
+                state,
+                &session,
+                &code,
+                None,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::InvitesRedeem(membership))
+        }
+
         ClientWsRequest::ServersCreate {
             new_server,
             target_host,
@@ -279,19 +539,50 @@ pub(super) async fn handle_client_request(
             Ok(ClientWsReply::ServersCreate(server))
         }
 
-        ClientWsRequest::ServersGetAll { target_host } => {
-            let servers =
-                ops::servers::get_all(state, target_host.as_deref()).await?;
+        ClientWsRequest::ServersGetAll {
+            include_private,
+            target_host,
+        } => {
+            if include_private {
+                authorize_client(
+                    state,
+                    conn_id,
+                    ops::servers::auth::get_all_including_private(),
+                )
+                .await?;
+            }
+            let servers = ops::servers::get_all(
+                state,
+                include_private,
+                target_host.as_deref(),
+            )
+            .await?;
             Ok(ClientWsReply::ServersGetAll(servers))
         }
 
         ClientWsRequest::ServersGetById {
             server_id,
+            force_refresh,
             target_host,
         } => {
+            let requirement = ops::servers::auth::get_by_id(
+                state,
+                server_id,
+                target_host.as_deref(),
+            )
+            .await?;
+            if let Some(requirement) = requirement {
+                authorize_client(state, conn_id, requirement).await?;
+            }
+            let viewer = state
+                .client_ws_manager
+                .authenticated_user_ref(conn_id)
+                .await;
             let server = ops::servers::get_by_id(
                 state,
+                viewer.as_ref(),
                 server_id,
+                force_refresh,
                 target_host.as_deref(),
             )
             .await?;
@@ -300,6 +591,7 @@ pub(super) async fn handle_client_request(
 
         ClientWsRequest::ServersGetWithChannels {
             server_id,
+            include_last_messages,
             target_host,
         } => {
             let session = authorize_client(
@@ -312,6 +604,7 @@ pub(super) async fn handle_client_request(
                 state,
                 &session,
                 server_id,
+                include_last_messages,
                 target_host.as_deref(),
             )
             .await?;
@@ -338,6 +631,30 @@ pub(super) async fn handle_client_request(
             Ok(ClientWsReply::ServersDelete)
         }
 
+        ClientWsRequest::ServersTransferOwnership {
+            server_id,
+            new_admin,
+            demote_self,
+            target_host,
+        } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::servers::auth::transfer_ownership(server_id),
+            )
+            .await?;
+            let membership = ops::servers::transfer_ownership(
+                state,
+                &session,
+                server_id,
+                new_admin,
+                demote_self,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::ServersTransferOwnership(membership))
+        }
+
         ClientWsRequest::ChannelsCreate {
             server_id,
             new_channel,
@@ -415,6 +732,52 @@ pub(super) async fn handle_client_request(
             Ok(ClientWsReply::ChannelsGetById(channel))
         }
 
+        ClientWsRequest::ChannelsUpdate {
+            server_id,
+            channel_id,
+            update,
+            target_host,
+        } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::channels::auth::update(server_id),
+            )
+            .await?;
+            let channel = ops::channels::update(
+                state,
+                &session,
+                server_id,
+                channel_id,
+                &update,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::ChannelsUpdate(channel))
+        }
+
+        ClientWsRequest::ChannelsReorder {
+            server_id,
+            channel_ids,
+            target_host,
+        } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::channels::auth::reorder(server_id),
+            )
+            .await?;
+            let channels = ops::channels::reorder(
+                state,
+                &session,
+                server_id,
+                &channel_ids,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::ChannelsReorder(channels))
+        }
+
         ClientWsRequest::ChannelsDelete {
             server_id,
             channel_id,
@@ -497,6 +860,8 @@ pub(super) async fn handle_client_request(
         ClientWsRequest::MessagesGetByChannel {
             server_id,
             channel_id,
+            before,
+            limit,
             target_host,
         } => {
             let session = authorize_client(
@@ -510,6 +875,8 @@ pub(super) async fn handle_client_request(
                 &session,
                 server_id,
                 channel_id,
+                before,
+                limit,
                 target_host.as_deref(),
             )
             .await?;
@@ -540,6 +907,54 @@ pub(super) async fn handle_client_request(
             Ok(ClientWsReply::MessagesGetById(message))
         }
 
+        ClientWsRequest::MessagesSearch {
+            server_id,
+            query,
+            limit,
+            target_host,
+        } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::messages::auth::search(server_id),
+            )
+            .await?;
+            let messages = ops::messages::search(
+                state,
+                &session,
+                server_id,
+                &query,
+                limit,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::MessagesSearch(messages))
+        }
+
+        ClientWsRequest::MessagesUpdate {
+            server_id,
+            channel_id,
+            message_id,
+            update,
+            target_host,
+        } => {
+            let requirement =
+                ops::messages::auth::update(state, server_id, message_id)
+                    .await?;
+            let session = authorize_client(state, conn_id, requirement).await?;
+            let message = ops::messages::update(
+                state,
+                &session,
+                server_id,
+                channel_id,
+                message_id,
+                &update,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::MessagesUpdate(message))
+        }
+
         ClientWsRequest::MessagesDelete {
             server_id,
             channel_id,
@@ -561,5 +976,363 @@ pub(super) async fn handle_client_request(
             .await?;
             Ok(ClientWsReply::MessagesDelete)
         }
+
+        ClientWsRequest::MessagesPurgeChannel {
+            server_id,
+            channel_id,
+            before,
+            target_host,
+        } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::messages::auth::purge_channel(server_id),
+            )
+            .await?;
+            let report = ops::messages::purge_channel(
+                state,
+                &session,
+                server_id,
+                channel_id,
+                before,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::MessagesPurgeChannel(report))
+        }
+
+        ClientWsRequest::MessagesReact {
+            server_id,
+            channel_id,
+            message_id,
+            new_reaction,
+            target_host,
+        } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::messages::auth::react(server_id),
+            )
+            .await?;
+            let message = ops::messages::add_reaction(
+                state,
+                &session,
+                server_id,
+                channel_id,
+                message_id,
+                &new_reaction,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::MessagesReact(message))
+        }
+
+        ClientWsRequest::MessagesUnreact {
+            server_id,
+            channel_id,
+            message_id,
+            emoji,
+            target_host,
+        } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::messages::auth::react(server_id),
+            )
+            .await?;
+            let message = ops::messages::remove_reaction(
+                state,
+                &session,
+                server_id,
+                channel_id,
+                message_id,
+                &emoji,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::MessagesUnreact(message))
+        }
+
+        ClientWsRequest::ChannelsMarkRead {
+            server_id,
+            channel_id,
+            message_id,
+            target_host,
+        } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::messages::auth::mark_read(server_id),
+            )
+            .await?;
+            let read_state = ops::messages::mark_read(
+                state,
+                &session,
+                server_id,
+                channel_id,
+                message_id,
+                target_host.as_deref(),
+            )
+            .await?;
+            // Keep the user's other devices in step with this one
+            let _ = state
+                .client_ws_manager
+                .send_update_to_user_except(
+                    &read_state.user_ref,
+                    conn_id,
+                    ClientWsUpdate::ReadStateUpdated(read_state.clone()),
+                )
+                .await;
+            Ok(ClientWsReply::ChannelsMarkRead(read_state))
+        }
+
+        ClientWsRequest::UnreadCounts {
+            server_id,
+            target_host,
+        } => {
+            let session = authorize_client(
+                state,
+                conn_id,
+                ops::messages::auth::get_unread_counts(server_id),
+            )
+            .await?;
+            let counts = ops::messages::get_unread_counts(
+                state,
+                &session,
+                server_id,
+                target_host.as_deref(),
+            )
+            .await?;
+            Ok(ClientWsReply::UnreadCounts(counts))
+        }
+
+        ClientWsRequest::DmSend { to, body } => {
+            let session =
+                authorize_client(state, conn_id, ops::dms::auth::send())
+                    .await?;
+            let message = ops::dms::send(state, &session, &to, body).await?;
+            Ok(ClientWsReply::DmSend(message))
+        }
+
+        ClientWsRequest::DmGetHistory {
+            with,
+            before,
+            limit,
+        } => {
+            let session =
+                authorize_client(state, conn_id, ops::dms::auth::get_history())
+                    .await?;
+            let messages =
+                ops::dms::get_history(state, &session, &with, before, limit)
+                    .await?;
+            Ok(ClientWsReply::DmGetHistory(messages))
+        }
+
+        ClientWsRequest::Subscribe {
+            server_id,
+            channel_id,
+        } => {
+            // Reading a channel's history and following it live need the
+            // same access.
+            authorize_client(
+                state,
+                conn_id,
+                ops::messages::auth::get_by_channel(server_id),
+            )
+            .await?;
+            let subscribed =
+                state.client_ws_manager.subscribe(conn_id, channel_id).await;
+            if !subscribed {
+                return Err(ApiError::Internal(
+                    "Client websocket connection not registered".into(),
+                ));
+            }
+            Ok(ClientWsReply::Subscribe)
+        }
+
+        ClientWsRequest::Unsubscribe {
+            server_id: _,
+            channel_id,
+        } => {
+            let _ = state
+                .client_ws_manager
+                .unsubscribe(conn_id, channel_id)
+                .await;
+            Ok(ClientWsReply::Unsubscribe)
+        }
+
+        ClientWsRequest::PresenceSubscribe { server_id } => {
+            authorize_client(
+                state,
+                conn_id,
+                ops::presence::auth::subscribe(server_id),
+            )
+            .await?;
+            // Subscribe first so no change slips between it and the snapshot
+            let subscribed = state
+                .client_ws_manager
+                .subscribe_presence(conn_id, server_id)
+                .await;
+            if !subscribed {
+                return Err(ApiError::Internal(
+                    "Client websocket connection not registered".into(),
+                ));
+            }
+            let online = ops::presence::get_online(state, server_id).await?;
+            Ok(ClientWsReply::PresenceSubscribe(online))
+        }
+
+        ClientWsRequest::Typing {
+            server_id,
+            channel_id,
+        } => {
+            // Indicators are ephemeral, so excess ones are dropped quietly
+            let allowed = state
+                .client_ws_manager
+                .mark_typing(conn_id, ops::typing::TYPING_INTERVAL)
+                .await;
+            if allowed {
+                let session = authorize_client(
+                    state,
+                    conn_id,
+                    ops::typing::auth::send(server_id),
+                )
+                .await?;
+                ops::typing::send(state, &session, server_id, channel_id)
+                    .await?;
+            }
+            Ok(ClientWsReply::Typing)
+        }
+
+        ClientWsRequest::ReplaySince {
+            server_id,
+            after_event_id,
+        } => {
+            authorize_client(
+                state,
+                conn_id,
+                ops::replay::auth::since(server_id),
+            )
+            .await?;
+            let replay =
+                ops::replay::since(state, server_id, after_event_id).await;
+            Ok(ClientWsReply::ReplaySince(replay))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use runelink_types::{
+        channel::ChannelId, message::MessageId, server::ServerId,
+        ws::ClientWsEnvelope,
+    };
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{db::DbPool, test_util};
+
+    #[sqlx::test]
+    async fn test_resume_restores_auth_and_replays_missed_updates(
+        pool: DbPool,
+    ) {
+        let state = test_util::state(pool);
+        let alice = test_util::local_user(&state, "alice").await.as_ref();
+        let server = test_util::server(&state, &alice, "Server").await;
+        let (resume_token, _) = state
+            .client_ws_manager
+            .issue_resume_token(alice.clone())
+            .await;
+
+        // Updates the old connection saw, then one it missed
+        let (sender, mut receiver) = mpsc::channel(4);
+        let old_conn = state
+            .client_ws_manager
+            .register_connection(sender, IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .await;
+        state
+            .client_ws_manager
+            .authenticate_connection(old_conn, alice.clone())
+            .await;
+        let message_deleted = || ClientWsUpdate::MessageDeleted {
+            server_id: server.id,
+            channel_id: ChannelId::new(),
+            message_id: MessageId::new(),
+        };
+        state
+            .client_ws_manager
+            .send_update_to_user(&alice, message_deleted())
+            .await;
+        let Ok(ClientWsEnvelope::Update {
+            event_id: last_seen,
+            ..
+        }) = receiver.try_recv()
+        else {
+            panic!("the old connection should see the first update");
+        };
+        state
+            .client_ws_manager
+            .deregister_connection(old_conn)
+            .await;
+        let missed = message_deleted();
+        state
+            .client_ws_manager
+            .send_update_to_user(&alice, missed.clone())
+            .await;
+
+        let (sender, _receiver) = mpsc::channel(4);
+        let conn_id = state
+            .client_ws_manager
+            .register_connection(sender, IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .await;
+        let unknown_server = ServerId::new();
+        let reply = handle_client_request(
+            &state,
+            conn_id,
+            ClientWsRequest::ResumeSession(ResumeSessionRequest {
+                resume_token,
+                cursors: vec![
+                    ReplayCursor {
+                        server_id: server.id,
+                        after_event_id: last_seen,
+                    },
+                    ReplayCursor {
+                        server_id: unknown_server,
+                        after_event_id: last_seen,
+                    },
+                ],
+            }),
+        )
+        .await
+        .unwrap();
+
+        let ClientWsReply::ResumeSession(resumed) = reply else {
+            panic!("expected a resumed session");
+        };
+        assert_eq!(
+            resumed.state,
+            ClientWsConnectionState::Authenticated {
+                user_ref: alice.clone()
+            }
+        );
+        assert_eq!(
+            state
+                .client_ws_manager
+                .authenticated_user_ref(conn_id)
+                .await,
+            Some(alice)
+        );
+        let [replayed, not_a_member] = resumed.replays.as_slice() else {
+            panic!("expected a replay per cursor");
+        };
+        assert_eq!(replayed.server_id, server.id);
+        let ClientWsReplay::Updates(updates) = &replayed.replay else {
+            panic!("expected the missed update");
+        };
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].update, missed);
+        assert_eq!(not_a_member.replay, ClientWsReplay::ResyncRequired);
     }
 }
@@ -1,5 +1,8 @@
 use log::info;
+use runelink_client::util::get_api_url;
 use runelink_types::{
+    channel::ChannelId,
+    ids::EventId,
     server::ServerId,
     user::UserRef,
     ws::{
@@ -7,6 +10,7 @@ use runelink_types::{
         FederationWsRequest, FederationWsUpdate,
     },
 };
+use time::OffsetDateTime;
 
 use super::shared::authorize_federation;
 use crate::{
@@ -33,9 +37,30 @@ async fn fanout_remote_server_update(
     Ok(())
 }
 
+/// Fanout a remote channel message update to the local users subscribed to
+/// the channel (best effort).
+async fn fanout_remote_channel_update(
+    state: &AppState,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    client_update: ClientWsUpdate,
+) -> ApiResult<()> {
+    let local_users = state
+        .routing_index
+        .users_for_remote_server(server_id)
+        .await?;
+    let _ = state
+        .client_ws_manager
+        .send_update_to_subscribers(local_users, channel_id, client_update)
+        .await;
+    Ok(())
+}
+
 /// Handle a federation websocket update.
 pub(super) async fn handle_federation_update(
     state: &AppState,
+    conn_id: ConnId,
+    event_id: EventId,
     update: FederationWsUpdate,
 ) -> ApiResult<()> {
     info!("WS federation: update={:#?}", update);
@@ -48,6 +73,10 @@ pub(super) async fn handle_federation_update(
                 &membership.clone().into(),
             )
             .await?;
+            state
+                .routing_index
+                .invalidate_server(membership.server.id)
+                .await;
             fanout_remote_server_update(
                 state,
                 membership.server.id,
@@ -66,6 +95,7 @@ pub(super) async fn handle_federation_update(
                 user_ref.clone(),
             )
             .await;
+            state.routing_index.invalidate_server(server_id).await;
             let mut targets = state
                 .routing_index
                 .users_for_remote_server(server_id)
@@ -79,12 +109,10 @@ pub(super) async fn handle_federation_update(
                 server_id,
                 user_ref,
             };
-            for local_user in targets {
-                let _ = state
-                    .client_ws_manager
-                    .send_update_to_user(&local_user, client_update.clone())
-                    .await;
-            }
+            let _ = state
+                .client_ws_manager
+                .send_update_to_users(targets, client_update)
+                .await;
         }
 
         FederationWsUpdate::ServerUpserted(server) => {
@@ -97,12 +125,16 @@ pub(super) async fn handle_federation_update(
         }
 
         FederationWsUpdate::ServerDeleted { server_id } => {
+            // Notify local members before their cached memberships vanish.
             fanout_remote_server_update(
                 state,
                 server_id,
                 ClientWsUpdate::ServerDeleted { server_id },
             )
             .await?;
+            queries::servers::delete_cached_remote(&state.db_pool, server_id)
+                .await?;
+            state.routing_index.invalidate_server(server_id).await;
         }
 
         FederationWsUpdate::ChannelUpserted(channel) => {
@@ -130,9 +162,15 @@ pub(super) async fn handle_federation_update(
         }
 
         FederationWsUpdate::MessageUpserted { server_id, message } => {
-            fanout_remote_server_update(
+            if message.server_id != server_id {
+                return Err(ApiError::BadRequest(
+                    "Message does not belong to the updated server".into(),
+                ));
+            }
+            fanout_remote_channel_update(
                 state,
                 server_id,
+                message.channel_id,
                 ClientWsUpdate::MessageUpserted(message),
             )
             .await?;
@@ -143,9 +181,10 @@ pub(super) async fn handle_federation_update(
             channel_id,
             message_id,
         } => {
-            fanout_remote_server_update(
+            fanout_remote_channel_update(
                 state,
                 server_id,
+                channel_id,
                 ClientWsUpdate::MessageDeleted {
                     server_id,
                     channel_id,
@@ -155,12 +194,132 @@ pub(super) async fn handle_federation_update(
             .await?;
         }
 
+        FederationWsUpdate::ChannelPurged {
+            server_id,
+            channel_id,
+            before,
+        } => {
+            fanout_remote_channel_update(
+                state,
+                server_id,
+                channel_id,
+                ClientWsUpdate::ChannelPurged {
+                    server_id,
+                    channel_id,
+                    before,
+                },
+            )
+            .await?;
+        }
+
+        FederationWsUpdate::MessageReactionUpserted {
+            server_id,
+            channel_id,
+            message_id,
+            user_ref,
+            emoji,
+        } => {
+            fanout_remote_channel_update(
+                state,
+                server_id,
+                channel_id,
+                ClientWsUpdate::MessageReactionUpserted {
+                    server_id,
+                    channel_id,
+                    message_id,
+                    user_ref,
+                    emoji,
+                },
+            )
+            .await?;
+        }
+
+        FederationWsUpdate::MessageReactionRemoved {
+            server_id,
+            channel_id,
+            message_id,
+            user_ref,
+            emoji,
+        } => {
+            fanout_remote_channel_update(
+                state,
+                server_id,
+                channel_id,
+                ClientWsUpdate::MessageReactionRemoved {
+                    server_id,
+                    channel_id,
+                    message_id,
+                    user_ref,
+                    emoji,
+                },
+            )
+            .await?;
+        }
+
+        FederationWsUpdate::UserUpserted(user) => {
+            // Only a user's home host may change their profile
+            let issuer = state
+                .federation_ws_manager
+                .authenticated_issuer(conn_id)
+                .await;
+            if issuer.as_deref()
+                != Some(get_api_url(&user.host, state.config.secure).as_str())
+            {
+                return Err(ApiError::Forbidden(format!(
+                    "Profile update for {} did not come from its home host",
+                    user.as_ref()
+                )));
+            }
+            let audience =
+                queries::memberships::get_user_refs_sharing_server_with(
+                    &state.db_pool,
+                    &user.as_ref(),
+                )
+                .await?;
+            let user =
+                queries::users::upsert_remote(&state.db_pool, &user).await?;
+            let _ = state
+                .client_ws_manager
+                .send_update_to_users(
+                    audience,
+                    ClientWsUpdate::UserUpserted(user),
+                )
+                .await;
+        }
+
+        FederationWsUpdate::PresenceChanged {
+            server_id,
+            user_ref,
+            online,
+        } => {
+            ops::presence::apply_federated(
+                state, conn_id, event_id, server_id, user_ref, online,
+            )
+            .await?;
+        }
+
+        FederationWsUpdate::Typing {
+            server_id,
+            channel_id,
+            user_ref,
+        } => {
+            ops::typing::apply_federated(
+                state, conn_id, event_id, server_id, channel_id, user_ref,
+            )
+            .await?;
+        }
+
         FederationWsUpdate::RemoteUserDeleted { user_ref } => {
             let _ = state
                 .client_ws_manager
                 .broadcast_update(ClientWsUpdate::UserDeleted { user_ref })
                 .await;
         }
+
+        FederationWsUpdate::Mentioned { message, user_refs } => {
+            ops::mentions::apply_federated(state, conn_id, message, user_refs)
+                .await?;
+        }
     }
     Ok(())
 }
@@ -188,8 +347,12 @@ pub(super) async fn handle_federation_request(
             Ok(FederationWsReply::ConnectionState(state))
         }
 
-        FederationWsRequest::UsersGetAll => {
-            let users = ops::users::get_all(state, None).await?;
+        FederationWsRequest::ServerTime => {
+            Ok(FederationWsReply::ServerTime(OffsetDateTime::now_utc()))
+        }
+
+        FederationWsRequest::UsersGetAll { query } => {
+            let users = ops::users::get_all(state, &query, None).await?;
             Ok(FederationWsReply::UsersGetAll(users))
         }
 
@@ -198,6 +361,11 @@ pub(super) async fn handle_federation_request(
             Ok(FederationWsReply::UsersGetByRef(user))
         }
 
+        FederationWsRequest::UsersSearch { query } => {
+            let users = ops::users::search(state, &query, &[]).await?;
+            Ok(FederationWsReply::UsersSearch(users))
+        }
+
         FederationWsRequest::UsersGetAssociatedHosts { user_ref } => {
             let hosts =
                 ops::users::get_associated_hosts(state, user_ref, None).await?;
@@ -225,21 +393,26 @@ pub(super) async fn handle_federation_request(
                         .into(),
                 ));
             }
+            let admin_ref = delegated_user_ref.clone().ok_or_else(|| {
+                ApiError::Forbidden("Federated delegated user required".into())
+            })?;
             let mut session = authorize_federation(
                 state,
                 conn_id,
-                Some(user.as_ref()),
+                Some(admin_ref.clone()),
                 ops::memberships::auth::federated::create(
                     new_membership.server_id,
-                    user.as_ref(),
+                    admin_ref.clone(),
                 ),
             )
             .await?;
+            // The payload is only trusted for users of the calling host
+            let remote_user = (user.host == admin_ref.host).then_some(&user);
             let membership = ops::memberships::upsert(
                 state,
                 &mut session,
                 &new_membership.into(),
-                Some(&user),
+                remote_user,
             )
             .await?;
             Ok(FederationWsReply::MembershipsUpsert(membership))
@@ -294,6 +467,122 @@ pub(super) async fn handle_federation_request(
             Ok(FederationWsReply::MembershipsGetByUserAndServer(member))
         }
 
+        FederationWsRequest::MembershipsUpdateRole {
+            server_id,
+            user_ref,
+            role,
+        } => {
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                ops::memberships::auth::federated::update_role(server_id),
+            )
+            .await?;
+            let membership = ops::memberships::update_role(
+                state, &session, server_id, user_ref, role, None,
+            )
+            .await?;
+            Ok(FederationWsReply::MembershipsUpdateRole(membership))
+        }
+
+        FederationWsRequest::MembershipsRemove {
+            server_id,
+            user_ref,
+        } => {
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                ops::memberships::auth::federated::remove_member(server_id),
+            )
+            .await?;
+            ops::memberships::remove_member(
+                state, &session, server_id, user_ref, None,
+            )
+            .await?;
+            Ok(FederationWsReply::MembershipsRemove)
+        }
+
+        FederationWsRequest::BansCreate { server_id, new_ban } => {
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                ops::bans::auth::federated::ban(server_id),
+            )
+            .await?;
+            let ban =
+                ops::bans::ban(state, &session, server_id, &new_ban, None)
+                    .await?;
+            Ok(FederationWsReply::BansCreate(ban))
+        }
+
+        FederationWsRequest::BansDelete {
+            server_id,
+            user_ref,
+        } => {
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                ops::bans::auth::federated::unban(server_id),
+            )
+            .await?;
+            ops::bans::unban(state, &session, server_id, user_ref, None)
+                .await?;
+            Ok(FederationWsReply::BansDelete)
+        }
+
+        FederationWsRequest::InvitesCreate {
+            server_id,
+            new_invite,
+        } => {
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                ops::invites::auth::federated::create(server_id),
+            )
+            .await?;
+            let invite = ops::invites::create(
+                state,
+                &session,
+                server_id,
+                &new_invite,
+                None,
+            )
+            .await?;
+            Ok(FederationWsReply::InvitesCreate(invite))
+        }
+
+        FederationWsRequest::InvitesRedeem { code, user } => {
+            if !state.config.is_remote_host(Some(&user.host)) {
+                return Err(ApiError::BadRequest(
+                    "User host in invite redemption should not match local host"
+                        .into(),
+                ));
+            }
+            let session = authorize_federation(
+                state,
+                conn_id,
+                Some(user.as_ref()),
+                ops::memberships::auth::federated::create_via_invite(
+                    user.as_ref(),
+                ),
+            )
+            .await?;
+            let membership = ops::memberships::create_via_invite(
+                state,
+                &session,
+                &code,
+                Some(&user),
+                None,
+            )
+            .await?;
+            Ok(FederationWsReply::InvitesRedeem(membership))
+        }
+
         FederationWsRequest::ServersCreate(new_server) => {
             let session = authorize_federation(
                 state,
@@ -320,18 +609,64 @@ pub(super) async fn handle_federation_request(
             Ok(FederationWsReply::ServersDelete)
         }
 
+        FederationWsRequest::ServersTransferOwnership {
+            server_id,
+            new_admin,
+            demote_self,
+        } => {
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                ops::servers::auth::federated::transfer_ownership(server_id),
+            )
+            .await?;
+            let membership = ops::servers::transfer_ownership(
+                state,
+                &session,
+                server_id,
+                new_admin,
+                demote_self,
+                None,
+            )
+            .await?;
+            Ok(FederationWsReply::ServersTransferOwnership(membership))
+        }
+
         FederationWsRequest::ServersGetAll => {
-            let servers = ops::servers::get_all(state, None).await?;
+            // Remote hosts only ever see public servers.
+            let servers = ops::servers::get_all(state, false, None).await?;
             Ok(FederationWsReply::ServersGetAll(servers))
         }
 
         FederationWsRequest::ServersGetById { server_id } => {
-            let server =
-                ops::servers::get_by_id(state, server_id, None).await?;
+            let requirement =
+                ops::servers::auth::federated::get_by_id(state, server_id)
+                    .await?;
+            if let Some(requirement) = requirement {
+                authorize_federation(
+                    state,
+                    conn_id,
+                    delegated_user_ref.clone(),
+                    requirement,
+                )
+                .await?;
+            }
+            let server = ops::servers::get_by_id(
+                state,
+                delegated_user_ref.as_ref(),
+                server_id,
+                false,
+                None,
+            )
+            .await?;
             Ok(FederationWsReply::ServersGetById(server))
         }
 
-        FederationWsRequest::ServersGetWithChannels { server_id } => {
+        FederationWsRequest::ServersGetWithChannels {
+            server_id,
+            include_last_messages,
+        } => {
             let session = authorize_federation(
                 state,
                 conn_id,
@@ -340,7 +675,11 @@ pub(super) async fn handle_federation_request(
             )
             .await?;
             let server_with_channels = ops::servers::get_with_channels(
-                state, &session, server_id, None,
+                state,
+                &session,
+                server_id,
+                include_last_messages,
+                None,
             )
             .await?;
             Ok(FederationWsReply::ServersGetWithChannels(
@@ -415,6 +754,47 @@ pub(super) async fn handle_federation_request(
             Ok(FederationWsReply::ChannelsGetById(channel))
         }
 
+        FederationWsRequest::ChannelsUpdate {
+            server_id,
+            channel_id,
+            update,
+        } => {
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                ops::channels::auth::federated::update(server_id),
+            )
+            .await?;
+            let channel = ops::channels::update(
+                state, &session, server_id, channel_id, &update, None,
+            )
+            .await?;
+            Ok(FederationWsReply::ChannelsUpdate(channel))
+        }
+
+        FederationWsRequest::ChannelsReorder {
+            server_id,
+            channel_ids,
+        } => {
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                ops::channels::auth::federated::reorder(server_id),
+            )
+            .await?;
+            let channels = ops::channels::reorder(
+                state,
+                &session,
+                server_id,
+                &channel_ids,
+                None,
+            )
+            .await?;
+            Ok(FederationWsReply::ChannelsReorder(channels))
+        }
+
         FederationWsRequest::ChannelsDelete {
             server_id,
             channel_id,
@@ -485,6 +865,8 @@ pub(super) async fn handle_federation_request(
         FederationWsRequest::MessagesGetByChannel {
             server_id,
             channel_id,
+            before,
+            limit,
         } => {
             let session = authorize_federation(
                 state,
@@ -494,7 +876,7 @@ pub(super) async fn handle_federation_request(
             )
             .await?;
             let messages = ops::messages::get_by_channel(
-                state, &session, server_id, channel_id, None,
+                state, &session, server_id, channel_id, before, limit, None,
             )
             .await?;
             Ok(FederationWsReply::MessagesGetByChannel(messages))
@@ -519,6 +901,50 @@ pub(super) async fn handle_federation_request(
             Ok(FederationWsReply::MessagesGetById(message))
         }
 
+        FederationWsRequest::MessagesSearch {
+            server_id,
+            query,
+            limit,
+        } => {
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                ops::messages::auth::federated::search(server_id),
+            )
+            .await?;
+            let messages = ops::messages::search(
+                state, &session, server_id, &query, limit, None,
+            )
+            .await?;
+            Ok(FederationWsReply::MessagesSearch(messages))
+        }
+
+        FederationWsRequest::MessagesUpdate {
+            server_id,
+            channel_id,
+            message_id,
+            update,
+        } => {
+            let requirement = ops::messages::auth::federated::update(
+                state, server_id, message_id,
+            )
+            .await?;
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                requirement,
+            )
+            .await?;
+            let message = ops::messages::update(
+                state, &session, server_id, channel_id, message_id, &update,
+                None,
+            )
+            .await?;
+            Ok(FederationWsReply::MessagesUpdate(message))
+        }
+
         FederationWsRequest::MessagesDelete {
             server_id,
             channel_id,
@@ -541,5 +967,122 @@ pub(super) async fn handle_federation_request(
             .await?;
             Ok(FederationWsReply::MessagesDelete)
         }
+
+        FederationWsRequest::MessagesPurgeChannel {
+            server_id,
+            channel_id,
+            before,
+        } => {
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                ops::messages::auth::federated::purge_channel(server_id),
+            )
+            .await?;
+            let report = ops::messages::purge_channel(
+                state, &session, server_id, channel_id, before, None,
+            )
+            .await?;
+            Ok(FederationWsReply::MessagesPurgeChannel(report))
+        }
+
+        FederationWsRequest::MessagesReact {
+            server_id,
+            channel_id,
+            message_id,
+            new_reaction,
+        } => {
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                ops::messages::auth::federated::react(server_id),
+            )
+            .await?;
+            let message = ops::messages::add_reaction(
+                state,
+                &session,
+                server_id,
+                channel_id,
+                message_id,
+                &new_reaction,
+                None,
+            )
+            .await?;
+            Ok(FederationWsReply::MessagesReact(message))
+        }
+
+        FederationWsRequest::MessagesUnreact {
+            server_id,
+            channel_id,
+            message_id,
+            emoji,
+        } => {
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                ops::messages::auth::federated::react(server_id),
+            )
+            .await?;
+            let message = ops::messages::remove_reaction(
+                state, &session, server_id, channel_id, message_id, &emoji,
+                None,
+            )
+            .await?;
+            Ok(FederationWsReply::MessagesUnreact(message))
+        }
+
+        FederationWsRequest::ChannelsMarkRead {
+            server_id,
+            channel_id,
+            message_id,
+        } => {
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                ops::messages::auth::federated::mark_read(server_id),
+            )
+            .await?;
+            let read_state = ops::messages::mark_read(
+                state, &session, server_id, channel_id, message_id, None,
+            )
+            .await?;
+            Ok(FederationWsReply::ChannelsMarkRead(read_state))
+        }
+
+        FederationWsRequest::UnreadCounts { server_id } => {
+            let session = authorize_federation(
+                state,
+                conn_id,
+                delegated_user_ref,
+                ops::messages::auth::federated::get_unread_counts(server_id),
+            )
+            .await?;
+            let counts = ops::messages::get_unread_counts(
+                state, &session, server_id, None,
+            )
+            .await?;
+            Ok(FederationWsReply::UnreadCounts(counts))
+        }
+
+        FederationWsRequest::DmSend { from, to, body } => {
+            if !state.config.is_remote_host(Some(&from.host)) {
+                return Err(ApiError::BadRequest(
+                    "Direct message sender should not match local host".into(),
+                ));
+            }
+            authorize_federation(
+                state,
+                conn_id,
+                Some(from.as_ref()),
+                ops::dms::auth::federated::send(from.as_ref()),
+            )
+            .await?;
+            let message = ops::dms::receive(state, &from, &to, body).await?;
+            Ok(FederationWsReply::DmSend(message))
+        }
     }
 }
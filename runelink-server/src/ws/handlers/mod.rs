@@ -117,9 +117,15 @@ pub async fn handle_federation_message(
                 log::warn!("Unmatched federation websocket response envelope");
             }
         }
-        FederationWsEnvelope::Update { update, .. } => {
-            if let Err(error) =
-                federation::handle_federation_update(state, update).await
+        FederationWsEnvelope::Update { event_id, update } => {
+            if !state.seen_federation_events.insert(event_id).await {
+                log::debug!("Dropping repeated federation update {event_id}");
+                return;
+            }
+            if let Err(error) = federation::handle_federation_update(
+                state, conn_id, event_id, update,
+            )
+            .await
             {
                 log::warn!(
                     "Failed handling federation websocket update: {error}"
@@ -19,7 +19,7 @@ pub(super) async fn authorize_client(
         .authenticated_user_ref(conn_id)
         .await
         .ok_or_else(|| {
-            ApiError::AuthError(
+            ApiError::Unauthorized(
                 "Client websocket connection is not authenticated".into(),
             )
         })?;
@@ -46,7 +46,7 @@ pub(super) async fn authorize_federation(
         .authenticated_issuer(conn_id)
         .await
         .ok_or_else(|| {
-            ApiError::AuthError(
+            ApiError::Unauthorized(
                 "Federation websocket connection is not authenticated".into(),
             )
         })?;
@@ -1,13 +1,20 @@
 mod client_manager;
+mod encoding;
 mod federation_manager;
 mod handlers;
+mod open_sockets;
 mod pools;
 mod routing;
+mod seen_events;
 mod socket_loops;
 
+pub mod breaker;
 pub mod error;
 
 pub use client_manager::ClientWsManager;
 pub use federation_manager::FederationWsManager;
+pub use open_sockets::OpenSockets;
+pub use pools::PresenceChange;
 pub use routing::RoutingIndex;
+pub use seen_events::SeenEvents;
 pub use socket_loops::{client_ws, federation_ws};
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// Counts running websocket loops, so shutdown can wait for them to send
+/// their close frames before the process exits.
+#[derive(Clone, Debug)]
+pub struct OpenSockets {
+    count: Arc<watch::Sender<usize>>,
+}
+
+impl Default for OpenSockets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Held by a socket loop while it runs; dropping it marks the socket closed.
+#[derive(Debug)]
+pub struct OpenSocketGuard {
+    count: Arc<watch::Sender<usize>>,
+}
+
+impl OpenSockets {
+    pub fn new() -> Self {
+        let (count, _) = watch::channel(0);
+        Self {
+            count: Arc::new(count),
+        }
+    }
+
+    pub fn open(&self) -> OpenSocketGuard {
+        self.count.send_modify(|count| *count += 1);
+        OpenSocketGuard {
+            count: self.count.clone(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        *self.count.borrow()
+    }
+
+    /// Resolves once every socket loop has exited.
+    pub async fn all_closed(&self) {
+        let mut count = self.count.subscribe();
+        // The sender lives in `self`, so this can't fail
+        let _ = count.wait_for(|count| *count == 0).await;
+    }
+}
+
+impl Drop for OpenSocketGuard {
+    fn drop(&mut self) {
+        self.count.send_modify(|count| *count -= 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_all_closed_waits_for_guards() {
+        let sockets = OpenSockets::new();
+        let first = sockets.open();
+        let second = sockets.open();
+        assert_eq!(sockets.count(), 2);
+
+        drop(first);
+        let waiting = tokio::time::timeout(
+            Duration::from_millis(20),
+            sockets.all_closed(),
+        );
+        assert!(waiting.await.is_err());
+
+        drop(second);
+        tokio::time::timeout(Duration::from_secs(1), sockets.all_closed())
+            .await
+            .unwrap();
+        assert_eq!(sockets.count(), 0);
+    }
+}
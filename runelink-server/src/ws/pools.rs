@@ -3,34 +3,99 @@
 use std::{
     borrow::Borrow,
     collections::{HashMap, HashSet},
-    sync::Arc,
+    net::IpAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
 };
 
 use runelink_types::{
+    channel::ChannelId,
+    server::ServerId,
     user::UserRef,
     ws::{ClientWsEnvelope, FederationWsEnvelope},
 };
-use time::OffsetDateTime;
-use tokio::sync::{RwLock, mpsc};
+use time::{Duration, OffsetDateTime};
+use tokio::sync::{RwLock, broadcast, mpsc};
 
 use crate::ids::ConnId;
 
+/// Queues an envelope without waiting for room in the connection's buffer.
+///
+/// Returns false if the queue is full or closed; callers prune the
+/// connection either way, which also closes its socket loop once the last
+/// sender is dropped.
+fn try_send<T>(sender: &mpsc::Sender<T>, envelope: T, conn_id: ConnId) -> bool {
+    match sender.try_send(envelope) {
+        Ok(()) => true,
+        Err(mpsc::error::TrySendError::Full(_)) => {
+            log::warn!(
+                "Websocket {conn_id:?} outbound queue is full, dropping connection"
+            );
+            false
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => false,
+    }
+}
+
+/// How many presence changes can queue up before slow listeners miss some.
+const PRESENCE_CHANNEL_CAPACITY: usize = 1024;
+
+/// A local user's first connection authenticated or their last one closed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PresenceChange {
+    pub user_ref: UserRef,
+    pub online: bool,
+}
+
 /// Tracks active client websocket connections and provides safe send helpers.
 #[derive(Clone, Debug, Default)]
 pub struct ClientWsPool {
     inner: Arc<RwLock<ClientPoolState>>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 struct ClientPoolState {
     connections: HashMap<ConnId, ClientConn>,
     by_user: HashMap<UserRef, HashSet<ConnId>>,
+    by_channel: HashMap<ChannelId, HashSet<ConnId>>,
+    by_presence_server: HashMap<ServerId, HashSet<ConnId>>,
+    presence_tx: broadcast::Sender<PresenceChange>,
+}
+
+impl Default for ClientPoolState {
+    fn default() -> Self {
+        let (presence_tx, _) = broadcast::channel(PRESENCE_CHANNEL_CAPACITY);
+        Self {
+            connections: HashMap::new(),
+            by_user: HashMap::new(),
+            by_channel: HashMap::new(),
+            by_presence_server: HashMap::new(),
+            presence_tx,
+        }
+    }
+}
+
+impl ClientPoolState {
+    fn emit_presence(&self, user_ref: UserRef, online: bool) {
+        // Nobody listening is fine; the change just goes unannounced
+        let _ = self.presence_tx.send(PresenceChange { user_ref, online });
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct ClientConn {
-    pub sender: mpsc::UnboundedSender<ClientWsEnvelope>,
+    pub sender: mpsc::Sender<ClientWsEnvelope>,
+    /// Address the connection came from, for per-IP rate limits.
+    pub peer_ip: IpAddr,
     pub user_ref: Option<UserRef>,
+    /// Channels whose message updates this connection receives.
+    pub subscriptions: HashSet<ChannelId>,
+    /// Servers whose members' presence changes this connection receives.
+    pub presence_subscriptions: HashSet<ServerId>,
+    /// When this connection last sent a typing indicator that went out.
+    pub last_typing_at: Option<OffsetDateTime>,
     pub connected_at: OffsetDateTime,
 }
 
@@ -40,60 +105,223 @@ impl ClientWsPool {
         Self::default()
     }
 
+    /// Returns a receiver for users going online or offline on this host.
+    pub async fn presence_changes(
+        &self,
+    ) -> broadcast::Receiver<PresenceChange> {
+        self.inner.read().await.presence_tx.subscribe()
+    }
+
     /// Registers a new connection with the pool.
     pub async fn register_connection(
         &self,
         conn_id: ConnId,
-        sender: mpsc::UnboundedSender<ClientWsEnvelope>,
+        sender: mpsc::Sender<ClientWsEnvelope>,
+        peer_ip: IpAddr,
     ) {
         let mut state = self.inner.write().await;
-        if let Some(previous) = state.connections.remove(&conn_id) {
-            if let Some(previous_user) = previous.user_ref {
-                Self::remove_conn_from_user_index(
-                    &mut state.by_user,
-                    &previous_user,
-                    conn_id,
-                );
-            }
-        }
+        let _ = Self::remove_client_connection(&mut state, conn_id);
         state.connections.insert(
             conn_id,
             ClientConn {
                 sender,
+                peer_ip,
                 user_ref: None,
+                subscriptions: HashSet::new(),
+                presence_subscriptions: HashSet::new(),
+                last_typing_at: None,
                 connected_at: OffsetDateTime::now_utc(),
             },
         );
     }
 
     /// Authenticates a connection for a given user.
+    ///
+    /// Switching the connection to a different user drops its channel and
+    /// presence subscriptions, since they were authorized for the previous
+    /// user.
     pub async fn authenticate_connection(
         &self,
         conn_id: ConnId,
         user_ref: UserRef,
     ) -> bool {
         let mut state = self.inner.write().await;
-        let old_user = match state.connections.get_mut(&conn_id) {
-            Some(conn) => conn.user_ref.replace(user_ref.clone()),
-            None => return false,
-        };
-        if let Some(previous_user) = old_user {
-            Self::remove_conn_from_user_index(
+        let (old_user, dropped_channels, dropped_servers) =
+            match state.connections.get_mut(&conn_id) {
+                Some(conn) => {
+                    let old_user = conn.user_ref.replace(user_ref.clone());
+                    if old_user.as_ref() != Some(&user_ref) {
+                        (
+                            old_user,
+                            std::mem::take(&mut conn.subscriptions),
+                            std::mem::take(&mut conn.presence_subscriptions),
+                        )
+                    } else {
+                        (old_user, HashSet::new(), HashSet::new())
+                    }
+                }
+                None => return false,
+            };
+        if let Some(previous_user) =
+            old_user.filter(|previous| previous != &user_ref)
+            && Self::remove_conn_from_user_index(
                 &mut state.by_user,
                 &previous_user,
                 conn_id,
+            )
+        {
+            state.emit_presence(previous_user, false);
+        }
+        for channel_id in dropped_channels {
+            Self::remove_conn_from_channel_index(
+                &mut state.by_channel,
+                channel_id,
+                conn_id,
             );
         }
-        state.by_user.entry(user_ref).or_default().insert(conn_id);
+        for server_id in dropped_servers {
+            Self::remove_conn_from_presence_index(
+                &mut state.by_presence_server,
+                server_id,
+                conn_id,
+            );
+        }
+        let user_conns = state.by_user.entry(user_ref.clone()).or_default();
+        let first_conn = user_conns.is_empty();
+        user_conns.insert(conn_id);
+        if first_conn {
+            state.emit_presence(user_ref, true);
+        }
         true
     }
 
+    /// Subscribes a connection to message updates for a channel.
+    ///
+    /// Returns false if the connection is not registered.
+    pub async fn subscribe(
+        &self,
+        conn_id: ConnId,
+        channel_id: ChannelId,
+    ) -> bool {
+        let mut state = self.inner.write().await;
+        let Some(conn) = state.connections.get_mut(&conn_id) else {
+            return false;
+        };
+        conn.subscriptions.insert(channel_id);
+        state
+            .by_channel
+            .entry(channel_id)
+            .or_default()
+            .insert(conn_id);
+        true
+    }
+
+    /// Unsubscribes a connection from message updates for a channel.
+    ///
+    /// Returns false if the connection was not subscribed.
+    pub async fn unsubscribe(
+        &self,
+        conn_id: ConnId,
+        channel_id: ChannelId,
+    ) -> bool {
+        let mut state = self.inner.write().await;
+        let removed = state
+            .connections
+            .get_mut(&conn_id)
+            .is_some_and(|conn| conn.subscriptions.remove(&channel_id));
+        if removed {
+            Self::remove_conn_from_channel_index(
+                &mut state.by_channel,
+                channel_id,
+                conn_id,
+            );
+        }
+        removed
+    }
+
+    /// Records a typing indicator from a connection.
+    ///
+    /// Returns false if the connection is not registered or its previous
+    /// indicator went out less than `interval` ago, in which case this one
+    /// should be dropped.
+    pub async fn mark_typing(
+        &self,
+        conn_id: ConnId,
+        interval: Duration,
+    ) -> bool {
+        let mut state = self.inner.write().await;
+        let Some(conn) = state.connections.get_mut(&conn_id) else {
+            return false;
+        };
+        let now = OffsetDateTime::now_utc();
+        if conn
+            .last_typing_at
+            .is_some_and(|last_typing_at| now - last_typing_at < interval)
+        {
+            return false;
+        }
+        conn.last_typing_at = Some(now);
+        true
+    }
+
+    /// Subscribes a connection to presence changes for a server's members.
+    ///
+    /// Returns false if the connection is not registered.
+    pub async fn subscribe_presence(
+        &self,
+        conn_id: ConnId,
+        server_id: ServerId,
+    ) -> bool {
+        let mut state = self.inner.write().await;
+        let Some(conn) = state.connections.get_mut(&conn_id) else {
+            return false;
+        };
+        conn.presence_subscriptions.insert(server_id);
+        state
+            .by_presence_server
+            .entry(server_id)
+            .or_default()
+            .insert(conn_id);
+        true
+    }
+
+    /// Returns the given users that have at least one authenticated
+    /// connection.
+    pub async fn online_users<I, S>(&self, users: I) -> Vec<UserRef>
+    where
+        I: IntoIterator<Item = S>,
+        S: Borrow<UserRef>,
+    {
+        let state = self.inner.read().await;
+        users
+            .into_iter()
+            .map(|user| user.borrow().clone())
+            .filter(|user| state.by_user.contains_key(user))
+            .collect()
+    }
+
     /// Deregisters a connection from the pool.
     pub async fn deregister_connection(&self, conn_id: ConnId) -> bool {
         let mut state = self.inner.write().await;
         Self::remove_client_connection(&mut state, conn_id)
     }
 
+    /// Number of open connections, authenticated or not.
+    pub async fn connection_count(&self) -> usize {
+        self.inner.read().await.connections.len()
+    }
+
+    /// Drops every connection, so each socket loop sends a close frame and
+    /// exits once its queued envelopes are flushed.
+    pub async fn close_all(&self) -> usize {
+        let mut state = self.inner.write().await;
+        let conn_ids = state.connections.keys().copied().collect::<Vec<_>>();
+        for conn_id in &conn_ids {
+            Self::remove_client_connection(&mut state, *conn_id);
+        }
+        conn_ids.len()
+    }
+
     /// Sends an envelope to the active connection for the given connection ID.
     pub async fn send_to_connection(
         &self,
@@ -110,7 +338,7 @@ impl ClientWsPool {
         let Some(sender) = sender else {
             return false;
         };
-        if sender.send(envelope).is_ok() {
+        if try_send(&sender, envelope, conn_id) {
             return true;
         }
         let _ = self.deregister_connection(conn_id).await;
@@ -129,10 +357,37 @@ impl ClientWsPool {
             .and_then(|conn| conn.user_ref.clone())
     }
 
+    pub async fn peer_ip(&self, conn_id: ConnId) -> Option<IpAddr> {
+        let state = self.inner.read().await;
+        state.connections.get(&conn_id).map(|conn| conn.peer_ip)
+    }
+
     pub async fn send_to_user(
         &self,
         user_ref: &UserRef,
         envelope: ClientWsEnvelope,
+    ) -> usize {
+        self.send_to_user_connections(user_ref, None, envelope)
+            .await
+    }
+
+    /// Sends an envelope to a user's active connections other than
+    /// `except`.
+    pub async fn send_to_user_except(
+        &self,
+        user_ref: &UserRef,
+        except: ConnId,
+        envelope: ClientWsEnvelope,
+    ) -> usize {
+        self.send_to_user_connections(user_ref, Some(except), envelope)
+            .await
+    }
+
+    async fn send_to_user_connections(
+        &self,
+        user_ref: &UserRef,
+        except: Option<ConnId>,
+        envelope: ClientWsEnvelope,
     ) -> usize {
         let targets = {
             let state = self.inner.read().await;
@@ -141,6 +396,7 @@ impl ClientWsPool {
                 .get(user_ref)
                 .into_iter()
                 .flat_map(|conn_ids| conn_ids.iter())
+                .filter(|conn_id| Some(**conn_id) != except)
                 .filter_map(|conn_id| {
                     state
                         .connections
@@ -187,6 +443,74 @@ impl ClientWsPool {
         Self::send_to_many_client(targets, envelope, self).await
     }
 
+    /// Sends an envelope to the connections of the given users that are
+    /// subscribed to a channel.
+    pub async fn send_to_subscribers<I, S>(
+        &self,
+        users: I,
+        channel_id: ChannelId,
+        envelope: ClientWsEnvelope,
+    ) -> usize
+    where
+        I: IntoIterator<Item = S>,
+        S: Borrow<UserRef>,
+    {
+        let users = users
+            .into_iter()
+            .map(|user| user.borrow().clone())
+            .collect::<HashSet<UserRef>>();
+        let targets = {
+            let state = self.inner.read().await;
+            let Some(subscribers) = state.by_channel.get(&channel_id) else {
+                return 0;
+            };
+            subscribers
+                .iter()
+                .filter_map(|conn_id| {
+                    let conn = state.connections.get(conn_id)?;
+                    let user_ref = conn.user_ref.as_ref()?;
+                    users
+                        .contains(user_ref)
+                        .then(|| (*conn_id, conn.sender.clone()))
+                })
+                .collect::<Vec<_>>()
+        };
+        Self::send_to_many_client(targets, envelope, self).await
+    }
+
+    /// Sends an envelope to the connections subscribed to presence changes
+    /// for any of the given servers, once per connection.
+    pub async fn send_to_presence_subscribers<I>(
+        &self,
+        server_ids: I,
+        envelope: ClientWsEnvelope,
+    ) -> usize
+    where
+        I: IntoIterator<Item = ServerId>,
+    {
+        let targets = {
+            let state = self.inner.read().await;
+            let mut conn_ids = HashSet::new();
+            for server_id in server_ids {
+                if let Some(subscribers) =
+                    state.by_presence_server.get(&server_id)
+                {
+                    conn_ids.extend(subscribers.iter().copied());
+                }
+            }
+            conn_ids
+                .into_iter()
+                .filter_map(|conn_id| {
+                    state
+                        .connections
+                        .get(&conn_id)
+                        .map(|conn| (conn_id, conn.sender.clone()))
+                })
+                .collect::<Vec<_>>()
+        };
+        Self::send_to_many_client(targets, envelope, self).await
+    }
+
     /// Broadcasts an envelope to all active connections.
     pub async fn broadcast(&self, envelope: ClientWsEnvelope) -> usize {
         let targets = {
@@ -200,15 +524,44 @@ impl ClientWsPool {
         Self::send_to_many_client(targets, envelope, self).await
     }
 
+    /// Returns true if this was the user's last connection.
     fn remove_conn_from_user_index(
         by_user: &mut HashMap<UserRef, HashSet<ConnId>>,
         user_ref: &UserRef,
         conn_id: ConnId,
+    ) -> bool {
+        if let Some(conn_ids) = by_user.get_mut(user_ref)
+            && conn_ids.remove(&conn_id)
+            && conn_ids.is_empty()
+        {
+            by_user.remove(user_ref);
+            return true;
+        }
+        false
+    }
+
+    fn remove_conn_from_channel_index(
+        by_channel: &mut HashMap<ChannelId, HashSet<ConnId>>,
+        channel_id: ChannelId,
+        conn_id: ConnId,
     ) {
-        if let Some(conn_ids) = by_user.get_mut(user_ref) {
+        if let Some(conn_ids) = by_channel.get_mut(&channel_id) {
             conn_ids.remove(&conn_id);
             if conn_ids.is_empty() {
-                by_user.remove(user_ref);
+                by_channel.remove(&channel_id);
+            }
+        }
+    }
+
+    fn remove_conn_from_presence_index(
+        by_presence_server: &mut HashMap<ServerId, HashSet<ConnId>>,
+        server_id: ServerId,
+        conn_id: ConnId,
+    ) {
+        if let Some(conn_ids) = by_presence_server.get_mut(&server_id) {
+            conn_ids.remove(&conn_id);
+            if conn_ids.is_empty() {
+                by_presence_server.remove(&server_id);
             }
         }
     }
@@ -220,25 +573,41 @@ impl ClientWsPool {
         let Some(connection) = state.connections.remove(&conn_id) else {
             return false;
         };
-        if let Some(user_ref) = connection.user_ref {
-            Self::remove_conn_from_user_index(
+        if let Some(user_ref) = connection.user_ref
+            && Self::remove_conn_from_user_index(
                 &mut state.by_user,
                 &user_ref,
                 conn_id,
+            )
+        {
+            state.emit_presence(user_ref, false);
+        }
+        for channel_id in connection.subscriptions {
+            Self::remove_conn_from_channel_index(
+                &mut state.by_channel,
+                channel_id,
+                conn_id,
+            );
+        }
+        for server_id in connection.presence_subscriptions {
+            Self::remove_conn_from_presence_index(
+                &mut state.by_presence_server,
+                server_id,
+                conn_id,
             );
         }
         true
     }
 
     async fn send_to_many_client(
-        targets: Vec<(ConnId, mpsc::UnboundedSender<ClientWsEnvelope>)>,
+        targets: Vec<(ConnId, mpsc::Sender<ClientWsEnvelope>)>,
         envelope: ClientWsEnvelope,
         pool: &ClientWsPool,
     ) -> usize {
         let mut sent = 0usize;
         let mut stale = Vec::<ConnId>::new();
         for (conn_id, sender) in targets {
-            if sender.send(envelope.clone()).is_ok() {
+            if try_send(&sender, envelope.clone(), conn_id) {
                 sent += 1;
             } else {
                 stale.push(conn_id);
@@ -256,8 +625,8 @@ impl ClientWsPool {
 }
 
 /// Tracks active federation websocket connections and provides safe send
-/// helpers. Maintains one authenticated federation connection per host and
-/// manages safe message delivery.
+/// helpers. A host may have several authenticated connections, e.g. one per
+/// instance of a clustered peer; sends to a host use one of them.
 #[derive(Clone, Debug, Default)]
 pub struct FederationWsPool {
     inner: Arc<RwLock<FederationPoolState>>,
@@ -266,12 +635,41 @@ pub struct FederationWsPool {
 #[derive(Debug, Default)]
 struct FederationPoolState {
     connections: HashMap<ConnId, FederationConn>,
-    by_host: HashMap<String, ConnId>,
+    by_host: HashMap<String, HashSet<ConnId>>,
+    /// Rotates sends across a host's connections.
+    next_pick: AtomicUsize,
+}
+
+impl FederationPoolState {
+    /// The host's live connections, starting with the next one in turn.
+    fn host_targets(
+        &self,
+        host: &str,
+    ) -> Vec<(ConnId, mpsc::Sender<FederationWsEnvelope>)> {
+        let Some(conn_ids) = self.by_host.get(host) else {
+            return Vec::new();
+        };
+        let mut targets = conn_ids
+            .iter()
+            .filter_map(|conn_id| {
+                let conn = self.connections.get(conn_id)?;
+                Some((*conn_id, conn.sender.clone()))
+            })
+            .collect::<Vec<_>>();
+        if !targets.is_empty() {
+            // Sorted so the rotation doesn't depend on hash order
+            targets.sort_by_key(|(conn_id, _)| conn_id.as_uuid());
+            let start =
+                self.next_pick.fetch_add(1, Ordering::Relaxed) % targets.len();
+            targets.rotate_left(start);
+        }
+        targets
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct FederationConn {
-    pub sender: mpsc::UnboundedSender<FederationWsEnvelope>,
+    pub sender: mpsc::Sender<FederationWsEnvelope>,
     pub host: Option<String>,
     pub issuer: Option<String>,
     pub connected_at: OffsetDateTime,
@@ -287,17 +685,17 @@ impl FederationWsPool {
     pub async fn register_connection(
         &self,
         conn_id: ConnId,
-        sender: mpsc::UnboundedSender<FederationWsEnvelope>,
+        sender: mpsc::Sender<FederationWsEnvelope>,
     ) {
         let mut state = self.inner.write().await;
-        if let Some(previous) = state.connections.remove(&conn_id) {
-            if let Some(previous_host) = previous.host {
-                Self::remove_conn_from_host_index(
-                    &mut state.by_host,
-                    &previous_host,
-                    conn_id,
-                );
-            }
+        if let Some(previous) = state.connections.remove(&conn_id)
+            && let Some(previous_host) = previous.host
+        {
+            Self::remove_conn_from_host_index(
+                &mut state.by_host,
+                &previous_host,
+                conn_id,
+            );
         }
 
         state.connections.insert(
@@ -312,6 +710,9 @@ impl FederationWsPool {
     }
 
     /// Authenticates a connection for a given host.
+    ///
+    /// A host may hold several connections, e.g. one per instance of a
+    /// clustered peer; none of them is evicted.
     pub async fn authenticate_connection(
         &self,
         conn_id: ConnId,
@@ -332,16 +733,11 @@ impl FederationWsPool {
             );
         }
 
-        if let Some(existing_conn_id) = state.by_host.get(&host).copied() {
-            if existing_conn_id != conn_id {
-                let _ = Self::remove_federation_connection(
-                    &mut state,
-                    existing_conn_id,
-                );
-            }
-        }
-
-        state.by_host.insert(host.clone(), conn_id);
+        state
+            .by_host
+            .entry(host.clone())
+            .or_default()
+            .insert(conn_id);
         if let Some(conn) = state.connections.get_mut(&conn_id) {
             conn.host = Some(host);
             conn.issuer = Some(issuer);
@@ -354,6 +750,36 @@ impl FederationWsPool {
         Self::remove_federation_connection(&mut state, conn_id)
     }
 
+    /// Number of open connections, authenticated or not.
+    pub async fn connection_count(&self) -> usize {
+        self.inner.read().await.connections.len()
+    }
+
+    /// Number of hosts with an authenticated connection.
+    pub async fn host_count(&self) -> usize {
+        self.inner.read().await.by_host.len()
+    }
+
+    /// Hosts with an authenticated connection, and how many each has.
+    pub async fn host_connection_counts(&self) -> Vec<(String, usize)> {
+        let state = self.inner.read().await;
+        state
+            .by_host
+            .iter()
+            .map(|(host, conn_ids)| (host.clone(), conn_ids.len()))
+            .collect()
+    }
+
+    /// Drops every connection, so each socket loop sends a close frame and
+    /// exits once its queued envelopes are flushed.
+    pub async fn close_all(&self) -> usize {
+        let mut state = self.inner.write().await;
+        state.by_host.clear();
+        let closed = state.connections.len();
+        state.connections.clear();
+        closed
+    }
+
     /// Sends an envelope to the active connection for the given connection ID.
     pub async fn send_to_connection(
         &self,
@@ -372,7 +798,7 @@ impl FederationWsPool {
             return false;
         };
 
-        if sender.send(envelope).is_ok() {
+        if try_send(&sender, envelope, conn_id) {
             return true;
         }
 
@@ -400,42 +826,32 @@ impl FederationWsPool {
             .and_then(|conn| conn.issuer.clone())
     }
 
-    /// Returns whether the given host currently has an authenticated connection.
+    /// Returns whether the given host currently has an authenticated
+    /// connection.
     pub async fn has_host(&self, host: &str) -> bool {
         let state = self.inner.read().await;
-        let Some(conn_id) = state.by_host.get(host).copied() else {
-            return false;
-        };
-        state.connections.contains_key(&conn_id)
+        state.by_host.get(host).is_some_and(|conn_ids| {
+            conn_ids
+                .iter()
+                .any(|conn_id| state.connections.contains_key(conn_id))
+        })
     }
 
-    /// Sends an envelope to the active connection for the given host.
+    /// Sends an envelope over one of the given host's connections, taking
+    /// them in turn.
     pub async fn send_to_host(
         &self,
         host: &str,
         envelope: FederationWsEnvelope,
     ) -> bool {
-        let target = {
-            let state = self.inner.read().await;
-            let Some(conn_id) = state.by_host.get(host).copied() else {
-                return false;
-            };
-            state
-                .connections
-                .get(&conn_id)
-                .map(|conn| (conn_id, conn.sender.clone()))
-        };
-        let Some((conn_id, sender)) = target else {
-            return false;
-        };
-        if sender.send(envelope).is_ok() {
-            return true;
-        }
-        let _ = self.deregister_connection(conn_id).await;
-        false
+        self.send_to_hosts([host], envelope).await == 1
     }
 
-    /// Sends an envelope to the active connection for the given hosts.
+    /// Sends an envelope once to each of the given hosts, over one of each
+    /// host's connections.
+    ///
+    /// A connection whose queue is full or closed is dropped and the next
+    /// one for that host is tried.
     pub async fn send_to_hosts<I, S>(
         &self,
         hosts: I,
@@ -451,19 +867,24 @@ impl FederationWsPool {
             .collect::<HashSet<String>>();
         let targets = {
             let state = self.inner.read().await;
-            let mut out = Vec::new();
-            for host in hosts {
-                let Some(conn_id) = state.by_host.get(&host).copied() else {
-                    continue;
-                };
-                if let Some(conn) = state.connections.get(&conn_id) {
-                    let sender = conn.sender.clone();
-                    out.push((conn_id, sender));
+            hosts
+                .iter()
+                .map(|host| state.host_targets(host))
+                .collect::<Vec<_>>()
+        };
+        let mut sent = 0usize;
+        let mut stale = Vec::<ConnId>::new();
+        for host_targets in targets {
+            for (conn_id, sender) in host_targets {
+                if try_send(&sender, envelope.clone(), conn_id) {
+                    sent += 1;
+                    break;
                 }
+                stale.push(conn_id);
             }
-            out
-        };
-        Self::send_to_many_federation(targets, envelope, self).await
+        }
+        self.remove_stale(stale).await;
+        sent
     }
 
     /// Sends an envelope to all active connections.
@@ -480,12 +901,15 @@ impl FederationWsPool {
     }
 
     fn remove_conn_from_host_index(
-        by_host: &mut HashMap<String, ConnId>,
+        by_host: &mut HashMap<String, HashSet<ConnId>>,
         host: &str,
         conn_id: ConnId,
     ) {
-        if by_host.get(host).copied() == Some(conn_id) {
-            by_host.remove(host);
+        if let Some(conn_ids) = by_host.get_mut(host) {
+            conn_ids.remove(&conn_id);
+            if conn_ids.is_empty() {
+                by_host.remove(host);
+            }
         }
     }
 
@@ -507,26 +931,326 @@ impl FederationWsPool {
     }
 
     async fn send_to_many_federation(
-        targets: Vec<(ConnId, mpsc::UnboundedSender<FederationWsEnvelope>)>,
+        targets: Vec<(ConnId, mpsc::Sender<FederationWsEnvelope>)>,
         envelope: FederationWsEnvelope,
         pool: &FederationWsPool,
     ) -> usize {
         let mut sent = 0usize;
         let mut stale = Vec::<ConnId>::new();
         for (conn_id, sender) in targets {
-            if sender.send(envelope.clone()).is_ok() {
+            if try_send(&sender, envelope.clone(), conn_id) {
                 sent += 1;
             } else {
                 stale.push(conn_id);
             }
         }
-        if !stale.is_empty() {
-            let stale_set = stale.into_iter().collect::<HashSet<_>>();
-            let mut state = pool.inner.write().await;
-            for conn_id in stale_set {
-                let _ = Self::remove_federation_connection(&mut state, conn_id);
-            }
-        }
+        pool.remove_stale(stale).await;
         sent
     }
+
+    async fn remove_stale(&self, stale: Vec<ConnId>) {
+        if stale.is_empty() {
+            return;
+        }
+        let mut state = self.inner.write().await;
+        for conn_id in stale {
+            let _ = Self::remove_federation_connection(&mut state, conn_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runelink_types::ws::{
+        ClientWsReply, EventId, FederationWsReply, RequestId,
+    };
+
+    const LOCALHOST: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    fn pong() -> ClientWsEnvelope {
+        ClientWsEnvelope::Reply {
+            request_id: RequestId::new(),
+            event_id: EventId::new(),
+            reply: ClientWsReply::Pong,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_prunes_connection() {
+        let pool = ClientWsPool::new();
+        let conn_id = ConnId::new();
+        let user = UserRef::new("alice".into(), "example.com".into());
+        let (sender, mut receiver) = mpsc::channel(1);
+        pool.register_connection(conn_id, sender, LOCALHOST).await;
+        pool.authenticate_connection(conn_id, user.clone()).await;
+
+        assert_eq!(pool.send_to_user(&user, pong()).await, 1);
+        // The consumer hasn't drained the first envelope, so this one is
+        // dropped along with the connection.
+        assert_eq!(pool.send_to_user(&user, pong()).await, 0);
+        assert_eq!(pool.authenticated_user_ref(conn_id).await, None);
+        assert!(!pool.send_to_connection(conn_id, pong()).await);
+
+        // The queued envelope is still delivered before the channel closes.
+        assert!(receiver.recv().await.is_some());
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_close_all_closes_every_queue() {
+        let pool = ClientWsPool::new();
+        let user = UserRef::new("alice".into(), "example.com".into());
+        let mut receivers = Vec::new();
+        for _ in 0..2 {
+            let conn_id = ConnId::new();
+            let (sender, receiver) = mpsc::channel(4);
+            pool.register_connection(conn_id, sender, LOCALHOST).await;
+            pool.authenticate_connection(conn_id, user.clone()).await;
+            receivers.push(receiver);
+        }
+        assert_eq!(pool.send_to_user(&user, pong()).await, 2);
+
+        assert_eq!(pool.close_all().await, 2);
+        assert_eq!(pool.send_to_user(&user, pong()).await, 0);
+        for mut receiver in receivers {
+            assert!(receiver.recv().await.is_some());
+            assert!(receiver.recv().await.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drained_queue_keeps_connection() {
+        let pool = ClientWsPool::new();
+        let conn_id = ConnId::new();
+        let (sender, mut receiver) = mpsc::channel(1);
+        pool.register_connection(conn_id, sender, LOCALHOST).await;
+
+        for _ in 0..3 {
+            assert!(pool.send_to_connection(conn_id, pong()).await);
+            assert!(receiver.recv().await.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_to_subscribers_filters_by_channel_and_user() {
+        let pool = ClientWsPool::new();
+        let alice = UserRef::new("alice".into(), "example.com".into());
+        let bob = UserRef::new("bob".into(), "example.com".into());
+        let channel_id = ChannelId::new();
+        let (subscribed, unsubscribed, other_user) =
+            (ConnId::new(), ConnId::new(), ConnId::new());
+        let mut receivers = Vec::new();
+        for (conn_id, user) in [
+            (subscribed, &alice),
+            (unsubscribed, &alice),
+            (other_user, &bob),
+        ] {
+            let (sender, receiver) = mpsc::channel(4);
+            pool.register_connection(conn_id, sender, LOCALHOST).await;
+            pool.authenticate_connection(conn_id, user.clone()).await;
+            receivers.push(receiver);
+        }
+        assert!(pool.subscribe(subscribed, channel_id).await);
+        assert!(pool.subscribe(other_user, channel_id).await);
+
+        // Bob is subscribed but isn't a target of this update.
+        let sent = pool.send_to_subscribers([&alice], channel_id, pong()).await;
+        assert_eq!(sent, 1);
+        assert!(receivers[0].try_recv().is_ok());
+        assert!(receivers[1].try_recv().is_err());
+        assert!(receivers[2].try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribe_and_deregister_clean_channel_index() {
+        let pool = ClientWsPool::new();
+        let user = UserRef::new("alice".into(), "example.com".into());
+        let (first, second) = (ChannelId::new(), ChannelId::new());
+        let conn_id = ConnId::new();
+        let (sender, _receiver) = mpsc::channel(4);
+        pool.register_connection(conn_id, sender, LOCALHOST).await;
+        pool.authenticate_connection(conn_id, user.clone()).await;
+        pool.subscribe(conn_id, first).await;
+        pool.subscribe(conn_id, second).await;
+
+        assert!(pool.unsubscribe(conn_id, first).await);
+        assert!(!pool.unsubscribe(conn_id, first).await);
+        assert_eq!(pool.send_to_subscribers([&user], first, pong()).await, 0);
+        assert!(!pool.inner.read().await.by_channel.contains_key(&first));
+
+        pool.deregister_connection(conn_id).await;
+        assert!(pool.inner.read().await.by_channel.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_presence_changes_on_first_and_last_connection() {
+        let pool = ClientWsPool::new();
+        let mut changes = pool.presence_changes().await;
+        let alice = UserRef::new("alice".into(), "example.com".into());
+        let (first, second) = (ConnId::new(), ConnId::new());
+        for conn_id in [first, second] {
+            let (sender, _receiver) = mpsc::channel(4);
+            pool.register_connection(conn_id, sender, LOCALHOST).await;
+            pool.authenticate_connection(conn_id, alice.clone()).await;
+        }
+        // Re-authenticating as the same user is not a transition.
+        pool.authenticate_connection(first, alice.clone()).await;
+        pool.deregister_connection(first).await;
+        pool.deregister_connection(second).await;
+
+        let online = PresenceChange {
+            user_ref: alice.clone(),
+            online: true,
+        };
+        let offline = PresenceChange {
+            user_ref: alice,
+            online: false,
+        };
+        assert_eq!(changes.try_recv().unwrap(), online);
+        assert_eq!(changes.try_recv().unwrap(), offline);
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_presence_subscribers_receive_once_per_connection() {
+        let pool = ClientWsPool::new();
+        let alice = UserRef::new("alice".into(), "example.com".into());
+        let (first, second) = (ServerId::new(), ServerId::new());
+        let conn_id = ConnId::new();
+        let (sender, mut receiver) = mpsc::channel(4);
+        pool.register_connection(conn_id, sender, LOCALHOST).await;
+        pool.authenticate_connection(conn_id, alice.clone()).await;
+        assert!(pool.subscribe_presence(conn_id, first).await);
+        assert!(pool.subscribe_presence(conn_id, second).await);
+
+        let sent = pool
+            .send_to_presence_subscribers([first, second], pong())
+            .await;
+        assert_eq!(sent, 1);
+        assert!(receiver.try_recv().is_ok());
+        assert_eq!(pool.online_users([&alice]).await, vec![alice]);
+
+        pool.deregister_connection(conn_id).await;
+        assert!(pool.inner.read().await.by_presence_server.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_typing_is_rate_limited_per_connection() {
+        let pool = ClientWsPool::new();
+        let first = ConnId::new();
+        let second = ConnId::new();
+        let (sender, _receiver) = mpsc::channel(1);
+        pool.register_connection(first, sender.clone(), LOCALHOST)
+            .await;
+        pool.register_connection(second, sender, LOCALHOST).await;
+
+        let interval = Duration::seconds(5);
+        assert!(pool.mark_typing(first, interval).await);
+        assert!(!pool.mark_typing(first, interval).await);
+        // Other connections keep their own allowance.
+        assert!(pool.mark_typing(second, interval).await);
+        assert!(pool.mark_typing(first, Duration::ZERO).await);
+        assert!(!pool.mark_typing(ConnId::new(), interval).await);
+    }
+
+    #[tokio::test]
+    async fn test_send_to_user_except_skips_origin() {
+        let pool = ClientWsPool::new();
+        let user = UserRef::new("alice".into(), "example.com".into());
+        let origin = ConnId::new();
+        let other = ConnId::new();
+        let (origin_sender, mut origin_receiver) = mpsc::channel(4);
+        let (other_sender, mut other_receiver) = mpsc::channel(4);
+        pool.register_connection(origin, origin_sender, LOCALHOST)
+            .await;
+        pool.register_connection(other, other_sender, LOCALHOST)
+            .await;
+        pool.authenticate_connection(origin, user.clone()).await;
+        pool.authenticate_connection(other, user.clone()).await;
+
+        assert_eq!(pool.send_to_user_except(&user, origin, pong()).await, 1);
+        assert!(other_receiver.try_recv().is_ok());
+        assert!(origin_receiver.try_recv().is_err());
+    }
+
+    fn federation_reply() -> FederationWsEnvelope {
+        FederationWsEnvelope::Reply {
+            request_id: RequestId::new(),
+            event_id: EventId::new(),
+            reply: FederationWsReply::UsersGetAssociatedHosts(Vec::new()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_host_keeps_every_authenticated_connection() {
+        let pool = FederationWsPool::new();
+        let host = "peer.example".to_string();
+        let (first, second) = (ConnId::new(), ConnId::new());
+        let (first_sender, mut first_receiver) = mpsc::channel(8);
+        let (second_sender, mut second_receiver) = mpsc::channel(8);
+        pool.register_connection(first, first_sender).await;
+        pool.register_connection(second, second_sender).await;
+        assert!(
+            pool.authenticate_connection(first, host.clone(), host.clone())
+                .await
+        );
+        assert!(
+            pool.authenticate_connection(second, host.clone(), host.clone())
+                .await
+        );
+        assert_eq!(
+            pool.host_connection_counts().await,
+            vec![(host.clone(), 2)]
+        );
+
+        // Sends alternate between the host's connections
+        for _ in 0..4 {
+            assert!(pool.send_to_host(&host, federation_reply()).await);
+        }
+        let mut received = [0, 0];
+        while first_receiver.try_recv().is_ok() {
+            received[0] += 1;
+        }
+        while second_receiver.try_recv().is_ok() {
+            received[1] += 1;
+        }
+        assert_eq!(received, [2, 2]);
+
+        // One send per host, however often it's listed
+        assert_eq!(
+            pool.send_to_hosts([&host, &host], federation_reply()).await,
+            1
+        );
+
+        pool.deregister_connection(first).await;
+        assert!(pool.has_host(&host).await);
+        pool.deregister_connection(second).await;
+        assert!(!pool.has_host(&host).await);
+        assert!(!pool.send_to_host(&host, federation_reply()).await);
+    }
+
+    #[tokio::test]
+    async fn test_send_to_host_falls_back_past_closed_connection() {
+        let pool = FederationWsPool::new();
+        let host = "peer.example".to_string();
+        let (closed, open) = (ConnId::new(), ConnId::new());
+        let (closed_sender, closed_receiver) = mpsc::channel(8);
+        let (open_sender, mut open_receiver) = mpsc::channel(8);
+        drop(closed_receiver);
+        pool.register_connection(closed, closed_sender).await;
+        pool.register_connection(open, open_sender).await;
+        pool.authenticate_connection(closed, host.clone(), host.clone())
+            .await;
+        pool.authenticate_connection(open, host.clone(), host.clone())
+            .await;
+
+        for _ in 0..2 {
+            assert!(pool.send_to_host(&host, federation_reply()).await);
+        }
+        assert!(open_receiver.try_recv().is_ok());
+        assert!(open_receiver.try_recv().is_ok());
+        assert_eq!(pool.authenticated_host(closed).await, None);
+        assert_eq!(pool.host_connection_counts().await, vec![(host, 1)]);
+    }
 }
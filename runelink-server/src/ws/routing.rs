@@ -1,13 +1,129 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+    time::Duration,
+};
 
 use runelink_types::{server::ServerId, user::UserRef};
+use tokio::{sync::RwLock, time::Instant};
 
 use crate::{config::ServerConfig, db::DbPool, error::ApiResult, queries};
 
+/// How long a server's members are reused before re-querying. Membership
+/// changes drop the cached entry right away.
+const SERVER_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Servers cached per kind before the oldest entries are evicted.
+const MAX_CACHED_SERVERS: usize = 4096;
+
+/// Local users and other hosts of a local server.
+type LocalServerTargets = (Vec<UserRef>, Vec<String>);
+
+/// Lookups per server that expire after a TTL, holding at most `capacity`
+/// servers.
+///
+/// Each server has a generation that changes whenever it is invalidated, so
+/// a lookup that raced with an invalidation is not cached.
+#[derive(Debug)]
+struct ServerCache<V> {
+    entries: HashMap<ServerId, (Instant, V)>,
+    ttl: Duration,
+    capacity: usize,
+    /// Generations of servers invalidated since the last `clear`
+    generations: HashMap<ServerId, u64>,
+    /// The generation of every other server
+    cleared_generation: u64,
+    next_generation: u64,
+}
+
+impl<V: Clone> ServerCache<V> {
+    fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            ttl,
+            capacity,
+            generations: HashMap::new(),
+            cleared_generation: 0,
+            next_generation: 1,
+        }
+    }
+
+    fn get(&self, server_id: ServerId, now: Instant) -> Option<V> {
+        let (cached_at, value) = self.entries.get(&server_id)?;
+        (now.duration_since(*cached_at) < self.ttl).then(|| value.clone())
+    }
+
+    /// To be read before looking a server up and passed to `insert`.
+    fn generation(&self, server_id: ServerId) -> u64 {
+        self.generations
+            .get(&server_id)
+            .copied()
+            .unwrap_or(self.cleared_generation)
+    }
+
+    fn bump_generation(&mut self) -> u64 {
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        generation
+    }
+
+    /// Caches a lookup, unless the server was invalidated since
+    /// `generation` was read.
+    fn insert(
+        &mut self,
+        server_id: ServerId,
+        generation: u64,
+        value: V,
+        now: Instant,
+    ) {
+        if self.generation(server_id) != generation {
+            return;
+        }
+        if !self.entries.contains_key(&server_id)
+            && self.entries.len() >= self.capacity
+        {
+            let ttl = self.ttl;
+            self.entries.retain(|_, (cached_at, _)| {
+                now.duration_since(*cached_at) < ttl
+            });
+            if self.entries.len() >= self.capacity {
+                let oldest = self
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, (cached_at, _))| *cached_at)
+                    .map(|(server_id, _)| *server_id);
+                if let Some(oldest) = oldest {
+                    self.entries.remove(&oldest);
+                }
+            }
+        }
+        self.entries.insert(server_id, (now, value));
+    }
+
+    fn remove(&mut self, server_id: ServerId) {
+        self.entries.remove(&server_id);
+        let generation = self.bump_generation();
+        self.generations.insert(server_id, generation);
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.generations.clear();
+        self.cleared_generation = self.bump_generation();
+    }
+}
+
+/// Who to route a server's updates to, cached in memory.
+///
+/// Each host in a cluster has its own index, so the caches are never shared.
+/// Code that changes memberships must call `invalidate_server`.
 #[derive(Clone, Debug)]
 pub struct RoutingIndex {
     db_pool: Arc<DbPool>,
     server_config: Arc<ServerConfig>,
+    local_servers: Arc<RwLock<ServerCache<LocalServerTargets>>>,
+    /// Remote server -> local users who are members of it
+    remote_servers: Arc<RwLock<ServerCache<Vec<UserRef>>>>,
 }
 
 impl RoutingIndex {
@@ -15,29 +131,69 @@ impl RoutingIndex {
         Self {
             db_pool,
             server_config,
+            local_servers: Arc::new(RwLock::new(ServerCache::new(
+                SERVER_CACHE_TTL,
+                MAX_CACHED_SERVERS,
+            ))),
+            remote_servers: Arc::new(RwLock::new(ServerCache::new(
+                SERVER_CACHE_TTL,
+                MAX_CACHED_SERVERS,
+            ))),
         }
     }
 }
 
 impl RoutingIndex {
-    /// Get the hosts for a server (excluding the local host).
-    pub async fn hosts_for_server(
+    /// Get the local users and other hosts for a local server, from one
+    /// membership query.
+    pub async fn targets_for_local_server(
         &self,
         server_id: ServerId,
-    ) -> ApiResult<Vec<String>> {
-        let users = queries::memberships::get_user_refs_by_local_server(
+    ) -> ApiResult<LocalServerTargets> {
+        let (cached, generation) = {
+            let cache = self.local_servers.read().await;
+            (
+                cache.get(server_id, Instant::now()),
+                cache.generation(server_id),
+            )
+        };
+        if let Some(targets) = cached {
+            return Ok(targets);
+        }
+        let members = queries::memberships::get_user_refs_by_local_server(
             self.db_pool.as_ref(),
             server_id,
         )
         .await?;
-        let local_host = self.server_config.public_host();
-        let hosts = users
-            .into_iter()
-            .map(|user| user.host)
-            .filter(|host| host != &local_host)
-            .collect::<std::collections::BTreeSet<_>>()
-            .into_iter()
-            .collect::<Vec<_>>();
+        let targets = split_by_host(members, &self.server_config.public_host());
+        self.local_servers.write().await.insert(
+            server_id,
+            generation,
+            targets.clone(),
+            Instant::now(),
+        );
+        Ok(targets)
+    }
+
+    /// Drop what is cached for a server, local or remote, after its members
+    /// changed.
+    pub async fn invalidate_server(&self, server_id: ServerId) {
+        self.local_servers.write().await.remove(server_id);
+        self.remote_servers.write().await.remove(server_id);
+    }
+
+    /// Drop everything cached, e.g. after a user left every server at once.
+    pub async fn invalidate_all(&self) {
+        self.local_servers.write().await.clear();
+        self.remote_servers.write().await.clear();
+    }
+
+    /// Get the hosts for a server (excluding the local host).
+    pub async fn hosts_for_server(
+        &self,
+        server_id: ServerId,
+    ) -> ApiResult<Vec<String>> {
+        let (_, hosts) = self.targets_for_local_server(server_id).await?;
         Ok(hosts)
     }
 
@@ -46,17 +202,8 @@ impl RoutingIndex {
         &self,
         server_id: ServerId,
     ) -> ApiResult<Vec<UserRef>> {
-        let server_users = queries::memberships::get_user_refs_by_local_server(
-            self.db_pool.as_ref(),
-            server_id,
-        )
-        .await?;
-        let local_host = self.server_config.public_host();
-        let local_users = server_users
-            .into_iter()
-            .filter(|user| user.host == local_host)
-            .collect::<Vec<_>>();
-        Ok(local_users)
+        let (users, _) = self.targets_for_local_server(server_id).await?;
+        Ok(users)
     }
 
     /// Get the users for a remote server.
@@ -64,10 +211,124 @@ impl RoutingIndex {
         &self,
         server_id: ServerId,
     ) -> ApiResult<Vec<UserRef>> {
-        queries::memberships::get_user_refs_by_remote_server(
+        let (cached, generation) = {
+            let cache = self.remote_servers.read().await;
+            (
+                cache.get(server_id, Instant::now()),
+                cache.generation(server_id),
+            )
+        };
+        if let Some(users) = cached {
+            return Ok(users);
+        }
+        let users = queries::memberships::get_user_refs_by_remote_server(
             self.db_pool.as_ref(),
             server_id,
         )
-        .await
+        .await?;
+        self.remote_servers.write().await.insert(
+            server_id,
+            generation,
+            users.clone(),
+            Instant::now(),
+        );
+        Ok(users)
+    }
+}
+
+/// Split members into those on `local_host` and the other hosts, sorted
+/// and without repeats.
+fn split_by_host(
+    members: Vec<UserRef>,
+    local_host: &str,
+) -> LocalServerTargets {
+    let mut local_users = Vec::new();
+    let mut remote_hosts = BTreeSet::new();
+    for user_ref in members {
+        if user_ref.host == local_host {
+            local_users.push(user_ref);
+        } else {
+            remote_hosts.insert(user_ref.host);
+        }
+    }
+    (local_users, remote_hosts.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_by_host_dedupes_remote_hosts() {
+        let members = vec![
+            UserRef::new("alice".into(), "local.example".into()),
+            UserRef::new("bob".into(), "remote.example".into()),
+            UserRef::new("carol".into(), "other.example".into()),
+            UserRef::new("dave".into(), "remote.example".into()),
+        ];
+        let (users, hosts) = split_by_host(members, "local.example");
+        assert_eq!(
+            users,
+            vec![UserRef::new("alice".into(), "local.example".into())]
+        );
+        assert_eq!(
+            hosts,
+            vec!["other.example".to_string(), "remote.example".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_server_cache_expires_entries() {
+        let mut cache = ServerCache::new(Duration::from_secs(30), 8);
+        let server_id = ServerId::new();
+        let now = Instant::now();
+        cache.insert(server_id, 0, 1, now);
+        assert_eq!(
+            cache.get(server_id, now + Duration::from_secs(29)),
+            Some(1)
+        );
+        assert_eq!(cache.get(server_id, now + Duration::from_secs(30)), None);
+        cache.remove(server_id);
+        assert_eq!(cache.get(server_id, now), None);
+    }
+
+    #[test]
+    fn test_server_cache_evicts_oldest_when_full() {
+        let mut cache = ServerCache::new(Duration::from_secs(30), 2);
+        let (first, second, third) =
+            (ServerId::new(), ServerId::new(), ServerId::new());
+        let now = Instant::now();
+        cache.insert(first, 0, 1, now);
+        cache.insert(second, 0, 2, now + Duration::from_secs(1));
+        cache.insert(third, 0, 3, now + Duration::from_secs(2));
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(cache.get(first, now), None);
+        assert_eq!(cache.get(second, now + Duration::from_secs(2)), Some(2));
+        assert_eq!(cache.get(third, now + Duration::from_secs(2)), Some(3));
+    }
+
+    #[test]
+    fn test_server_cache_skips_lookups_that_raced_an_invalidation() {
+        let mut cache = ServerCache::new(Duration::from_secs(30), 8);
+        let (server_id, other_id) = (ServerId::new(), ServerId::new());
+        let now = Instant::now();
+
+        let generation = cache.generation(server_id);
+        let other_generation = cache.generation(other_id);
+        cache.remove(server_id);
+        cache.insert(server_id, generation, 1, now);
+        assert_eq!(cache.get(server_id, now), None);
+        // Other servers are unaffected
+        cache.insert(other_id, other_generation, 2, now);
+        assert_eq!(cache.get(other_id, now), Some(2));
+
+        let generation = cache.generation(server_id);
+        cache.clear();
+        cache.insert(server_id, generation, 3, now);
+        assert_eq!(cache.get(server_id, now), None);
+
+        let generation = cache.generation(server_id);
+        cache.insert(server_id, generation, 4, now);
+        assert_eq!(cache.get(server_id, now), Some(4));
     }
 }
@@ -0,0 +1,75 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+};
+
+use runelink_types::ids::EventId;
+use tokio::sync::Mutex;
+
+/// The most recent event ids received over federation, so an update that
+/// reaches this host twice (for example by bouncing between hosts that
+/// relay to each other) is only applied once.
+#[derive(Clone, Debug)]
+pub struct SeenEvents {
+    inner: Arc<Mutex<SeenEventsInner>>,
+}
+
+#[derive(Debug)]
+struct SeenEventsInner {
+    ids: HashSet<EventId>,
+    /// Oldest first; the front is evicted once `capacity` is reached.
+    order: VecDeque<EventId>,
+    capacity: usize,
+}
+
+impl SeenEvents {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(SeenEventsInner {
+                ids: HashSet::with_capacity(capacity),
+                order: VecDeque::with_capacity(capacity),
+                capacity,
+            })),
+        }
+    }
+
+    /// Records an event id, returning false if it was already seen.
+    pub async fn insert(&self, event_id: EventId) -> bool {
+        let mut inner = self.inner.lock().await;
+        if inner.ids.contains(&event_id) {
+            return false;
+        }
+        if inner.order.len() >= inner.capacity
+            && let Some(oldest) = inner.order.pop_front()
+        {
+            inner.ids.remove(&oldest);
+        }
+        inner.ids.insert(event_id);
+        inner.order.push_back(event_id);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_repeated_event_is_rejected() {
+        let seen = SeenEvents::new(4);
+        let event_id = EventId::new();
+        assert!(seen.insert(event_id).await);
+        assert!(!seen.insert(event_id).await);
+        assert!(seen.insert(EventId::new()).await);
+    }
+
+    #[tokio::test]
+    async fn test_oldest_event_is_forgotten_at_capacity() {
+        let seen = SeenEvents::new(2);
+        let first = EventId::new();
+        seen.insert(first).await;
+        seen.insert(EventId::new()).await;
+        seen.insert(EventId::new()).await;
+        assert!(seen.insert(first).await);
+    }
+}
@@ -1,47 +1,201 @@
+use std::net::{IpAddr, SocketAddr};
+
 use axum::{
     extract::{
-        State,
+        ConnectInfo, State,
         ws::{Message as AxumMessage, WebSocket, WebSocketUpgrade},
     },
-    http::HeaderMap,
-    response::IntoResponse,
+    http::{HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
 };
 use futures_util::{SinkExt, StreamExt};
 use runelink_client::util::host_from_issuer;
 use runelink_types::{
+    ids::RequestId,
     user::UserRef,
-    ws::{ClientWsEnvelope, FederationWsEnvelope},
+    ws::{
+        ClientWsEnvelope, FederationWsEnvelope, WS_JSON_SUBPROTOCOL,
+        WS_MSGPACK_SUBPROTOCOL, WS_PROTOCOL_VERSION,
+        WS_PROTOCOL_VERSION_HEADER, WsError, WsErrorCode,
+        is_supported_protocol_version,
+    },
+};
+use serde_json::{Value, json};
+use tokio::{
+    net::TcpStream,
+    sync::mpsc,
+    time::{Instant, Interval, MissedTickBehavior},
 };
-use tokio::{net::TcpStream, sync::mpsc};
 use tokio_tungstenite::{
     MaybeTlsStream, WebSocketStream,
     tungstenite::protocol::Message as WsMessage,
 };
 
-use super::handlers::{handle_client_message, handle_federation_message};
-use crate::{auth::Principal, ids::ConnId, state::AppState};
+use super::{
+    encoding::{WsEncoding, WsFrame},
+    handlers::{handle_client_message, handle_federation_message},
+};
+use crate::{
+    auth::Principal,
+    error::{ApiError, ApiResult},
+    ids::ConnId,
+    ops,
+    state::AppState,
+};
 
 pub enum FederationSocket {
-    Inbound(WebSocket),
-    Outbound(WebSocketStream<MaybeTlsStream<TcpStream>>),
+    Inbound(Box<WebSocket>),
+    Outbound(Box<WebSocketStream<MaybeTlsStream<TcpStream>>>),
 }
 
 enum FederationIncomingEvent {
-    Text(String),
+    Frame(WsFrame),
     Closed,
     Ignored,
     Error(String),
 }
 
+/// Oversized messages a connection may send before it is closed.
+const MAX_OVERSIZED_FRAMES: u32 = 3;
+
+/// How far past `max_ws_frame_bytes` the transport still reads a message, so
+/// a moderately oversized one can be answered with an error. Bigger ones are
+/// cut off before they are buffered.
+const TRANSPORT_LIMIT_FACTOR: usize = 4;
+
+/// The largest message or frame the websocket transport reads.
+pub(super) fn transport_limit(state: &AppState) -> usize {
+    state
+        .config
+        .max_ws_frame_bytes
+        .saturating_mul(TRANSPORT_LIMIT_FACTOR)
+}
+
+/// The error sent back for a message over `max_ws_frame_bytes`.
+fn payload_too_large(size: usize, max: usize) -> WsError {
+    WsError {
+        code: WsErrorCode::PayloadTooLarge,
+        message: format!(
+            "Websocket message of {size} bytes is over the {max} byte limit"
+        ),
+        details: Some(json!({ "size": size, "max": max })),
+    }
+}
+
+/// The error sent back for a frame that doesn't parse as an envelope, and
+/// the request id to echo if the frame had a readable one.
+///
+/// Returns `None` for frames that were themselves errors, so two peers that
+/// can't parse each other's errors don't bounce them back and forth.
+fn malformed_envelope(
+    payload: &WsFrame,
+    error: &str,
+) -> Option<(Option<RequestId>, WsError)> {
+    // Indexing a missing key (or a non-object) yields Null
+    let frame = payload.decode::<Value>().unwrap_or(Value::Null);
+    if frame["type"] == "error" {
+        return None;
+    }
+    let request_id = serde_json::from_value::<RequestId>(
+        frame["data"]["request_id"].clone(),
+    )
+    .ok();
+    Some((
+        request_id,
+        WsError {
+            code: WsErrorCode::MalformedEnvelope,
+            message: "Could not parse websocket message".into(),
+            details: Some(json!({ "error": error })),
+        },
+    ))
+}
+
+/// Periodic pings plus an idle deadline that any inbound frame pushes back.
+struct Heartbeat {
+    interval: Interval,
+    idle_timeout: std::time::Duration,
+    last_seen: Instant,
+}
+
+impl Heartbeat {
+    fn new(state: &AppState) -> Self {
+        let period = state.config.ws_ping_interval;
+        let mut interval =
+            tokio::time::interval_at(Instant::now() + period, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Self {
+            interval,
+            idle_timeout: state.config.ws_idle_timeout,
+            last_seen: Instant::now(),
+        }
+    }
+
+    async fn tick(&mut self) {
+        self.interval.tick().await;
+    }
+
+    fn saw_frame(&mut self) {
+        self.last_seen = Instant::now();
+    }
+
+    fn timed_out(&self) -> bool {
+        self.last_seen.elapsed() > self.idle_timeout
+    }
+}
+
+impl From<WsFrame> for AxumMessage {
+    fn from(frame: WsFrame) -> Self {
+        match frame {
+            WsFrame::Text(payload) => AxumMessage::Text(payload.into()),
+            WsFrame::Binary(payload) => AxumMessage::Binary(payload.into()),
+        }
+    }
+}
+
+impl From<WsFrame> for WsMessage {
+    fn from(frame: WsFrame) -> Self {
+        match frame {
+            WsFrame::Text(payload) => WsMessage::Text(payload.into()),
+            WsFrame::Binary(payload) => WsMessage::Binary(payload.into()),
+        }
+    }
+}
+
 impl FederationSocket {
-    async fn send_text(&mut self, payload: String) -> Result<(), String> {
+    async fn send_frame(&mut self, frame: WsFrame) -> Result<(), String> {
+        match self {
+            FederationSocket::Inbound(socket) => socket
+                .send(frame.into())
+                .await
+                .map_err(|error| error.to_string()),
+            FederationSocket::Outbound(socket) => socket
+                .send(frame.into())
+                .await
+                .map_err(|error| error.to_string()),
+        }
+    }
+
+    async fn send_close(&mut self) -> Result<(), String> {
         match self {
             FederationSocket::Inbound(socket) => socket
-                .send(AxumMessage::Text(payload.into()))
+                .send(AxumMessage::Close(None))
                 .await
                 .map_err(|error| error.to_string()),
             FederationSocket::Outbound(socket) => socket
-                .send(WsMessage::Text(payload.into()))
+                .send(WsMessage::Close(None))
+                .await
+                .map_err(|error| error.to_string()),
+        }
+    }
+
+    async fn send_ping(&mut self) -> Result<(), String> {
+        match self {
+            FederationSocket::Inbound(socket) => socket
+                .send(AxumMessage::Ping(Default::default()))
+                .await
+                .map_err(|error| error.to_string()),
+            FederationSocket::Outbound(socket) => socket
+                .send(WsMessage::Ping(Default::default()))
                 .await
                 .map_err(|error| error.to_string()),
         }
@@ -51,13 +205,19 @@ impl FederationSocket {
         match self {
             FederationSocket::Inbound(socket) => match socket.recv().await {
                 Some(Ok(AxumMessage::Text(payload))) => {
-                    FederationIncomingEvent::Text(payload.to_string())
+                    FederationIncomingEvent::Frame(WsFrame::Text(
+                        payload.to_string(),
+                    ))
+                }
+                Some(Ok(AxumMessage::Binary(payload))) => {
+                    FederationIncomingEvent::Frame(WsFrame::Binary(
+                        payload.to_vec(),
+                    ))
                 }
                 Some(Ok(AxumMessage::Close(_))) | None => {
                     FederationIncomingEvent::Closed
                 }
-                Some(Ok(AxumMessage::Binary(_)))
-                | Some(Ok(AxumMessage::Ping(_)))
+                Some(Ok(AxumMessage::Ping(_)))
                 | Some(Ok(AxumMessage::Pong(_))) => {
                     FederationIncomingEvent::Ignored
                 }
@@ -67,13 +227,19 @@ impl FederationSocket {
             },
             FederationSocket::Outbound(socket) => match socket.next().await {
                 Some(Ok(WsMessage::Text(payload))) => {
-                    FederationIncomingEvent::Text(payload.to_string())
+                    FederationIncomingEvent::Frame(WsFrame::Text(
+                        payload.to_string(),
+                    ))
+                }
+                Some(Ok(WsMessage::Binary(payload))) => {
+                    FederationIncomingEvent::Frame(WsFrame::Binary(
+                        payload.to_vec(),
+                    ))
                 }
                 Some(Ok(WsMessage::Close(_))) | None => {
                     FederationIncomingEvent::Closed
                 }
-                Some(Ok(WsMessage::Binary(_)))
-                | Some(Ok(WsMessage::Ping(_)))
+                Some(Ok(WsMessage::Ping(_)))
                 | Some(Ok(WsMessage::Pong(_)))
                 | Some(Ok(WsMessage::Frame(_))) => {
                     FederationIncomingEvent::Ignored
@@ -86,53 +252,125 @@ impl FederationSocket {
     }
 }
 
+/// Accepts the encoding subprotocols, preferring whichever the peer lists
+/// first, and returns the encoding that was agreed on.
+fn negotiate_encoding(ws: WebSocketUpgrade) -> (WebSocketUpgrade, WsEncoding) {
+    let ws = ws.protocols([WS_MSGPACK_SUBPROTOCOL, WS_JSON_SUBPROTOCOL]);
+    let encoding = WsEncoding::from_subprotocol(
+        ws.selected_protocol().map(HeaderValue::as_bytes),
+    );
+    (ws, encoding)
+}
+
 pub async fn client_ws(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| client_ws_loop(state, headers, socket))
+    let (ws, encoding) = negotiate_encoding(ws);
+    let limit = transport_limit(&state);
+    ws.max_message_size(limit)
+        .max_frame_size(limit)
+        .on_upgrade(move |socket| {
+            client_ws_loop(state, headers, peer.ip(), encoding, socket)
+        })
+}
+
+/// The protocol version a federation peer advertised on its handshake.
+///
+/// Peers that predate the header speak version 1.
+pub(super) fn peer_protocol_version(headers: &HeaderMap) -> ApiResult<u32> {
+    let Some(value) = headers.get(WS_PROTOCOL_VERSION_HEADER) else {
+        return Ok(1);
+    };
+    let version = value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse::<u32>().ok())
+        .ok_or_else(|| {
+            ApiError::BadRequest(format!(
+                "Invalid {WS_PROTOCOL_VERSION_HEADER} header"
+            ))
+        })?;
+    if !is_supported_protocol_version(version) {
+        return Err(ApiError::UnsupportedProtocol(version));
+    }
+    Ok(version)
 }
 
 pub async fn federation_ws(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
     headers: HeaderMap,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| {
-        federation_ws_upgrade_loop(state, headers, socket)
-    })
+) -> ApiResult<Response> {
+    peer_protocol_version(&headers)?;
+    let (ws, encoding) = negotiate_encoding(ws);
+    let limit = transport_limit(&state);
+    let mut response = ws
+        .max_message_size(limit)
+        .max_frame_size(limit)
+        .on_upgrade(move |socket| {
+            federation_ws_upgrade_loop(state, headers, encoding, socket)
+        })
+        .into_response();
+    response.headers_mut().insert(
+        WS_PROTOCOL_VERSION_HEADER,
+        HeaderValue::from(WS_PROTOCOL_VERSION),
+    );
+    Ok(response)
 }
 
 async fn client_ws_loop(
     state: AppState,
     headers: HeaderMap,
+    peer_ip: IpAddr,
+    encoding: WsEncoding,
     mut socket: WebSocket,
 ) {
-    let (sender, mut outbound_rx) =
-        mpsc::unbounded_channel::<ClientWsEnvelope>();
-    let conn_id = state.client_ws_manager.register_connection(sender).await;
+    let _open = state.open_sockets.open();
+    let (sender, mut outbound_rx) = mpsc::channel::<ClientWsEnvelope>(
+        state.config.ws_outbound_queue_capacity,
+    );
+    let conn_id = state
+        .client_ws_manager
+        .register_connection(sender, peer_ip)
+        .await;
 
     if let Ok(Principal::Client(auth)) =
         Principal::from_client_headers(&headers, &state)
+        && let Some(user_ref) = UserRef::parse_subject(&auth.claims.sub)
     {
-        if let Some(user_ref) = UserRef::parse_subject(&auth.claims.sub) {
-            let _ = state
-                .client_ws_manager
-                .authenticate_connection(conn_id, user_ref)
-                .await;
-        }
+        let _ = state
+            .client_ws_manager
+            .authenticate_connection(conn_id, user_ref)
+            .await;
     }
 
+    let max_frame_bytes = state.config.max_ws_frame_bytes;
+    let mut oversized = 0;
+    let mut heartbeat = Heartbeat::new(&state);
     loop {
         tokio::select! {
+            _ = heartbeat.tick() => {
+                if heartbeat.timed_out() {
+                    log::info!("Client websocket {conn_id:?} idle, closing");
+                    break;
+                }
+                if let Err(error) = socket.send(AxumMessage::Ping(Default::default())).await {
+                    log::warn!("Client websocket ping error: {error}");
+                    break;
+                }
+            }
             outbound = outbound_rx.recv() => {
+                // The pool dropped this connection (or is shutting down)
                 let Some(envelope) = outbound else {
+                    let _ = socket.send(AxumMessage::Close(None)).await;
                     break;
                 };
-                match serde_json::to_string(&envelope) {
-                    Ok(payload) => {
-                        if let Err(error) = socket.send(AxumMessage::Text(payload.into())).await {
+                match encoding.encode(&envelope) {
+                    Ok(frame) => {
+                        if let Err(error) = socket.send(frame.into()).await {
                             log::warn!("Client websocket send error: {error}");
                             break;
                         }
@@ -143,21 +381,44 @@ async fn client_ws_loop(
                 }
             }
             incoming = socket.recv() => {
-                match incoming {
-                    Some(Ok(AxumMessage::Text(payload))) => {
-                        match serde_json::from_str::<ClientWsEnvelope>(&payload) {
-                            Ok(message) => handle_client_message(&state, conn_id, message).await,
-                            Err(error) => {
-                                log::warn!("Failed to parse client websocket message: {error}");
-                            }
-                        }
-                    }
+                if let Some(Ok(_)) = incoming {
+                    heartbeat.saw_frame();
+                }
+                let payload = match incoming {
+                    Some(Ok(AxumMessage::Text(payload))) => WsFrame::Text(payload.to_string()),
+                    Some(Ok(AxumMessage::Binary(payload))) => WsFrame::Binary(payload.to_vec()),
                     Some(Ok(AxumMessage::Close(_))) | None => break,
-                    Some(Ok(AxumMessage::Binary(_))) | Some(Ok(AxumMessage::Ping(_))) | Some(Ok(AxumMessage::Pong(_))) => {}
+                    Some(Ok(AxumMessage::Ping(_))) | Some(Ok(AxumMessage::Pong(_))) => continue,
                     Some(Err(error)) => {
                         log::warn!("Client websocket receive error: {error}");
                         break;
                     }
+                };
+                if payload.size() > max_frame_bytes {
+                    log::warn!("Client websocket {conn_id:?} sent an oversized message ({} bytes)", payload.size());
+                    let error = payload_too_large(payload.size(), max_frame_bytes);
+                    let _ = state
+                        .client_ws_manager
+                        .send_error_to_connection(conn_id, None, error)
+                        .await;
+                    oversized += 1;
+                    if oversized >= MAX_OVERSIZED_FRAMES {
+                        log::info!("Closing client websocket {conn_id:?} after repeated oversized messages");
+                        break;
+                    }
+                    continue;
+                }
+                match payload.decode::<ClientWsEnvelope>() {
+                    Ok(message) => handle_client_message(&state, conn_id, message).await,
+                    Err(error) => {
+                        log::warn!("Failed to parse client websocket message: {error}");
+                        if let Some((request_id, error)) = malformed_envelope(&payload, &error) {
+                            let _ = state
+                                .client_ws_manager
+                                .send_error_to_connection(conn_id, request_id, error)
+                                .await;
+                        }
+                    }
                 }
             }
         }
@@ -169,10 +430,12 @@ async fn client_ws_loop(
 async fn federation_ws_upgrade_loop(
     state: AppState,
     headers: HeaderMap,
+    encoding: WsEncoding,
     socket: WebSocket,
 ) {
-    let (sender, outbound_rx) =
-        mpsc::unbounded_channel::<FederationWsEnvelope>();
+    let (sender, outbound_rx) = mpsc::channel::<FederationWsEnvelope>(
+        state.config.ws_outbound_queue_capacity,
+    );
     let conn_id = state
         .federation_ws_manager
         .register_connection(sender)
@@ -182,17 +445,22 @@ async fn federation_ws_upgrade_loop(
         Principal::from_federation_headers(&headers, &state).await
     {
         let host = host_from_issuer(&auth.claims.iss);
-        let issuer = auth.claims.iss.clone();
-        let _ = state
-            .federation_ws_manager
-            .authenticate_connection(conn_id, host, issuer)
-            .await;
+        if state.config.is_federation_allowed(&host) {
+            let issuer = auth.claims.iss.clone();
+            let _ = state
+                .federation_ws_manager
+                .authenticate_connection(conn_id, host, issuer)
+                .await;
+        } else {
+            log::info!("Refusing federation connection from {host}");
+        }
     }
 
     federation_socket_loop(
         state,
         conn_id,
-        FederationSocket::Inbound(socket),
+        FederationSocket::Inbound(Box::new(socket)),
+        encoding,
         outbound_rx,
     )
     .await;
@@ -202,17 +470,34 @@ pub async fn federation_socket_loop(
     state: AppState,
     conn_id: ConnId,
     mut socket: FederationSocket,
-    mut outbound_rx: mpsc::UnboundedReceiver<FederationWsEnvelope>,
+    encoding: WsEncoding,
+    mut outbound_rx: mpsc::Receiver<FederationWsEnvelope>,
 ) {
+    let _open = state.open_sockets.open();
+    let max_frame_bytes = state.config.max_ws_frame_bytes;
+    let mut oversized = 0;
+    let mut heartbeat = Heartbeat::new(&state);
     loop {
         tokio::select! {
+            _ = heartbeat.tick() => {
+                if heartbeat.timed_out() {
+                    log::info!("Federation websocket {conn_id:?} idle, closing");
+                    break;
+                }
+                if let Err(error) = socket.send_ping().await {
+                    log::warn!("Federation websocket ping error: {error}");
+                    break;
+                }
+            }
             outbound = outbound_rx.recv() => {
+                // The pool dropped this connection (or is shutting down)
                 let Some(envelope) = outbound else {
+                    let _ = socket.send_close().await;
                     break;
                 };
-                match serde_json::to_string(&envelope) {
-                    Ok(payload) => {
-                        if let Err(error) = socket.send_text(payload).await {
+                match encoding.encode(&envelope) {
+                    Ok(frame) => {
+                        if let Err(error) = socket.send_frame(frame).await {
                             log::warn!("Federation websocket send error: {error}");
                             break;
                         }
@@ -223,14 +508,40 @@ pub async fn federation_socket_loop(
                 }
             }
             incoming = socket.recv_event() => {
+                if !matches!(
+                    incoming,
+                    FederationIncomingEvent::Closed | FederationIncomingEvent::Error(_)
+                ) {
+                    heartbeat.saw_frame();
+                }
                 match incoming {
-                    FederationIncomingEvent::Text(payload) => {
-                        match serde_json::from_str::<FederationWsEnvelope>(&payload) {
+                    FederationIncomingEvent::Frame(payload) => {
+                        if payload.size() > max_frame_bytes {
+                            log::warn!("Federation websocket {conn_id:?} sent an oversized message ({} bytes)", payload.size());
+                            let error = payload_too_large(payload.size(), max_frame_bytes);
+                            let _ = state
+                                .federation_ws_manager
+                                .send_error_to_connection(conn_id, None, error)
+                                .await;
+                            oversized += 1;
+                            if oversized >= MAX_OVERSIZED_FRAMES {
+                                log::info!("Closing federation websocket {conn_id:?} after repeated oversized messages");
+                                break;
+                            }
+                            continue;
+                        }
+                        match payload.decode::<FederationWsEnvelope>() {
                             Ok(message) => {
                                 handle_federation_message(&state, conn_id, message).await;
                             }
                             Err(error) => {
                                 log::warn!("Failed to parse federation websocket message: {error}");
+                                if let Some((request_id, error)) = malformed_envelope(&payload, &error) {
+                                    let _ = state
+                                        .federation_ws_manager
+                                        .send_error_to_connection(conn_id, request_id, error)
+                                        .await;
+                                }
                             }
                         }
                     }
@@ -245,8 +556,121 @@ pub async fn federation_socket_loop(
         }
     }
 
+    let host = state
+        .federation_ws_manager
+        .authenticated_host(conn_id)
+        .await;
     let _ = state
         .federation_ws_manager
         .deregister_connection(conn_id)
         .await;
+    // Presence reported over this connection still holds while the host has
+    // other connections open
+    if let Some(host) = host
+        && !state.federation_ws_manager.has_host(&host).await
+    {
+        ops::presence::forget_host(&state, &host).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(payload: &WsFrame) -> String {
+        payload.decode::<ClientWsEnvelope>().unwrap_err()
+    }
+
+    #[test]
+    fn test_malformed_envelope_echoes_request_id() {
+        let request_id = RequestId::new();
+        let payload = WsFrame::Text(
+            json!({
+                "type": "request",
+                "data": {
+                    "request_id": request_id,
+                    "request": { "type": "nope" },
+                },
+            })
+            .to_string(),
+        );
+        let (echoed, error) =
+            malformed_envelope(&payload, &parse_error(&payload)).unwrap();
+        assert_eq!(echoed, Some(request_id));
+        assert_eq!(error.code, WsErrorCode::MalformedEnvelope);
+        assert!(error.details.unwrap()["error"].is_string());
+    }
+
+    #[test]
+    fn test_malformed_msgpack_envelope_echoes_request_id() {
+        let request_id = RequestId::new();
+        let payload = WsEncoding::MessagePack
+            .encode(&json!({
+                "type": "request",
+                "data": {
+                    "request_id": request_id,
+                    "request": { "type": "nope" },
+                },
+            }))
+            .unwrap();
+        let (echoed, _) =
+            malformed_envelope(&payload, &parse_error(&payload)).unwrap();
+        assert_eq!(echoed, Some(request_id));
+    }
+
+    #[test]
+    fn test_payload_too_large_reports_the_limit() {
+        let payload = WsFrame::Text("x".repeat(10));
+        let error = payload_too_large(payload.size(), 4);
+        assert_eq!(error.code, WsErrorCode::PayloadTooLarge);
+        assert_eq!(error.details, Some(json!({ "size": 10, "max": 4 })));
+    }
+
+    #[test]
+    fn test_peer_protocol_version_defaults_and_rejects() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(peer_protocol_version(&headers).unwrap(), 1);
+
+        headers.insert(
+            WS_PROTOCOL_VERSION_HEADER,
+            HeaderValue::from(WS_PROTOCOL_VERSION),
+        );
+        assert_eq!(
+            peer_protocol_version(&headers).unwrap(),
+            WS_PROTOCOL_VERSION
+        );
+
+        headers.insert(
+            WS_PROTOCOL_VERSION_HEADER,
+            HeaderValue::from(WS_PROTOCOL_VERSION + 1),
+        );
+        assert!(matches!(
+            peer_protocol_version(&headers),
+            Err(ApiError::UnsupportedProtocol(_))
+        ));
+
+        headers.insert(
+            WS_PROTOCOL_VERSION_HEADER,
+            HeaderValue::from_static("two"),
+        );
+        assert!(matches!(
+            peer_protocol_version(&headers),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_malformed_envelope_without_request_id() {
+        let payload = WsFrame::Text("{not json".into());
+        let (echoed, _) =
+            malformed_envelope(&payload, &parse_error(&payload)).unwrap();
+        assert_eq!(echoed, None);
+    }
+
+    #[test]
+    fn test_malformed_error_is_not_answered() {
+        let payload =
+            WsFrame::Text(json!({ "type": "error", "data": {} }).to_string());
+        assert!(malformed_envelope(&payload, &parse_error(&payload)).is_none());
+    }
 }
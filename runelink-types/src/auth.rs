@@ -3,7 +3,7 @@ use rand::{RngCore, rngs::OsRng};
 use serde::{Deserialize, Serialize};
 use time::{Duration, OffsetDateTime};
 
-use crate::user::UserRef;
+use crate::user::{User, UserRef, UserRole};
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
@@ -23,6 +23,23 @@ pub struct SignupRequest {
     pub password: String,
 }
 
+/// Admin request to create a local account, e.g. when signups are disabled.
+///
+/// If `password` is omitted, a temporary one is generated and returned.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AdminCreateUserRequest {
+    pub name: String,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AdminCreateUserResponse {
+    pub user: User,
+    /// Set only when the server generated the password.
+    pub temporary_password: Option<String>,
+}
+
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
 pub struct RefreshToken {
@@ -30,6 +47,8 @@ pub struct RefreshToken {
     pub user_name: String,
     pub user_host: String,
     pub client_id: String,
+    /// Space-separated scopes granted at login; refreshes never widen them
+    pub scope: String,
     #[serde(with = "time::serde::rfc3339")]
     pub issued_at: OffsetDateTime,
     #[serde(with = "time::serde::rfc3339")]
@@ -37,6 +56,17 @@ pub struct RefreshToken {
     pub revoked: bool,
 }
 
+/// An active refresh token, as shown to its user; never includes the token.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct SessionInfo {
+    pub client_id: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub issued_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+}
+
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct TokenRequest {
     pub grant_type: String,
@@ -69,6 +99,16 @@ pub struct OidcDiscoveryDocument {
     pub token_endpoint_auth_methods_supported: Vec<String>,
 }
 
+/// Claims about the authenticated user returned from /auth/userinfo
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserinfoResponse {
+    /// Subject identifier for the user ("name@host")
+    pub sub: String,
+    pub name: String,
+    pub host: String,
+    pub role: UserRole,
+}
+
 /// JWKS response returned from /.well-known/jwks.json
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct JwksResponse {
@@ -96,6 +136,7 @@ impl RefreshToken {
     pub fn new(
         user_ref: UserRef,
         client_id: String,
+        scope: String,
         lifetime: Duration,
     ) -> Self {
         let mut bytes = [0u8; 32]; // 256 bits
@@ -107,6 +148,7 @@ impl RefreshToken {
             user_name: user_ref.name,
             user_host: user_ref.host,
             client_id,
+            scope,
             issued_at: now,
             expires_at: now + lifetime,
             revoked: false,
@@ -184,6 +226,13 @@ impl ClientAccessClaims {
             client_id,
         }
     }
+
+    /// Whether `scope` is one of the token's space-separated scopes.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scope
+            .split_whitespace()
+            .any(|granted| granted == scope)
+    }
 }
 
 /// JWT claims used for server-to-server federation requests.
@@ -266,6 +315,27 @@ impl std::fmt::Debug for SignupRequest {
     }
 }
 
+impl std::fmt::Debug for AdminCreateUserRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let password = self.password.as_ref().map(|_| "[REDACTED]");
+        f.debug_struct("AdminCreateUserRequest")
+            .field("name", &self.name)
+            .field("password", &password)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for AdminCreateUserResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let temporary_password =
+            self.temporary_password.as_ref().map(|_| "[REDACTED]");
+        f.debug_struct("AdminCreateUserResponse")
+            .field("user", &self.user)
+            .field("temporary_password", &temporary_password)
+            .finish()
+    }
+}
+
 impl std::fmt::Debug for AuthTokenPasswordRequest {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("AuthTokenPasswordRequest")
@@ -294,6 +364,7 @@ impl std::fmt::Debug for RefreshToken {
             .field("user_name", &self.user_name)
             .field("user_host", &self.user_host)
             .field("client_id", &self.client_id)
+            .field("scope", &self.scope)
             .field("issued_at", &self.issued_at)
             .field("expires_at", &self.expires_at)
             .finish()
@@ -329,7 +400,10 @@ impl std::fmt::Debug for TokenResponse {
 
 #[cfg(test)]
 mod tests {
-    use super::{AuthTokenPasswordRequest, AuthTokenRefreshRequest};
+    use super::{
+        AuthTokenPasswordRequest, AuthTokenRefreshRequest, ClientAccessClaims,
+    };
+    use crate::user::UserRef;
 
     #[test]
     fn auth_token_password_request_debug_redacts_password() {
@@ -359,4 +433,20 @@ mod tests {
         assert!(debug.contains("[REDACTED]"));
         assert!(!debug.contains("secret-refresh-token"));
     }
+
+    #[test]
+    fn client_access_claims_has_scope() {
+        let claims = ClientAccessClaims::new(
+            &UserRef::new("connor".into(), "example.com".into()),
+            "client-1".into(),
+            "https://example.com".into(),
+            "openid  messages.read".into(),
+            time::Duration::hours(1),
+        );
+
+        assert!(claims.has_scope("openid"));
+        assert!(claims.has_scope("messages.read"));
+        assert!(!claims.has_scope("messages.write"));
+        assert!(!claims.has_scope("messages"));
+    }
 }
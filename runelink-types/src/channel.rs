@@ -2,9 +2,16 @@ use serde::{Deserialize, Serialize};
 use std::fmt;
 use time::OffsetDateTime;
 
-use crate::ids::ServerId;
+use crate::{
+    ids::{MessageId, ServerId},
+    user::UserRef,
+    validation::{
+        ValidationError, validate_category, validate_description,
+        validate_title,
+    },
+};
 
-pub use crate::ids::ChannelId;
+pub use crate::ids::{ChannelId, WebhookId};
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
@@ -13,6 +20,16 @@ pub struct Channel {
     pub server_id: ServerId,
     pub title: String,
     pub description: Option<String>,
+    /// Seconds members must wait between their messages, if slow mode is
+    /// on.
+    #[serde(default)]
+    pub slow_mode_secs: Option<i32>,
+    /// Where the channel sits within its category, lowest first.
+    #[serde(default)]
+    pub position: i32,
+    /// Channels without a category are listed before any category.
+    #[serde(default)]
+    pub category: Option<String>,
     #[serde(with = "time::serde::rfc3339")]
     pub created_at: OffsetDateTime,
     #[serde(with = "time::serde::rfc3339")]
@@ -23,6 +40,51 @@ pub struct Channel {
 pub struct NewChannel {
     pub title: String,
     pub description: Option<String>,
+    #[serde(default)]
+    pub slow_mode_secs: Option<i32>,
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+impl NewChannel {
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        validate_title(&self.title)?;
+        validate_description(self.description.as_deref())?;
+        validate_category(self.category.as_deref())
+    }
+}
+
+/// A partial channel update; `None` fields are left unchanged.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChannelUpdate {
+    #[serde(default)]
+    pub title: Option<String>,
+    /// An empty description clears it.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Zero turns slow mode off.
+    #[serde(default)]
+    pub slow_mode_secs: Option<i32>,
+}
+
+/// How far a user has read in a channel.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChannelReadState {
+    pub server_id: ServerId,
+    pub channel_id: ChannelId,
+    pub user_ref: UserRef,
+    pub last_read_message_id: MessageId,
+    /// When the last read message was sent.
+    #[serde(with = "time::serde::rfc3339")]
+    pub last_read_at: OffsetDateTime,
+}
+
+/// Messages in a channel newer than the user's read position, not counting
+/// their own.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChannelUnreadCount {
+    pub channel_id: ChannelId,
+    pub unread: i64,
 }
 
 impl Channel {
@@ -40,3 +102,37 @@ impl fmt::Display for Channel {
         }
     }
 }
+
+/// An incoming webhook that posts to a channel.
+///
+/// Anyone holding the id and token can post, so the token is only returned
+/// when the webhook is created.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChannelWebhook {
+    pub id: WebhookId,
+    pub server_id: ServerId,
+    pub channel_id: ChannelId,
+    pub token: String,
+    pub created_by: UserRef,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+impl fmt::Debug for ChannelWebhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChannelWebhook")
+            .field("id", &self.id)
+            .field("server_id", &self.server_id)
+            .field("channel_id", &self.channel_id)
+            .field("token", &"[REDACTED]")
+            .field("created_by", &self.created_by)
+            .field("created_at", &self.created_at)
+            .finish()
+    }
+}
+
+/// The JSON body external systems POST to a webhook.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WebhookPayload {
+    pub body: String,
+}
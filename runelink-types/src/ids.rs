@@ -18,6 +18,16 @@ pub struct ChannelId(Uuid);
 #[cfg_attr(feature = "sqlx", derive(sqlx::Type))]
 pub struct MessageId(Uuid);
 
+#[repr(transparent)]
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type))]
+pub struct AttachmentId(Uuid);
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type))]
+pub struct WebhookId(Uuid);
+
 #[repr(transparent)]
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RequestId(Uuid);
@@ -36,6 +46,12 @@ impl ServerId {
     }
 }
 
+impl Default for ServerId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ChannelId {
     pub fn new() -> Self {
         Self(Uuid::new_v4())
@@ -46,6 +62,12 @@ impl ChannelId {
     }
 }
 
+impl Default for ChannelId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MessageId {
     pub fn new() -> Self {
         Self(Uuid::new_v4())
@@ -56,6 +78,44 @@ impl MessageId {
     }
 }
 
+impl Default for MessageId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AttachmentId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    pub fn as_uuid(&self) -> Uuid {
+        self.0
+    }
+}
+
+impl Default for AttachmentId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WebhookId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    pub fn as_uuid(&self) -> Uuid {
+        self.0
+    }
+}
+
+impl Default for WebhookId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RequestId {
     pub fn new() -> Self {
         Self(Uuid::new_v4())
@@ -66,6 +126,12 @@ impl RequestId {
     }
 }
 
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl EventId {
     pub fn new() -> Self {
         Self(Uuid::new_v4())
@@ -76,6 +142,12 @@ impl EventId {
     }
 }
 
+impl Default for EventId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl From<Uuid> for ServerId {
     fn from(value: Uuid) -> Self {
         Self(value)
@@ -94,6 +166,18 @@ impl From<Uuid> for MessageId {
     }
 }
 
+impl From<Uuid> for AttachmentId {
+    fn from(value: Uuid) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Uuid> for WebhookId {
+    fn from(value: Uuid) -> Self {
+        Self(value)
+    }
+}
+
 impl From<Uuid> for RequestId {
     fn from(value: Uuid) -> Self {
         Self(value)
@@ -124,6 +208,18 @@ impl From<MessageId> for Uuid {
     }
 }
 
+impl From<AttachmentId> for Uuid {
+    fn from(value: AttachmentId) -> Self {
+        value.0
+    }
+}
+
+impl From<WebhookId> for Uuid {
+    fn from(value: WebhookId) -> Self {
+        value.0
+    }
+}
+
 impl From<RequestId> for Uuid {
     fn from(value: RequestId) -> Self {
         value.0
@@ -166,12 +262,36 @@ impl fmt::Display for MessageId {
     }
 }
 
+impl fmt::Display for AttachmentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Display for WebhookId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl fmt::Debug for MessageId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
+impl fmt::Debug for AttachmentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Debug for WebhookId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl fmt::Display for RequestId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.0)
@@ -220,6 +340,22 @@ impl FromStr for MessageId {
     }
 }
 
+impl FromStr for AttachmentId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(Uuid::parse_str(s)?))
+    }
+}
+
+impl FromStr for WebhookId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(Uuid::parse_str(s)?))
+    }
+}
+
 impl FromStr for RequestId {
     type Err = uuid::Error;
 
@@ -4,6 +4,7 @@ pub mod ids;
 pub mod message;
 pub mod server;
 pub mod user;
+pub mod validation;
 pub mod ws;
 
 pub use auth::*;
@@ -3,32 +3,140 @@ use std::fmt;
 use time::OffsetDateTime;
 
 use crate::{
-    ids::ChannelId,
+    ids::{ChannelId, ServerId},
     user::{User, UserRef},
+    validation::{ValidationError, validate_message_body},
 };
 
-pub use crate::ids::MessageId;
+pub use crate::ids::{AttachmentId, MessageId};
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Message {
     pub id: MessageId,
     pub channel_id: ChannelId,
+    pub server_id: ServerId,
     pub author: Option<User>,
     pub body: String,
+    /// Whether this message was posted by the host rather than a user.
+    #[serde(default)]
+    pub system: bool,
     #[serde(with = "time::serde::rfc3339")]
     pub created_at: OffsetDateTime,
     #[serde(with = "time::serde::rfc3339")]
     pub updated_at: OffsetDateTime,
+    /// When the body was last edited, if ever.
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub edited_at: Option<OffsetDateTime>,
+    /// Whether this is the tombstone of a deleted message, with its body,
+    /// reactions and attachments blanked.
+    #[serde(default)]
+    pub deleted: bool,
+    /// Reactions grouped by emoji, in the order they were first added.
+    #[serde(default)]
+    pub reactions: Vec<ReactionCount>,
+    #[serde(default)]
+    pub attachments: Vec<AttachmentRef>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct NewMessage {
     pub author: UserRef,
     pub body: String,
+    /// Files previously uploaded by the author to attach to the message.
+    #[serde(default)]
+    pub attachments: Vec<AttachmentRef>,
+}
+
+impl NewMessage {
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        validate_message_body(&self.body, !self.attachments.is_empty())
+    }
+}
+
+/// Paging for channel message listings, which return the newest first.
+#[derive(
+    Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq,
+)]
+pub struct MessagePage {
+    /// Only messages older than this one, for fetching earlier pages.
+    #[serde(default)]
+    pub before: Option<MessageId>,
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+/// An uploaded file, as returned by the upload endpoint and listed on
+/// messages.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AttachmentRef {
+    pub id: AttachmentId,
+    pub filename: String,
+    pub content_type: String,
+    /// Size in bytes.
+    pub size: i64,
+    /// Where the file can be downloaded from.
+    pub url: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MessageUpdate {
+    pub body: String,
+}
+
+impl MessageUpdate {
+    /// An edit always leaves a non-blank body, even on messages with
+    /// attachments.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        validate_message_body(&self.body, false)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NewReaction {
+    /// A single unicode emoji or a `:custom_name:` reference.
+    pub emoji: String,
+}
+
+/// How many users reacted to a message with one emoji.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: u32,
+    /// Whether the user the message was fetched for is among them. Always
+    /// false in pushed updates, which are shared by every recipient.
+    #[serde(default)]
+    pub reacted: bool,
+}
+
+/// What an admin purge of deleted messages removed.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MessagePurgeReport {
+    /// Tombstones removed for good.
+    pub purged: u64,
+}
+
+/// What a moderator's purge of a channel deleted.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChannelPurgeReport {
+    pub channel_id: ChannelId,
+    /// Messages deleted, each leaving a tombstone.
+    pub purged: u64,
+}
+
+impl fmt::Display for ReactionCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.emoji, self.count)
+    }
 }
 
 impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.deleted {
+            return write!(f, "[deleted]");
+        }
+        if self.system {
+            return write!(f, "* {}", self.body);
+        }
         write!(
             f,
             "{}: {}",
@@ -37,6 +145,103 @@ impl fmt::Display for Message {
                 .map(|u| u.name.as_str())
                 .unwrap_or("anon"),
             self.body
-        )
+        )?;
+        if self.edited_at.is_some() {
+            write!(f, " (edited)")?;
+        }
+        for attachment in &self.attachments {
+            write!(f, " <{}>", attachment.filename)?;
+        }
+        if !self.reactions.is_empty() {
+            let reactions = self
+                .reactions
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            write!(f, " [{}]", reactions.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The users mentioned in a message body as `@name@host`, in order of first
+/// mention.
+///
+/// A mention must start the body or follow a character that can't be part
+/// of a name, so email-like text such as `ada@b@c` isn't one.
+pub fn parse_mentions(body: &str) -> Vec<UserRef> {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '-';
+    let is_host_char =
+        |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | ':');
+    let mut mentions = Vec::<UserRef>::new();
+    let mut prev = None::<char>;
+    for (start, c) in body.char_indices() {
+        let at_boundary = prev.is_none_or(|p| !is_name_char(p) && p != '@');
+        prev = Some(c);
+        if c != '@' || !at_boundary {
+            continue;
+        }
+        let rest = &body[start + 1..];
+        let name_len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+        let Some(host_part) = rest[name_len..].strip_prefix('@') else {
+            continue;
+        };
+        let host_len = host_part
+            .find(|c| !is_host_char(c))
+            .unwrap_or(host_part.len());
+        let host = host_part[..host_len].trim_end_matches(['.', ':', '-']);
+        if name_len == 0 || host.is_empty() {
+            continue;
+        }
+        let mention = UserRef::new(
+            rest[..name_len].to_ascii_lowercase(),
+            host.to_ascii_lowercase(),
+        );
+        if !mentions.contains(&mention) {
+            mentions.push(mention);
+        }
+    }
+    mentions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::MAX_MESSAGE_BODY_LENGTH;
+
+    #[test]
+    fn test_parse_mentions() {
+        let mentions = parse_mentions(
+            "@ada@a.example, see this (cc @Bob@b.example:7001.) and \
+             @ada@a.example again",
+        );
+        assert_eq!(
+            mentions,
+            vec![
+                UserRef::new("ada".into(), "a.example".into()),
+                UserRef::new("bob".into(), "b.example:7001".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_mentions_ignores_partial_and_email_like_text() {
+        assert!(parse_mentions("mail ada@a.example or @ada or @@x").is_empty());
+        assert!(parse_mentions("x@ada@a.example").is_empty());
+        assert!(parse_mentions("@ada@").is_empty());
+    }
+
+    #[test]
+    fn test_message_update_validates_body() {
+        let update = |body: &str| MessageUpdate { body: body.into() };
+        assert!(update("hello").validate().is_ok());
+        assert_eq!(
+            update("  ").validate(),
+            Err(ValidationError::MessageBodyEmpty)
+        );
+        assert_eq!(
+            update(&"x".repeat(MAX_MESSAGE_BODY_LENGTH + 1)).validate(),
+            Err(ValidationError::MessageBodyTooLong)
+        );
     }
 }
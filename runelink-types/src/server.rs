@@ -3,8 +3,10 @@ use std::fmt;
 use time::OffsetDateTime;
 
 use crate::{
-    channel::Channel,
+    channel::{Channel, ChannelId},
+    message::Message,
     user::{User, UserRef},
+    validation::{ValidationError, validate_description, validate_title},
 };
 
 pub use crate::ids::ServerId;
@@ -16,6 +18,8 @@ pub struct Server {
     pub host: String,
     pub title: String,
     pub description: Option<String>,
+    #[serde(default)]
+    pub visibility: ServerVisibility,
     #[serde(with = "time::serde::rfc3339")]
     pub created_at: OffsetDateTime,
     #[serde(with = "time::serde::rfc3339")]
@@ -26,12 +30,92 @@ pub struct Server {
 pub struct NewServer {
     pub title: String,
     pub description: Option<String>,
+    #[serde(default)]
+    pub visibility: ServerVisibility,
+}
+
+impl NewServer {
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        validate_title(&self.title)?;
+        validate_description(self.description.as_deref())
+    }
+}
+
+/// Whether a server is listed to, and readable by, non-members.
+#[derive(
+    Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type))]
+#[cfg_attr(
+    feature = "sqlx",
+    sqlx(type_name = "server_visibility", rename_all = "lowercase")
+)]
+pub enum ServerVisibility {
+    #[default]
+    Public,
+    Private,
+}
+
+/// A server along with how many members and channels it has.
+///
+/// The counts are `None` when unknown, e.g. for a cached remote server whose
+/// host hasn't reported them. The server's fields are flattened, so a plain
+/// `Server` reads as a summary without counts.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerSummary {
+    #[serde(flatten)]
+    pub server: Server,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub member_count: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel_count: Option<i64>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ServerWithChannels {
     pub server: Server,
-    pub channels: Vec<Channel>,
+    pub channels: Vec<ChannelWithLastMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub member_count: Option<i64>,
+}
+
+/// A channel along with its most recent message, for sidebar previews.
+///
+/// The message is only included when previews are requested and the
+/// channel has one. The channel's fields are flattened, so a plain
+/// `Channel` reads as an entry without a preview.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChannelWithLastMessage {
+    #[serde(flatten)]
+    pub channel: Channel,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_message: Option<Message>,
+}
+
+/// Message activity for a server over a bounded window of days.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerAnalytics {
+    pub server_id: ServerId,
+    pub window_days: u32,
+    pub daily_message_counts: Vec<ChannelDailyMessageCount>,
+    pub active_members: i64,
+    pub top_posters: Vec<TopPoster>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChannelDailyMessageCount {
+    pub channel_id: ChannelId,
+    /// Start of the UTC day the messages were posted on.
+    #[serde(with = "time::serde::rfc3339")]
+    pub day: OffsetDateTime,
+    pub count: i64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TopPoster {
+    pub user_ref: UserRef,
+    pub message_count: i64,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -90,6 +174,101 @@ pub struct NewServerMembership {
     pub role: ServerRole,
 }
 
+/// Limits for a new server invite; both are optional.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NewServerInvite {
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub expires_at: Option<OffsetDateTime>,
+    #[serde(default)]
+    pub max_uses: Option<u32>,
+}
+
+/// An opaque code that lets whoever holds it join a server as a member.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerInvite {
+    pub code: String,
+    pub server_id: ServerId,
+    pub server_host: String,
+    pub created_by: UserRef,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub expires_at: Option<OffsetDateTime>,
+    pub max_uses: Option<u32>,
+    /// `None` when the invite has no use limit.
+    pub uses_remaining: Option<u32>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+/// Body for redeeming an invite code.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InviteRedemption {
+    pub code: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NewServerBan {
+    pub user_ref: UserRef,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// A user barred from joining a server.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerBan {
+    pub server_id: ServerId,
+    pub user_ref: UserRef,
+    pub banned_by: UserRef,
+    pub reason: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+/// One entry of a bulk membership import.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BulkMembershipEntry {
+    pub user_ref: UserRef,
+    pub role: ServerRole,
+}
+
+/// Per-entry outcome of a bulk membership import.
+///
+/// Exactly one of `membership` and `error` is set.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BulkMembershipResult {
+    pub user_ref: UserRef,
+    pub membership: Option<FullServerMembership>,
+    pub error: Option<String>,
+}
+
+/// Selects cached remote servers to re-sync from their home hosts.
+///
+/// At least one of `server_id` or `host` must be set; if both are, the
+/// server must live on that host.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FederationResyncRequest {
+    #[serde(default)]
+    pub server_id: Option<ServerId>,
+    #[serde(default)]
+    pub host: Option<String>,
+}
+
+/// What a federation re-sync changed in the local caches.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FederationResyncReport {
+    pub servers_refreshed: Vec<ServerId>,
+    /// Servers the remote host no longer has; their caches were dropped.
+    pub servers_removed: Vec<ServerId>,
+    pub memberships_upserted: usize,
+    pub memberships_removed: usize,
+    pub failures: Vec<FederationResyncFailure>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FederationResyncFailure {
+    pub server_id: ServerId,
+    pub error: String,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct NewServerMembershipFull {
     pub user: User,
@@ -104,6 +283,16 @@ impl Server {
     }
 }
 
+impl From<Server> for ServerSummary {
+    fn from(server: Server) -> Self {
+        ServerSummary {
+            server,
+            member_count: None,
+            channel_count: None,
+        }
+    }
+}
+
 impl fmt::Display for Server {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if let Some(desc) = &self.description {
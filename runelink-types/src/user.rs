@@ -1,7 +1,16 @@
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use std::{error::Error, fmt, str::FromStr};
 use time::OffsetDateTime;
 
+use crate::validation::{ValidationError, validate_stored_username};
+
+/// Reserved username for the per-host system identity (`system@<host>`).
+pub const SYSTEM_USER_NAME: &str = "system";
+
+/// Reserved username that incoming webhook messages are authored by
+/// (`webhook@<host>`).
+pub const WEBHOOK_USER_NAME: &str = "webhook";
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 #[cfg_attr(feature = "sqlx", derive(sqlx::Type))]
@@ -20,6 +29,10 @@ pub struct User {
     pub name: String,
     pub host: String,
     pub role: UserRole,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub avatar_url: Option<String>,
     #[serde(with = "time::serde::rfc3339")]
     pub created_at: OffsetDateTime,
     #[serde(with = "time::serde::rfc3339")]
@@ -35,6 +48,32 @@ pub struct UserRef {
     pub host: String,
 }
 
+/// Partial update of a user's profile. Omitted fields are left unchanged;
+/// an empty string clears a field.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserProfileUpdate {
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+}
+
+/// Filters and paging for user listings, ordered by host then name.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserQuery {
+    /// Only users whose name starts with this.
+    #[serde(default)]
+    pub name_prefix: Option<String>,
+    /// Only users from this host.
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default)]
+    pub limit: Option<u32>,
+    /// Users to skip, for fetching later pages.
+    #[serde(default)]
+    pub offset: Option<u32>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct NewUser {
     pub name: String,
@@ -42,6 +81,12 @@ pub struct NewUser {
     pub role: UserRole,
 }
 
+impl NewUser {
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        validate_stored_username(&self.name)
+    }
+}
+
 impl User {
     pub fn as_ref(&self) -> UserRef {
         UserRef {
@@ -86,20 +131,104 @@ impl UserRef {
         Self { name, host }
     }
 
+    /// The reserved system identity for a host.
+    pub fn system(host: String) -> Self {
+        Self {
+            name: SYSTEM_USER_NAME.to_string(),
+            host,
+        }
+    }
+
+    /// The reserved identity webhook messages are posted as on a host.
+    pub fn webhook(host: String) -> Self {
+        Self {
+            name: WEBHOOK_USER_NAME.to_string(),
+            host,
+        }
+    }
+
+    pub fn is_system(&self) -> bool {
+        self.name == SYSTEM_USER_NAME
+    }
+
     /// Format as "name@host" for use in JWT subject claims.
     pub fn as_subject(&self) -> String {
-        format!("{}@{}", self.name, self.host)
+        self.to_string()
     }
 
     /// Parse "name@host" string into UserRef. Returns None if format is invalid.
     pub fn parse_subject(s: &str) -> Option<Self> {
-        let (name, host) = s.split_once('@')?;
-        if name.is_empty() || host.is_empty() {
-            return None;
+        s.parse().ok()
+    }
+}
+
+/// Why a string isn't a `name@host` user reference.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseUserRefError {
+    /// There isn't exactly one `@`.
+    InvalidSeparator,
+    EmptyName,
+    EmptyHost,
+}
+
+impl fmt::Display for ParseUserRefError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSeparator => {
+                write!(f, "User must be written as name@host.")
+            }
+            Self::EmptyName => write!(f, "User name cannot be empty."),
+            Self::EmptyHost => write!(f, "User host cannot be empty."),
+        }
+    }
+}
+
+impl Error for ParseUserRefError {}
+
+/// Parses the canonical `name@host` form written by `Display`.
+///
+/// The parts are taken as they are; normalizing user input is up to the
+/// caller.
+impl FromStr for UserRef {
+    type Err = ParseUserRefError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, host) = s
+            .split_once('@')
+            .filter(|(_, host)| !host.contains('@'))
+            .ok_or(ParseUserRefError::InvalidSeparator)?;
+        if name.is_empty() {
+            return Err(ParseUserRefError::EmptyName);
+        }
+        if host.is_empty() {
+            return Err(ParseUserRefError::EmptyHost);
         }
-        Some(Self {
-            name: name.to_string(),
-            host: host.to_string(),
-        })
+        Ok(Self::new(name.to_string(), host.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_ref_round_trips() {
+        let user_ref = UserRef::new("ada".into(), "example.com:7001".into());
+        assert_eq!(user_ref.to_string(), "ada@example.com:7001");
+        assert_eq!("ada@example.com:7001".parse(), Ok(user_ref.clone()));
+        assert_eq!(
+            UserRef::parse_subject(&user_ref.as_subject()),
+            Some(user_ref)
+        );
+    }
+
+    #[test]
+    fn test_user_ref_needs_one_separator_and_both_parts() {
+        let parse = |s: &str| s.parse::<UserRef>().unwrap_err();
+        assert_eq!(parse("ada"), ParseUserRefError::InvalidSeparator);
+        assert_eq!(parse("ada@a@b"), ParseUserRefError::InvalidSeparator);
+        assert_eq!(parse("@example.com"), ParseUserRefError::EmptyName);
+        assert_eq!(parse("ada@"), ParseUserRefError::EmptyHost);
+        assert_eq!(UserRef::parse_subject("ada@a@b"), None);
     }
 }
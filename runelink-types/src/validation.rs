@@ -0,0 +1,633 @@
+use std::{error::Error, fmt};
+
+pub const MAX_USERNAME_LENGTH: usize = 32;
+pub const MAX_CUSTOM_EMOJI_NAME_LENGTH: usize = 32;
+/// Upper bound on code points in one emoji, covering long ZWJ sequences.
+pub const MAX_EMOJI_CODEPOINTS: usize = 16;
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+pub const MAX_AVATAR_URL_LENGTH: usize = 2048;
+/// Applies to server and channel titles and channel categories.
+pub const MAX_TITLE_LENGTH: usize = 100;
+pub const MAX_DESCRIPTION_LENGTH: usize = 1000;
+pub const MAX_MESSAGE_BODY_LENGTH: usize = 4000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    UsernameEmpty,
+    UsernameTooLong,
+    UsernameInvalidCharacters,
+    HostEmpty,
+    HostInvalidCharacters,
+    HostMultiplePorts,
+    HostPortNotAllowed,
+    HostInvalidPort,
+    EmojiEmpty,
+    EmojiInvalid,
+    CustomEmojiInvalidName,
+    DisplayNameTooLong,
+    DisplayNameInvalidCharacters,
+    AvatarUrlTooLong,
+    AvatarUrlInvalid,
+    TitleEmpty,
+    TitleTooLong,
+    DescriptionTooLong,
+    CategoryEmpty,
+    CategoryTooLong,
+    MessageBodyEmpty,
+    MessageBodyTooLong,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UsernameEmpty => write!(f, "Username cannot be empty."),
+            Self::UsernameTooLong => write!(
+                f,
+                "Username cannot be longer than {MAX_USERNAME_LENGTH} characters."
+            ),
+            Self::UsernameInvalidCharacters => write!(
+                f,
+                "Username may only contain lowercase letters, digits, and single hyphens between them."
+            ),
+            Self::HostEmpty => write!(f, "Host cannot be empty."),
+            Self::HostInvalidCharacters => write!(
+                f,
+                "Host may only contain lowercase letters, digits, dots, hyphens, and an optional :port.",
+            ),
+            Self::HostMultiplePorts => {
+                write!(f, "Host can include at most one port separator.")
+            }
+            Self::HostPortNotAllowed => {
+                write!(f, "Host must not include a port here.")
+            }
+            Self::HostInvalidPort => {
+                write!(f, "Host port must contain digits only.")
+            }
+            Self::EmojiEmpty => write!(f, "Emoji cannot be empty."),
+            Self::EmojiInvalid => {
+                write!(f, "Emoji must be a single unicode emoji.")
+            }
+            Self::CustomEmojiInvalidName => write!(
+                f,
+                "Custom emoji must look like :name: with up to {MAX_CUSTOM_EMOJI_NAME_LENGTH} lowercase letters, digits, or underscores."
+            ),
+            Self::DisplayNameTooLong => write!(
+                f,
+                "Display name cannot be longer than {MAX_DISPLAY_NAME_LENGTH} characters."
+            ),
+            Self::DisplayNameInvalidCharacters => {
+                write!(f, "Display name cannot contain control characters.")
+            }
+            Self::AvatarUrlTooLong => write!(
+                f,
+                "Avatar URL cannot be longer than {MAX_AVATAR_URL_LENGTH} characters."
+            ),
+            Self::AvatarUrlInvalid => {
+                write!(f, "Avatar URL must be a valid http or https URL.")
+            }
+            Self::TitleEmpty => write!(f, "Title cannot be empty."),
+            Self::TitleTooLong => write!(
+                f,
+                "Title cannot be longer than {MAX_TITLE_LENGTH} characters."
+            ),
+            Self::DescriptionTooLong => write!(
+                f,
+                "Description cannot be longer than {MAX_DESCRIPTION_LENGTH} characters."
+            ),
+            Self::CategoryEmpty => write!(f, "Category cannot be empty."),
+            Self::CategoryTooLong => write!(
+                f,
+                "Category cannot be longer than {MAX_TITLE_LENGTH} characters."
+            ),
+            Self::MessageBodyEmpty => {
+                write!(f, "Message cannot be empty without attachments.")
+            }
+            Self::MessageBodyTooLong => write!(
+                f,
+                "Message cannot be longer than {MAX_MESSAGE_BODY_LENGTH} characters."
+            ),
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+pub fn normalize_username(input: &str) -> String {
+    let mut normalized = String::new();
+    let mut pending_dash = false;
+
+    for ch in input.trim().chars() {
+        let ch = ch.to_ascii_lowercase();
+
+        if ch.is_ascii_lowercase() || ch.is_ascii_digit() {
+            if pending_dash && !normalized.is_empty() {
+                normalized.push('-');
+            }
+            normalized.push(ch);
+            pending_dash = false;
+            continue;
+        }
+
+        if ch.is_ascii_whitespace() || ch == '_' || ch == '-' || ch == '.' {
+            pending_dash = !normalized.is_empty();
+        }
+    }
+
+    normalized
+}
+
+pub fn normalize_host_input(input: &str) -> String {
+    let lowercased = input.trim().to_ascii_lowercase();
+    lowercased
+        .trim_start_matches("http://")
+        .trim_start_matches("https://")
+        .trim_end_matches('/')
+        .to_string()
+}
+
+pub fn validate_username(input: &str) -> Result<String, ValidationError> {
+    let normalized = normalize_username(input);
+    if normalized.is_empty() {
+        return Err(ValidationError::UsernameEmpty);
+    }
+    if normalized.len() > MAX_USERNAME_LENGTH {
+        return Err(ValidationError::UsernameTooLong);
+    }
+    Ok(normalized)
+}
+
+/// Checks a username is already in normalized form, as stored.
+pub fn validate_stored_username(name: &str) -> Result<(), ValidationError> {
+    if name.is_empty() {
+        return Err(ValidationError::UsernameEmpty);
+    }
+    if name.len() > MAX_USERNAME_LENGTH {
+        return Err(ValidationError::UsernameTooLong);
+    }
+    if normalize_username(name) != name {
+        return Err(ValidationError::UsernameInvalidCharacters);
+    }
+    Ok(())
+}
+
+/// Checks a server or channel title isn't blank or too long.
+pub fn validate_title(title: &str) -> Result<(), ValidationError> {
+    let trimmed = title.trim();
+    if trimmed.is_empty() {
+        return Err(ValidationError::TitleEmpty);
+    }
+    if trimmed.chars().count() > MAX_TITLE_LENGTH {
+        return Err(ValidationError::TitleTooLong);
+    }
+    Ok(())
+}
+
+pub fn validate_description(
+    description: Option<&str>,
+) -> Result<(), ValidationError> {
+    if description.is_some_and(|d| d.chars().count() > MAX_DESCRIPTION_LENGTH) {
+        return Err(ValidationError::DescriptionTooLong);
+    }
+    Ok(())
+}
+
+/// Checks a channel category, if set, isn't blank or too long.
+pub fn validate_category(
+    category: Option<&str>,
+) -> Result<(), ValidationError> {
+    let Some(category) = category.map(str::trim) else {
+        return Ok(());
+    };
+    if category.is_empty() {
+        return Err(ValidationError::CategoryEmpty);
+    }
+    if category.chars().count() > MAX_TITLE_LENGTH {
+        return Err(ValidationError::CategoryTooLong);
+    }
+    Ok(())
+}
+
+/// Checks a message body fits and, unless the message has attachments,
+/// isn't blank.
+pub fn validate_message_body(
+    body: &str,
+    has_attachments: bool,
+) -> Result<(), ValidationError> {
+    if body.trim().is_empty() && !has_attachments {
+        return Err(ValidationError::MessageBodyEmpty);
+    }
+    if body.chars().count() > MAX_MESSAGE_BODY_LENGTH {
+        return Err(ValidationError::MessageBodyTooLong);
+    }
+    Ok(())
+}
+
+/// Validates a reaction emoji.
+///
+/// Accepts either a single unicode emoji (one grapheme cluster, including
+/// flags, keycaps, skin tones, and ZWJ sequences) or a custom emoji
+/// reference of the form `:name:`.
+pub fn validate_emoji(input: &str) -> Result<String, ValidationError> {
+    let trimmed = input.trim();
+    if trimmed.is_empty() {
+        return Err(ValidationError::EmojiEmpty);
+    }
+    if let Some(name) = trimmed
+        .strip_prefix(':')
+        .and_then(|rest| rest.strip_suffix(':'))
+    {
+        let valid_name = !name.is_empty()
+            && name.len() <= MAX_CUSTOM_EMOJI_NAME_LENGTH
+            && name.chars().all(|ch| {
+                ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '_'
+            });
+        if !valid_name {
+            return Err(ValidationError::CustomEmojiInvalidName);
+        }
+        return Ok(trimmed.to_string());
+    }
+    if !is_single_emoji(trimmed) {
+        return Err(ValidationError::EmojiInvalid);
+    }
+    Ok(trimmed.to_string())
+}
+
+/// Validates a display name, returning it trimmed. An empty result means
+/// "no display name".
+pub fn validate_display_name(input: &str) -> Result<String, ValidationError> {
+    let trimmed = input.trim();
+    if trimmed.chars().count() > MAX_DISPLAY_NAME_LENGTH {
+        return Err(ValidationError::DisplayNameTooLong);
+    }
+    if trimmed.chars().any(char::is_control) {
+        return Err(ValidationError::DisplayNameInvalidCharacters);
+    }
+    Ok(trimmed.to_string())
+}
+
+/// Validates an avatar URL, returning it trimmed. An empty result means
+/// "no avatar".
+pub fn validate_avatar_url(input: &str) -> Result<String, ValidationError> {
+    let trimmed = input.trim();
+    if trimmed.is_empty() {
+        return Ok(String::new());
+    }
+    if trimmed.len() > MAX_AVATAR_URL_LENGTH {
+        return Err(ValidationError::AvatarUrlTooLong);
+    }
+    let url = url::Url::parse(trimmed)
+        .map_err(|_| ValidationError::AvatarUrlInvalid)?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return Err(ValidationError::AvatarUrlInvalid);
+    }
+    Ok(trimmed.to_string())
+}
+
+fn is_single_emoji(input: &str) -> bool {
+    const ZWJ: char = '\u{200D}';
+    const VS16: char = '\u{FE0F}';
+    const KEYCAP: char = '\u{20E3}';
+
+    let chars: Vec<char> = input.chars().collect();
+    if chars.len() > MAX_EMOJI_CODEPOINTS {
+        return false;
+    }
+    // Flags are a pair of regional indicators
+    if chars.iter().any(|ch| is_regional_indicator(*ch)) {
+        return chars.len() == 2
+            && chars.iter().all(|ch| is_regional_indicator(*ch));
+    }
+    // Keycaps: digit, '#' or '*', optional VS16, then the keycap mark
+    if let Some(first) = chars.first()
+        && (first.is_ascii_digit() || *first == '#' || *first == '*')
+    {
+        return matches!(chars[1..], [KEYCAP] | [VS16, KEYCAP]);
+    }
+    // base modifier* (ZWJ base modifier*)*
+    let mut expect_base = true;
+    for ch in chars {
+        if expect_base {
+            if !is_emoji_base(ch) {
+                return false;
+            }
+            expect_base = false;
+        } else if ch == ZWJ {
+            expect_base = true;
+        } else if !is_emoji_modifier(ch) {
+            return false;
+        }
+    }
+    !expect_base
+}
+
+fn is_regional_indicator(ch: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&ch)
+}
+
+fn is_emoji_modifier(ch: char) -> bool {
+    matches!(ch,
+        '\u{FE0E}' | '\u{FE0F}' // variation selectors
+        | '\u{1F3FB}'..='\u{1F3FF}' // skin tones
+        | '\u{E0020}'..='\u{E007F}' // tag sequences (subdivision flags)
+    )
+}
+
+fn is_emoji_base(ch: char) -> bool {
+    !is_regional_indicator(ch)
+        && !is_emoji_modifier(ch)
+        && matches!(ch,
+            '\u{1F000}'..='\u{1FAFF}'
+            | '\u{2600}'..='\u{27BF}'
+            | '\u{2300}'..='\u{23FF}'
+            | '\u{2B00}'..='\u{2BFF}'
+            | '\u{2190}'..='\u{21FF}'
+            | '\u{25A0}'..='\u{25FF}'
+            | '\u{2900}'..='\u{297F}'
+            | '\u{3030}' | '\u{303D}' | '\u{3297}' | '\u{3299}'
+            | '\u{00A9}' | '\u{00AE}' | '\u{203C}' | '\u{2049}'
+            | '\u{2122}' | '\u{2139}' | '\u{24C2}'
+        )
+}
+
+pub fn validate_host(input: &str) -> Result<String, ValidationError> {
+    validate_host_internal(input, true)
+}
+
+pub fn validate_config_host(input: &str) -> Result<String, ValidationError> {
+    validate_host_internal(input, false)
+}
+
+fn validate_host_internal(
+    input: &str,
+    allow_port: bool,
+) -> Result<String, ValidationError> {
+    let normalized = normalize_host_input(input);
+
+    if normalized.is_empty() {
+        return Err(ValidationError::HostEmpty);
+    }
+
+    let colon_count = normalized.chars().filter(|ch| *ch == ':').count();
+    if colon_count > 1 {
+        return Err(ValidationError::HostMultiplePorts);
+    }
+
+    let (host_part, port_part) = match normalized.split_once(':') {
+        Some((host_part, port_part)) => {
+            if !allow_port {
+                return Err(ValidationError::HostPortNotAllowed);
+            }
+            (host_part, Some(port_part))
+        }
+        None => (normalized.as_str(), None),
+    };
+
+    if host_part.is_empty() {
+        return Err(ValidationError::HostEmpty);
+    }
+
+    if !host_part.chars().all(|ch| {
+        ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '.' || ch == '-'
+    }) {
+        return Err(ValidationError::HostInvalidCharacters);
+    }
+
+    if let Some(port_part) = port_part
+        && (port_part.is_empty()
+            || !port_part.chars().all(|ch| ch.is_ascii_digit()))
+    {
+        return Err(ValidationError::HostInvalidPort);
+    }
+
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_usernames() {
+        assert_eq!(normalize_username("John   Smith"), "john-smith");
+        assert_eq!(normalize_username("John_Smith"), "john-smith");
+        assert_eq!(normalize_username("John.Smith"), "john-smith");
+        assert_eq!(normalize_username("__ John  Smith __"), "john-smith");
+        assert_eq!(normalize_username("A@B!"), "ab");
+    }
+
+    #[test]
+    fn rejects_empty_usernames() {
+        assert_eq!(
+            validate_username("___!!!").unwrap_err(),
+            ValidationError::UsernameEmpty
+        );
+    }
+
+    #[test]
+    fn rejects_long_usernames() {
+        assert_eq!(
+            validate_username("abcdefghijklmnopqrstuvwxyz-123456").unwrap_err(),
+            ValidationError::UsernameTooLong
+        );
+    }
+
+    #[test]
+    fn validates_stored_usernames() {
+        assert_eq!(validate_stored_username("ada-lovelace-2"), Ok(()));
+        assert_eq!(
+            validate_stored_username(&"a".repeat(MAX_USERNAME_LENGTH)),
+            Ok(())
+        );
+        assert_eq!(
+            validate_stored_username(""),
+            Err(ValidationError::UsernameEmpty)
+        );
+        assert_eq!(
+            validate_stored_username(&"a".repeat(MAX_USERNAME_LENGTH + 1)),
+            Err(ValidationError::UsernameTooLong)
+        );
+        for name in ["Ada", "ada lovelace", "ada_l", "-ada", "ada--l", "ädá"]
+        {
+            assert_eq!(
+                validate_stored_username(name),
+                Err(ValidationError::UsernameInvalidCharacters),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn validates_titles_and_descriptions() {
+        assert_eq!(validate_title(" general "), Ok(()));
+        assert_eq!(validate_title(&"é".repeat(MAX_TITLE_LENGTH)), Ok(()));
+        assert_eq!(validate_title(" \t"), Err(ValidationError::TitleEmpty));
+        assert_eq!(
+            validate_title(&"a".repeat(MAX_TITLE_LENGTH + 1)),
+            Err(ValidationError::TitleTooLong)
+        );
+        assert_eq!(validate_description(None), Ok(()));
+        assert_eq!(
+            validate_description(Some(&"a".repeat(MAX_DESCRIPTION_LENGTH))),
+            Ok(())
+        );
+        assert_eq!(
+            validate_description(Some(&"a".repeat(MAX_DESCRIPTION_LENGTH + 1))),
+            Err(ValidationError::DescriptionTooLong)
+        );
+    }
+
+    #[test]
+    fn validates_categories() {
+        assert_eq!(validate_category(None), Ok(()));
+        assert_eq!(validate_category(Some("Voice")), Ok(()));
+        assert_eq!(
+            validate_category(Some("  ")),
+            Err(ValidationError::CategoryEmpty)
+        );
+        assert_eq!(
+            validate_category(Some(&"a".repeat(MAX_TITLE_LENGTH + 1))),
+            Err(ValidationError::CategoryTooLong)
+        );
+    }
+
+    #[test]
+    fn validates_message_bodies() {
+        assert_eq!(validate_message_body("hi", false), Ok(()));
+        assert_eq!(validate_message_body("", true), Ok(()));
+        assert_eq!(
+            validate_message_body(" \n", false),
+            Err(ValidationError::MessageBodyEmpty)
+        );
+        let longest = "é".repeat(MAX_MESSAGE_BODY_LENGTH);
+        assert_eq!(validate_message_body(&longest, false), Ok(()));
+        assert_eq!(
+            validate_message_body(&format!("{longest}a"), false),
+            Err(ValidationError::MessageBodyTooLong)
+        );
+    }
+
+    #[test]
+    fn normalizes_and_validates_hosts() {
+        assert_eq!(
+            validate_host(" HTTPS://Example.COM:7000/ ").unwrap(),
+            "example.com:7000"
+        );
+        assert_eq!(validate_config_host("Example.COM").unwrap(), "example.com");
+    }
+
+    #[test]
+    fn rejects_invalid_ports() {
+        assert_eq!(
+            validate_host("example.com:abc").unwrap_err(),
+            ValidationError::HostInvalidPort
+        );
+        assert_eq!(
+            validate_host("example.com:7000:1").unwrap_err(),
+            ValidationError::HostMultiplePorts
+        );
+    }
+
+    #[test]
+    fn rejects_ports_in_config_hosts() {
+        assert_eq!(
+            validate_config_host("example.com:7000").unwrap_err(),
+            ValidationError::HostPortNotAllowed
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_host_characters() {
+        assert_eq!(
+            validate_host("exa$mple.com").unwrap_err(),
+            ValidationError::HostInvalidCharacters
+        );
+    }
+
+    #[test]
+    fn accepts_single_emoji() {
+        for emoji in ["👍", "❤️", "👍🏽", "👨‍👩‍👧‍👦", "🏳️‍🌈", "🇳🇴", "#️⃣", "1⃣"]
+        {
+            assert_eq!(validate_emoji(emoji).unwrap(), emoji, "{emoji}");
+        }
+    }
+
+    #[test]
+    fn accepts_custom_emoji_references() {
+        assert_eq!(
+            validate_emoji(" :party_parrot: ").unwrap(),
+            ":party_parrot:"
+        );
+    }
+
+    #[test]
+    fn rejects_arbitrary_strings_as_emoji() {
+        assert_eq!(
+            validate_emoji("   ").unwrap_err(),
+            ValidationError::EmojiEmpty
+        );
+        for input in ["a", "lol", "👍👍", "👍 nice", "🇳", "🇳🇴🇸🇪", "12"]
+        {
+            assert_eq!(
+                validate_emoji(input).unwrap_err(),
+                ValidationError::EmojiInvalid,
+                "{input}"
+            );
+        }
+        let long = "👍".repeat(100);
+        assert_eq!(
+            validate_emoji(&long).unwrap_err(),
+            ValidationError::EmojiInvalid
+        );
+        assert_eq!(
+            validate_emoji(":Not Valid:").unwrap_err(),
+            ValidationError::CustomEmojiInvalidName
+        );
+        assert_eq!(
+            validate_emoji(&format!(":{}:", "a".repeat(33))).unwrap_err(),
+            ValidationError::CustomEmojiInvalidName
+        );
+    }
+
+    #[test]
+    fn validates_display_names() {
+        assert_eq!(validate_display_name("  Ada  "), Ok("Ada".into()));
+        assert_eq!(validate_display_name(""), Ok(String::new()));
+        assert_eq!(
+            validate_display_name(&"é".repeat(MAX_DISPLAY_NAME_LENGTH)),
+            Ok("é".repeat(MAX_DISPLAY_NAME_LENGTH))
+        );
+        assert_eq!(
+            validate_display_name(&"a".repeat(MAX_DISPLAY_NAME_LENGTH + 1)),
+            Err(ValidationError::DisplayNameTooLong)
+        );
+        assert_eq!(
+            validate_display_name("Ada\nLovelace"),
+            Err(ValidationError::DisplayNameInvalidCharacters)
+        );
+    }
+
+    #[test]
+    fn validates_avatar_urls() {
+        assert_eq!(
+            validate_avatar_url(" https://cdn.example.com/a.png "),
+            Ok("https://cdn.example.com/a.png".into())
+        );
+        assert_eq!(
+            validate_avatar_url("http://localhost:8080/a.png"),
+            Ok("http://localhost:8080/a.png".into())
+        );
+        assert_eq!(validate_avatar_url(""), Ok(String::new()));
+        for invalid in [
+            "not a url",
+            "ftp://example.com/a.png",
+            "javascript:alert(1)",
+            "data:image/png;base64,AAAA",
+        ] {
+            assert_eq!(
+                validate_avatar_url(invalid),
+                Err(ValidationError::AvatarUrlInvalid),
+                "{invalid}"
+            );
+        }
+    }
+}
@@ -1,27 +1,143 @@
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use time::OffsetDateTime;
 
 use crate::{
     auth::{
         AuthTokenPasswordRequest, AuthTokenRefreshRequest, JwksResponse,
-        OidcDiscoveryDocument, SignupRequest, TokenResponse,
+        OidcDiscoveryDocument, SignupRequest, TokenResponse, UserinfoResponse,
+    },
+    channel::{
+        Channel, ChannelId, ChannelReadState, ChannelUnreadCount,
+        ChannelUpdate, NewChannel,
+    },
+    message::{
+        ChannelPurgeReport, Message, MessageId, MessageUpdate, NewMessage,
+        NewReaction,
     },
-    channel::{Channel, ChannelId, NewChannel},
-    message::{Message, MessageId, NewMessage},
     server::{
-        FullServerMembership, NewServer, NewServerMembership,
-        NewServerMembershipFull, Server, ServerId, ServerMember,
-        ServerMembership, ServerWithChannels,
+        FullServerMembership, NewServer, NewServerBan, NewServerInvite,
+        NewServerMembership, NewServerMembershipFull, Server, ServerBan,
+        ServerId, ServerInvite, ServerMember, ServerMembership, ServerRole,
+        ServerSummary, ServerWithChannels,
     },
-    user::{NewUser, User, UserRef},
+    user::{NewUser, User, UserProfileUpdate, UserQuery, UserRef},
 };
 
 pub use crate::ids::{EventId, RequestId};
 
+/// The websocket protocol version this build speaks.
+///
+/// Bump it whenever an envelope, request or reply changes shape in a way an
+/// older peer can't read.
+pub const WS_PROTOCOL_VERSION: u32 = 1;
+
+/// The oldest protocol version this build still accepts from a peer.
+pub const MIN_SUPPORTED_WS_PROTOCOL_VERSION: u32 = 1;
+
+/// Header carrying the protocol version on federation websocket upgrades,
+/// both on the request and on the `101` response.
+pub const WS_PROTOCOL_VERSION_HEADER: &str = "x-runelink-protocol-version";
+
+/// Websocket subprotocol for JSON text frames, the default encoding.
+pub const WS_JSON_SUBPROTOCOL: &str = "runelink.json";
+
+/// Websocket subprotocol for MessagePack binary frames. Offer it in
+/// `Sec-WebSocket-Protocol` to opt in; the envelopes are the same as their
+/// JSON form, field names and all.
+pub const WS_MSGPACK_SUBPROTOCOL: &str = "runelink.msgpack";
+
+/// Whether this build can talk to a peer speaking `version`.
+pub fn is_supported_protocol_version(version: u32) -> bool {
+    (MIN_SUPPORTED_WS_PROTOCOL_VERSION..=WS_PROTOCOL_VERSION).contains(&version)
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct WsError {
-    pub code: String,
+    pub code: WsErrorCode,
+    pub message: String,
+    pub details: Option<Value>,
+}
+
+/// What kind of error a `WsError` is, for clients to branch on.
+///
+/// HTTP error responses carry the same codes.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum WsErrorCode {
+    /// Missing or invalid credentials; authenticating again may help.
+    Unauthorized,
+    /// Sent by older hosts where `unauthorized` is meant.
+    AuthError,
+    /// Bad credentials or an unusable refresh token on a token grant.
+    InvalidGrant,
+    /// Authenticated, but not allowed; authenticating again won't help.
+    Forbidden,
+    BadRequest,
+    /// The websocket message couldn't be parsed as an envelope.
+    MalformedEnvelope,
+    /// A websocket message was over the size limit; `details.max` is the
+    /// limit in bytes.
+    PayloadTooLarge,
+    /// The peer speaks a protocol version this host doesn't support;
+    /// `details` carries the supported range.
+    UnsupportedProtocol,
+    InviteExpired,
+    InviteExhausted,
+    /// Too many requests; `details.retry_after` says how many seconds to
+    /// wait.
+    RateLimited,
+    NotFound,
+    Conflict,
+    /// A transient outage; safe to retry.
+    ServiceUnavailable,
+    /// A request this host made to another host failed.
+    UpstreamError,
+    InternalError,
+    /// A code this version doesn't know, e.g. from a newer peer.
+    #[serde(other)]
+    Unknown,
+}
+
+impl WsErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WsErrorCode::Unauthorized => "unauthorized",
+            WsErrorCode::AuthError => "auth_error",
+            WsErrorCode::InvalidGrant => "invalid_grant",
+            WsErrorCode::Forbidden => "forbidden",
+            WsErrorCode::BadRequest => "bad_request",
+            WsErrorCode::MalformedEnvelope => "malformed_envelope",
+            WsErrorCode::PayloadTooLarge => "payload_too_large",
+            WsErrorCode::UnsupportedProtocol => "unsupported_protocol",
+            WsErrorCode::InviteExpired => "invite_expired",
+            WsErrorCode::InviteExhausted => "invite_exhausted",
+            WsErrorCode::RateLimited => "rate_limited",
+            WsErrorCode::NotFound => "not_found",
+            WsErrorCode::Conflict => "conflict",
+            WsErrorCode::ServiceUnavailable => "service_unavailable",
+            WsErrorCode::UpstreamError => "upstream_error",
+            WsErrorCode::InternalError => "internal_error",
+            WsErrorCode::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for WsErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The JSON body of an HTTP error response.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ApiErrorBody {
+    #[serde(rename = "error")]
     pub message: String,
+    pub code: WsErrorCode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub details: Option<Value>,
 }
 
@@ -30,6 +146,44 @@ pub struct AuthTokenAccessRequest {
     pub access_token: String,
 }
 
+/// Presents a resume token to re-authenticate a new client connection.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ResumeSessionRequest {
+    pub resume_token: String,
+    /// The last event seen per server, to replay what was missed since.
+    #[serde(default)]
+    pub cursors: Vec<ReplayCursor>,
+}
+
+/// The last event a client saw from a server.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ReplayCursor {
+    pub server_id: ServerId,
+    pub after_event_id: EventId,
+}
+
+/// A resumed session, with the updates missed since each cursor.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ResumedSession {
+    pub state: ClientWsConnectionState,
+    pub replays: Vec<ServerReplay>,
+}
+
+/// The updates a server sent after a client's cursor.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ServerReplay {
+    pub server_id: ServerId,
+    pub replay: ClientWsReplay,
+}
+
+/// A short-lived, single-use token for resuming a client session.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ResumeToken {
+    pub resume_token: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum ClientWsConnectionState {
@@ -48,7 +202,13 @@ pub enum FederationWsConnectionState {
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum ClientWsRequest {
+    /// Expected as the first message on a connection. Connections that skip
+    /// it are treated as speaking protocol version 1.
+    Hello {
+        protocol_version: u32,
+    },
     Ping,
+    ServerTime,
     OidcDiscovery,
     OidcJwks,
     ConnectionState,
@@ -56,16 +216,25 @@ pub enum ClientWsRequest {
     AuthTokenPassword(AuthTokenPasswordRequest),
     AuthTokenRefresh(AuthTokenRefreshRequest),
     AuthTokenAccess(AuthTokenAccessRequest),
+    ResumeSession(ResumeSessionRequest),
     AuthUserinfo,
     AuthRegisterClient,
     UsersCreate(NewUser),
     UsersGetAll {
+        #[serde(default)]
+        query: UserQuery,
         target_host: Option<String>,
     },
     UsersGetByRef {
         user_ref: UserRef,
         target_host: Option<String>,
     },
+    /// Find users by name on this host and on `hosts`.
+    UsersSearch {
+        query: String,
+        #[serde(default)]
+        hosts: Vec<String>,
+    },
     UsersGetAssociatedHosts {
         user_ref: UserRef,
         target_host: Option<String>,
@@ -73,6 +242,10 @@ pub enum ClientWsRequest {
     UsersDelete {
         user_ref: UserRef,
     },
+    UsersUpdateProfile {
+        user_ref: UserRef,
+        update: UserProfileUpdate,
+    },
     MembershipsGetByUser {
         user_ref: UserRef,
     },
@@ -94,25 +267,71 @@ pub enum ClientWsRequest {
         user_ref: UserRef,
         target_host: Option<String>,
     },
+    MembershipsUpdateRole {
+        server_id: ServerId,
+        user_ref: UserRef,
+        role: ServerRole,
+        target_host: Option<String>,
+    },
+    MembershipsRemove {
+        server_id: ServerId,
+        user_ref: UserRef,
+        target_host: Option<String>,
+    },
+    BansCreate {
+        server_id: ServerId,
+        new_ban: NewServerBan,
+        target_host: Option<String>,
+    },
+    BansDelete {
+        server_id: ServerId,
+        user_ref: UserRef,
+        target_host: Option<String>,
+    },
+    InvitesCreate {
+        server_id: ServerId,
+        new_invite: NewServerInvite,
+        target_host: Option<String>,
+    },
+    InvitesRedeem {
+        code: String,
+        target_host: Option<String>,
+    },
     ServersCreate {
         new_server: NewServer,
         target_host: Option<String>,
     },
     ServersGetAll {
+        /// Also list private servers; host admins only.
+        #[serde(default)]
+        include_private: bool,
         target_host: Option<String>,
     },
     ServersGetById {
         server_id: ServerId,
+        /// Skip the remote server cache and re-fetch from the host.
+        #[serde(default)]
+        force_refresh: bool,
         target_host: Option<String>,
     },
     ServersGetWithChannels {
         server_id: ServerId,
+        #[serde(default)]
+        include_last_messages: bool,
         target_host: Option<String>,
     },
     ServersDelete {
         server_id: ServerId,
         target_host: Option<String>,
     },
+    /// Make another member an admin, optionally stepping down as one.
+    ServersTransferOwnership {
+        server_id: ServerId,
+        new_admin: UserRef,
+        #[serde(default)]
+        demote_self: bool,
+        target_host: Option<String>,
+    },
     ChannelsCreate {
         server_id: ServerId,
         new_channel: NewChannel,
@@ -130,6 +349,18 @@ pub enum ClientWsRequest {
         channel_id: ChannelId,
         target_host: Option<String>,
     },
+    ChannelsUpdate {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        update: ChannelUpdate,
+        target_host: Option<String>,
+    },
+    /// List each of the server's channels once, in their new order.
+    ChannelsReorder {
+        server_id: ServerId,
+        channel_ids: Vec<ChannelId>,
+        target_host: Option<String>,
+    },
     ChannelsDelete {
         server_id: ServerId,
         channel_id: ChannelId,
@@ -151,6 +382,11 @@ pub enum ClientWsRequest {
     MessagesGetByChannel {
         server_id: ServerId,
         channel_id: ChannelId,
+        /// Only return messages older than this one.
+        #[serde(default)]
+        before: Option<MessageId>,
+        #[serde(default)]
+        limit: Option<u32>,
         target_host: Option<String>,
     },
     MessagesGetById {
@@ -159,51 +395,192 @@ pub enum ClientWsRequest {
         message_id: MessageId,
         target_host: Option<String>,
     },
+    /// Search a server's messages, best matches first.
+    MessagesSearch {
+        server_id: ServerId,
+        query: String,
+        #[serde(default)]
+        limit: Option<u32>,
+        target_host: Option<String>,
+    },
+    MessagesUpdate {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        update: MessageUpdate,
+        target_host: Option<String>,
+    },
     MessagesDelete {
         server_id: ServerId,
         channel_id: ChannelId,
         message_id: MessageId,
         target_host: Option<String>,
     },
+    /// Delete every message in a channel, or only those older than `before`.
+    MessagesPurgeChannel {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        #[serde(default)]
+        before: Option<MessageId>,
+        target_host: Option<String>,
+    },
+    MessagesReact {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        new_reaction: NewReaction,
+        target_host: Option<String>,
+    },
+    MessagesUnreact {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: String,
+        target_host: Option<String>,
+    },
+    /// Move the user's read position in a channel to a message.
+    ChannelsMarkRead {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        target_host: Option<String>,
+    },
+    /// Unread message counts for each channel in a server.
+    UnreadCounts {
+        server_id: ServerId,
+        target_host: Option<String>,
+    },
+    /// Send a direct message. Remote recipients are reached through their
+    /// home host.
+    DmSend {
+        to: UserRef,
+        body: String,
+    },
+    /// Direct messages exchanged with a user, newest first.
+    DmGetHistory {
+        with: UserRef,
+        /// Only return messages older than this one.
+        #[serde(default)]
+        before: Option<MessageId>,
+        #[serde(default)]
+        limit: Option<u32>,
+    },
+    /// Start receiving message updates for a channel on this connection.
+    Subscribe {
+        server_id: ServerId,
+        channel_id: ChannelId,
+    },
+    /// Stop receiving message updates for a channel on this connection.
+    Unsubscribe {
+        server_id: ServerId,
+        channel_id: ChannelId,
+    },
+    /// Start receiving presence changes for a server's members on this
+    /// connection. Replies with the members currently online.
+    PresenceSubscribe {
+        server_id: ServerId,
+    },
+    /// Tell the channel's other subscribers that this user is typing.
+    /// Nothing is stored, and repeats sent too quickly are dropped.
+    Typing {
+        server_id: ServerId,
+        channel_id: ChannelId,
+    },
+    /// Updates about a server sent after the given event, for catching up
+    /// after a reconnect.
+    ReplaySince {
+        server_id: ServerId,
+        after_event_id: EventId,
+    },
 }
 
 /// Reply enum for websocket client traffic. Variants map 1:1 with request outcomes.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum ClientWsReply {
+    Hello {
+        server_version: u32,
+        min_supported: u32,
+    },
     Pong,
+    /// The server's current time, for estimating clock offset.
+    ServerTime(#[serde(with = "time::serde::rfc3339")] OffsetDateTime),
     OidcDiscovery(OidcDiscoveryDocument),
     OidcJwks(JwksResponse),
     ConnectionState(ClientWsConnectionState),
     AuthSignup(User),
     AuthToken(TokenResponse),
     AuthTokenAccess(ClientWsConnectionState),
+    ResumeSession(ResumedSession),
+    AuthUserinfo(UserinfoResponse),
     UsersCreate(User),
     UsersGetAll(Vec<User>),
     UsersGetByRef(User),
+    UsersSearch(Vec<User>),
     UsersGetAssociatedHosts(Vec<String>),
     UsersDelete,
+    UsersUpdateProfile(User),
     MembershipsGetByUser(Vec<ServerMembership>),
     MembershipsGetMembersByServer(Vec<ServerMember>),
     MembershipsGetByUserAndServer(ServerMember),
     MembershipsUpsert(FullServerMembership),
     MembershipsDelete,
+    MembershipsUpdateRole(FullServerMembership),
+    MembershipsRemove,
+    BansCreate(ServerBan),
+    BansDelete,
+    InvitesCreate(ServerInvite),
+    InvitesRedeem(FullServerMembership),
     ServersCreate(Server),
-    ServersGetAll(Vec<Server>),
+    ServersGetAll(Vec<ServerSummary>),
     ServersGetById(Server),
     ServersGetWithChannels(ServerWithChannels),
     ServersDelete,
+    ServersTransferOwnership(FullServerMembership),
     ChannelsCreate(Channel),
     ChannelsGetAll(Vec<Channel>),
     ChannelsGetByServer(Vec<Channel>),
     ChannelsGetById(Channel),
+    ChannelsUpdate(Channel),
+    ChannelsReorder(Vec<Channel>),
     ChannelsDelete,
     MessagesCreate(Message),
     MessagesGetAll(Vec<Message>),
     MessagesGetByServer(Vec<Message>),
     MessagesGetByChannel(Vec<Message>),
     MessagesGetById(Message),
+    MessagesSearch(Vec<Message>),
+    MessagesUpdate(Message),
     MessagesDelete,
+    MessagesPurgeChannel(ChannelPurgeReport),
+    MessagesReact(Message),
+    MessagesUnreact(Message),
+    ChannelsMarkRead(ChannelReadState),
+    UnreadCounts(Vec<ChannelUnreadCount>),
+    DmSend(Message),
+    DmGetHistory(Vec<Message>),
+    Subscribe,
+    Unsubscribe,
+    PresenceSubscribe(Vec<UserRef>),
+    Typing,
+    ReplaySince(ClientWsReplay),
+}
+
+/// Updates a client missed, or a signal that it missed too many.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum ClientWsReplay {
+    /// The updates after the given event, oldest first.
+    Updates(Vec<ReplayedUpdate>),
+    /// The given event is no longer buffered; refetch the server's state.
+    ResyncRequired,
+}
+
+/// A buffered update with the event id it was first sent with.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ReplayedUpdate {
+    pub event_id: EventId,
+    pub update: ClientWsUpdate,
 }
 
 /// Request enum for federation websocket traffic.
@@ -211,10 +588,18 @@ pub enum ClientWsReply {
 #[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum FederationWsRequest {
     ConnectionState,
-    UsersGetAll,
+    ServerTime,
+    UsersGetAll {
+        #[serde(default)]
+        query: UserQuery,
+    },
     UsersGetByRef {
         user_ref: UserRef,
     },
+    /// Find this host's own users by name.
+    UsersSearch {
+        query: String,
+    },
     UsersGetAssociatedHosts {
         user_ref: UserRef,
     },
@@ -231,6 +616,23 @@ pub enum FederationWsRequest {
         server_id: ServerId,
         user_ref: UserRef,
     },
+    MembershipsUpdateRole {
+        server_id: ServerId,
+        user_ref: UserRef,
+        role: ServerRole,
+    },
+    MembershipsRemove {
+        server_id: ServerId,
+        user_ref: UserRef,
+    },
+    BansCreate {
+        server_id: ServerId,
+        new_ban: NewServerBan,
+    },
+    BansDelete {
+        server_id: ServerId,
+        user_ref: UserRef,
+    },
     MembershipsGetMembersByServer {
         server_id: ServerId,
     },
@@ -238,16 +640,32 @@ pub enum FederationWsRequest {
         server_id: ServerId,
         user_ref: UserRef,
     },
+    InvitesCreate {
+        server_id: ServerId,
+        new_invite: NewServerInvite,
+    },
+    InvitesRedeem {
+        code: String,
+        user: User,
+    },
     ServersCreate(NewServer),
     ServersDelete {
         server_id: ServerId,
     },
+    ServersTransferOwnership {
+        server_id: ServerId,
+        new_admin: UserRef,
+        #[serde(default)]
+        demote_self: bool,
+    },
     ServersGetAll,
     ServersGetById {
         server_id: ServerId,
     },
     ServersGetWithChannels {
         server_id: ServerId,
+        #[serde(default)]
+        include_last_messages: bool,
     },
     ChannelsCreate {
         server_id: ServerId,
@@ -261,6 +679,15 @@ pub enum FederationWsRequest {
         server_id: ServerId,
         channel_id: ChannelId,
     },
+    ChannelsUpdate {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        update: ChannelUpdate,
+    },
+    ChannelsReorder {
+        server_id: ServerId,
+        channel_ids: Vec<ChannelId>,
+    },
     ChannelsDelete {
         server_id: ServerId,
         channel_id: ChannelId,
@@ -277,17 +704,66 @@ pub enum FederationWsRequest {
     MessagesGetByChannel {
         server_id: ServerId,
         channel_id: ChannelId,
+        #[serde(default)]
+        before: Option<MessageId>,
+        #[serde(default)]
+        limit: Option<u32>,
     },
     MessagesGetById {
         server_id: ServerId,
         channel_id: ChannelId,
         message_id: MessageId,
     },
+    MessagesSearch {
+        server_id: ServerId,
+        query: String,
+        #[serde(default)]
+        limit: Option<u32>,
+    },
+    MessagesUpdate {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        update: MessageUpdate,
+    },
     MessagesDelete {
         server_id: ServerId,
         channel_id: ChannelId,
         message_id: MessageId,
     },
+    MessagesPurgeChannel {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        #[serde(default)]
+        before: Option<MessageId>,
+    },
+    MessagesReact {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        new_reaction: NewReaction,
+    },
+    MessagesUnreact {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: String,
+    },
+    ChannelsMarkRead {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    },
+    UnreadCounts {
+        server_id: ServerId,
+    },
+    /// Deliver a direct message to a user on the receiving host. The sender
+    /// keeps their own copy of the conversation.
+    DmSend {
+        from: User,
+        to: UserRef,
+        body: String,
+    },
 }
 
 /// Reply enum for federation websocket traffic. Variants map 1:1 with request outcomes.
@@ -295,37 +771,59 @@ pub enum FederationWsRequest {
 #[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum FederationWsReply {
     ConnectionState(FederationWsConnectionState),
+    /// The server's current time, for estimating clock offset.
+    ServerTime(#[serde(with = "time::serde::rfc3339")] OffsetDateTime),
     UsersGetAll(Vec<User>),
     UsersGetByRef(User),
+    UsersSearch(Vec<User>),
     UsersGetAssociatedHosts(Vec<String>),
     UsersDelete,
     MembershipsUpsert(FullServerMembership),
     MembershipsGetByUser(Vec<ServerMembership>),
     MembershipsDelete,
+    MembershipsUpdateRole(FullServerMembership),
+    MembershipsRemove,
+    BansCreate(ServerBan),
+    BansDelete,
     MembershipsGetMembersByServer(Vec<ServerMember>),
     MembershipsGetByUserAndServer(ServerMember),
+    InvitesCreate(ServerInvite),
+    InvitesRedeem(FullServerMembership),
     ServersCreate(Server),
     ServersDelete,
-    ServersGetAll(Vec<Server>),
+    ServersTransferOwnership(FullServerMembership),
+    ServersGetAll(Vec<ServerSummary>),
     ServersGetById(Server),
     ServersGetWithChannels(ServerWithChannels),
     ChannelsCreate(Channel),
     ChannelsGetAll(Vec<Channel>),
     ChannelsGetByServer(Vec<Channel>),
     ChannelsGetById(Channel),
+    ChannelsUpdate(Channel),
+    ChannelsReorder(Vec<Channel>),
     ChannelsDelete,
     MessagesCreate(Message),
     MessagesGetAll(Vec<Message>),
     MessagesGetByServer(Vec<Message>),
     MessagesGetByChannel(Vec<Message>),
     MessagesGetById(Message),
+    MessagesSearch(Vec<Message>),
+    MessagesUpdate(Message),
     MessagesDelete,
+    MessagesPurgeChannel(ChannelPurgeReport),
+    MessagesReact(Message),
+    MessagesUnreact(Message),
+    ChannelsMarkRead(ChannelReadState),
+    UnreadCounts(Vec<ChannelUnreadCount>),
+    DmSend(Message),
 }
 
 /// Client websocket updates are push-only events and do not map 1:1 with requests.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum ClientWsUpdate {
+    /// Sent to a connection after it authenticates; replaces any prior token.
+    ResumeTokenIssued(ResumeToken),
     UserUpserted(User),
     UserDeleted {
         user_ref: UserRef,
@@ -350,6 +848,80 @@ pub enum ClientWsUpdate {
         channel_id: ChannelId,
         message_id: MessageId,
     },
+    /// Every message in the channel, or every one older than `before`, was
+    /// deleted at once.
+    ChannelPurged {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        before: Option<MessageId>,
+    },
+    MessageReactionUpserted {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        user_ref: UserRef,
+        emoji: String,
+    },
+    MessageReactionRemoved {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        user_ref: UserRef,
+        emoji: String,
+    },
+    /// A user's first connection opened or their last one closed.
+    PresenceChanged {
+        user_ref: UserRef,
+        online: bool,
+    },
+    /// The user moved their read position on another connection.
+    ReadStateUpdated(ChannelReadState),
+    /// A user is typing in a channel this connection is subscribed to.
+    Typing {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        user_ref: UserRef,
+    },
+    /// The connection's user was mentioned in a message, whether or not
+    /// they're subscribed to its channel.
+    Mentioned {
+        message: Message,
+        channel_id: ChannelId,
+    },
+}
+
+impl ClientWsUpdate {
+    /// The server a replayable update belongs to.
+    ///
+    /// Only updates any member of the server may see qualify, so read
+    /// states and mentions (private to a user) and typing indicators
+    /// (ephemeral) are excluded.
+    pub fn replay_server_id(&self) -> Option<ServerId> {
+        match self {
+            ClientWsUpdate::MembershipUpserted(membership) => {
+                Some(membership.server.id)
+            }
+            ClientWsUpdate::ServerUpserted(server) => Some(server.id),
+            ClientWsUpdate::ChannelUpserted(channel) => Some(channel.server_id),
+            ClientWsUpdate::MessageUpserted(message) => Some(message.server_id),
+            ClientWsUpdate::MembershipDeleted { server_id, .. }
+            | ClientWsUpdate::ServerDeleted { server_id }
+            | ClientWsUpdate::ChannelDeleted { server_id, .. }
+            | ClientWsUpdate::MessageDeleted { server_id, .. }
+            | ClientWsUpdate::ChannelPurged { server_id, .. }
+            | ClientWsUpdate::MessageReactionUpserted { server_id, .. }
+            | ClientWsUpdate::MessageReactionRemoved { server_id, .. } => {
+                Some(*server_id)
+            }
+            ClientWsUpdate::ResumeTokenIssued(_)
+            | ClientWsUpdate::UserUpserted(_)
+            | ClientWsUpdate::UserDeleted { .. }
+            | ClientWsUpdate::PresenceChanged { .. }
+            | ClientWsUpdate::ReadStateUpdated(_)
+            | ClientWsUpdate::Typing { .. }
+            | ClientWsUpdate::Mentioned { .. } => None,
+        }
+    }
 }
 
 /// Federation websocket updates are push-only events
@@ -379,9 +951,50 @@ pub enum FederationWsUpdate {
         channel_id: ChannelId,
         message_id: MessageId,
     },
+    ChannelPurged {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        before: Option<MessageId>,
+    },
+    MessageReactionUpserted {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        user_ref: UserRef,
+        emoji: String,
+    },
+    MessageReactionRemoved {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        user_ref: UserRef,
+        emoji: String,
+    },
+    /// A user's profile changed on their home host.
+    UserUpserted(User),
+    /// A member of the server went online or offline. Sent by the user's
+    /// home host, or relayed by the server's home host.
+    PresenceChanged {
+        server_id: ServerId,
+        user_ref: UserRef,
+        online: bool,
+    },
+    /// A member of the server is typing in a channel. Sent by the user's
+    /// home host, or relayed by the server's home host.
+    Typing {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        user_ref: UserRef,
+    },
     RemoteUserDeleted {
         user_ref: UserRef,
     },
+    /// Users of the receiving host were mentioned in a message. Sent by
+    /// the message's server's home host.
+    Mentioned {
+        message: Message,
+        user_refs: Vec<UserRef>,
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -440,9 +1053,36 @@ impl std::fmt::Debug for AuthTokenAccessRequest {
     }
 }
 
+impl std::fmt::Debug for ResumeSessionRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResumeSessionRequest")
+            .field("resume_token", &"[REDACTED]")
+            .field("cursors", &self.cursors)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for ResumeToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResumeToken")
+            .field("resume_token", &"[REDACTED]")
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::AuthTokenAccessRequest;
+    use super::{
+        ApiErrorBody, AuthTokenAccessRequest, ClientWsReply, ClientWsRequest,
+        FederationWsReply, FederationWsRequest,
+        MIN_SUPPORTED_WS_PROTOCOL_VERSION, ResumeSessionRequest,
+        WS_PROTOCOL_VERSION, WsErrorCode, is_supported_protocol_version,
+    };
+    use crate::{
+        message::{Message, NewMessage},
+        user::UserQuery,
+    };
 
     #[test]
     fn auth_token_access_request_debug_redacts_access_token() {
@@ -455,4 +1095,316 @@ mod tests {
         assert!(debug.contains("[REDACTED]"));
         assert!(!debug.contains("secret-token"));
     }
+
+    #[test]
+    fn resume_session_request_round_trips_and_redacts_token() {
+        let request = ClientWsRequest::ResumeSession(ResumeSessionRequest {
+            resume_token: "resume-secret".into(),
+            cursors: Vec::new(),
+        });
+
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["type"], "resume_session");
+        assert_eq!(json["data"]["resume_token"], "resume-secret");
+        let parsed: ClientWsRequest = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, request);
+
+        let debug = format!("{request:?}");
+        assert!(!debug.contains("resume-secret"));
+    }
+
+    #[test]
+    fn servers_get_with_channels_defaults_to_no_previews() {
+        let request: ClientWsRequest = serde_json::from_str(
+            r#"{
+                "type": "servers_get_with_channels",
+                "data": {
+                    "server_id": "00000000-0000-0000-0000-000000000001",
+                    "target_host": null
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let ClientWsRequest::ServersGetWithChannels {
+            include_last_messages,
+            ..
+        } = request
+        else {
+            panic!("unexpected request variant");
+        };
+        assert!(!include_last_messages);
+    }
+
+    #[test]
+    fn channel_purge_request_defaults_to_whole_channel() {
+        let request: FederationWsRequest = serde_json::from_str(
+            r#"{
+                "type": "messages_purge_channel",
+                "data": {
+                    "server_id": "00000000-0000-0000-0000-000000000001",
+                    "channel_id": "00000000-0000-0000-0000-000000000002"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let FederationWsRequest::MessagesPurgeChannel { before, .. } = request
+        else {
+            panic!("unexpected request variant");
+        };
+        assert_eq!(before, None);
+    }
+
+    #[test]
+    fn federation_servers_get_all_reads_servers_without_counts() {
+        let reply: FederationWsReply = serde_json::from_str(
+            r#"{
+                "type": "servers_get_all",
+                "data": [{
+                    "id": "00000000-0000-0000-0000-000000000001",
+                    "host": "remote.example",
+                    "title": "General",
+                    "description": null,
+                    "created_at": "2026-01-01T00:00:00Z",
+                    "updated_at": "2026-01-01T00:00:00Z"
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let FederationWsReply::ServersGetAll(servers) = reply else {
+            panic!("unexpected reply variant");
+        };
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].server.title, "General");
+        assert_eq!(servers[0].member_count, None);
+        assert_eq!(servers[0].channel_count, None);
+    }
+
+    #[test]
+    fn federation_messages_get_by_channel_defaults_to_first_page() {
+        let request: FederationWsRequest = serde_json::from_str(
+            r#"{
+                "type": "messages_get_by_channel",
+                "data": {
+                    "server_id": "00000000-0000-0000-0000-000000000001",
+                    "channel_id": "00000000-0000-0000-0000-000000000002"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let FederationWsRequest::MessagesGetByChannel { before, limit, .. } =
+            request
+        else {
+            panic!("unexpected request variant");
+        };
+        assert_eq!(before, None);
+        assert_eq!(limit, None);
+    }
+
+    #[test]
+    fn test_users_get_all_query_defaults() {
+        let request: FederationWsRequest =
+            serde_json::from_str(r#"{ "type": "users_get_all", "data": {} }"#)
+                .unwrap();
+
+        assert_eq!(
+            request,
+            FederationWsRequest::UsersGetAll {
+                query: UserQuery::default(),
+            }
+        );
+    }
+
+    #[test]
+    fn new_message_ignores_client_supplied_system_flag() {
+        let new_message: NewMessage = serde_json::from_str(
+            r#"{
+                "author": { "name": "alice", "host": "example.com" },
+                "body": "hello",
+                "system": true
+            }"#,
+        )
+        .unwrap();
+
+        let json = serde_json::to_value(&new_message).unwrap();
+        assert!(json.get("system").is_none());
+    }
+
+    #[test]
+    fn message_system_flag_defaults_to_false() {
+        let message: Message = serde_json::from_str(
+            r#"{
+                "id": "00000000-0000-0000-0000-000000000001",
+                "channel_id": "00000000-0000-0000-0000-000000000002",
+                "server_id": "00000000-0000-0000-0000-000000000003",
+                "author": null,
+                "body": "hello",
+                "created_at": "2026-01-01T00:00:00Z",
+                "updated_at": "2026-01-01T00:00:00Z"
+            }"#,
+        )
+        .unwrap();
+
+        assert!(!message.system);
+        assert_eq!(message.to_string(), "anon: hello");
+    }
+
+    #[test]
+    fn edited_message_is_marked_in_display() {
+        let message: Message = serde_json::from_str(
+            r#"{
+                "id": "00000000-0000-0000-0000-000000000001",
+                "channel_id": "00000000-0000-0000-0000-000000000002",
+                "server_id": "00000000-0000-0000-0000-000000000003",
+                "author": null,
+                "body": "hello again",
+                "created_at": "2026-01-01T00:00:00Z",
+                "updated_at": "2026-01-01T00:05:00Z",
+                "edited_at": "2026-01-01T00:05:00Z"
+            }"#,
+        )
+        .unwrap();
+
+        assert!(message.edited_at.is_some());
+        assert_eq!(message.to_string(), "anon: hello again (edited)");
+    }
+
+    #[test]
+    fn message_reactions_default_to_empty_and_show_in_display() {
+        let mut message: Message = serde_json::from_str(
+            r#"{
+                "id": "00000000-0000-0000-0000-000000000001",
+                "channel_id": "00000000-0000-0000-0000-000000000002",
+                "server_id": "00000000-0000-0000-0000-000000000003",
+                "author": null,
+                "body": "hello",
+                "created_at": "2026-01-01T00:00:00Z",
+                "updated_at": "2026-01-01T00:00:00Z"
+            }"#,
+        )
+        .unwrap();
+        assert!(message.reactions.is_empty());
+
+        message.reactions = serde_json::from_str(
+            r#"[
+                {"emoji": "👍", "count": 2, "reacted": true},
+                {"emoji": ":party_parrot:", "count": 1}
+            ]"#,
+        )
+        .unwrap();
+        assert!(message.reactions[0].reacted);
+        assert!(!message.reactions[1].reacted);
+        assert_eq!(message.to_string(), "anon: hello [👍 2, :party_parrot: 1]");
+    }
+
+    #[test]
+    fn server_time_reply_is_near_current_rfc3339() {
+        use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+        let now = OffsetDateTime::now_utc();
+        let json =
+            serde_json::to_value(ClientWsReply::ServerTime(now)).unwrap();
+        assert_eq!(json["type"], "server_time");
+        let parsed =
+            OffsetDateTime::parse(json["data"].as_str().unwrap(), &Rfc3339)
+                .unwrap();
+        assert!((parsed - now).abs() < time::Duration::seconds(1));
+
+        let json =
+            serde_json::to_string(&FederationWsReply::ServerTime(now)).unwrap();
+        let FederationWsReply::ServerTime(roundtrip) =
+            serde_json::from_str(&json).unwrap()
+        else {
+            panic!("expected server_time reply");
+        };
+        assert_eq!(roundtrip, now);
+    }
+
+    #[test]
+    fn servers_get_all_defaults_to_public_only() {
+        let json = serde_json::json!({
+            "type": "servers_get_all",
+            "data": { "target_host": null },
+        });
+        let parsed: ClientWsRequest = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            parsed,
+            ClientWsRequest::ServersGetAll {
+                include_private: false,
+                target_host: None,
+            }
+        );
+    }
+
+    #[test]
+    fn hello_round_trips_and_checks_the_supported_range() {
+        let json = serde_json::json!({
+            "type": "hello",
+            "data": { "protocol_version": 1 },
+        });
+        let parsed: ClientWsRequest = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            parsed,
+            ClientWsRequest::Hello {
+                protocol_version: 1
+            }
+        );
+
+        let reply = ClientWsReply::Hello {
+            server_version: WS_PROTOCOL_VERSION,
+            min_supported: MIN_SUPPORTED_WS_PROTOCOL_VERSION,
+        };
+        let json = serde_json::to_value(&reply).unwrap();
+        assert_eq!(json["type"], "hello");
+        assert_eq!(json["data"]["server_version"], WS_PROTOCOL_VERSION);
+
+        assert!(is_supported_protocol_version(WS_PROTOCOL_VERSION));
+        assert!(!is_supported_protocol_version(0));
+        assert!(!is_supported_protocol_version(WS_PROTOCOL_VERSION + 1));
+    }
+
+    #[test]
+    fn ws_error_code_serializes_as_its_str() {
+        for code in [
+            WsErrorCode::Unauthorized,
+            WsErrorCode::AuthError,
+            WsErrorCode::MalformedEnvelope,
+            WsErrorCode::PayloadTooLarge,
+            WsErrorCode::UnsupportedProtocol,
+            WsErrorCode::ServiceUnavailable,
+            WsErrorCode::InternalError,
+        ] {
+            let json = serde_json::to_string(&code).unwrap();
+            assert_eq!(json, format!("\"{}\"", code.as_str()));
+            assert_eq!(
+                serde_json::from_str::<WsErrorCode>(&json).unwrap(),
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn unrecognized_ws_error_code_is_unknown() {
+        let code =
+            serde_json::from_str::<WsErrorCode>("\"slow_down\"").unwrap();
+        assert_eq!(code, WsErrorCode::Unknown);
+    }
+
+    #[test]
+    fn api_error_body_reads_the_error_field_as_message() {
+        let body: ApiErrorBody = serde_json::from_str(
+            r#"{"error":"Forbidden: not a member","code":"forbidden"}"#,
+        )
+        .unwrap();
+        assert_eq!(body.message, "Forbidden: not a member");
+        assert_eq!(body.code, WsErrorCode::Forbidden);
+        assert_eq!(body.details, None);
+
+        let json = serde_json::to_value(&body).unwrap();
+        assert_eq!(json["error"], "Forbidden: not a member");
+        assert!(json.get("details").is_none());
+    }
 }